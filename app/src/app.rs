@@ -6,20 +6,31 @@ use relm4::{
 };
 use relm4_icons::icon_name;
 
+use crate::components::contacts_list::{ContactsListInput, ContactsListOutput};
 use crate::components::identities_list::IdentitiesListInput;
+use crate::components::rules_list::RulesListInput;
 
+use super::components::contacts_list::ContactsListModel;
 use super::components::dialogs::identity_dialog::{IdentityDialogModel, IdentityDialogOutput};
 use super::components::identities_list::{IdentitiesListModel, IdentitiesListOutput};
-use super::components::message_composer::MessageComposer;
+use super::components::message_composer::{MessageComposer, MessageComposerInit};
 use super::components::messages::{MessagesInput, MessagesModel};
 use super::components::network_status::NetworkStatusModel;
+use super::components::rules_list::RulesListModel;
+use super::components::settings::SettingsModel;
+use crate::state;
 
 pub(crate) struct AppModel {
     identities_list: AsyncController<IdentitiesListModel>,
+    contacts_list: AsyncController<ContactsListModel>,
     messages: AsyncController<MessagesModel>,
+    rules_list: AsyncController<RulesListModel>,
     network_status: AsyncController<NetworkStatusModel>,
+    settings: Controller<SettingsModel>,
     stack: adw::ViewStack,
     show_plus_button: bool,
+    /// Node is in low-power mode, see [`crate::power`]
+    low_power: bool,
     identity_dialog: Controller<IdentityDialogModel>,
 }
 
@@ -29,6 +40,10 @@ pub(crate) enum AppInput {
     HandleClickPlusButton,
     ShowPlusButton(bool),
     IdentitiesListUpdated,
+    SubscriptionsUpdated,
+    SetLowPower(bool),
+    /// Battery state reported by UPower changed
+    OnBattery(bool),
 }
 
 #[relm4::component(pub)]
@@ -60,7 +75,17 @@ impl SimpleComponent for AppModel {
                             set_icon_name: icon_name::PLUS,
                             connect_clicked => AppInput::HandleClickPlusButton
                         }
-                    } else { gtk::Box{} }
+                    } else { gtk::Box{} },
+                    pack_end = &gtk::ToggleButton {
+                        set_icon_name: "power-profile-power-saver-symbolic",
+                        set_tooltip_text: Some("Low-power mode: pause proof of work and reduce network activity"),
+                        #[watch]
+                        #[block_signal(low_power_toggled)]
+                        set_active: model.low_power,
+                        connect_toggled[sender] => move |b| {
+                            sender.input(AppInput::SetLowPower(b.is_active()))
+                        } @low_power_toggled,
+                    },
                 },
 
                 gtk::Box {
@@ -77,13 +102,25 @@ impl SimpleComponent for AppModel {
                             set_icon_name: Some(icon_name::PERSON),
                         },
 
+                        add_titled[Some("contacts"), "Contacts"] = model.contacts_list.widget() -> &gtk::ScrolledWindow {} -> {
+                            set_icon_name: Some(icon_name::ADDRESS_BOOK),
+                        },
+
                         add_titled[Some("messages"), "Messages"] = model.messages.widget() -> &gtk::ScrolledWindow {} -> {
                             set_icon_name: Some(icon_name::MAIL_INBOX_FILLED),
                         },
 
+                        add_titled[Some("rules"), "Rules"] = model.rules_list.widget() -> &gtk::ScrolledWindow {} -> {
+                            set_icon_name: Some(icon_name::FILTER_REGULAR),
+                        },
+
                         add_titled[Some("status"), "Network Status"] = model.network_status.widget() -> &gtk::ScrolledWindow {} -> {
                             set_icon_name: Some(icon_name::DESKTOP_PULSE_FILLED),
                         },
+
+                        add_titled[Some("settings"), "Settings"] = model.settings.widget() -> &gtk::ScrolledWindow {} -> {
+                            set_icon_name: Some(icon_name::SETTINGS),
+                        },
                     },
 
                     #[name = "view_bar"]
@@ -107,8 +144,23 @@ impl SimpleComponent for AppModel {
                     IdentitiesListOutput::EmptyList(v) => AppInput::ShowPlusButton(!v),
                     IdentitiesListOutput::IdentitiesListUpdated => AppInput::IdentitiesListUpdated,
                 });
+        let contacts_list_component =
+            ContactsListModel::builder()
+                .launch(())
+                .forward(sender.input_sender(), |message| match message {
+                    ContactsListOutput::SubscriptionsUpdated => AppInput::SubscriptionsUpdated,
+                });
         let messages_component = MessagesModel::builder().launch(()).detach();
+        let rules_list_component = RulesListModel::builder().launch(()).detach();
         let network_status_component = NetworkStatusModel::builder().launch(()).detach();
+        let settings_component = SettingsModel::builder().launch(()).detach();
+
+        state::STATE.read_inner().settings.theme.apply();
+        crate::notifications::start();
+        let battery_sender = sender.input_sender().clone();
+        crate::power::watch_battery(move |on_battery| {
+            battery_sender.emit(AppInput::OnBattery(on_battery))
+        });
 
         let identity_dialog_controller = IdentityDialogModel::builder().launch(None).forward(
             identities_list_component.sender(),
@@ -116,17 +168,24 @@ impl SimpleComponent for AppModel {
                 IdentityDialogOutput::GenerateIdentity(label) => {
                     IdentitiesListInput::GenerateNewIdentity { label }
                 }
+                IdentityDialogOutput::GenerateFromPassphrase { label, passphrase } => {
+                    IdentitiesListInput::GenerateDeterministicIdentity { label, passphrase }
+                }
                 IdentityDialogOutput::RenameIdentity { .. } => todo!(),
             },
         );
 
         let mut model = AppModel {
             identities_list: identities_list_component,
+            contacts_list: contacts_list_component,
             messages: messages_component,
+            rules_list: rules_list_component,
             network_status: network_status_component,
+            settings: settings_component,
             stack: adw::ViewStack::default(),
             identity_dialog: identity_dialog_controller,
             show_plus_button: false,
+            low_power: false,
         };
 
         let widgets = view_output!();
@@ -146,17 +205,21 @@ impl SimpleComponent for AppModel {
     fn update(&mut self, message: Self::Input, _sender: ComponentSender<Self>) {
         match message {
             AppInput::PageChanged => match self.stack.visible_child_name().unwrap().as_str() {
-                "identities" | "messages" => self.show_plus_button = true,
+                "identities" | "contacts" | "messages" | "rules" => self.show_plus_button = true,
                 _ => self.show_plus_button = false,
             },
             AppInput::HandleClickPlusButton => {
                 match self.stack.visible_child_name().unwrap().as_str() {
                     "messages" => {
-                        let mut message_composer = MessageComposer::builder().launch(()).detach();
+                        let mut message_composer = MessageComposer::builder()
+                            .launch(MessageComposerInit::default())
+                            .detach();
                         message_composer.widget().present();
                         message_composer.detach_runtime();
                     }
                     "identities" => self.identity_dialog.widget().present(),
+                    "contacts" => self.contacts_list.emit(ContactsListInput::OpenAddDialog),
+                    "rules" => self.rules_list.emit(RulesListInput::OpenAddDialog),
                     _ => {}
                 }
             }
@@ -164,6 +227,22 @@ impl SimpleComponent for AppModel {
             AppInput::IdentitiesListUpdated => {
                 self.messages.emit(MessagesInput::IdentitiesListUpdated)
             }
+            AppInput::SubscriptionsUpdated => {
+                self.messages.emit(MessagesInput::SubscriptionsUpdated)
+            }
+            AppInput::SetLowPower(enabled) => {
+                if enabled != self.low_power {
+                    self.low_power = enabled;
+                    crate::power::set_low_power(enabled);
+                }
+            }
+            AppInput::OnBattery(on_battery) => {
+                let follow_battery = state::STATE.read_inner().settings.low_power_on_battery;
+                if follow_battery && on_battery != self.low_power {
+                    self.low_power = on_battery;
+                    crate::power::set_low_power(on_battery);
+                }
+            }
         }
     }
 }
@@ -0,0 +1,419 @@
+use adw::prelude::*;
+use async_std::stream::StreamExt;
+use gtk;
+use relm4::factory::FactoryVecDeque;
+use relm4::prelude::DynamicIndex;
+use relm4::{
+    component::{AsyncComponent, AsyncComponentParts},
+    loading_widgets::LoadingWidgets,
+    view,
+};
+use relm4::{Component, ComponentController, Controller, RelmWidgetExt};
+
+use crate::network::node::worker::KeyMismatchEvent;
+use crate::state;
+
+use super::dialogs::contact_dialog::{ContactDialogInit, ContactDialogModel, ContactDialogOutput};
+use super::dialogs::share_dialog::{ShareDialogInit, ShareDialogModel};
+use super::factories::contact_list_row::{ContactListRow, ContactListRowInit, ContactListRowInput};
+
+pub(crate) struct ContactsListModel {
+    is_list_empty: bool,
+    contact_dialog: Controller<ContactDialogModel>,
+    share_dialog: Option<Controller<ShareDialogModel>>,
+    list_view: FactoryVecDeque<ContactListRow>,
+    /// Contacts whose pubkeys were rejected since their keys differ from the pinned ones
+    key_mismatches: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ContactsListInput {
+    OpenAddDialog,
+    AddContact {
+        label: String,
+        address: String,
+    },
+    DeleteContact(DynamicIndex),
+    HandleRenameContact(DynamicIndex),
+    HandleShareContact(DynamicIndex),
+    ToggleSubscription(DynamicIndex),
+    HandleRepinContact(DynamicIndex),
+    /// Accept the next received pubkey of the contact instead of the pinned keys
+    RepinContact(String),
+    RenameContact {
+        new_label: String,
+        address: String,
+        index: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum ContactsListOutput {
+    /// Broadcasts of a contact are (no longer) received, so its feed is added or removed
+    SubscriptionsUpdated,
+}
+
+impl ContactsListModel {
+    async fn reload_list(&mut self) {
+        let contacts = state::STATE
+            .write_inner()
+            .client
+            .as_mut()
+            .unwrap()
+            .get_contacts()
+            .await;
+        let contacts = match contacts {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("Failed to load contacts: {}", e);
+                return;
+            }
+        };
+        let mut client = state::STATE.read_inner().client.clone().unwrap();
+        let subscriptions: Vec<String> = match client.get_subscriptions().await {
+            Ok(s) => s.into_iter().map(|s| s.address).collect(),
+            Err(e) => {
+                log::error!("Failed to load subscriptions: {}", e);
+                Vec::new()
+            }
+        };
+        self.is_list_empty = contacts.is_empty();
+        let mut guard = self.list_view.guard();
+        guard.clear();
+        for c in contacts {
+            guard.push_back(ContactListRowInit {
+                label: c.label,
+                subscribed: subscriptions.contains(&c.string_repr),
+                key_mismatch: self.key_mismatches.contains(&c.string_repr),
+                address: c.string_repr,
+            });
+        }
+    }
+
+    /// Show or hide the key mismatch warning of the contact
+    fn set_key_mismatch(&mut self, address: &str, mismatch: bool) {
+        self.key_mismatches.retain(|a| a != address);
+        if mismatch {
+            self.key_mismatches.push(address.to_string());
+        }
+        let index = self.list_view.iter().position(|c| c.address == address);
+        if let Some(index) = index {
+            self.list_view
+                .send(index, ContactListRowInput::SetKeyMismatch(mismatch));
+        }
+    }
+
+    fn create_contact_dialog_controller(
+        sender: relm4::AsyncComponentSender<Self>,
+        init: Option<ContactDialogInit>,
+    ) -> Controller<ContactDialogModel> {
+        ContactDialogModel::builder()
+            .launch(init)
+            .forward(sender.input_sender(), |message| match message {
+                ContactDialogOutput::AddContact { label, address } => {
+                    ContactsListInput::AddContact { label, address }
+                }
+                ContactDialogOutput::RenameContact {
+                    new_label,
+                    address,
+                    index,
+                } => ContactsListInput::RenameContact {
+                    new_label,
+                    address,
+                    index,
+                },
+            })
+    }
+}
+
+fn show_message(root: &gtk::ScrolledWindow, heading: &str, body: &str) {
+    let dialog = adw::MessageDialog::new(
+        root.root().and_downcast_ref::<gtk::Window>(),
+        Some(heading),
+        Some(body),
+    );
+    dialog.add_response("ok", "OK");
+    dialog.present();
+}
+
+#[relm4::component(pub async)]
+impl AsyncComponent for ContactsListModel {
+    type CommandOutput = KeyMismatchEvent;
+    type Input = ContactsListInput;
+    type Output = ContactsListOutput;
+    type Init = ();
+
+    view! {
+        #[root]
+        gtk::ScrolledWindow {
+            gtk::CenterBox {
+                #[wrap(Some)]
+                set_center_widget = &gtk::Box{
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+
+                        #[watch]
+                        set_visible: model.is_list_empty,
+                        set_spacing: 3,
+                        set_valign: gtk::Align::Center,
+
+                        gtk::Label {
+                            set_label: "No contacts yet :(",
+                            add_css_class: "large-title"
+                        },
+                        gtk::Button {
+                            set_label: "Add new one",
+                            set_hexpand: false,
+                            connect_clicked => ContactsListInput::OpenAddDialog
+                        }
+                    },
+
+                    #[local]
+                    list_view -> gtk::ListBox {
+                        set_valign: gtk::Align::Start,
+                        set_margin_top: 12,
+                        set_margin_bottom: 12,
+                        add_css_class: "boxed-list",
+                    }
+                }
+            }
+        }
+    }
+
+    fn init_loading_widgets(root: &mut Self::Root) -> Option<LoadingWidgets> {
+        view! {
+                #[local_ref]
+                root {
+                    #[name(loading)]
+                    gtk::CenterBox {
+                        set_margin_all: 100,
+                        set_orientation: gtk::Orientation::Vertical,
+                        #[wrap(Some)]
+                        set_center_widget = &gtk::Spinner {
+                            start: (),
+                            set_size_request: (40, 40),
+                            set_halign: gtk::Align::Center,
+                            set_valign: gtk::Align::Center,
+                        },
+                    }
+                }
+        }
+        Some(LoadingWidgets::new(root, loading))
+    }
+
+    async fn init(
+        _init: Self::Init,
+        root: Self::Root,
+        sender: relm4::AsyncComponentSender<Self>,
+    ) -> AsyncComponentParts<Self> {
+        let list_view = gtk::ListBox::default();
+        let list_view_factory = FactoryVecDeque::new(list_view.clone(), sender.input_sender());
+
+        let mut model = Self {
+            is_list_empty: true,
+            list_view: list_view_factory,
+            contact_dialog: Self::create_contact_dialog_controller(sender.clone(), None),
+            share_dialog: None,
+            key_mismatches: Vec::new(),
+        };
+
+        model.reload_list().await;
+
+        let mut client = state::STATE.read_inner().client.clone().unwrap();
+        match client.subscribe_key_mismatch().await {
+            Ok(mut mismatches) => sender.command(|out, shutdown| {
+                shutdown
+                    .register(async move {
+                        while let Some(event) = mismatches.next().await {
+                            if out.send(event).is_err() {
+                                break;
+                            }
+                        }
+                    })
+                    .drop_on_shutdown()
+            }),
+            Err(e) => log::error!("Failed to subscribe to key mismatches: {}", e),
+        }
+
+        let widgets = view_output!();
+        AsyncComponentParts { model, widgets }
+    }
+
+    async fn update_cmd(
+        &mut self,
+        event: Self::CommandOutput,
+        _sender: relm4::AsyncComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        // warn once per contact, the row keeps showing it until the contact is re-pinned
+        if !self.key_mismatches.contains(&event.address) {
+            show_message(
+                root,
+                "Pubkey of a contact was rejected",
+                &format!("A pubkey with other keys than the pinned ones was received for {}. Messages to it use the pinned keys until you accept the new ones in the contacts list.", event.address),
+            );
+        }
+        self.set_key_mismatch(&event.address, true);
+    }
+
+    async fn update(
+        &mut self,
+        message: Self::Input,
+        sender: relm4::AsyncComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match message {
+            ContactsListInput::OpenAddDialog => {
+                self.contact_dialog = Self::create_contact_dialog_controller(sender.clone(), None);
+                self.contact_dialog.widget().present();
+            }
+            ContactsListInput::AddContact { label, address } => {
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .add_contact(address, label)
+                    .await;
+                match result {
+                    Ok(_) => self.reload_list().await,
+                    Err(e) => {
+                        let dialog = adw::MessageDialog::new(
+                            root.root().and_downcast_ref::<gtk::Window>(),
+                            Some("Failed to add contact"),
+                            Some(&e.to_string()),
+                        );
+                        dialog.add_response("ok", "OK");
+                        dialog.present();
+                    }
+                }
+            }
+            ContactsListInput::DeleteContact(i) => {
+                let item = self
+                    .list_view
+                    .guard()
+                    .remove(i.current_index())
+                    .expect("contact to be existing");
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .delete_contact(item.address)
+                    .await;
+                if let Err(e) = result {
+                    log::error!("Failed to delete contact: {}", e);
+                    self.reload_list().await;
+                }
+                self.is_list_empty = self.list_view.is_empty();
+            }
+            ContactsListInput::HandleRenameContact(i) => {
+                let guard = self.list_view.guard();
+                let contact_item = guard
+                    .get(i.current_index())
+                    .expect("contact to be existing");
+
+                self.contact_dialog = Self::create_contact_dialog_controller(
+                    sender.clone(),
+                    Some(ContactDialogInit {
+                        label: contact_item.label.clone(),
+                        address: contact_item.address.clone(),
+                        index: i.current_index(),
+                    }),
+                );
+                self.contact_dialog.widget().present();
+            }
+            ContactsListInput::HandleShareContact(i) => {
+                let guard = self.list_view.guard();
+                let contact_item = guard
+                    .get(i.current_index())
+                    .expect("contact to be existing");
+                let share_dialog = ShareDialogModel::builder()
+                    .launch(ShareDialogInit {
+                        label: contact_item.label.clone(),
+                        address: contact_item.address.clone(),
+                    })
+                    .detach();
+                share_dialog.widget().present();
+                self.share_dialog = Some(share_dialog);
+            }
+            ContactsListInput::ToggleSubscription(i) => {
+                let (label, address, subscribed) = {
+                    let guard = self.list_view.guard();
+                    let contact_item = guard
+                        .get(i.current_index())
+                        .expect("contact to be existing");
+                    (
+                        contact_item.label.clone(),
+                        contact_item.address.clone(),
+                        contact_item.subscribed,
+                    )
+                };
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                let result = if subscribed {
+                    client.remove_subscription(address).await
+                } else {
+                    client.add_subscription(address, label).await
+                };
+                match result {
+                    Ok(_) => {
+                        self.list_view.send(
+                            i.current_index(),
+                            ContactListRowInput::SetSubscribed(!subscribed),
+                        );
+                        _ = sender.output(ContactsListOutput::SubscriptionsUpdated);
+                    }
+                    Err(e) => log::error!("Failed to update subscription: {}", e),
+                }
+            }
+            ContactsListInput::HandleRepinContact(i) => {
+                let address = self
+                    .list_view
+                    .get(i.current_index())
+                    .expect("contact to be existing")
+                    .address
+                    .clone();
+                let dialog = adw::MessageDialog::new(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    Some("Accept new keys?"),
+                    Some(&format!("A pubkey with other keys than the pinned ones was received for {}, so messages can't be exchanged with it. Only accept the new keys if the contact confirmed they have changed them, otherwise someone may impersonate the contact.", address)),
+                );
+                dialog.add_responses(&[("cancel", "Cancel"), ("repin", "Accept")]);
+                dialog.set_response_appearance("repin", adw::ResponseAppearance::Destructive);
+                dialog.set_close_response("cancel");
+                dialog.connect_response(None, move |_, response| {
+                    if response == "repin" {
+                        sender.input(ContactsListInput::RepinContact(address.clone()));
+                    }
+                });
+                dialog.present();
+            }
+            ContactsListInput::RepinContact(address) => {
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                match client.repin_contact(address.clone()).await {
+                    Ok(()) => self.set_key_mismatch(&address, false),
+                    Err(e) => show_message(root, "Failed to re-pin contact", &e.to_string()),
+                }
+            }
+            ContactsListInput::RenameContact {
+                new_label,
+                address,
+                index,
+            } => {
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .rename_contact(address, new_label.clone())
+                    .await;
+                match result {
+                    Ok(_) => self
+                        .list_view
+                        .send(index, ContactListRowInput::RenameLabel(new_label)),
+                    Err(e) => log::error!("Failed to rename contact: {}", e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use adw;
+use gtk::{self, prelude::*};
+use relm4::{Component, ComponentParts, ComponentSender, RelmWidgetExt};
+
+pub struct ChanDialogModel {
+    pub name: gtk::EntryBuffer,
+    /// Address of the chan to check the name against, may be empty
+    pub address: gtk::EntryBuffer,
+}
+
+#[derive(Debug)]
+pub enum ChanDialogInput {
+    HandleEntry,
+}
+
+#[derive(Debug)]
+pub enum ChanDialogOutput {
+    JoinChan {
+        name: String,
+        address: Option<String>,
+    },
+}
+
+#[relm4::component(pub)]
+impl Component for ChanDialogModel {
+    type Input = ChanDialogInput;
+    type Output = ChanDialogOutput;
+    type Init = ();
+    type CommandOutput = ();
+
+    view! {
+        #[root]
+        adw::Window {
+            set_hide_on_close: true,
+            set_default_width: 320,
+            set_resizable: false,
+            set_modal: true,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                    set_css_classes: &["flat"],
+                    set_title_widget: Some(&gtk::Box::default())
+                },
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 20,
+                    set_spacing: 10,
+                    gtk::Image {
+                        set_icon_size: gtk::IconSize::Large,
+                        set_icon_name: Some("system-users-symbolic"),
+                    },
+                    gtk::Label {
+                        set_css_classes: &["title-4"],
+                        set_label: "You're about to join a chan.",
+                    },
+                    gtk::Label {
+                        set_label: "Everyone who knows the chan name can read and send its messages.",
+                        set_wrap: true,
+                        set_justify: gtk::Justification::Center,
+                    },
+                    gtk::Entry {
+                        set_placeholder_text: Some("Enter chan name..."),
+                        set_buffer: &model.name,
+                        connect_activate => ChanDialogInput::HandleEntry,
+                    },
+                    gtk::Entry {
+                        set_placeholder_text: Some("Chan address (optional)"),
+                        set_tooltip_text: Some("Check that the name matches the address of an existing chan"),
+                        set_buffer: &model.address,
+                        connect_activate => ChanDialogInput::HandleEntry,
+                    },
+                    gtk::Button {
+                        set_css_classes: &["suggested-action"],
+                        set_label: "Join chan",
+                        connect_clicked => ChanDialogInput::HandleEntry,
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        _init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = ChanDialogModel {
+            name: gtk::EntryBuffer::new(Some("")),
+            address: gtk::EntryBuffer::new(Some("")),
+        };
+
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        match message {
+            ChanDialogInput::HandleEntry => {
+                let address = self.address.text().trim().to_string();
+                sender
+                    .output(ChanDialogOutput::JoinChan {
+                        name: self.name.text().to_string(),
+                        address: Some(address).filter(|a| !a.is_empty()),
+                    })
+                    .unwrap_or_default();
+                self.name.set_text("");
+                self.address.set_text("");
+                root.close();
+            }
+        }
+    }
+}
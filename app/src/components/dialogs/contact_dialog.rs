@@ -0,0 +1,177 @@
+use adw;
+use gtk::{self, prelude::*};
+use relm4::{Component, ComponentParts, ComponentSender, RelmWidgetExt};
+use relm4_icons::icon_name;
+
+use crate::network::uri::AddressUri;
+
+pub struct ContactDialogModel {
+    pub label: gtk::EntryBuffer,
+    pub address: gtk::EntryBuffer,
+    pub mode: ContactDialogMode,
+    pub index: Option<usize>,
+}
+
+pub struct ContactDialogInit {
+    pub label: String,
+    pub address: String,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContactDialogMode {
+    New,
+    Edit,
+}
+
+#[derive(Debug)]
+pub enum ContactDialogInput {
+    HandleEntry,
+    AddressChanged,
+}
+
+#[derive(Debug)]
+pub enum ContactDialogOutput {
+    AddContact {
+        label: String,
+        address: String,
+    },
+    RenameContact {
+        new_label: String,
+        address: String,
+        index: usize,
+    },
+}
+
+#[relm4::component(pub)]
+impl Component for ContactDialogModel {
+    type Input = ContactDialogInput;
+    type Output = ContactDialogOutput;
+    type Init = Option<ContactDialogInit>;
+    type CommandOutput = ();
+
+    view! {
+        #[root]
+        adw::Window {
+            set_hide_on_close: true,
+            set_default_width: 320,
+            set_resizable: false,
+            set_modal: true,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                    set_css_classes: &["flat"],
+                    set_title_widget: Some(&gtk::Box::default())
+                },
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 20,
+                    set_spacing: 10,
+                    gtk::Image {
+                            set_icon_size: gtk::IconSize::Large,
+                            set_icon_name: Some(match model.mode {
+                                ContactDialogMode::New => icon_name::PLUS,
+                                ContactDialogMode::Edit => icon_name::PENCIL_AND_PAPER
+                            }),
+                    },
+                    gtk::Label {
+                        set_css_classes: &["title-4"],
+                        set_label: match model.mode {
+                            ContactDialogMode::New => "You're about to add a contact.",
+                            ContactDialogMode::Edit => "You're about to rename this contact."
+                        },
+                    },
+                    gtk::Entry {
+                        set_placeholder_text: Some("Enter contact name..."),
+                        set_buffer: &model.label,
+                        connect_activate => ContactDialogInput::HandleEntry,
+                    },
+                    gtk::Entry {
+                        set_visible: model.mode == ContactDialogMode::New,
+                        set_placeholder_text: Some("Enter address or bitmessage: link..."),
+                        set_buffer: &model.address,
+                        connect_activate => ContactDialogInput::HandleEntry,
+                        connect_changed => ContactDialogInput::AddressChanged,
+                    },
+                    gtk::Button {
+                        set_css_classes: &["suggested-action"],
+                        set_label: match model.mode {
+                            ContactDialogMode::New => "Add contact",
+                            ContactDialogMode::Edit => "Rename contact"
+                        },
+                        connect_clicked => ContactDialogInput::HandleEntry,
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = if let Some(contact) = init {
+            ContactDialogModel {
+                label: gtk::EntryBuffer::new(Some(contact.label)),
+                address: gtk::EntryBuffer::new(Some(contact.address)),
+                mode: ContactDialogMode::Edit,
+                index: Some(contact.index),
+            }
+        } else {
+            ContactDialogModel {
+                label: gtk::EntryBuffer::new(Some("")),
+                address: gtk::EntryBuffer::new(Some("")),
+                mode: ContactDialogMode::New,
+                index: None,
+            }
+        };
+
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        match message {
+            ContactDialogInput::HandleEntry => {
+                let label = self.label.text().to_string();
+                let address = self.address.text().trim().to_string();
+
+                match self.mode {
+                    ContactDialogMode::New => {
+                        if address.is_empty() {
+                            return;
+                        }
+                        sender
+                            .output(ContactDialogOutput::AddContact { label, address })
+                            .unwrap_or_default();
+                        self.label.set_text("");
+                        self.address.set_text("");
+                    }
+                    ContactDialogMode::Edit => {
+                        sender
+                            .output(ContactDialogOutput::RenameContact {
+                                new_label: label,
+                                address,
+                                index: self.index.unwrap(),
+                            })
+                            .unwrap_or_default();
+                    }
+                }
+                root.close();
+            }
+            ContactDialogInput::AddressChanged => {
+                // pasted link is split into the address and the label
+                if let Ok(uri) = AddressUri::parse(self.address.text().as_str()) {
+                    self.address.set_text(uri.address);
+                    if let Some(label) = uri.label.filter(|_| self.label.text().is_empty()) {
+                        self.label.set_text(label);
+                    }
+                }
+            }
+        }
+    }
+}
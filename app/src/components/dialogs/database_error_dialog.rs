@@ -0,0 +1,86 @@
+use adw;
+use gtk::{self, prelude::*};
+use relm4::{ComponentParts, ComponentSender, RelmWidgetExt, SimpleComponent};
+use relm4_icons::icon_name;
+
+/// Window shown instead of the app when the database can't be opened, e.g. it was
+/// migrated by a newer version or it's corrupted
+pub struct DatabaseErrorDialogModel {
+    pub error: String,
+}
+
+#[derive(Debug)]
+pub enum DatabaseErrorDialogInput {
+    Quit,
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for DatabaseErrorDialogModel {
+    type Input = DatabaseErrorDialogInput;
+    type Output = ();
+    /// Description of the error
+    type Init = String;
+
+    view! {
+        #[root]
+        adw::Window {
+            set_title: Some("Bitmessage-rs"),
+            set_default_width: 360,
+            set_resizable: false,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                    set_css_classes: &["flat"],
+                    set_title_widget: Some(&gtk::Box::default())
+                },
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 20,
+                    set_spacing: 10,
+                    gtk::Image {
+                            set_icon_size: gtk::IconSize::Large,
+                            set_icon_name: Some(icon_name::ALERT_REGULAR),
+                    },
+                    gtk::Label {
+                        set_css_classes: &["title-4"],
+                        set_label: "The database can't be opened.",
+                    },
+                    gtk::Label {
+                        set_css_classes: &["error"],
+                        set_wrap: true,
+                        set_justify: gtk::Justification::Center,
+                        set_label: &model.error,
+                    },
+                    gtk::Label {
+                        set_wrap: true,
+                        set_justify: gtk::Justification::Center,
+                        set_label: "If it was migrated, a copy made before that is kept next to it as database.db.backup.",
+                    },
+                    gtk::Button {
+                        set_label: "Quit",
+                        connect_clicked => DatabaseErrorDialogInput::Quit,
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = DatabaseErrorDialogModel { error: init };
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, _sender: ComponentSender<Self>) {
+        match message {
+            DatabaseErrorDialogInput::Quit => relm4::main_application().quit(),
+        }
+    }
+}
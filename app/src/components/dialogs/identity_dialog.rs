@@ -5,6 +5,8 @@ use relm4_icons::icon_name;
 
 pub struct IdentityDialogModel {
     pub label: gtk::EntryBuffer,
+    /// Passphrase of deterministic identity, the identity is random if it's empty
+    pub passphrase: gtk::PasswordEntry,
     pub mode: IdentityDialogMode,
     pub button_label: String,
     pub address: String,
@@ -31,6 +33,10 @@ pub enum IdentityDialogInput {
 #[derive(Debug)]
 pub enum IdentityDialogOutput {
     GenerateIdentity(String),
+    GenerateFromPassphrase {
+        label: String,
+        passphrase: String,
+    },
     RenameIdentity {
         new_label: String,
         address: String,
@@ -88,6 +94,14 @@ impl Component for IdentityDialogModel {
                         set_buffer: &model.label,
                         connect_activate => IdentityDialogInput::HandleEntry,
                     },
+                    #[local_ref]
+                    passphrase_entry -> gtk::PasswordEntry {
+                        set_visible: matches!(model.mode, IdentityDialogMode::New),
+                        set_placeholder_text: Some("Passphrase (optional)"),
+                        set_tooltip_text: Some("Derive identity keys from the passphrase, so that the same identity can be recreated on another machine"),
+                        set_show_peek_icon: true,
+                        connect_activate => IdentityDialogInput::HandleEntry,
+                    },
                     gtk::Button {
                         set_css_classes: &["suggested-action"],
                         set_label: model.button_label.as_str(),
@@ -106,6 +120,7 @@ impl Component for IdentityDialogModel {
         let model = if let Some(name) = init {
             IdentityDialogModel {
                 label: gtk::EntryBuffer::new(Some(name.label)),
+                passphrase: gtk::PasswordEntry::new(),
                 mode: IdentityDialogMode::Edit,
                 button_label: "Rename identity".to_string(),
                 address: name.address,
@@ -114,6 +129,7 @@ impl Component for IdentityDialogModel {
         } else {
             IdentityDialogModel {
                 label: gtk::EntryBuffer::new(Some("")),
+                passphrase: gtk::PasswordEntry::new(),
                 mode: IdentityDialogMode::New,
                 button_label: "Create new identity".to_string(),
                 address: "".to_string(),
@@ -121,6 +137,7 @@ impl Component for IdentityDialogModel {
             }
         };
 
+        let passphrase_entry = &model.passphrase;
         let widgets = view_output!();
         ComponentParts { model, widgets }
     }
@@ -132,10 +149,18 @@ impl Component for IdentityDialogModel {
 
                 match self.mode {
                     IdentityDialogMode::New => {
-                        sender
-                            .output(IdentityDialogOutput::GenerateIdentity(name.to_string()))
-                            .unwrap_or_default();
+                        let passphrase = self.passphrase.text();
+                        let output = if passphrase.is_empty() {
+                            IdentityDialogOutput::GenerateIdentity(name.to_string())
+                        } else {
+                            IdentityDialogOutput::GenerateFromPassphrase {
+                                label: name.to_string(),
+                                passphrase: passphrase.to_string(),
+                            }
+                        };
+                        sender.output(output).unwrap_or_default();
                         self.label.set_text("");
+                        self.passphrase.set_text("");
                     }
                     IdentityDialogMode::Edit => {
                         sender
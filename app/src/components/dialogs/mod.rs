@@ -1 +1,6 @@
+pub mod chan_dialog;
+pub mod contact_dialog;
+pub mod database_error_dialog;
 pub mod identity_dialog;
+pub mod share_dialog;
+pub mod unlock_dialog;
@@ -0,0 +1,193 @@
+use std::{io::Cursor, path::PathBuf};
+
+use adw::{self, prelude::MessageDialogExt};
+use gtk::{self, gdk, gio, glib, prelude::*};
+use qrcode::{Color, QrCode};
+use relm4::{Component, ComponentParts, ComponentSender, RelmWidgetExt};
+
+use crate::network::uri::AddressUri;
+
+/// Size of a QR code module in pixels
+const QR_MODULE_SIZE: u32 = 8;
+/// Width of the empty border around the QR code in modules
+const QR_QUIET_ZONE: u32 = 4;
+
+pub struct ShareDialogModel {
+    uri: String,
+    png_data: Vec<u8>,
+    qr_code: gtk::Picture,
+}
+
+pub struct ShareDialogInit {
+    pub label: String,
+    pub address: String,
+}
+
+#[derive(Debug)]
+pub enum ShareDialogInput {
+    CopyUri,
+    HandleSaveImage,
+    SaveImage(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum ShareDialogCommand {
+    LoadQrCode(Vec<u8>),
+}
+
+/// Render the data as a black on white QR code in PNG format
+fn qr_code_png(data: &str) -> Result<Vec<u8>, String> {
+    let code = QrCode::new(data).map_err(|e| e.to_string())?;
+    let width = code.width() as u32;
+    let colors = code.to_colors();
+    let size = (width + 2 * QR_QUIET_ZONE) * QR_MODULE_SIZE;
+    let image = image::GrayImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x / QR_MODULE_SIZE, y / QR_MODULE_SIZE);
+        let dark = (QR_QUIET_ZONE..width + QR_QUIET_ZONE).contains(&x)
+            && (QR_QUIET_ZONE..width + QR_QUIET_ZONE).contains(&y)
+            && colors[((y - QR_QUIET_ZONE) * width + x - QR_QUIET_ZONE) as usize] == Color::Dark;
+        image::Luma([if dark { 0 } else { 255 }])
+    });
+    let mut png_data = Vec::new();
+    image
+        .write_to(
+            &mut Cursor::new(&mut png_data),
+            image::ImageOutputFormat::Png,
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(png_data)
+}
+
+#[relm4::component(pub)]
+impl Component for ShareDialogModel {
+    type Input = ShareDialogInput;
+    type Output = ();
+    type Init = ShareDialogInit;
+    type CommandOutput = ShareDialogCommand;
+
+    view! {
+        #[root]
+        adw::Window {
+            set_hide_on_close: true,
+            set_default_width: 320,
+            set_resizable: false,
+            set_modal: true,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                    set_css_classes: &["flat"],
+                    set_title_widget: Some(&gtk::Box::default())
+                },
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 20,
+                    set_spacing: 10,
+
+                    #[local_ref]
+                    qr_code -> gtk::Picture {
+                        set_size_request: (240, 240),
+                        set_can_shrink: true,
+                    },
+                    gtk::Label {
+                        set_label: &model.uri,
+                        set_selectable: true,
+                        set_wrap: true,
+                        set_wrap_mode: gtk::pango::WrapMode::Char,
+                        add_css_class: "caption",
+                    },
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Horizontal,
+                        set_halign: gtk::Align::Center,
+                        set_spacing: 10,
+
+                        gtk::Button {
+                            set_label: "Copy link",
+                            connect_clicked => ShareDialogInput::CopyUri,
+                        },
+                        gtk::Button {
+                            set_css_classes: &["suggested-action"],
+                            set_label: "Save QR code",
+                            connect_clicked => ShareDialogInput::HandleSaveImage,
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let uri = AddressUri::new(init.address, Some(init.label)).to_string();
+        let model = ShareDialogModel {
+            uri: uri.clone(),
+            png_data: Vec::new(),
+            qr_code: gtk::Picture::default(),
+        };
+        sender.oneshot_command(async move {
+            let png_data = qr_code_png(&uri).unwrap_or_else(|e| {
+                log::error!("Failed to generate QR code: {}", e);
+                Vec::new()
+            });
+            ShareDialogCommand::LoadQrCode(png_data)
+        });
+
+        let qr_code = &model.qr_code;
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update_cmd(
+        &mut self,
+        message: Self::CommandOutput,
+        _sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        match message {
+            ShareDialogCommand::LoadQrCode(png_data) => {
+                if let Ok(texture) =
+                    gdk::Texture::from_bytes(&glib::Bytes::from(png_data.as_slice()))
+                {
+                    self.qr_code.set_paintable(Some(&texture));
+                }
+                self.png_data = png_data;
+            }
+        }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, root: &Self::Root) {
+        match message {
+            ShareDialogInput::CopyUri => root.clipboard().set_text(&self.uri),
+            ShareDialogInput::HandleSaveImage => {
+                if self.png_data.is_empty() {
+                    return;
+                }
+                let dialog = gtk::FileDialog::builder()
+                    .title("Save QR code")
+                    .initial_name("address.png")
+                    .build();
+                dialog.save(Some(root), gio::Cancellable::NONE, move |res| {
+                    if let Some(path) = res.ok().and_then(|f| f.path()) {
+                        sender.input(ShareDialogInput::SaveImage(path));
+                    }
+                });
+            }
+            ShareDialogInput::SaveImage(path) => {
+                if let Err(e) = std::fs::write(path, &self.png_data) {
+                    let dialog = adw::MessageDialog::new(
+                        Some(root),
+                        Some("Failed to save QR code"),
+                        Some(&e.to_string()),
+                    );
+                    dialog.add_response("ok", "OK");
+                    dialog.present();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use adw;
+use gtk::{self, prelude::*};
+use relm4::{Component, ComponentController, ComponentParts, ComponentSender, RelmWidgetExt};
+use relm4_icons::icon_name;
+
+use crate::{app::AppModel, network, state};
+
+/// Window asking for the database password before the node is started
+pub struct UnlockDialogModel {
+    pub mode: UnlockDialogMode,
+    pub password: gtk::PasswordEntry,
+    /// Repeated password, when the new one is set
+    pub confirmation: gtk::PasswordEntry,
+    pub error: Option<String>,
+    pub checking: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum UnlockDialogMode {
+    /// Database is encrypted, ask for its password
+    Unlock,
+    /// Database is not encrypted yet, ask for a new password
+    SetPassword,
+}
+
+#[derive(Debug)]
+pub enum UnlockDialogInput {
+    Submit,
+}
+
+#[relm4::component(pub)]
+impl Component for UnlockDialogModel {
+    type Input = UnlockDialogInput;
+    type Output = ();
+    type Init = UnlockDialogMode;
+    /// Whether the password is correct
+    type CommandOutput = bool;
+
+    view! {
+        #[root]
+        adw::Window {
+            set_title: Some("Bitmessage-rs"),
+            set_default_width: 320,
+            set_resizable: false,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::HeaderBar {
+                    set_show_end_title_buttons: true,
+                    set_css_classes: &["flat"],
+                    set_title_widget: Some(&gtk::Box::default())
+                },
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 20,
+                    set_spacing: 10,
+                    gtk::Image {
+                            set_icon_size: gtk::IconSize::Large,
+                            set_icon_name: Some(icon_name::LOCK_CLOSED_REGULAR),
+                    },
+                    gtk::Label {
+                        set_css_classes: &["title-4"],
+                        set_label: match model.mode {
+                            UnlockDialogMode::Unlock => "The database is encrypted.",
+                            UnlockDialogMode::SetPassword => "You're about to encrypt the database.",
+                        },
+                    },
+                    gtk::Label {
+                        set_wrap: true,
+                        set_justify: gtk::Justification::Center,
+                        set_label: match model.mode {
+                            UnlockDialogMode::Unlock => "Enter the password to unlock it.",
+                            UnlockDialogMode::SetPassword => "Pick a password. It can't be recovered if you forget it.",
+                        },
+                    },
+                    #[local_ref]
+                    password_entry -> gtk::PasswordEntry {
+                        set_placeholder_text: Some("Password"),
+                        set_show_peek_icon: true,
+                        connect_activate => UnlockDialogInput::Submit,
+                    },
+                    #[local_ref]
+                    confirmation_entry -> gtk::PasswordEntry {
+                        set_visible: matches!(model.mode, UnlockDialogMode::SetPassword),
+                        set_placeholder_text: Some("Repeat password"),
+                        set_show_peek_icon: true,
+                        connect_activate => UnlockDialogInput::Submit,
+                    },
+                    gtk::Label {
+                        set_css_classes: &["error"],
+                        #[watch]
+                        set_visible: model.error.is_some(),
+                        #[watch]
+                        set_label: model.error.as_deref().unwrap_or_default(),
+                    },
+                    gtk::Button {
+                        set_css_classes: &["suggested-action"],
+                        set_label: match model.mode {
+                            UnlockDialogMode::Unlock => "Unlock",
+                            UnlockDialogMode::SetPassword => "Encrypt database",
+                        },
+                        #[watch]
+                        set_sensitive: !model.checking,
+                        connect_clicked => UnlockDialogInput::Submit,
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = UnlockDialogModel {
+            mode: init,
+            password: gtk::PasswordEntry::new(),
+            confirmation: gtk::PasswordEntry::new(),
+            error: None,
+            checking: false,
+        };
+
+        let password_entry = &model.password;
+        let confirmation_entry = &model.confirmation;
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
+        match message {
+            UnlockDialogInput::Submit => {
+                if self.checking {
+                    return;
+                }
+                let password = self.password.text().to_string();
+                if password.is_empty() {
+                    self.error = Some("Password must not be empty".to_string());
+                    return;
+                }
+                match self.mode {
+                    UnlockDialogMode::Unlock => {
+                        self.checking = true;
+                        self.error = None;
+                        let database_path = {
+                            let state = state::STATE.read_inner();
+                            state.config.database_path(&state.data_dir)
+                        };
+                        sender.oneshot_command(async move {
+                            network::check_database_password(&database_path, &password).await
+                        });
+                    }
+                    UnlockDialogMode::SetPassword => {
+                        if password != self.confirmation.text() {
+                            self.error = Some("Passwords don't match".to_string());
+                            return;
+                        }
+                        sender.command_sender().emit(true);
+                    }
+                }
+            }
+        }
+    }
+
+    fn update_cmd(
+        &mut self,
+        valid: Self::CommandOutput,
+        _sender: ComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        self.checking = false;
+        if !valid {
+            self.error = Some("Wrong password".to_string());
+            self.password.set_text("");
+            return;
+        }
+
+        if let Err(e) = crate::start_node(Some(self.password.text().to_string())) {
+            self.error = Some(e.to_string());
+            return;
+        }
+
+        let mut app = AppModel::builder().launch(()).detach();
+        relm4::main_application().add_window(app.widget());
+        app.widget().present();
+        app.detach_runtime();
+        root.close();
+    }
+}
@@ -0,0 +1,185 @@
+use adw::traits::{ActionRowExt, PreferencesRowExt};
+use gtk::{
+    gdk,
+    traits::{ButtonExt, ListBoxRowExt, WidgetExt},
+};
+use relm4::{
+    prelude::{DynamicIndex, FactoryComponent},
+    FactorySender, RelmWidgetExt,
+};
+use relm4_icons::icon_name;
+
+use crate::components::{contacts_list::ContactsListInput, utils::avatar};
+
+pub struct ContactListRow {
+    pub label: String,
+    pub address: String,
+    /// Broadcasts of the contact are received
+    pub subscribed: bool,
+    /// A pubkey with other keys than the pinned ones was received for the contact
+    pub key_mismatch: bool,
+    contact_avatar: gtk::Image,
+}
+
+pub struct ContactListRowInit {
+    pub label: String,
+    pub address: String,
+    pub subscribed: bool,
+    pub key_mismatch: bool,
+}
+
+#[derive(Debug)]
+pub enum ContactListRowOutput {
+    Delete(DynamicIndex),
+    Rename(DynamicIndex),
+    Share(DynamicIndex),
+    ToggleSubscription(DynamicIndex),
+    Repin(DynamicIndex),
+}
+
+#[derive(Debug)]
+pub enum ContactListRowCommand {
+    LoadIdenticon(gdk::Texture),
+}
+
+#[derive(Debug)]
+pub enum ContactListRowInput {
+    RenameLabel(String),
+    SetSubscribed(bool),
+    SetKeyMismatch(bool),
+}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for ContactListRow {
+    type Init = ContactListRowInit;
+    type Input = ContactListRowInput;
+    type Output = ContactListRowOutput;
+    type CommandOutput = ContactListRowCommand;
+    type ParentInput = ContactsListInput;
+    type ParentWidget = gtk::ListBox;
+
+    view! {
+        #[root]
+        adw::ActionRow {
+            set_selectable: false,
+            set_activatable: false,
+            #[watch]
+            set_title: if self.label.is_empty() { "No label" } else { self.label.as_str() },
+            set_subtitle: &self.address.to_string(),
+            set_subtitle_selectable: true,
+
+            #[name(contact_avatar)]
+            add_prefix = &gtk::Image {},
+
+            add_suffix = &gtk::Button {
+                set_icon_name: "dialog-warning-symbolic",
+                set_tooltip_text: Some("Received keys differ from the pinned ones, click to accept them"),
+                #[watch]
+                set_visible: self.key_mismatch,
+                add_css_class: "circular",
+                add_css_class: "flat",
+                add_css_class: "warning",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(ContactListRowOutput::Repin(index.clone()))
+                },
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: "application-rss+xml-symbolic",
+                #[watch]
+                set_tooltip_text: Some(if self.subscribed {
+                    "Unsubscribe from broadcasts"
+                } else {
+                    "Subscribe to broadcasts"
+                }),
+                #[watch]
+                set_class_active: ("accent", self.subscribed),
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(ContactListRowOutput::ToggleSubscription(index.clone()))
+                },
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: icon_name::QR_CODE,
+                set_tooltip_text: Some("Share address"),
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(ContactListRowOutput::Share(index.clone()))
+                },
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: icon_name::EDIT,
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(ContactListRowOutput::Rename(index.clone()))
+                },
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: icon_name::X_CIRCULAR,
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(ContactListRowOutput::Delete(index.clone()));
+                }
+            }
+        }
+    }
+
+    fn init_model(init: Self::Init, _index: &Self::Index, _sender: FactorySender<Self>) -> Self {
+        Self {
+            label: init.label,
+            address: init.address,
+            subscribed: init.subscribed,
+            key_mismatch: init.key_mismatch,
+            contact_avatar: gtk::Image::default(),
+        }
+    }
+
+    fn init_widgets(
+        &mut self,
+        index: &Self::Index,
+        root: &Self::Root,
+        _returned_widget: &<Self::ParentWidget as relm4::factory::FactoryView>::ReturnedWidget,
+        sender: FactorySender<Self>,
+    ) -> Self::Widgets {
+        let widgets = view_output!();
+
+        self.contact_avatar = widgets.contact_avatar.clone();
+        let address = self.address.clone();
+        sender.oneshot_command(async move {
+            ContactListRowCommand::LoadIdenticon(avatar::load_avatar(address).await)
+        });
+
+        widgets
+    }
+
+    fn forward_to_parent(output: Self::Output) -> Option<Self::ParentInput> {
+        Some(match output {
+            ContactListRowOutput::Delete(i) => ContactsListInput::DeleteContact(i),
+            ContactListRowOutput::Rename(i) => ContactsListInput::HandleRenameContact(i),
+            ContactListRowOutput::Share(i) => ContactsListInput::HandleShareContact(i),
+            ContactListRowOutput::ToggleSubscription(i) => ContactsListInput::ToggleSubscription(i),
+            ContactListRowOutput::Repin(i) => ContactsListInput::HandleRepinContact(i),
+        })
+    }
+
+    fn update_cmd(&mut self, message: Self::CommandOutput, _sender: FactorySender<Self>) {
+        match message {
+            ContactListRowCommand::LoadIdenticon(texture) => {
+                self.contact_avatar.set_paintable(Some(&texture));
+            }
+        }
+    }
+
+    fn update(&mut self, message: Self::Input, _sender: FactorySender<Self>) {
+        match message {
+            ContactListRowInput::RenameLabel(new_label) => {
+                self.label = new_label;
+            }
+            ContactListRowInput::SetSubscribed(subscribed) => self.subscribed = subscribed,
+            ContactListRowInput::SetKeyMismatch(mismatch) => self.key_mismatch = mismatch,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use adw::traits::{ActionRowExt, PreferencesRowExt};
+use gtk::traits::{ButtonExt, ListBoxRowExt, SwitchExt, WidgetExt};
+use nantoka_core::repositories::models::FilterRule;
+use relm4::{
+    prelude::{DynamicIndex, FactoryComponent},
+    FactorySender,
+};
+use relm4_icons::icon_name;
+
+use crate::components::rules_list::RulesListInput;
+
+pub struct FilterRuleRow {
+    pub rule: FilterRule,
+}
+
+#[derive(Debug)]
+pub enum FilterRuleRowOutput {
+    Delete(DynamicIndex),
+    Edit(DynamicIndex),
+    SetEnabled(DynamicIndex, bool),
+}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for FilterRuleRow {
+    type Init = FilterRule;
+    type Input = ();
+    type Output = FilterRuleRowOutput;
+    type CommandOutput = ();
+    type ParentInput = RulesListInput;
+    type ParentWidget = gtk::ListBox;
+
+    view! {
+        #[root]
+        adw::ActionRow {
+            set_selectable: false,
+            set_activatable: false,
+            set_title: if self.rule.label.is_empty() { "No label" } else { self.rule.label.as_str() },
+            set_subtitle: &describe(&self.rule),
+
+            add_suffix = &gtk::Switch {
+                set_valign: gtk::Align::Center,
+                set_tooltip_text: Some("Apply the rule to received messages"),
+                set_active: self.rule.enabled,
+                connect_active_notify[sender, index] => move |switch| {
+                    sender.output(FilterRuleRowOutput::SetEnabled(index.clone(), switch.is_active()))
+                },
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: icon_name::EDIT,
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(FilterRuleRowOutput::Edit(index.clone()))
+                },
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: icon_name::X_CIRCULAR,
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(FilterRuleRowOutput::Delete(index.clone()));
+                }
+            }
+        }
+    }
+
+    fn init_model(rule: Self::Init, _index: &Self::Index, _sender: FactorySender<Self>) -> Self {
+        Self { rule }
+    }
+
+    fn forward_to_parent(output: Self::Output) -> Option<Self::ParentInput> {
+        Some(match output {
+            FilterRuleRowOutput::Delete(i) => RulesListInput::DeleteRule(i),
+            FilterRuleRowOutput::Edit(i) => RulesListInput::HandleEditRule(i),
+            FilterRuleRowOutput::SetEnabled(i, enabled) => RulesListInput::SetEnabled(i, enabled),
+        })
+    }
+}
+
+/// Conditions and actions of the rule, e.g. `Subject contains "sale" → Move to Trash`
+fn describe(rule: &FilterRule) -> String {
+    let mut conditions = Vec::new();
+    if let Some(sender) = &rule.sender {
+        conditions.push(format!("From {}", sender));
+    }
+    if let Some(identity) = &rule.identity {
+        conditions.push(format!("To {}", identity));
+    }
+    if let Some(subject) = &rule.subject_contains {
+        conditions.push(format!("Subject contains \"{}\"", subject));
+    }
+    if let Some(min) = rule.min_size {
+        conditions.push(format!("Larger than {} KiB", min / 1024));
+    }
+    if let Some(max) = rule.max_size {
+        conditions.push(format!("Smaller than {} KiB", max / 1024));
+    }
+    if conditions.is_empty() {
+        conditions.push("Any message".to_string());
+    }
+
+    let mut actions = Vec::new();
+    if rule.delete_message {
+        actions.push("Delete".to_string());
+    } else {
+        if let Some(folder) = &rule.move_to {
+            actions.push(format!("Move to {}", folder));
+        }
+        if rule.mark_read {
+            actions.push("Mark as read".to_string());
+        }
+    }
+    if rule.notify {
+        actions.push("Notify".to_string());
+    }
+    format!("{} → {}", conditions.join(", "), actions.join(", "))
+}
@@ -1,6 +1,6 @@
 use adw::traits::{ActionRowExt, PreferencesRowExt};
 use gtk::{
-    gdk, glib,
+    gdk,
     traits::{ButtonExt, ListBoxRowExt, WidgetExt},
 };
 use relm4::{
@@ -9,23 +9,35 @@ use relm4::{
 };
 use relm4_icons::icon_name;
 
-use crate::components::identities_list::IdentitiesListInput;
+use crate::{
+    components::{identities_list::IdentitiesListInput, utils::avatar},
+    state,
+};
 
 pub struct IdentityListRow {
     pub label: String,
     pub address: String,
+    pub chan: bool,
+    /// Desktop notifications about messages of the identity are disabled
+    muted: bool,
     identity_avatar: gtk::Image,
 }
 
 pub struct IdentityListRowInit {
     pub label: String,
     pub address: String,
+    pub chan: bool,
 }
 
 #[derive(Debug)]
 pub enum IdentityListRowOutput {
     DeleteIdentity(DynamicIndex),
     RenameIdentity(DynamicIndex),
+    RotateKeys(DynamicIndex),
+    ShareIdentity(DynamicIndex),
+    ManageDevices(DynamicIndex),
+    EditStrangerPolicy(DynamicIndex),
+    EditAutoReply(DynamicIndex),
 }
 
 #[derive(Debug)]
@@ -36,6 +48,7 @@ pub enum IdentityListRowCommand {
 #[derive(Debug)]
 pub enum IdentityListRowInput {
     RenameLabel(String),
+    ToggleMute,
 }
 
 #[relm4::factory(pub)]
@@ -60,6 +73,30 @@ impl FactoryComponent for IdentityListRow {
             #[name(identity_avatar)]
             add_prefix = &gtk::Image {},
 
+            add_suffix = &gtk::Label {
+                set_visible: self.chan,
+                set_label: "chan",
+                set_css_classes: &["caption", "dim-label"],
+            },
+
+            add_suffix = &gtk::Button {
+                #[watch]
+                set_icon_name: if self.muted { icon_name::ALERT_OFF_REGULAR } else { icon_name::ALERT_REGULAR },
+                #[watch]
+                set_tooltip_text: Some(if self.muted { "Unmute notifications" } else { "Mute notifications" }),
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked => IdentityListRowInput::ToggleMute,
+            },
+            add_suffix = &gtk::Button {
+                set_icon_name: icon_name::QR_CODE,
+                set_tooltip_text: Some("Share address"),
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(IdentityListRowOutput::ShareIdentity(index.clone()))
+                },
+            },
             add_suffix = &gtk::Button {
                 set_icon_name: icon_name::EDIT,
                 add_css_class: "circular",
@@ -68,8 +105,49 @@ impl FactoryComponent for IdentityListRow {
                     sender.output(IdentityListRowOutput::RenameIdentity(index.clone()))
                 },
             },
+            add_suffix = &gtk::Button {
+                set_visible: !self.chan,
+                set_icon_name: "computer-symbolic",
+                set_tooltip_text: Some("Devices"),
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(IdentityListRowOutput::ManageDevices(index.clone()))
+                },
+            },
+            add_suffix = &gtk::Button {
+                set_visible: !self.chan,
+                set_icon_name: "security-high-symbolic",
+                set_tooltip_text: Some("Messages from strangers"),
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(IdentityListRowOutput::EditStrangerPolicy(index.clone()))
+                },
+            },
+            add_suffix = &gtk::Button {
+                set_visible: !self.chan,
+                set_icon_name: "mail-reply-sender-symbolic",
+                set_tooltip_text: Some("Auto-reply"),
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(IdentityListRowOutput::EditAutoReply(index.clone()))
+                },
+            },
+            add_suffix = &gtk::Button {
+                set_visible: !self.chan,
+                set_icon_name: icon_name::ARROW_SYNC_REGULAR,
+                set_tooltip_text: Some("Rotate keys"),
+                add_css_class: "circular",
+                add_css_class: "flat",
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(IdentityListRowOutput::RotateKeys(index.clone()))
+                },
+            },
             add_suffix = &gtk::Button {
                 set_icon_name: icon_name::X_CIRCULAR,
+                set_tooltip_text: Some(if self.chan { "Leave chan" } else { "Delete identity" }),
                 add_css_class: "circular",
                 add_css_class: "flat",
                 connect_clicked[sender, index] => move |_| {
@@ -80,9 +158,12 @@ impl FactoryComponent for IdentityListRow {
     }
 
     fn init_model(init: Self::Init, _index: &Self::Index, _sender: FactorySender<Self>) -> Self {
+        let muted = state::STATE.read_inner().settings.is_muted(&init.address);
         Self {
             label: init.label,
             address: init.address,
+            chan: init.chan,
+            muted,
             identity_avatar: gtk::Image::default(),
         }
     }
@@ -99,10 +180,7 @@ impl FactoryComponent for IdentityListRow {
         self.identity_avatar = widgets.identity_avatar.clone();
         let address = self.address.clone();
         sender.oneshot_command(async move {
-            let png_data = identicon_rs::new(address).export_png_data().unwrap();
-            let texture =
-                gdk::Texture::from_bytes(&glib::Bytes::from(png_data.as_slice())).unwrap();
-            IdentityListRowCommand::LoadIdenticon(texture)
+            IdentityListRowCommand::LoadIdenticon(avatar::load_avatar(address).await)
         });
 
         widgets
@@ -114,6 +192,13 @@ impl FactoryComponent for IdentityListRow {
             IdentityListRowOutput::RenameIdentity(i) => {
                 IdentitiesListInput::HandleRenameIdentity(i)
             }
+            IdentityListRowOutput::RotateKeys(i) => IdentitiesListInput::HandleRotateIdentity(i),
+            IdentityListRowOutput::ShareIdentity(i) => IdentitiesListInput::HandleShareIdentity(i),
+            IdentityListRowOutput::ManageDevices(i) => IdentitiesListInput::HandleManageDevices(i),
+            IdentityListRowOutput::EditStrangerPolicy(i) => {
+                IdentitiesListInput::HandleStrangerPolicy(i)
+            }
+            IdentityListRowOutput::EditAutoReply(i) => IdentitiesListInput::HandleAutoReply(i),
         })
     }
 
@@ -130,6 +215,13 @@ impl FactoryComponent for IdentityListRow {
             IdentityListRowInput::RenameLabel(new_label) => {
                 self.label = new_label;
             }
+            IdentityListRowInput::ToggleMute => {
+                self.muted = !self.muted;
+
+                let mut state = state::STATE.write_inner();
+                state.settings.set_muted(&self.address, self.muted);
+                state.settings.save();
+            }
         }
     }
 }
@@ -0,0 +1,55 @@
+use adw::traits::{ActionRowExt, PreferencesRowExt};
+use gtk::traits::{ListBoxRowExt, WidgetExt};
+use nantoka_core::repositories::inventory::InventoryItem;
+use relm4::{
+    prelude::{DynamicIndex, FactoryComponent},
+    FactorySender,
+};
+
+use crate::components::{messages_content::format_expiration, network_status::NetworkStatusInput};
+
+/// Stored object, shown for debugging
+pub(crate) struct InventoryListRow {
+    pub item: InventoryItem,
+}
+
+#[relm4::factory(pub(crate))]
+impl FactoryComponent for InventoryListRow {
+    type Init = InventoryItem;
+    type Input = ();
+    type Output = ();
+    type CommandOutput = ();
+    type ParentInput = NetworkStatusInput;
+    type ParentWidget = gtk::ListBox;
+
+    view! {
+        #[root]
+        adw::ActionRow {
+            set_selectable: false,
+            set_activatable: false,
+            set_title: &self.item.hash,
+            set_title_selectable: true,
+            set_subtitle: &self
+                .item
+                .object_type
+                .map_or("unknown".to_string(), |t| t.to_string()),
+
+            add_suffix = &gtk::Label {
+                set_label: &format_expiration(Some(self.item.expires)),
+                set_tooltip_text: Some(
+                    &self
+                        .item
+                        .expires
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string(),
+                ),
+                add_css_class: "dim-label",
+            }
+        }
+    }
+
+    fn init_model(init: Self::Init, _index: &DynamicIndex, _sender: FactorySender<Self>) -> Self {
+        Self { item: init }
+    }
+}
@@ -1 +1,5 @@
+pub mod contact_list_row;
+pub mod filter_rule_row;
 pub mod identity_list_row;
+pub mod inventory_list_row;
+pub mod peer_list_row;
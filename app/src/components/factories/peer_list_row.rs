@@ -0,0 +1,67 @@
+use adw::traits::{ActionRowExt, PreferencesRowExt};
+use gtk::traits::{ButtonExt, ListBoxRowExt, WidgetExt};
+use relm4::{
+    prelude::{DynamicIndex, FactoryComponent},
+    FactorySender,
+};
+
+use crate::components::network_status::NetworkStatusInput;
+
+/// Connected peer which can be banned, or a ban which can be lifted
+pub(crate) struct PeerListRow {
+    /// Peer id or banned multiaddr
+    pub target: String,
+    pub subtitle: String,
+    pub banned: bool,
+}
+
+#[derive(Debug)]
+pub(crate) enum PeerListRowOutput {
+    Ban(String),
+    Unban(String),
+}
+
+#[relm4::factory(pub(crate))]
+impl FactoryComponent for PeerListRow {
+    type Init = PeerListRow;
+    type Input = ();
+    type Output = PeerListRowOutput;
+    type CommandOutput = ();
+    type ParentInput = NetworkStatusInput;
+    type ParentWidget = gtk::ListBox;
+
+    view! {
+        #[root]
+        adw::ActionRow {
+            set_selectable: false,
+            set_activatable: false,
+            set_title: &self.target,
+            set_title_selectable: true,
+            set_subtitle: &self.subtitle,
+
+            add_suffix = &gtk::Button {
+                set_label: if self.banned { "Unban" } else { "Ban" },
+                set_valign: gtk::Align::Center,
+                add_css_class: if self.banned { "flat" } else { "destructive-action" },
+                connect_clicked[sender, target = self.target.clone(), banned = self.banned] => move |_| {
+                    sender.output(if banned {
+                        PeerListRowOutput::Unban(target.clone())
+                    } else {
+                        PeerListRowOutput::Ban(target.clone())
+                    });
+                }
+            }
+        }
+    }
+
+    fn init_model(init: Self::Init, _index: &DynamicIndex, _sender: FactorySender<Self>) -> Self {
+        init
+    }
+
+    fn forward_to_parent(output: Self::Output) -> Option<Self::ParentInput> {
+        Some(match output {
+            PeerListRowOutput::Ban(target) => NetworkStatusInput::BanPeer(target),
+            PeerListRowOutput::Unban(target) => NetworkStatusInput::UnbanPeer(target),
+        })
+    }
+}
@@ -1,4 +1,7 @@
-use gtk::{self, prelude::*};
+use std::path::PathBuf;
+
+use adw::prelude::*;
+use gtk::{self, gio};
 use relm4::factory::FactoryVecDeque;
 use relm4::prelude::DynamicIndex;
 use relm4::{
@@ -10,9 +13,13 @@ use relm4::{Component, ComponentController, Controller, RelmWidgetExt};
 
 use crate::components::dialogs::identity_dialog::IdentityDialogOutput;
 
+use nantoka_core::repositories::models::AutoReply;
+
 use crate::state;
 
+use super::dialogs::chan_dialog::{ChanDialogModel, ChanDialogOutput};
 use super::dialogs::identity_dialog::{IdentityDialogInit, IdentityDialogModel};
+use super::dialogs::share_dialog::{ShareDialogInit, ShareDialogModel};
 use super::factories::identity_list_row::{
     IdentityListRow, IdentityListRowInit, IdentityListRowInput,
 };
@@ -63,8 +70,12 @@ use super::factories::identity_list_row::{
 
 pub(crate) struct IdentitiesListModel {
     is_list_empty: bool,
+    /// Several identities can be selected for batch operations
+    selection_mode: bool,
     //list_view_wrapper: TypedListView<IdentityItem, gtk::SingleSelection, gtk::ColumnView>,
     identity_dialog: Controller<IdentityDialogModel>,
+    chan_dialog: Controller<ChanDialogModel>,
+    share_dialog: Option<Controller<ShareDialogModel>>,
     list_view: FactoryVecDeque<IdentityListRow>,
 }
 
@@ -74,6 +85,15 @@ pub enum IdentitiesListInput {
     GenerateNewIdentity {
         label: String,
     },
+    GenerateDeterministicIdentity {
+        label: String,
+        passphrase: String,
+    },
+    HandleJoinChan,
+    JoinChan {
+        name: String,
+        address: Option<String>,
+    },
     DeleteIdentity(DynamicIndex),
     HandleRenameIdentity(DynamicIndex),
     RenameIdentity {
@@ -81,6 +101,42 @@ pub enum IdentitiesListInput {
         address: String,
         index: usize,
     },
+    HandleRotateIdentity(DynamicIndex),
+    RotateIdentity {
+        address: String,
+        archive_old: bool,
+    },
+    SetSelectionMode(bool),
+    HandleDeleteSelected,
+    DeleteIdentities(Vec<String>),
+    ExportSelected,
+    HandleExportIdentities,
+    ExportIdentities(Vec<String>),
+    SaveExport {
+        addresses: Vec<String>,
+        path: PathBuf,
+    },
+    HandleImportIdentities,
+    ImportIdentities(PathBuf),
+    HandleShareIdentity(DynamicIndex),
+    HandleManageDevices(DynamicIndex),
+    PairDevice {
+        code: String,
+        label: String,
+    },
+    HandleStrangerPolicy(DynamicIndex),
+    SetStrangerPolicy {
+        address: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+        quarantine: bool,
+    },
+    HandleAutoReply(DynamicIndex),
+    /// Set auto-reply of the identity, or remove it if it's `None`
+    SetAutoReply {
+        address: String,
+        auto_reply: Option<AutoReply>,
+    },
 }
 
 #[derive(Debug)]
@@ -89,7 +145,40 @@ pub enum IdentitiesListOutput {
     IdentitiesListUpdated,
 }
 
+/// Show simple dialog with a message and OK button
+fn show_message(root: &gtk::ScrolledWindow, heading: &str, body: &str) {
+    let dialog = adw::MessageDialog::new(
+        root.root().and_downcast_ref::<gtk::Window>(),
+        Some(heading),
+        Some(body),
+    );
+    dialog.add_response("ok", "OK");
+    dialog.present();
+}
+
 impl IdentitiesListModel {
+    /// Addresses of the identities selected in the selection mode
+    fn selected_addresses(&self) -> Vec<String> {
+        self.list_view
+            .widget()
+            .selected_rows()
+            .iter()
+            .filter_map(|row| self.list_view.get(row.index() as usize))
+            .map(|i| i.address.clone())
+            .collect()
+    }
+
+    fn set_selection_mode(&mut self, active: bool) {
+        self.selection_mode = active;
+        let list = self.list_view.widget();
+        if active {
+            list.set_selection_mode(gtk::SelectionMode::Multiple);
+        } else {
+            list.unselect_all();
+            list.set_selection_mode(gtk::SelectionMode::Single);
+        }
+    }
+
     async fn reload_list(&mut self, sender: relm4::AsyncComponentSender<Self>) {
         let identities = state::STATE
             .write_inner()
@@ -98,6 +187,13 @@ impl IdentitiesListModel {
             .unwrap()
             .get_own_identities()
             .await;
+        let identities = match identities {
+            Ok(i) => i,
+            Err(e) => {
+                log::error!("Failed to load identities: {}", e);
+                return;
+            }
+        };
         if !identities.is_empty() {
             self.is_list_empty = false;
             sender
@@ -115,6 +211,7 @@ impl IdentitiesListModel {
             guard.push_back(IdentityListRowInit {
                 label: i.label,
                 address: i.string_repr,
+                chan: i.chan,
             });
         }
     }
@@ -129,6 +226,9 @@ impl IdentitiesListModel {
                 IdentityDialogOutput::GenerateIdentity(label) => {
                     IdentitiesListInput::GenerateNewIdentity { label }
                 }
+                IdentityDialogOutput::GenerateFromPassphrase { label, passphrase } => {
+                    IdentitiesListInput::GenerateDeterministicIdentity { label, passphrase }
+                }
                 IdentityDialogOutput::RenameIdentity {
                     new_label,
                     address,
@@ -179,12 +279,66 @@ impl AsyncComponent for IdentitiesListModel {
                     //    #[watch]
                     //    set_visible: !model.is_list_empty,
                     //}
-                    #[local]
-                    list_view -> gtk::ListBox {
-                        set_valign: gtk::Align::Start,
-                        set_margin_top: 12,
-                        set_margin_bottom: 12,
-                        add_css_class: "boxed-list",
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+                        set_valign: gtk::Align::Center,
+
+                        #[local]
+                        list_view -> gtk::ListBox {
+                            set_valign: gtk::Align::Start,
+                            set_margin_top: 12,
+                            set_margin_bottom: 12,
+                            add_css_class: "boxed-list",
+                        },
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Horizontal,
+                            set_halign: gtk::Align::Center,
+                            set_spacing: 6,
+                            set_margin_bottom: 12,
+                            #[watch]
+                            set_visible: model.selection_mode,
+
+                            gtk::Button {
+                                set_label: "Export selected…",
+                                connect_clicked => IdentitiesListInput::ExportSelected
+                            },
+                            gtk::Button {
+                                set_label: "Delete selected…",
+                                add_css_class: "destructive-action",
+                                connect_clicked => IdentitiesListInput::HandleDeleteSelected
+                            },
+                        },
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Horizontal,
+                            set_halign: gtk::Align::Center,
+                            set_spacing: 6,
+                            set_margin_bottom: 12,
+
+                            gtk::ToggleButton {
+                                set_label: "Select",
+                                #[watch]
+                                set_visible: !model.is_list_empty,
+                                #[watch]
+                                set_active: model.selection_mode,
+                                connect_toggled[sender] => move |b| {
+                                    sender.input(IdentitiesListInput::SetSelectionMode(b.is_active()));
+                                }
+                            },
+                            gtk::Button {
+                                set_label: "Export keys…",
+                                #[watch]
+                                set_visible: !model.is_list_empty,
+                                connect_clicked => IdentitiesListInput::HandleExportIdentities
+                            },
+                            gtk::Button {
+                                set_label: "Import keys…",
+                                connect_clicked => IdentitiesListInput::HandleImportIdentities
+                            },
+                            gtk::Button {
+                                set_label: "Join chan…",
+                                connect_clicked => IdentitiesListInput::HandleJoinChan
+                            }
+                        }
                     }
                 }
             }
@@ -224,8 +378,18 @@ impl AsyncComponent for IdentitiesListModel {
 
         let mut model = Self {
             is_list_empty: true,
+            selection_mode: false,
             list_view: list_view_factory,
             identity_dialog: Self::create_identity_dialog_controller(sender.clone(), None),
+            chan_dialog: ChanDialogModel::builder().launch(()).forward(
+                sender.input_sender(),
+                |message| match message {
+                    ChanDialogOutput::JoinChan { name, address } => {
+                        IdentitiesListInput::JoinChan { name, address }
+                    }
+                },
+            ),
+            share_dialog: None,
         };
 
         model.reload_list(sender.clone()).await;
@@ -238,7 +402,7 @@ impl AsyncComponent for IdentitiesListModel {
         &mut self,
         message: Self::Input,
         sender: relm4::AsyncComponentSender<Self>,
-        _root: &Self::Root,
+        root: &Self::Root,
     ) {
         match message {
             IdentitiesListInput::HandleCreateNewIdentity => {
@@ -254,9 +418,18 @@ impl AsyncComponent for IdentitiesListModel {
                     .unwrap()
                     .generate_new_identity(label.clone())
                     .await;
-                self.list_view
-                    .guard()
-                    .push_back(IdentityListRowInit { label, address });
+                let address = match address {
+                    Ok(a) => a,
+                    Err(e) => {
+                        show_message(root, "Failed to create identity", &e.to_string());
+                        return;
+                    }
+                };
+                self.list_view.guard().push_back(IdentityListRowInit {
+                    label,
+                    address,
+                    chan: false,
+                });
                 if self.is_list_empty {
                     self.is_list_empty = false;
                     sender
@@ -267,19 +440,69 @@ impl AsyncComponent for IdentitiesListModel {
                     .output(IdentitiesListOutput::IdentitiesListUpdated)
                     .unwrap();
             }
+            IdentitiesListInput::GenerateDeterministicIdentity { label, passphrase } => {
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .generate_deterministic_identity(passphrase, label)
+                    .await;
+                match result {
+                    Ok(_) => {
+                        self.reload_list(sender.clone()).await;
+                        sender
+                            .output(IdentitiesListOutput::IdentitiesListUpdated)
+                            .unwrap();
+                    }
+                    Err(e) => {
+                        let dialog = adw::MessageDialog::new(
+                            root.root().and_downcast_ref::<gtk::Window>(),
+                            Some("Failed to create identity"),
+                            Some(&e.to_string()),
+                        );
+                        dialog.add_response("ok", "OK");
+                        dialog.present();
+                    }
+                }
+            }
+            IdentitiesListInput::HandleJoinChan => self.chan_dialog.widget().present(),
+            IdentitiesListInput::JoinChan { name, address } => {
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .join_chan(name, address)
+                    .await;
+                match result {
+                    Ok(_) => {
+                        self.reload_list(sender.clone()).await;
+                        sender
+                            .output(IdentitiesListOutput::IdentitiesListUpdated)
+                            .unwrap();
+                    }
+                    Err(e) => show_message(root, "Failed to join chan", &e.to_string()),
+                }
+            }
             IdentitiesListInput::DeleteIdentity(i) => {
                 let item = self
                     .list_view
                     .guard()
                     .remove(i.current_index())
                     .expect("identity to be existing");
-                state::STATE
+                let result = state::STATE
                     .write_inner()
                     .client
                     .as_mut()
                     .unwrap()
                     .delete_identity(item.address)
                     .await;
+                if let Err(e) = result {
+                    log::error!("Failed to delete identity: {}", e);
+                    self.reload_list(sender.clone()).await;
+                    return;
+                }
                 if self.list_view.len() == 0 {
                     self.is_list_empty = true;
                     sender
@@ -306,24 +529,555 @@ impl AsyncComponent for IdentitiesListModel {
                 );
                 self.identity_dialog.widget().present();
             }
+            IdentitiesListInput::HandleShareIdentity(i) => {
+                let guard = self.list_view.guard();
+                let identity_item = guard
+                    .get(i.current_index())
+                    .expect("identity to be existing");
+                let share_dialog = ShareDialogModel::builder()
+                    .launch(ShareDialogInit {
+                        label: identity_item.label.clone(),
+                        address: identity_item.address.clone(),
+                    })
+                    .detach();
+                share_dialog.widget().present();
+                self.share_dialog = Some(share_dialog);
+            }
+            IdentitiesListInput::HandleManageDevices(i) => {
+                let address = self
+                    .list_view
+                    .guard()
+                    .get(i.current_index())
+                    .expect("identity to be existing")
+                    .address
+                    .clone();
+                let devices = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .get_device_keys(address)
+                    .await;
+                let devices = match devices {
+                    Ok(d) => d,
+                    Err(e) => {
+                        show_message(root, "Failed to load devices", &e.to_string());
+                        return;
+                    }
+                };
+                let body = if devices.is_empty() {
+                    "Messages to this identity are only received by this device.".to_string()
+                } else {
+                    let labels: Vec<&str> = devices
+                        .iter()
+                        .map(|d| {
+                            if d.label.is_empty() {
+                                "unnamed"
+                            } else {
+                                d.label.as_str()
+                            }
+                        })
+                        .collect();
+                    format!("Paired devices: {}.", labels.join(", "))
+                };
+
+                let fields = gtk::Box::new(gtk::Orientation::Vertical, 6);
+                let code_entry = gtk::Entry::builder()
+                    .placeholder_text("Pairing code from the other device")
+                    .build();
+                let label_entry = gtk::Entry::builder()
+                    .placeholder_text("Device name, e.g. server")
+                    .build();
+                fields.append(&code_entry);
+                fields.append(&label_entry);
+
+                let dialog = adw::MessageDialog::new(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    Some("Devices"),
+                    Some(&format!("{} Paired devices get their own copies of messages sent to this identity. Create the pairing code on the other device, e.g. with create_device_key API method.", body)),
+                );
+                dialog.set_extra_child(Some(&fields));
+                dialog.add_responses(&[("cancel", "Cancel"), ("pair", "Pair")]);
+                dialog.set_response_appearance("pair", adw::ResponseAppearance::Suggested);
+                dialog.set_default_response(Some("pair"));
+                dialog.set_close_response("cancel");
+                dialog.connect_response(None, move |_, response| {
+                    let code = code_entry.text().to_string();
+                    if response != "pair" || code.trim().is_empty() {
+                        return;
+                    }
+                    sender.input(IdentitiesListInput::PairDevice {
+                        code,
+                        label: label_entry.text().to_string(),
+                    });
+                });
+                dialog.present();
+            }
+            IdentitiesListInput::PairDevice { code, label } => {
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .pair_device(code, label)
+                    .await;
+                match result {
+                    Ok(()) => show_message(
+                        root,
+                        "Device paired",
+                        "Contacts will send copies of messages to the device once they get the new public key of the identity.",
+                    ),
+                    Err(e) => show_message(root, "Failed to pair device", &e.to_string()),
+                }
+            }
+            IdentitiesListInput::HandleStrangerPolicy(i) => {
+                let address = self
+                    .list_view
+                    .guard()
+                    .get(i.current_index())
+                    .expect("identity to be existing")
+                    .address
+                    .clone();
+                let identities = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .get_own_identities()
+                    .await;
+                let identity = match identities {
+                    Ok(i) => i.into_iter().find(|i| i.string_repr == address),
+                    Err(e) => {
+                        show_message(root, "Failed to load identity", &e.to_string());
+                        return;
+                    }
+                };
+                let Some(identity) = identity else {
+                    return;
+                };
+
+                // difficulty for strangers is picked as a multiple of the one for contacts
+                let fields = gtk::Box::new(gtk::Orientation::Vertical, 6);
+                let multiplier = gtk::SpinButton::with_range(1.0, 100.0, 1.0);
+                multiplier.set_value(
+                    f64::from(identity.stranger_nonce_trials_per_byte)
+                        / f64::from(identity.nonce_trials_per_byte),
+                );
+                multiplier.set_tooltip_text(Some(
+                    "How many times more proof of work strangers have to do than contacts",
+                ));
+                let quarantine = gtk::CheckButton::with_label(
+                    "Put messages with less work into Quarantine instead of dropping them",
+                );
+                quarantine.set_active(identity.quarantine_strangers);
+                fields.append(&multiplier);
+                fields.append(&quarantine);
+
+                let dialog = adw::MessageDialog::new(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    Some("Messages from strangers"),
+                    Some("Senders which aren't your contacts have to do more proof of work to reach this identity. They learn it from the public key of the identity."),
+                );
+                dialog.set_extra_child(Some(&fields));
+                dialog.add_responses(&[("cancel", "Cancel"), ("save", "Save")]);
+                dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+                dialog.set_default_response(Some("save"));
+                dialog.set_close_response("cancel");
+                dialog.connect_response(None, move |_, response| {
+                    if response != "save" {
+                        return;
+                    }
+                    let base = f64::from(identity.nonce_trials_per_byte);
+                    sender.input(IdentitiesListInput::SetStrangerPolicy {
+                        address: address.clone(),
+                        nonce_trials_per_byte: (base * multiplier.value()) as i32,
+                        extra_bytes: identity.extra_bytes,
+                        quarantine: quarantine.is_active(),
+                    });
+                });
+                dialog.present();
+            }
+            IdentitiesListInput::SetStrangerPolicy {
+                address,
+                nonce_trials_per_byte,
+                extra_bytes,
+                quarantine,
+            } => {
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .set_stranger_policy(address, nonce_trials_per_byte, extra_bytes, quarantine)
+                    .await;
+                if let Err(e) = result {
+                    show_message(root, "Failed to save settings", &e.to_string());
+                }
+            }
+            IdentitiesListInput::HandleAutoReply(i) => {
+                let address = self
+                    .list_view
+                    .guard()
+                    .get(i.current_index())
+                    .expect("identity to be existing")
+                    .address
+                    .clone();
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .get_auto_reply(address.clone())
+                    .await;
+                let existing = match result {
+                    Ok(r) => r,
+                    Err(e) => {
+                        show_message(root, "Failed to load auto-reply", &e.to_string());
+                        return;
+                    }
+                };
+
+                let fields = gtk::Box::new(gtk::Orientation::Vertical, 6);
+                let enabled = gtk::CheckButton::with_label("Reply to received messages");
+                enabled.set_active(existing.is_some());
+                let prefix_entry = gtk::Entry::builder()
+                    .placeholder_text("Subject prefix, e.g. Re:")
+                    .text(existing.as_ref().map_or("Re: ", |r| &r.subject_prefix))
+                    .build();
+                let body_view = gtk::TextView::builder()
+                    .wrap_mode(gtk::WrapMode::WordChar)
+                    .top_margin(6)
+                    .bottom_margin(6)
+                    .left_margin(6)
+                    .right_margin(6)
+                    .build();
+                body_view
+                    .buffer()
+                    .set_text(existing.as_ref().map_or("", |r| &r.body));
+                let body_scroll = gtk::ScrolledWindow::builder()
+                    .min_content_height(120)
+                    .child(&body_view)
+                    .build();
+                body_scroll.add_css_class("card");
+                let interval = gtk::SpinButton::with_range(1.0, 24.0 * 30.0, 1.0);
+                interval.set_value(existing.as_ref().map_or(24.0, |r| r.interval_hours as f64));
+                interval.set_tooltip_text(Some(
+                    "Each sender gets at most one reply within this many hours",
+                ));
+                fields.append(&enabled);
+                fields.append(&prefix_entry);
+                fields.append(&body_scroll);
+                fields.append(&interval);
+
+                let dialog = adw::MessageDialog::new(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    Some("Auto-reply"),
+                    Some("Messages to this identity are answered automatically, e.g. while you're away. Automatic messages aren't replied to."),
+                );
+                dialog.set_extra_child(Some(&fields));
+                dialog.add_responses(&[("cancel", "Cancel"), ("save", "Save")]);
+                dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+                dialog.set_default_response(Some("save"));
+                dialog.set_close_response("cancel");
+                dialog.connect_response(None, move |_, response| {
+                    if response != "save" {
+                        return;
+                    }
+                    let buffer = body_view.buffer();
+                    let auto_reply = enabled.is_active().then(|| AutoReply {
+                        address: address.clone(),
+                        subject_prefix: prefix_entry.text().to_string(),
+                        body: buffer
+                            .text(&buffer.start_iter(), &buffer.end_iter(), false)
+                            .to_string(),
+                        interval_hours: interval.value() as i64,
+                    });
+                    sender.input(IdentitiesListInput::SetAutoReply {
+                        address: address.clone(),
+                        auto_reply,
+                    });
+                });
+                dialog.present();
+            }
+            IdentitiesListInput::SetAutoReply {
+                address,
+                auto_reply,
+            } => {
+                let mut state = state::STATE.write_inner();
+                let client = state.client.as_mut().unwrap();
+                let result = match auto_reply {
+                    Some(auto_reply) => client.set_auto_reply(auto_reply).await,
+                    None => client.remove_auto_reply(address).await,
+                };
+                drop(state);
+                if let Err(e) = result {
+                    show_message(root, "Failed to save auto-reply", &e.to_string());
+                }
+            }
             IdentitiesListInput::RenameIdentity {
                 new_label,
                 address,
                 index,
             } => {
-                state::STATE
+                let result = state::STATE
                     .write_inner()
                     .client
                     .as_mut()
                     .unwrap()
                     .rename_identity(address, new_label.clone())
                     .await;
+                if let Err(e) = result {
+                    log::error!("Failed to rename identity: {}", e);
+                    return;
+                }
                 self.list_view
                     .send(index, IdentityListRowInput::RenameLabel(new_label));
                 sender
                     .output(IdentitiesListOutput::IdentitiesListUpdated)
                     .unwrap();
             }
+            IdentitiesListInput::HandleRotateIdentity(i) => {
+                let address = self
+                    .list_view
+                    .guard()
+                    .get(i.current_index())
+                    .expect("identity to be existing")
+                    .address
+                    .clone();
+
+                let dialog = adw::MessageDialog::new(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    Some("Rotate identity keys?"),
+                    Some("New keys and a new address will be generated for this identity. Each of your contacts will be sent a message from the new address, so that they stop using the old one."),
+                );
+                dialog.add_responses(&[
+                    ("cancel", "Cancel"),
+                    ("keep", "Keep old identity"),
+                    ("archive", "Archive old identity"),
+                ]);
+                dialog.set_response_appearance("archive", adw::ResponseAppearance::Destructive);
+                dialog.set_default_response(Some("cancel"));
+                dialog.set_close_response("cancel");
+                dialog.connect_response(None, move |_, response| {
+                    if response == "cancel" {
+                        return;
+                    }
+                    sender.input(IdentitiesListInput::RotateIdentity {
+                        address: address.clone(),
+                        archive_old: response == "archive",
+                    });
+                });
+                dialog.present();
+            }
+            IdentitiesListInput::RotateIdentity {
+                address,
+                archive_old,
+            } => {
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .rotate_identity(address, None, archive_old)
+                    .await;
+                if let Err(e) = result {
+                    show_message(root, "Failed to rotate identity", &e.to_string());
+                    return;
+                }
+                self.reload_list(sender.clone()).await;
+                sender
+                    .output(IdentitiesListOutput::IdentitiesListUpdated)
+                    .unwrap();
+            }
+            IdentitiesListInput::SetSelectionMode(active) => {
+                if active != self.selection_mode {
+                    self.set_selection_mode(active);
+                }
+            }
+            IdentitiesListInput::ExportSelected => {
+                let addresses = self.selected_addresses();
+                if addresses.is_empty() {
+                    show_message(
+                        root,
+                        "No identities selected",
+                        "Select identities to export.",
+                    );
+                    return;
+                }
+                sender.input(IdentitiesListInput::ExportIdentities(addresses));
+            }
+            IdentitiesListInput::HandleDeleteSelected => {
+                let addresses = self.selected_addresses();
+                if addresses.is_empty() {
+                    show_message(
+                        root,
+                        "No identities selected",
+                        "Select identities to delete.",
+                    );
+                    return;
+                }
+                let dialog = adw::MessageDialog::new(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    Some(&format!("Delete {} identities?", addresses.len())),
+                    Some("Their keys will be deleted, so messages sent to them can't be read anymore. Export the keys first to keep them."),
+                );
+                dialog.add_responses(&[("cancel", "Cancel"), ("delete", "Delete")]);
+                dialog.set_response_appearance("delete", adw::ResponseAppearance::Destructive);
+                dialog.set_default_response(Some("cancel"));
+                dialog.set_close_response("cancel");
+                dialog.connect_response(None, move |_, response| {
+                    if response == "delete" {
+                        sender.input(IdentitiesListInput::DeleteIdentities(addresses.clone()));
+                    }
+                });
+                dialog.present();
+            }
+            IdentitiesListInput::DeleteIdentities(addresses) => {
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .delete_identities(addresses)
+                    .await;
+                if let Err(e) = result {
+                    show_message(root, "Failed to delete identities", &e.to_string());
+                    return;
+                }
+                self.set_selection_mode(false);
+                self.reload_list(sender.clone()).await;
+                sender
+                    .output(IdentitiesListOutput::IdentitiesListUpdated)
+                    .unwrap();
+            }
+            IdentitiesListInput::HandleExportIdentities => {
+                let identities = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .get_own_identities()
+                    .await;
+                let identities = match identities {
+                    Ok(i) => i,
+                    Err(e) => {
+                        show_message(root, "Failed to export identities", &e.to_string());
+                        return;
+                    }
+                };
+
+                let checkboxes = gtk::Box::new(gtk::Orientation::Vertical, 6);
+                let mut buttons = Vec::new();
+                for i in identities {
+                    let button = gtk::CheckButton::with_label(&if i.label.is_empty() {
+                        i.string_repr.clone()
+                    } else {
+                        format!("{} ({})", i.label, i.string_repr)
+                    });
+                    button.set_active(true);
+                    checkboxes.append(&button);
+                    buttons.push((button, i.string_repr));
+                }
+
+                let dialog = adw::MessageDialog::new(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    Some("Export identities"),
+                    Some("Private keys of the selected identities will be saved to a keys.dat file. Anyone who gets the file can read your messages and send messages on your behalf, so keep it safe."),
+                );
+                dialog.set_extra_child(Some(&checkboxes));
+                dialog.add_responses(&[("cancel", "Cancel"), ("export", "Export")]);
+                dialog.set_response_appearance("export", adw::ResponseAppearance::Suggested);
+                dialog.set_default_response(Some("export"));
+                dialog.set_close_response("cancel");
+                dialog.connect_response(None, move |_, response| {
+                    if response != "export" {
+                        return;
+                    }
+                    let addresses: Vec<String> = buttons
+                        .iter()
+                        .filter(|(b, _)| b.is_active())
+                        .map(|(_, a)| a.clone())
+                        .collect();
+                    if !addresses.is_empty() {
+                        sender.input(IdentitiesListInput::ExportIdentities(addresses));
+                    }
+                });
+                dialog.present();
+            }
+            IdentitiesListInput::ExportIdentities(addresses) => {
+                let dialog = gtk::FileDialog::builder()
+                    .title("Export identities")
+                    .initial_name("keys.dat")
+                    .build();
+                dialog.save(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    gio::Cancellable::NONE,
+                    move |res| {
+                        if let Some(path) = res.ok().and_then(|f| f.path()) {
+                            sender.input(IdentitiesListInput::SaveExport { addresses, path });
+                        }
+                    },
+                );
+            }
+            IdentitiesListInput::SaveExport { addresses, path } => {
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .export_identities(addresses)
+                    .await;
+                let result = result.and_then(|data| {
+                    std::fs::write(path, data).map_err(|e| Box::from(e.to_string()))
+                });
+                if let Err(e) = result {
+                    show_message(root, "Failed to export identities", &e.to_string());
+                }
+            }
+            IdentitiesListInput::HandleImportIdentities => {
+                let dialog = gtk::FileDialog::builder()
+                    .title("Import identities")
+                    .build();
+                dialog.open(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    gio::Cancellable::NONE,
+                    move |res| {
+                        if let Some(path) = res.ok().and_then(|f| f.path()) {
+                            sender.input(IdentitiesListInput::ImportIdentities(path));
+                        }
+                    },
+                );
+            }
+            IdentitiesListInput::ImportIdentities(path) => {
+                let data = match std::fs::read_to_string(path) {
+                    Ok(d) => d,
+                    Err(e) => {
+                        show_message(root, "Failed to import identities", &e.to_string());
+                        return;
+                    }
+                };
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .import_identities(data)
+                    .await;
+                match result {
+                    Ok(imported) => {
+                        self.reload_list(sender.clone()).await;
+                        sender
+                            .output(IdentitiesListOutput::IdentitiesListUpdated)
+                            .unwrap();
+                        show_message(
+                            root,
+                            "Identities imported",
+                            &format!("{} new identities have been imported", imported.len()),
+                        );
+                    }
+                    Err(e) => show_message(root, "Failed to import identities", &e.to_string()),
+                }
+            }
         }
     }
 }
@@ -1,13 +1,17 @@
 use std::cell::Ref;
 
-use adw;
+use adw::{
+    self,
+    prelude::{MessageDialogExt, MessageDialogExtManual},
+};
+use chrono::{DateTime, Duration, Local, TimeZone, Timelike, Utc};
 use gtk::{
     self, gio,
     glib::BoxedAnyObject,
     prelude::{Cast, CastNone, EntryBufferExtManual, ObjectExt, StaticType},
     traits::{
-        ButtonExt, EntryExt, GridExt, GtkWindowExt, OrientableExt, TextBufferExt, TextViewExt,
-        WidgetExt,
+        AdjustmentExt, BoxExt, ButtonExt, EditableExt, EntryExt, GridExt, GtkWindowExt,
+        ListBoxRowExt, OrientableExt, PopoverExt, TextBufferExt, TextViewExt, WidgetExt,
     },
 };
 use relm4::{
@@ -15,10 +19,15 @@ use relm4::{
     view, AsyncComponentSender, RelmWidgetExt,
 };
 
-use crate::{components::utils::typed_list_view, state};
+use crate::{components::utils::typed_list_view, network::node::client::parse_recipients, state};
 
 use super::utils::typed_list_view::RelmListItem;
 
+/// Composer warns about messages whose PoW is expected to take longer
+const POW_WARNING_SECONDS: u64 = 5 * 60;
+/// Max number of contacts suggested for the recipient being typed
+const MAX_COMPLETIONS: usize = 8;
+
 #[derive(Debug, Clone)]
 pub struct IdentityDropdownItem {
     label: String,
@@ -61,25 +70,241 @@ impl RelmListItem for IdentityDropdownItem {
     }
 }
 
+/// Initial contents of the composer, e.g. a saved draft or a reply to a message
+#[derive(Debug, Clone, Default)]
+pub struct MessageComposerInit {
+    /// Hash of the draft being edited, a new message is composed otherwise
+    pub draft_hash: Option<String>,
+    /// Identity to send the message from, the first one is picked if it's not given
+    pub from: Option<String>,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl MessageComposerInit {
+    /// Reply to the sender of the message, quoting its body. `sender_name` is the
+    /// label of the sender in contacts or its address.
+    pub fn reply(
+        identity: String,
+        sender: String,
+        sender_name: &str,
+        date: DateTime<Utc>,
+        subject: &str,
+        body: &str,
+    ) -> Self {
+        Self {
+            draft_hash: None,
+            from: Some(identity),
+            to: sender,
+            subject: prefixed_subject("Re:", subject),
+            body: format!(
+                "\n\nOn {}, {} wrote:\n{}",
+                date.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                sender_name,
+                quote(body)
+            ),
+        }
+    }
+
+    /// Forward the message to a new recipient, which is picked in the composer
+    pub fn forward(
+        identity: String,
+        from: &str,
+        to: &str,
+        date: DateTime<Utc>,
+        subject: &str,
+        body: &str,
+    ) -> Self {
+        Self {
+            draft_hash: None,
+            from: Some(identity),
+            to: String::new(),
+            subject: prefixed_subject("Fwd:", subject),
+            body: format!(
+                "\n\n---------- Forwarded message ----------\nFrom: {}\nTo: {}\nDate: {}\nSubject: {}\n\n{}",
+                from,
+                to,
+                date.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                subject,
+                body
+            ),
+        }
+    }
+}
+
+/// Add prefix like `Re:` to the subject, unless it's there already
+fn prefixed_subject(prefix: &str, subject: &str) -> String {
+    let has_prefix = subject
+        .get(..prefix.len())
+        .is_some_and(|s| s.eq_ignore_ascii_case(prefix));
+    if has_prefix {
+        subject.to_string()
+    } else {
+        format!("{} {}", prefix, subject)
+    }
+}
+
+/// Quote the text email-style, i.e. prefix each line with `>`
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|l| {
+            if l.is_empty() {
+                ">".to_string()
+            } else {
+                format!("> {}", l)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 pub struct MessageComposer {
+    /// Hash of the draft being edited, if any
+    draft_hash: Option<String>,
     current_identity: Option<IdentityDropdownItem>,
     to_buffer: gtk::EntryBuffer,
     subject_buffer: gtk::EntryBuffer,
     body_buffer: gtk::TextBuffer,
+    /// Date and time picked in the "Send later" popover
+    send_at_calendar: gtk::Calendar,
+    send_at_hour: gtk::Adjustment,
+    send_at_minute: gtk::Adjustment,
+    /// Expected PoW duration of the message body, per recipient
+    pow_estimate: Option<std::time::Duration>,
+    to_entry: gtk::Entry,
+    /// Contacts and chans suggested as recipients, shown text and address
+    completions: Vec<(String, String)>,
+    /// Addresses of the suggestions shown in the completion popover
+    completion_matches: Vec<String>,
+    completion_popover: gtk::Popover,
+    completion_list: gtk::ListBox,
 }
 
 #[derive(Debug)]
 pub enum MessageComposerInput {
     CancelButtonClicked,
     SendButtonClicked,
+    ScheduleButtonClicked,
+    SaveDraft,
     IdentityItemSelected(IdentityDropdownItem),
+    ContactSelected(String),
+    /// To field was edited, so recipient suggestions are refreshed
+    ToChanged,
+    CompletionSelected(usize),
+    /// Body was edited, so PoW estimate is refreshed
+    BodyChanged,
+}
+
+#[derive(Debug)]
+pub enum MessageComposerOutput {
+    /// Message was sent or saved as a draft
+    MessagesChanged,
+}
+
+impl MessageComposer {
+    fn body_text(&self) -> String {
+        self.body_buffer
+            .text(
+                &self.body_buffer.start_iter(),
+                &self.body_buffer.end_iter(),
+                false,
+            )
+            .to_string()
+    }
+
+    /// Local time picked in the "Send later" popover, `None` if it doesn't exist
+    /// (e.g. skipped due to DST)
+    fn scheduled_time(&self) -> Option<DateTime<Utc>> {
+        let date = self.send_at_calendar.date();
+        Local
+            .with_ymd_and_hms(
+                date.year(),
+                date.month() as u32,
+                date.day_of_month() as u32,
+                self.send_at_hour.value() as u32,
+                self.send_at_minute.value() as u32,
+                0,
+            )
+            .single()
+            .map(|t| t.with_timezone(&Utc))
+    }
+
+    fn pow_estimate_text(&self) -> String {
+        match self.pow_estimate {
+            Some(d) if d.as_secs() >= POW_WARNING_SECONDS => format!(
+                "Proof of work will take about {} per recipient, consider making the message shorter",
+                format_duration(d)
+            ),
+            Some(d) => format!(
+                "Proof of work will take about {} per recipient",
+                format_duration(d)
+            ),
+            None => String::new(),
+        }
+    }
+
+    /// Suggest contacts matching the recipient being typed, i.e. the text after the
+    /// last comma, by their label or address
+    fn update_completions(&mut self) {
+        let text = self.to_buffer.text();
+        let typed = text
+            .rsplit(',')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        self.completion_matches.clear();
+        while let Some(row) = self.completion_list.row_at_index(0) {
+            self.completion_list.remove(&row);
+        }
+        if !typed.is_empty() {
+            for (shown, address) in self
+                .completions
+                .iter()
+                .filter(|(shown, address)| {
+                    shown.to_lowercase().contains(&typed) && address.to_lowercase() != typed
+                })
+                .take(MAX_COMPLETIONS)
+            {
+                let label = gtk::Label::new(Some(shown));
+                label.set_halign(gtk::Align::Start);
+                label.set_margin_all(5);
+                self.completion_list.append(&label);
+                self.completion_matches.push(address.clone());
+            }
+        }
+        if self.completion_matches.is_empty() {
+            self.completion_popover.popdown();
+        } else {
+            self.completion_popover.popup();
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.to_buffer.text().is_empty()
+            && self.subject_buffer.text().is_empty()
+            && self.body_text().is_empty()
+    }
+}
+
+/// Format duration roughly, e.g. `3 minutes`
+fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    let (value, unit) = match secs {
+        0 => return "a second".to_string(),
+        1..=119 => (secs, "second"),
+        120..=7199 => (secs / 60, "minute"),
+        _ => (secs / 3600, "hour"),
+    };
+    format!("{} {}{}", value, unit, if value == 1 { "" } else { "s" })
 }
 
 #[relm4::component(pub async)]
 impl AsyncComponent for MessageComposer {
     type Input = MessageComposerInput;
-    type Output = ();
-    type Init = ();
+    type Output = MessageComposerOutput;
+    type Init = MessageComposerInit;
     type CommandOutput = ();
 
     view! {
@@ -99,12 +324,67 @@ impl AsyncComponent for MessageComposer {
                         connect_clicked => MessageComposerInput::CancelButtonClicked
                     },
 
-                    pack_end = &gtk::Button {
-                        #[watch]
-                        set_sensitive: !model.current_identity.is_none(),
-                        set_label: "Send",
-                        add_css_class: "suggested-action",
-                        connect_clicked => MessageComposerInput::SendButtonClicked
+                    pack_end = &gtk::Box {
+                        add_css_class: "linked",
+
+                        gtk::Button {
+                            #[watch]
+                            set_sensitive: !model.current_identity.is_none(),
+                            set_label: "Send",
+                            add_css_class: "suggested-action",
+                            connect_clicked => MessageComposerInput::SendButtonClicked
+                        },
+                        gtk::MenuButton {
+                            #[watch]
+                            set_sensitive: model.current_identity.is_some(),
+                            set_icon_name: "pan-down-symbolic",
+                            set_tooltip_text: Some("Send later"),
+                            add_css_class: "suggested-action",
+
+                            #[wrap(Some)]
+                            set_popover = &gtk::Popover {
+                                gtk::Box {
+                                    set_orientation: gtk::Orientation::Vertical,
+                                    set_spacing: 10,
+
+                                    #[local_ref]
+                                    send_at_calendar -> gtk::Calendar {},
+                                    gtk::Box {
+                                        set_halign: gtk::Align::Center,
+                                        set_spacing: 5,
+
+                                        gtk::SpinButton {
+                                            set_adjustment: &model.send_at_hour,
+                                            set_orientation: gtk::Orientation::Vertical,
+                                            set_numeric: true,
+                                            set_wrap: true,
+                                            connect_output => |s| {
+                                                s.set_text(&format!("{:02}", s.value() as u32));
+                                                gtk::Inhibit(true)
+                                            },
+                                        },
+                                        gtk::Label {
+                                            set_label: ":",
+                                        },
+                                        gtk::SpinButton {
+                                            set_adjustment: &model.send_at_minute,
+                                            set_orientation: gtk::Orientation::Vertical,
+                                            set_numeric: true,
+                                            set_wrap: true,
+                                            connect_output => |s| {
+                                                s.set_text(&format!("{:02}", s.value() as u32));
+                                                gtk::Inhibit(true)
+                                            },
+                                        },
+                                    },
+                                    gtk::Button {
+                                        set_label: "Schedule",
+                                        add_css_class: "suggested-action",
+                                        connect_clicked => MessageComposerInput::ScheduleButtonClicked
+                                    },
+                                }
+                            }
+                        }
                     }
                 },
 
@@ -122,8 +402,15 @@ impl AsyncComponent for MessageComposer {
                         set_halign: gtk::Align::End,
                         set_label: "To"
                     },
-                    attach[3,1,1,1] = &gtk::Entry {
-                        set_buffer: &model.to_buffer
+                    #[local_ref]
+                    attach[3,1,1,1] = &to_entry -> gtk::Entry {
+                        set_buffer: &model.to_buffer,
+                        set_placeholder_text: Some("Addresses or contact names separated by commas"),
+                        connect_changed => MessageComposerInput::ToChanged,
+                    },
+                    #[local_ref]
+                    attach[4,1,1,1] = &contacts_dropdown -> gtk::DropDown {
+                        set_tooltip_text: Some("Add recipient from contacts and chans"),
                     },
                     attach[0,2,2,1] = &gtk::Label {
                         set_halign: gtk::Align::End,
@@ -150,29 +437,78 @@ impl AsyncComponent for MessageComposer {
                         #[wrap(Some)]
                         set_buffer = &model.body_buffer.clone(),
                     }
+                },
+                gtk::Label {
+                    set_margin_all: 5,
+                    set_halign: gtk::Align::Start,
+                    set_wrap: true,
+                    #[watch]
+                    set_label: &model.pow_estimate_text(),
+                    #[watch]
+                    set_css_classes: if model.pow_estimate.is_some_and(|d| d.as_secs() >= POW_WARNING_SECONDS) {
+                        &["caption", "warning"]
+                    } else {
+                        &["caption", "dim-label"]
+                    },
                 }
             }
         }
     }
 
     async fn init(
-        _init: Self::Init,
+        init: Self::Init,
         root: Self::Root,
         sender: AsyncComponentSender<Self>,
     ) -> AsyncComponentParts<Self> {
+        // an hour later by default
+        let send_at = Local::now() + Duration::hours(1);
+        let to_entry = gtk::Entry::new();
+        let completion_list = gtk::ListBox::new();
+        let s = sender.clone();
+        completion_list.connect_row_activated(move |_, row| {
+            s.input(MessageComposerInput::CompletionSelected(
+                row.index() as usize
+            ))
+        });
+        // typing goes on in the entry while suggestions are shown
+        let completion_popover = gtk::Popover::builder()
+            .autohide(false)
+            .has_arrow(false)
+            .position(gtk::PositionType::Bottom)
+            .child(&completion_list)
+            .build();
+        completion_popover.set_parent(&to_entry);
         let mut model = MessageComposer {
+            draft_hash: None,
             current_identity: None,
             to_buffer: gtk::EntryBuffer::new(Some("")),
             subject_buffer: gtk::EntryBuffer::new(Some("")),
             body_buffer: gtk::TextBuffer::new(None),
+            send_at_calendar: gtk::Calendar::new(),
+            send_at_hour: gtk::Adjustment::new(send_at.hour() as f64, 0.0, 23.0, 1.0, 0.0, 0.0),
+            send_at_minute: gtk::Adjustment::new(send_at.minute() as f64, 0.0, 59.0, 1.0, 0.0, 0.0),
+            pow_estimate: None,
+            to_entry: to_entry.clone(),
+            completions: Vec::new(),
+            completion_matches: Vec::new(),
+            completion_popover,
+            completion_list,
         };
+        model.draft_hash = init.draft_hash.clone();
+        model.to_buffer.set_text(init.to.clone());
+        model.subject_buffer.set_text(init.subject.clone());
+        model.body_buffer.set_text(&init.body);
         let identities = state::STATE
             .write_inner()
             .client
             .as_mut()
             .unwrap()
             .get_own_identities()
-            .await;
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("Failed to load identities: {}", e);
+                Vec::new()
+            });
 
         let factory = gtk::SignalListItemFactory::new();
         factory.connect_setup(move |_, list_item| {
@@ -232,9 +568,71 @@ impl AsyncComponent for MessageComposer {
             let item: Ref<IdentityDropdownItem> = obj.borrow();
             s.input(MessageComposerInput::IdentityItemSelected(item.clone()));
         });
-        if !items.is_empty() {
-            model.current_identity = Some(items[0].clone());
+        let selected = init
+            .from
+            .as_ref()
+            .and_then(|from| items.iter().position(|i| &i.address == from))
+            .unwrap_or_default();
+        if let Some(item) = items.get(selected) {
+            model.current_identity = Some(item.clone());
+            dropdown.set_selected(selected as u32);
         }
+
+        let contacts = state::STATE
+            .write_inner()
+            .client
+            .as_mut()
+            .unwrap()
+            .get_contacts()
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("Failed to load contacts: {}", e);
+                Vec::new()
+            });
+        // messages sent to a chan are received by all of its members
+        let contacts: Vec<_> = contacts
+            .into_iter()
+            .chain(identities.into_iter().filter(|i| i.chan))
+            .collect();
+        let contact_labels: Vec<String> = contacts
+            .iter()
+            .map(|c| {
+                if c.chan {
+                    format!("[chan] {} ({})", c.label, c.string_repr)
+                } else if c.label.is_empty() {
+                    c.string_repr.clone()
+                } else {
+                    format!("{} ({})", c.label, c.string_repr)
+                }
+            })
+            .collect();
+        let contacts_dropdown = gtk::DropDown::from_strings(
+            &contact_labels
+                .iter()
+                .map(|x| x.as_str())
+                .collect::<Vec<&str>>(),
+        );
+        model.completions = contact_labels
+            .iter()
+            .cloned()
+            .zip(contacts.iter().map(|c| c.string_repr.clone()))
+            .collect();
+        contacts_dropdown.set_selected(gtk::INVALID_LIST_POSITION);
+        contacts_dropdown.set_sensitive(!contacts.is_empty());
+        let s = sender.clone();
+        contacts_dropdown.connect_selected_notify(move |x| {
+            if let Some(c) = contacts.get(x.selected() as usize) {
+                s.input(MessageComposerInput::ContactSelected(c.string_repr.clone()));
+                // reset selection, so that more recipients can be picked
+                x.set_selected(gtk::INVALID_LIST_POSITION);
+            }
+        });
+        let s = sender.clone();
+        model
+            .body_buffer
+            .connect_changed(move |_| s.input(MessageComposerInput::BodyChanged));
+        sender.input(MessageComposerInput::BodyChanged);
+        let send_at_calendar = &model.send_at_calendar;
         let widgets = view_output!();
         AsyncComponentParts { model, widgets }
     }
@@ -242,12 +640,86 @@ impl AsyncComponent for MessageComposer {
     async fn update(
         &mut self,
         message: Self::Input,
-        _sender: AsyncComponentSender<Self>,
+        sender: AsyncComponentSender<Self>,
         root: &Self::Root,
     ) {
         match message {
-            MessageComposerInput::CancelButtonClicked => root.close(),
-            MessageComposerInput::SendButtonClicked => {
+            MessageComposerInput::CancelButtonClicked => {
+                if self.is_empty() || self.current_identity.is_none() {
+                    root.close();
+                    return;
+                }
+                let dialog = adw::MessageDialog::new(
+                    Some(root.upcast_ref::<gtk::Window>()),
+                    Some("Save draft?"),
+                    Some("The message is not sent yet. It can be saved to Drafts and finished later."),
+                );
+                dialog.add_responses(&[
+                    ("cancel", "Cancel"),
+                    ("discard", "Discard"),
+                    ("save", "Save draft"),
+                ]);
+                dialog.set_response_appearance("discard", adw::ResponseAppearance::Destructive);
+                dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+                dialog.set_default_response(Some("save"));
+                dialog.set_close_response("cancel");
+                let root = root.clone();
+                dialog.connect_response(None, move |_, response| match response {
+                    "discard" => root.close(),
+                    "save" => sender.input(MessageComposerInput::SaveDraft),
+                    _ => {}
+                });
+                dialog.present();
+            }
+            MessageComposerInput::SaveDraft => {
+                let from = self.current_identity.as_ref().unwrap().address.clone();
+                let to = self.to_buffer.text().to_string();
+                let subject = self.subject_buffer.text().to_string();
+                let body = self.body_text();
+                let mut state = state::STATE.write_inner();
+                let client = state.client.as_mut().unwrap();
+                let result = match self.draft_hash.clone() {
+                    Some(hash) => client.update_draft(hash, from, to, subject, body).await,
+                    None => client
+                        .save_draft(from, to, subject, body)
+                        .await
+                        .map(|hash| self.draft_hash = Some(hash)),
+                };
+                drop(state);
+                if let Err(e) = result {
+                    // keep the composer open, so that the message isn't lost
+                    let dialog = adw::MessageDialog::new(
+                        Some(root.upcast_ref::<gtk::Window>()),
+                        Some("Failed to save draft"),
+                        Some(&e.to_string()),
+                    );
+                    dialog.add_response("ok", "OK");
+                    dialog.present();
+                    return;
+                }
+                root.close();
+                _ = sender.output(MessageComposerOutput::MessagesChanged);
+            }
+            MessageComposerInput::SendButtonClicked
+            | MessageComposerInput::ScheduleButtonClicked => {
+                let send_at = match message {
+                    MessageComposerInput::ScheduleButtonClicked => {
+                        match self.scheduled_time().filter(|t| *t > Utc::now()) {
+                            Some(t) => Some(t),
+                            None => {
+                                let dialog = adw::MessageDialog::new(
+                                    Some(root.upcast_ref::<gtk::Window>()),
+                                    Some("Invalid time"),
+                                    Some("Pick a time in the future to send the message at."),
+                                );
+                                dialog.add_response("ok", "OK");
+                                dialog.present();
+                                return;
+                            }
+                        }
+                    }
+                    _ => None,
+                };
                 log::debug!(
                     "from: {:?}, to: {}, subject: {}, body: {}",
                     self.current_identity,
@@ -259,27 +731,105 @@ impl AsyncComponent for MessageComposer {
                         false
                     )
                 );
+                let to = parse_recipients(&self.to_buffer.text());
+                if to.is_empty() {
+                    let dialog = adw::MessageDialog::new(
+                        Some(root.upcast_ref::<gtk::Window>()),
+                        Some("No recipients"),
+                        Some("Enter at least one recipient address."),
+                    );
+                    dialog.add_response("ok", "OK");
+                    dialog.present();
+                    return;
+                }
+                // contact names are resolved into their addresses
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                let mut addresses: Vec<String> = Vec::new();
+                let mut invalid: Vec<String> = Vec::new();
+                for recipient in to {
+                    match client.resolve_recipient(recipient.clone()).await {
+                        Ok(a) if addresses.contains(&a.string_repr) => {}
+                        Ok(a) => addresses.push(a.string_repr),
+                        Err(e) => invalid.push(format!("{}: {}", recipient, e)),
+                    }
+                }
+                if !invalid.is_empty() {
+                    let dialog = adw::MessageDialog::new(
+                        Some(root.upcast_ref::<gtk::Window>()),
+                        Some("Invalid recipients"),
+                        Some(&invalid.join("\n")),
+                    );
+                    dialog.add_response("ok", "OK");
+                    dialog.present();
+                    return;
+                }
+                let to = addresses;
+                let from = self.current_identity.as_ref().unwrap().address.clone();
+                let subject = self.subject_buffer.text().to_string();
+                let body = self.body_text();
+                // progress of the messages is followed by the messages list
+                let result = match (send_at, self.draft_hash.clone()) {
+                    (Some(send_at), draft_hash) => client
+                        .schedule_message(draft_hash, from, to, subject, body, send_at)
+                        .await
+                        .map_err(|e| e.to_string()),
+                    (None, Some(hash)) => client
+                        .send_draft(hash, from, to, subject, body)
+                        .await
+                        .map_err(|e| e.to_string()),
+                    (None, None) => client
+                        .send_message(from, to, subject, body)
+                        .await
+                        .map_err(|e| e.to_string()),
+                };
+                if let Err(e) = result {
+                    let dialog = adw::MessageDialog::new(
+                        Some(root.upcast_ref::<gtk::Window>()),
+                        Some("Failed to send message"),
+                        Some(&e),
+                    );
+                    dialog.add_response("ok", "OK");
+                    dialog.present();
+                    return;
+                }
+                self.draft_hash = None;
                 root.close();
-                state::STATE
-                    .write_inner()
-                    .client
-                    .as_mut()
-                    .unwrap()
-                    .send_message(
-                        self.current_identity.as_ref().unwrap().address.clone(),
-                        self.to_buffer.text().to_string(),
-                        self.subject_buffer.text().to_string(),
-                        self.body_buffer
-                            .text(
-                                &self.body_buffer.start_iter(),
-                                &self.body_buffer.end_iter(),
-                                false,
-                            )
-                            .to_string(),
-                    )
-                    .await;
+                _ = sender.output(MessageComposerOutput::MessagesChanged);
             }
             MessageComposerInput::IdentityItemSelected(v) => self.current_identity = Some(v),
+            MessageComposerInput::ContactSelected(address) => {
+                let mut recipients = parse_recipients(&self.to_buffer.text());
+                if !recipients.contains(&address) {
+                    recipients.push(address);
+                }
+                self.to_buffer.set_text(recipients.join(", "));
+            }
+            MessageComposerInput::ToChanged => self.update_completions(),
+            MessageComposerInput::CompletionSelected(i) => {
+                let Some(address) = self.completion_matches.get(i).cloned() else {
+                    return;
+                };
+                // the recipient being typed is replaced with the picked address
+                let text = self.to_buffer.text().to_string();
+                let before = text.rfind(',').map_or("", |i| &text[..=i]);
+                self.to_buffer
+                    .set_text(format!("{} {}, ", before, address).trim_start());
+                self.to_entry.grab_focus_without_selecting();
+                self.to_entry.set_position(-1);
+            }
+            MessageComposerInput::BodyChanged => {
+                let msg_size = self.subject_buffer.text().len() + self.body_text().len();
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                match client.estimate_pow(msg_size, None).await {
+                    Ok(estimate) => self.pow_estimate = Some(estimate.expected_duration),
+                    Err(e) => log::warn!("Failed to estimate PoW: {}", e),
+                }
+            }
         }
     }
+
+    fn shutdown(&mut self, _widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        // popovers aren't removed along with their parents
+        self.completion_popover.unparent();
+    }
 }
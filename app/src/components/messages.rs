@@ -1,4 +1,5 @@
 use adw;
+use async_std::stream::StreamExt;
 use gtk::{self, prelude::*};
 use relm4::component::{AsyncComponentController, AsyncController};
 use relm4::{
@@ -8,7 +9,9 @@ use relm4::{
 };
 use relm4::{AsyncComponentSender, RelmWidgetExt};
 
-use super::messages_content::{MessagesContent, MessagesContentInput};
+use crate::state;
+
+use super::messages_content::{MessagesContent, MessagesContentInput, MessagesContentOutput};
 use super::messages_sidebar::{
     MessagesSidebar, MessagesSidebarInput, MessagesSidebarOutput, SelectedFolder,
 };
@@ -16,17 +19,21 @@ use super::messages_sidebar::{
 pub(crate) struct MessagesModel {
     sidebar: AsyncController<MessagesSidebar>,
     content: AsyncController<MessagesContent>,
+    /// Whether there are peers to broadcast messages to
+    online: bool,
 }
 
 #[derive(Debug)]
 pub(crate) enum MessagesInput {
     FolderSelected(SelectedFolder),
     IdentitiesListUpdated,
+    SubscriptionsUpdated,
+    MessagesChanged,
 }
 
 #[relm4::component(pub async)]
 impl AsyncComponent for MessagesModel {
-    type CommandOutput = ();
+    type CommandOutput = bool;
     type Input = MessagesInput;
     type Output = ();
     type Init = ();
@@ -34,10 +41,22 @@ impl AsyncComponent for MessagesModel {
     view! {
         #[root]
         gtk::ScrolledWindow {
-            adw::Leaflet {
-                model.sidebar.widget() -> &gtk::ScrolledWindow,
-                gtk::Separator {},
-                model.content.widget() -> &gtk::Box {}
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+
+                adw::Banner {
+                    set_title: "Offline, messages will be sent once peers are connected",
+                    #[watch]
+                    set_revealed: !model.online,
+                },
+
+                adw::Leaflet {
+                    set_vexpand: true,
+
+                    model.sidebar.widget() -> &gtk::ScrolledWindow,
+                    gtk::Separator {},
+                    model.content.widget() -> &gtk::Box {}
+                }
             }
         }
     }
@@ -73,12 +92,46 @@ impl AsyncComponent for MessagesModel {
             .forward(sender.input_sender(), |msg| match msg {
                 MessagesSidebarOutput::FolderSelected(v) => MessagesInput::FolderSelected(v),
             });
-        let content = MessagesContent::builder().launch(()).detach();
-        let model = Self { sidebar, content };
+        let content = MessagesContent::builder()
+            .launch(())
+            .forward(sender.input_sender(), |msg| match msg {
+                MessagesContentOutput::MessagesChanged => MessagesInput::MessagesChanged,
+            });
+        let mut client = state::STATE.read_inner().client.clone().unwrap();
+        match client.subscribe_connectivity().await {
+            Ok(mut connectivity) => sender.command(|out, shutdown| {
+                shutdown
+                    .register(async move {
+                        while let Some(online) = connectivity.next().await {
+                            if out.send(online).is_err() {
+                                break;
+                            }
+                        }
+                    })
+                    .drop_on_shutdown()
+            }),
+            Err(e) => log::error!("Failed to subscribe to connectivity changes: {}", e),
+        }
+
+        // the banner is only shown once the node reports that it's offline
+        let model = Self {
+            sidebar,
+            content,
+            online: true,
+        };
         let widgets = view_output!();
         AsyncComponentParts { model, widgets }
     }
 
+    async fn update_cmd(
+        &mut self,
+        online: Self::CommandOutput,
+        _sender: AsyncComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        self.online = online;
+    }
+
     async fn update(
         &mut self,
         message: Self::Input,
@@ -92,6 +145,12 @@ impl AsyncComponent for MessagesModel {
             MessagesInput::IdentitiesListUpdated => self
                 .sidebar
                 .emit(MessagesSidebarInput::IdentitiesListUpdated),
+            MessagesInput::SubscriptionsUpdated => self
+                .sidebar
+                .emit(MessagesSidebarInput::SubscriptionsUpdated),
+            MessagesInput::MessagesChanged => {
+                self.sidebar.emit(MessagesSidebarInput::FolderStatsChanged)
+            }
         }
     }
 }
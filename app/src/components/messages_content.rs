@@ -1,32 +1,56 @@
-use std::cell::Ref;
+use std::{cell::Ref, path::PathBuf};
 
+use adw::prelude::MessageDialogExt;
+use async_std::stream::StreamExt;
 use chrono::Utc;
 use gtk::{
-    glib::BoxedAnyObject,
+    gio,
+    glib::{object::CastNone, BoxedAnyObject},
     prelude::Cast,
-    traits::{OrientableExt, TextBufferExt, TextViewExt, WidgetExt},
+    traits::{
+        BoxExt, ButtonExt, EditableExt, GtkWindowExt, OrientableExt, PopoverExt, TextBufferExt,
+        TextViewExt, ToggleButtonExt, WidgetExt,
+    },
 };
 use relm4::{
-    component::{AsyncComponent, AsyncComponentParts},
+    component::{AsyncComponent, AsyncComponentController, AsyncComponentParts},
     loading_widgets::LoadingWidgets,
     view, AsyncComponentSender, RelmWidgetExt,
 };
 
-use crate::{network::node::worker::Folder, state};
+use crate::{
+    network::node::worker::{Folder, MessageStatusEvent, PubkeyRequest},
+    state,
+};
 
 use super::{
+    message_composer::{MessageComposer, MessageComposerInit, MessageComposerOutput},
     messages_sidebar::SelectedFolder,
-    utils::typed_list_view::{RelmListItem, TypedListView},
+    utils::{
+        message_body::MessageBody,
+        typed_list_view::{RelmListItem, TypedListView},
+    },
 };
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct MessagesListItem {
+    hash: String,
     title: String,
     date: chrono::DateTime<Utc>,
     from: String,
     to: String,
-    body: String,
+    /// Label of the sender if it's a known address
+    from_label: Option<String>,
+    /// Sender and recipient as they're shown, e.g. `Alice (BM-...)`
+    from_name: String,
+    to_name: String,
+    body: MessageBody,
     status: String,
+    failure_reason: Option<String>,
+    expires: Option<chrono::DateTime<Utc>>,
+    read: bool,
+    verified: bool,
+    signer_fingerprint: Option<String>,
 }
 
 pub struct MessagesListItemWidgets {
@@ -55,36 +79,312 @@ impl RelmListItem for MessagesListItem {
             0 => widgets
                 .label
                 .set_text(&self.date.format("%Y-%m-%d %H:%M:%S").to_string()), // Date
-            1 => widgets.label.set_text(&self.from),  // From
-            2 => widgets.label.set_text(&self.to),    // To
-            3 => widgets.label.set_text(&self.title), // Title
+            1 => widgets.label.set_text(&self.from_name), // From
+            2 => widgets.label.set_text(&self.to_name),   // To
+            3 => {
+                // Title, unread messages are highlighted
+                widgets.label.set_text(&self.title);
+                if self.read {
+                    widgets.label.remove_css_class("heading");
+                } else {
+                    widgets.label.add_css_class("heading");
+                }
+            }
             4 => widgets.label.set_text(&self.status), // Status
+            5 => widgets.label.set_text(&format_expiration(self.expires)), // Expires
             _ => {}
         }
     }
 }
 
+/// Format object expiration time relative to now
+pub(crate) fn format_expiration(expires: Option<chrono::DateTime<Utc>>) -> String {
+    let expires = match expires {
+        Some(e) => e,
+        None => return "-".to_string(),
+    };
+    let delta = expires - Utc::now();
+    let format_duration = |d: chrono::Duration| {
+        if d.num_days() > 0 {
+            format!("{} d", d.num_days())
+        } else if d.num_hours() > 0 {
+            format!("{} h", d.num_hours())
+        } else {
+            format!("{} min", d.num_minutes())
+        }
+    };
+    if delta > chrono::Duration::zero() {
+        format!("in {}", format_duration(delta))
+    } else {
+        format!("expired {} ago", format_duration(-delta))
+    }
+}
+
+/// Number of messages loaded at once as the list is scrolled
+const MESSAGES_PAGE_SIZE: u32 = 100;
+
 pub struct MessagesContent {
     selected_folder: Option<SelectedFolder>,
     messages_list_view: TypedListView<MessagesListItem, gtk::SingleSelection, gtk::ColumnView>,
+    /// Number of messages of the folder loaded so far
+    loaded_messages: u32,
+    /// The folder has more messages than loaded so far
+    has_more_messages: bool,
     current_msg: Option<MessagesListItem>,
     current_msg_buffer: gtk::TextBuffer,
+    /// Show HTML and markdown bodies with their formatting rather than as plain text
+    show_formatting: bool,
+    search_query: String,
 
     list_stack: gtk::Stack,
+    failure_banner: adw::Banner,
+    /// Menu of the message list, shown on right click
+    context_menu: gtk::Popover,
+}
+
+impl MessagesContent {
+    /// Load the next page of messages of the selected folder, or all messages
+    /// matching the search query at once
+    async fn load_messages(&mut self) {
+        let Some(selected_folder) = &self.selected_folder else {
+            return;
+        };
+        let address = selected_folder.identity_address.clone();
+        let folder = selected_folder.folder;
+        let mut client = state::STATE.read_inner().client.clone().unwrap();
+        let msgs = if self.search_query.is_empty() {
+            client
+                .get_messages_page(address, folder, self.loaded_messages, MESSAGES_PAGE_SIZE)
+                .await
+        } else {
+            client
+                .search_messages(self.search_query.clone(), address, folder)
+                .await
+        };
+        let msgs = msgs.unwrap_or_else(|e| {
+            log::error!("Failed to load messages: {}", e);
+            Vec::new()
+        });
+        self.has_more_messages =
+            self.search_query.is_empty() && msgs.len() == MESSAGES_PAGE_SIZE as usize;
+        self.loaded_messages += msgs.len() as u32;
+        for m in msgs {
+            let mime_msg = mail_parser::Message::parse(m.data.as_slice()).unwrap();
+            let title = mime_msg.subject().unwrap_or_default().to_string();
+            let date = m.created_at;
+            let from_name = m.sender_display_name();
+            let to_name = m.recipient_display_name();
+            let body = MessageBody::parse(&mime_msg);
+            self.messages_list_view.append(MessagesListItem {
+                hash: m.hash,
+                title,
+                date,
+                from: m.sender,
+                to: m.recipient,
+                from_label: m.sender_label,
+                from_name,
+                to_name,
+                body,
+                status: m.status,
+                failure_reason: m.failure_reason,
+                expires: m.expires,
+                read: m.read,
+                verified: m.verified,
+                signer_fingerprint: m.signer_fingerprint,
+            });
+        }
+        if self.messages_list_view.len() > 0 {
+            self.list_stack.set_visible_child_name("list");
+        } else {
+            self.list_stack.set_visible_child_name("empty");
+        }
+    }
+
+    fn is_trash_selected(&self) -> bool {
+        matches!(&self.selected_folder, Some(f) if f.folder == Folder::Trash)
+    }
+
+    fn is_quarantine_selected(&self) -> bool {
+        matches!(&self.selected_folder, Some(f) if f.folder == Folder::Quarantine)
+    }
+
+    fn is_sent_selected(&self) -> bool {
+        matches!(&self.selected_folder, Some(f) if f.folder == Folder::Sent)
+    }
+
+    fn is_drafts_selected(&self) -> bool {
+        matches!(&self.selected_folder, Some(f) if f.folder == Folder::Drafts)
+    }
+
+    /// Messages which are still being prepared can be cancelled before they're sent
+    fn is_current_msg_cancellable(&self) -> bool {
+        matches!(&self.current_msg, Some(m) if is_outbox_status(&m.status))
+    }
+
+    fn is_current_msg_received(&self) -> bool {
+        matches!(&self.current_msg, Some(m) if m.status == "Received" || m.status == "Unverified")
+    }
+
+    /// Tell that the message waits for the recipient's key, it can be requested again
+    fn show_pubkey_request(&self, request: &PubkeyRequest) {
+        let mut title = format!(
+            "Awaiting recipient's key since {}",
+            request.since.format("%Y-%m-%d %H:%M:%S")
+        );
+        if request.retries > 0 {
+            title.push_str(&format!(", retried {} times", request.retries));
+        }
+        self.failure_banner.set_title(&title);
+        self.failure_banner.set_button_label(Some("Retry now"));
+        self.failure_banner.set_revealed(true);
+    }
+
+    /// Tell that sending was cancelled, the message won't be sent anymore
+    fn show_cancelled(&self) {
+        self.failure_banner
+            .set_title("Sending was cancelled before the message was broadcast");
+        self.failure_banner.set_button_label(None);
+        self.failure_banner.set_revealed(true);
+    }
+
+    /// Open the composer, reloading the folder once the message is sent or saved
+    fn open_composer(&self, init: MessageComposerInit, sender: &AsyncComponentSender<Self>) {
+        let mut message_composer = MessageComposer::builder().launch(init).forward(
+            sender.input_sender(),
+            |msg| match msg {
+                MessageComposerOutput::MessagesChanged => MessagesContentInput::Reload,
+            },
+        );
+        message_composer.widget().present();
+        message_composer.detach_runtime();
+    }
+
+    /// Badge of the received message telling whether it's signed by its sender,
+    /// `None` for outgoing messages
+    fn signature_badge(&self) -> Option<(bool, String)> {
+        let m = self
+            .current_msg
+            .as_ref()
+            .filter(|m| m.status == "Received" || m.status == "Unverified")?;
+        let mut text = if m.verified {
+            format!("Signed by {}", m.from_name)
+        } else {
+            format!("Not signed by {}", m.from_name)
+        };
+        if let Some(fingerprint) = &m.signer_fingerprint {
+            text.push_str(&format!(", key {}", fingerprint));
+        }
+        Some((m.verified, text))
+    }
+
+    /// Messages can only be imported into folders of received or sent messages
+    fn is_import_allowed(&self) -> bool {
+        matches!(
+            self.selected_folder.as_ref().map(|f| f.folder),
+            Some(Folder::Inbox | Folder::Sent)
+        )
+    }
+
+    fn mark_item_read(&self, hash: &str) {
+        for position in 0..self.messages_list_view.len() {
+            let Some(item) = self.messages_list_view.get(position) else {
+                continue;
+            };
+            if item.borrow().hash == hash {
+                item.borrow_mut().read = true;
+                self.messages_list_view.refresh(position);
+                return;
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum MessagesContentInput {
     FolderSelected(SelectedFolder),
     MessageSelected(MessagesListItem),
+    RetryMessage,
+    CancelSend,
+    DeleteMessage,
+    RestoreMessage,
+    EditDraft,
+    Reply,
+    Forward,
+    Reload,
+    /// Load the next page of messages once the list is scrolled to the bottom
+    LoadMore,
+    SearchChanged(String),
+    /// Export the current message as `.eml` file
+    ExportMessage,
+    /// Export the listed messages as mbox file
+    ExportFolder,
+    SaveExport {
+        hashes: Vec<String>,
+        mbox: bool,
+        path: PathBuf,
+    },
+    HandleImport,
+    ImportMessages(PathBuf),
+    ShowFormatting(bool),
+    /// Right click on the message list at the position
+    ShowContextMenu(f64, f64),
+    /// Announce the current sent message to peers again
+    RebroadcastMessage,
+}
+
+/// Messages with these statuses haven't been sent out yet, they're listed in Outbox
+fn is_outbox_status(status: &str) -> bool {
+    matches!(
+        status,
+        "WaitingForPubkey" | "WaitingForPOW" | "WaitingForPeers" | "Scheduled"
+    )
+}
+
+fn show_message(root: &gtk::Box, heading: &str, body: &str) {
+    let dialog = adw::MessageDialog::new(
+        root.root().and_downcast_ref::<gtk::Window>(),
+        Some(heading),
+        Some(body),
+    );
+    dialog.add_response("ok", "OK");
+    dialog.present();
+}
+
+/// Ask where to save the exported messages
+fn choose_export_path(
+    root: &gtk::Box,
+    sender: AsyncComponentSender<MessagesContent>,
+    hashes: Vec<String>,
+    mbox: bool,
+    initial_name: &str,
+) {
+    let dialog = gtk::FileDialog::builder()
+        .title("Export messages")
+        .initial_name(initial_name)
+        .build();
+    dialog.save(
+        root.root().and_downcast_ref::<gtk::Window>(),
+        gio::Cancellable::NONE,
+        move |res| {
+            if let Some(path) = res.ok().and_then(|f| f.path()) {
+                sender.input(MessagesContentInput::SaveExport { hashes, mbox, path });
+            }
+        },
+    );
+}
+
+#[derive(Debug)]
+pub enum MessagesContentOutput {
+    /// Messages were received, read or moved, so that folder counters are changed
+    MessagesChanged,
 }
 
 #[relm4::component(pub async)]
 impl AsyncComponent for MessagesContent {
     type Init = ();
     type Input = MessagesContentInput;
-    type Output = ();
-    type CommandOutput = ();
+    type Output = MessagesContentOutput;
+    type CommandOutput = MessageStatusEvent;
 
     view! {
         #[root]
@@ -93,45 +393,238 @@ impl AsyncComponent for MessagesContent {
             set_hexpand: true,
             match model.selected_folder.clone() {
                 Some(_) => {
-                    #[name(list_stack)]
-                    gtk::Stack {
-                        set_vexpand: true,
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
 
-                        add_named[Some("list")] = &gtk::Paned {
-                            set_margin_all: 12,
-                            set_orientation: gtk::Orientation::Vertical,
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Horizontal,
+                            set_margin_top: 12,
+                            set_margin_start: 12,
+                            set_margin_end: 12,
+                            set_spacing: 5,
 
-                            #[wrap(Some)]
-                            set_start_child = &gtk::Frame {
-                                gtk::ScrolledWindow {
-                                    #[local_ref]
-                                    messages_list -> gtk::ColumnView {},
+                            gtk::SearchEntry {
+                                set_hexpand: true,
+                                set_placeholder_text: Some("Search messages"),
+                                connect_search_changed[sender] => move |e| {
+                                    sender.input(MessagesContentInput::SearchChanged(e.text().to_string()))
                                 }
                             },
-                            #[wrap(Some)]
-                            set_end_child = &gtk::Frame {
-                                #[name(message_text_view)]
-                                gtk::TextView {
-                                    set_left_margin: 5,
-                                    set_right_margin: 5,
-                                    set_top_margin: 5,
-                                    set_bottom_margin: 5,
-
-                                    set_editable: false,
-                                    set_cursor_visible: false,
-
-                                    #[wrap(Some)]
-                                    set_buffer = &model.current_msg_buffer.clone(),
+                            gtk::Button {
+                                set_icon_name: "document-save-symbolic",
+                                set_tooltip_text: Some("Export listed messages as mbox file"),
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(MessagesContentInput::ExportFolder)
+                                }
+                            },
+                            gtk::Button {
+                                set_icon_name: "document-open-symbolic",
+                                set_tooltip_text: Some("Import messages from mbox or .eml file"),
+                                #[watch]
+                                set_visible: model.is_import_allowed(),
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(MessagesContentInput::HandleImport)
                                 }
                             },
                         },
-                        add_named[Some("empty")] = &gtk::Label {
+
+                        #[name(list_stack)]
+                        gtk::Stack {
                             set_vexpand: true,
-                            set_label: "No messages in the folder :(",
-                            add_css_class: "large-title"
-                        },
 
-                        set_visible_child_name: "empty",
+                            add_named[Some("list")] = &gtk::Paned {
+                                set_margin_all: 12,
+                                set_orientation: gtk::Orientation::Vertical,
+
+                                #[wrap(Some)]
+                                set_start_child = &gtk::Frame {
+                                    gtk::ScrolledWindow {
+                                        connect_edge_reached[sender] => move |_, position| {
+                                            if position == gtk::PositionType::Bottom {
+                                                sender.input(MessagesContentInput::LoadMore)
+                                            }
+                                        },
+
+                                        #[local_ref]
+                                        messages_list -> gtk::ColumnView {},
+                                    }
+                                },
+                                #[wrap(Some)]
+                                set_end_child = &gtk::Frame {
+                                    gtk::Box {
+                                        set_orientation: gtk::Orientation::Vertical,
+
+                                        #[name(failure_banner)]
+                                        adw::Banner {
+                                            set_button_label: Some("Retry"),
+                                            set_revealed: false,
+                                            connect_button_clicked[sender] => move |_| {
+                                                sender.input(MessagesContentInput::RetryMessage)
+                                            }
+                                        },
+
+                                        gtk::Box {
+                                            set_orientation: gtk::Orientation::Horizontal,
+                                            set_margin_all: 5,
+
+                                            gtk::Box {
+                                                set_hexpand: true,
+                                                set_spacing: 5,
+                                                #[watch]
+                                                set_visible: model.signature_badge().is_some(),
+                                                #[watch]
+                                                set_css_classes: match model.signature_badge() {
+                                                    Some((false, _)) => &["error"],
+                                                    _ => &["success"],
+                                                },
+
+                                                gtk::Image {
+                                                    #[watch]
+                                                    set_icon_name: match model.signature_badge() {
+                                                        Some((false, _)) => Some("dialog-warning-symbolic"),
+                                                        _ => Some("emblem-ok-symbolic"),
+                                                    },
+                                                },
+                                                gtk::Label {
+                                                    set_selectable: true,
+                                                    set_ellipsize: gtk::pango::EllipsizeMode::Middle,
+                                                    #[watch]
+                                                    set_label: &model
+                                                        .signature_badge()
+                                                        .map(|(_, text)| text)
+                                                        .unwrap_or_default(),
+                                                },
+                                            },
+                                            gtk::Box {
+                                                set_hexpand: true,
+                                                #[watch]
+                                                set_visible: model.signature_badge().is_none(),
+                                            },
+
+                                            gtk::ToggleButton {
+                                                set_label: "Formatted",
+                                                set_tooltip_text: Some("Show formatting of HTML and markdown messages"),
+                                                set_margin_end: 5,
+                                                set_active: true,
+                                                #[watch]
+                                                set_visible: matches!(&model.current_msg, Some(m) if m.body.formatted.is_some()),
+                                                connect_toggled[sender] => move |button| {
+                                                    sender.input(MessagesContentInput::ShowFormatting(button.is_active()))
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_label: "Edit",
+                                                set_margin_end: 5,
+                                                #[watch]
+                                                set_visible: model.is_drafts_selected(),
+                                                #[watch]
+                                                set_sensitive: model.current_msg.is_some(),
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(MessagesContentInput::EditDraft)
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_label: "Reply",
+                                                set_margin_end: 5,
+                                                #[watch]
+                                                set_visible: model.is_current_msg_received(),
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(MessagesContentInput::Reply)
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_label: "Forward",
+                                                set_margin_end: 5,
+                                                #[watch]
+                                                set_visible: !model.is_drafts_selected(),
+                                                #[watch]
+                                                set_sensitive: model.current_msg.is_some(),
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(MessagesContentInput::Forward)
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_label: "Cancel",
+                                                set_margin_end: 5,
+                                                #[watch]
+                                                set_visible: model.is_current_msg_cancellable(),
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(MessagesContentInput::CancelSend)
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_label: "Export",
+                                                set_margin_end: 5,
+                                                #[watch]
+                                                set_sensitive: model.current_msg.is_some(),
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(MessagesContentInput::ExportMessage)
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_label: "Delete",
+                                                add_css_class: "destructive-action",
+                                                #[watch]
+                                                set_visible: !model.is_trash_selected(),
+                                                #[watch]
+                                                set_sensitive: model.current_msg.is_some(),
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(MessagesContentInput::DeleteMessage)
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_label: "Restore",
+                                                #[watch]
+                                                set_visible: model.is_trash_selected(),
+                                                #[watch]
+                                                set_sensitive: model.current_msg.is_some(),
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(MessagesContentInput::RestoreMessage)
+                                                }
+                                            },
+                                            gtk::Button {
+                                                set_label: "Move to Inbox",
+                                                set_margin_start: 5,
+                                                #[watch]
+                                                set_visible: model.is_quarantine_selected(),
+                                                #[watch]
+                                                set_sensitive: model.current_msg.is_some(),
+                                                connect_clicked[sender] => move |_| {
+                                                    sender.input(MessagesContentInput::RestoreMessage)
+                                                }
+                                            },
+                                        },
+
+                                        #[name(message_text_view)]
+                                        gtk::TextView {
+                                            set_vexpand: true,
+                                            set_left_margin: 5,
+                                            set_right_margin: 5,
+                                            set_top_margin: 5,
+                                            set_bottom_margin: 5,
+
+                                            set_editable: false,
+                                            set_cursor_visible: false,
+
+                                            #[wrap(Some)]
+                                            set_buffer = &model.current_msg_buffer.clone(),
+                                        }
+                                    }
+                                },
+                            },
+                            add_named[Some("empty")] = &gtk::Label {
+                                set_vexpand: true,
+                                #[watch]
+                                set_label: if model.search_query.is_empty() {
+                                    "No messages in the folder :("
+                                } else {
+                                    "No messages found"
+                                },
+                                add_css_class: "large-title"
+                            },
+
+                            set_visible_child_name: "empty",
+                        }
                     }
                 },
                 None => {
@@ -178,12 +671,14 @@ impl AsyncComponent for MessagesContent {
                 "To".to_string(),
                 "Title".to_string(),
                 "Status".to_string(),
+                "Expires".to_string(),
             ]);
 
+        let selection_sender = sender.clone();
         messages_list_view
             .selection_model
             .connect_selected_item_notify(move |sel_model| {
-                let sender = sender.clone();
+                let sender = selection_sender.clone();
                 if sel_model.selected_item().is_none() {
                     return;
                 }
@@ -196,68 +691,447 @@ impl AsyncComponent for MessagesContent {
                 sender.input(MessagesContentInput::MessageSelected(selected_item.clone()));
             });
 
+        // keep statuses of the shown messages up to date while they're being sent
+        let mut client = state::STATE.read_inner().client.clone().unwrap();
+        match client.subscribe_message_status().await {
+            Ok(mut statuses) => sender.command(|out, shutdown| {
+                shutdown
+                    .register(async move {
+                        while let Some(event) = statuses.next().await {
+                            if out.send(event).is_err() {
+                                break;
+                            }
+                        }
+                    })
+                    .drop_on_shutdown()
+            }),
+            Err(e) => log::error!("Failed to subscribe to message status changes: {}", e),
+        }
+
+        let rebroadcast_button = gtk::Button::builder()
+            .label("Rebroadcast")
+            .tooltip_text("Offer the message to peers again, e.g. to ones which joined later")
+            .css_classes(["flat"])
+            .build();
+        let context_menu = gtk::Popover::builder()
+            .has_arrow(false)
+            .position(gtk::PositionType::Bottom)
+            .child(&rebroadcast_button)
+            .build();
+        context_menu.set_parent(&messages_list_view.view);
+        let s = sender.clone();
+        let menu = context_menu.clone();
+        rebroadcast_button.connect_clicked(move |_| {
+            menu.popdown();
+            s.input(MessagesContentInput::RebroadcastMessage);
+        });
+        let right_click = gtk::GestureClick::builder()
+            .button(gtk::gdk::BUTTON_SECONDARY)
+            .build();
+        let s = sender.clone();
+        right_click.connect_pressed(move |_, _, x, y| {
+            s.input(MessagesContentInput::ShowContextMenu(x, y));
+        });
+        messages_list_view.view.add_controller(right_click);
+
         let mut model = Self {
             selected_folder: None,
             messages_list_view,
+            loaded_messages: 0,
+            has_more_messages: false,
             current_msg: None,
             current_msg_buffer: gtk::TextBuffer::new(None),
+            show_formatting: true,
+            search_query: String::new(),
             list_stack: gtk::Stack::default(),
+            failure_banner: adw::Banner::default(),
+            context_menu,
         };
 
         let messages_list = &model.messages_list_view.view;
         let widgets = view_output!();
         model.list_stack = widgets.list_stack.clone();
+        model.failure_banner = widgets.failure_banner.clone();
         AsyncComponentParts { model, widgets }
     }
 
+    async fn update_cmd(
+        &mut self,
+        event: Self::CommandOutput,
+        sender: AsyncComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        if event.status == "Received" || event.status == "Unverified" {
+            _ = sender.output(MessagesContentOutput::MessagesChanged);
+        }
+        let matches =
+            |hash: &str| hash == event.hash || Some(hash) == event.previous_hash.as_deref();
+        let position = (0..self.messages_list_view.len()).find(|i| {
+            self.messages_list_view
+                .get(*i)
+                .map_or(false, |item| matches(&item.borrow().hash))
+        });
+        // messages move from Outbox to Sent once they're sent out
+        let folder = self.selected_folder.as_ref().map(|f| f.folder);
+        let moved = match (folder, position) {
+            (Some(Folder::Outbox), Some(_)) => !is_outbox_status(&event.status),
+            (Some(Folder::Outbox), None) => is_outbox_status(&event.status),
+            (Some(Folder::Sent), None) => event.status == "Sent",
+            _ => false,
+        };
+        if moved {
+            sender.input(MessagesContentInput::Reload);
+            return;
+        }
+        let Some(position) = position else {
+            return;
+        };
+        let mut item = self
+            .messages_list_view
+            .get(position)
+            .unwrap()
+            .borrow()
+            .clone();
+        item.hash = event.hash.clone();
+        item.status = event.status.clone();
+        // list items are rebound only when they're replaced
+        self.messages_list_view.remove(position);
+        self.messages_list_view.insert(position, item.clone());
+
+        if matches!(&self.current_msg, Some(m) if matches(&m.hash)) {
+            match &event.pubkey_request {
+                Some(request) => self.show_pubkey_request(request),
+                None if item.status == "Cancelled" => self.show_cancelled(),
+                None if item.failure_reason.is_none() => self.failure_banner.set_revealed(false),
+                None => {}
+            }
+            self.current_msg = Some(item);
+        }
+    }
+
     async fn update(
         &mut self,
         message: Self::Input,
-        _sender: AsyncComponentSender<Self>,
-        _root: &Self::Root,
+        sender: AsyncComponentSender<Self>,
+        root: &Self::Root,
     ) {
         match message {
             MessagesContentInput::FolderSelected(selected_folder) => {
                 self.messages_list_view.clear();
-                self.selected_folder = Some(selected_folder.clone());
-                let folder = match selected_folder.folder.as_str() {
-                    "Inbox" => Folder::Inbox,
-                    "Sent" => Folder::Sent,
-                    _ => Folder::Inbox,
+                self.failure_banner.set_revealed(false);
+                self.current_msg = None;
+                self.current_msg_buffer.set_text("");
+                self.selected_folder = Some(selected_folder);
+                self.loaded_messages = 0;
+                self.has_more_messages = false;
+                self.load_messages().await;
+            }
+            MessagesContentInput::ShowFormatting(show) => {
+                self.show_formatting = show;
+                if let Some(m) = &self.current_msg {
+                    m.body.show(&self.current_msg_buffer, show);
+                }
+            }
+            MessagesContentInput::LoadMore => {
+                if self.has_more_messages {
+                    self.load_messages().await;
+                }
+            }
+            MessagesContentInput::MessageSelected(mut m) => {
+                if !m.read {
+                    let mut client = state::STATE.read_inner().client.clone().unwrap();
+                    match client.mark_read(m.hash.clone(), true).await {
+                        Ok(_) => {
+                            m.read = true;
+                            self.mark_item_read(&m.hash);
+                            _ = sender.output(MessagesContentOutput::MessagesChanged);
+                        }
+                        Err(e) => log::error!("Failed to mark message as read: {}", e),
+                    }
+                }
+                self.current_msg = Some(m.clone());
+                m.body.show(&self.current_msg_buffer, self.show_formatting);
+                match &m.failure_reason {
+                    Some(reason) if m.status == "Unverified" => {
+                        self.failure_banner.set_title(&format!(
+                            "The sender can't be verified, the message might be forged: {}",
+                            reason
+                        ));
+                        self.failure_banner.set_button_label(None);
+                        self.failure_banner.set_revealed(true);
+                    }
+                    Some(reason) => {
+                        self.failure_banner
+                            .set_title(&format!("Message delivery failed: {}", reason));
+                        self.failure_banner.set_button_label(Some("Retry"));
+                        self.failure_banner.set_revealed(true);
+                    }
+                    None if m.status == "Cancelled" => self.show_cancelled(),
+                    None if m.status == "WaitingForPubkey" => {
+                        let mut client = state::STATE.read_inner().client.clone().unwrap();
+                        match client.get_pubkey_request(m.hash.clone()).await {
+                            Ok(Some(request)) => self.show_pubkey_request(&request),
+                            Ok(None) => self.failure_banner.set_revealed(false),
+                            Err(e) => {
+                                log::error!("Failed to get pubkey request: {}", e);
+                                self.failure_banner.set_revealed(false);
+                            }
+                        }
+                    }
+                    None => self.failure_banner.set_revealed(false),
+                }
+            }
+            MessagesContentInput::RetryMessage => {
+                let (hash, status) = match &self.current_msg {
+                    Some(m) => (m.hash.clone(), m.status.clone()),
+                    None => return,
                 };
-                // load messages from db
-                let msgs = state::STATE
+                // the banner shows the new request once it's sent
+                if status == "WaitingForPubkey" {
+                    let mut client = state::STATE.read_inner().client.clone().unwrap();
+                    if let Err(e) = client.retry_pubkey_request(hash).await {
+                        log::error!("Failed to request the pubkey again: {}", e);
+                    }
+                    return;
+                }
+                let result = state::STATE
                     .write_inner()
                     .client
                     .as_mut()
                     .unwrap()
-                    .get_messages(selected_folder.identity_address.clone(), folder)
+                    .retry_message(hash)
                     .await;
-                if !msgs.is_empty() {
-                    self.list_stack.set_visible_child_name("list");
-                    for m in msgs {
-                        let mime_msg = mail_parser::Message::parse(m.data.as_slice()).unwrap();
-                        let title = mime_msg.subject().unwrap().to_string();
-                        let date = m.created_at;
-                        let from = m.sender;
-                        let body = mime_msg.body_text(0).unwrap();
-                        self.messages_list_view.append(MessagesListItem {
-                            title,
-                            date,
-                            from,
-                            to: m.recipient,
-                            body: body.to_string(),
-                            status: m.status,
-                        });
+                if let Err(e) = result {
+                    log::error!("Failed to retry message: {}", e);
+                }
+                if let Some(folder) = self.selected_folder.clone() {
+                    sender.input(MessagesContentInput::FolderSelected(folder));
+                }
+            }
+            MessagesContentInput::CancelSend => {
+                let hash = match &self.current_msg {
+                    Some(m) => m.hash.clone(),
+                    None => return,
+                };
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                let result = client.cancel_send(hash).await;
+                if let Err(e) = result {
+                    log::error!("Failed to cancel message: {}", e);
+                }
+                if let Some(folder) = self.selected_folder.clone() {
+                    sender.input(MessagesContentInput::FolderSelected(folder));
+                }
+            }
+            MessagesContentInput::DeleteMessage => {
+                let hash = match &self.current_msg {
+                    Some(m) => m.hash.clone(),
+                    None => return,
+                };
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .delete_message(hash)
+                    .await;
+                match result {
+                    Ok(_) => _ = sender.output(MessagesContentOutput::MessagesChanged),
+                    Err(e) => log::error!("Failed to delete message: {}", e),
+                }
+                if let Some(folder) = self.selected_folder.clone() {
+                    sender.input(MessagesContentInput::FolderSelected(folder));
+                }
+            }
+            MessagesContentInput::EditDraft => {
+                let init = match &self.current_msg {
+                    Some(m) => MessageComposerInit {
+                        draft_hash: Some(m.hash.clone()),
+                        from: Some(m.from.clone()),
+                        to: m.to.clone(),
+                        subject: m.title.clone(),
+                        body: m.body.text.clone(),
+                    },
+                    None => return,
+                };
+                self.open_composer(init, &sender);
+            }
+            MessagesContentInput::Reply => {
+                let Some(m) = &self.current_msg else {
+                    return;
+                };
+                // quote the sender by their name if they're known
+                let sender_name = m.from_label.clone().unwrap_or_else(|| m.from.clone());
+                let init = MessageComposerInit::reply(
+                    m.to.clone(),
+                    m.from.clone(),
+                    &sender_name,
+                    m.date,
+                    &m.title,
+                    &m.body.text,
+                );
+                self.open_composer(init, &sender);
+            }
+            MessagesContentInput::Forward => {
+                let (Some(m), Some(folder)) = (&self.current_msg, &self.selected_folder) else {
+                    return;
+                };
+                let init = MessageComposerInit::forward(
+                    folder.identity_address.clone(),
+                    &m.from_name,
+                    &m.to_name,
+                    m.date,
+                    &m.title,
+                    &m.body.text,
+                );
+                self.open_composer(init, &sender);
+            }
+            MessagesContentInput::SearchChanged(query) => {
+                self.search_query = query;
+                if let Some(folder) = self.selected_folder.clone() {
+                    sender.input(MessagesContentInput::FolderSelected(folder));
+                }
+            }
+            MessagesContentInput::Reload => {
+                if let Some(folder) = self.selected_folder.clone() {
+                    sender.input(MessagesContentInput::FolderSelected(folder));
+                }
+            }
+            MessagesContentInput::ExportMessage => {
+                let Some(m) = &self.current_msg else {
+                    return;
+                };
+                choose_export_path(root, sender, vec![m.hash.clone()], false, "message.eml");
+            }
+            MessagesContentInput::ExportFolder => {
+                let Some(folder) = &self.selected_folder else {
+                    return;
+                };
+                // messages which aren't loaded yet are exported too
+                let hashes: Vec<String> = if self.has_more_messages {
+                    let mut client = state::STATE.read_inner().client.clone().unwrap();
+                    match client
+                        .get_messages(folder.identity_address.clone(), folder.folder)
+                        .await
+                    {
+                        Ok(msgs) => msgs.into_iter().map(|m| m.hash).collect(),
+                        Err(e) => {
+                            show_message(root, "Failed to export messages", &e.to_string());
+                            return;
+                        }
                     }
                 } else {
-                    self.list_stack.set_visible_child_name("empty");
+                    (0..self.messages_list_view.len())
+                        .filter_map(|i| self.messages_list_view.get(i))
+                        .map(|item| item.borrow().hash.clone())
+                        .collect()
+                };
+                if hashes.is_empty() {
+                    return;
                 }
+                let name = format!("{}.mbox", folder.folder.to_string().to_lowercase());
+                choose_export_path(root, sender, hashes, true, &name);
             }
-            MessagesContentInput::MessageSelected(m) => {
-                self.current_msg = Some(m.clone());
-                self.current_msg_buffer.set_text(m.body.as_str());
+            MessagesContentInput::SaveExport { hashes, mbox, path } => {
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                let result = client.export_messages(hashes, mbox).await.and_then(|data| {
+                    std::fs::write(path, data).map_err(|e| Box::from(e.to_string()))
+                });
+                if let Err(e) = result {
+                    show_message(root, "Failed to export messages", &e.to_string());
+                }
+            }
+            MessagesContentInput::HandleImport => {
+                let dialog = gtk::FileDialog::builder().title("Import messages").build();
+                dialog.open(
+                    root.root().and_downcast_ref::<gtk::Window>(),
+                    gio::Cancellable::NONE,
+                    move |res| {
+                        if let Some(path) = res.ok().and_then(|f| f.path()) {
+                            sender.input(MessagesContentInput::ImportMessages(path));
+                        }
+                    },
+                );
+            }
+            MessagesContentInput::ImportMessages(path) => {
+                let Some(selected_folder) = self.selected_folder.clone() else {
+                    return;
+                };
+                let data = match std::fs::read(path) {
+                    Ok(d) => d,
+                    Err(e) => {
+                        show_message(root, "Failed to import messages", &e.to_string());
+                        return;
+                    }
+                };
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                let result = client
+                    .import_messages(
+                        selected_folder.identity_address.clone(),
+                        selected_folder.folder,
+                        data,
+                    )
+                    .await;
+                match result {
+                    Ok(imported) => {
+                        _ = sender.output(MessagesContentOutput::MessagesChanged);
+                        sender.input(MessagesContentInput::FolderSelected(selected_folder));
+                        show_message(
+                            root,
+                            "Messages imported",
+                            &format!("{} new messages have been imported", imported),
+                        );
+                    }
+                    Err(e) => show_message(root, "Failed to import messages", &e.to_string()),
+                }
+            }
+            MessagesContentInput::ShowContextMenu(x, y) => {
+                // only sent messages have actions so far
+                if !self.is_sent_selected() || self.current_msg.is_none() {
+                    return;
+                }
+                self.context_menu
+                    .set_pointing_to(Some(&gtk::gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+                self.context_menu.popup();
+            }
+            MessagesContentInput::RebroadcastMessage => {
+                let Some(m) = &self.current_msg else {
+                    return;
+                };
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                match client.rebroadcast_object(m.hash.clone()).await {
+                    Ok(()) => show_message(
+                        root,
+                        "Message is rebroadcast",
+                        "The message has been offered to connected peers again",
+                    ),
+                    Err(e) => show_message(root, "Failed to rebroadcast message", &e.to_string()),
+                }
+            }
+            MessagesContentInput::RestoreMessage => {
+                let hash = match &self.current_msg {
+                    Some(m) => m.hash.clone(),
+                    None => return,
+                };
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .restore_message(hash)
+                    .await;
+                match result {
+                    Ok(_) => _ = sender.output(MessagesContentOutput::MessagesChanged),
+                    Err(e) => log::error!("Failed to restore message: {}", e),
+                }
+                if let Some(folder) = self.selected_folder.clone() {
+                    sender.input(MessagesContentInput::FolderSelected(folder));
+                }
             }
         }
     }
+
+    fn shutdown(&mut self, _widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        // the menu is attached to the list rather than placed in the widget tree
+        self.context_menu.unparent();
+    }
 }
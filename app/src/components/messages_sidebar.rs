@@ -1,9 +1,13 @@
-use std::cell::{Ref, RefMut};
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+    rc::Rc,
+};
 
 use gtk::{
     self, gio,
     glib::BoxedAnyObject,
-    prelude::{Cast, CastNone, ObjectExt, StaticType},
+    prelude::{Cast, CastNone, ListModelExt, ObjectExt, StaticType},
     traits::{OrientableExt, WidgetExt},
 };
 use relm4::{
@@ -12,19 +16,30 @@ use relm4::{
 };
 
 use super::utils::typed_list_view::RelmListItem;
-use crate::state;
+use crate::{network::node::worker::Folder, state};
+
+/// Folders listed under each identity
+const IDENTITY_FOLDERS: [Folder; 6] = [
+    Folder::Inbox,
+    Folder::Outbox,
+    Folder::Sent,
+    Folder::Drafts,
+    Folder::Trash,
+    Folder::Quarantine,
+];
 
 #[derive(Debug, Clone)]
 pub struct SelectedFolder {
     pub identity_address: String,
-    pub folder: String,
+    pub folder: Folder,
 }
 
 #[derive(Debug)]
 enum FolderItemType {
     Identity,
-    Inbox,
-    Sent,
+    /// Address whose broadcasts are received
+    Subscription,
+    Folder(Folder),
 }
 
 #[derive(Debug)]
@@ -38,6 +53,8 @@ struct IdentityItemWidgets {
     expander: gtk::TreeExpander,
     label: gtk::Label,
     subtitle: gtk::Label,
+    /// Number of unread messages, shown for Inbox
+    badge: gtk::Label,
 }
 
 impl RelmListItem for FolderItem {
@@ -50,17 +67,28 @@ impl RelmListItem for FolderItem {
             gtk::TreeExpander {
                 #[wrap(Some)]
                 set_child = &gtk::Box {
-                    set_orientation: gtk::Orientation::Vertical,
-                    set_valign: gtk::Align::Center,
+                    set_orientation: gtk::Orientation::Horizontal,
 
-                    #[name(label)]
-                    gtk::Label {
-                        set_halign: gtk::Align::Start,
-                        set_valign: gtk::Align::Center
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+                        set_valign: gtk::Align::Center,
+                        set_hexpand: true,
+
+                        #[name(label)]
+                        gtk::Label {
+                            set_halign: gtk::Align::Start,
+                            set_valign: gtk::Align::Center
+                        },
+                        #[name(subtitle)]
+                        gtk::Label {
+                            add_css_class: "subtitle",
+                            set_visible: false
+                        }
                     },
-                    #[name(subtitle)]
+                    #[name(badge)]
                     gtk::Label {
-                        add_css_class: "subtitle",
+                        add_css_class: "dim-label",
+                        add_css_class: "numeric",
                         set_visible: false
                     }
                 }
@@ -71,27 +99,110 @@ impl RelmListItem for FolderItem {
             expander: expander.clone(),
             label,
             subtitle,
+            badge,
         };
         (expander, widgets)
     }
 
     fn bind(&mut self, widgets: &mut Self::Widgets, _root: &mut Self::Root, _column_index: usize) {
         widgets.label.set_text(&self.label);
-        if let FolderItemType::Identity = self.item_type {
+        if let FolderItemType::Identity | FolderItemType::Subscription = self.item_type {
             widgets.subtitle.set_visible(true);
             widgets.subtitle.set_text(&self.subtitle);
         }
     }
 }
 
+/// Label of the identity row, chans are marked like in PyBitmessage
+fn identity_label(label: String, chan: bool) -> String {
+    if label.is_empty() {
+        "No label".to_string()
+    } else if chan {
+        format!("[chan] {}", label)
+    } else {
+        label
+    }
+}
+
+/// Label of the subscription row, marked like chans
+fn subscription_label(label: String) -> String {
+    if label.is_empty() {
+        "[subscription] No label".to_string()
+    } else {
+        format!("[subscription] {}", label)
+    }
+}
+
+/// Identities followed by subscriptions, which are the top level rows of the list
+async fn load_root_items() -> Vec<FolderItem> {
+    let mut client = state::STATE.read_inner().client.clone().unwrap();
+    let identities = client.get_own_identities().await.unwrap_or_else(|e| {
+        log::error!("Failed to load identities: {}", e);
+        Vec::new()
+    });
+    let subscriptions = client.get_subscriptions().await.unwrap_or_else(|e| {
+        log::error!("Failed to load subscriptions: {}", e);
+        Vec::new()
+    });
+    identities
+        .into_iter()
+        .map(|i| FolderItem {
+            label: identity_label(i.label, i.chan),
+            subtitle: i.string_repr,
+            item_type: FolderItemType::Identity,
+        })
+        .chain(subscriptions.into_iter().map(|s| FolderItem {
+            label: subscription_label(s.label),
+            subtitle: s.address,
+            item_type: FolderItemType::Subscription,
+        }))
+        .collect()
+}
+
+/// Show number of unread messages, the badge is hidden if there are none
+fn set_badge(badge: &gtk::Label, unread: u64) {
+    badge.set_visible(unread > 0);
+    badge.set_text(&unread.to_string());
+}
+
 pub struct MessagesSidebar {
     tree_model: gtk::TreeListModel,
     list_view: gtk::ListView,
+    /// Unread messages in Inbox of each identity
+    unread: Rc<RefCell<HashMap<String, u64>>>,
+    /// Badges of Inbox rows which are currently bound, by identity address
+    badges: Rc<RefCell<HashMap<String, gtk::Label>>>,
+}
+
+impl MessagesSidebar {
+    /// Reload unread counters of all identities and subscriptions in the list
+    async fn update_folder_stats(&self) {
+        let root_model = self.tree_model.model();
+        let addresses: Vec<String> = (0..root_model.n_items())
+            .filter_map(|i| root_model.item(i).and_downcast::<BoxedAnyObject>())
+            .map(|o| o.borrow::<FolderItem>().subtitle.clone())
+            .collect();
+        let mut client = state::STATE.read_inner().client.clone().unwrap();
+        let mut unread = HashMap::new();
+        for address in addresses {
+            match client.get_folder_stats(address.clone()).await {
+                Ok(stats) => _ = unread.insert(address, stats.inbox.unread),
+                Err(e) => log::error!("Failed to load folder stats: {}", e),
+            }
+        }
+        for (address, badge) in self.badges.borrow().iter() {
+            set_badge(badge, unread.get(address).copied().unwrap_or_default());
+        }
+        *self.unread.borrow_mut() = unread;
+    }
 }
 
 #[derive(Debug)]
 pub enum MessagesSidebarInput {
     IdentitiesListUpdated,
+    SubscriptionsUpdated,
+    /// Messages were received, read or moved
+    FolderStatsChanged,
 }
 
 #[derive(Debug)]
@@ -122,46 +233,32 @@ impl SimpleAsyncComponent for MessagesSidebar {
         sender: AsyncComponentSender<Self>,
     ) -> AsyncComponentParts<Self> {
         let root_store = gio::ListStore::new(BoxedAnyObject::static_type());
-
-        let identities = state::STATE
-            .write_inner()
-            .client
-            .as_mut()
-            .unwrap()
-            .get_own_identities()
-            .await;
-        for i in identities {
-            root_store.append(&BoxedAnyObject::new(FolderItem {
-                label: if i.label.is_empty() {
-                    "No label".to_string()
-                } else {
-                    i.label
-                },
-                subtitle: i.string_repr,
-                item_type: FolderItemType::Identity,
-            }))
+        for item in load_root_items().await {
+            root_store.append(&BoxedAnyObject::new(item));
         }
 
         let tree_model = gtk::TreeListModel::new(root_store.clone(), false, true, |o| {
             let boxed_object = o.clone().downcast::<BoxedAnyObject>().unwrap();
             let item: Ref<FolderItem> = boxed_object.borrow();
-            if let FolderItemType::Identity = item.item_type {
-                let inner_folders = gio::ListStore::new(BoxedAnyObject::static_type());
+            let folders: &[Folder] = match item.item_type {
+                FolderItemType::Identity => &IDENTITY_FOLDERS,
+                FolderItemType::Subscription => &[Folder::Broadcasts],
+                FolderItemType::Folder(_) => return None,
+            };
+            let inner_folders = gio::ListStore::new(BoxedAnyObject::static_type());
+            for folder in folders {
                 inner_folders.append(&BoxedAnyObject::new(FolderItem {
-                    label: "Inbox".to_string(),
+                    label: folder.to_string(),
                     subtitle: String::new(),
-                    item_type: FolderItemType::Inbox,
+                    item_type: FolderItemType::Folder(*folder),
                 }));
-                inner_folders.append(&BoxedAnyObject::new(FolderItem {
-                    label: "Sent".to_string(),
-                    subtitle: String::new(),
-                    item_type: FolderItemType::Sent,
-                }));
-                return Some(inner_folders.upcast());
             }
-            None
+            Some(inner_folders.upcast())
         });
 
+        let unread: Rc<RefCell<HashMap<String, u64>>> = Rc::default();
+        let badges: Rc<RefCell<HashMap<String, gtk::Label>>> = Rc::default();
+
         let factory = gtk::SignalListItemFactory::new();
         factory.connect_setup(move |_factory, item| {
             let item = item.downcast_ref::<gtk::ListItem>().unwrap();
@@ -170,6 +267,7 @@ impl SimpleAsyncComponent for MessagesSidebar {
             item.set_child(Some(&root));
         });
 
+        let (bind_unread, bind_badges) = (unread.clone(), badges.clone());
         factory.connect_bind(move |_factory, item| {
             let list_item = item.downcast_ref::<gtk::ListItem>().unwrap();
             let widget = list_item.child();
@@ -190,11 +288,30 @@ impl SimpleAsyncComponent for MessagesSidebar {
                 .unwrap();
 
             let mut widgets = unsafe { root.steal_data("widgets") }.unwrap();
-            if let FolderItemType::Identity = obj.item_type {
+            if let FolderItemType::Identity | FolderItemType::Subscription = obj.item_type {
                 list_item.set_activatable(false);
                 list_item.set_selectable(false);
             }
             obj.bind(&mut widgets, &mut root, 0);
+            // rows are recycled, so the badge might have been bound to another Inbox
+            bind_badges
+                .borrow_mut()
+                .retain(|_, badge| badge != &widgets.badge);
+            let address = list_row
+                .parent()
+                .and_then(|p| p.item())
+                .and_downcast::<BoxedAnyObject>()
+                .map(|o| o.borrow::<FolderItem>().subtitle.clone());
+            match (&obj.item_type, address) {
+                (FolderItemType::Folder(Folder::Inbox | Folder::Broadcasts), Some(address)) => {
+                    let count = bind_unread.borrow().get(&address).copied();
+                    set_badge(&widgets.badge, count.unwrap_or_default());
+                    bind_badges
+                        .borrow_mut()
+                        .insert(address, widgets.badge.clone());
+                }
+                _ => widgets.badge.set_visible(false),
+            }
             widgets.expander.set_list_row(Some(&list_row));
             unsafe { root.set_data("widgets", widgets) };
         });
@@ -228,6 +345,9 @@ impl SimpleAsyncComponent for MessagesSidebar {
                 .downcast::<BoxedAnyObject>()
                 .unwrap();
             let selected_item: Ref<FolderItem> = boxed_obj.borrow();
+            let FolderItemType::Folder(folder) = selected_item.item_type else {
+                return;
+            };
             log::debug!(
                 "Item selected: {:?}/{:?}",
                 parent_of_selected_item,
@@ -237,7 +357,7 @@ impl SimpleAsyncComponent for MessagesSidebar {
             sender
                 .output(MessagesSidebarOutput::FolderSelected(SelectedFolder {
                     identity_address: parent_of_selected_item.subtitle.clone(),
-                    folder: selected_item.label.clone(),
+                    folder,
                 }))
                 .unwrap();
         });
@@ -245,7 +365,10 @@ impl SimpleAsyncComponent for MessagesSidebar {
         let model = Self {
             list_view: list_view.clone(),
             tree_model,
+            unread,
+            badges,
         };
+        model.update_folder_stats().await;
 
         let widgets = view_output!();
         AsyncComponentParts { model, widgets }
@@ -253,32 +376,21 @@ impl SimpleAsyncComponent for MessagesSidebar {
 
     async fn update(&mut self, message: Self::Input, _sender: AsyncComponentSender<Self>) {
         match message {
-            MessagesSidebarInput::IdentitiesListUpdated => {
+            MessagesSidebarInput::IdentitiesListUpdated
+            | MessagesSidebarInput::SubscriptionsUpdated => {
                 let root_model = self
                     .tree_model
                     .model()
                     .downcast::<gio::ListStore>()
                     .unwrap();
+                let items = load_root_items().await;
                 root_model.remove_all();
-                let identities = state::STATE
-                    .write_inner()
-                    .client
-                    .as_mut()
-                    .unwrap()
-                    .get_own_identities()
-                    .await;
-                for i in identities {
-                    root_model.append(&BoxedAnyObject::new(FolderItem {
-                        label: if i.label.is_empty() {
-                            "No label".to_string()
-                        } else {
-                            i.label
-                        },
-                        subtitle: i.string_repr,
-                        item_type: FolderItemType::Identity,
-                    }))
+                for item in items {
+                    root_model.append(&BoxedAnyObject::new(item));
                 }
+                self.update_folder_stats().await;
             }
+            MessagesSidebarInput::FolderStatsChanged => self.update_folder_stats().await,
         }
     }
 }
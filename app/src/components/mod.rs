@@ -1,3 +1,4 @@
+pub mod contacts_list;
 pub mod dialogs;
 mod factories;
 pub mod identities_list;
@@ -6,4 +7,7 @@ pub mod messages;
 mod messages_content;
 mod messages_sidebar;
 pub mod network_status;
+pub mod node_settings;
+pub mod rules_list;
+pub mod settings;
 mod utils;
@@ -1,15 +1,115 @@
-use gtk::{self, prelude::*};
+use adw::prelude::*;
+use gtk::{self, glib};
 use relm4::RelmWidgetExt;
 use relm4::{
     component::{AsyncComponent, AsyncComponentParts},
+    factory::FactoryVecDeque,
     loading_widgets::LoadingWidgets,
     view,
 };
 
-pub(crate) struct NetworkStatusModel {}
+use crate::components::factories::{
+    inventory_list_row::InventoryListRow, peer_list_row::PeerListRow,
+};
+use crate::network::{
+    messages::InventoryCursor,
+    node::{
+        command_queue::CommandQueueStats,
+        rate_limit::TrafficStats,
+        worker::{BootstrapResult, DhtStatus},
+    },
+};
+use crate::state;
+
+/// How often the status is refreshed
+const REFRESH_INTERVAL_SECONDS: u32 = 5;
+/// Number of inventory objects loaded at once
+const INVENTORY_PAGE_SIZE: usize = 50;
+
+pub(crate) struct NetworkStatusModel {
+    peer_id: String,
+    connected_peers: Vec<String>,
+    peers_list: FactoryVecDeque<PeerListRow>,
+    banned_list: FactoryVecDeque<PeerListRow>,
+    traffic: TrafficStats,
+    command_queue: CommandQueueStats,
+    /// Size of the inventory along with its limit
+    inventory: String,
+    /// Stored objects, loaded page by page on demand, since the inventory may be large
+    inventory_list: FactoryVecDeque<InventoryListRow>,
+    /// Where the next page of `inventory_list` starts, `None` once all objects are loaded
+    inventory_cursor: Option<InventoryCursor>,
+    dht: DhtStatus,
+}
 
 #[derive(Debug)]
-pub(crate) enum NetworkStatusInput {}
+pub(crate) enum NetworkStatusInput {
+    Refresh,
+    /// Load the inventory list from the start
+    ReloadInventory,
+    LoadMoreInventory,
+    BanPeer(String),
+    UnbanPeer(String),
+}
+
+/// Format amount of bytes in human readable units
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_bootstrap(bootstrap: &Option<BootstrapResult>) -> String {
+    let Some(bootstrap) = bootstrap else {
+        return "Not finished yet".to_string();
+    };
+    let time = bootstrap
+        .finished_at
+        .with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M");
+    match &bootstrap.error {
+        Some(e) => format!("Failed at {}: {}", time, e),
+        None => format!("Succeeded at {}", time),
+    }
+}
+
+fn show_error(root: &gtk::ScrolledWindow, title: &str, message: &str) {
+    let dialog = adw::MessageDialog::new(
+        root.root().and_downcast_ref::<gtk::Window>(),
+        Some(title),
+        Some(message),
+    );
+    dialog.add_response("ok", "OK");
+    dialog.present();
+}
+
+impl NetworkStatusModel {
+    /// Append the page of inventory objects starting after `after`
+    async fn load_inventory(&mut self, after: Option<InventoryCursor>, root: &gtk::ScrolledWindow) {
+        let mut client = state::STATE.read_inner().client.clone().unwrap();
+        match client.get_inventory(after, INVENTORY_PAGE_SIZE).await {
+            Ok(page) => {
+                self.inventory_cursor = match page.last() {
+                    Some(last) if page.len() == INVENTORY_PAGE_SIZE => Some(InventoryCursor {
+                        expires: last.expires.timestamp(),
+                        hash: last.hash.clone(),
+                    }),
+                    _ => None,
+                };
+                let mut guard = self.inventory_list.guard();
+                for item in page {
+                    guard.push_back(item);
+                }
+            }
+            Err(e) => show_error(root, "Failed to load inventory", &e.to_string()),
+        }
+    }
+}
 
 #[relm4::component(pub async)]
 impl AsyncComponent for NetworkStatusModel {
@@ -21,11 +121,141 @@ impl AsyncComponent for NetworkStatusModel {
     view! {
         #[root]
         gtk::ScrolledWindow {
-            gtk::CenterBox {
-                #[wrap(Some)]
-                set_center_widget = &gtk::Label {
-                    set_label: "Network Status is not implemented",
-                    add_css_class: "large-title"
+            adw::Clamp {
+                set_margin_all: 12,
+
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 12,
+
+                    adw::PreferencesGroup {
+                        set_title: "Node",
+
+                        adw::ActionRow {
+                            set_title: "Peer ID",
+                            #[watch]
+                            set_subtitle: &model.peer_id,
+                            set_subtitle_selectable: true,
+                        },
+                        adw::ActionRow {
+                            set_title: "Connected peers",
+                            #[watch]
+                            set_subtitle: &model.connected_peers.len().to_string(),
+                        },
+                        adw::ActionRow {
+                            set_title: "Queued commands",
+                            set_tooltip_text: Some("Requests of the app waiting to be handled by the node, more are rejected as busy"),
+                            #[watch]
+                            set_subtitle: &format!(
+                                "{} of {} (max {}, rejected {})",
+                                model.command_queue.queued,
+                                model.command_queue.capacity,
+                                model.command_queue.max_queued,
+                                model.command_queue.rejected,
+                            ),
+                        }
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: "Peers",
+
+                        #[local_ref]
+                        peers_list -> gtk::ListBox {
+                            add_css_class: "boxed-list",
+                            set_selection_mode: gtk::SelectionMode::None,
+                        }
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: "Peer discovery",
+                        set_description: Some("New peers are found via the DHT, it's bootstrapped again once it knows no peers"),
+
+                        adw::ActionRow {
+                            set_title: "DHT peers",
+                            #[watch]
+                            set_subtitle: &format!(
+                                "{} in {} buckets",
+                                model.dht.known_peers,
+                                model.dht.buckets.len(),
+                            ),
+                        },
+                        adw::ActionRow {
+                            set_title: "Last bootstrap",
+                            #[watch]
+                            set_subtitle: &format_bootstrap(&model.dht.last_bootstrap),
+                        }
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: "Banned peers",
+                        set_description: Some("Banned peers are disconnected and never connected again"),
+                        #[watch]
+                        set_visible: !model.banned_list.is_empty(),
+
+                        #[local_ref]
+                        banned_list -> gtk::ListBox {
+                            add_css_class: "boxed-list",
+                            set_selection_mode: gtk::SelectionMode::None,
+                        }
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: "Inventory",
+
+                        adw::ActionRow {
+                            set_title: "Stored objects",
+                            set_tooltip_text: Some("Objects beyond the size limit are evicted"),
+                            #[watch]
+                            set_subtitle: &model.inventory,
+                        }
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: "Inventory objects",
+                        set_description: Some("Objects which expire first are listed first"),
+                        #[wrap(Some)]
+                        set_header_suffix = &gtk::Button {
+                            set_icon_name: "view-refresh-symbolic",
+                            set_tooltip_text: Some("Reload"),
+                            add_css_class: "flat",
+                            connect_clicked => NetworkStatusInput::ReloadInventory,
+                        },
+
+                        #[local_ref]
+                        inventory_list -> gtk::ListBox {
+                            add_css_class: "boxed-list",
+                            set_selection_mode: gtk::SelectionMode::None,
+                        },
+                        gtk::Button {
+                            set_label: "Load more",
+                            set_margin_top: 12,
+                            set_halign: gtk::Align::Center,
+                            #[watch]
+                            set_visible: model.inventory_cursor.is_some(),
+                            connect_clicked => NetworkStatusInput::LoadMoreInventory,
+                        }
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: "Traffic",
+
+                        adw::ActionRow {
+                            set_title: "Received",
+                            #[watch]
+                            set_subtitle: &format_bytes(model.traffic.bytes_received),
+                        },
+                        adw::ActionRow {
+                            set_title: "Sent",
+                            #[watch]
+                            set_subtitle: &format_bytes(model.traffic.bytes_sent),
+                        },
+                        adw::ActionRow {
+                            set_title: "Dropped messages",
+                            set_tooltip_text: Some("Incoming messages dropped due to rate or bandwidth limits"),
+                            #[watch]
+                            set_subtitle: &model.traffic.dropped_messages.to_string(),
+                        }
+                    }
                 }
             }
         }
@@ -55,10 +285,145 @@ impl AsyncComponent for NetworkStatusModel {
     async fn init(
         _init: Self::Init,
         root: Self::Root,
-        _sender: relm4::AsyncComponentSender<Self>,
+        sender: relm4::AsyncComponentSender<Self>,
     ) -> AsyncComponentParts<Self> {
-        let model = Self {};
+        let peer_id = state::STATE
+            .write_inner()
+            .client
+            .as_mut()
+            .unwrap()
+            .get_peer_id()
+            .await;
+        let peers_list = gtk::ListBox::default();
+        let banned_list = gtk::ListBox::default();
+        let inventory_list = gtk::ListBox::default();
+        let model = Self {
+            peer_id: peer_id.map(|p| p.to_string()).unwrap_or_else(|e| {
+                log::error!("Failed to get peer id: {}", e);
+                "Unknown".to_string()
+            }),
+            connected_peers: Vec::new(),
+            peers_list: FactoryVecDeque::new(peers_list.clone(), sender.input_sender()),
+            banned_list: FactoryVecDeque::new(banned_list.clone(), sender.input_sender()),
+            traffic: TrafficStats::default(),
+            command_queue: CommandQueueStats::default(),
+            inventory: "Unknown".to_string(),
+            inventory_list: FactoryVecDeque::new(inventory_list.clone(), sender.input_sender()),
+            inventory_cursor: None,
+            dht: DhtStatus::default(),
+        };
+        sender.input(NetworkStatusInput::Refresh);
+        sender.input(NetworkStatusInput::ReloadInventory);
+        let refresh_sender = sender.clone();
+        glib::timeout_add_seconds_local(REFRESH_INTERVAL_SECONDS, move || {
+            refresh_sender.input(NetworkStatusInput::Refresh);
+            glib::Continue(true)
+        });
+
         let widgets = view_output!();
         AsyncComponentParts { model, widgets }
     }
+
+    async fn update(
+        &mut self,
+        message: Self::Input,
+        sender: relm4::AsyncComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match message {
+            NetworkStatusInput::Refresh => {
+                let mut state = state::STATE.write_inner();
+                let client = state.client.as_mut().unwrap();
+                self.command_queue = client.command_queue_stats();
+                match client.get_connected_peers().await {
+                    Ok(peers) => {
+                        let mut peers: Vec<String> = peers.iter().map(|p| p.to_string()).collect();
+                        peers.sort();
+                        // rows are recreated only when peers change, so that buttons don't flicker
+                        if peers != self.connected_peers {
+                            let mut guard = self.peers_list.guard();
+                            guard.clear();
+                            for peer in &peers {
+                                guard.push_back(PeerListRow {
+                                    target: peer.clone(),
+                                    subtitle: "Connected".to_string(),
+                                    banned: false,
+                                });
+                            }
+                            self.connected_peers = peers;
+                        }
+                    }
+                    Err(e) => log::warn!("Failed to get connected peers: {}", e),
+                }
+                match client.list_banned().await {
+                    Ok(banned) => {
+                        let mut guard = self.banned_list.guard();
+                        guard.clear();
+                        for b in banned {
+                            guard.push_back(PeerListRow {
+                                target: b.target,
+                                subtitle: format!(
+                                    "{} ({})",
+                                    b.reason.as_deref().unwrap_or("Banned manually"),
+                                    b.banned_at
+                                        .with_timezone(&chrono::Local)
+                                        .format("%Y-%m-%d %H:%M")
+                                ),
+                                banned: true,
+                            });
+                        }
+                    }
+                    Err(e) => log::warn!("Failed to get banned peers: {}", e),
+                }
+                match client.get_traffic_stats().await {
+                    Ok(traffic) => self.traffic = traffic,
+                    Err(e) => log::warn!("Failed to get traffic stats: {}", e),
+                }
+                match client.get_dht_status().await {
+                    Ok(dht) => self.dht = dht,
+                    Err(e) => log::warn!("Failed to get DHT status: {}", e),
+                }
+                match client.get_inventory_stats().await {
+                    Ok(stats) => {
+                        let limit = match stats.max_bytes {
+                            Some(max) => format!("of {}", format_bytes(max)),
+                            None => "no limit".to_string(),
+                        };
+                        self.inventory = format!(
+                            "{} objects, {} ({}, evicted {})",
+                            stats.objects,
+                            format_bytes(stats.bytes),
+                            limit,
+                            stats.evicted
+                        );
+                    }
+                    Err(e) => log::warn!("Failed to get inventory stats: {}", e),
+                }
+            }
+            NetworkStatusInput::ReloadInventory => {
+                self.inventory_list.guard().clear();
+                self.load_inventory(None, root).await;
+            }
+            NetworkStatusInput::LoadMoreInventory => {
+                let cursor = self.inventory_cursor.take();
+                if cursor.is_some() {
+                    self.load_inventory(cursor, root).await;
+                }
+            }
+            NetworkStatusInput::BanPeer(target) => {
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                if let Err(e) = client.ban_peer(target, None).await {
+                    show_error(root, "Failed to ban peer", &e.to_string());
+                }
+                sender.input(NetworkStatusInput::Refresh);
+            }
+            NetworkStatusInput::UnbanPeer(target) => {
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                if let Err(e) = client.unban_peer(target).await {
+                    show_error(root, "Failed to unban peer", &e.to_string());
+                }
+                sender.input(NetworkStatusInput::Refresh);
+            }
+        }
+    }
 }
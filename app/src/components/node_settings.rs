@@ -0,0 +1,304 @@
+use adw::prelude::*;
+use gtk;
+use relm4::component::{AsyncComponent, AsyncComponentParts};
+use relm4::RelmWidgetExt;
+
+use crate::state;
+use nantoka_core::config::{Config, PoWEngineKind};
+
+/// Settings of the node kept in `config.toml` in the data dir
+pub(crate) struct NodeSettingsModel {
+    listen_port: u16,
+    /// Comma-separated multiaddrs
+    bootstrap_peers: String,
+    pow_engine: PoWEngineKind,
+    /// KiB/s, 0 means unlimited
+    max_download_rate: u64,
+    /// KiB/s, 0 means unlimited
+    max_upload_rate: u64,
+    /// MiB, 0 means unlimited
+    max_inventory_size: u64,
+    /// Empty means the default location in the data dir
+    database_path: String,
+    /// Keys of the applied values which take effect only after restart
+    restart_required: Vec<&'static str>,
+}
+
+#[derive(Debug)]
+pub(crate) enum NodeSettingsInput {
+    ListenPortChanged(u16),
+    BootstrapPeersChanged(String),
+    PoWEngineSelected(u32),
+    MaxDownloadRateChanged(u64),
+    MaxUploadRateChanged(u64),
+    MaxInventorySizeChanged(u64),
+    DatabasePathChanged(String),
+    Apply,
+}
+
+fn pow_engines() -> gtk::StringList {
+    let labels: Vec<String> = PoWEngineKind::ALL.iter().map(|e| e.to_string()).collect();
+    gtk::StringList::new(&labels.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+fn show_error(root: &gtk::Box, title: &str, message: &str) {
+    let dialog = adw::MessageDialog::new(
+        root.root().and_downcast_ref::<gtk::Window>(),
+        Some(title),
+        Some(message),
+    );
+    dialog.add_response("ok", "OK");
+    dialog.present();
+}
+
+impl NodeSettingsModel {
+    fn new(config: &Config) -> Self {
+        Self {
+            listen_port: config.listen_port().unwrap_or_default(),
+            bootstrap_peers: config
+                .bootstrap_peers
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            pow_engine: config.pow_engine,
+            max_download_rate: config.max_download_rate.unwrap_or_default() / 1024,
+            max_upload_rate: config.max_upload_rate.unwrap_or_default() / 1024,
+            max_inventory_size: config.max_inventory_size.unwrap_or_default() / 1024 / 1024,
+            database_path: config
+                .database_path
+                .as_ref()
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            restart_required: Vec::new(),
+        }
+    }
+
+    /// Apply values of the page to the config
+    fn to_config(&self, config: &Config) -> Result<Config, String> {
+        let bootstrap_peers = self
+            .bootstrap_peers
+            .split(',')
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(|a| a.parse().map_err(|_| format!("Invalid address {}", a)))
+            .collect::<Result<_, _>>()?;
+        let mut config = Config {
+            bootstrap_peers,
+            pow_engine: self.pow_engine,
+            max_download_rate: Some(self.max_download_rate)
+                .filter(|r| *r > 0)
+                .map(|r| r * 1024),
+            max_upload_rate: Some(self.max_upload_rate)
+                .filter(|r| *r > 0)
+                .map(|r| r * 1024),
+            max_inventory_size: Some(self.max_inventory_size)
+                .filter(|s| *s > 0)
+                .map(|s| s * 1024 * 1024),
+            database_path: Some(self.database_path.trim())
+                .filter(|p| !p.is_empty())
+                .map(Into::into),
+            ..config.clone()
+        };
+        config.set_listen_port(self.listen_port);
+        Ok(config)
+    }
+}
+
+#[relm4::component(pub async)]
+impl AsyncComponent for NodeSettingsModel {
+    type CommandOutput = ();
+    type Input = NodeSettingsInput;
+    type Output = ();
+    type Init = ();
+
+    view! {
+        #[root]
+        gtk::Box {
+            set_orientation: gtk::Orientation::Vertical,
+            set_spacing: 12,
+
+            adw::Banner {
+                #[watch]
+                set_title: &format!(
+                    "Restart the app to apply: {}",
+                    model.restart_required.join(", ")
+                ),
+                #[watch]
+                set_revealed: !model.restart_required.is_empty(),
+            },
+
+            adw::PreferencesGroup {
+                set_title: "Node",
+                set_description: Some("Kept in the node config in the data dir"),
+
+                adw::ActionRow {
+                    set_title: "Listen port",
+                    set_subtitle: "Applied after restart",
+
+                    add_suffix = &gtk::SpinButton {
+                        set_valign: gtk::Align::Center,
+                        set_adjustment: &gtk::Adjustment::new(
+                            model.listen_port as f64, 1.0, 65535.0, 1.0, 10.0, 0.0,
+                        ),
+                        connect_value_changed[sender] => move |b| {
+                            sender.input(NodeSettingsInput::ListenPortChanged(b.value() as u16))
+                        }
+                    }
+                },
+                adw::EntryRow {
+                    set_title: "Bootstrap peers (comma-separated, with peer ids)",
+                    set_text: &model.bootstrap_peers,
+                    connect_changed[sender] => move |e| {
+                        sender.input(NodeSettingsInput::BootstrapPeersChanged(e.text().to_string()))
+                    }
+                },
+                adw::ComboRow {
+                    set_title: "Proof of work engine",
+                    set_subtitle: "Applied after restart",
+                    set_model: Some(&pow_engines()),
+                    set_selected: PoWEngineKind::ALL
+                        .iter()
+                        .position(|e| *e == model.pow_engine)
+                        .unwrap_or_default() as u32,
+                    connect_selected_notify[sender] => move |row| {
+                        sender.input(NodeSettingsInput::PoWEngineSelected(row.selected()))
+                    }
+                },
+                adw::EntryRow {
+                    set_title: "Database location (empty for the data dir)",
+                    set_text: &model.database_path,
+                    set_tooltip_text: Some("Applied after restart, the existing database isn't moved"),
+                    connect_changed[sender] => move |e| {
+                        sender.input(NodeSettingsInput::DatabasePathChanged(e.text().to_string()))
+                    }
+                }
+            },
+
+            adw::PreferencesGroup {
+                set_title: "Network",
+                set_description: Some("Limits are in KiB/s, 0 means unlimited"),
+
+                adw::ActionRow {
+                    set_title: "Download limit",
+
+                    add_suffix = &gtk::SpinButton {
+                        set_valign: gtk::Align::Center,
+                        set_adjustment: &gtk::Adjustment::new(
+                            model.max_download_rate as f64, 0.0, 1_000_000.0, 10.0, 100.0, 0.0,
+                        ),
+                        connect_value_changed[sender] => move |b| {
+                            sender.input(NodeSettingsInput::MaxDownloadRateChanged(b.value() as u64))
+                        }
+                    }
+                },
+                adw::ActionRow {
+                    set_title: "Relay upload limit",
+                    set_subtitle: "Traffic spent on sending objects to other peers",
+
+                    add_suffix = &gtk::SpinButton {
+                        set_valign: gtk::Align::Center,
+                        set_adjustment: &gtk::Adjustment::new(
+                            model.max_upload_rate as f64, 0.0, 1_000_000.0, 10.0, 100.0, 0.0,
+                        ),
+                        connect_value_changed[sender] => move |b| {
+                            sender.input(NodeSettingsInput::MaxUploadRateChanged(b.value() as u64))
+                        }
+                    }
+                },
+                adw::ActionRow {
+                    set_title: "Inventory size limit",
+                    set_subtitle: "MiB, objects beyond it are evicted. 0 means unlimited",
+
+                    add_suffix = &gtk::SpinButton {
+                        set_valign: gtk::Align::Center,
+                        set_adjustment: &gtk::Adjustment::new(
+                            model.max_inventory_size as f64, 0.0, 1_000_000.0, 100.0, 1000.0, 0.0,
+                        ),
+                        connect_value_changed[sender] => move |b| {
+                            sender.input(NodeSettingsInput::MaxInventorySizeChanged(b.value() as u64))
+                        }
+                    }
+                }
+            },
+
+            gtk::Button {
+                set_label: "Apply",
+                set_halign: gtk::Align::End,
+                add_css_class: "suggested-action",
+                connect_clicked => NodeSettingsInput::Apply,
+            }
+        }
+    }
+
+    async fn init(
+        _init: Self::Init,
+        root: Self::Root,
+        sender: relm4::AsyncComponentSender<Self>,
+    ) -> AsyncComponentParts<Self> {
+        let mut client = state::STATE.read_inner().client.clone().unwrap();
+        let config = match client.get_config().await {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("Failed to get node config: {}", e);
+                state::STATE.read_inner().config.clone()
+            }
+        };
+        let model = NodeSettingsModel::new(&config);
+        let widgets = view_output!();
+        AsyncComponentParts { model, widgets }
+    }
+
+    async fn update(
+        &mut self,
+        message: Self::Input,
+        _sender: relm4::AsyncComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match message {
+            NodeSettingsInput::ListenPortChanged(p) => self.listen_port = p,
+            NodeSettingsInput::BootstrapPeersChanged(p) => self.bootstrap_peers = p,
+            NodeSettingsInput::PoWEngineSelected(i) => {
+                if let Some(e) = PoWEngineKind::ALL.get(i as usize) {
+                    self.pow_engine = *e;
+                }
+            }
+            NodeSettingsInput::MaxDownloadRateChanged(r) => self.max_download_rate = r,
+            NodeSettingsInput::MaxUploadRateChanged(r) => self.max_upload_rate = r,
+            NodeSettingsInput::MaxInventorySizeChanged(s) => self.max_inventory_size = s,
+            NodeSettingsInput::DatabasePathChanged(p) => self.database_path = p,
+            NodeSettingsInput::Apply => {
+                let mut client = state::STATE.read_inner().client.clone().unwrap();
+                let current = match client.get_config().await {
+                    Ok(c) => c,
+                    Err(e) => {
+                        show_error(root, "Failed to apply settings", &e.to_string());
+                        return;
+                    }
+                };
+                let config = match self.to_config(&current) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        show_error(root, "Invalid bootstrap peers", &e);
+                        return;
+                    }
+                };
+                match client.update_config(config.clone()).await {
+                    Ok(keys) => {
+                        for key in keys {
+                            if !self.restart_required.contains(&key) {
+                                self.restart_required.push(key);
+                            }
+                        }
+                        // the password is only passed to the node on start
+                        state::STATE.write_inner().config = Config {
+                            database_password: None,
+                            ..config
+                        };
+                    }
+                    Err(e) => show_error(root, "Failed to apply settings", &e.to_string()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use adw::prelude::*;
+use chrono::Utc;
+use gtk;
+use nantoka_core::repositories::models::FilterRule;
+use relm4::factory::FactoryVecDeque;
+use relm4::prelude::DynamicIndex;
+use relm4::RelmWidgetExt;
+use relm4::{
+    component::{AsyncComponent, AsyncComponentParts},
+    loading_widgets::LoadingWidgets,
+    view,
+};
+
+use crate::state;
+
+use super::factories::filter_rule_row::FilterRuleRow;
+
+/// Folders received messages can be moved to, as they're listed in the rule dialog
+const MOVE_TO_FOLDERS: [Option<&str>; 3] = [None, Some("Trash"), Some("Quarantine")];
+
+pub(crate) struct RulesListModel {
+    is_list_empty: bool,
+    list_view: FactoryVecDeque<FilterRuleRow>,
+}
+
+#[derive(Debug)]
+pub enum RulesListInput {
+    OpenAddDialog,
+    HandleEditRule(DynamicIndex),
+    DeleteRule(DynamicIndex),
+    SetEnabled(DynamicIndex, bool),
+    /// Add the rule if it has no id yet, or update the existing one
+    SaveRule(FilterRule),
+}
+
+impl RulesListModel {
+    async fn reload_list(&mut self) {
+        let rules = state::STATE
+            .write_inner()
+            .client
+            .as_mut()
+            .unwrap()
+            .get_filter_rules()
+            .await;
+        let rules = match rules {
+            Ok(r) => r,
+            Err(e) => {
+                log::error!("Failed to load filtering rules: {}", e);
+                return;
+            }
+        };
+        self.is_list_empty = rules.is_empty();
+        let mut guard = self.list_view.guard();
+        guard.clear();
+        for rule in rules {
+            guard.push_back(rule);
+        }
+    }
+
+    /// Show dialog editing conditions and actions of the rule, a new one if it's `None`
+    fn show_rule_dialog(
+        root: &gtk::ScrolledWindow,
+        sender: relm4::AsyncComponentSender<Self>,
+        rule: Option<FilterRule>,
+    ) {
+        let rule = rule.unwrap_or_else(|| FilterRule {
+            id: String::new(),
+            label: String::new(),
+            enabled: true,
+            sender: None,
+            subject_contains: None,
+            identity: None,
+            min_size: None,
+            max_size: None,
+            move_to: None,
+            mark_read: false,
+            delete_message: false,
+            notify: false,
+            created_at: Utc::now(),
+        });
+
+        let entry = |placeholder: &str, text: &Option<String>| {
+            gtk::Entry::builder()
+                .placeholder_text(placeholder)
+                .text(text.as_deref().unwrap_or_default())
+                .build()
+        };
+        let label = entry("Label", &Some(rule.label.clone()));
+        let from = entry("From (BM-...)", &rule.sender);
+        let identity = entry("To identity or subscription (BM-...)", &rule.identity);
+        let subject = entry("Subject contains", &rule.subject_contains);
+        // sizes are set in KiB, 0 leaves the condition unset
+        let size = |tooltip: &str, bytes: Option<i64>| {
+            let button = gtk::SpinButton::with_range(0.0, 1024.0 * 1024.0, 1.0);
+            button.set_value(bytes.map_or(0.0, |b| (b / 1024) as f64));
+            button.set_tooltip_text(Some(tooltip));
+            button
+        };
+        let min_size = size("Larger than, KiB", rule.min_size);
+        let max_size = size("Smaller than, KiB", rule.max_size);
+        let sizes = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        sizes.append(&gtk::Label::new(Some("Size, KiB")));
+        sizes.append(&min_size);
+        sizes.append(&gtk::Label::new(Some("–")));
+        sizes.append(&max_size);
+
+        let move_to =
+            gtk::DropDown::from_strings(&["Don't move", "Move to Trash", "Move to Quarantine"]);
+        let folder = MOVE_TO_FOLDERS
+            .iter()
+            .position(|f| *f == rule.move_to.as_deref())
+            .unwrap_or_default();
+        move_to.set_selected(folder as u32);
+        let mark_read = gtk::CheckButton::with_label("Mark as read");
+        mark_read.set_active(rule.mark_read);
+        let delete = gtk::CheckButton::with_label("Delete for good");
+        delete.set_active(rule.delete_message);
+        let notify = gtk::CheckButton::with_label("Notify, even if the identity is muted");
+        notify.set_active(rule.notify);
+
+        let fields = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        fields.append(&label);
+        fields.append(&from);
+        fields.append(&identity);
+        fields.append(&subject);
+        fields.append(&sizes);
+        fields.append(&move_to);
+        fields.append(&mark_read);
+        fields.append(&delete);
+        fields.append(&notify);
+
+        let dialog = adw::MessageDialog::new(
+            root.root().and_downcast_ref::<gtk::Window>(),
+            Some(if rule.id.is_empty() { "New rule" } else { "Edit rule" }),
+            Some("Actions are performed on received messages matching all of the conditions which are filled"),
+        );
+        dialog.set_extra_child(Some(&fields));
+        dialog.add_responses(&[("cancel", "Cancel"), ("save", "Save")]);
+        dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("save"));
+        dialog.set_close_response("cancel");
+        dialog.connect_response(None, move |_, response| {
+            if response != "save" {
+                return;
+            }
+            let text = |entry: &gtk::Entry| {
+                Some(entry.text().trim().to_string()).filter(|t| !t.is_empty())
+            };
+            let bytes =
+                |button: &gtk::SpinButton| Some(button.value() as i64 * 1024).filter(|b| *b > 0);
+            sender.input(RulesListInput::SaveRule(FilterRule {
+                label: label.text().to_string(),
+                sender: text(&from),
+                subject_contains: text(&subject),
+                identity: text(&identity),
+                min_size: bytes(&min_size),
+                max_size: bytes(&max_size),
+                move_to: MOVE_TO_FOLDERS[move_to.selected() as usize].map(str::to_string),
+                mark_read: mark_read.is_active(),
+                delete_message: delete.is_active(),
+                notify: notify.is_active(),
+                ..rule.clone()
+            }));
+        });
+        dialog.present();
+    }
+}
+
+fn show_message(root: &gtk::ScrolledWindow, heading: &str, body: &str) {
+    let dialog = adw::MessageDialog::new(
+        root.root().and_downcast_ref::<gtk::Window>(),
+        Some(heading),
+        Some(body),
+    );
+    dialog.add_response("ok", "OK");
+    dialog.present();
+}
+
+#[relm4::component(pub async)]
+impl AsyncComponent for RulesListModel {
+    type CommandOutput = ();
+    type Input = RulesListInput;
+    type Output = ();
+    type Init = ();
+
+    view! {
+        #[root]
+        gtk::ScrolledWindow {
+            gtk::CenterBox {
+                #[wrap(Some)]
+                set_center_widget = &gtk::Box{
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+
+                        #[watch]
+                        set_visible: model.is_list_empty,
+                        set_spacing: 3,
+                        set_valign: gtk::Align::Center,
+
+                        gtk::Label {
+                            set_label: "No filtering rules yet",
+                            add_css_class: "large-title"
+                        },
+                        gtk::Label {
+                            set_label: "Rules move, mark or delete messages once they're received",
+                            add_css_class: "dim-label"
+                        },
+                        gtk::Button {
+                            set_label: "Add new one",
+                            set_hexpand: false,
+                            connect_clicked => RulesListInput::OpenAddDialog
+                        }
+                    },
+
+                    #[local]
+                    list_view -> gtk::ListBox {
+                        set_valign: gtk::Align::Start,
+                        set_margin_top: 12,
+                        set_margin_bottom: 12,
+                        add_css_class: "boxed-list",
+                    }
+                }
+            }
+        }
+    }
+
+    fn init_loading_widgets(root: &mut Self::Root) -> Option<LoadingWidgets> {
+        view! {
+                #[local_ref]
+                root {
+                    #[name(loading)]
+                    gtk::CenterBox {
+                        set_margin_all: 100,
+                        set_orientation: gtk::Orientation::Vertical,
+                        #[wrap(Some)]
+                        set_center_widget = &gtk::Spinner {
+                            start: (),
+                            set_size_request: (40, 40),
+                            set_halign: gtk::Align::Center,
+                            set_valign: gtk::Align::Center,
+                        },
+                    }
+                }
+        }
+        Some(LoadingWidgets::new(root, loading))
+    }
+
+    async fn init(
+        _init: Self::Init,
+        root: Self::Root,
+        sender: relm4::AsyncComponentSender<Self>,
+    ) -> AsyncComponentParts<Self> {
+        let list_view = gtk::ListBox::default();
+        let list_view_factory = FactoryVecDeque::new(list_view.clone(), sender.input_sender());
+
+        let mut model = Self {
+            is_list_empty: true,
+            list_view: list_view_factory,
+        };
+
+        model.reload_list().await;
+
+        let widgets = view_output!();
+        AsyncComponentParts { model, widgets }
+    }
+
+    async fn update(
+        &mut self,
+        message: Self::Input,
+        sender: relm4::AsyncComponentSender<Self>,
+        root: &Self::Root,
+    ) {
+        match message {
+            RulesListInput::OpenAddDialog => Self::show_rule_dialog(root, sender, None),
+            RulesListInput::HandleEditRule(i) => {
+                let rule = self
+                    .list_view
+                    .guard()
+                    .get(i.current_index())
+                    .expect("rule to be existing")
+                    .rule
+                    .clone();
+                Self::show_rule_dialog(root, sender, Some(rule));
+            }
+            RulesListInput::DeleteRule(i) => {
+                let item = self
+                    .list_view
+                    .guard()
+                    .remove(i.current_index())
+                    .expect("rule to be existing");
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .remove_filter_rule(item.rule.id)
+                    .await;
+                if let Err(e) = result {
+                    log::error!("Failed to delete filtering rule: {}", e);
+                    self.reload_list().await;
+                }
+                self.is_list_empty = self.list_view.is_empty();
+            }
+            RulesListInput::SetEnabled(i, enabled) => {
+                let rule = {
+                    let mut guard = self.list_view.guard();
+                    let item = guard
+                        .get_mut(i.current_index())
+                        .expect("rule to be existing");
+                    item.rule.enabled = enabled;
+                    item.rule.clone()
+                };
+                let result = state::STATE
+                    .write_inner()
+                    .client
+                    .as_mut()
+                    .unwrap()
+                    .update_filter_rule(rule)
+                    .await;
+                if let Err(e) = result {
+                    log::error!("Failed to update filtering rule: {}", e);
+                    self.reload_list().await;
+                }
+            }
+            RulesListInput::SaveRule(rule) => {
+                let mut state = state::STATE.write_inner();
+                let client = state.client.as_mut().unwrap();
+                let result = if rule.id.is_empty() {
+                    client.add_filter_rule(rule).await.map(|_| ())
+                } else {
+                    client.update_filter_rule(rule).await
+                };
+                drop(state);
+                match result {
+                    Ok(_) => self.reload_list().await,
+                    Err(e) => show_message(root, "Failed to save rule", &e.to_string()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use adw::{self, prelude::*};
+use gtk;
+use relm4::{
+    component::{AsyncComponent, AsyncComponentController, AsyncController},
+    ComponentParts, ComponentSender, RelmWidgetExt, SimpleComponent,
+};
+
+use super::node_settings::NodeSettingsModel;
+use crate::{network, settings::Theme, state};
+
+pub(crate) struct SettingsModel {
+    theme: Theme,
+    pow_difficulty_multiplier: f64,
+    low_power_on_battery: bool,
+    encrypt_database: bool,
+    /// Database is already encrypted, so encryption can't be turned off
+    database_encrypted: bool,
+    node_settings: AsyncController<NodeSettingsModel>,
+}
+
+#[derive(Debug)]
+pub(crate) enum SettingsInput {
+    ThemeSelected(u32),
+    PoWDifficultyMultiplierChanged(f64),
+    LowPowerOnBatteryChanged(bool),
+    EncryptDatabaseChanged(bool),
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for SettingsModel {
+    type Input = SettingsInput;
+    type Output = ();
+    type Init = ();
+
+    view! {
+        #[root]
+        gtk::ScrolledWindow {
+            adw::Clamp {
+                set_margin_all: 12,
+
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 12,
+
+                    adw::PreferencesGroup {
+                        set_title: "Appearance",
+
+                        adw::ComboRow {
+                            set_title: "Theme",
+                            set_model: Some(&gtk::StringList::new(
+                                &Theme::ALL.map(|t| t.label())
+                            )),
+                            set_selected: Theme::ALL
+                                .iter()
+                                .position(|t| *t == model.theme)
+                                .unwrap_or_default() as u32,
+                            connect_selected_notify[sender] => move |row| {
+                                sender.input(SettingsInput::ThemeSelected(row.selected()))
+                            }
+                        }
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: "Proof of work",
+
+                        adw::ActionRow {
+                            set_title: "Difficulty multiplier",
+                            set_subtitle: "Do more work for outgoing messages than the network minimum. Applied after restart",
+
+                            add_suffix = &gtk::SpinButton {
+                                set_valign: gtk::Align::Center,
+                                set_digits: 1,
+                                set_adjustment: &gtk::Adjustment::new(
+                                    model.pow_difficulty_multiplier, 1.0, 100.0, 0.5, 1.0, 0.0,
+                                ),
+                                connect_value_changed[sender] => move |b| {
+                                    sender.input(SettingsInput::PoWDifficultyMultiplierChanged(b.value()))
+                                }
+                            }
+                        },
+
+                        adw::ActionRow {
+                            set_title: "Low-power mode on battery",
+                            set_subtitle: "Pause proof of work and keep fewer connections while the computer runs on battery",
+
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                set_active: model.low_power_on_battery,
+                                connect_active_notify[sender] => move |s| {
+                                    sender.input(SettingsInput::LowPowerOnBatteryChanged(s.is_active()))
+                                }
+                            }
+                        }
+                    },
+
+                    adw::PreferencesGroup {
+                        set_title: "Security",
+
+                        adw::ActionRow {
+                            set_title: "Encrypt database",
+                            set_subtitle: if model.database_encrypted {
+                                "The database is encrypted, the password is asked on start"
+                            } else {
+                                "Messages and keys are encrypted with a password, which is asked on start. Applied after restart"
+                            },
+
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                set_active: model.encrypt_database || model.database_encrypted,
+                                set_sensitive: !model.database_encrypted,
+                                connect_active_notify[sender] => move |s| {
+                                    sender.input(SettingsInput::EncryptDatabaseChanged(s.is_active()))
+                                }
+                            }
+                        }
+                    },
+
+                    #[local_ref]
+                    node_settings_widget -> gtk::Box {}
+                }
+            }
+        }
+    }
+
+    fn init(
+        _init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = SettingsModel {
+            theme: state::STATE.read_inner().settings.theme,
+            pow_difficulty_multiplier: state::STATE.read_inner().settings.pow_difficulty_multiplier,
+            low_power_on_battery: state::STATE.read_inner().settings.low_power_on_battery,
+            encrypt_database: state::STATE.read_inner().settings.encrypt_database,
+            database_encrypted: {
+                let state = state::STATE.read_inner();
+                network::is_database_encrypted(&state.config.database_path(&state.data_dir))
+            },
+            node_settings: NodeSettingsModel::builder().launch(()).detach(),
+        };
+        let node_settings_widget = model.node_settings.widget();
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, _sender: ComponentSender<Self>) {
+        match message {
+            SettingsInput::ThemeSelected(i) => {
+                let theme = match Theme::ALL.get(i as usize) {
+                    Some(t) => *t,
+                    None => return,
+                };
+                self.theme = theme;
+                theme.apply();
+
+                let mut state = state::STATE.write_inner();
+                state.settings.theme = theme;
+                state.settings.save();
+            }
+            SettingsInput::PoWDifficultyMultiplierChanged(m) => {
+                self.pow_difficulty_multiplier = m;
+
+                let mut state = state::STATE.write_inner();
+                state.settings.pow_difficulty_multiplier = m;
+                state.settings.save();
+            }
+            SettingsInput::LowPowerOnBatteryChanged(v) => {
+                self.low_power_on_battery = v;
+
+                let mut state = state::STATE.write_inner();
+                state.settings.low_power_on_battery = v;
+                state.settings.save();
+            }
+            SettingsInput::EncryptDatabaseChanged(v) => {
+                self.encrypt_database = v;
+
+                let mut state = state::STATE.write_inner();
+                state.settings.encrypt_database = v;
+                state.settings.save();
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use gtk::{gdk, glib};
+
+use crate::{network::node::worker::Avatar, state};
+
+/// Load avatar of the address from the node as a texture. Identicon is shown if no
+/// image is set, it can't be loaded or it's broken.
+pub async fn load_avatar(address: String) -> gdk::Texture {
+    let client = state::STATE.read_inner().client.clone();
+    let avatar = match client {
+        Some(mut client) => client.get_avatar(address.clone()).await.ok(),
+        None => None,
+    };
+    if let Some(Avatar::Image(data)) = &avatar {
+        if let Ok(texture) = gdk::Texture::from_bytes(&glib::Bytes::from(data.as_slice())) {
+            return texture;
+        }
+    }
+    let seed = match avatar {
+        Some(Avatar::Identicon(seed)) => seed,
+        _ => address,
+    };
+    let png_data = identicon_rs::new(seed).export_png_data().unwrap();
+    gdk::Texture::from_bytes(&glib::Bytes::from(png_data.as_slice())).unwrap()
+}
@@ -0,0 +1,95 @@
+//! Message bodies in the message pane, formatted parts are shown with the styles
+//! they're rendered to.
+
+use bitmessage_rs::formatting::{render_html, render_markdown, Style};
+use gtk::{
+    pango,
+    prelude::{TextBufferExt, TextBufferExtManual, TextTagTableExt},
+};
+use mail_parser::{MimeHeaders, PartType};
+
+/// Formatting of the body part which can be shown with styles
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum BodyFormat {
+    Markdown,
+    Html,
+}
+
+/// Body parts of the message chosen for the message pane
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+pub struct MessageBody {
+    /// Plain text of the message, converted from HTML if it has no text part
+    pub text: String,
+    /// HTML part, or the text part if it's markdown
+    pub formatted: Option<(BodyFormat, String)>,
+}
+
+impl MessageBody {
+    pub fn parse(mime: &mail_parser::Message) -> Self {
+        let text = mime.body_text(0).unwrap_or_default().into_owned();
+        // messages without HTML part list their text part as HTML one
+        let formatted = match mime.html_part(0).map(|p| &p.body) {
+            Some(PartType::Html(html)) => Some((BodyFormat::Html, html.to_string())),
+            _ => mime
+                .text_part(0)
+                .and_then(|p| p.content_type())
+                .filter(|c| {
+                    c.subtype()
+                        .is_some_and(|s| s.eq_ignore_ascii_case("markdown"))
+                })
+                .map(|_| (BodyFormat::Markdown, text.clone())),
+        };
+        Self { text, formatted }
+    }
+
+    /// Show the body in the buffer, with styles if `formatted` is set and the message
+    /// has a formatted part
+    pub fn show(&self, buffer: &gtk::TextBuffer, formatted: bool) {
+        let spans = match &self.formatted {
+            Some((BodyFormat::Html, html)) if formatted => render_html(html),
+            Some((BodyFormat::Markdown, markdown)) if formatted => render_markdown(markdown),
+            _ => {
+                buffer.set_text(&self.text);
+                return;
+            }
+        };
+        create_tags(buffer);
+        buffer.set_text("");
+        let mut end = buffer.end_iter();
+        for span in spans {
+            let tags: Vec<&str> = span.styles.iter().map(|s| s.tag_name()).collect();
+            buffer.insert_with_tags_by_name(&mut end, &span.text, &tags);
+        }
+    }
+}
+
+/// Create tags of the styles in the buffer, unless they're created already
+fn create_tags(buffer: &gtk::TextBuffer) {
+    if buffer.tag_table().lookup(Style::Bold.tag_name()).is_some() {
+        return;
+    }
+    buffer.create_tag(Some(Style::Bold.tag_name()), &[("weight", &700)]);
+    buffer.create_tag(
+        Some(Style::Italic.tag_name()),
+        &[("style", &pango::Style::Italic)],
+    );
+    buffer.create_tag(
+        Some(Style::Monospace.tag_name()),
+        &[("family", &"monospace")],
+    );
+    buffer.create_tag(
+        Some(Style::Heading.tag_name()),
+        &[("weight", &700), ("scale", &1.3)],
+    );
+    buffer.create_tag(
+        Some(Style::Link.tag_name()),
+        &[
+            ("underline", &pango::Underline::Single),
+            ("foreground", &"#3584e4"),
+        ],
+    );
+    buffer.create_tag(
+        Some(Style::Quote.tag_name()),
+        &[("left-margin", &24), ("foreground", &"#77767b")],
+    );
+}
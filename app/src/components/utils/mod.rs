@@ -1 +1,3 @@
+pub mod avatar;
+pub mod message_body;
 pub mod typed_list_view;
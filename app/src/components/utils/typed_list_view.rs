@@ -453,6 +453,12 @@ where
         self.store.insert_sorted(&item, compare)
     }
 
+    /// Rebind an item at a specific position after it was changed in place.
+    /// Unlike replacing the item, this keeps it selected.
+    pub fn refresh(&self, position: u32) {
+        self.store.items_changed(position, 1, 1);
+    }
+
     /// Remove an item at a specific position.
     pub fn remove(&mut self, position: u32) {
         self.store.remove(position);
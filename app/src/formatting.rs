@@ -0,0 +1,377 @@
+//! Rendering of formatted message bodies into styled text. HTML and markdown are
+//! reduced to a few text styles, anything else (scripts, images, remote content)
+//! is dropped, so showing them can't leak anything.
+
+use std::borrow::Cow;
+
+/// Tags whose content isn't shown
+const HIDDEN_TAGS: &[&str] = &["head", "script", "style", "template", "title"];
+/// Tags shown as separate paragraphs
+const BLOCK_TAGS: &[&str] = &[
+    "blockquote",
+    "div",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ol",
+    "p",
+    "pre",
+    "table",
+    "ul",
+];
+/// Longest entity name, e.g. `&#x1F600;`, longer ones are shown as they are
+const MAX_ENTITY_LENGTH: usize = 10;
+
+/// Style of the text, shown with the text tag of the same name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Bold,
+    Italic,
+    Monospace,
+    Heading,
+    Link,
+    Quote,
+}
+
+impl Style {
+    pub fn tag_name(&self) -> &'static str {
+        match self {
+            Style::Bold => "bold",
+            Style::Italic => "italic",
+            Style::Monospace => "monospace",
+            Style::Heading => "heading",
+            Style::Link => "link",
+            Style::Quote => "quote",
+        }
+    }
+}
+
+/// Text with the same styles
+#[derive(Debug, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub styles: Vec<Style>,
+}
+
+#[derive(Default)]
+struct Spans {
+    spans: Vec<Span>,
+    /// Text of all the spans, e.g. to collapse whitespace across them
+    plain: String,
+}
+
+impl Spans {
+    fn push(&mut self, text: &str, styles: &[Style]) {
+        if text.is_empty() {
+            return;
+        }
+        self.plain.push_str(text);
+        match self.spans.last_mut() {
+            Some(last) if last.styles == styles => last.text.push_str(text),
+            _ => self.spans.push(Span {
+                text: text.to_string(),
+                styles: styles.to_vec(),
+            }),
+        }
+    }
+
+    /// Push HTML text, whitespace is collapsed like browsers do
+    fn push_collapsed(&mut self, text: &str, styles: &[Style]) {
+        let mut collapsed = String::with_capacity(text.len());
+        let mut space = self.plain.is_empty() || self.plain.ends_with(char::is_whitespace);
+        for c in text.chars() {
+            if !c.is_whitespace() {
+                collapsed.push(c);
+                space = false;
+            } else if !space {
+                collapsed.push(' ');
+                space = true;
+            }
+        }
+        self.push(&collapsed, styles);
+    }
+
+    /// Start a new line, after a blank one if `blank` is set
+    fn break_line(&mut self, blank: bool) {
+        if self.plain.is_empty() {
+            return;
+        }
+        let newlines = self.plain.chars().rev().take_while(|c| *c == '\n').count();
+        for _ in newlines..if blank { 2 } else { 1 } {
+            self.push("\n", &[]);
+        }
+    }
+}
+
+/// Toggle the style, e.g. on a markdown emphasis marker
+fn toggle(styles: &mut Vec<Style>, style: Style) {
+    match styles.iter().rposition(|s| *s == style) {
+        Some(i) => _ = styles.remove(i),
+        None => styles.push(style),
+    }
+}
+
+/// Reduce HTML to styled text
+pub fn render_html(html: &str) -> Vec<Span> {
+    let mut out = Spans::default();
+    let mut styles: Vec<Style> = Vec::new();
+    // targets of the open links, along with where their text starts
+    let mut links: Vec<(String, usize)> = Vec::new();
+    let mut hidden: Option<String> = None;
+    let mut preformatted = 0;
+    let mut rest = html;
+    loop {
+        let (text, tag_start) = match find_tag(rest) {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        if hidden.is_none() {
+            let text = decode_entities(text);
+            if preformatted > 0 {
+                out.push(&text, &styles);
+            } else {
+                out.push_collapsed(&text, &styles);
+            }
+        }
+        let Some(tag_start) = tag_start else {
+            break;
+        };
+        if let Some(comment) = tag_start.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |i| &comment[i + 3..]);
+            continue;
+        }
+        let end = tag_start.find('>').unwrap_or(tag_start.len());
+        let tag = &tag_start[..end];
+        rest = tag_start.get(end + 1..).unwrap_or("");
+
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if hidden.is_some() {
+            if closing && hidden.as_deref() == Some(name.as_str()) {
+                hidden = None;
+            }
+            continue;
+        }
+        if HIDDEN_TAGS.contains(&name.as_str()) {
+            if !closing && !self_closing {
+                hidden = Some(name);
+            }
+            continue;
+        }
+
+        match name.as_str() {
+            "br" => out.push("\n", &styles),
+            "li" if !closing => {
+                out.break_line(false);
+                out.push("• ", &styles);
+            }
+            "tr" => out.break_line(false),
+            "td" | "th" if !closing => out.push_collapsed(" ", &styles),
+            name if BLOCK_TAGS.contains(&name) => out.break_line(true),
+            _ => {}
+        }
+        if name == "pre" {
+            preformatted = if closing {
+                preformatted - 1
+            } else {
+                preformatted + 1
+            }
+            .max(0);
+        }
+        let style = match name.as_str() {
+            "b" | "strong" => Style::Bold,
+            "i" | "em" => Style::Italic,
+            "code" | "pre" | "tt" => Style::Monospace,
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Style::Heading,
+            "a" => Style::Link,
+            "blockquote" => Style::Quote,
+            _ => continue,
+        };
+        if !closing && !self_closing {
+            styles.push(style);
+            if style == Style::Link {
+                let href = attribute(tag, "href").unwrap_or_default();
+                links.push((decode_entities(href).into_owned(), out.plain.len()));
+            }
+        } else if closing {
+            if let Some(i) = styles.iter().rposition(|s| *s == style) {
+                styles.remove(i);
+            }
+            // the target is shown, so that the text can't disguise it
+            if let Some((href, start)) = (style == Style::Link).then(|| links.pop()).flatten() {
+                if !href.is_empty() && out.plain[start..].trim() != href {
+                    out.push(&format!(" ({})", href), &styles);
+                }
+            }
+        }
+    }
+    out.spans
+}
+
+/// Position of the first tag (or comment) in the text. Other `<` (e.g. in `1 < 2`)
+/// are text, like browsers show them.
+fn find_tag(text: &str) -> Option<usize> {
+    text.match_indices('<').map(|(i, _)| i).find(|i| {
+        text[i + 1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!')
+    })
+}
+
+/// Value of the attribute of the tag, e.g. `href` of a link
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.to_ascii_lowercase().find(&format!("{}=", name))? + name.len() + 1;
+    let value = &tag[start..];
+    match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next(),
+        _ => value.split(|c: char| c.is_whitespace()).next(),
+    }
+}
+
+fn decode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        decoded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let entity = rest
+            .find(';')
+            .filter(|end| *end <= MAX_ENTITY_LENGTH)
+            .map(|end| &rest[..end]);
+        match entity.and_then(decode_entity) {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[entity.unwrap().len() + 1..];
+            }
+            None => decoded.push('&'),
+        }
+    }
+    decoded.push_str(rest);
+    Cow::Owned(decoded)
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let code = match code.strip_prefix('x').or_else(|| code.strip_prefix('X')) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+/// Simple markdown: headings, quotes, lists, code blocks, emphasis, code spans and links
+pub fn render_markdown(markdown: &str) -> Vec<Span> {
+    let mut out = Spans::default();
+    let mut code_block = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            code_block = !code_block;
+            continue;
+        }
+        if code_block {
+            out.push(line, &[Style::Monospace]);
+            out.push("\n", &[]);
+            continue;
+        }
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        if let Some(heading) = Some(&trimmed[level..])
+            .filter(|_| (1..=6).contains(&level))
+            .and_then(|h| h.strip_prefix(' '))
+        {
+            push_inline(&mut out, heading, &[Style::Heading]);
+        } else if let Some(quote) = trimmed.strip_prefix('>') {
+            push_inline(&mut out, quote.trim_start(), &[Style::Quote]);
+        } else if let Some(item) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|marker| trimmed.strip_prefix(marker))
+        {
+            out.push("• ", &[]);
+            push_inline(&mut out, item, &[]);
+        } else {
+            push_inline(&mut out, line, &[]);
+        }
+        out.push("\n", &[]);
+    }
+    out.spans
+}
+
+/// Emphasis, code spans and links within the line. Markers without a closing one
+/// are shown as they are.
+fn push_inline(out: &mut Spans, line: &str, base: &[Style]) {
+    let mut styles = base.to_vec();
+    let mut text = String::new();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let marker = match c {
+            '`' => rest[1..].find('`').map(|end| (1, end + 2)),
+            '*' | '_' if rest[1..].starts_with(c) => {
+                let closed = styles.contains(&Style::Bold) || rest[2..].contains(&rest[..2]);
+                closed.then_some((2, 2))
+            }
+            '*' => {
+                let closed = styles.contains(&Style::Italic) || rest[1..].contains('*');
+                closed.then_some((1, 1))
+            }
+            '[' => parse_link(rest).map(|(_, len)| (0, len)),
+            _ => None,
+        };
+        let Some((marker_len, len)) = marker else {
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+        out.push(&text, &styles);
+        text.clear();
+        match c {
+            '`' => {
+                let mut code = styles.clone();
+                code.push(Style::Monospace);
+                out.push(&rest[marker_len..len - 1], &code);
+            }
+            '[' => {
+                let (url, _) = parse_link(rest).unwrap();
+                let mut link = styles.clone();
+                link.push(Style::Link);
+                out.push(&rest[1..rest.find("](").unwrap()], &link);
+                out.push(&format!(" ({})", url), &styles);
+            }
+            _ if marker_len == 2 => toggle(&mut styles, Style::Bold),
+            _ => toggle(&mut styles, Style::Italic),
+        }
+        rest = &rest[len..];
+    }
+    out.push(&text, &styles);
+}
+
+/// Target of the link at the start of the text, e.g. `[text](url)`, along with
+/// the length of the link
+fn parse_link(text: &str) -> Option<(&str, usize)> {
+    let label_end = text.find("](")?;
+    let url_start = label_end + 2;
+    let url_len = text[url_start..].find(')')?;
+    Some((
+        &text[url_start..url_start + url_len],
+        url_start + url_len + 1,
+    ))
+}
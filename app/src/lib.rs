@@ -0,0 +1,4 @@
+//! Parts of the app which don't depend on GTK, so that they can be tested
+//! without a display
+
+pub mod formatting;
@@ -1,30 +1,126 @@
+use std::error::Error;
+
 use crate::app::AppModel;
 use async_std::task;
+use components::dialogs::{
+    database_error_dialog::DatabaseErrorDialogModel,
+    unlock_dialog::{UnlockDialogMode, UnlockDialogModel},
+};
 use directories::ProjectDirs;
-use nantoka_core::network;
+use nantoka_core::{
+    config::{Config, StorageKind},
+    network::{self, node::data_dir_lock::DataDirLock},
+};
 use relm4::RelmApp;
+use settings::AppSettings;
 
 pub mod app;
 mod components;
+mod notifications;
+mod power;
+pub mod settings;
 pub mod state;
 
+const APP_ID: &str = "io.github.chronosx88.BitmessageRs";
+
 fn main() {
     pretty_env_logger::init();
 
     let dirs = ProjectDirs::from("", "", "bitmessage-rs").unwrap();
     let data_dir = dirs.data_dir();
 
-    let (mut client, worker) = network::new(None, data_dir.to_path_buf());
+    let settings = AppSettings::load(dirs.config_dir().to_path_buf());
+    let config = Config::load(data_dir).unwrap_or_else(|e| {
+        log::error!("Failed to load node config, using defaults: {}", e);
+        Config::default()
+    });
+    // e.g. the CLI node might be running with the same data dir
+    let data_dir_lock = if config.storage == StorageKind::Memory {
+        None
+    } else {
+        match DataDirLock::acquire(data_dir) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                let app = RelmApp::new(APP_ID);
+                relm4_icons::initialize_icons();
+                app.run::<DatabaseErrorDialogModel>(e.to_string());
+                return;
+            }
+        }
+    };
+    let database_encrypted = network::is_database_encrypted(&config.database_path(data_dir));
+    let ask_password = database_encrypted || settings.encrypt_database;
+
+    state::STATE.write_inner().settings = settings;
+    state::STATE.write_inner().config = config;
+    state::STATE.write_inner().data_dir = data_dir.to_path_buf();
+    state::STATE.write_inner().data_dir_lock = data_dir_lock;
+    relm4::RELM_THREADS.set(4).unwrap();
+
+    if ask_password {
+        // the node is started once the database is unlocked
+        let app = RelmApp::new(APP_ID);
+        relm4_icons::initialize_icons();
+        app.run::<UnlockDialogModel>(if database_encrypted {
+            UnlockDialogMode::Unlock
+        } else {
+            UnlockDialogMode::SetPassword
+        });
+    } else {
+        let app = RelmApp::new(APP_ID);
+        relm4_icons::initialize_icons();
+        match start_node(None) {
+            Ok(()) => app.run::<AppModel>(()),
+            Err(e) => app.run::<DatabaseErrorDialogModel>(e.to_string()),
+        }
+    }
+
+    let client = state::STATE.read_inner().client.clone();
+    if let Some(mut client) = client {
+        if let Err(e) = task::block_on(client.shutdown()) {
+            log::error!("Failed to stop the node: {}", e);
+        }
+    }
+    // the node hasn't been started, e.g. the database wasn't unlocked
+    state::STATE.write_inner().data_dir_lock = None;
+}
+
+/// Start the node using config, settings and data dir from the global state.
+/// Values managed on the settings page override ones from the node config file.
+/// Fails if the database can't be opened by this version or it's corrupted.
+pub(crate) fn start_node(database_password: Option<String>) -> Result<(), Box<dyn Error>> {
+    let (config, data_dir) = {
+        let state = state::STATE.read_inner();
+        let settings = &state.settings;
+        let config = Config {
+            pow_difficulty_multiplier: settings.pow_difficulty_multiplier,
+            database_password,
+            ..state.config.clone()
+        };
+        (config, state.data_dir.clone())
+    };
+
+    task::block_on(network::check_database(
+        &config.database_path(&data_dir),
+        config.database_password.as_deref(),
+    ))?;
+
+    let listen_addresses = config.listen_addresses.clone();
+    let mut builder = network::NodeBuilder::new(config).data_dir(data_dir);
+    if let Some(lock) = state::STATE.write_inner().data_dir_lock.take() {
+        builder = builder.data_dir_lock(lock);
+    }
+    let (mut client, worker) = builder.build()?;
 
     task::spawn(worker.run());
 
-    task::block_on(client.start_listening("/ip4/0.0.0.0/tcp/34064".parse().unwrap()))
-        .expect("listening not to fail");
+    for addr in listen_addresses {
+        // e.g. IPv6 might be unavailable, the node can still dial out
+        if let Err(e) = task::block_on(client.start_listening(addr.clone())) {
+            log::error!("Failed to listen on {}: {}", addr, e);
+        }
+    }
 
     state::STATE.write_inner().client = Some(client);
-    relm4::RELM_THREADS.set(4).unwrap();
-
-    let app = RelmApp::new("io.github.chronosx88.BitmessageRs");
-    relm4_icons::initialize_icons();
-    app.run::<AppModel>(());
+    Ok(())
 }
@@ -0,0 +1,65 @@
+//! Desktop notifications about received messages and delivery acknowledgements
+
+use async_std::stream::StreamExt;
+use gtk::{gio, glib, prelude::ApplicationExt};
+
+use crate::{
+    network::node::{client::NodeClient, worker::MessageStatusEvent},
+    state,
+};
+
+/// Listen to message events of the node and raise notifications for them
+pub fn start() {
+    let mut client = state::STATE.read_inner().client.clone().unwrap();
+    glib::MainContext::default().spawn_local(async move {
+        let mut events = match client.subscribe_message_status().await {
+            Ok(e) => e,
+            Err(e) => {
+                log::error!("Failed to subscribe to message events: {}", e);
+                return;
+            }
+        };
+        while let Some(event) = events.next().await {
+            notify(&mut client, event).await;
+        }
+    });
+}
+
+async fn notify(client: &mut NodeClient, event: MessageStatusEvent) {
+    let msg = match event.message {
+        Some(m) => m,
+        None => return,
+    };
+    // messages of strangers put into Quarantine are checked by the user when they like,
+    // unless a filtering rule asks to notify about them
+    if msg.folder.is_some() && !event.notify {
+        return;
+    }
+    // notifications are muted by the identity the message is received or sent by
+    let (identity, peer, title) = match event.status.as_str() {
+        "Received" => (&msg.recipient, &msg.sender, "New message from"),
+        "Unverified" => (&msg.recipient, &msg.sender, "New unverified message from"),
+        "Delivered" => (&msg.sender, &msg.recipient, "Message delivered to"),
+        _ => return,
+    };
+    if !event.notify && state::STATE.read_inner().settings.is_muted(identity) {
+        return;
+    }
+
+    let peer_label = client
+        .get_contacts()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .find(|c| c.string_repr == *peer)
+        .map(|c| c.label)
+        .filter(|l| !l.is_empty())
+        .unwrap_or_else(|| peer.clone());
+    let subject = mail_parser::Message::parse(&msg.data)
+        .and_then(|m| m.subject().map(str::to_string))
+        .unwrap_or_default();
+
+    let notification = gio::Notification::new(&format!("{} {}", title, peer_label));
+    notification.set_body(Some(&subject));
+    relm4::main_application().send_notification(Some(&msg.hash), &notification);
+}
@@ -0,0 +1,73 @@
+//! Low-power mode of the node, toggled by the user or following the battery state
+//! reported by UPower
+
+use async_std::{channel, stream::StreamExt};
+use gtk::{gio, glib};
+
+use crate::{network::node::worker::PowerMode, state};
+
+const UPOWER_NAME: &str = "org.freedesktop.UPower";
+const UPOWER_PATH: &str = "/org/freedesktop/UPower";
+
+/// Switch the node to low-power mode or back in the background
+pub fn set_low_power(enabled: bool) {
+    let mut client = state::STATE.read_inner().client.clone().unwrap();
+    let mode = if enabled {
+        PowerMode::LowPower
+    } else {
+        PowerMode::Normal
+    };
+    glib::MainContext::default().spawn_local(async move {
+        if let Err(e) = client.set_power_mode(mode).await {
+            log::error!("Failed to switch power mode: {}", e);
+        }
+    });
+}
+
+/// Call `on_change` with whether the system runs on battery, once it's known and then
+/// on each change. Nothing is called if UPower isn't available.
+pub fn watch_battery<F: Fn(bool) + 'static>(on_change: F) {
+    glib::MainContext::default().spawn_local(async move {
+        let proxy = match gio::DBusProxy::for_bus_future(
+            gio::BusType::System,
+            gio::DBusProxyFlags::NONE,
+            None,
+            UPOWER_NAME,
+            UPOWER_PATH,
+            UPOWER_NAME,
+        )
+        .await
+        {
+            Ok(p) => p,
+            Err(e) => {
+                log::debug!("UPower isn't available: {}", e);
+                return;
+            }
+        };
+        let on_battery = |proxy: &gio::DBusProxy| {
+            proxy
+                .cached_property("OnBattery")
+                .and_then(|v| v.get::<bool>())
+        };
+        // the proxy is created even if the service isn't running, its properties are missing then
+        let Some(mut current) = on_battery(&proxy) else {
+            log::debug!("UPower doesn't report the battery state");
+            return;
+        };
+        on_change(current);
+
+        let (sender, mut changes) = channel::unbounded();
+        proxy.connect_g_properties_changed(move |proxy, _, _| {
+            if let Some(state) = on_battery(proxy) {
+                _ = sender.try_send(state);
+            }
+        });
+        // the proxy is kept alive while its changes are listened to
+        while let Some(state) = changes.next().await {
+            if state != current {
+                current = state;
+                on_change(state);
+            }
+        }
+    });
+}
@@ -0,0 +1,106 @@
+use std::{fs, path::PathBuf};
+
+use relm4::adw;
+use serde::{Deserialize, Serialize};
+
+const SETTINGS_FILE_NAME: &str = "settings.toml";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::System, Theme::Light, Theme::Dark];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Theme::System => "System",
+            Theme::Light => "Light",
+            Theme::Dark => "Dark",
+        }
+    }
+
+    /// Apply theme to the whole application
+    pub fn apply(&self) {
+        let color_scheme = match self {
+            Theme::System => adw::ColorScheme::Default,
+            Theme::Light => adw::ColorScheme::ForceLight,
+            Theme::Dark => adw::ColorScheme::ForceDark,
+        };
+        adw::StyleManager::default().set_color_scheme(color_scheme);
+    }
+}
+
+/// GUI-specific settings, persisted in the config directory of the app
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AppSettings {
+    pub theme: Theme,
+    /// PoW difficulty multiplier for outgoing messages, applied on node start
+    pub pow_difficulty_multiplier: f64,
+    /// Ask for a password on start and encrypt the database with it
+    pub encrypt_database: bool,
+    /// Identities whose received messages and delivery acks don't raise desktop notifications
+    pub muted_identities: Vec<String>,
+    /// Switch the node to low-power mode while the system runs on battery
+    pub low_power_on_battery: bool,
+
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            theme: Theme::default(),
+            pow_difficulty_multiplier: 1.0,
+            encrypt_database: false,
+            muted_identities: Vec::new(),
+            low_power_on_battery: true,
+            path: PathBuf::default(),
+        }
+    }
+}
+
+impl AppSettings {
+    pub fn load(config_dir: PathBuf) -> Self {
+        let path = config_dir.join(SETTINGS_FILE_NAME);
+        let mut settings: AppSettings = match fs::read_to_string(&path) {
+            Ok(data) => toml::from_str(&data).unwrap_or_else(|e| {
+                log::error!("Failed to parse settings file, using defaults: {}", e);
+                AppSettings::default()
+            }),
+            Err(_) => AppSettings::default(),
+        };
+        settings.path = path;
+        settings
+    }
+
+    pub fn is_muted(&self, identity: &str) -> bool {
+        self.muted_identities.iter().any(|i| i == identity)
+    }
+
+    pub fn set_muted(&mut self, identity: &str, muted: bool) {
+        self.muted_identities.retain(|i| i != identity);
+        if muted {
+            self.muted_identities.push(identity.to_string());
+        }
+    }
+
+    pub fn save(&self) {
+        if let Some(dir) = self.path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                log::error!("Failed to create config directory: {}", e);
+                return;
+            }
+        }
+        let data = toml::to_string_pretty(self).expect("settings to be serializable");
+        if let Err(e) = fs::write(&self.path, data) {
+            log::error!("Failed to save settings: {}", e);
+        }
+    }
+}
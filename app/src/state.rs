@@ -1,10 +1,19 @@
+use std::path::PathBuf;
+
+use nantoka_core::{config::Config, network::node::data_dir_lock::DataDirLock};
 use relm4::SharedState;
 
-use crate::network::node::client::NodeClient;
+use crate::{network::node::client::NodeClient, settings::AppSettings};
 
 pub(crate) static STATE: SharedState<GlobalAppState> = SharedState::new();
 
 #[derive(Default)]
 pub struct GlobalAppState {
     pub client: Option<NodeClient>,
+    pub settings: AppSettings,
+    /// Node config loaded from the data dir
+    pub config: Config,
+    pub data_dir: PathBuf,
+    /// Held until the node is started, which takes it over
+    pub data_dir_lock: Option<DataDirLock>,
 }
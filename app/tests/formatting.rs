@@ -0,0 +1,104 @@
+use bitmessage_rs::formatting::{
+    render_html, render_markdown, Span,
+    Style::{self, *},
+};
+
+fn span(text: &str, styles: &[Style]) -> Span {
+    Span {
+        text: text.to_string(),
+        styles: styles.to_vec(),
+    }
+}
+
+fn plain(spans: &[Span]) -> String {
+    spans.iter().map(|s| s.text.as_str()).collect()
+}
+
+#[test]
+fn nested_markers_combine_styles() {
+    assert_eq!(
+        render_markdown("***both*** and **bold *italic***"),
+        vec![
+            span("both", &[Bold, Italic]),
+            span(" and ", &[]),
+            span("bold ", &[Bold]),
+            span("italic", &[Bold, Italic]),
+            span("\n", &[]),
+        ]
+    );
+    assert_eq!(
+        render_html("<b>bold <i>both</i></b> <i>unclosed"),
+        vec![
+            span("bold ", &[Bold]),
+            span("both", &[Bold, Italic]),
+            span(" ", &[]),
+            span("unclosed", &[Italic]),
+        ]
+    );
+}
+
+#[test]
+fn unclosed_markers_are_shown_as_they_are() {
+    for line in ["*a", "**b", "__c", "`d", "[e](f", "_g"] {
+        assert_eq!(
+            render_markdown(line),
+            vec![span(&format!("{}\n", line), &[])]
+        );
+    }
+    // styles don't continue on the next line
+    assert_eq!(render_markdown("*a\nb*"), vec![span("*a\nb*\n", &[])]);
+}
+
+#[test]
+fn escaped_characters_stay_text() {
+    assert_eq!(
+        render_html("<p>1 &lt; 2 &amp;&amp; &quot;a&quot; < b & c</p>"),
+        vec![span("1 < 2 && \"a\" < b & c\n\n", &[])]
+    );
+    // escaped tags aren't interpreted, and entities aren't decoded twice
+    assert_eq!(
+        render_html("&lt;b&gt;x&lt;/b&gt; &amp;lt;"),
+        vec![span("<b>x</b> &lt;", &[])]
+    );
+    assert_eq!(
+        render_markdown("<b>x</b> & \"y\""),
+        vec![span("<b>x</b> & \"y\"\n", &[])]
+    );
+}
+
+#[test]
+fn link_targets_are_shown() {
+    assert_eq!(
+        render_html(r#"<script>alert(1)</script><a href="javascript:alert(1)">safe page</a>"#),
+        vec![
+            span("safe page", &[Link]),
+            span(" (javascript:alert(1))", &[]),
+        ]
+    );
+    let spans = render_markdown("[safe page](javascript:alert(1))");
+    assert_eq!(spans[0], span("safe page", &[Link]));
+    assert_eq!(plain(&spans), "safe page (javascript:alert(1))\n");
+}
+
+#[test]
+fn multibyte_text_next_to_markers() {
+    assert_eq!(
+        render_markdown("**日本**語 *ü*`€`"),
+        vec![
+            span("日本", &[Bold]),
+            span("語 ", &[]),
+            span("ü", &[Italic]),
+            span("€", &[Monospace]),
+            span("\n", &[]),
+        ]
+    );
+    assert_eq!(
+        render_html("<b>日本</b>語<i>ü</i>&euro;&#x20AC;"),
+        vec![
+            span("日本", &[Bold]),
+            span("語", &[]),
+            span("ü", &[Italic]),
+            span("&euro;€", &[]),
+        ]
+    );
+}
@@ -1,8 +1,28 @@
-use std::{error::Error, path::PathBuf};
+use std::{
+    error::Error,
+    fs::OpenOptions,
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
 
+#[cfg(feature = "sqlite")]
+use async_std::stream::StreamExt;
 use async_std::task;
 use clap::Parser;
-use nantoka_core::network;
+#[cfg(feature = "sqlite")]
+use clap::Subcommand;
+use nantoka_core::{
+    config::{Config, InventoryEviction, NodeRole, ObjectType, PoWEngineKind, StorageKind},
+    network::{
+        self,
+        node::data_dir_lock::{DataDirLock, DataDirLockError},
+    },
+    rpc,
+};
+#[cfg(feature = "sqlite")]
+use nantoka_core::{ImportProgress, NodeClient};
 use signal_hook::{
     consts::{SIGINT, SIGTERM},
     iterator::Signals,
@@ -11,14 +31,175 @@ use signal_hook::{
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Data dir of the node, `config.toml` in it is used as the node config
     #[arg(short, long)]
     data_dir: String,
 
-    #[arg(short, long, default_value_t = String::from("0.0.0.0"))]
-    ip: String,
+    /// IP address (v4 or v6) to listen on, overrides listen addresses from the config file
+    #[arg(short, long)]
+    ip: Option<IpAddr>,
+
+    /// Port to listen on, overrides listen addresses from the config file
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Multiaddr to listen on, can be repeated. Overrides listen addresses from the config file.
+    #[arg(long)]
+    listen: Vec<String>,
+
+    /// Also use QUIC transport (listen on `/udp/<port>/quic-v1` addresses with --listen)
+    #[arg(long)]
+    quic: bool,
+
+    /// Only sync objects which stay valid for at least this amount of hours (for light nodes)
+    #[arg(long)]
+    sync_window: Option<i64>,
+
+    /// Only sync objects of these types, e.g. `msg,getpubkey,pubkey` to skip broadcasts
+    #[arg(long, value_delimiter = ',')]
+    object_types: Vec<ObjectType>,
+
+    /// Announce objects in a separate gossipsub topic per object type, so that only
+    /// announcements of --object-types are received. All peers have to use it.
+    #[arg(long)]
+    topic_sharding: bool,
+
+    /// Disconnect peers which haven't exchanged anything with us for this amount of minutes
+    /// (default 10, 0 disables it)
+    #[arg(long)]
+    peer_idle_timeout: Option<i64>,
+
+    /// Accept new public keys of already known contacts instead of rejecting them
+    #[arg(long)]
+    no_key_pinning: bool,
+
+    /// Don't try to get through NAT with AutoNAT, relays and hole punching
+    #[arg(long)]
+    no_nat_traversal: bool,
+
+    /// Relay connections for peers behind NAT
+    #[arg(long)]
+    relay_server: bool,
+
+    /// Do this many times more proof of work for outgoing objects than the network minimum
+    /// (default 1.0)
+    #[arg(long)]
+    pow_difficulty_multiplier: Option<f64>,
+
+    /// Engine used to calculate proof of work, fast (default), async (with the
+    /// `pow-async` feature) or opencl (with the `opencl` feature)
+    #[arg(long)]
+    pow_engine: Option<PoWEngineKind>,
+
+    /// Max number of objects whose proof of work is calculated at the same time (default 2)
+    #[arg(long)]
+    pow_concurrency: Option<usize>,
+
+    /// Permanently remove messages which stay in Trash for this amount of days
+    /// (default 30, 0 keeps them forever)
+    #[arg(long)]
+    trash_retention: Option<i64>,
+
+    /// Where the node data is kept, sqlite (default, with the `sqlite` feature) or memory.
+    /// Nothing is written to the data dir with in-memory storage.
+    #[arg(long)]
+    storage: Option<StorageKind>,
+
+    /// Role of the node: full (default), relay (stores and relays objects, no identities)
+    /// or client (doesn't store or relay objects of others)
+    #[arg(long)]
+    role: Option<NodeRole>,
+
+    /// Generate new peer key (and thus new PeerId) instead of using the stored one
+    #[arg(long)]
+    regenerate_peer_key: bool,
+
+    /// Connect to the peer with given multiaddr on start (can be repeated)
+    #[arg(long = "peer")]
+    peers: Vec<String>,
+
+    /// Reconnect to this many of the most recently seen peers on start
+    /// (default 8, 0 disables it)
+    #[arg(long)]
+    reconnect_peers: Option<usize>,
 
-    #[arg(short, long, default_value_t = 34064)]
-    port: u16,
+    /// Start JSON-RPC over HTTP API server on this port
+    #[arg(long)]
+    rpc_port: Option<u16>,
+
+    /// Address the API server is bound to
+    #[arg(long, default_value_t = String::from("127.0.0.1"))]
+    rpc_ip: String,
+
+    /// Read the token API clients have to send (`Authorization: Bearer <token>`) from
+    /// the file. By default a new token is generated on every start and written to
+    /// `rpc_token` in the data dir.
+    #[arg(long)]
+    rpc_token_file: Option<PathBuf>,
+
+    /// Serve Prometheus metrics on this port
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// Address the metrics exporter is bound to
+    #[cfg(feature = "metrics")]
+    #[arg(long, default_value_t = String::from("127.0.0.1"))]
+    metrics_ip: String,
+
+    /// Create identity derived from the passphrase stored in the file, e.g. to recreate
+    /// an identity from another machine
+    #[arg(long)]
+    deterministic_identity: Option<PathBuf>,
+
+    /// Receive copies of messages to the identity kept on another device. The pairing
+    /// code to be entered on that device is printed.
+    #[arg(long)]
+    pair_with: Option<String>,
+
+    /// Global cap of incoming traffic in KiB/s
+    #[arg(long)]
+    max_download_rate: Option<u64>,
+
+    /// Global cap of traffic spent on relaying objects to other peers in KiB/s
+    #[arg(long)]
+    max_upload_rate: Option<u64>,
+
+    /// Max size of the inventory in MiB (0 disables the limit), objects beyond it are evicted
+    #[arg(long)]
+    max_inventory_size: Option<u64>,
+
+    /// Objects evicted first once the inventory is full, expiring (default) or largest
+    #[arg(long)]
+    inventory_eviction: Option<InventoryEviction>,
+
+    /// Max number of incoming messages per second from a single peer
+    /// (default 20, 0 disables the limit)
+    #[arg(long)]
+    peer_message_rate: Option<u32>,
+
+    /// Encrypt the database with the password stored in the file. Existing unencrypted
+    /// database is encrypted on start.
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    db_password_file: Option<PathBuf>,
+
+    /// Max number of API commands waiting to be handled by the node, more are
+    /// rejected as busy (default 64)
+    #[arg(long)]
+    command_queue_size: Option<usize>,
+
+    #[cfg(feature = "sqlite")]
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Import identities, contacts, subscriptions and messages from PyBitmessage data dir
+    /// (with keys.dat and messages.dat) into the data dir and exit
+    ImportPybitmessage { dir: PathBuf },
 }
 
 #[async_std::main]
@@ -27,27 +208,290 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
     log::debug!("a");
-    let (mut client, worker) = network::new(None, PathBuf::from(args.data_dir));
+    let data_dir = PathBuf::from(args.data_dir);
+    #[cfg(feature = "sqlite")]
+    let database_password = match args.db_password_file {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read database password file: {}", e))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        ),
+        None => None,
+    };
+    // options from the command line override ones from the config file
+    let mut config = Config::load(&data_dir)?;
+    if args.ip.is_some() || args.port.is_some() {
+        let ip = args.ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let port = args.port.unwrap_or(34064);
+        let protocol = if ip.is_ipv6() { "ip6" } else { "ip4" };
+        config.listen_addresses = vec![format!("/{}/{}/tcp/{}", protocol, ip, port).parse()?];
+    }
+    if !args.listen.is_empty() {
+        config.listen_addresses = args
+            .listen
+            .iter()
+            .map(|a| a.parse())
+            .collect::<Result<_, _>>()?;
+    }
+    if args.quic {
+        config.quic = true;
+    }
+    if let Some(v) = args.sync_window {
+        config.sync_window = Some(chrono::Duration::hours(v));
+    }
+    if !args.object_types.is_empty() {
+        config.object_types = Some(args.object_types);
+    }
+    if args.topic_sharding {
+        config.topic_sharding = true;
+    }
+    if let Some(v) = args.peer_idle_timeout {
+        config.peer_idle_timeout = Some(v).filter(|t| *t > 0).map(chrono::Duration::minutes);
+    }
+    if args.no_key_pinning {
+        config.pin_public_keys = false;
+    }
+    if args.no_nat_traversal {
+        config.nat_traversal = false;
+    }
+    if args.relay_server {
+        config.relay_server = true;
+    }
+    if let Some(v) = args.pow_difficulty_multiplier {
+        config.pow_difficulty_multiplier = v;
+    }
+    if let Some(v) = args.pow_engine {
+        config.pow_engine = v;
+    }
+    if let Some(v) = args.pow_concurrency {
+        config.pow_concurrency = v.max(1);
+    }
+    if let Some(v) = args.reconnect_peers {
+        config.reconnect_peers = v;
+    }
+    if let Some(v) = args.command_queue_size {
+        config.command_queue_size = v.max(1);
+    }
+    if let Some(v) = args.trash_retention {
+        config.trash_retention = Some(v).filter(|t| *t > 0).map(chrono::Duration::days);
+    }
+    if let Some(v) = args.storage {
+        config.storage = v;
+    }
+    if let Some(v) = args.role {
+        config.role = v;
+    }
+    if args.regenerate_peer_key {
+        config.regenerate_peer_key = true;
+    }
+    if let Some(v) = args.max_download_rate {
+        config.max_download_rate = Some(
+            v.checked_mul(1024)
+                .ok_or("max download rate is too large")?,
+        );
+    }
+    if let Some(v) = args.max_upload_rate {
+        config.max_upload_rate = Some(v.checked_mul(1024).ok_or("max upload rate is too large")?);
+    }
+    if let Some(v) = args.max_inventory_size {
+        config.max_inventory_size = Some(v)
+            .filter(|s| *s > 0)
+            .map(|s| {
+                s.checked_mul(1024 * 1024)
+                    .ok_or("max inventory size is too large")
+            })
+            .transpose()?;
+    }
+    if let Some(v) = args.inventory_eviction {
+        config.inventory_eviction = v;
+    }
+    if let Some(v) = args.peer_message_rate {
+        config.peer_message_rate = Some(v).filter(|r| *r > 0);
+    }
+    // the database is checked (and maybe migrated) only once no other node uses it
+    let data_dir_lock = if config.storage == StorageKind::Memory {
+        None
+    } else {
+        Some(DataDirLock::acquire(&data_dir).map_err(|e| match e {
+            DataDirLockError::Locked(_) => {
+                format!("{}, control it with its API (--rpc-port) instead", e)
+            }
+            e => e.to_string(),
+        })?)
+    };
+    #[cfg(feature = "sqlite")]
+    if config.storage == StorageKind::Sqlite {
+        config.database_password = database_password;
+
+        let database_path = config.database_path(&data_dir);
+        if network::is_database_encrypted(&database_path) {
+            match &config.database_password {
+                Some(password) => {
+                    if !network::check_database_password(&database_path, password).await {
+                        return Err("wrong database password".into());
+                    }
+                }
+                None => return Err("database is encrypted, use --db-password-file".into()),
+            }
+        }
+        network::check_database(&database_path, config.database_password.as_deref()).await?;
+    }
+
+    // read before the node starts, so that a bad token file doesn't leave it running
+    let rpc_token = match (args.rpc_port, args.rpc_token_file) {
+        (None, _) => None,
+        (Some(_), Some(path)) => Some(
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read API token file: {}", e))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        ),
+        (Some(_), None) if config.storage == StorageKind::Memory => {
+            return Err("set the API token with --rpc-token-file for in-memory storage".into())
+        }
+        (Some(_), None) => {
+            let token = rpc::generate_token();
+            write_token_file(&data_dir.join(rpc::TOKEN_FILE), &token)
+                .map_err(|e| format!("failed to write API token file: {}", e))?;
+            Some(token)
+        }
+    };
+    if rpc_token.as_deref().is_some_and(str::is_empty) {
+        return Err("API token is empty".into());
+    }
+
+    let listen_addresses = config.listen_addresses.clone();
+    let mut builder = network::NodeBuilder::new(config).data_dir(data_dir);
+    if let Some(lock) = data_dir_lock {
+        builder = builder.data_dir_lock(lock);
+    }
+    let (mut client, worker) = builder.build()?;
 
     task::spawn(worker.run());
 
-    client
-        .start_listening(
-            format!("/ip4/{}/tcp/{}", args.ip, args.port)
-                .parse()
-                .unwrap(),
-        )
-        .await
-        .expect("listening not to fail");
+    #[cfg(feature = "sqlite")]
+    if let Some(Command::ImportPybitmessage { dir }) = args.command {
+        return import_pybitmessage(&mut client, dir).await;
+    }
+
+    let mut listening = false;
+    for addr in listen_addresses {
+        match client.start_listening(addr.clone()).await {
+            Ok(()) => listening = true,
+            // e.g. IPv6 might be unavailable
+            Err(e) => log::error!("Failed to listen on {}: {}", addr, e),
+        }
+    }
+    if !listening {
+        return Err("failed to listen on any address".into());
+    }
+
+    for peer in args.peers {
+        let addr = match peer.parse() {
+            Ok(a) => a,
+            Err(e) => {
+                log::error!("Invalid peer address {}: {}", peer, e);
+                continue;
+            }
+        };
+        match client.dial(addr).await {
+            Ok(_) => log::info!("Connected to {}", peer),
+            Err(e) => log::error!("Failed to connect to {}: {}", peer, e),
+        }
+    }
+
+    if let Some(path) = args.deterministic_identity {
+        let result = match std::fs::read_to_string(path) {
+            Ok(passphrase) => {
+                client
+                    .generate_deterministic_identity(
+                        passphrase.trim_end_matches(['\r', '\n']).to_string(),
+                        String::new(),
+                    )
+                    .await
+            }
+            Err(e) => Err(Box::from(e.to_string())),
+        };
+        match result {
+            Ok(address) => log::info!("Deterministic identity {} has been created", address),
+            Err(e) => log::error!("Failed to create deterministic identity: {}", e),
+        }
+    }
+
+    if let Some(address) = args.pair_with {
+        match client.create_device_key(address).await {
+            Ok(code) => println!("Pairing code of this device: {}", code),
+            Err(e) => log::error!("Failed to create device key: {}", e),
+        }
+    }
+
+    if let (Some(rpc_port), Some(token)) = (args.rpc_port, rpc_token) {
+        let rpc_client = client.clone();
+        task::spawn(async move {
+            let addr = (args.rpc_ip.as_str(), rpc_port);
+            if let Err(e) = rpc::serve(addr, rpc_client, token).await {
+                log::error!("RPC server has failed: {}", e);
+            }
+        });
+    }
+
+    #[cfg(feature = "metrics")]
+    if let Some(metrics_port) = args.metrics_port {
+        let metrics_client = client.clone();
+        task::spawn(async move {
+            let addr = (args.metrics_ip.as_str(), metrics_port);
+            if let Err(e) = nantoka_core::metrics::serve(addr, metrics_client).await {
+                log::error!("Metrics exporter has failed: {}", e);
+            }
+        });
+    }
 
     log::info!("node has started successfully!");
 
     let mut signals = Signals::new(&[SIGTERM, SIGINT])?;
     for sig in signals.forever() {
         log::debug!("Received signal {:?}", sig);
-        client.shutdown();
+        client.shutdown().await?;
         return Ok(());
     }
 
     Ok(())
 }
+
+#[cfg(feature = "sqlite")]
+async fn import_pybitmessage(client: &mut NodeClient, dir: PathBuf) -> Result<(), Box<dyn Error>> {
+    let mut progress = client.import_pybitmessage(dir).await?;
+    let mut result = Err("node has stopped during the import".to_string());
+    while let Some(p) = progress.next().await {
+        match p {
+            ImportProgress::Stage { stage, done, total } => {
+                print!("\rImporting {}: {}/{}", stage, done, total);
+                if done == total {
+                    println!();
+                }
+                _ = io::stdout().flush();
+            }
+            ImportProgress::Finished(res) => {
+                result = res;
+                break;
+            }
+        }
+    }
+    client.shutdown().await?;
+    for (stage, count) in result? {
+        println!("Imported {} new {}", count, stage);
+    }
+    Ok(())
+}
+
+/// Write the token readable only by the user running the node
+fn write_token_file(path: &Path, token: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(token.as_bytes())
+}
@@ -0,0 +1,97 @@
+//! Peak memory and time of reading a large request in chunks with the codec,
+//! compared to reading it as a single length-prefixed frame, as it was done before
+//! requests were split into chunks. Run with `cargo bench -p nantoka-core --bench codec`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicIsize, Ordering},
+    time::Instant,
+};
+
+use async_std::task;
+use futures::io::Cursor;
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use nantoka_core::testing;
+
+/// Sizes of the requests, the largest one is close to the limit of the codec
+const SIZES: [usize; 3] = [64 * 1024, 1_000_000, 9_000_000];
+/// Limit of the single frame read before chunking
+const MAX_FRAME_SIZE: usize = 10_000_000;
+
+/// Counts memory allocated by the whole process, the benchmark is single-threaded
+struct CountingAllocator;
+
+static ALLOCATED: AtomicIsize = AtomicIsize::new(0);
+static PEAK: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size() as isize, Ordering::Relaxed)
+                + layout.size() as isize;
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size() as isize, Ordering::Relaxed);
+    }
+
+    /// Growing buffers are usually reallocated in place, so only the difference is counted
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            let delta = new_size as isize - layout.size() as isize;
+            let allocated = ALLOCATED.fetch_add(delta, Ordering::Relaxed) + delta;
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Measure reading of the request, prints its peak memory and time
+fn measure(name: &str, size: usize, read: impl FnOnce() -> Vec<u8>) {
+    let baseline = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let started_at = Instant::now();
+    let read = read();
+    let elapsed = started_at.elapsed();
+    let peak = PEAK.load(Ordering::Relaxed) - baseline;
+    assert_eq!(read.len(), size);
+    println!(
+        "{:<10} {:>12} {:>14} {:>10.2}",
+        name,
+        size,
+        peak,
+        elapsed.as_secs_f64() * 1000.0
+    );
+}
+
+fn main() {
+    println!(
+        "{:<10} {:>12} {:>14} {:>10}",
+        "protocol", "size, bytes", "peak, bytes", "time, ms"
+    );
+    for size in SIZES {
+        let mut written = Cursor::new(Vec::new());
+        task::block_on(write_length_prefixed(&mut written, vec![1; size]))
+            .expect("writing into memory not to fail");
+        let written = written.into_inner();
+        measure("Frame", size, || {
+            task::block_on(read_length_prefixed(&mut &written[..], MAX_FRAME_SIZE))
+                .expect("request to be read")
+        });
+    }
+    for size in SIZES {
+        let written = task::block_on(testing::write_request(vec![1; size]));
+        measure("Chunked", size, || {
+            task::block_on(testing::read_request(&written)).expect("request to be read")
+        });
+    }
+}
@@ -0,0 +1,61 @@
+//! Node replying to every message it receives with the text of the message.
+//!
+//! `cargo run -p nantoka-core --example echo_bot -- <data dir>`, the data dir is
+//! created if it doesn't exist and `config.toml` in it is used as the node config.
+
+use std::{env, error::Error, path::PathBuf};
+
+use async_std::task;
+use futures::StreamExt;
+use nantoka_core::{repositories::models::MessageStatus, Config, NodeBuilder};
+
+#[async_std::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    pretty_env_logger::init();
+
+    let data_dir = match env::args().nth(1) {
+        Some(dir) => PathBuf::from(dir),
+        None => env::temp_dir().join("nantoka-echo-bot"),
+    };
+    let config = Config::load(&data_dir)?;
+    let listen_addresses = config.listen_addresses.clone();
+    let (mut client, worker) = NodeBuilder::new(config).data_dir(data_dir).build()?;
+    task::spawn(worker.run());
+
+    for addr in listen_addresses {
+        if let Err(e) = client.start_listening(addr.clone()).await {
+            log::error!("Failed to listen on {}: {}", addr, e);
+        }
+    }
+
+    // the identity is kept in the data dir, so the bot has the same address after restart
+    let address = match client.get_own_identities().await?.into_iter().next() {
+        Some(identity) => identity.string_repr,
+        None => client.generate_new_identity("Echo bot".to_string()).await?,
+    };
+    println!("Send messages to {}", address);
+
+    let mut events = client.subscribe_message_status().await?;
+    while let Some(event) = events.next().await {
+        let Some(msg) = event
+            .message
+            .filter(|m| m.status == MessageStatus::Received.to_string() && m.recipient == address)
+        else {
+            continue;
+        };
+        let Some(mime) = mail_parser::Message::parse(&msg.data) else {
+            log::warn!("Failed to parse message {}", msg.hash);
+            continue;
+        };
+        let title = format!("Re: {}", mime.subject().unwrap_or_default());
+        let body = mime.body_text(0).unwrap_or_default().into_owned();
+        match client
+            .send_message(address.clone(), vec![msg.sender.clone()], title, body)
+            .await
+        {
+            Ok(_) => println!("Replied to {}", msg.sender),
+            Err(e) => log::error!("Failed to reply to {}: {}", msg.sender, e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,800 @@
+use std::{
+    env, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::Duration;
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use serde::Deserialize;
+use strum::{Display, EnumString};
+
+use crate::{network::validation::MAX_OBJECT_TTL_DAYS, pow};
+
+/// Name of the config file in the data dir
+const CONFIG_FILE_NAME: &str = "config.toml";
+/// Prefix of environment variables overriding values from the config file,
+/// e.g. `NANTOKA_PUBSUB_TOPIC=test`
+const ENV_PREFIX: &str = "NANTOKA_";
+
+/// Listen on all IPv4 and IPv6 interfaces by default
+const DEFAULT_LISTEN_ADDRESSES: [&str; 2] = ["/ip4/0.0.0.0/tcp/34064", "/ip6/::/tcp/34064"];
+/// Default max number of database connections
+const DEFAULT_DATABASE_POOL_SIZE: u32 = 10;
+const DEFAULT_PUBSUB_TOPIC: &str = "common";
+/// Default amount of time after which idle peers are disconnected
+const DEFAULT_PEER_IDLE_TIMEOUT_MINUTES: i64 = 10;
+/// Default amount of time messages wait for recipient's pubkey before they fail
+const DEFAULT_PUBKEY_WAIT_TIMEOUT_DAYS: i64 = 7;
+/// Default amount of time messages are kept in Trash
+const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+/// Default number of incoming messages per second accepted from a single peer
+const DEFAULT_PEER_MESSAGE_RATE: u32 = 20;
+/// Default TTL of outgoing messages and pubkey requests
+const DEFAULT_MSG_TTL_DAYS: i64 = 7;
+/// Default TTL of own pubkeys sent out on request
+const DEFAULT_PUBKEY_TTL_DAYS: i64 = 28;
+/// Default amount of time pubkey requests for the same tag are ignored after it's served
+const DEFAULT_GETPUBKEY_RESPONSE_INTERVAL_HOURS: i64 = 24;
+/// Default amount of time former keys of identities still decrypt messages, it's long
+/// enough for objects encrypted to them before the rotation to expire
+const DEFAULT_KEY_ROTATION_GRACE_DAYS: i64 = 28;
+/// Default number of objects whose PoW is calculated at the same time
+const DEFAULT_POW_CONCURRENCY: usize = 2;
+/// Default number of previously seen peers the node reconnects to
+const DEFAULT_RECONNECT_PEERS: usize = 8;
+/// Default max number of client commands waiting to be handled by the node
+const DEFAULT_COMMAND_QUEUE_SIZE: usize = 64;
+/// Default number of times unacknowledged messages are sent again
+const DEFAULT_MAX_RESENDS: u32 = 3;
+/// Default amount of time clients wait for the node to handle a command
+const DEFAULT_COMMAND_TIMEOUT_SECS: i64 = 30;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config file: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to parse config file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("invalid value of {0}: {1}")]
+    InvalidValue(&'static str, String),
+}
+
+/// Implementation used to calculate PoW of outgoing objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum PoWEngineKind {
+    /// Reference implementation using big integers, slow
+    #[cfg(feature = "pow-async")]
+    Async,
+    /// Same algorithm using 64-bit math
+    #[default]
+    Fast,
+    /// Search on a GPU with OpenCL, the fast engine is used if there are no OpenCL
+    /// devices
+    #[cfg(feature = "opencl")]
+    OpenCL,
+}
+
+impl PoWEngineKind {
+    /// Engines the node is built with
+    pub const ALL: &'static [PoWEngineKind] = &[
+        #[cfg(feature = "pow-async")]
+        PoWEngineKind::Async,
+        PoWEngineKind::Fast,
+        #[cfg(feature = "opencl")]
+        PoWEngineKind::OpenCL,
+    ];
+}
+
+/// Backend the node keeps its data in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum StorageKind {
+    /// SQLite database in the data dir
+    #[cfg(feature = "sqlite")]
+    #[default]
+    Sqlite,
+    /// Everything is kept in memory and lost once the node is stopped, e.g. for tests
+    /// and ephemeral nodes. It's the default when the node is built without SQLite.
+    #[cfg_attr(not(feature = "sqlite"), default)]
+    Memory,
+}
+
+/// Transport the node connects to peers with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum TransportKind {
+    /// TCP, peers are also discovered with mDNS
+    #[default]
+    Tcp,
+    /// In-process transport (`/memory/<port>` addresses), only nodes running in the
+    /// same process can be reached. Used for tests, mDNS is disabled.
+    Memory,
+}
+
+/// Which objects are removed first once the inventory exceeds its size limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum InventoryEviction {
+    /// Objects expiring soonest, they'd be removed soon anyway
+    #[default]
+    Expiring,
+    /// Largest objects, so that fewer objects are lost
+    Largest,
+}
+
+/// Type of objects, nodes may sync only some of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum ObjectType {
+    Msg = 0,
+    Broadcast = 1,
+    Getpubkey = 2,
+    Pubkey = 3,
+}
+
+impl ObjectType {
+    pub const ALL: [ObjectType; 4] = [
+        ObjectType::Msg,
+        ObjectType::Broadcast,
+        ObjectType::Getpubkey,
+        ObjectType::Pubkey,
+    ];
+}
+
+/// Part the node plays in the network, advertised to peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum NodeRole {
+    /// Has identities, stores and relays all objects
+    #[default]
+    Full,
+    /// Only stores and relays objects, identities can't be created
+    Relay,
+    /// Only processes objects addressed to its identities, objects of others are
+    /// neither stored nor relayed
+    Client,
+}
+
+/// Node configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Addresses the node listens on, e.g. IPv4 and IPv6 ones or several ports
+    pub listen_addresses: Vec<Multiaddr>,
+
+    /// Peers used to bootstrap the DHT, addresses must contain peer id.
+    /// These peers are never disconnected due to inactivity.
+    pub bootstrap_peers: Vec<Multiaddr>,
+
+    /// Number of the most recently seen peers (remembered in the database) dialed on
+    /// start, as if they were bootstrap peers. They are redialed with backoff while
+    /// the node has fewer connections, along with peers shared by connected ones.
+    /// 0 disables reconnecting.
+    pub reconnect_peers: usize,
+
+    /// Transport used to connect to peers, listen and bootstrap addresses must match it
+    pub transport: TransportKind,
+
+    /// Use QUIC (`/udp/<port>/quic-v1` addresses) along with TCP transport
+    pub quic: bool,
+
+    /// Find out with AutoNAT whether the node is reachable. If it's behind NAT, it
+    /// listens via relay servers among its peers and connections coming through them
+    /// are upgraded to direct ones with hole punching (DCUtR).
+    pub nat_traversal: bool,
+
+    /// Relay connections for peers behind NAT (circuit relay v2 server)
+    pub relay_server: bool,
+
+    /// Where the node keeps its data. In-memory storage doesn't touch the data dir
+    /// at all, so a new peer key is generated on each start.
+    pub storage: StorageKind,
+
+    /// Max total size of objects in the inventory (bytes), objects beyond it are evicted
+    /// according to `inventory_eviction`. Own objects waiting for PoW are never evicted.
+    pub max_inventory_size: Option<u64>,
+
+    /// Objects evicted first once the inventory exceeds `max_inventory_size`
+    pub inventory_eviction: InventoryEviction,
+
+    /// Relay and client nodes keep only part of the usual functionality, see [`NodeRole`]
+    pub role: NodeRole,
+
+    /// Path of the database file, `None` means `db/database.db` in the data dir
+    pub database_path: Option<PathBuf>,
+
+    /// Max number of open database connections for reads, writes go through a single one
+    pub database_pool_size: u32,
+
+    /// Gossipsub topic objects are announced in. Nodes using different topics
+    /// don't learn about each other's objects.
+    pub pubsub_topic: String,
+
+    /// TTL of outgoing messages and pubkey requests
+    pub msg_ttl: Duration,
+
+    /// TTL of own pubkeys sent out on request
+    pub pubkey_ttl: Duration,
+
+    /// Own pubkey is sent out at most once per this amount of time, no matter how many
+    /// times it's requested. Repeated requests in between are ignored.
+    pub getpubkey_response_interval: Duration,
+
+    /// Messages encrypted to former keys of identities are still decrypted for this
+    /// amount of time after the keys are rotated
+    pub key_rotation_grace_period: Duration,
+
+    /// Messages which aren't acknowledged before their objects expire are sent again
+    /// with doubled TTL (up to the maximum one), at most this number of times. Then
+    /// they're marked as failed. 0 disables resending.
+    pub max_resends: u32,
+
+    /// Selective sync for lightweight clients: if set, only objects which stay valid
+    /// for at least this amount of time are requested and stored. Older objects
+    /// (which are close to their expiration) are not back-filled. Disabled by default.
+    pub sync_window: Option<Duration>,
+
+    /// Only objects of these types are requested and stored, e.g. lightweight clients
+    /// may skip broadcasts. The filter is advertised to peers, so that they don't offer
+    /// other objects. `None` syncs all objects.
+    pub object_types: Option<Vec<ObjectType>>,
+
+    /// Announce objects in a separate topic per object type (`<pubsub_topic>-<type>`)
+    /// instead of the common one. Nodes subscribe only to topics of `object_types`, so
+    /// lightweight clients don't receive announcements of other objects at all. Like
+    /// `pubsub_topic`, it has to be the same for all nodes of the network.
+    pub topic_sharding: bool,
+
+    /// Messages waiting for recipient's pubkey longer than this are marked as failed
+    pub pubkey_wait_timeout: Duration,
+
+    /// Peers which haven't exchanged any gossip or objects with us for this amount of
+    /// time are disconnected. Bootstrap peers are never disconnected. `None` keeps all
+    /// connections alive.
+    pub peer_idle_timeout: Option<Duration>,
+
+    /// Trust on first use: once public keys of a contact are learned, different keys
+    /// received later for the same address are rejected until the contact is re-pinned.
+    pub pin_public_keys: bool,
+
+    /// Multiplier of the PoW difficulty for outgoing objects. Values below 1.0 are
+    /// treated as 1.0, since the network minimum can't be undercut.
+    pub pow_difficulty_multiplier: f64,
+
+    /// Number of trials the PoW of each object takes is divided by it, for own and
+    /// received objects alike. Like `pubsub_topic`, it has to be the same for all nodes
+    /// of the network. Only test networks lower the difficulty, so it's not available
+    /// to other builds.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub pow_trials_divisor: u64,
+
+    /// Engine doing PoW of outgoing objects
+    pub pow_engine: PoWEngineKind,
+
+    /// Max number of objects whose PoW is calculated at the same time. If it's more
+    /// than one, a slot is always left for getpubkey and pubkey objects, so that
+    /// they aren't stuck behind long messages.
+    pub pow_concurrency: usize,
+
+    /// Messages moved to Trash are permanently removed after this amount of time.
+    /// `None` keeps them until they are restored.
+    pub trash_retention: Option<Duration>,
+
+    /// Generate new libp2p identity keypair (and thus new PeerId) instead of loading
+    /// the one stored in the data dir.
+    pub regenerate_peer_key: bool,
+
+    /// Global cap of incoming traffic in bytes per second, messages over it are dropped.
+    /// `None` means unlimited.
+    pub max_download_rate: Option<u64>,
+
+    /// Global cap of traffic spent on answering requests of other peers (i.e. relaying
+    /// objects) in bytes per second. Own outgoing messages are never limited.
+    pub max_upload_rate: Option<u64>,
+
+    /// Max number of incoming messages per second accepted from a single peer.
+    /// `None` means unlimited.
+    pub peer_message_rate: Option<u32>,
+
+    /// Passphrase the database is encrypted with (using SQLCipher). Existing plaintext
+    /// database is encrypted on start. `None` keeps the database unencrypted.
+    pub database_password: Option<String>,
+
+    /// Max number of client commands waiting to be handled by the node, commands sent
+    /// while the queue is full fail with `ClientError::Busy`
+    pub command_queue_size: usize,
+
+    /// Clients give up waiting for the reply of the node after this amount of time,
+    /// so that a stuck node doesn't freeze them
+    pub command_timeout: Duration,
+
+    /// Nodes of the classic Bitmessage network (`host:port`) the node bridges objects
+    /// with. Only used when built with the `legacy-bridge` feature.
+    pub legacy_peers: Vec<String>,
+
+    /// Address the node accepts connections of classic Bitmessage nodes on.
+    /// Only used when built with the `legacy-bridge` feature.
+    pub legacy_listen_address: Option<SocketAddr>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_addresses: DEFAULT_LISTEN_ADDRESSES
+                .iter()
+                .map(|a| a.parse().unwrap())
+                .collect(),
+            bootstrap_peers: Vec::new(),
+            reconnect_peers: DEFAULT_RECONNECT_PEERS,
+            transport: TransportKind::default(),
+            quic: false,
+            nat_traversal: true,
+            relay_server: false,
+            storage: StorageKind::default(),
+            role: NodeRole::default(),
+            max_inventory_size: None,
+            inventory_eviction: InventoryEviction::default(),
+            database_path: None,
+            database_pool_size: DEFAULT_DATABASE_POOL_SIZE,
+            pubsub_topic: DEFAULT_PUBSUB_TOPIC.to_string(),
+            msg_ttl: Duration::days(DEFAULT_MSG_TTL_DAYS),
+            pubkey_ttl: Duration::days(DEFAULT_PUBKEY_TTL_DAYS),
+            getpubkey_response_interval: Duration::hours(DEFAULT_GETPUBKEY_RESPONSE_INTERVAL_HOURS),
+            key_rotation_grace_period: Duration::days(DEFAULT_KEY_ROTATION_GRACE_DAYS),
+            max_resends: DEFAULT_MAX_RESENDS,
+            sync_window: None,
+            object_types: None,
+            topic_sharding: false,
+            pubkey_wait_timeout: Duration::days(DEFAULT_PUBKEY_WAIT_TIMEOUT_DAYS),
+            peer_idle_timeout: Some(Duration::minutes(DEFAULT_PEER_IDLE_TIMEOUT_MINUTES)),
+            pin_public_keys: true,
+            pow_difficulty_multiplier: 1.0,
+            #[cfg(any(test, feature = "test-utils"))]
+            pow_trials_divisor: 1,
+            pow_engine: PoWEngineKind::default(),
+            pow_concurrency: DEFAULT_POW_CONCURRENCY,
+            trash_retention: Some(Duration::days(DEFAULT_TRASH_RETENTION_DAYS)),
+            regenerate_peer_key: false,
+            max_download_rate: None,
+            max_upload_rate: None,
+            peer_message_rate: Some(DEFAULT_PEER_MESSAGE_RATE),
+            database_password: None,
+            command_queue_size: DEFAULT_COMMAND_QUEUE_SIZE,
+            command_timeout: Duration::seconds(DEFAULT_COMMAND_TIMEOUT_SECS),
+            legacy_peers: Vec::new(),
+            legacy_listen_address: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load config from `config.toml` in the data dir, values missing in the file are
+    /// defaults. Values can be overridden with `NANTOKA_<KEY>` environment variables,
+    /// which are parsed as TOML values, or taken as strings if that fails.
+    pub fn load(data_dir: &Path) -> Result<Self, ConfigError> {
+        let mut table = match fs::read_to_string(data_dir.join(CONFIG_FILE_NAME)) {
+            Ok(data) => data.parse::<toml::Table>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+            Err(e) => return Err(e.into()),
+        };
+        for (key, value) in env::vars() {
+            let key = match key.strip_prefix(ENV_PREFIX) {
+                Some(k) => k.to_lowercase(),
+                None => continue,
+            };
+            let value = format!("v = {}", value)
+                .parse::<toml::Table>()
+                .ok()
+                .and_then(|mut t| t.remove("v"))
+                .unwrap_or(toml::Value::String(value));
+            table.insert(key, value);
+        }
+        toml::Value::Table(table)
+            .try_into::<ConfigFile>()?
+            .into_config()
+    }
+
+    /// Save values editable at runtime (see [`WorkerCommand::UpdateConfig`]) to
+    /// `config.toml` in the data dir, other keys of the file are kept as they are
+    ///
+    /// [`WorkerCommand::UpdateConfig`]: crate::network::node::worker::WorkerCommand::UpdateConfig
+    pub fn save(&self, data_dir: &Path) -> Result<(), ConfigError> {
+        let path = data_dir.join(CONFIG_FILE_NAME);
+        let mut table = match fs::read_to_string(&path) {
+            Ok(data) => data.parse::<toml::Table>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let strings = |values: &[Multiaddr]| {
+            toml::Value::Array(
+                values
+                    .iter()
+                    .map(|a| toml::Value::String(a.to_string()))
+                    .collect(),
+            )
+        };
+        table.insert(
+            "listen_addresses".to_string(),
+            strings(&self.listen_addresses),
+        );
+        table.insert(
+            "bootstrap_peers".to_string(),
+            strings(&self.bootstrap_peers),
+        );
+        table.insert(
+            "pow_engine".to_string(),
+            toml::Value::String(self.pow_engine.to_string()),
+        );
+        table.insert(
+            "inventory_eviction".to_string(),
+            toml::Value::String(self.inventory_eviction.to_string()),
+        );
+        let optional = [
+            (
+                "max_download_rate",
+                self.max_download_rate.map(|r| r / 1024),
+            ),
+            ("max_upload_rate", self.max_upload_rate.map(|r| r / 1024)),
+            (
+                "max_inventory_size",
+                self.max_inventory_size.map(|s| s / 1024 / 1024),
+            ),
+        ];
+        for (key, value) in optional {
+            match value {
+                Some(v) => table.insert(key.to_string(), toml::Value::Integer(v as i64)),
+                None => table.remove(key),
+            };
+        }
+        match &self.database_path {
+            Some(p) => table.insert(
+                "database_path".to_string(),
+                toml::Value::String(p.to_string_lossy().into_owned()),
+            ),
+            None => table.remove("database_path"),
+        };
+
+        fs::create_dir_all(data_dir)?;
+        let data = toml::to_string(&table).expect("config to be serializable");
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Keys of values which differ in the new config, but can't be changed while the
+    /// node is running
+    pub fn restart_required(&self, new: &Config) -> Vec<&'static str> {
+        let mut keys = Vec::new();
+        if self.listen_addresses != new.listen_addresses {
+            keys.push("listen_addresses");
+        }
+        if self.pow_engine != new.pow_engine {
+            keys.push("pow_engine");
+        }
+        if self.database_path != new.database_path {
+            keys.push("database_path");
+        }
+        keys
+    }
+
+    /// Port of the first TCP or UDP listen address
+    pub fn listen_port(&self) -> Option<u16> {
+        self.listen_addresses
+            .iter()
+            .flat_map(|a| a.iter())
+            .find_map(|p| match p {
+                Protocol::Tcp(port) | Protocol::Udp(port) => Some(port),
+                _ => None,
+            })
+    }
+
+    /// Replace TCP and UDP ports of all listen addresses
+    pub fn set_listen_port(&mut self, port: u16) {
+        for address in &mut self.listen_addresses {
+            *address = address
+                .iter()
+                .map(|p| match p {
+                    Protocol::Tcp(_) => Protocol::Tcp(port),
+                    Protocol::Udp(_) => Protocol::Udp(port),
+                    p => p,
+                })
+                .collect();
+        }
+    }
+
+    /// Path of the database file in given data dir
+    pub fn database_path(&self, data_dir: &Path) -> PathBuf {
+        self.database_path
+            .clone()
+            .unwrap_or_else(|| data_dir.join("db").join("database.db"))
+    }
+
+    /// Check if object with given expiration time (unix timestamp) should be synced
+    pub fn is_within_sync_window(&self, expires: i64) -> bool {
+        match self.sync_window {
+            Some(window) => expires >= (chrono::Utc::now() + window).timestamp(),
+            None => true,
+        }
+    }
+
+    /// Check if object of given type (see [`ObjectKind::object_type`]) should be synced
+    ///
+    /// [`ObjectKind::object_type`]: crate::network::messages::ObjectKind::object_type
+    pub fn wants_object_type(&self, object_type: u8) -> bool {
+        match &self.object_types {
+            Some(types) => types.iter().any(|t| *t as u8 == object_type),
+            None => true,
+        }
+    }
+
+    /// Get `nonce_trials_per_byte` and `extra_bytes` for outgoing objects
+    pub fn outgoing_pow_difficulty(&self) -> (i32, i32) {
+        let multiplier = self.pow_difficulty_multiplier.max(1.0);
+        (
+            (pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE as f64 * multiplier) as i32,
+            (pow::NETWORK_MIN_EXTRA_BYTES as f64 * multiplier) as i32,
+        )
+    }
+
+    /// Divisor of the PoW trials, the difficulty is only lowered on test networks
+    pub(crate) fn pow_trials_divisor(&self) -> u64 {
+        #[cfg(any(test, feature = "test-utils"))]
+        return self.pow_trials_divisor;
+        #[cfg(not(any(test, feature = "test-utils")))]
+        1
+    }
+}
+
+/// Representation of the config file, durations are in the same units as in CLI options.
+/// Missing values are taken from the default config.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    listen_addresses: Option<Vec<String>>,
+    bootstrap_peers: Option<Vec<String>>,
+    /// 0 disables reconnecting
+    reconnect_peers: Option<usize>,
+    quic: Option<bool>,
+    nat_traversal: Option<bool>,
+    relay_server: Option<bool>,
+    storage: Option<String>,
+    role: Option<String>,
+    /// MiB, 0 disables the limit
+    max_inventory_size: Option<u64>,
+    inventory_eviction: Option<String>,
+    database_path: Option<PathBuf>,
+    database_pool_size: Option<u32>,
+    pubsub_topic: Option<String>,
+    msg_ttl_days: Option<i64>,
+    pubkey_ttl_days: Option<i64>,
+    getpubkey_response_interval_hours: Option<i64>,
+    key_rotation_grace_days: Option<i64>,
+    /// 0 disables resending
+    max_resends: Option<u32>,
+    sync_window_hours: Option<i64>,
+    object_types: Option<Vec<String>>,
+    topic_sharding: Option<bool>,
+    /// 0 disables the timeout
+    pubkey_wait_timeout_days: Option<i64>,
+    peer_idle_timeout_minutes: Option<i64>,
+    pin_public_keys: Option<bool>,
+    pow_difficulty_multiplier: Option<f64>,
+    pow_engine: Option<String>,
+    pow_concurrency: Option<usize>,
+    /// 0 keeps messages forever
+    trash_retention_days: Option<i64>,
+    /// KiB/s
+    max_download_rate: Option<u64>,
+    /// KiB/s
+    max_upload_rate: Option<u64>,
+    /// 0 disables the limit
+    peer_message_rate: Option<u32>,
+    command_queue_size: Option<usize>,
+    command_timeout_secs: Option<i64>,
+    legacy_peers: Option<Vec<String>>,
+    legacy_listen_address: Option<String>,
+}
+
+impl ConfigFile {
+    fn into_config(self) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        if let Some(v) = self.listen_addresses {
+            config.listen_addresses = parse_multiaddrs("listen_addresses", v)?;
+        }
+        if let Some(v) = self.bootstrap_peers {
+            config.bootstrap_peers = parse_peer_multiaddrs("bootstrap_peers", v)?;
+        }
+        if let Some(v) = self.reconnect_peers {
+            config.reconnect_peers = v;
+        }
+        if let Some(v) = self.storage {
+            config.storage =
+                StorageKind::from_str(&v).map_err(|_| ConfigError::InvalidValue("storage", v))?;
+        }
+        if let Some(v) = self.role {
+            config.role =
+                NodeRole::from_str(&v).map_err(|_| ConfigError::InvalidValue("role", v))?;
+        }
+        if let Some(v) = self.max_inventory_size {
+            config.max_inventory_size = Some(v)
+                .filter(|s| *s > 0)
+                .map(|s| parse_size("max_inventory_size", s, 1024 * 1024))
+                .transpose()?;
+        }
+        if let Some(v) = self.inventory_eviction {
+            config.inventory_eviction = InventoryEviction::from_str(&v)
+                .map_err(|_| ConfigError::InvalidValue("inventory_eviction", v))?;
+        }
+        if let Some(v) = self.database_path {
+            config.database_path = Some(v);
+        }
+        if let Some(v) = self.database_pool_size {
+            config.database_pool_size = v;
+        }
+        if let Some(v) = self.pubsub_topic {
+            config.pubsub_topic = v;
+        }
+        if let Some(v) = self.msg_ttl_days {
+            config.msg_ttl = parse_ttl("msg_ttl_days", v)?;
+        }
+        if let Some(v) = self.pubkey_ttl_days {
+            config.pubkey_ttl = parse_ttl("pubkey_ttl_days", v)?;
+        }
+        if let Some(v) = self.getpubkey_response_interval_hours {
+            if v < 0 {
+                return Err(ConfigError::InvalidValue(
+                    "getpubkey_response_interval_hours",
+                    v.to_string(),
+                ));
+            }
+            config.getpubkey_response_interval = Duration::hours(v);
+        }
+        if let Some(v) = self.key_rotation_grace_days {
+            if v < 0 {
+                return Err(ConfigError::InvalidValue(
+                    "key_rotation_grace_days",
+                    v.to_string(),
+                ));
+            }
+            config.key_rotation_grace_period = Duration::days(v);
+        }
+        if let Some(v) = self.max_resends {
+            config.max_resends = v;
+        }
+        if let Some(v) = self.sync_window_hours {
+            config.sync_window = Some(Duration::hours(v));
+        }
+        if let Some(v) = self.object_types {
+            if v.is_empty() {
+                return Err(ConfigError::InvalidValue("object_types", "[]".to_string()));
+            }
+            config.object_types = Some(
+                v.into_iter()
+                    .map(|t| {
+                        ObjectType::from_str(&t)
+                            .map_err(|_| ConfigError::InvalidValue("object_types", t))
+                    })
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        if let Some(v) = self.topic_sharding {
+            config.topic_sharding = v;
+        }
+        if let Some(v) = self.pubkey_wait_timeout_days {
+            if v <= 0 {
+                return Err(ConfigError::InvalidValue(
+                    "pubkey_wait_timeout_days",
+                    v.to_string(),
+                ));
+            }
+            config.pubkey_wait_timeout = Duration::days(v);
+        }
+        if let Some(v) = self.peer_idle_timeout_minutes {
+            config.peer_idle_timeout = Some(v).filter(|t| *t > 0).map(Duration::minutes);
+        }
+        if let Some(v) = self.quic {
+            config.quic = v;
+        }
+        if let Some(v) = self.nat_traversal {
+            config.nat_traversal = v;
+        }
+        if let Some(v) = self.relay_server {
+            config.relay_server = v;
+        }
+        if let Some(v) = self.pin_public_keys {
+            config.pin_public_keys = v;
+        }
+        if let Some(v) = self.pow_difficulty_multiplier {
+            config.pow_difficulty_multiplier = v;
+        }
+        if let Some(v) = self.pow_engine {
+            config.pow_engine = PoWEngineKind::from_str(&v)
+                .map_err(|_| ConfigError::InvalidValue("pow_engine", v))?;
+        }
+        if let Some(v) = self.pow_concurrency {
+            if v == 0 {
+                return Err(ConfigError::InvalidValue("pow_concurrency", v.to_string()));
+            }
+            config.pow_concurrency = v;
+        }
+        if let Some(v) = self.trash_retention_days {
+            config.trash_retention = Some(v).filter(|t| *t > 0).map(Duration::days);
+        }
+        if let Some(v) = self.max_download_rate {
+            config.max_download_rate = Some(parse_size("max_download_rate", v, 1024)?);
+        }
+        if let Some(v) = self.max_upload_rate {
+            config.max_upload_rate = Some(parse_size("max_upload_rate", v, 1024)?);
+        }
+        if let Some(v) = self.peer_message_rate {
+            config.peer_message_rate = Some(v).filter(|r| *r > 0);
+        }
+        if let Some(v) = self.command_queue_size {
+            if v == 0 {
+                return Err(ConfigError::InvalidValue(
+                    "command_queue_size",
+                    v.to_string(),
+                ));
+            }
+            config.command_queue_size = v;
+        }
+        if let Some(v) = self.command_timeout_secs {
+            if v <= 0 {
+                return Err(ConfigError::InvalidValue(
+                    "command_timeout_secs",
+                    v.to_string(),
+                ));
+            }
+            config.command_timeout = Duration::seconds(v);
+        }
+        if let Some(v) = self.legacy_peers {
+            config.legacy_peers = v;
+        }
+        if let Some(v) = self.legacy_listen_address {
+            config.legacy_listen_address = Some(
+                v.parse()
+                    .map_err(|_| ConfigError::InvalidValue("legacy_listen_address", v))?,
+            );
+        }
+        Ok(config)
+    }
+}
+
+/// Peers reject objects living longer than the maximum TTL
+fn parse_ttl(key: &'static str, days: i64) -> Result<Duration, ConfigError> {
+    if !(1..=MAX_OBJECT_TTL_DAYS).contains(&days) {
+        return Err(ConfigError::InvalidValue(key, days.to_string()));
+    }
+    Ok(Duration::days(days))
+}
+
+/// Size in bytes of the value given in `unit`s, e.g. MiB
+fn parse_size(key: &'static str, value: u64, unit: u64) -> Result<u64, ConfigError> {
+    value
+        .checked_mul(unit)
+        .ok_or_else(|| ConfigError::InvalidValue(key, value.to_string()))
+}
+
+fn parse_multiaddrs(key: &'static str, values: Vec<String>) -> Result<Vec<Multiaddr>, ConfigError> {
+    values
+        .into_iter()
+        .map(|v| v.parse().map_err(|_| ConfigError::InvalidValue(key, v)))
+        .collect()
+}
+
+/// Addresses of peers, they have to end with the peer id so that the peers can be
+/// added to the DHT
+fn parse_peer_multiaddrs(
+    key: &'static str,
+    values: Vec<String>,
+) -> Result<Vec<Multiaddr>, ConfigError> {
+    let addresses = parse_multiaddrs(key, values)?;
+    match addresses
+        .iter()
+        .find(|a| !matches!(a.iter().last(), Some(Protocol::P2p(_))))
+    {
+        Some(a) => Err(ConfigError::InvalidValue(key, a.to_string())),
+        None => Ok(addresses),
+    }
+}
@@ -0,0 +1,285 @@
+//! Import and export of identities (`keys.dat`), messages (`.eml` files and mbox archives)
+//! and PyBitmessage data dirs. The worker runs these on its repositories when it
+//! receives the corresponding commands.
+
+#[cfg(feature = "sqlite")]
+use std::path::Path;
+use std::{collections::HashSet, error::Error};
+
+use chrono::Utc;
+#[cfg(feature = "sqlite")]
+use futures::channel::mpsc;
+use sha2::{Digest, Sha256};
+use strum::Display;
+
+use crate::{
+    network::node::worker::{store_identity, Folder},
+    repositories::{
+        address::AddressRepositorySync,
+        message::MessageRepositorySync,
+        sqlite::models::{self, MessageStatus},
+    },
+};
+
+pub(crate) mod keys_dat;
+pub(crate) mod mbox;
+#[cfg(feature = "sqlite")]
+pub(crate) mod pybitmessage;
+
+/// Kind of data imported from PyBitmessage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum ImportStage {
+    Identities,
+    Contacts,
+    Subscriptions,
+    Messages,
+}
+
+#[derive(Debug)]
+pub enum ImportProgress {
+    /// `done` of `total` items of the stage are processed, including the skipped ones
+    Stage {
+        stage: ImportStage,
+        done: usize,
+        total: usize,
+    },
+    /// Number of items imported in each stage, already existing ones are skipped
+    Finished(Result<Vec<(ImportStage, usize)>, String>),
+}
+
+/// Serialize identities with given addresses into `keys.dat` format
+pub(crate) async fn export_identities(
+    address_repo: &AddressRepositorySync,
+    addresses: Vec<String>,
+) -> Result<String, Box<dyn Error>> {
+    let mut identities = Vec::new();
+    for a in addresses {
+        match address_repo.get_by_ripe_or_tag(a.clone()).await? {
+            Some(identity) if identity.private_signing_key.is_some() => {
+                // rotated keys don't hash to the address, so it'd change on import
+                if identity.keys_rotated_at.is_some() {
+                    return Err(format!("keys of identity {} were rotated", a).into());
+                }
+                identities.push(identity)
+            }
+            _ => return Err(format!("identity {} not found", a).into()),
+        }
+    }
+    Ok(keys_dat::export(&identities))
+}
+
+/// Import identities from `keys.dat` data, returns addresses of the new identities
+pub(crate) async fn import_identities(
+    address_repo: &mut AddressRepositorySync,
+    data: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut imported = Vec::new();
+    for identity in keys_dat::import(data)? {
+        let address = identity.string_repr.clone();
+        if store_identity(address_repo, identity).await? {
+            imported.push(address);
+        }
+    }
+    Ok(imported)
+}
+
+/// Export messages in the given order as mbox file, or the first of them as `.eml` file
+pub(crate) async fn export_messages(
+    messages_repo: &MessageRepositorySync,
+    hashes: Vec<String>,
+    as_mbox: bool,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut msgs = messages_repo.get_messages_by_hashes(hashes.clone()).await?;
+    msgs.sort_by_key(|m| hashes.iter().position(|h| *h == m.hash));
+    if as_mbox {
+        return Ok(mbox::export(&msgs));
+    }
+    let msg = msgs.first().ok_or("no such message")?;
+    Ok(mbox::to_eml(msg))
+}
+
+/// Import messages from `.eml` or mbox file into Inbox or Sent of the identity.
+/// Messages of other identities and already existing ones are skipped.
+pub(crate) async fn import_messages(
+    messages_repo: &mut MessageRepositorySync,
+    address: String,
+    folder: Folder,
+    data: Vec<u8>,
+) -> Result<usize, Box<dyn Error>> {
+    let status = match folder {
+        Folder::Inbox => MessageStatus::Received,
+        Folder::Sent => MessageStatus::Sent,
+        _ => return Err("messages can only be imported into Inbox or Sent".into()),
+    };
+    let msgs: Vec<mbox::ImportedMessage> = mbox::import(&data)?
+        .into_iter()
+        .filter(|m| match folder {
+            Folder::Inbox => m.recipient == address,
+            _ => m.sender == address,
+        })
+        .collect();
+    let hashes: Vec<String> = msgs
+        .iter()
+        .map(|m| {
+            m.hash
+                .clone()
+                .unwrap_or_else(|| bs58::encode(Sha256::digest(&m.data)).into_string())
+        })
+        .collect();
+    let existing: HashSet<String> = messages_repo
+        .get_messages_by_hashes(hashes.clone())
+        .await?
+        .into_iter()
+        .map(|m| m.hash)
+        .collect();
+
+    let mut imported = 0;
+    for (msg, hash) in msgs.into_iter().zip(hashes) {
+        if existing.contains(&hash) {
+            continue;
+        }
+        messages_repo
+            .save_model(models::Message {
+                hash,
+                sender: msg.sender,
+                recipient: msg.recipient,
+                data: msg.data,
+                created_at: msg.created_at.unwrap_or_else(Utc::now),
+                status: status.to_string(),
+                signature: Vec::new(),
+                failure_reason: None,
+                folder: None,
+                deleted_at: None,
+                ack_data: None,
+                read: true,
+                send_at: None,
+                verified: false,
+                signer_fingerprint: None,
+                resend_count: 0,
+                resend_at: None,
+                expires: None,
+                sender_label: None,
+                recipient_label: None,
+            })
+            .await?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+/// Import PyBitmessage data dir, returns the number of imported items of each stage
+#[cfg(feature = "sqlite")]
+pub(crate) async fn import_pybitmessage(
+    address_repo: &mut AddressRepositorySync,
+    messages_repo: &mut MessageRepositorySync,
+    dir: &Path,
+    progress: &mpsc::UnboundedSender<ImportProgress>,
+) -> Result<Vec<(ImportStage, usize)>, Box<dyn Error>> {
+    let data = pybitmessage::read(dir).await?;
+    let report = |stage, done, total| {
+        _ = progress.unbounded_send(ImportProgress::Stage { stage, done, total });
+    };
+    let mut imported = Vec::new();
+
+    let (mut count, total) = (0, data.identities.len());
+    for (i, identity) in data.identities.into_iter().enumerate() {
+        if store_identity(address_repo, identity).await? {
+            count += 1;
+        }
+        report(ImportStage::Identities, i + 1, total);
+    }
+    imported.push((ImportStage::Identities, count));
+
+    let (mut count, total) = (0, data.contacts.len());
+    for (i, contact) in data.contacts.into_iter().enumerate() {
+        let mut address = contact.address;
+        let existing = address_repo
+            .get_by_ripe_or_tag(address.string_repr.clone())
+            .await?;
+        if existing.is_none() {
+            address.label = contact.label;
+            address_repo.store(address).await?;
+            count += 1;
+        }
+        report(ImportStage::Contacts, i + 1, total);
+    }
+    imported.push((ImportStage::Contacts, count));
+
+    let existing: HashSet<String> = address_repo
+        .get_subscriptions()
+        .await?
+        .into_iter()
+        .map(|s| s.address)
+        .collect();
+    let (mut count, total) = (0, data.subscriptions.len());
+    for (i, subscription) in data.subscriptions.into_iter().enumerate() {
+        let address = subscription.address.string_repr;
+        if !existing.contains(&address) {
+            address_repo
+                .add_subscription(models::Subscription {
+                    address,
+                    label: subscription.label,
+                    created_at: Utc::now(),
+                })
+                .await?;
+            count += 1;
+        }
+        report(ImportStage::Subscriptions, i + 1, total);
+    }
+    imported.push((ImportStage::Subscriptions, count));
+
+    // ids of PyBitmessage are kept as hashes, so importing again skips the messages
+    let hashes: Vec<String> = data
+        .messages
+        .iter()
+        .map(|m| bs58::encode(&m.msgid).into_string())
+        .collect();
+    let existing: HashSet<String> = messages_repo
+        .get_messages_by_hashes(hashes.clone())
+        .await?
+        .into_iter()
+        .map(|m| m.hash)
+        .collect();
+    let (mut count, total) = (0, data.messages.len());
+    for (i, (msg, hash)) in data.messages.into_iter().zip(hashes).enumerate() {
+        if !existing.contains(&hash) {
+            let status = if msg.outgoing {
+                MessageStatus::Sent
+            } else {
+                MessageStatus::Received
+            };
+            messages_repo
+                .save_model(models::Message {
+                    hash: hash.clone(),
+                    sender: msg.sender,
+                    recipient: msg.recipient,
+                    data: msg.data,
+                    created_at: msg.created_at,
+                    status: status.to_string(),
+                    signature: Vec::new(),
+                    failure_reason: None,
+                    folder: None,
+                    deleted_at: None,
+                    ack_data: None,
+                    read: msg.read,
+                    send_at: None,
+                    verified: false,
+                    signer_fingerprint: None,
+                    resend_count: 0,
+                    resend_at: None,
+                    expires: None,
+                    sender_label: None,
+                    recipient_label: None,
+                })
+                .await?;
+            if msg.trashed {
+                messages_repo.move_to_trash(hash).await?;
+            }
+            count += 1;
+        }
+        report(ImportStage::Messages, i + 1, total);
+    }
+    imported.push((ImportStage::Messages, count));
+    Ok(imported)
+}
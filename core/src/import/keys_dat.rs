@@ -0,0 +1,134 @@
+//! Export and import of identities in PyBitmessage `keys.dat` format, i.e. INI file
+//! with a section per address and private keys encoded as WIF.
+
+use ecies::SecretKey;
+use sha2::{Digest, Sha256};
+
+use crate::network::address::Address;
+
+/// Version byte of WIF encoded private keys
+const WIF_PREFIX: u8 = 0x80;
+const WIF_CHECKSUM_LENGTH: usize = 4;
+
+#[derive(thiserror::Error, Debug)]
+pub enum KeysDatError {
+    #[error("line {0} is malformed")]
+    MalformedLine(usize),
+    #[error("section {0} has no {1}")]
+    MissingKey(String, &'static str),
+    #[error("private key in section {0} is invalid")]
+    InvalidKey(String),
+}
+
+/// Serialize identities into `keys.dat` format
+pub fn export(identities: &[Address]) -> String {
+    let mut data = String::new();
+    for identity in identities {
+        let (psk, pek) = match (
+            &identity.private_signing_key,
+            &identity.private_encryption_key,
+        ) {
+            (Some(psk), Some(pek)) => (psk, pek),
+            _ => continue,
+        };
+        data.push_str(&format!("[{}]\n", identity.string_repr));
+        data.push_str(&format!("label = {}\n", identity.label));
+        data.push_str("enabled = true\n");
+        data.push_str("decoy = false\n");
+        if identity.chan {
+            data.push_str("chan = true\n");
+        }
+        data.push_str(&format!(
+            "noncetrialsperbyte = {}\n",
+            identity.nonce_trials_per_byte
+        ));
+        data.push_str(&format!(
+            "payloadlengthextrabytes = {}\n",
+            identity.extra_bytes
+        ));
+        data.push_str(&format!("privsigningkey = {}\n", encode_wif(psk)));
+        data.push_str(&format!("privencryptionkey = {}\n\n", encode_wif(pek)));
+    }
+    data
+}
+
+/// Parse identities from `keys.dat` file. Addresses are derived from the keys,
+/// section names are not used, as PyBitmessage encodes addresses differently.
+/// The `[bitmessagesettings]` section is skipped.
+pub fn import(data: &str) -> Result<Vec<Address>, KeysDatError> {
+    let mut sections: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((name.to_string(), Vec::new()));
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(KeysDatError::MalformedLine(i + 1))?;
+        let (_, entries) = sections
+            .last_mut()
+            .ok_or(KeysDatError::MalformedLine(i + 1))?;
+        entries.push((key.trim().to_lowercase(), value.trim().to_string()));
+    }
+
+    let mut identities = Vec::new();
+    for (name, entries) in sections {
+        if name == "bitmessagesettings" {
+            continue;
+        }
+        let get = |key: &'static str| {
+            entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+                .ok_or(KeysDatError::MissingKey(name.clone(), key))
+        };
+        let psk =
+            decode_wif(&get("privsigningkey")?).ok_or(KeysDatError::InvalidKey(name.clone()))?;
+        let pek =
+            decode_wif(&get("privencryptionkey")?).ok_or(KeysDatError::InvalidKey(name.clone()))?;
+
+        let mut identity = Address::with_private_key(psk, pek);
+        identity.label = get("label").unwrap_or_default();
+        identity.chan = get("chan").is_ok_and(|v| v.eq_ignore_ascii_case("true"));
+        if let Some(v) = get("noncetrialsperbyte").ok().and_then(|v| v.parse().ok()) {
+            identity.nonce_trials_per_byte = v;
+        }
+        if let Some(v) = get("payloadlengthextrabytes")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            identity.extra_bytes = v;
+        }
+        identities.push(identity);
+    }
+    Ok(identities)
+}
+
+fn wif_checksum(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(Sha256::digest(data))[..WIF_CHECKSUM_LENGTH].to_vec()
+}
+
+fn encode_wif(key: &SecretKey) -> String {
+    let mut data = vec![WIF_PREFIX];
+    data.extend_from_slice(&key.serialize());
+    let checksum = wif_checksum(&data);
+    data.extend_from_slice(&checksum);
+    bs58::encode(data).into_string()
+}
+
+fn decode_wif(wif: &str) -> Option<SecretKey> {
+    let data = bs58::decode(wif).into_vec().ok()?;
+    if data.len() <= WIF_CHECKSUM_LENGTH + 1 {
+        return None;
+    }
+    let (payload, checksum) = data.split_at(data.len() - WIF_CHECKSUM_LENGTH);
+    if payload[0] != WIF_PREFIX || wif_checksum(payload) != checksum {
+        return None;
+    }
+    SecretKey::parse_slice(&payload[1..]).ok()
+}
@@ -0,0 +1,222 @@
+//! Export and import of messages as `.eml` files and mbox archives (mboxrd variant),
+//! so that they can be archived or opened in mail clients. Addresses are written as
+//! `<address>@bitmessage`, the same as PyBitmessage's SMTP gateway does.
+
+use chrono::{DateTime, Utc};
+
+use crate::repositories::sqlite::models;
+
+/// Domain of mail addresses Bitmessage addresses are represented with
+const ADDRESS_DOMAIN: &str = "@bitmessage";
+/// Start of the separator line of messages in mbox files
+const MBOX_SEPARATOR: &[u8] = b"From ";
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum MboxError {
+    #[error("file contains no messages")]
+    Empty,
+    #[error("message {0} has no {1} header with Bitmessage address")]
+    MissingAddress(usize, &'static str),
+}
+
+/// Headers added by [`to_eml`], they're removed from imported messages, so that
+/// exporting them again doesn't duplicate them
+const ENVELOPE_HEADERS: [&str; 4] = ["From", "To", "Date", "Message-ID"];
+
+/// Message parsed from `.eml` or mbox file
+#[derive(Debug, Clone)]
+pub struct ImportedMessage {
+    pub sender: String,
+    pub recipient: String,
+    pub created_at: Option<DateTime<Utc>>,
+    /// Hash of the message it was exported from, if it was exported by this app
+    pub hash: Option<String>,
+    /// MIME data of the message without the envelope headers
+    pub data: Vec<u8>,
+}
+
+/// Serialize message as `.eml` file, i.e. its MIME data preceded by the headers
+/// describing the envelope
+pub fn to_eml(msg: &models::Message) -> Vec<u8> {
+    let mut data = format!(
+        "From: {}{}\r\nTo: {}{}\r\nDate: {}\r\nMessage-ID: <{}{}>\r\n",
+        msg.sender,
+        ADDRESS_DOMAIN,
+        msg.recipient,
+        ADDRESS_DOMAIN,
+        msg.created_at.to_rfc2822(),
+        msg.hash,
+        ADDRESS_DOMAIN,
+    )
+    .into_bytes();
+    data.extend_from_slice(&msg.data);
+    data
+}
+
+/// Serialize messages into mbox file. Lines of messages looking like separators
+/// are escaped with `>`.
+pub fn export(msgs: &[models::Message]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for msg in msgs {
+        data.extend_from_slice(
+            format!(
+                "From {}{} {}\n",
+                msg.sender,
+                ADDRESS_DOMAIN,
+                msg.created_at.format("%a %b %e %H:%M:%S %Y")
+            )
+            .as_bytes(),
+        );
+        for line in to_eml(msg).split_inclusive(|b| *b == b'\n') {
+            let quotes = line.iter().take_while(|b| **b == b'>').count();
+            if line[quotes..].starts_with(MBOX_SEPARATOR) {
+                data.push(b'>');
+            }
+            data.extend_from_slice(line);
+        }
+        if !data.ends_with(b"\n") {
+            data.push(b'\n');
+        }
+        data.push(b'\n');
+    }
+    data
+}
+
+/// Parse mbox file or a single `.eml` file
+pub fn import(data: &[u8]) -> Result<Vec<ImportedMessage>, MboxError> {
+    let messages = if data.starts_with(MBOX_SEPARATOR) {
+        split_mbox(data)
+    } else {
+        vec![data.to_vec()]
+    };
+    let messages: Vec<Vec<u8>> = messages
+        .into_iter()
+        .filter(|m| m.iter().any(|b| !b.is_ascii_whitespace()))
+        .collect();
+    if messages.is_empty() {
+        return Err(MboxError::Empty);
+    }
+    messages
+        .into_iter()
+        .enumerate()
+        .map(|(i, data)| {
+            let sender =
+                header_address(&data, "From").ok_or(MboxError::MissingAddress(i + 1, "From"))?;
+            let recipient =
+                header_address(&data, "To").ok_or(MboxError::MissingAddress(i + 1, "To"))?;
+            let created_at = header(&data, "Date")
+                .and_then(|d| DateTime::parse_from_rfc2822(&d).ok())
+                .map(|d| d.with_timezone(&Utc));
+            let hash = header(&data, "Message-ID").and_then(|id| {
+                id.trim_start_matches('<')
+                    .strip_suffix(&format!("{}>", ADDRESS_DOMAIN))
+                    .filter(|h| !h.is_empty() && bs58::decode(h).into_vec().is_ok())
+                    .map(str::to_string)
+            });
+            Ok(ImportedMessage {
+                sender,
+                recipient,
+                created_at,
+                hash,
+                data: strip_envelope(&data),
+            })
+        })
+        .collect()
+}
+
+/// Split mbox file into messages, removing separator lines and unescaping the lines
+/// escaped by [`export`]
+fn split_mbox(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages: Vec<Vec<u8>> = Vec::new();
+    let mut previous_blank = true;
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        if previous_blank && line.starts_with(MBOX_SEPARATOR) {
+            messages.push(Vec::new());
+            previous_blank = false;
+            continue;
+        }
+        previous_blank = line.iter().all(|b| b.is_ascii_whitespace());
+        let Some(message) = messages.last_mut() else {
+            continue;
+        };
+        let quotes = line.iter().take_while(|b| **b == b'>').count();
+        if quotes > 0 && line[quotes..].starts_with(MBOX_SEPARATOR) {
+            message.extend_from_slice(&line[1..]);
+        } else {
+            message.extend_from_slice(line);
+        }
+    }
+    // the blank line before the next separator belongs to the mbox format
+    for message in messages.iter_mut() {
+        if message.ends_with(b"\n\n") {
+            message.pop();
+        }
+    }
+    messages
+}
+
+/// Remove the envelope headers (and their folded lines) from the message
+fn strip_envelope(data: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(data.len());
+    let mut in_headers = true;
+    let mut skipping = false;
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        if in_headers {
+            if line.iter().all(|b| b.is_ascii_whitespace()) {
+                in_headers = false;
+            } else if line.starts_with(b" ") || line.starts_with(b"\t") {
+                if skipping {
+                    continue;
+                }
+            } else {
+                let name = line.split(|b| *b == b':').next().unwrap_or_default();
+                skipping = ENVELOPE_HEADERS
+                    .iter()
+                    .any(|h| h.as_bytes().eq_ignore_ascii_case(name));
+                if skipping {
+                    continue;
+                }
+            }
+        }
+        stripped.extend_from_slice(line);
+    }
+    stripped
+}
+
+/// Value of the header of the message, folded lines are joined
+fn header(data: &[u8], name: &str) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    let mut value: Option<String> = None;
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some(v) = value.as_mut() {
+                v.push(' ');
+                v.push_str(line.trim());
+            }
+            continue;
+        }
+        if value.is_some() {
+            break;
+        }
+        if let Some((n, v)) = line.split_once(':') {
+            if n.trim().eq_ignore_ascii_case(name) {
+                value = Some(v.trim().to_string());
+            }
+        }
+    }
+    value
+}
+
+/// Bitmessage address in the header, e.g. `Name <BM-...@bitmessage>`
+fn header_address(data: &[u8], name: &str) -> Option<String> {
+    let value = header(data, name)?;
+    let start = value.find("BM-")?;
+    let address: String = value[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect();
+    Some(address)
+}
@@ -0,0 +1,190 @@
+//! Import of PyBitmessage data dir: identities from `keys.dat`, and the address book,
+//! subscriptions and messages from the `messages.dat` SQLite database.
+
+use std::{fs, io, path::Path};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use emailmessage::{header, Message, SinglePart};
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, Connection};
+
+use super::keys_dat::{self, KeysDatError};
+use crate::network::address::Address;
+
+const KEYS_FILE_NAME: &str = "keys.dat";
+const MESSAGES_FILE_NAME: &str = "messages.dat";
+/// Folder of deleted messages, both in the `inbox` and `sent` tables
+const TRASH_FOLDER: &str = "trash";
+/// Statuses of outgoing messages which were sent out. The others are still in
+/// the outbox of PyBitmessage, they aren't imported.
+const SENT_STATUSES: [&str; 4] = [
+    "msgsent",
+    "msgsentnoackexpected",
+    "ackreceived",
+    "broadcastsent",
+];
+
+/// Message id, recipient, sender, subject, time, body, folder and the read flag
+/// of received messages or the status of sent ones
+type MessageRow<T> = (Vec<u8>, String, String, String, i64, String, String, T);
+
+#[derive(thiserror::Error, Debug)]
+pub enum PyBitmessageError {
+    #[error("neither {KEYS_FILE_NAME} nor {MESSAGES_FILE_NAME} is found")]
+    NotFound,
+    #[error("failed to read {KEYS_FILE_NAME}: {0}")]
+    Read(#[from] io::Error),
+    #[error(transparent)]
+    KeysDat(#[from] KeysDatError),
+    #[error("failed to read {MESSAGES_FILE_NAME}: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Entry of the address book or subscription
+pub struct LabeledAddress {
+    pub address: Address,
+    pub label: String,
+}
+
+pub struct ImportedMessage {
+    /// Inventory hash of received messages, random id of sent ones
+    pub msgid: Vec<u8>,
+    pub sender: String,
+    pub recipient: String,
+    pub created_at: DateTime<Utc>,
+    /// Title and body encoded as MIME
+    pub data: Vec<u8>,
+    pub outgoing: bool,
+    pub read: bool,
+    pub trashed: bool,
+}
+
+pub struct PyBitmessageData {
+    pub identities: Vec<Address>,
+    pub contacts: Vec<LabeledAddress>,
+    pub subscriptions: Vec<LabeledAddress>,
+    pub messages: Vec<ImportedMessage>,
+}
+
+/// Read the data dir of PyBitmessage. Either of the files may be missing, e.g. when
+/// only `keys.dat` is copied. Addresses of older versions are converted, the ones
+/// which can't be (e.g. `[Broadcast subscribers]` recipient) are kept as they are.
+pub async fn read(dir: &Path) -> Result<PyBitmessageData, PyBitmessageError> {
+    let keys_path = dir.join(KEYS_FILE_NAME);
+    let messages_path = dir.join(MESSAGES_FILE_NAME);
+    if !keys_path.exists() && !messages_path.exists() {
+        return Err(PyBitmessageError::NotFound);
+    }
+    let identities = match fs::read_to_string(&keys_path) {
+        Ok(data) => keys_dat::import(&data)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let mut data = PyBitmessageData {
+        identities,
+        contacts: Vec::new(),
+        subscriptions: Vec::new(),
+        messages: Vec::new(),
+    };
+    if !messages_path.exists() {
+        return Ok(data);
+    }
+
+    // PyBitmessage may be running, so the database is never written to
+    let mut conn = SqliteConnectOptions::new()
+        .filename(&messages_path)
+        .read_only(true)
+        .connect()
+        .await?;
+
+    let rows: Vec<(String, String)> =
+        sqlx::query_as("SELECT COALESCE(CAST(label AS TEXT), ''), address FROM addressbook")
+            .fetch_all(&mut conn)
+            .await?;
+    data.contacts = labeled_addresses(rows);
+
+    let rows: Vec<(String, String)> =
+        sqlx::query_as("SELECT COALESCE(CAST(label AS TEXT), ''), address FROM subscriptions")
+            .fetch_all(&mut conn)
+            .await?;
+    data.subscriptions = labeled_addresses(rows);
+
+    let rows: Vec<MessageRow<bool>> = sqlx::query_as(
+        "SELECT msgid, toaddress, fromaddress, COALESCE(CAST(subject AS TEXT), ''),
+            CAST(received AS INTEGER), COALESCE(CAST(message AS TEXT), ''), folder, read
+        FROM inbox",
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    for (msgid, to, from, subject, received, body, folder, read) in rows {
+        data.messages.push(ImportedMessage {
+            msgid,
+            sender: convert_address(&from),
+            recipient: convert_address(&to),
+            created_at: timestamp(received),
+            data: to_mime(subject, &body),
+            outgoing: false,
+            read,
+            trashed: folder == TRASH_FOLDER,
+        });
+    }
+
+    let rows: Vec<MessageRow<String>> = sqlx::query_as(
+        "SELECT msgid, toaddress, fromaddress, COALESCE(CAST(subject AS TEXT), ''),
+            CAST(senttime AS INTEGER), COALESCE(CAST(message AS TEXT), ''), folder, status
+        FROM sent",
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    for (msgid, to, from, subject, sent_at, body, folder, status) in rows {
+        if !SENT_STATUSES.contains(&status.as_str()) {
+            continue;
+        }
+        data.messages.push(ImportedMessage {
+            msgid,
+            sender: convert_address(&from),
+            recipient: convert_address(&to),
+            created_at: timestamp(sent_at),
+            data: to_mime(subject, &body),
+            outgoing: true,
+            read: true,
+            trashed: folder == TRASH_FOLDER,
+        });
+    }
+
+    conn.close().await?;
+    Ok(data)
+}
+
+fn labeled_addresses(rows: Vec<(String, String)>) -> Vec<LabeledAddress> {
+    rows.into_iter()
+        .filter_map(|(label, address)| {
+            let address = Address::with_pybitmessage_string_repr(&address).ok()?;
+            Some(LabeledAddress { address, label })
+        })
+        .collect()
+}
+
+fn convert_address(address: &str) -> String {
+    Address::with_pybitmessage_string_repr(address)
+        .map(|a| a.string_repr)
+        .unwrap_or_else(|_| address.to_string())
+}
+
+fn timestamp(secs: i64) -> DateTime<Utc> {
+    NaiveDateTime::from_timestamp_opt(secs, 0)
+        .map(|t| DateTime::from_utc(t, Utc))
+        .unwrap_or_else(Utc::now)
+}
+
+/// Encode the message the same way as the ones composed by this node
+fn to_mime(subject: String, body: &str) -> Vec<u8> {
+    let m: Message<SinglePart<&str>> = Message::builder().subject(subject).mime_body(
+        SinglePart::builder()
+            .header(header::ContentType(
+                "text/plain; charset=utf8".parse().unwrap(),
+            ))
+            .header(header::ContentTransferEncoding::QuotedPrintable)
+            .body(body),
+    );
+    m.to_string().into_bytes()
+}
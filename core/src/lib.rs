@@ -1,3 +1,28 @@
+//! Bitmessage node which can be embedded into other applications.
+//!
+//! [`NodeBuilder`] creates a [`NodeWorker`], which has to be run (e.g. spawned as a task),
+//! and a [`NodeClient`] controlling it. Received messages and status changes of the
+//! outgoing ones are reported by [`NodeClient::subscribe_message_status`]. See
+//! `examples/echo_bot.rs` for a node replying to the messages it receives.
+
+pub mod config;
+mod import;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod network;
 mod pow;
-mod repositories;
+pub mod repositories;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "test-utils")]
+pub mod testing;
+
+pub use config::Config;
+pub use import::{ImportProgress, ImportStage};
+pub use network::{
+    node::{
+        client::{ClientError, NodeClient},
+        worker::{Folder, MessageStatusEvent, NodeWorker, PowerMode},
+    },
+    NodeBuilder,
+};
@@ -0,0 +1,170 @@
+//! Prometheus exporter, so that operators of always-on nodes can monitor them.
+//!
+//! Metrics are served in the text exposition format on `GET /metrics`, they're
+//! collected from the node on every scrape.
+
+use std::fmt::Write;
+
+use async_std::{
+    io::{prelude::BufReadExt, BufReader, WriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    task,
+};
+use futures::StreamExt;
+
+use crate::network::node::{client::NodeClient, worker::NodeMetrics};
+
+/// Prefix of names of all exported metrics
+const PREFIX: &str = "nantoka";
+
+/// Accept scrapes on the given address until the listener fails
+pub async fn serve(addr: impl ToSocketAddrs, client: NodeClient) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Metrics exporter is listening on {}", listener.local_addr()?);
+
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = stream?;
+        let client = client.clone();
+        task::spawn(async move {
+            if let Err(e) = handle_connection(stream, client).await {
+                log::debug!("Metrics connection failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_connection(mut stream: TcpStream, mut client: NodeClient) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.clone());
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    // headers don't matter
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = if !request_line.starts_with("GET ") {
+        ("405 Method Not Allowed", String::new())
+    } else if path != "/metrics" {
+        ("404 Not Found", String::new())
+    } else {
+        match client.get_metrics().await {
+            Ok(metrics) => ("200 OK", render(&metrics)),
+            Err(e) => {
+                log::warn!("Failed to collect metrics: {}", e);
+                ("503 Service Unavailable", String::new())
+            }
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+/// Format metrics in the Prometheus text exposition format
+fn render(metrics: &NodeMetrics) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+        _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+        _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind);
+        _ = writeln!(out, "{}_{} {}", PREFIX, name, value);
+    };
+
+    metric(
+        "connected_peers",
+        "gauge",
+        "Number of connected peers",
+        metrics.connected_peers as f64,
+    );
+    metric(
+        "inventory_objects",
+        "gauge",
+        "Number of objects in the inventory",
+        metrics.inventory.objects as f64,
+    );
+    metric(
+        "inventory_bytes",
+        "gauge",
+        "Total size of objects in the inventory",
+        metrics.inventory.bytes as f64,
+    );
+    metric(
+        "inventory_evicted_objects_total",
+        "counter",
+        "Objects evicted to keep the inventory within its limit",
+        metrics.inventory.evicted as f64,
+    );
+    metric(
+        "objects_relayed_total",
+        "counter",
+        "Objects received from peers and announced further",
+        metrics.traffic.objects_relayed as f64,
+    );
+    metric(
+        "gossip_messages_received_total",
+        "counter",
+        "Gossipsub messages received from peers",
+        metrics.traffic.gossip_messages_received as f64,
+    );
+    metric(
+        "gossip_messages_published_total",
+        "counter",
+        "Gossipsub messages published by the node",
+        metrics.traffic.gossip_messages_published as f64,
+    );
+    metric(
+        "dropped_messages_total",
+        "counter",
+        "Incoming messages dropped due to rate or bandwidth limits",
+        metrics.traffic.dropped_messages as f64,
+    );
+    metric(
+        "received_bytes_total",
+        "counter",
+        "Bytes received from peers",
+        metrics.traffic.bytes_received as f64,
+    );
+    metric(
+        "sent_bytes_total",
+        "counter",
+        "Bytes sent to peers",
+        metrics.traffic.bytes_sent as f64,
+    );
+    metric(
+        "pow_queue_length",
+        "gauge",
+        "Objects waiting for proof of work or being processed",
+        metrics.pow.queue_length as f64,
+    );
+    metric(
+        "pow_completed_total",
+        "counter",
+        "Objects whose proof of work is finished",
+        metrics.pow.completed as f64,
+    );
+    metric(
+        "pow_hash_rate",
+        "gauge",
+        "Nonces tried per second, measured on the last finished proof of work",
+        metrics.pow.trials_per_second.unwrap_or_default(),
+    );
+    metric(
+        "storage_bytes",
+        "gauge",
+        "Size of the database",
+        metrics.storage_bytes as f64,
+    );
+    out
+}
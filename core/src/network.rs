@@ -1,16 +1,142 @@
+#[cfg(feature = "sqlite")]
+use std::path::Path;
 use std::path::PathBuf;
 
-use libp2p::Multiaddr;
+#[cfg(feature = "sqlite")]
+use crate::repositories::sqlite::database;
+use crate::{
+    config::{Config, StorageKind},
+    repositories::{memory::storage::MemoryStorage, storage::Storage},
+};
 
-use self::node::{client::NodeClient, worker::NodeWorker};
+#[cfg(feature = "sqlite")]
+pub use crate::repositories::sqlite::database::DatabaseError;
 
-pub(crate) mod address;
+#[cfg(feature = "sqlite")]
+use self::node::storage::open_sqlite_storage;
+use self::{
+    address::{Address, AddressError},
+    node::{
+        client::NodeClient,
+        data_dir_lock::{DataDirLock, DataDirLockError},
+        worker::NodeWorker,
+    },
+};
+
+pub mod address;
 pub(crate) mod behaviour;
-pub(crate) mod messages;
+#[cfg(feature = "legacy-bridge")]
+pub mod legacy;
+pub mod messages;
 pub mod node;
+pub mod uri;
+pub(crate) mod validation;
+
+/// Failure to build the node, see [`NodeBuilder::build`]
+#[derive(thiserror::Error, Debug)]
+pub enum NodeBuildError {
+    #[error(transparent)]
+    DataDirLock(#[from] DataDirLockError),
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+/// Create the node keeping its data in `data_dir`, see [`NodeBuilder`]
+pub fn new(data_dir: PathBuf, config: Config) -> Result<(NodeClient, NodeWorker), NodeBuildError> {
+    NodeBuilder::new(config).data_dir(data_dir).build()
+}
+
+/// Builder of the node, wires the parts whose implementations depend on the
+/// enabled features (e.g. the storage)
+pub struct NodeBuilder {
+    config: Config,
+    data_dir: PathBuf,
+    data_dir_lock: Option<DataDirLock>,
+    storage: Option<Box<dyn Storage>>,
+}
+
+impl NodeBuilder {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            data_dir: PathBuf::new(),
+            data_dir_lock: None,
+            storage: None,
+        }
+    }
+
+    /// Folder with the database and the peer key, not needed with in-memory storage
+    pub fn data_dir(mut self, data_dir: PathBuf) -> Self {
+        self.data_dir = data_dir;
+        self
+    }
+
+    /// Lock of the data dir acquired beforehand, e.g. to check the database before
+    /// the node is built. Otherwise it's acquired by [`Self::build`].
+    pub fn data_dir_lock(mut self, lock: DataDirLock) -> Self {
+        self.data_dir_lock = Some(lock);
+        self
+    }
+
+    /// Storage with custom implementations of the repositories, replacing the one
+    /// chosen by [`Config::storage`]
+    pub fn storage(mut self, storage: Box<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Lock the data dir, open the storage and create the worker, which has to be run,
+    /// and the client controlling it. Fails if another node uses the data dir, or if
+    /// the database can't be opened.
+    pub fn build(self) -> Result<(NodeClient, NodeWorker), NodeBuildError> {
+        let timeout = self
+            .config
+            .command_timeout
+            .to_std()
+            .expect("command timeout to be positive");
+        // nothing is written to the data dir with in-memory storage
+        let data_dir_lock = match self.data_dir_lock {
+            _ if self.config.storage == StorageKind::Memory => None,
+            Some(lock) => Some(lock),
+            None => Some(DataDirLock::acquire(&self.data_dir)?),
+        };
+        let storage: Box<dyn Storage> = match self.storage {
+            Some(storage) => storage,
+            #[cfg(feature = "sqlite")]
+            None if self.config.storage == StorageKind::Sqlite => {
+                Box::new(open_sqlite_storage(&self.data_dir, &self.config)?)
+            }
+            None => Box::new(MemoryStorage::new()),
+        };
+        let (worker, sender) = NodeWorker::new(self.data_dir, self.config, storage, data_dir_lock);
+        let client = NodeClient::new(sender, timeout);
+        Ok((client, worker))
+    }
+}
+
+/// Check that the address is well-formed and its checksum matches
+pub fn validate_address(address: &str) -> Result<(), AddressError> {
+    Address::with_string_repr(address).map(|_| ())
+}
+
+#[cfg(feature = "sqlite")]
+/// Check if the database file is encrypted, so the password is needed to open it
+pub fn is_database_encrypted(database_path: &Path) -> bool {
+    database::is_encrypted(database_path).unwrap_or(false)
+}
+
+#[cfg(feature = "sqlite")]
+/// Check if the password opens the encrypted database file
+pub async fn check_database_password(database_path: &Path, password: &str) -> bool {
+    database::check_password(database_path, password).await
+}
 
-pub fn new(bootstrap_nodes: Option<Vec<Multiaddr>>, data_dir: PathBuf) -> (NodeClient, NodeWorker) {
-    let (worker, sender) = NodeWorker::new(bootstrap_nodes, data_dir);
-    let client = NodeClient::new(sender);
-    (client, worker)
+#[cfg(feature = "sqlite")]
+/// Check that the database can be opened by this version of the node and isn't corrupted
+pub async fn check_database(
+    database_path: &Path,
+    password: Option<&str>,
+) -> Result<(), DatabaseError> {
+    database::check(database_path, password).await
 }
@@ -1,8 +1,46 @@
+use std::ops::RangeInclusive;
+
+use chrono::{DateTime, Utc};
 use ecies::{PublicKey, SecretKey};
 use rand::rngs::OsRng;
 use ripemd::{Digest, Ripemd160};
 use sha2::Sha512;
 
+use crate::pow;
+
+/// Number of leading zero bytes of the ripe hash required for deterministic addresses,
+/// the same as PyBitmessage uses by default
+const DETERMINISTIC_RIPE_NULL_BYTES: usize = 1;
+
+/// Prefix of address string representation
+const ADDRESS_PREFIX: &str = "BM-";
+/// Version of address encoding, v4 addresses strip all leading zero bytes of the ripe
+const ADDRESS_VERSION: u64 = 4;
+/// Versions of PyBitmessage addresses which can be converted, older ones strip
+/// at most two leading zero bytes of the ripe
+const PYBITMESSAGE_ADDRESS_VERSIONS: RangeInclusive<u64> = 2..=4;
+const ADDRESS_STREAM: u64 = 1;
+const ADDRESS_CHECKSUM_LENGTH: usize = 4;
+const RIPE_LENGTH: usize = 20;
+/// Separates the address from the device key in device pairing codes
+const PAIRING_CODE_SEPARATOR: char = ':';
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum AddressError {
+    #[error("address is not valid base58")]
+    InvalidEncoding,
+    #[error("address checksum doesn't match, it's probably mistyped")]
+    InvalidChecksum,
+    #[error("address version {0} is not supported")]
+    UnsupportedVersion(u64),
+    #[error("address stream {0} is not supported")]
+    UnsupportedStream(u64),
+    #[error("address has invalid length")]
+    InvalidLength,
+    #[error("device pairing code is malformed")]
+    InvalidPairingCode,
+}
+
 #[derive(Clone, Debug)]
 pub struct Address {
     pub label: String,
@@ -14,6 +52,27 @@ pub struct Address {
     pub public_encryption_key: Option<PublicKey>,
     pub private_signing_key: Option<SecretKey>,
     pub private_encryption_key: Option<SecretKey>,
+    /// PoW difficulty required for messages sent to this address. Own identities
+    /// advertise it in their pubkey objects, for contacts it's learned from them.
+    pub nonce_trials_per_byte: i32,
+    pub extra_bytes: i32,
+    /// PoW difficulty required for messages from senders which aren't among contacts
+    /// of the address, at least the one above. Advertised in pubkey objects as well.
+    pub stranger_nonce_trials_per_byte: i32,
+    pub stranger_extra_bytes: i32,
+    /// Put messages of strangers which don't meet their difficulty into Quarantine
+    /// instead of dropping them (for own identities)
+    pub quarantine_strangers: bool,
+    /// When own pubkey was last sent out on request
+    pub pubkey_published_at: Option<DateTime<Utc>>,
+    /// When public keys of the contact were received, they expire after a while
+    pub pubkey_received_at: Option<DateTime<Utc>>,
+    /// Identity shared by everyone who knows the chan name, its keys are derived
+    /// from the name like deterministic identities
+    pub chan: bool,
+    /// When keys of the address were last rotated. Its keys don't hash to the address
+    /// then, they're endorsed by the former ones.
+    pub keys_rotated_at: Option<DateTime<Utc>>,
 }
 
 impl Address {
@@ -22,8 +81,7 @@ impl Address {
         let public_decryption_key = SecretKey::parse_slice(&checksum[..32]).unwrap();
         let tag = checksum[32..].to_vec();
 
-        // FIXME make checksum of ripe
-        let string_repr = bs58::encode(&ripe).into_string();
+        let string_repr = encode_address(&ripe);
         Address {
             label: "".to_string(),
             ripe,
@@ -34,6 +92,15 @@ impl Address {
             public_encryption_key: None,
             private_encryption_key: None,
             string_repr,
+            nonce_trials_per_byte: pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE,
+            extra_bytes: pow::NETWORK_MIN_EXTRA_BYTES,
+            stranger_nonce_trials_per_byte: pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE,
+            stranger_extra_bytes: pow::NETWORK_MIN_EXTRA_BYTES,
+            quarantine_strangers: false,
+            pubkey_published_at: None,
+            pubkey_received_at: None,
+            chan: false,
+            keys_rotated_at: None,
         }
     }
 
@@ -63,11 +130,41 @@ impl Address {
         address
     }
 
-    pub fn with_string_repr(address: String) -> Self {
-        let ripe = bs58::decode(address)
-            .into_vec()
-            .expect("address string to be base58 encoded");
-        Self::new(ripe)
+    /// Parse address string, verifying its checksum. The `BM-` prefix is optional.
+    pub fn with_string_repr(address: &str) -> Result<Self, AddressError> {
+        let ripe = decode_address(address)?;
+        Ok(Self::new(ripe))
+    }
+
+    /// Address string as it's stored, i.e. with the `BM-` prefix. Strings which
+    /// aren't valid addresses are returned as they are.
+    pub fn canonical_string_repr(address: &str) -> String {
+        Self::with_string_repr(address)
+            .map(|a| a.string_repr)
+            .unwrap_or_else(|_| address.to_string())
+    }
+
+    /// Parse address string of PyBitmessage, which may be of older versions. Their
+    /// ripe is derived from the keys the same way, so only the encoding changes.
+    pub fn with_pybitmessage_string_repr(address: &str) -> Result<Self, AddressError> {
+        let ripe = decode_address_of_versions(address, PYBITMESSAGE_ADDRESS_VERSIONS)?;
+        Ok(Self::new(ripe))
+    }
+
+    /// Parse address string as it was encoded before checksums were added,
+    /// i.e. plain base58 of the ripe hash. Only used to migrate stored addresses.
+    pub fn with_legacy_string_repr(address: &str) -> Option<Self> {
+        let ripe = bs58::decode(address).into_vec().ok()?;
+        if ripe.len() != RIPE_LENGTH {
+            return None;
+        }
+        Some(Self::new(ripe))
+    }
+
+    /// Seed of the identicon shown as the avatar of the address, it's the hash of
+    /// the address, so it doesn't depend on how the address is written
+    pub fn avatar_seed(&self) -> String {
+        bs58::encode(&self.ripe).into_string()
     }
 
     pub fn generate() -> Self {
@@ -76,6 +173,166 @@ impl Address {
         let address = Self::with_private_key(psk, pek);
         address
     }
+
+    /// Derive identity keys from the passphrase the same way PyBitmessage does, so that
+    /// the identity can be recreated on another machine (also used for chans).
+    ///
+    /// Private keys are the first 32 bytes of `sha512(passphrase || varint(nonce))`, with even
+    /// nonces for signing and odd ones for encryption keys. Nonces are increased until
+    /// the ripe hash starts with a zero byte.
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        let derive_key = |nonce: u64| {
+            let hash = Sha512::new()
+                .chain_update(passphrase.as_bytes())
+                .chain_update(encode_varint(nonce))
+                .finalize();
+            SecretKey::parse_slice(&hash[..32]).ok()
+        };
+
+        let mut signing_key_nonce = 0;
+        loop {
+            let keys = derive_key(signing_key_nonce).zip(derive_key(signing_key_nonce + 1));
+            signing_key_nonce += 2;
+            if let Some((psk, pek)) = keys {
+                let address = Self::with_private_key(psk, pek);
+                if address.ripe[..DETERMINISTIC_RIPE_NULL_BYTES]
+                    .iter()
+                    .all(|b| *b == 0)
+                {
+                    return address;
+                }
+            }
+        }
+    }
+
+    /// Generate identity with keys derived from the seed, so that tests get stable
+    /// addresses, tags and ripes
+    #[cfg(feature = "test-utils")]
+    pub fn generate_seeded(seed: u64) -> Self {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let psk = SecretKey::random(&mut rng);
+        let pek = SecretKey::random(&mut rng);
+        Self::with_private_key(psk, pek)
+    }
+}
+
+/// Encode code pairing a device with the identity, i.e. the address followed by
+/// base58 of the encryption key of the device
+pub fn encode_pairing_code(address: &str, device_key: &PublicKey) -> String {
+    format!(
+        "{}{}{}",
+        address,
+        PAIRING_CODE_SEPARATOR,
+        bs58::encode(device_key.serialize()).into_string()
+    )
+}
+
+/// Decode identity and device key from the pairing code, see [`encode_pairing_code`]
+pub fn decode_pairing_code(code: &str) -> Result<(Address, PublicKey), AddressError> {
+    let (address, key) = code
+        .trim()
+        .split_once(PAIRING_CODE_SEPARATOR)
+        .ok_or(AddressError::InvalidPairingCode)?;
+    let address = Address::with_string_repr(address)?;
+    let key = bs58::decode(key)
+        .into_vec()
+        .ok()
+        .and_then(|k| PublicKey::parse_slice(&k, None).ok())
+        .ok_or(AddressError::InvalidPairingCode)?;
+    Ok((address, key))
+}
+
+/// Encode ripe the same way PyBitmessage does for v4 addresses:
+/// `BM-` + base58 of `varint(version) || varint(stream) || ripe || checksum`, where ripe has
+/// its leading zero bytes stripped and checksum is the first 4 bytes of double sha512 of the rest.
+fn encode_address(ripe: &[u8]) -> String {
+    let mut data = encode_varint(ADDRESS_VERSION);
+    data.extend(encode_varint(ADDRESS_STREAM));
+    let stripped = ripe.iter().position(|b| *b != 0).unwrap_or(ripe.len());
+    data.extend_from_slice(&ripe[stripped..]);
+    let checksum = Sha512::digest(Sha512::digest(&data));
+    data.extend_from_slice(&checksum[..ADDRESS_CHECKSUM_LENGTH]);
+    format!("{}{}", ADDRESS_PREFIX, bs58::encode(data).into_string())
+}
+
+/// Decode ripe from the address string, see [`encode_address`]
+fn decode_address(address: &str) -> Result<Vec<u8>, AddressError> {
+    decode_address_of_versions(address, ADDRESS_VERSION..=ADDRESS_VERSION)
+}
+
+fn decode_address_of_versions(
+    address: &str,
+    versions: RangeInclusive<u64>,
+) -> Result<Vec<u8>, AddressError> {
+    let address = address.trim();
+    let encoded = address.strip_prefix(ADDRESS_PREFIX).unwrap_or(address);
+    let data = bs58::decode(encoded)
+        .into_vec()
+        .map_err(|_| AddressError::InvalidEncoding)?;
+    if data.len() <= ADDRESS_CHECKSUM_LENGTH {
+        return Err(AddressError::InvalidLength);
+    }
+    let (data, checksum) = data.split_at(data.len() - ADDRESS_CHECKSUM_LENGTH);
+    if Sha512::digest(Sha512::digest(data))[..ADDRESS_CHECKSUM_LENGTH] != *checksum {
+        return Err(AddressError::InvalidChecksum);
+    }
+
+    let (version, data) = decode_varint(data).ok_or(AddressError::InvalidLength)?;
+    if !versions.contains(&version) {
+        return Err(AddressError::UnsupportedVersion(version));
+    }
+    let (stream, stripped_ripe) = decode_varint(data).ok_or(AddressError::InvalidLength)?;
+    if stream != ADDRESS_STREAM {
+        return Err(AddressError::UnsupportedStream(stream));
+    }
+    // v4 addresses must not have leading zeros, as they're stripped on encoding
+    if stripped_ripe.len() > RIPE_LENGTH
+        || (version == ADDRESS_VERSION && stripped_ripe.first() == Some(&0))
+    {
+        return Err(AddressError::InvalidLength);
+    }
+
+    let mut ripe = vec![0; RIPE_LENGTH - stripped_ripe.len()];
+    ripe.extend_from_slice(stripped_ripe);
+    Ok(ripe)
+}
+
+/// Decode Bitmessage protocol varint, returning the value and the remaining bytes
+fn decode_varint(data: &[u8]) -> Option<(u64, &[u8])> {
+    let (first, rest) = data.split_first()?;
+    let length = match first {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        v => return Some((*v as u64, rest)),
+    };
+    if rest.len() < length {
+        return None;
+    }
+    let (bytes, rest) = rest.split_at(length);
+    let value = bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u64);
+    Some((value, rest))
+}
+
+/// Encode integer as Bitmessage protocol varint
+fn encode_varint(value: u64) -> Vec<u8> {
+    if value < 0xfd {
+        vec![value as u8]
+    } else if value <= 0xffff {
+        let mut bytes = vec![0xfd];
+        bytes.extend_from_slice(&(value as u16).to_be_bytes());
+        bytes
+    } else if value <= 0xffff_ffff {
+        let mut bytes = vec![0xfe];
+        bytes.extend_from_slice(&(value as u32).to_be_bytes());
+        bytes
+    } else {
+        let mut bytes = vec![0xff];
+        bytes.extend_from_slice(&value.to_be_bytes());
+        bytes
+    }
 }
 
 #[allow(dead_code)]
@@ -1,19 +1,38 @@
-use std::io;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    task::{Context, Poll},
+};
 
 use async_trait::async_trait;
-use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use libp2p::{
-    core::upgrade::{read_length_prefixed, write_length_prefixed},
-    gossipsub, identify,
+    allow_block_list, autonat,
+    core::{
+        upgrade::{read_varint, write_length_prefixed, write_varint, DeniedUpgrade},
+        Endpoint, Multiaddr,
+    },
+    dcutr, gossipsub, identify,
     kad::{record::store::MemoryStore, Kademlia, KademliaEvent},
-    mdns,
+    mdns, relay,
     request_response::{self, Codec, ProtocolName},
-    swarm::{keep_alive, NetworkBehaviour},
+    swarm::{
+        behaviour::toggle::Toggle,
+        handler::{ConnectionEvent, FullyNegotiatedInbound, FullyNegotiatedOutbound},
+        ConnectionClosed, ConnectionDenied, ConnectionHandler, ConnectionHandlerEvent,
+        ConnectionId, FromSwarm, KeepAlive, NetworkBehaviour, NotifyHandler, PollParameters,
+        SubstreamProtocol, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    PeerId,
 };
 use log::error;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use void::Void;
 
+/// Maximum size of a single chunk of the message on the wire
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+/// Maximum size of the whole message (sum of all its chunks)
+const MAX_MESSAGE_SIZE: usize = 10_000_000;
+
 #[derive(Debug, Clone)]
 pub struct BitmessageProtocol();
 #[derive(Clone)]
@@ -21,59 +40,84 @@ pub struct BitmessageProtocolCodec();
 
 impl ProtocolName for BitmessageProtocol {
     fn protocol_name(&self) -> &[u8] {
-        "/bitmessage/1.0".as_bytes()
+        "/bitmessage/1.1".as_bytes()
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct BitmessageRequest(pub super::messages::NetworkMessage);
+/// Encoded [`super::messages::NetworkMessage`], it's decoded by the node, so that
+/// malformed messages are just ignored
+#[derive(Debug)]
+pub struct BitmessageRequest(pub Vec<u8>);
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct BitmessageResponse(pub super::messages::NetworkMessage);
+#[derive(Debug)]
+pub struct BitmessageResponse(pub Vec<u8>);
 
+/// Messages are transferred as a sequence of length-prefixed chunks (each at most
+/// [`MAX_CHUNK_SIZE`] bytes long), terminated by an empty chunk. Memory is only
+/// allocated for the data which actually arrives, not for the length a peer
+/// announces, and chunks are read right into the buffer of the message.
+///
+/// Peak memory still grows with the message size (see `benches/codec.rs`) and can't be
+/// capped by streaming chunks into the decoder: a response is a batch of objects which
+/// are all stored once it's decoded, so the decoded message is as large as the encoded
+/// one. The limit of the message size is what bounds memory per request.
 impl BitmessageProtocolCodec {
-    async fn _read_data<T, B>(&self, io: &mut B) -> io::Result<T>
+    async fn _read_data<B>(&self, io: &mut B) -> io::Result<Vec<u8>>
     where
-        T: DeserializeOwned,
         B: AsyncRead + Unpin + Send,
     {
-        let vec = read_length_prefixed(io, 10_000_000).await?;
+        let mut vec = Vec::new();
+        loop {
+            let len = read_varint(io).await?;
+            if len == 0 {
+                break;
+            }
+            if len > MAX_CHUNK_SIZE {
+                error!("Received chunk exceeds maximum size");
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            if vec.len() + len > MAX_MESSAGE_SIZE {
+                error!("Received message exceeds maximum size");
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            read_chunk(io, &mut vec, len, MAX_MESSAGE_SIZE).await?;
+        }
 
         if vec.is_empty() {
             return Err(io::ErrorKind::UnexpectedEof.into());
         }
-
-        let res: T = match serde_cbor::from_slice(vec.as_slice()) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Deserialization error: {}", e);
-                return io::Result::Err(io::ErrorKind::InvalidInput.into());
-            }
-        };
-
-        Ok(res)
+        Ok(vec)
     }
 
-    async fn _write_data<T, B>(&self, io: &mut B, data: T) -> io::Result<()>
+    async fn _write_data<B>(&self, io: &mut B, data: Vec<u8>) -> io::Result<()>
     where
-        T: Serialize,
         B: AsyncWrite + Unpin + Send,
     {
-        let res = match serde_cbor::to_vec(&data) {
-            Ok(vec) => vec,
-            Err(e) => {
-                error!("Serialization error: {}", e);
-                return io::Result::Err(io::ErrorKind::InvalidInput.into());
-            }
-        };
-
-        write_length_prefixed(io, res).await?;
+        for chunk in data.chunks(MAX_CHUNK_SIZE) {
+            write_length_prefixed(io, chunk).await?;
+        }
+        write_varint(io, 0).await?;
         io.close().await?;
 
         Ok(())
     }
 }
 
+/// Read `len` bytes from `io` right into the end of the buffer. Capacity of the buffer
+/// is at most doubled, but never grows beyond `max_len`, i.e. the limit of the message.
+async fn read_chunk<B>(io: &mut B, buf: &mut Vec<u8>, len: usize, max_len: usize) -> io::Result<()>
+where
+    B: AsyncRead + Unpin + Send,
+{
+    let start = buf.len();
+    if buf.capacity() < start + len {
+        let grow_by = buf.capacity().max(len).min(max_len - start);
+        buf.reserve_exact(grow_by);
+    }
+    buf.resize(start + len, 0);
+    io.read_exact(&mut buf[start..]).await
+}
+
 #[async_trait]
 impl Codec for BitmessageProtocolCodec {
     type Protocol = BitmessageProtocol;
@@ -88,7 +132,7 @@ impl Codec for BitmessageProtocolCodec {
     where
         T: AsyncRead + Unpin + Send,
     {
-        self._read_data(io).await
+        self._read_data(io).await.map(BitmessageRequest)
     }
 
     async fn read_response<T>(
@@ -99,7 +143,7 @@ impl Codec for BitmessageProtocolCodec {
     where
         T: AsyncRead + Unpin + Send,
     {
-        self._read_data(io).await
+        self._read_data(io).await.map(BitmessageResponse)
     }
 
     async fn write_request<T>(
@@ -111,7 +155,7 @@ impl Codec for BitmessageProtocolCodec {
     where
         T: AsyncWrite + Unpin + Send,
     {
-        self._write_data(io, req).await
+        self._write_data(io, req.0).await
     }
 
     async fn write_response<T>(
@@ -123,19 +167,211 @@ impl Codec for BitmessageProtocolCodec {
     where
         T: AsyncWrite + Unpin + Send,
     {
-        self._write_data(io, resp).await
+        self._write_data(io, resp.0).await
     }
 }
 
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "BitmessageBehaviourEvent")]
 pub struct BitmessageNetBehaviour {
+    /// Denies connections of banned peers
+    pub blocked_peers: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
     pub gossipsub: gossipsub::Behaviour,
     pub identify: identify::Behaviour,
     pub kademlia: Kademlia<MemoryStore>,
     pub rpc: request_response::Behaviour<BitmessageProtocolCodec>,
-    pub mdns: mdns::async_io::Behaviour,
-    pub keep_alive: keep_alive::Behaviour,
+    /// Disabled for in-process transport
+    pub mdns: Toggle<mdns::async_io::Behaviour>,
+    /// NAT traversal, disabled with `Config::nat_traversal`
+    pub autonat: Toggle<autonat::Behaviour>,
+    pub relay_client: Toggle<relay::client::Behaviour>,
+    pub dcutr: Toggle<dcutr::Behaviour>,
+    /// Enabled with `Config::relay_server`
+    pub relay: Toggle<relay::Behaviour>,
+    /// Overrides idle timeouts of the protocol handlers (e.g. 10 seconds of
+    /// request-response) until the worker releases an idle peer, see
+    /// `Config::peer_idle_timeout`.
+    pub keep_alive: KeepAlivePolicy,
+}
+
+/// Keeps connections alive, except for those of the peers released by the worker.
+/// Connections of a released peer are closed by libp2p as soon as the other
+/// protocols have no substream open.
+#[derive(Default)]
+pub struct KeepAlivePolicy {
+    connections: HashMap<PeerId, HashSet<ConnectionId>>,
+    released: HashSet<PeerId>,
+    pending_events: VecDeque<ToSwarm<Void, bool>>,
+}
+
+impl KeepAlivePolicy {
+    /// Stop keeping connections of the peer alive. Returns false if the peer is
+    /// already released or not connected.
+    pub fn release(&mut self, peer_id: PeerId) -> bool {
+        if !self.connections.contains_key(&peer_id) || !self.released.insert(peer_id) {
+            return false;
+        }
+        self.notify_handlers(peer_id, false);
+        true
+    }
+
+    /// Keep connections of the released peer alive again
+    pub fn retain(&mut self, peer_id: PeerId) {
+        if self.released.remove(&peer_id) {
+            self.notify_handlers(peer_id, true);
+        }
+    }
+
+    fn notify_handlers(&mut self, peer_id: PeerId, keep_alive: bool) {
+        for connection in self.connections.get(&peer_id).into_iter().flatten() {
+            self.pending_events.push_back(ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(*connection),
+                event: keep_alive,
+            });
+        }
+    }
+
+    fn add_connection(&mut self, peer_id: PeerId, connection_id: ConnectionId) {
+        // a new connection means the peer isn't idle anymore
+        self.retain(peer_id);
+        self.connections
+            .entry(peer_id)
+            .or_default()
+            .insert(connection_id);
+    }
+}
+
+impl NetworkBehaviour for KeepAlivePolicy {
+    type ConnectionHandler = KeepAliveHandler;
+    type OutEvent = Void;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.add_connection(peer_id, connection_id);
+        Ok(KeepAliveHandler { keep_alive: true })
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.add_connection(peer_id, connection_id);
+        Ok(KeepAliveHandler { keep_alive: true })
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        void::unreachable(event)
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context<'_>,
+        _: &mut impl PollParameters,
+    ) -> Poll<ToSwarm<Self::OutEvent, THandlerInEvent<Self>>> {
+        match self.pending_events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm<Self::ConnectionHandler>) {
+        if let FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            connection_id,
+            remaining_established,
+            ..
+        }) = event
+        {
+            if let Some(connections) = self.connections.get_mut(&peer_id) {
+                connections.remove(&connection_id);
+            }
+            if remaining_established == 0 {
+                self.connections.remove(&peer_id);
+                self.released.remove(&peer_id);
+            }
+        }
+    }
+}
+
+/// Keeps the connection alive until the behaviour tells otherwise, opens no substreams
+pub struct KeepAliveHandler {
+    keep_alive: bool,
+}
+
+impl ConnectionHandler for KeepAliveHandler {
+    type InEvent = bool;
+    type OutEvent = Void;
+    type Error = Void;
+    type InboundProtocol = DeniedUpgrade;
+    type OutboundProtocol = DeniedUpgrade;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = Void;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        SubstreamProtocol::new(DeniedUpgrade, ())
+    }
+
+    fn on_behaviour_event(&mut self, keep_alive: bool) {
+        self.keep_alive = keep_alive;
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.keep_alive {
+            KeepAlive::Yes
+        } else {
+            KeepAlive::No
+        }
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context<'_>,
+    ) -> Poll<
+        ConnectionHandlerEvent<
+            Self::OutboundProtocol,
+            Self::OutboundOpenInfo,
+            Self::OutEvent,
+            Self::Error,
+        >,
+    > {
+        Poll::Pending
+    }
+
+    fn on_connection_event(
+        &mut self,
+        event: ConnectionEvent<
+            Self::InboundProtocol,
+            Self::OutboundProtocol,
+            Self::InboundOpenInfo,
+            Self::OutboundOpenInfo,
+        >,
+    ) {
+        match event {
+            ConnectionEvent::FullyNegotiatedInbound(FullyNegotiatedInbound {
+                protocol, ..
+            }) => void::unreachable(protocol),
+            ConnectionEvent::FullyNegotiatedOutbound(FullyNegotiatedOutbound {
+                protocol, ..
+            }) => void::unreachable(protocol),
+            ConnectionEvent::DialUpgradeError(_)
+            | ConnectionEvent::ListenUpgradeError(_)
+            | ConnectionEvent::AddressChange(_) => {}
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -145,6 +381,10 @@ pub enum BitmessageBehaviourEvent {
     Identify(identify::Event),
     Gossipsub(gossipsub::Event),
     Mdns(mdns::Event),
+    Autonat(autonat::Event),
+    RelayClient(relay::client::Event),
+    Dcutr(dcutr::Event),
+    Relay(relay::Event),
     Void,
 }
 
@@ -180,6 +420,30 @@ impl From<mdns::Event> for BitmessageBehaviourEvent {
     }
 }
 
+impl From<autonat::Event> for BitmessageBehaviourEvent {
+    fn from(value: autonat::Event) -> Self {
+        BitmessageBehaviourEvent::Autonat(value)
+    }
+}
+
+impl From<relay::client::Event> for BitmessageBehaviourEvent {
+    fn from(value: relay::client::Event) -> Self {
+        BitmessageBehaviourEvent::RelayClient(value)
+    }
+}
+
+impl From<dcutr::Event> for BitmessageBehaviourEvent {
+    fn from(value: dcutr::Event) -> Self {
+        BitmessageBehaviourEvent::Dcutr(value)
+    }
+}
+
+impl From<relay::Event> for BitmessageBehaviourEvent {
+    fn from(value: relay::Event) -> Self {
+        BitmessageBehaviourEvent::Relay(value)
+    }
+}
+
 impl From<Void> for BitmessageBehaviourEvent {
     fn from(_value: Void) -> Self {
         BitmessageBehaviourEvent::Void
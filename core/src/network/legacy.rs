@@ -0,0 +1,7 @@
+//! Bridge to the classic Bitmessage network (PyBitmessage). Objects of legacy nodes
+//! are relayed as they are: they're exchanged with legacy peers over the classic
+//! wire protocol and between bridge nodes over a separate gossipsub topic. They are
+//! never stored into the inventory, since their encryption differs from native objects.
+
+pub(crate) mod bridge;
+pub mod wire;
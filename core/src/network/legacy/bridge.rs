@@ -0,0 +1,484 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io,
+    net::{Shutdown, SocketAddr},
+    time::Duration,
+};
+
+use async_std::{
+    future,
+    net::{TcpListener, TcpStream},
+    stream, task,
+};
+use chrono::Utc;
+use futures::{channel::mpsc, pin_mut, select, AsyncReadExt, AsyncWriteExt, FutureExt, StreamExt};
+use libp2p::gossipsub::Sha256Topic;
+use log::{debug, info, warn};
+
+use crate::{config::Config, network::node::worker::WorkerCommand};
+
+use super::wire::{
+    Header, InventoryHash, LegacyObject, Message, NetAddr, Version, WireError, HEADER_LENGTH,
+    MAX_INVENTORY_VECTORS, PROTOCOL_VERSION, SERVICES, STREAM,
+};
+
+const USER_AGENT: &str = concat!("/nantoka:", env!("CARGO_PKG_VERSION"), "/");
+/// Legacy peers must finish the version handshake within this time
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(20);
+/// How often lost connections to configured legacy peers are restored
+const DIAL_INTERVAL: Duration = Duration::from_secs(60);
+/// How often expired objects are dropped
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Max number of legacy connections, incoming connections beyond it are refused
+const MAX_CONNECTIONS: usize = 32;
+/// Max number of relayed objects kept in memory, the ones expiring first are dropped
+const MAX_INVENTORY_SIZE: usize = 20_000;
+/// Max number of objects requested from a legacy node and not received yet, the node
+/// is dropped once it announces more
+const MAX_PENDING_REQUESTS: usize = MAX_INVENTORY_VECTORS;
+/// Max number of messages waiting to be sent to a legacy node, the node is dropped
+/// if it doesn't read them fast enough (e.g. it requests too many objects at once)
+const MAX_QUEUED_MESSAGES: usize = 2_000;
+
+#[derive(thiserror::Error, Debug)]
+enum ConnectionError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Wire(#[from] WireError),
+    #[error("handshake failed: {0}")]
+    Handshake(&'static str),
+}
+
+enum BridgeEvent {
+    Incoming(TcpStream),
+    /// Handshake with the legacy node is done
+    Ready {
+        id: usize,
+        remote: SocketAddr,
+    },
+    Message {
+        id: usize,
+        message: Message,
+    },
+    Disconnected {
+        id: usize,
+    },
+}
+
+struct Connection {
+    sink: mpsc::Sender<Message>,
+    /// Configured address if the connection was dialed by the node
+    dialed: Option<String>,
+    ready: bool,
+    /// Objects requested from the node, but not received yet
+    requested: HashSet<InventoryHash>,
+}
+
+/// Part of the bridge kept by the node worker
+pub(crate) struct BridgeHandle {
+    /// Topic the bridge nodes exchange legacy objects on
+    pub topic: Sha256Topic,
+    /// Objects received on the topic
+    pub sink: mpsc::UnboundedSender<Vec<u8>>,
+    /// Taken when the node is started
+    pub bridge: Option<LegacyBridge>,
+}
+
+impl BridgeHandle {
+    /// Create the bridge, `None` if neither legacy peers nor listen address are configured
+    pub fn new(
+        config: &Config,
+        node_worker_sink: mpsc::UnboundedSender<WorkerCommand>,
+    ) -> Option<Self> {
+        if config.legacy_peers.is_empty() && config.legacy_listen_address.is_none() {
+            return None;
+        }
+        let (sink, overlay_receiver) = mpsc::unbounded();
+        let (event_sink, event_receiver) = mpsc::unbounded();
+        let bridge = LegacyBridge {
+            peers: config.legacy_peers.clone(),
+            listen_address: config.legacy_listen_address,
+            node_worker_sink,
+            overlay_receiver,
+            event_sink,
+            event_receiver,
+            nonce: rand::random(),
+            next_connection_id: 0,
+            connections: HashMap::new(),
+            inventory: HashMap::new(),
+            requested: HashSet::new(),
+        };
+        Some(Self {
+            topic: Sha256Topic::new(format!("{}-legacy", config.pubsub_topic)),
+            sink,
+            bridge: Some(bridge),
+        })
+    }
+}
+
+/// Relays objects between legacy nodes and other bridge nodes
+pub(crate) struct LegacyBridge {
+    peers: Vec<String>,
+    listen_address: Option<SocketAddr>,
+    node_worker_sink: mpsc::UnboundedSender<WorkerCommand>,
+    overlay_receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    event_sink: mpsc::UnboundedSender<BridgeEvent>,
+    event_receiver: mpsc::UnboundedReceiver<BridgeEvent>,
+    /// Random value sent in version messages, used to detect connections to self
+    nonce: u64,
+    next_connection_id: usize,
+    connections: HashMap<usize, Connection>,
+    inventory: HashMap<InventoryHash, LegacyObject>,
+    /// Objects requested from any legacy node, so that they aren't requested twice
+    requested: HashSet<InventoryHash>,
+}
+
+impl LegacyBridge {
+    /// Run until the node worker is dropped
+    pub async fn run(mut self) {
+        if let Some(address) = self.listen_address {
+            match TcpListener::bind(address).await {
+                Ok(listener) => {
+                    info!("Listening for legacy nodes on {}", address);
+                    task::spawn(accept_connections(listener, self.event_sink.clone()));
+                }
+                Err(e) => warn!("Failed to listen for legacy nodes on {}: {}", address, e),
+            }
+        }
+        self.dial_peers();
+
+        let mut dial_timer = stream::interval(DIAL_INTERVAL).fuse();
+        let mut cleanup_timer = stream::interval(CLEANUP_INTERVAL).fuse();
+        loop {
+            select! {
+                event = self.event_receiver.select_next_some() => self.handle_event(event),
+                data = self.overlay_receiver.next() => match data {
+                    Some(data) => match LegacyObject::decode(&data) {
+                        Ok(object) => _ = self.accept_object(object, None),
+                        Err(e) => debug!("Malformed legacy object from bridge nodes: {}", e),
+                    },
+                    None => return,
+                },
+                _ = dial_timer.next() => self.dial_peers(),
+                _ = cleanup_timer.next() => self.cleanup(),
+            }
+        }
+    }
+
+    fn add_connection(&mut self, dialed: Option<String>) -> (usize, mpsc::Receiver<Message>) {
+        let id = self.next_connection_id;
+        self.next_connection_id += 1;
+        let (sink, outgoing) = mpsc::channel(MAX_QUEUED_MESSAGES);
+        self.connections.insert(
+            id,
+            Connection {
+                sink,
+                dialed,
+                ready: false,
+                requested: HashSet::new(),
+            },
+        );
+        (id, outgoing)
+    }
+
+    /// Forget the connection, so that objects requested from it can be requested from
+    /// other nodes. Dropping its sink closes the connection.
+    fn remove_connection(&mut self, id: usize) {
+        if let Some(connection) = self.connections.remove(&id) {
+            for hash in connection.requested {
+                self.requested.remove(&hash);
+            }
+        }
+    }
+
+    /// Queue the message, dropping the node if too many messages are waiting for it
+    fn send(&mut self, id: usize, message: Message) {
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return;
+        };
+        if let Err(e) = connection.sink.try_send(message) {
+            if e.is_full() {
+                debug!("Legacy node doesn't read its messages, dropping it");
+                self.remove_connection(id);
+            }
+        }
+    }
+
+    /// Connect to configured legacy peers the node isn't connected to
+    fn dial_peers(&mut self) {
+        for address in self.peers.clone() {
+            if self
+                .connections
+                .values()
+                .any(|c| c.dialed.as_ref() == Some(&address))
+            {
+                continue;
+            }
+            let (id, outgoing) = self.add_connection(Some(address.clone()));
+            task::spawn(handle_connection(
+                id,
+                async move { TcpStream::connect(address).await },
+                self.nonce,
+                self.event_sink.clone(),
+                outgoing,
+            ));
+        }
+    }
+
+    fn handle_event(&mut self, event: BridgeEvent) {
+        match event {
+            BridgeEvent::Incoming(stream) => {
+                if self.connections.len() >= MAX_CONNECTIONS {
+                    debug!("Too many legacy connections, refusing incoming one");
+                    return;
+                }
+                let (id, outgoing) = self.add_connection(None);
+                task::spawn(handle_connection(
+                    id,
+                    future::ready(Ok(stream)),
+                    self.nonce,
+                    self.event_sink.clone(),
+                    outgoing,
+                ));
+            }
+            BridgeEvent::Ready { id, remote } => {
+                info!("Connected to legacy node {}", remote);
+                let hashes: Vec<InventoryHash> = self.inventory.keys().copied().collect();
+                let Some(connection) = self.connections.get_mut(&id) else {
+                    return;
+                };
+                connection.ready = true;
+                for chunk in hashes.chunks(MAX_INVENTORY_VECTORS) {
+                    self.send(id, Message::Inv(chunk.to_vec()));
+                }
+            }
+            BridgeEvent::Message { id, message } => self.handle_message(id, message),
+            BridgeEvent::Disconnected { id } => self.remove_connection(id),
+        }
+    }
+
+    fn handle_message(&mut self, id: usize, message: Message) {
+        let reply = match message {
+            Message::Inv(hashes) => {
+                let wanted: Vec<InventoryHash> = hashes
+                    .into_iter()
+                    .filter(|h| !self.inventory.contains_key(h) && !self.requested.contains(h))
+                    .collect();
+                let Some(connection) = self.connections.get_mut(&id) else {
+                    return;
+                };
+                connection.requested.extend(&wanted);
+                if connection.requested.len() > MAX_PENDING_REQUESTS {
+                    debug!("Legacy node announced too many objects, dropping it");
+                    self.remove_connection(id);
+                    return;
+                }
+                self.requested.extend(&wanted);
+                Some(wanted).filter(|w| !w.is_empty()).map(Message::GetData)
+            }
+            Message::GetData(hashes) => {
+                let objects: Vec<LegacyObject> = hashes
+                    .iter()
+                    .filter_map(|h| self.inventory.get(h).cloned())
+                    .collect();
+                for object in objects {
+                    self.send(id, Message::Object(object));
+                }
+                None
+            }
+            Message::Object(object) => {
+                let data = object.encode();
+                if self.accept_object(object, Some(id)) {
+                    _ = self
+                        .node_worker_sink
+                        .unbounded_send(WorkerCommand::PublishLegacyObject { data });
+                }
+                None
+            }
+            Message::Ping => Some(Message::Pong),
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            self.send(id, reply);
+        }
+    }
+
+    /// Store the object and announce it to legacy nodes other than the one it came from.
+    /// Returns `false` if the object is already known or invalid.
+    fn accept_object(&mut self, object: LegacyObject, source: Option<usize>) -> bool {
+        let hash = object.inventory_hash();
+        if self.requested.remove(&hash) {
+            for connection in self.connections.values_mut() {
+                connection.requested.remove(&hash);
+            }
+        }
+        if self.inventory.contains_key(&hash) || object.stream != STREAM {
+            return false;
+        }
+        if let Err(e) = object.validate() {
+            debug!("Rejected legacy object: {}", e);
+            return false;
+        }
+        if let Err(e) = object.check_pow() {
+            debug!("Rejected legacy object: {}", e);
+            return false;
+        }
+
+        if self.inventory.len() >= MAX_INVENTORY_SIZE {
+            let first_expiring = self
+                .inventory
+                .iter()
+                .min_by_key(|(_, o)| o.expires)
+                .map(|(h, _)| *h);
+            if let Some(h) = first_expiring {
+                self.inventory.remove(&h);
+            }
+        }
+        self.inventory.insert(hash, object);
+
+        let peers: Vec<usize> = self
+            .connections
+            .iter()
+            .filter(|(id, c)| c.ready && Some(**id) != source)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in peers {
+            self.send(id, Message::Inv(vec![hash]));
+        }
+        true
+    }
+
+    fn cleanup(&mut self) {
+        let now = Utc::now().timestamp();
+        self.inventory.retain(|_, o| o.expires > now);
+        // objects which were never delivered are requested again on the next announcement
+        self.requested.clear();
+        for connection in self.connections.values_mut() {
+            connection.requested.clear();
+        }
+    }
+}
+
+async fn accept_connections(listener: TcpListener, event_sink: mpsc::UnboundedSender<BridgeEvent>) {
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                if event_sink
+                    .unbounded_send(BridgeEvent::Incoming(stream))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Err(e) => debug!("Failed to accept legacy connection: {}", e),
+        }
+    }
+}
+
+/// Exchange messages with the legacy node until either side closes the connection
+async fn handle_connection(
+    id: usize,
+    stream: impl Future<Output = io::Result<TcpStream>>,
+    nonce: u64,
+    event_sink: mpsc::UnboundedSender<BridgeEvent>,
+    outgoing: mpsc::Receiver<Message>,
+) {
+    if let Err(e) = run_connection(id, stream, nonce, &event_sink, outgoing).await {
+        debug!("Legacy connection closed: {}", e);
+    }
+    _ = event_sink.unbounded_send(BridgeEvent::Disconnected { id });
+}
+
+async fn run_connection(
+    id: usize,
+    stream: impl Future<Output = io::Result<TcpStream>>,
+    nonce: u64,
+    event_sink: &mpsc::UnboundedSender<BridgeEvent>,
+    mut outgoing: mpsc::Receiver<Message>,
+) -> Result<(), ConnectionError> {
+    let mut stream = stream.await?;
+    let remote = stream.peer_addr()?;
+    future::timeout(HANDSHAKE_TIMEOUT, handshake(&mut stream, remote, nonce))
+        .await
+        .map_err(|_| ConnectionError::Handshake("timed out"))??;
+    _ = event_sink.unbounded_send(BridgeEvent::Ready { id, remote });
+
+    let mut reader = stream.clone();
+    let read = async {
+        loop {
+            let message = read_message(&mut reader).await?;
+            if event_sink
+                .unbounded_send(BridgeEvent::Message { id, message })
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+    }
+    .fuse();
+    let mut writer = stream.clone();
+    let write = async {
+        while let Some(message) = outgoing.next().await {
+            writer.write_all(&message.encode()).await?;
+        }
+        Ok(())
+    }
+    .fuse();
+    pin_mut!(read, write);
+    let result = select! {
+        r = read => r,
+        r = write => r,
+    };
+    _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+async fn handshake(
+    stream: &mut TcpStream,
+    remote: SocketAddr,
+    nonce: u64,
+) -> Result<(), ConnectionError> {
+    let version = Message::Version(Version {
+        version: PROTOCOL_VERSION,
+        services: SERVICES,
+        timestamp: Utc::now().timestamp(),
+        addr_recv: NetAddr::new(remote),
+        addr_from: NetAddr::new(stream.local_addr()?),
+        nonce,
+        user_agent: USER_AGENT.to_string(),
+        streams: vec![STREAM],
+    });
+    stream.write_all(&version.encode()).await?;
+
+    let (mut got_version, mut got_verack) = (false, false);
+    while !(got_version && got_verack) {
+        match read_message(stream).await? {
+            Message::Version(v) => {
+                if v.nonce == nonce {
+                    return Err(ConnectionError::Handshake("connected to self"));
+                }
+                if v.version < PROTOCOL_VERSION || !v.streams.contains(&STREAM) {
+                    return Err(ConnectionError::Handshake("incompatible node"));
+                }
+                stream.write_all(&Message::Verack.encode()).await?;
+                got_version = true;
+            }
+            Message::Verack => got_verack = true,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+async fn read_message(stream: &mut TcpStream) -> Result<Message, ConnectionError> {
+    let mut header = [0u8; HEADER_LENGTH];
+    stream.read_exact(&mut header).await?;
+    let header = Header::decode(&header)?;
+    let mut payload = vec![0u8; header.length];
+    stream.read_exact(&mut payload).await?;
+    header.verify(&payload)?;
+    Ok(Message::decode(&header.command, &payload)?)
+}
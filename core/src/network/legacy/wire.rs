@@ -0,0 +1,397 @@
+//! Classic Bitmessage wire protocol, as spoken by PyBitmessage. Every message is
+//! a 24-byte header followed by the payload, all integers are big-endian.
+
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+use num_bigint::BigUint;
+use sha2::{Digest, Sha512};
+
+use crate::{
+    network::validation::{self, ValidationError, MAX_OBJECT_SIZE},
+    pow::{self, PoWError},
+};
+
+/// Start of every message on the main network
+pub const MAGIC: u32 = 0xE9BEB4D9;
+pub const HEADER_LENGTH: usize = 24;
+/// Largest payload PyBitmessage accepts
+pub const MAX_PAYLOAD_SIZE: usize = 1_600_100;
+/// Largest number of vectors in a single inv or getdata message
+pub const MAX_INVENTORY_VECTORS: usize = 50_000;
+/// Protocol version the node announces
+pub const PROTOCOL_VERSION: i32 = 3;
+/// The only stream the node participates in
+pub const STREAM: u64 = 1;
+/// NODE_NETWORK, the node relays objects
+pub const SERVICES: u64 = 1;
+const COMMAND_LENGTH: usize = 12;
+
+pub type InventoryHash = [u8; 32];
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum WireError {
+    #[error("message doesn't start with the network magic")]
+    BadMagic,
+    #[error("payload checksum doesn't match")]
+    BadChecksum,
+    #[error("payload is too large ({0} bytes)")]
+    TooLarge(usize),
+    #[error("payload is truncated")]
+    Truncated,
+    #[error("malformed {0} payload")]
+    Malformed(&'static str),
+}
+
+/// Header preceding every payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub command: String,
+    pub length: usize,
+    pub checksum: [u8; 4],
+}
+
+impl Header {
+    pub fn decode(data: &[u8; HEADER_LENGTH]) -> Result<Self, WireError> {
+        let mut r = Reader::new(data);
+        if r.u32()? != MAGIC {
+            return Err(WireError::BadMagic);
+        }
+        let command = r.bytes(COMMAND_LENGTH)?;
+        let command = String::from_utf8_lossy(command)
+            .trim_end_matches('\0')
+            .to_string();
+        let length = r.u32()? as usize;
+        if length > MAX_PAYLOAD_SIZE {
+            return Err(WireError::TooLarge(length));
+        }
+        let checksum = r.bytes(4)?.try_into().unwrap();
+        Ok(Self {
+            command,
+            length,
+            checksum,
+        })
+    }
+
+    /// Check that the payload read after the header is intact
+    pub fn verify(&self, payload: &[u8]) -> Result<(), WireError> {
+        if Sha512::digest(payload)[..4] != self.checksum {
+            return Err(WireError::BadChecksum);
+        }
+        Ok(())
+    }
+}
+
+/// Address of a node as used in version and addr messages
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetAddr {
+    pub services: u64,
+    pub addr: SocketAddr,
+}
+
+impl NetAddr {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            services: SERVICES,
+            addr,
+        }
+    }
+
+    fn encode(&self, w: &mut Vec<u8>) {
+        w.extend_from_slice(&self.services.to_be_bytes());
+        let ip = match self.addr.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        w.extend_from_slice(&ip.octets());
+        w.extend_from_slice(&self.addr.port().to_be_bytes());
+    }
+
+    fn decode(r: &mut Reader) -> Result<Self, WireError> {
+        let services = r.u64()?;
+        let octets: [u8; 16] = r.bytes(16)?.try_into().unwrap();
+        let ip = Ipv6Addr::from(octets);
+        let ip = match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(ip),
+        };
+        let port = r.u16()?;
+        Ok(Self {
+            services,
+            addr: SocketAddr::new(ip, port),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    pub version: i32,
+    pub services: u64,
+    pub timestamp: i64,
+    pub addr_recv: NetAddr,
+    pub addr_from: NetAddr,
+    /// Random value used to detect connections to self
+    pub nonce: u64,
+    pub user_agent: String,
+    pub streams: Vec<u64>,
+}
+
+/// Object as relayed by legacy nodes. Its payload is opaque to this node, since
+/// legacy objects use different encryption and can't be read by native identities.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegacyObject {
+    pub nonce: u64,
+    pub expires: i64,
+    pub object_type: u32,
+    pub version: u64,
+    pub stream: u64,
+    pub payload: Vec<u8>,
+}
+
+impl LegacyObject {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Vec::with_capacity(self.payload.len() + 32);
+        w.extend_from_slice(&self.nonce.to_be_bytes());
+        w.extend_from_slice(&self.expires.to_be_bytes());
+        w.extend_from_slice(&self.object_type.to_be_bytes());
+        write_var_int(&mut w, self.version);
+        write_var_int(&mut w, self.stream);
+        w.extend_from_slice(&self.payload);
+        w
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, WireError> {
+        let mut r = Reader::new(data);
+        let malformed = |_| WireError::Malformed("object");
+        Ok(Self {
+            nonce: r.u64().map_err(malformed)?,
+            expires: r.u64().map_err(malformed)? as i64,
+            object_type: r.u32().map_err(malformed)?,
+            version: r.var_int().map_err(malformed)?,
+            stream: r.var_int().map_err(malformed)?,
+            payload: r.rest().to_vec(),
+        })
+    }
+
+    /// Hash the object is announced by, the first half of double SHA-512
+    /// of the encoded object
+    pub fn inventory_hash(&self) -> InventoryHash {
+        let hash = Sha512::digest(Sha512::digest(self.encode()));
+        hash[..32].try_into().unwrap()
+    }
+
+    /// Check what PyBitmessage checks before relaying the object
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let size = self.encode().len();
+        if size > MAX_OBJECT_SIZE {
+            return Err(ValidationError::TooLarge(size));
+        }
+        validation::validate_expiry(self.expires)
+    }
+
+    /// Check PoW with the network minimum difficulty, the same way PyBitmessage does.
+    /// Unlike native objects, the nonce is always hashed as 8 bytes.
+    pub fn check_pow(&self) -> Result<(), PoWError> {
+        let data = self.encode();
+        let ttl = pow::pow_ttl(self.expires);
+        let length = (data.len() + pow::NETWORK_MIN_EXTRA_BYTES as usize) as u64;
+        let target = BigUint::from(2u32).pow(64)
+            / (BigUint::from(pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE as u64)
+                * BigUint::from(length + ttl * length / (1 << 16)));
+
+        let mut hasher = Sha512::new();
+        hasher.update(&data[..8]);
+        hasher.update(Sha512::digest(&data[8..]));
+        let result_hash = Sha512::digest(hasher.finalize());
+        let trial_value = BigUint::from_bytes_be(&result_hash[..8]);
+        if trial_value > target {
+            return Err(PoWError::InsufficientProofOfWork);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Version(Version),
+    Verack,
+    Inv(Vec<InventoryHash>),
+    GetData(Vec<InventoryHash>),
+    Object(LegacyObject),
+    Ping,
+    Pong,
+    /// Commands the node doesn't handle (e.g. addr), they're ignored
+    Other(String),
+}
+
+impl Message {
+    pub fn command(&self) -> &str {
+        match self {
+            Message::Version(_) => "version",
+            Message::Verack => "verack",
+            Message::Inv(_) => "inv",
+            Message::GetData(_) => "getdata",
+            Message::Object(_) => "object",
+            Message::Ping => "ping",
+            Message::Pong => "pong",
+            Message::Other(c) => c,
+        }
+    }
+
+    /// Serialize the message together with its header
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        match self {
+            Message::Version(v) => {
+                payload.extend_from_slice(&v.version.to_be_bytes());
+                payload.extend_from_slice(&v.services.to_be_bytes());
+                payload.extend_from_slice(&v.timestamp.to_be_bytes());
+                v.addr_recv.encode(&mut payload);
+                v.addr_from.encode(&mut payload);
+                payload.extend_from_slice(&v.nonce.to_be_bytes());
+                write_var_int(&mut payload, v.user_agent.len() as u64);
+                payload.extend_from_slice(v.user_agent.as_bytes());
+                write_var_int(&mut payload, v.streams.len() as u64);
+                for stream in &v.streams {
+                    write_var_int(&mut payload, *stream);
+                }
+            }
+            Message::Inv(hashes) | Message::GetData(hashes) => {
+                write_var_int(&mut payload, hashes.len() as u64);
+                for hash in hashes {
+                    payload.extend_from_slice(hash);
+                }
+            }
+            Message::Object(object) => payload = object.encode(),
+            Message::Verack | Message::Ping | Message::Pong | Message::Other(_) => {}
+        }
+
+        let mut data = Vec::with_capacity(HEADER_LENGTH + payload.len());
+        data.extend_from_slice(&MAGIC.to_be_bytes());
+        let mut command = [0u8; COMMAND_LENGTH];
+        command[..self.command().len()].copy_from_slice(self.command().as_bytes());
+        data.extend_from_slice(&command);
+        data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        data.extend_from_slice(&Sha512::digest(&payload)[..4]);
+        data.extend_from_slice(&payload);
+        data
+    }
+
+    /// Parse the payload of the message with given command
+    pub fn decode(command: &str, payload: &[u8]) -> Result<Self, WireError> {
+        let mut r = Reader::new(payload);
+        let message = match command {
+            "version" => {
+                let malformed = |_| WireError::Malformed("version");
+                let version = r.i32().map_err(malformed)?;
+                let services = r.u64().map_err(malformed)?;
+                let timestamp = r.u64().map_err(malformed)? as i64;
+                let addr_recv = NetAddr::decode(&mut r).map_err(malformed)?;
+                let addr_from = NetAddr::decode(&mut r).map_err(malformed)?;
+                let nonce = r.u64().map_err(malformed)?;
+                let length = r.var_int().map_err(malformed)? as usize;
+                let user_agent =
+                    String::from_utf8_lossy(r.bytes(length).map_err(malformed)?).to_string();
+                let count = r.var_int().map_err(malformed)?;
+                let mut streams = Vec::new();
+                for _ in 0..count.min(160_000) {
+                    streams.push(r.var_int().map_err(malformed)?);
+                }
+                Message::Version(Version {
+                    version,
+                    services,
+                    timestamp,
+                    addr_recv,
+                    addr_from,
+                    nonce,
+                    user_agent,
+                    streams,
+                })
+            }
+            "inv" | "getdata" => {
+                let malformed = |_| WireError::Malformed("inventory");
+                let count = r.var_int().map_err(malformed)? as usize;
+                if count > MAX_INVENTORY_VECTORS {
+                    return Err(WireError::Malformed("inventory"));
+                }
+                let mut hashes = Vec::with_capacity(count);
+                for _ in 0..count {
+                    hashes.push(r.bytes(32).map_err(malformed)?.try_into().unwrap());
+                }
+                if command == "inv" {
+                    Message::Inv(hashes)
+                } else {
+                    Message::GetData(hashes)
+                }
+            }
+            "object" => Message::Object(LegacyObject::decode(payload)?),
+            "verack" => Message::Verack,
+            "ping" => Message::Ping,
+            "pong" => Message::Pong,
+            c => Message::Other(c.to_string()),
+        };
+        Ok(message)
+    }
+}
+
+fn write_var_int(w: &mut Vec<u8>, value: u64) {
+    if value < 0xfd {
+        w.push(value as u8);
+    } else if value <= u16::MAX as u64 {
+        w.push(0xfd);
+        w.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u32::MAX as u64 {
+        w.push(0xfe);
+        w.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        w.push(0xff);
+        w.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], WireError> {
+        if self.data.len() < length {
+            return Err(WireError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    fn u16(&mut self) -> Result<u16, WireError> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, WireError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, WireError> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, WireError> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn var_int(&mut self) -> Result<u64, WireError> {
+        Ok(match self.bytes(1)?[0] {
+            0xfd => self.u16()? as u64,
+            0xfe => self.u32()? as u64,
+            0xff => self.u64()?,
+            v => v as u64,
+        })
+    }
+}
@@ -1,22 +1,83 @@
-use crate::pow::{self, async_pow::AsyncPoW};
+use crate::pow::{self, PoWEngine, PoWError};
 use async_std::task;
 use chrono::Utc;
-use futures::{channel::mpsc, FutureExt, SinkExt};
-use serde::{Deserialize, Serialize};
+use futures::{
+    channel::mpsc,
+    future::{AbortHandle, Abortable},
+    SinkExt,
+};
+use log::debug;
+use num_bigint::BigUint;
+use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_cbor::Value;
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use sha2::Digest;
+use strum::EnumString;
 
 use super::{address::Address, node::pow_worker::ProofOfWorkWorkerCommand};
 
 pub type InventoryVector = Vec<String>;
 
+/// Version of the wire format of network messages, advertised to peers via identify
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Version of nodes which send messages without the envelope
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// Length of the random ack data embedded in msg objects
+pub const ACK_DATA_LENGTH: usize = 32;
+
+/// Maximum number of hashes in a single inv message, so that it fits into
+/// the gossipsub message size limit. Larger inventories are split into pages.
+pub const MAX_INV_HASHES: usize = 500;
+
+/// Maximum number of peer addresses in a single addr message
+pub const MAX_ADDR_PEERS: usize = 100;
+
+/// Position in the inventory ordered by expiration time and hash, from which
+/// the next page of inventory is requested
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InventoryCursor {
+    pub expires: i64,
+    pub hash: String,
+}
+
+/// Address of a peer known to the sender of an addr message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerAddress {
+    pub peer_id: String,
+    /// Address the peer listens on, including its peer id
+    pub multiaddr: String,
+    /// Unix time the sender saw the peer last time
+    pub last_seen: i64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "kind")]
 pub enum ObjectKind {
-    Msg { encrypted: Vec<u8> },
-    Broadcast { tag: Vec<u8>, encrypted: Vec<u8> },
-    Getpubkey { tag: Vec<u8> },
-    Pubkey { tag: Vec<u8>, encrypted: Vec<u8> },
+    Msg {
+        encrypted: Vec<u8>,
+        /// Payload encrypted to the other devices of the recipient. Omitted when there
+        /// are none, so that the object stays the same for nodes unaware of devices.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        copies: Vec<Vec<u8>>,
+    },
+    Broadcast {
+        tag: Vec<u8>,
+        encrypted: Vec<u8>,
+    },
+    Getpubkey {
+        tag: Vec<u8>,
+    },
+    Pubkey {
+        tag: Vec<u8>,
+        encrypted: Vec<u8>,
+    },
+    /// Pubkey of the identity whose keys were rotated, see [`UnencryptedKeyUpdate`]
+    KeyUpdate {
+        tag: Vec<u8>,
+        encrypted: Vec<u8>,
+    },
 }
 
 impl ObjectKind {
@@ -25,7 +86,8 @@ impl ObjectKind {
             ObjectKind::Msg { .. } => 0,
             ObjectKind::Broadcast { .. } => 1,
             ObjectKind::Getpubkey { .. } => 2,
-            ObjectKind::Pubkey { .. } => 3,
+            // key updates are pubkeys to everyone filtering objects by type
+            ObjectKind::Pubkey { .. } | ObjectKind::KeyUpdate { .. } => 3,
         }
     }
 }
@@ -78,51 +140,179 @@ impl Object {
         object
     }
 
-    pub fn do_proof_of_work(mut self, mut worker_sink: mpsc::Sender<ProofOfWorkWorkerCommand>) {
-        let target = pow::get_pow_target(
-            &self,
-            pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE,
-            pow::NETWORK_MIN_EXTRA_BYTES,
-        );
+    pub fn do_proof_of_work(
+        mut self,
+        target: BigUint,
+        engine: &dyn PoWEngine,
+        mut worker_sink: mpsc::Sender<ProofOfWorkWorkerCommand>,
+    ) -> AbortHandle {
+        let result = engine.do_pow(target, self.hash.clone());
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
 
-        task::spawn(async move {
-            AsyncPoW::do_pow(target, self.hash.clone())
-                .then(move |res| async move {
-                    let (_, nonce) = res.unwrap();
+        let pow_task = async move {
+            // aborting drops the result receiver, which stops the engine
+            let res = match Abortable::new(result, abort_registration).await {
+                Ok(res) => res.unwrap_or(Err(PoWError::Cancelled)),
+                Err(_) => Err(PoWError::Cancelled),
+            };
+            let command = match res {
+                Ok((_, nonce)) => {
                     self.nonce = nonce.to_bytes_be();
-                    worker_sink
-                        .send(ProofOfWorkWorkerCommand::NonceCalculated { object: self })
-                        .await
-                        .expect("receiver not to be dropped");
-                })
-                .await;
-        });
+                    ProofOfWorkWorkerCommand::NonceCalculated { object: self }
+                }
+                Err(error) => ProofOfWorkWorkerCommand::PoWFailed {
+                    object: self,
+                    error,
+                },
+            };
+            // the worker ignores results of objects it doesn't run anymore
+            _ = worker_sink.send(command).await;
+        };
+        task::spawn(pow_task);
+        abort_handle
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "kind")]
 pub enum MessagePayload {
-    GetData { inventory: InventoryVector },
-    Inv { inventory: InventoryVector },
-    Objects { objects: Vec<Object> },
+    GetData {
+        inventory: InventoryVector,
+    },
+    Inv {
+        inventory: InventoryVector,
+        /// Expiration times of objects in `inventory`, in the same order.
+        /// Might be empty if peer doesn't provide them.
+        #[serde(default)]
+        expires: Vec<i64>,
+        /// Types of objects in `inventory` (see [`ObjectKind::object_type`]), in the
+        /// same order. Might be empty if peer doesn't provide them.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        types: Vec<u8>,
+        /// Set if the inventory doesn't fit into a single message, the rest of it
+        /// can be requested with `ReqInv` starting after this cursor
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next: Option<InventoryCursor>,
+    },
+    /// Request for the next page of the inventory, the first page is requested
+    /// with an empty payload
+    ReqInv {
+        after: InventoryCursor,
+    },
+    Objects {
+        /// Objects which fail to decode (e.g. of kinds added in newer versions) are skipped
+        #[serde(deserialize_with = "deserialize_known_objects")]
+        objects: Vec<Object>,
+    },
+    /// Sample of the peers known to the sender, so that the node can find peers
+    /// even if the bootstrap ones are down. The receiver replies with its own sample.
+    Addr {
+        peers: Vec<PeerAddress>,
+    },
     None,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+fn deserialize_known_objects<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Object>, D::Error> {
+    let values: Vec<Value> = Vec::deserialize(d)?;
+    Ok(values
+        .into_iter()
+        .filter_map(|v| match serde_cbor::value::from_value(v) {
+            Ok(o) => Some(o),
+            Err(e) => {
+                debug!("Skipping object which failed to decode: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+impl MessagePayload {
+    /// Create inventory payload from object hashes along with their expiration times
+    /// and types
+    pub fn inv(items: Vec<(String, i64, u8)>, next: Option<InventoryCursor>) -> Self {
+        let mut inventory = Vec::with_capacity(items.len());
+        let mut expires = Vec::with_capacity(items.len());
+        let mut types = Vec::with_capacity(items.len());
+        for (hash, e, t) in items {
+            inventory.push(hash);
+            expires.push(e);
+            types.push(t);
+        }
+        MessagePayload::Inv {
+            inventory,
+            expires,
+            types,
+            next,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, EnumString)]
 pub enum MessageCommand {
     GetData,
     Inv,
     ReqInv,
     Objects,
+    Addr,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NetworkMessage {
     pub command: MessageCommand,
     pub payload: MessagePayload,
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum DecodeError {
+    #[error("malformed message: {0}")]
+    Malformed(#[from] serde_cbor::Error),
+    #[error("unknown command {0}")]
+    UnknownCommand(String),
+}
+
+/// Message on the wire along with the version of its format
+#[derive(Serialize, Deserialize)]
+struct Envelope<M> {
+    version: u32,
+    message: M,
+}
+
+impl NetworkMessage {
+    /// Encode the message so that a peer of given protocol version can decode it
+    pub fn encode(&self, version: u32) -> Vec<u8> {
+        let data = if version <= LEGACY_PROTOCOL_VERSION {
+            serde_cbor::to_vec(self)
+        } else {
+            serde_cbor::to_vec(&Envelope {
+                version: PROTOCOL_VERSION,
+                message: self,
+            })
+        };
+        data.expect("message to be serializable")
+    }
+
+    /// Decode the message of any protocol version, returning the version along with it
+    pub fn decode(data: &[u8]) -> Result<(Self, u32), DecodeError> {
+        let value: Value = serde_cbor::from_slice(data)?;
+        let is_envelope =
+            matches!(&value, Value::Map(m) if m.contains_key(&Value::Text("version".to_string())));
+        let (version, message) = if is_envelope {
+            let envelope: Envelope<Value> = serde_cbor::value::from_value(value)?;
+            (envelope.version, envelope.message)
+        } else {
+            (LEGACY_PROTOCOL_VERSION, value)
+        };
+        if let Value::Map(m) = &message {
+            if let Some(Value::Text(command)) = m.get(&Value::Text("command".to_string())) {
+                if command.parse::<MessageCommand>().is_err() {
+                    return Err(DecodeError::UnknownCommand(command.clone()));
+                }
+            }
+        }
+        Ok((serde_cbor::value::from_value(message)?, version))
+    }
+}
+
 #[derive(Serialize_repr, Deserialize_repr, Debug, Clone)]
 #[repr(u8)]
 pub enum MsgEncoding {
@@ -141,6 +331,34 @@ pub struct UnencryptedMsg {
     pub message: Vec<u8>,
     pub public_signing_key: Vec<u8>,
     pub public_encryption_key: Vec<u8>,
+    /// Random data, which recipient sends back as a msg object to acknowledge
+    /// the delivery. Empty if sender doesn't want an acknowledgement.
+    #[serde(default)]
+    pub ack_data: Vec<u8>,
+}
+
+impl UnencryptedMsg {
+    pub fn generate_ack_data() -> Vec<u8> {
+        let mut ack_data = vec![0u8; ACK_DATA_LENGTH];
+        rand::thread_rng().fill_bytes(&mut ack_data);
+        ack_data
+    }
+
+    /// Create acknowledgement object for this message, which needs PoW before sending.
+    /// To other nodes it's indistinguishable from a regular msg object which they can't decrypt.
+    pub fn ack_object(&self, expires: i64) -> Option<Object> {
+        if self.ack_data.is_empty() {
+            return None;
+        }
+        Some(Object::new(
+            expires,
+            Vec::new(),
+            ObjectKind::Msg {
+                encrypted: self.ack_data.clone(),
+                copies: Vec::new(),
+            },
+        ))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -148,4 +366,94 @@ pub struct UnencryptedPubkey {
     pub behaviour_bitfield: u32, // TODO currently unused
     pub public_signing_key: Vec<u8>,
     pub public_encryption_key: Vec<u8>,
+    /// PoW difficulty the owner requires for messages sent to it.
+    /// Zero (e.g. if omitted by older clients) means network minimum.
+    #[serde(default)]
+    pub nonce_trials_per_byte: i32,
+    #[serde(default)]
+    pub extra_bytes: i32,
+    /// PoW difficulty the owner requires for messages from senders which aren't
+    /// among its contacts. Zero means the same as above.
+    #[serde(default)]
+    pub stranger_nonce_trials_per_byte: i32,
+    #[serde(default)]
+    pub stranger_extra_bytes: i32,
+    /// Encryption keys of the other devices of the owner, which get their own copy
+    /// of messages sent to it
+    #[serde(default)]
+    pub device_keys: Vec<Vec<u8>>,
+}
+
+/// Pubkey of the identity whose keys were rotated. The new keys don't hash to the
+/// address, so they're endorsed by the keys they replace, all the way back
+/// to the original keys of the address.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnencryptedKeyUpdate {
+    pub public_signing_key: Vec<u8>,
+    pub public_encryption_key: Vec<u8>,
+    /// Keys the address had before, the original ones first
+    pub chain: Vec<KeyEndorsement>,
+    pub nonce_trials_per_byte: i32,
+    pub extra_bytes: i32,
+    #[serde(default)]
+    pub stranger_nonce_trials_per_byte: i32,
+    #[serde(default)]
+    pub stranger_extra_bytes: i32,
+    pub device_keys: Vec<Vec<u8>>,
+}
+
+/// Former keys of the address along with the signature of the keys which replaced them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyEndorsement {
+    pub public_signing_key: Vec<u8>,
+    pub public_encryption_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl KeyEndorsement {
+    /// Sign the next keys of the identity with its current signing key
+    pub fn sign(
+        identity: &Address,
+        next_signing_key: &ecies::PublicKey,
+        next_encryption_key: &ecies::PublicKey,
+    ) -> Self {
+        let key =
+            libsecp256k1::SecretKey::parse(&identity.private_signing_key.unwrap().serialize())
+                .unwrap();
+        let (signature, _) = libsecp256k1::sign(
+            &endorsed_keys_hash(
+                &next_signing_key.serialize(),
+                &next_encryption_key.serialize(),
+            ),
+            &key,
+        );
+        Self {
+            public_signing_key: identity.public_signing_key.unwrap().serialize().to_vec(),
+            public_encryption_key: identity.public_encryption_key.unwrap().serialize().to_vec(),
+            signature: signature.serialize().to_vec(),
+        }
+    }
+
+    /// Check that the next keys are signed with the signing key of the endorsement
+    pub fn endorses(&self, next_signing_key: &[u8], next_encryption_key: &[u8]) -> bool {
+        let Ok(key) = libsecp256k1::PublicKey::parse_slice(&self.public_signing_key, None) else {
+            return false;
+        };
+        let Ok(signature) = libsecp256k1::Signature::parse_standard_slice(&self.signature) else {
+            return false;
+        };
+        libsecp256k1::verify(
+            &endorsed_keys_hash(next_signing_key, next_encryption_key),
+            &signature,
+            &key,
+        )
+    }
+}
+
+fn endorsed_keys_hash(signing_key: &[u8], encryption_key: &[u8]) -> libsecp256k1::Message {
+    let hash = sha2::Sha256::new()
+        .chain_update(signing_key)
+        .chain_update(encryption_key)
+        .finalize();
+    libsecp256k1::Message::parse_slice(&hash).unwrap()
 }
@@ -1,4 +1,68 @@
+use std::error::Error;
+
+/// How many times a repository call failing with a transient error is made by [`retry_db`]
+const DB_RETRIES: u32 = 4;
+
+/// Make the repository call again while it fails with a transient error, i.e. the
+/// database is busy or locked. SQLite already waits for locks held by other
+/// connections off the event loop (see its busy timeout), so the call is retried
+/// right away, only letting other tasks run in between. Evaluates to the result
+/// of the last attempt, the error is a string so that it can be held across awaits.
+macro_rules! retry_db {
+    ($call:expr) => {{
+        let mut attempt = 1;
+        loop {
+            let error = match $call.await {
+                Ok(v) => break Ok(v),
+                Err(e) if $crate::network::node::is_transient(&*e) => e.to_string(),
+                Err(e) => break Err(e.to_string()),
+            };
+            if attempt == $crate::network::node::DB_RETRIES {
+                break Err(error);
+            }
+            log::warn!("Database call failed, retrying: {}", error);
+            async_std::task::yield_now().await;
+            attempt += 1;
+        }
+    }};
+}
+
+/// Check if the repository call might succeed when it's made again, e.g. errors
+/// of constraints or decoding never go away
+pub(crate) fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    #[cfg(feature = "sqlite")]
+    if let Some(e) = error.downcast_ref::<sqlx::Error>() {
+        const SQLITE_BUSY: i32 = 5;
+        const SQLITE_LOCKED: i32 = 6;
+        return match e {
+            // writer connection is held by a long write
+            sqlx::Error::PoolTimedOut => true,
+            // extended codes (e.g. SQLITE_BUSY_SNAPSHOT) keep the primary one in the low byte
+            sqlx::Error::Database(e) => e
+                .code()
+                .and_then(|code| code.parse::<i32>().ok())
+                .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED)),
+            _ => false,
+        };
+    }
+    #[cfg(not(feature = "sqlite"))]
+    let _ = error;
+    false
+}
+
+pub(crate) mod announcements;
 pub mod client;
-pub mod handler;
-pub mod pow_worker;
+pub mod command_queue;
+mod commands;
+pub mod data_dir_lock;
+pub(crate) mod handler;
+pub(crate) mod pow_worker;
+pub mod protocol;
+pub(crate) mod pubkeys;
+pub mod rate_limit;
+mod sending;
+#[cfg(feature = "sqlite")]
+pub(crate) mod storage;
 pub mod worker;
+
+pub use pow_worker::{PoWEstimate, PoWQueueItem};
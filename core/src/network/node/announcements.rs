@@ -0,0 +1,62 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// Hashes of objects announced recently, so that the same object isn't announced
+/// again within the window, e.g. when it's relayed by several peers at once.
+/// The oldest hashes are forgotten once there are more than `capacity` of them.
+pub(crate) struct RecentAnnouncements {
+    window: Duration,
+    capacity: usize,
+    announced_at: HashMap<String, Instant>,
+    /// Hashes in the order they were announced in, the oldest first
+    order: VecDeque<(String, Instant)>,
+}
+
+impl RecentAnnouncements {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            announced_at: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Remember the hash as announced now. Returns `false` if it was already
+    /// announced within the window, so it shouldn't be announced again.
+    pub fn insert(&mut self, hash: &str, now: Instant) -> bool {
+        if self
+            .announced_at
+            .get(hash)
+            .is_some_and(|at| now.duration_since(*at) < self.window)
+        {
+            return false;
+        }
+        self.announced_at.insert(hash.to_string(), now);
+        self.order.push_back((hash.to_string(), now));
+        self.evict(now);
+        true
+    }
+
+    /// Forget the hash, so that it can be announced again right away
+    pub fn forget(&mut self, hash: &str) {
+        self.announced_at.remove(hash);
+    }
+
+    /// Forget hashes whose window has passed and the oldest ones beyond the capacity
+    fn evict(&mut self, now: Instant) {
+        while let Some((_, at)) = self.order.front() {
+            if now.duration_since(*at) < self.window && self.announced_at.len() <= self.capacity {
+                break;
+            }
+            let (hash, at) = self.order.pop_front().expect("front exists");
+            // the hash might have been announced again after the window, then
+            // there's a newer entry of it further in the queue
+            if self.announced_at.get(&hash) == Some(&at) {
+                self.announced_at.remove(&hash);
+            }
+        }
+    }
+}
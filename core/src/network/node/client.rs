@@ -1,156 +1,1066 @@
 use emailmessage::{header, Message, SinglePart};
-use std::error::Error;
+use std::{error::Error, time::Duration};
 
-use chrono::Utc;
-use futures::{
-    channel::{mpsc, oneshot},
-    SinkExt,
-};
+use async_std::future;
+use chrono::{DateTime, Utc};
+use futures::channel::{mpsc, oneshot};
 use libp2p::{Multiaddr, PeerId};
 
 use crate::{
-    network::address::Address,
-    repositories::sqlite::models::{self, MessageStatus},
+    config::Config,
+    network::{address::Address, messages::InventoryCursor},
+    repositories::{
+        inventory::{InventoryItem, InventoryStats},
+        message::{FolderStats, Page},
+        sqlite::models::{self, MessageStatus},
+    },
+};
+
+use super::{
+    command_queue::{CommandQueueStats, CommandSender},
+    pow_worker::{PoWEstimate, PoWQueueItem},
+    rate_limit::TrafficStats,
+    worker::{
+        Avatar, DhtStatus, Folder, KeyMismatchEvent, MessageStatusEvent, NodeMetrics, PowerMode,
+        PubkeyRequest, WorkerCommand,
+    },
 };
 
-use super::worker::{Folder, WorkerCommand};
+#[cfg(feature = "sqlite")]
+use crate::import::ImportProgress;
+#[cfg(feature = "sqlite")]
+use std::path::PathBuf;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    #[error("node is busy, too many commands are waiting")]
+    Busy,
+    #[error("node didn't respond in time")]
+    Timeout,
+    #[error("node is stopped")]
+    Stopped,
+    /// Node failed to handle the command, e.g. its database failed
+    #[error("{0}")]
+    Node(String),
+}
 
+impl From<Box<dyn Error + Send + Sync>> for ClientError {
+    fn from(e: Box<dyn Error + Send + Sync>) -> Self {
+        Self::Node(e.to_string())
+    }
+}
+
+#[derive(Clone)]
 pub struct NodeClient {
-    sender: mpsc::Sender<WorkerCommand>,
+    sender: CommandSender,
+    /// Max amount of time to wait for the reply of the node
+    timeout: Duration,
 }
 
 impl NodeClient {
-    pub fn new(sender: mpsc::Sender<WorkerCommand>) -> Self {
-        Self { sender }
+    pub(crate) fn new(sender: CommandSender, timeout: Duration) -> Self {
+        Self { sender, timeout }
+    }
+
+    /// Send the command and wait for the reply, without blocking if the node is busy
+    /// or stuck
+    async fn request<T>(
+        &mut self,
+        command: impl FnOnce(oneshot::Sender<T>) -> WorkerCommand,
+    ) -> Result<T, ClientError> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender.try_send(command(sender))?;
+        match future::timeout(self.timeout, receiver).await {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(_)) => Err(ClientError::Stopped),
+            Err(_) => Err(ClientError::Timeout),
+        }
+    }
+
+    /// Metrics of the command queue, available even if the node is stuck
+    pub fn command_queue_stats(&self) -> CommandQueueStats {
+        self.sender.stats()
     }
 
     pub async fn start_listening(
         &mut self,
         multiaddr: Multiaddr,
     ) -> Result<(), Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(WorkerCommand::StartListening { multiaddr, sender })
+        self.request(|sender| WorkerCommand::StartListening { multiaddr, sender })
             .await
-            .expect("Command receiver not to be dropped");
-        receiver.await.expect("Sender not to be dropped")
+            .unwrap_or_else(|e| Err(Box::new(e)))
     }
 
-    pub async fn get_listeners(&mut self) -> Multiaddr {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(WorkerCommand::GetListenerAddress { sender })
+    /// Connect to the peer. If the address contains peer id, waits until the connection
+    /// is established or failed.
+    pub async fn dial(&mut self, peer: Multiaddr) -> Result<(), Box<dyn Error + Send>> {
+        self.request(|sender| WorkerCommand::Dial { peer, sender })
             .await
-            .expect("Command receiver not to be dropped");
-        receiver.await.expect("Sender not to be dropped")
+            .unwrap_or_else(|e| Err(Box::new(e)))
     }
 
-    pub async fn get_peer_id(&mut self) -> PeerId {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(WorkerCommand::GetPeerID { sender })
+    /// Get all active listen addresses, waits until the node listens on at least one
+    pub async fn get_listeners(&mut self) -> Result<Vec<Multiaddr>, ClientError> {
+        self.request(|sender| WorkerCommand::GetListenerAddress { sender })
             .await
-            .expect("Command receiver not to be dropped");
-        receiver.await.expect("Sender not to be dropped")
     }
 
-    pub fn shutdown(&mut self) {
-        self.sender.close_channel();
+    pub async fn get_peer_id(&mut self) -> Result<PeerId, ClientError> {
+        self.request(|sender| WorkerCommand::GetPeerID { sender })
+            .await
     }
 
-    pub async fn get_own_identities(&mut self) -> Vec<Address> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(WorkerCommand::GetOwnIdentities { sender })
+    pub async fn get_connected_peers(&mut self) -> Result<Vec<PeerId>, ClientError> {
+        self.request(|sender| WorkerCommand::GetConnectedPeers { sender })
             .await
-            .expect("Receiver not to be dropped");
-        receiver.await.expect("Sender not to be dropped").unwrap()
     }
 
-    pub async fn generate_new_identity(&mut self, label: String) -> String {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(WorkerCommand::GenerateIdentity { label, sender })
+    /// Ban peer id or multiaddr (or its prefix, e.g. `/ip4/1.2.3.4`),
+    /// disconnecting matching peers
+    pub async fn ban_peer(
+        &mut self,
+        target: String,
+        reason: Option<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::BanPeer {
+            target,
+            reason,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn unban_peer(&mut self, target: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::UnbanPeer { target, sender })
             .await
-            .expect("Receiver not to be dropped");
-        receiver
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn list_banned(&mut self) -> Result<Vec<models::BannedPeer>, ClientError> {
+        Ok(self
+            .request(|sender| WorkerCommand::GetBannedPeers { sender })
+            .await??)
+    }
+
+    /// Traffic counters of the node since start
+    pub async fn get_traffic_stats(&mut self) -> Result<TrafficStats, ClientError> {
+        self.request(|sender| WorkerCommand::GetTrafficStats { sender })
             .await
-            .expect("Sender not to be dropped")
-            .expect("repo not to fail")
     }
 
-    pub async fn delete_identity(&mut self, address: String) {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(WorkerCommand::DeleteIdentity { address, sender })
+    /// Routing table of the DHT and the result of the latest bootstrap, e.g. to tell
+    /// why no peers are discovered
+    pub async fn get_dht_status(&mut self) -> Result<DhtStatus, ClientError> {
+        self.request(|sender| WorkerCommand::GetDhtStatus { sender })
             .await
-            .expect("Receiver not to be dropped");
-        receiver
+    }
+
+    /// Size of the inventory and its limit
+    pub async fn get_inventory_stats(
+        &mut self,
+    ) -> Result<InventoryStats, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetInventoryStats { sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Page of stored objects ordered by expiration time, the next page starts after
+    /// the last object of the previous one
+    pub async fn get_inventory(
+        &mut self,
+        after: Option<InventoryCursor>,
+        limit: usize,
+    ) -> Result<Vec<InventoryItem>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetInventory {
+            after,
+            limit,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Config the node is running with, including changes made with [`Self::update_config`]
+    pub async fn get_config(&mut self) -> Result<Config, ClientError> {
+        self.request(|sender| WorkerCommand::GetConfig { sender })
+            .await
+    }
+
+    /// Apply the config and save it to the data dir, returns keys of the values which
+    /// are only applied after restart
+    pub async fn update_config(
+        &mut self,
+        config: Config,
+    ) -> Result<Vec<&'static str>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::UpdateConfig { config, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Counters and gauges of the node, e.g. for monitoring
+    pub async fn get_metrics(&mut self) -> Result<NodeMetrics, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetMetrics { sender })
             .await
-            .expect("Sender not to be dropped")
-            .expect("repo not to fail")
+            .unwrap_or_else(|e| Err(Box::new(e)))
     }
 
-    pub async fn rename_identity(&mut self, address: String, new_label: String) {
+    /// Pause PoW and reduce network activity, e.g. while running on battery, or
+    /// switch back to normal operation
+    pub async fn set_power_mode(&mut self, mode: PowerMode) -> Result<(), ClientError> {
+        self.request(|sender| WorkerCommand::SetPowerMode { mode, sender })
+            .await
+    }
+
+    pub async fn get_power_mode(&mut self) -> Result<PowerMode, ClientError> {
+        self.request(|sender| WorkerCommand::GetPowerMode { sender })
+            .await
+    }
+
+    /// Stop the node. Resolves once pending PoW and broadcasts are persisted, so that
+    /// they're resumed on the next start. It waits for a free slot if the queue is full,
+    /// but not longer than for replies to other commands. Shutdown itself isn't timed
+    /// out, since running PoW has to be drained first.
+    pub async fn shutdown(&mut self) -> Result<(), ClientError> {
         let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(WorkerCommand::RenameIdentity {
+        let queued = self.sender.send(WorkerCommand::Shutdown { sender });
+        match future::timeout(self.timeout, queued).await {
+            Ok(Ok(_)) => {}
+            // node might be already stopped
+            Ok(Err(ClientError::Stopped)) => return Ok(()),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Err(ClientError::Timeout),
+        }
+        // node might stop without replying, e.g. if its command channel was closed
+        _ = receiver.await;
+        Ok(())
+    }
+
+    pub async fn get_own_identities(&mut self) -> Result<Vec<Address>, ClientError> {
+        Ok(self
+            .request(|sender| WorkerCommand::GetOwnIdentities { sender })
+            .await??)
+    }
+
+    /// Fails on relay nodes, which can't have identities
+    pub async fn generate_new_identity(
+        &mut self,
+        label: String,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GenerateIdentity { label, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Create identity with keys derived from the passphrase, so that the same identity
+    /// can be recreated on another machine
+    pub async fn generate_deterministic_identity(
+        &mut self,
+        passphrase: String,
+        label: String,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GenerateDeterministicIdentity {
+            passphrase,
+            label,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Join chan, i.e. identity shared by everyone who knows its name, returns its
+    /// address. Leaving the chan is deleting the identity.
+    pub async fn join_chan(
+        &mut self,
+        name: String,
+        address: Option<String>,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::JoinChan {
+            name,
+            address,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Export identities with private keys in PyBitmessage `keys.dat` format
+    pub async fn export_identities(
+        &mut self,
+        addresses: Vec<String>,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::ExportIdentities { addresses, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Import identities from `keys.dat` data, returns addresses of the new identities.
+    /// Already existing identities are skipped.
+    pub async fn import_identities(
+        &mut self,
+        data: String,
+    ) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::ImportIdentities { data, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Generate encryption key of this device for the identity kept on another device,
+    /// returns the code which pairs it there. Once paired, this device receives
+    /// copies of messages sent to the identity.
+    pub async fn create_device_key(
+        &mut self,
+        address: String,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::CreateDeviceKey { address, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Add device with the pairing code (see [`Self::create_device_key`]) to its identity.
+    /// Contacts learn about it from the new pubkey of the identity.
+    pub async fn pair_device(
+        &mut self,
+        code: String,
+        label: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::PairDevice {
+            code,
+            label,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Get devices of the address, the key of this device has its private part
+    pub async fn get_device_keys(
+        &mut self,
+        address: String,
+    ) -> Result<Vec<models::DeviceKey>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetDeviceKeys { address, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Remove device (its key encoded as base58) from the address
+    pub async fn remove_device_key(
+        &mut self,
+        address: String,
+        public_key: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::RemoveDeviceKey {
+            address,
+            public_key,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Set image shown as the avatar of the identity or the contact, `None` brings
+    /// back the identicon
+    pub async fn set_avatar(
+        &mut self,
+        address: String,
+        avatar: Option<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::SetAvatar {
+            address,
+            avatar,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Avatar of any address, so that it's shown the same everywhere
+    pub async fn get_avatar(
+        &mut self,
+        address: String,
+    ) -> Result<Avatar, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetAvatar { address, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Set PoW difficulty required for messages sent to the identity. Contacts learn
+    /// it from the pubkey object of the identity.
+    pub async fn set_identity_pow_difficulty(
+        &mut self,
+        address: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::SetIdentityPoWDifficulty {
+            address,
+            nonce_trials_per_byte,
+            extra_bytes,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Require more PoW from senders which aren't contacts of the identity. Their messages
+    /// which don't meet it are put into Quarantine if `quarantine` is set, otherwise
+    /// they're dropped. Senders learn the difficulty from the pubkey object of the identity.
+    pub async fn set_stranger_policy(
+        &mut self,
+        address: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+        quarantine: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::SetStrangerPolicy {
+            address,
+            nonce_trials_per_byte,
+            extra_bytes,
+            quarantine,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn delete_identity(&mut self, address: String) -> Result<(), ClientError> {
+        self.request(|sender| WorkerCommand::DeleteIdentity { address, sender })
+            .await??;
+        Ok(())
+    }
+
+    /// Delete several identities at once, either all of them or none
+    pub async fn delete_identities(
+        &mut self,
+        addresses: Vec<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::DeleteIdentities { addresses, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Forget pinned keys of the contact, so that the next received pubkey is accepted,
+    /// e.g. after it was rejected with [`KeyMismatchEvent`]
+    pub async fn repin_contact(&mut self, address: String) -> Result<(), ClientError> {
+        self.request(|sender| WorkerCommand::RepinContact { address, sender })
+            .await??;
+        Ok(())
+    }
+
+    pub async fn get_contacts(&mut self) -> Result<Vec<Address>, ClientError> {
+        Ok(self
+            .request(|sender| WorkerCommand::GetContacts { sender })
+            .await??)
+    }
+
+    /// Find the contact with the given address (e.g. sender of a message), `None`
+    /// if it's unknown or it's our own identity
+    pub async fn resolve_contact(
+        &mut self,
+        address: String,
+    ) -> Result<Option<Address>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::ResolveContact { address, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Find the address of the recipient typed by the user, i.e. an address or a label
+    /// of a contact or an identity (e.g. a chan)
+    pub async fn resolve_recipient(
+        &mut self,
+        input: String,
+    ) -> Result<Address, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::ResolveRecipient { input, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn add_contact(
+        &mut self,
+        address: String,
+        label: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::AddContact {
+            address,
+            label,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn rename_contact(
+        &mut self,
+        address: String,
+        new_label: String,
+    ) -> Result<(), ClientError> {
+        self.request(|sender| WorkerCommand::RenameContact {
+            address,
+            new_label,
+            sender,
+        })
+        .await??;
+        Ok(())
+    }
+
+    pub async fn delete_contact(&mut self, address: String) -> Result<(), ClientError> {
+        self.request(|sender| WorkerCommand::DeleteContact { address, sender })
+            .await??;
+        Ok(())
+    }
+
+    /// Estimate difficulty and duration of PoW of a message with the body of `msg_size`
+    /// bytes, based on the benchmark of the PoW engine. `ttl` defaults to TTL of
+    /// outgoing messages.
+    pub async fn estimate_pow(
+        &mut self,
+        msg_size: usize,
+        ttl: Option<chrono::Duration>,
+    ) -> Result<PoWEstimate, ClientError> {
+        self.request(|sender| WorkerCommand::EstimatePoW {
+            msg_size,
+            ttl,
+            sender,
+        })
+        .await
+    }
+
+    /// Get objects waiting for PoW, the one being processed goes first
+    pub async fn get_pow_queue(&mut self) -> Result<Vec<PoWQueueItem>, ClientError> {
+        self.request(|sender| WorkerCommand::GetPoWQueue { sender })
+            .await
+    }
+
+    /// Stop sending the message, unless it was already broadcast. Unlike [`Self::cancel_pow`],
+    /// the message is kept with `Cancelled` status.
+    pub async fn cancel_send(&mut self, hash: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::CancelSend { hash, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Cancel PoW of the object. If it's a message, the message is deleted.
+    pub async fn cancel_pow(&mut self, hash: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::CancelPoW { hash, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn retry_message(&mut self, hash: String) -> Result<(), ClientError> {
+        self.request(|sender| WorkerCommand::RetryMessage { hash, sender })
+            .await??;
+        Ok(())
+    }
+
+    /// Announce the sent object to peers again, as long as it hasn't expired
+    pub async fn rebroadcast_object(
+        &mut self,
+        hash: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::RebroadcastObject { hash, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Request the pubkey the message is waiting for now instead of waiting until
+    /// the previous request expires
+    pub async fn retry_pubkey_request(
+        &mut self,
+        hash: String,
+    ) -> Result<PubkeyRequest, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::RetryPubkeyRequest { hash, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Since when and how many times the pubkey the message is waiting for was requested
+    pub async fn get_pubkey_request(
+        &mut self,
+        hash: String,
+    ) -> Result<Option<PubkeyRequest>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetPubkeyRequest { hash, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Move message to Trash
+    pub async fn delete_message(&mut self, hash: String) -> Result<(), ClientError> {
+        self.request(|sender| WorkerCommand::DeleteMessage { hash, sender })
+            .await??;
+        Ok(())
+    }
+
+    /// Move message from Trash back to its original folder, or from Quarantine to Inbox
+    pub async fn restore_message(&mut self, hash: String) -> Result<(), ClientError> {
+        self.request(|sender| WorkerCommand::RestoreMessage { hash, sender })
+            .await??;
+        Ok(())
+    }
+
+    /// Mark message as read or unread
+    pub async fn mark_read(
+        &mut self,
+        hash: String,
+        read: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::MarkRead { hash, read, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Count all and unread messages in the folders of the identity
+    pub async fn get_folder_stats(
+        &mut self,
+        address: String,
+    ) -> Result<FolderStats, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetFolderStats { address, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn rename_identity(
+        &mut self,
+        address: String,
+        new_label: String,
+    ) -> Result<(), ClientError> {
+        self.request(|sender| WorkerCommand::RenameIdentity {
+            new_label,
+            address,
+            sender,
+        })
+        .await??;
+        Ok(())
+    }
+
+    pub async fn rotate_identity(
+        &mut self,
+        old_address: String,
+        new_label: Option<String>,
+        archive_old: bool,
+    ) -> Result<String, ClientError> {
+        Ok(self
+            .request(|sender| WorkerCommand::RotateIdentity {
+                old_address,
                 new_label,
-                address,
+                archive_old,
                 sender,
             })
+            .await??)
+    }
+
+    /// Replace keys of the identity keeping its address, contacts get the new keys
+    /// endorsed by the former ones
+    pub async fn rotate_keys(
+        &mut self,
+        address: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::RotateKeys { address, sender })
             .await
-            .expect("Receiver not to be dropped");
-        receiver
-            .await
-            .expect("Sender not to be dropped")
-            .expect("repo not to fail")
+            .unwrap_or_else(|e| Err(Box::new(e)))
     }
 
-    pub async fn get_messages(&mut self, address: String, folder: Folder) -> Vec<models::Message> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(WorkerCommand::GetMessages {
+    pub async fn get_messages(
+        &mut self,
+        address: String,
+        folder: Folder,
+    ) -> Result<Vec<models::Message>, ClientError> {
+        Ok(self
+            .request(|sender| WorkerCommand::GetMessages {
                 address,
                 folder,
+                page: None,
                 sender,
             })
-            .await
-            .expect("Receiver not to be dropped");
-        receiver
-            .await
-            .expect("Sender not to be dropped")
-            .expect("repo not to fail")
+            .await??)
     }
 
-    pub async fn send_message(&mut self, from: String, to: String, title: String, body: String) {
-        let (sender, receiver) = oneshot::channel();
-        let m: Message<SinglePart<&str>> = Message::builder().subject(title).mime_body(
-            SinglePart::builder()
-                .header(header::ContentType(
-                    "text/plain; charset=utf8".parse().unwrap(),
-                ))
-                .header(header::ContentTransferEncoding::QuotedPrintable)
-                .body(&body),
-        );
-        let data = m.to_string().into_bytes();
-        let msg = models::Message {
-            hash: "".to_string(),
-            sender: from.clone(),
-            recipient: to,
-            created_at: Utc::now(),
-            status: MessageStatus::Unknown.to_string(),
-            signature: Vec::new(),
+    /// Get at most `limit` messages of the folder, skipping `offset` newest ones
+    pub async fn get_messages_page(
+        &mut self,
+        address: String,
+        folder: Folder,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<models::Message>, ClientError> {
+        Ok(self
+            .request(|sender| WorkerCommand::GetMessages {
+                address,
+                folder,
+                page: Some(Page { offset, limit }),
+                sender,
+            })
+            .await??)
+    }
+
+    /// Full-text search over subjects and bodies of the messages in the folder
+    pub async fn search_messages(
+        &mut self,
+        query: String,
+        address: String,
+        folder: Folder,
+    ) -> Result<Vec<models::Message>, ClientError> {
+        Ok(self
+            .request(|sender| WorkerCommand::SearchMessages {
+                query,
+                address,
+                folder,
+                sender,
+            })
+            .await??)
+    }
+
+    /// Export messages as mbox file, or a single message as `.eml` file
+    pub async fn export_messages(
+        &mut self,
+        hashes: Vec<String>,
+        mbox: bool,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::ExportMessages {
+            hashes,
+            mbox,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Import messages from `.eml` or mbox file into Inbox or Sent of the identity,
+    /// returns the number of imported messages. Messages which don't belong to the
+    /// identity and already existing ones are skipped.
+    pub async fn import_messages(
+        &mut self,
+        address: String,
+        folder: Folder,
+        data: Vec<u8>,
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::ImportMessages {
+            address,
+            folder,
             data,
-        };
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
 
+    /// Import identities, address book, subscriptions and messages from PyBitmessage
+    /// data dir with `keys.dat` and `messages.dat`. Progress is reported by the receiver,
+    /// ending with [`ImportProgress::Finished`]. Already existing items are skipped.
+    #[cfg(feature = "sqlite")]
+    pub async fn import_pybitmessage(
+        &mut self,
+        dir: PathBuf,
+    ) -> Result<mpsc::UnboundedReceiver<ImportProgress>, ClientError> {
+        let (progress, receiver) = mpsc::unbounded();
         self.sender
-            .send(WorkerCommand::SendMessage { msg, from, sender })
+            .try_send(WorkerCommand::ImportPyBitmessage { dir, progress })?;
+        Ok(receiver)
+    }
+
+    /// Send message to each of the recipients, every recipient gets its own copy
+    /// of the message with independent status. Returns hashes of the copies, which
+    /// are reported by [`NodeClient::subscribe_message_status`] as they progress
+    pub async fn send_message(
+        &mut self,
+        from: String,
+        to: Vec<String>,
+        title: String,
+        body: String,
+    ) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        self.send(None, from, to, title, body).await
+    }
+
+    /// Send message composed from the draft. The draft is removed once every copy of
+    /// the message is sent, copies which can't be sent are stored as failed and the
+    /// draft is kept.
+    pub async fn send_draft(
+        &mut self,
+        hash: String,
+        from: String,
+        to: Vec<String>,
+        title: String,
+        body: String,
+    ) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        self.send(Some(hash), from, to, title, body).await
+    }
+
+    /// Send message to each of the recipients once `send_at` comes. Until then the
+    /// copies are kept with `Scheduled` status and can be cancelled.
+    pub async fn schedule_message(
+        &mut self,
+        draft_hash: Option<String>,
+        from: String,
+        to: Vec<String>,
+        title: String,
+        body: String,
+        send_at: DateTime<Utc>,
+    ) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let msg = compose_message(from.clone(), String::new(), title, body);
+        self.request(|sender| WorkerCommand::ScheduleMessage {
+            msg,
+            from,
+            to,
+            draft_hash,
+            send_at,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    async fn send(
+        &mut self,
+        draft_hash: Option<String>,
+        from: String,
+        to: Vec<String>,
+        title: String,
+        body: String,
+    ) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        // recipient is set by the worker for each copy of the message
+        let msg = compose_message(from.clone(), String::new(), title, body);
+        self.request(|sender| WorkerCommand::SendMessage {
+            msg,
+            from,
+            to,
+            draft_hash,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Send message to everyone subscribed to the identity. It's listed in Sent of the
+    /// identity with [`BROADCAST_RECIPIENT`](super::worker::BROADCAST_RECIPIENT) as its recipient.
+    pub async fn send_broadcast(
+        &mut self,
+        from: String,
+        title: String,
+        body: String,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let msg = compose_message(from.clone(), String::new(), title, body);
+        self.request(|sender| WorkerCommand::SendBroadcast { msg, from, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Receive broadcasts of the address, they're listed in [`Folder::Inbox`] of the address
+    pub async fn add_subscription(
+        &mut self,
+        address: String,
+        label: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::AddSubscription {
+            address,
+            label,
+            sender,
+        })
+        .await
+        .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Stop receiving broadcasts of the address, the received ones are kept
+    pub async fn remove_subscription(
+        &mut self,
+        address: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::RemoveSubscription { address, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn get_subscriptions(
+        &mut self,
+    ) -> Result<Vec<models::Subscription>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetSubscriptions { sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Reply automatically to messages received by the identity, replacing its existing
+    /// auto-reply. Each sender gets at most one reply within the interval of the auto-reply.
+    pub async fn set_auto_reply(
+        &mut self,
+        auto_reply: models::AutoReply,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::SetAutoReply { auto_reply, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn remove_auto_reply(
+        &mut self,
+        address: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::RemoveAutoReply { address, sender })
             .await
-            .expect("Receiver not to be dropped");
-        receiver
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn get_auto_reply(
+        &mut self,
+        address: String,
+    ) -> Result<Option<models::AutoReply>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetAutoReply { address, sender })
             .await
-            .expect("Sender not to be dropped")
-            .expect("repo not to fail")
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Get filtering rules applied to received messages, in the order they're applied
+    pub async fn get_filter_rules(
+        &mut self,
+    ) -> Result<Vec<models::FilterRule>, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::GetFilterRules { sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Add filtering rule applied after the existing ones. Its id and creation time
+    /// are ignored, returns the id the rule got.
+    pub async fn add_filter_rule(
+        &mut self,
+        rule: models::FilterRule,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::AddFilterRule { rule, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Replace conditions and actions of the rule with the same id
+    pub async fn update_filter_rule(
+        &mut self,
+        rule: models::FilterRule,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::UpdateFilterRule { rule, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    pub async fn remove_filter_rule(
+        &mut self,
+        id: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request(|sender| WorkerCommand::RemoveFilterRule { id, sender })
+            .await
+            .unwrap_or_else(|e| Err(Box::new(e)))
+    }
+
+    /// Receive status changes of outgoing messages, until the receiver is dropped
+    pub async fn subscribe_message_status(
+        &mut self,
+    ) -> Result<mpsc::UnboundedReceiver<MessageStatusEvent>, ClientError> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.sender
+            .try_send(WorkerCommand::SubscribeMessageStatus { sender })?;
+        Ok(receiver)
+    }
+
+    /// Get pubkeys rejected for pinned contacts, since their keys have changed
+    pub async fn subscribe_key_mismatch(
+        &mut self,
+    ) -> Result<mpsc::UnboundedReceiver<KeyMismatchEvent>, ClientError> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.sender
+            .try_send(WorkerCommand::SubscribeKeyMismatch { sender })?;
+        Ok(receiver)
+    }
+
+    /// Receive whether there are peers to broadcast objects to, starting with the
+    /// current state. Objects are broadcast once they appear, until the receiver is dropped.
+    pub async fn subscribe_connectivity(
+        &mut self,
+    ) -> Result<mpsc::UnboundedReceiver<bool>, ClientError> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.sender
+            .try_send(WorkerCommand::SubscribeConnectivity { sender })?;
+        Ok(receiver)
+    }
+
+    /// Save unsent message as a draft, returns hash of the draft
+    pub async fn save_draft(
+        &mut self,
+        from: String,
+        to: String,
+        title: String,
+        body: String,
+    ) -> Result<String, ClientError> {
+        let msg = compose_message(from, to, title, body);
+        Ok(self
+            .request(|sender| WorkerCommand::SaveDraft { msg, sender })
+            .await??)
+    }
+
+    pub async fn update_draft(
+        &mut self,
+        hash: String,
+        from: String,
+        to: String,
+        title: String,
+        body: String,
+    ) -> Result<(), ClientError> {
+        let msg = compose_message(from, to, title, body);
+        self.request(|sender| WorkerCommand::UpdateDraft { hash, msg, sender })
+            .await??;
+        Ok(())
+    }
+}
+
+/// Split comma separated list of recipients (e.g. the To field of the composer), dropping
+/// duplicates. Recipients are addresses or labels, see [`NodeClient::resolve_recipient`].
+/// Addresses may be separated by whitespace as well, while labels may contain it.
+pub fn parse_recipients(to: &str) -> Vec<String> {
+    let mut recipients: Vec<String> = Vec::new();
+    for item in to.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let words: Vec<&str> = item.split_whitespace().collect();
+        let items = if words.iter().all(|w| Address::with_string_repr(w).is_ok()) {
+            words
+        } else {
+            vec![item]
+        };
+        for recipient in items {
+            if !recipients.iter().any(|r| r == recipient) {
+                recipients.push(recipient.to_string());
+            }
+        }
+    }
+    recipients
+}
+
+/// Build outgoing message model with MIME-encoded title and body
+pub(crate) fn compose_message(
+    from: String,
+    to: String,
+    title: String,
+    body: String,
+) -> models::Message {
+    let m: Message<SinglePart<&str>> = Message::builder().subject(title).mime_body(
+        SinglePart::builder()
+            .header(header::ContentType(
+                "text/plain; charset=utf8".parse().unwrap(),
+            ))
+            .header(header::ContentTransferEncoding::QuotedPrintable)
+            .body(&body),
+    );
+    let data = m.to_string().into_bytes();
+    models::Message {
+        hash: "".to_string(),
+        sender: from,
+        recipient: to,
+        created_at: Utc::now(),
+        status: MessageStatus::Unknown.to_string(),
+        signature: Vec::new(),
+        data,
+        failure_reason: None,
+        folder: None,
+        deleted_at: None,
+        ack_data: None,
+        read: true,
+        send_at: None,
+        verified: false,
+        signer_fingerprint: None,
+        resend_count: 0,
+        resend_at: None,
+        expires: None,
+        sender_label: None,
+        recipient_label: None,
     }
 }
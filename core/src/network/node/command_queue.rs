@@ -0,0 +1,147 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use futures::{
+    channel::mpsc,
+    stream::{FusedStream, Stream},
+    SinkExt,
+};
+
+use super::{client::ClientError, worker::WorkerCommand};
+
+/// Metrics of the queue of client commands since start
+#[derive(Debug, Clone, Default)]
+pub struct CommandQueueStats {
+    /// Commands waiting to be handled by the node
+    pub queued: usize,
+    /// Max number of commands which were waiting at the same time
+    pub max_queued: usize,
+    /// Max number of waiting commands, more are rejected
+    pub capacity: usize,
+    /// Commands rejected because the queue was full
+    pub rejected: u64,
+}
+
+/// Counters shared by all senders and the receiver of the queue
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    max_queued: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+/// Create bounded queue of client commands
+pub(crate) fn channel(capacity: usize) -> (CommandSender, CommandReceiver) {
+    // the capacity is enforced by the counters, since every sender of the channel
+    // has a guaranteed slot in addition to the buffer
+    let (sender, receiver) = mpsc::channel(capacity);
+    let counters = Arc::new(Counters::default());
+    (
+        CommandSender {
+            sender,
+            counters: counters.clone(),
+            capacity,
+        },
+        CommandReceiver { receiver, counters },
+    )
+}
+
+#[derive(Clone)]
+pub(crate) struct CommandSender {
+    sender: mpsc::Sender<WorkerCommand>,
+    counters: Arc<Counters>,
+    capacity: usize,
+}
+
+impl CommandSender {
+    /// Queue the command without waiting, fails with [`ClientError::Busy`] if the queue is full
+    pub fn try_send(&mut self, command: WorkerCommand) -> Result<(), ClientError> {
+        // commands left in the queue of the stopped node still count against the capacity
+        if self.sender.is_closed() {
+            return Err(ClientError::Stopped);
+        }
+        // counted before sending, so that the receiver never sees less than zero
+        let queued = self.counters.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        let result = if queued > self.capacity {
+            Err(ClientError::Busy)
+        } else {
+            self.sender.try_send(command).map_err(|e| {
+                if e.is_full() {
+                    ClientError::Busy
+                } else {
+                    ClientError::Stopped
+                }
+            })
+        };
+        match result {
+            Ok(_) => {
+                self.counters
+                    .max_queued
+                    .fetch_max(queued, Ordering::Relaxed);
+            }
+            Err(ClientError::Busy) => {
+                self.counters.queued.fetch_sub(1, Ordering::SeqCst);
+                self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.counters.queued.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        result
+    }
+
+    /// Queue the command, waiting for a free slot if the queue is full
+    pub async fn send(&mut self, command: WorkerCommand) -> Result<(), ClientError> {
+        let queued = self.counters.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        match self.sender.send(command).await {
+            Ok(_) => {
+                self.counters
+                    .max_queued
+                    .fetch_max(queued, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(_) => {
+                self.counters.queued.fetch_sub(1, Ordering::SeqCst);
+                Err(ClientError::Stopped)
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CommandQueueStats {
+        CommandQueueStats {
+            queued: self.counters.queued.load(Ordering::SeqCst),
+            max_queued: self.counters.max_queued.load(Ordering::Relaxed),
+            capacity: self.capacity,
+            rejected: self.counters.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub(crate) struct CommandReceiver {
+    receiver: mpsc::Receiver<WorkerCommand>,
+    counters: Arc<Counters>,
+}
+
+impl Stream for CommandReceiver {
+    type Item = WorkerCommand;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.receiver).poll_next(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.counters.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        poll
+    }
+}
+
+impl FusedStream for CommandReceiver {
+    fn is_terminated(&self) -> bool {
+        self.receiver.is_terminated()
+    }
+}
@@ -0,0 +1,1213 @@
+//! Handling of the commands clients send to [`NodeWorker`] via the node client.
+
+use async_std::task;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use ecies::{PublicKey, SecretKey};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    rngs::OsRng,
+};
+use std::{collections::HashMap, error::Error};
+
+use futures::{channel::oneshot, SinkExt};
+use libp2p::{gossipsub::PublishError, Multiaddr};
+use log::{debug, info};
+
+use crate::{
+    config::{Config, NodeRole, ObjectType},
+    import,
+    network::{
+        address::{decode_pairing_code, encode_pairing_code, Address},
+        messages::{MessagePayload, ObjectKind, MAX_INV_HASHES},
+    },
+    pow,
+    repositories::{
+        inventory::{InventoryItem, InventoryStats},
+        message::Page,
+        sqlite::models::{self, MessageStatus},
+    },
+};
+
+#[cfg(feature = "sqlite")]
+use crate::import::ImportProgress;
+
+use super::{
+    client::compose_message,
+    pow_worker::{PoWStats, ProofOfWorkWorkerCommand},
+    rate_limit::TokenBucket,
+    worker::{
+        extract_peer_id_from_multiaddr, store_identity, Avatar, BatchedAnnouncement, DynError,
+        Folder, NodeMetrics, NodeWorker, WorkerCommand, MAX_DEVICE_KEYS,
+    },
+};
+
+/// Max size of avatar images of identities and contacts
+const MAX_AVATAR_SIZE: usize = 256 * 1024;
+
+impl NodeWorker {
+    pub(super) async fn handle_command(&mut self, command: WorkerCommand) {
+        match command {
+            WorkerCommand::StartListening { multiaddr, sender } => {
+                debug!("Starting listening to the network...");
+                match self.swarm.listen_on(multiaddr.clone()) {
+                    Ok(id) => {
+                        self.listeners.push(id);
+                        _ = sender.send(Ok(()))
+                    }
+                    Err(e) => _ = sender.send(Err(Box::new(e))),
+                };
+            }
+            WorkerCommand::Dial { peer, sender } if self.is_address_banned(&peer) => {
+                _ = sender.send(Err(Box::<dyn Error + Send + Sync>::from(
+                    "address is banned",
+                )));
+            }
+            WorkerCommand::Dial { peer, sender } => match self.swarm.dial(peer.clone()) {
+                // if peer id is known, wait for the outcome of the connection attempt
+                Ok(_) => match extract_peer_id_from_multiaddr(&peer) {
+                    Ok(peer_id) => {
+                        self.swarm
+                            .behaviour_mut()
+                            .kademlia
+                            .add_address(&peer_id, peer.clone());
+                        self.pending_commands
+                            .push(WorkerCommand::Dial { peer, sender });
+                    }
+                    Err(_) => _ = sender.send(Ok(())),
+                },
+                Err(e) => _ = sender.send(Err(Box::new(e))),
+            },
+            WorkerCommand::GetListenerAddress { sender } => {
+                let listeners: Vec<Multiaddr> = self.swarm.listeners().cloned().collect();
+                if listeners.is_empty() {
+                    self.pending_commands
+                        .push(WorkerCommand::GetListenerAddress { sender });
+                } else {
+                    _ = sender.send(listeners);
+                }
+            }
+            WorkerCommand::GetPeerID { sender } => _ = sender.send(self.local_peer_id),
+            WorkerCommand::GetConnectedPeers { sender } => {
+                _ = sender.send(self.swarm.connected_peers().cloned().collect())
+            }
+            WorkerCommand::GetTrafficStats { sender } => {
+                _ = sender.send(self.traffic_stats.clone())
+            }
+            WorkerCommand::GetDhtStatus { sender } => _ = sender.send(self.dht_status()),
+            WorkerCommand::GetConfig { sender } => _ = sender.send(self.config.clone()),
+            WorkerCommand::UpdateConfig { config, sender } => {
+                let res = self.update_config(config);
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetInventoryStats { sender } => {
+                let res = self.inventory_repo.stats().await.map(|s| InventoryStats {
+                    max_bytes: self.max_inventory_size,
+                    evicted: self.evicted_objects,
+                    ..s
+                });
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetInventory {
+                after,
+                limit,
+                sender,
+            } => {
+                let res = self
+                    .inventory_repo
+                    .get_page(after, limit)
+                    .await
+                    .map(|page| {
+                        page.into_iter()
+                            .map(|(hash, expires, object_type)| InventoryItem {
+                                hash,
+                                object_type: ObjectType::ALL
+                                    .into_iter()
+                                    .find(|t| *t as u8 == object_type),
+                                expires: NaiveDateTime::from_timestamp_opt(expires, 0)
+                                    .map(|t| DateTime::<Utc>::from_utc(t, Utc))
+                                    .unwrap_or_default(),
+                            })
+                            .collect()
+                    });
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetMetrics { sender } => self.get_metrics(sender).await,
+            WorkerCommand::SetPowerMode { mode, sender } => {
+                self.set_power_mode(mode).await;
+                _ = sender.send(());
+            }
+            WorkerCommand::GetPowerMode { sender } => _ = sender.send(self.power_mode),
+            WorkerCommand::BanPeer {
+                target,
+                reason,
+                sender,
+            } => {
+                let res = self.ban_peer(target, reason).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::UnbanPeer { target, sender } => {
+                let res = self.unban_peer(target).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetBannedPeers { sender } => {
+                let res = self.peer_repo.get_banned().await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::BanMisbehavingPeer { peer, reason } => {
+                info!("Banning peer {}: {}", peer, reason);
+                if let Err(e) = self.ban_peer(peer.to_string(), Some(reason)).await {
+                    log::error!("Failed to ban peer {}: {}", peer, e);
+                }
+            }
+            WorkerCommand::Shutdown { .. } => unreachable!("shutdown is handled by the event loop"),
+            #[cfg(feature = "legacy-bridge")]
+            WorkerCommand::PublishLegacyObject { data } => self.publish_legacy_object(data),
+            WorkerCommand::BroadcastMsgByPubSub { sender, msg } => {
+                if let MessagePayload::Inv { inventory, .. } = &msg.payload {
+                    self.traffic_stats.objects_relayed += inventory.len() as u64;
+                }
+                match self.publish_pubsub(msg) {
+                    Ok(_) | Err(PublishError::InsufficientPeers) => _ = sender.send(Ok(())),
+                    Err(e) => _ = sender.send(Err(Box::new(e))),
+                }
+            }
+            WorkerCommand::NonceCalculated { obj } => {
+                // sending might be cancelled while PoW of the message is finishing
+                match retry_db!(self
+                    .inventory_repo
+                    .get_object(bs58::encode(&obj.hash).into_string()))
+                {
+                    Ok(Some(_)) => {}
+                    Ok(None) => return,
+                    Err(e) => {
+                        log::error!("Failed to look up object with calculated nonce: {}", e);
+                        return;
+                    }
+                }
+                // broadcasts aren't acknowledged, so they're never resent
+                let (is_message, resend_at) = match &obj.kind {
+                    ObjectKind::Msg { .. } => (true, Some(obj.expires)),
+                    ObjectKind::Broadcast { .. } => (true, None),
+                    _ => (false, None),
+                };
+                self.announcement_batch.push(BatchedAnnouncement {
+                    hash: bs58::encode(&obj.hash).into_string(),
+                    expires: obj.expires,
+                    object_type: obj.kind.object_type(),
+                    is_message,
+                    resend_at,
+                });
+                if self.announcement_batch.len() >= MAX_INV_HASHES {
+                    self.announce_batch().await;
+                }
+            }
+            WorkerCommand::GetOwnIdentities { sender } => {
+                let result = self.address_repo.get_identities().await;
+                match result {
+                    Ok(a) => {
+                        _ = sender.send(Ok(a));
+                    }
+                    Err(e) => {
+                        _ = sender.send(Err(Box::from(e.to_string())));
+                        return;
+                    }
+                }
+            }
+            WorkerCommand::GenerateIdentity { label, sender } => {
+                if let Err(e) = self.check_identities_allowed() {
+                    _ = sender.send(Err(Box::from(e.to_string())));
+                    return;
+                }
+                let mut address = Address::generate();
+                address.label = label;
+                let res = self.address_repo.store(address.clone()).await;
+                match res {
+                    Ok(_) => {
+                        _ = sender.send(Ok(address.string_repr));
+                    }
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::RenameIdentity {
+                new_label,
+                address,
+                sender,
+            } => match self.address_repo.update_label(address, new_label).await {
+                Ok(_) => {
+                    _ = sender.send(Ok(()));
+                }
+                Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+            },
+            WorkerCommand::SetIdentityPoWDifficulty {
+                address,
+                nonce_trials_per_byte,
+                extra_bytes,
+                sender,
+            } => {
+                if nonce_trials_per_byte < pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE
+                    || extra_bytes < pow::NETWORK_MIN_EXTRA_BYTES
+                {
+                    _ = sender.send(Err(Box::from(
+                        "difficulty can't be lower than network minimum",
+                    )));
+                    return;
+                }
+                match self
+                    .address_repo
+                    .update_pow_difficulty(address, nonce_trials_per_byte, extra_bytes)
+                    .await
+                {
+                    Ok(_) => _ = sender.send(Ok(())),
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::SetStrangerPolicy {
+                address,
+                nonce_trials_per_byte,
+                extra_bytes,
+                quarantine,
+                sender,
+            } => {
+                let res = self
+                    .set_stranger_policy(address, nonce_trials_per_byte, extra_bytes, quarantine)
+                    .await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::DeleteIdentity { address, sender } => {
+                match self.address_repo.delete_address(address).await {
+                    Ok(_) => {
+                        _ = sender.send(Ok(()));
+                    }
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::DeleteIdentities { addresses, sender } => {
+                let res = self.delete_identities(addresses).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetContacts { sender } => match self.address_repo.get_contacts().await {
+                Ok(a) => _ = sender.send(Ok(a)),
+                Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+            },
+            WorkerCommand::ResolveContact { address, sender } => {
+                let res = self.resolve_contact(address).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::ResolveRecipient { input, sender } => {
+                let res = self.resolve_recipient(input).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GenerateDeterministicIdentity {
+                passphrase,
+                label,
+                sender,
+            } => {
+                let res = self
+                    .generate_deterministic_identity(passphrase, label)
+                    .await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::JoinChan {
+                name,
+                address,
+                sender,
+            } => {
+                let res = self.join_chan(name, address).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::ExportIdentities { addresses, sender } => {
+                let res = import::export_identities(self.address_repo.as_ref(), addresses).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::ImportIdentities { data, sender } => {
+                if let Err(e) = self.check_identities_allowed() {
+                    _ = sender.send(Err(Box::from(e.to_string())));
+                    return;
+                }
+                let res = import::import_identities(self.address_repo.as_mut(), &data).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::CreateDeviceKey { address, sender } => {
+                let res = self.create_device_key(address).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::PairDevice {
+                code,
+                label,
+                sender,
+            } => {
+                let res = self.pair_device(code, label).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetDeviceKeys { address, sender } => {
+                match self.address_repo.get_device_keys(address).await {
+                    Ok(v) => _ = sender.send(Ok(v)),
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::RemoveDeviceKey {
+                address,
+                public_key,
+                sender,
+            } => {
+                let res = self.remove_device_key(address, public_key).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::SetAvatar {
+                address,
+                avatar,
+                sender,
+            } => {
+                let res = self.set_avatar(address, avatar).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetAvatar { address, sender } => {
+                let res = self.get_avatar(address).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::AddContact {
+                address,
+                label,
+                sender,
+            } => {
+                let res = self.add_contact(address, label).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::RenameContact {
+                address,
+                new_label,
+                sender,
+            } => match self.address_repo.update_label(address, new_label).await {
+                Ok(_) => _ = sender.send(Ok(())),
+                Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+            },
+            WorkerCommand::DeleteContact { address, sender } => {
+                match self.address_repo.delete_address(address).await {
+                    Ok(_) => _ = sender.send(Ok(())),
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::RotateIdentity {
+                old_address,
+                new_label,
+                archive_old,
+                sender,
+            } => {
+                let res = self
+                    .rotate_identity(old_address, new_label, archive_old)
+                    .await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::RotateKeys { address, sender } => {
+                let res = self.rotate_keys(address).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::RepinContact { address, sender } => {
+                match self.address_repo.clear_public_keys(address.clone()).await {
+                    Ok(true) => _ = sender.send(Ok(())),
+                    Ok(false) => {
+                        _ = sender.send(Err(Box::from(format!("no such contact: {}", address))))
+                    }
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::GetMessages {
+                address,
+                folder,
+                page,
+                sender,
+            } => {
+                let res = self.get_messages(address, folder, page).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::SearchMessages {
+                query,
+                address,
+                folder,
+                sender,
+            } => {
+                let res = self.search_messages(query, address, folder).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::ExportMessages {
+                hashes,
+                mbox,
+                sender,
+            } => {
+                let res = import::export_messages(self.messages_repo.as_ref(), hashes, mbox).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::ImportMessages {
+                address,
+                folder,
+                data,
+                sender,
+            } => {
+                let res =
+                    import::import_messages(self.messages_repo.as_mut(), address, folder, data)
+                        .await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            #[cfg(feature = "sqlite")]
+            WorkerCommand::ImportPyBitmessage { dir, progress } => {
+                if let Err(e) = self.check_identities_allowed() {
+                    _ = progress.unbounded_send(ImportProgress::Finished(Err(e.to_string())));
+                    return;
+                }
+                let res = import::import_pybitmessage(
+                    self.address_repo.as_mut(),
+                    self.messages_repo.as_mut(),
+                    &dir,
+                    &progress,
+                )
+                .await;
+                _ = progress
+                    .unbounded_send(ImportProgress::Finished(res.map_err(|e| e.to_string())));
+            }
+            WorkerCommand::DeleteMessage { hash, sender } => {
+                match self.messages_repo.move_to_trash(hash).await {
+                    Ok(_) => _ = sender.send(Ok(())),
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::RestoreMessage { hash, sender } => {
+                match self.messages_repo.restore_message(hash).await {
+                    Ok(_) => _ = sender.send(Ok(())),
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::MarkRead { hash, read, sender } => {
+                match self.messages_repo.mark_read(hash, read).await {
+                    Ok(true) => _ = sender.send(Ok(())),
+                    Ok(false) => _ = sender.send(Err(Box::from("no such message"))),
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::GetFolderStats { address, sender } => {
+                let res = self.messages_repo.get_folder_stats(address).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetPoWQueue { sender } => {
+                self.send_pow_worker_command(ProofOfWorkWorkerCommand::GetQueue { sender })
+                    .await
+            }
+            WorkerCommand::EstimatePoW {
+                msg_size,
+                ttl,
+                sender,
+            } => {
+                self.send_pow_worker_command(ProofOfWorkWorkerCommand::Estimate {
+                    msg_size,
+                    ttl,
+                    sender,
+                })
+                .await
+            }
+            WorkerCommand::CancelPoW { hash, sender } => {
+                self.send_pow_worker_command(ProofOfWorkWorkerCommand::CancelObject {
+                    hash,
+                    remove_message: true,
+                    sender,
+                })
+                .await
+            }
+            WorkerCommand::CancelSend { hash, sender } => {
+                let res = self.cancel_send(hash).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::RetryMessage { hash, sender } => {
+                let res = self.retry_message(hash).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::RebroadcastObject { hash, sender } => {
+                let res = self.rebroadcast_object(hash).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::RetryPubkeyRequest { hash, sender } => {
+                let res = self.retry_pubkey_request(hash).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetPubkeyRequest { hash, sender } => {
+                let res = self.pubkey_request(hash).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::SendMessage {
+                msg,
+                from,
+                to,
+                draft_hash,
+                sender,
+            } => {
+                let res = self.send_messages(msg, from, to, draft_hash).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::ScheduleMessage {
+                msg,
+                from,
+                to,
+                draft_hash,
+                send_at,
+                sender,
+            } => {
+                let res = self
+                    .schedule_message(msg, from, to, draft_hash, send_at)
+                    .await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::SendBroadcast { msg, from, sender } => {
+                let res = self.send_broadcast(msg, from).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::AddSubscription {
+                address,
+                label,
+                sender,
+            } => {
+                let res = self.add_subscription(address, label).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::RemoveSubscription { address, sender } => {
+                let res = self.address_repo.remove_subscription(address).await;
+                _ = sender.send(res.map(|_| ()).map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetSubscriptions { sender } => {
+                let res = self.address_repo.get_subscriptions().await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::SetAutoReply { auto_reply, sender } => {
+                let res = self.set_auto_reply(auto_reply).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::RemoveAutoReply { address, sender } => {
+                let res = self.address_repo.remove_auto_reply(address).await;
+                _ = sender.send(res.map(|_| ()).map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetAutoReply { address, sender } => {
+                let res = self.address_repo.get_auto_reply(address).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::GetFilterRules { sender } => {
+                let res = self.messages_repo.get_filter_rules().await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::AddFilterRule { rule, sender } => {
+                let res = self.add_filter_rule(rule).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::UpdateFilterRule { rule, sender } => {
+                let res = self.update_filter_rule(rule).await;
+                _ = sender.send(res.map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::RemoveFilterRule { id, sender } => {
+                let res = self.messages_repo.remove_filter_rule(id).await;
+                _ = sender.send(res.map(|_| ()).map_err(|e| Box::from(e.to_string())));
+            }
+            WorkerCommand::SubscribeMessageStatus { sender } => {
+                self.message_status_subscribers.push(sender);
+            }
+            WorkerCommand::SubscribeConnectivity { sender } => {
+                if sender.unbounded_send(self.online).is_ok() {
+                    self.connectivity_subscribers.push(sender);
+                }
+            }
+            WorkerCommand::MessageStatusChanged { event } => {
+                if let Some(msg) = &event.message {
+                    if let Err(e) = self.send_auto_reply(msg).await {
+                        log::error!("Failed to send auto-reply: {}", e);
+                    }
+                }
+                self.notify_message_status(event);
+            }
+            WorkerCommand::SubscribeKeyMismatch { sender } => {
+                self.key_mismatch_subscribers.push(sender);
+            }
+            WorkerCommand::KeyMismatch { event } => {
+                self.key_mismatch_subscribers
+                    .retain(|s| s.unbounded_send(event.clone()).is_ok());
+            }
+            WorkerCommand::SaveDraft { mut msg, sender } => {
+                msg.hash = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+                msg.status = MessageStatus::Draft.to_string();
+                let hash = msg.hash.clone();
+                match self.messages_repo.save_model(msg).await {
+                    Ok(_) => _ = sender.send(Ok(hash)),
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+            WorkerCommand::UpdateDraft { hash, msg, sender } => {
+                match self.messages_repo.update_draft(hash, msg).await {
+                    Ok(_) => _ = sender.send(Ok(())),
+                    Err(e) => _ = sender.send(Err(Box::from(e.to_string()))),
+                }
+            }
+        };
+    }
+
+    /// Messages of the identity in the folder, with labels of their senders and recipients
+    async fn get_messages(
+        &mut self,
+        address: String,
+        folder: Folder,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let repo = &self.messages_repo;
+        let mut msgs = match folder {
+            Folder::Inbox | Folder::Broadcasts => {
+                repo.get_messages_by_recipient(address, page).await?
+            }
+            Folder::Outbox => repo.get_outbox(address, page).await?,
+            Folder::Sent => repo.get_messages_by_sender(address, page).await?,
+            Folder::Drafts => repo.get_drafts(address, page).await?,
+            Folder::Trash => repo.get_trashed_messages(address, page).await?,
+            Folder::Quarantine => repo.get_quarantined_messages(address, page).await?,
+        };
+        self.resolve_labels(&mut msgs).await?;
+        Ok(msgs)
+    }
+
+    async fn search_messages(
+        &mut self,
+        query: String,
+        address: String,
+        folder: Folder,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let mut msgs = self
+            .messages_repo
+            .search_messages(query, folder, address)
+            .await?;
+        self.resolve_labels(&mut msgs).await?;
+        Ok(msgs)
+    }
+
+    /// Fill labels of senders and recipients of the messages from our identities and
+    /// contacts, addresses without a label are left as they are
+    async fn resolve_labels(&mut self, msgs: &mut [models::Message]) -> Result<(), Box<dyn Error>> {
+        let identities = self.address_repo.get_identities().await?;
+        let contacts = self.address_repo.get_contacts().await?;
+        let labels: HashMap<String, String> = identities
+            .into_iter()
+            .chain(contacts)
+            .filter(|a| !a.label.is_empty())
+            .map(|a| (a.string_repr, a.label))
+            .collect();
+        for msg in msgs {
+            msg.sender_label = labels.get(&msg.sender).cloned();
+            msg.recipient_label = labels.get(&msg.recipient).cloned();
+        }
+        Ok(())
+    }
+
+    async fn generate_deterministic_identity(
+        &mut self,
+        passphrase: String,
+        label: String,
+    ) -> Result<String, Box<dyn Error>> {
+        if passphrase.is_empty() {
+            return Err("passphrase is empty".into());
+        }
+        let mut address = Address::from_passphrase(&passphrase);
+        address.label = label;
+        if !self.store_identity(address.clone()).await? {
+            return Err("identity with this passphrase already exists".into());
+        }
+        Ok(address.string_repr)
+    }
+
+    async fn join_chan(
+        &mut self,
+        name: String,
+        address: Option<String>,
+    ) -> Result<String, Box<dyn Error>> {
+        if name.is_empty() {
+            return Err("chan name is empty".into());
+        }
+        let mut chan = Address::from_passphrase(&name);
+        if let Some(address) = address {
+            if Address::with_string_repr(&address)?.ripe != chan.ripe {
+                return Err("chan address doesn't match the name".into());
+            }
+        }
+        chan.label = name;
+        chan.chan = true;
+        if !self.store_identity(chan.clone()).await? {
+            return Err("chan is already joined".into());
+        }
+        Ok(chan.string_repr)
+    }
+
+    fn check_identities_allowed(&self) -> Result<(), Box<dyn Error>> {
+        if self.role == NodeRole::Relay {
+            return Err("relay nodes can't have identities".into());
+        }
+        Ok(())
+    }
+
+    /// Store identity with known private keys. Returns `false` if such identity already exists.
+    async fn store_identity(&mut self, address: Address) -> Result<bool, Box<dyn Error>> {
+        self.check_identities_allowed()?;
+        store_identity(self.address_repo.as_mut(), address).await
+    }
+
+    /// Delete the identities in a single transaction, nothing is deleted if any of
+    /// the addresses isn't an identity
+    async fn delete_identities(&mut self, addresses: Vec<String>) -> Result<(), Box<dyn Error>> {
+        for a in &addresses {
+            match self.address_repo.get_by_ripe_or_tag(a.clone()).await? {
+                Some(identity) if identity.private_signing_key.is_some() => {}
+                _ => return Err(format!("identity {} not found", a).into()),
+            }
+        }
+        self.address_repo.delete_addresses(addresses).await
+    }
+
+    /// Generate encryption key of this device for the identity kept on another device,
+    /// unless it's generated already. Returns the pairing code, which is entered on that device.
+    async fn create_device_key(&mut self, address: String) -> Result<String, Box<dyn Error>> {
+        let address = Address::with_string_repr(&address)?;
+        let existing = self
+            .address_repo
+            .get_by_ripe_or_tag(address.string_repr.clone())
+            .await?;
+        if existing.is_some_and(|a| a.private_encryption_key.is_some()) {
+            return Err("keys of the identity are already kept on this device".into());
+        }
+        let own_key = self
+            .address_repo
+            .get_device_keys(address.string_repr.clone())
+            .await?
+            .into_iter()
+            .find(|k| k.private_key.is_some());
+        if let Some(key) = own_key {
+            let public_key = PublicKey::parse_slice(&key.public_key, None)
+                .map_err(|_| "stored device key is malformed")?;
+            return Ok(encode_pairing_code(&address.string_repr, &public_key));
+        }
+        let secret_key = SecretKey::random(&mut OsRng);
+        let public_key = PublicKey::from_secret_key(&secret_key);
+        self.address_repo
+            .store_device_key(models::DeviceKey {
+                address: address.string_repr.clone(),
+                public_key: public_key.serialize().to_vec(),
+                private_key: Some(secret_key.serialize().to_vec()),
+                label: String::new(),
+                created_at: Utc::now(),
+            })
+            .await?;
+        Ok(encode_pairing_code(&address.string_repr, &public_key))
+    }
+
+    /// Add device with the pairing code to its identity and publish new pubkey,
+    /// so that contacts start sending copies of messages to the device
+    async fn pair_device(&mut self, code: String, label: String) -> Result<(), Box<dyn Error>> {
+        let (address, public_key) = decode_pairing_code(&code)?;
+        let identity = self
+            .address_repo
+            .get_by_ripe_or_tag(address.string_repr)
+            .await?
+            .filter(|i| i.private_signing_key.is_some())
+            .ok_or("identity isn't kept on this device")?;
+        if identity.chan {
+            return Err("chan members share the keys already".into());
+        }
+        let public_key = public_key.serialize().to_vec();
+        let devices = self
+            .address_repo
+            .get_device_keys(identity.string_repr.clone())
+            .await?;
+        if !devices.iter().any(|d| d.public_key == public_key) && devices.len() >= MAX_DEVICE_KEYS {
+            return Err(
+                format!("identity can't have more than {} devices", MAX_DEVICE_KEYS).into(),
+            );
+        }
+        self.address_repo
+            .store_device_key(models::DeviceKey {
+                address: identity.string_repr.clone(),
+                public_key,
+                private_key: None,
+                label,
+                created_at: Utc::now(),
+            })
+            .await?;
+        self.publish_pubkey(&identity).await
+    }
+
+    async fn remove_device_key(
+        &mut self,
+        address: String,
+        public_key: String,
+    ) -> Result<(), Box<dyn Error>> {
+        let public_key = bs58::decode(public_key).into_vec()?;
+        let removed = self
+            .address_repo
+            .remove_device_key(address.clone(), public_key)
+            .await?;
+        if !removed {
+            return Err("no such device".into());
+        }
+        // contacts have to learn that the device is gone, if it's our identity
+        let identity = self.address_repo.get_by_ripe_or_tag(address).await?;
+        match identity {
+            Some(identity) if identity.private_signing_key.is_some() => {
+                self.publish_pubkey(&identity).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn set_avatar(
+        &mut self,
+        address: String,
+        avatar: Option<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error>> {
+        if avatar.as_ref().is_some_and(|a| a.len() > MAX_AVATAR_SIZE) {
+            return Err(format!("avatar is larger than {} KiB", MAX_AVATAR_SIZE / 1024).into());
+        }
+        let address = Address::with_string_repr(&address)?;
+        if !self
+            .address_repo
+            .set_avatar(address.string_repr, avatar)
+            .await?
+        {
+            return Err("no such identity or contact".into());
+        }
+        Ok(())
+    }
+
+    /// Avatar image of the address if it's set, otherwise seed of its identicon.
+    /// Unknown addresses (e.g. senders of messages) get identicons too.
+    async fn get_avatar(&mut self, address: String) -> Result<Avatar, Box<dyn Error>> {
+        let address = Address::with_string_repr(&address)?;
+        let image = self
+            .address_repo
+            .get_avatar(address.string_repr.clone())
+            .await?;
+        Ok(match image {
+            Some(image) => Avatar::Image(image),
+            None => Avatar::Identicon(address.avatar_seed()),
+        })
+    }
+
+    async fn add_contact(&mut self, address: String, label: String) -> Result<(), Box<dyn Error>> {
+        let mut contact = Address::with_string_repr(&address)?;
+        let address = contact.string_repr.clone();
+        let existing = self
+            .address_repo
+            .get_by_ripe_or_tag(address.clone())
+            .await?;
+        match existing {
+            Some(a) if a.private_signing_key.is_some() => Err("address is our own identity".into()),
+            // address is already known (e.g. we've sent a message to it before)
+            Some(_) => self.address_repo.update_label(address, label).await,
+            None => {
+                contact.label = label;
+                self.address_repo.store(contact).await
+            }
+        }
+    }
+
+    async fn add_subscription(
+        &mut self,
+        address: String,
+        label: String,
+    ) -> Result<(), Box<dyn Error>> {
+        let address = Address::with_string_repr(&address)?;
+        self.address_repo
+            .add_subscription(models::Subscription {
+                address: address.string_repr,
+                label,
+                created_at: Utc::now(),
+            })
+            .await
+    }
+
+    async fn set_auto_reply(
+        &mut self,
+        auto_reply: models::AutoReply,
+    ) -> Result<(), Box<dyn Error>> {
+        match self
+            .address_repo
+            .get_by_ripe_or_tag(auto_reply.address.clone())
+            .await?
+        {
+            // every member of the chan would get the reply
+            Some(identity) if identity.chan => return Err("chans can't have auto-replies".into()),
+            Some(identity) if identity.private_signing_key.is_some() => {}
+            _ => return Err(format!("identity {} not found", auto_reply.address).into()),
+        }
+        if auto_reply.interval_hours < 1 {
+            return Err("auto-reply interval has to be at least an hour".into());
+        }
+        self.address_repo.set_auto_reply(auto_reply).await
+    }
+
+    async fn add_filter_rule(
+        &mut self,
+        mut rule: models::FilterRule,
+    ) -> Result<String, Box<dyn Error>> {
+        validate_filter_rule(&mut rule)?;
+        rule.id = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        rule.created_at = Utc::now();
+        let id = rule.id.clone();
+        self.messages_repo.add_filter_rule(rule).await?;
+        Ok(id)
+    }
+
+    async fn update_filter_rule(
+        &mut self,
+        mut rule: models::FilterRule,
+    ) -> Result<(), Box<dyn Error>> {
+        validate_filter_rule(&mut rule)?;
+        let id = rule.id.clone();
+        if !self.messages_repo.update_filter_rule(rule).await? {
+            return Err(format!("filtering rule {} not found", id).into());
+        }
+        Ok(())
+    }
+
+    async fn resolve_contact(
+        &mut self,
+        address: String,
+    ) -> Result<Option<Address>, Box<dyn Error>> {
+        let address = Address::with_string_repr(&address)?;
+        let contact = self
+            .address_repo
+            .get_by_ripe_or_tag(address.string_repr)
+            .await?;
+        Ok(contact.filter(|c| c.private_signing_key.is_none()))
+    }
+
+    /// Labels are matched case-insensitively, they have to be unique. Input starting with
+    /// `BM-` is always parsed as an address, so that mistyped addresses aren't looked up.
+    async fn resolve_recipient(&mut self, input: String) -> Result<Address, Box<dyn Error>> {
+        let input = input.trim();
+        let parse_error = match Address::with_string_repr(input) {
+            Ok(address) => {
+                let known = self
+                    .address_repo
+                    .get_by_ripe_or_tag(address.string_repr.clone())
+                    .await?;
+                return Ok(known.unwrap_or(address));
+            }
+            Err(e) if input.starts_with("BM-") => return Err(e.into()),
+            Err(e) => e,
+        };
+        let contacts = self.address_repo.get_contacts().await?;
+        let identities = self.address_repo.get_identities().await?;
+        let mut matches: Vec<Address> = contacts
+            .into_iter()
+            .chain(identities)
+            .filter(|a| !a.label.is_empty() && a.label.to_lowercase() == input.to_lowercase())
+            .collect();
+        match matches.len() {
+            0 => Err(format!(
+                "no contact is labelled \"{}\" and it's not an address: {}",
+                input, parse_error
+            )
+            .into()),
+            1 => Ok(matches.remove(0)),
+            _ => Err(format!(
+                "\"{}\" is a label of several addresses: {}",
+                input,
+                matches
+                    .iter()
+                    .map(|a| a.string_repr.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into()),
+        }
+    }
+
+    async fn rotate_identity(
+        &mut self,
+        old_address: String,
+        new_label: Option<String>,
+        archive_old: bool,
+    ) -> Result<String, Box<dyn Error>> {
+        let old_identity = self
+            .address_repo
+            .get_by_ripe_or_tag(old_address.clone())
+            .await?
+            .ok_or("no such identity")?;
+        if old_identity.private_signing_key.is_none() {
+            return Err("address is not our own identity".into());
+        }
+        if old_identity.chan {
+            return Err("keys of a chan are derived from its name".into());
+        }
+
+        let mut address = Address::generate();
+        address.label = new_label.unwrap_or(old_identity.label);
+        self.address_repo.store(address.clone()).await?;
+
+        // the old identity would be listed among contacts once it's archived
+        let contacts = self.address_repo.get_contacts().await?;
+        if archive_old {
+            self.address_repo.remove_private_keys(old_address).await?;
+        }
+        // the old keys might be compromised, so the notice comes from the new address
+        for contact in contacts {
+            let msg = compose_message(
+                address.string_repr.clone(),
+                contact.string_repr,
+                "My address has changed".to_string(),
+                format!(
+                    "I've moved to {}, please use it instead of {} from now on.",
+                    address.string_repr, old_identity.string_repr
+                ),
+            );
+            self.send_message(msg, address.string_repr.clone()).await?;
+        }
+        Ok(address.string_repr)
+    }
+
+    async fn set_stranger_policy(
+        &mut self,
+        address: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+        quarantine: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let identity = self
+            .address_repo
+            .get_by_ripe_or_tag(address)
+            .await?
+            .filter(|i| i.private_signing_key.is_some())
+            .ok_or("no such identity")?;
+        if nonce_trials_per_byte < identity.nonce_trials_per_byte
+            || extra_bytes < identity.extra_bytes
+        {
+            return Err("difficulty for strangers can't be lower than for contacts".into());
+        }
+        self.address_repo
+            .update_stranger_pow_difficulty(
+                identity.string_repr.clone(),
+                nonce_trials_per_byte,
+                extra_bytes,
+            )
+            .await?;
+        self.address_repo
+            .set_quarantine_strangers(identity.string_repr, quarantine)
+            .await
+    }
+
+    fn update_config(&mut self, config: Config) -> Result<Vec<&'static str>, Box<dyn Error>> {
+        let bootstrap_peers = config
+            .bootstrap_peers
+            .iter()
+            .map(|a| extract_peer_id_from_multiaddr(a).map(|id| (id, a.clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(data_dir) = &self.data_dir {
+            config.save(data_dir)?;
+        }
+
+        for address in &self.config.bootstrap_peers {
+            if let Ok(peer_id) = extract_peer_id_from_multiaddr(address) {
+                self.protected_peers.remove(&peer_id);
+            }
+        }
+        for (peer_id, address) in &bootstrap_peers {
+            self.protected_peers.insert(*peer_id);
+            if self.config.bootstrap_peers.contains(address) {
+                continue;
+            }
+            self.swarm
+                .behaviour_mut()
+                .kademlia
+                .add_address(peer_id, address.clone());
+            if let Err(e) = self.swarm.dial(address.clone()) {
+                log::warn!("Failed to dial bootstrap peer {}: {}", address, e);
+            }
+        }
+        if !bootstrap_peers.is_empty() && config.bootstrap_peers != self.config.bootstrap_peers {
+            _ = self.swarm.behaviour_mut().kademlia.bootstrap();
+        }
+
+        if config.max_download_rate != self.config.max_download_rate {
+            self.download_limiter = config.max_download_rate.map(|r| TokenBucket::new(r, r));
+        }
+        if config.max_upload_rate != self.config.max_upload_rate {
+            self.upload_limiter = config.max_upload_rate.map(|r| TokenBucket::new(r, r));
+        }
+        // the inventory is trimmed to the new size by the next maintenance
+        self.max_inventory_size = config.max_inventory_size;
+        self.inventory_eviction = config.inventory_eviction;
+
+        let restart_required = self.config.restart_required(&config);
+        self.config = config;
+        Ok(restart_required)
+    }
+
+    /// Collect metrics of the node. Stats of the PoW worker are awaited in the background,
+    /// so that the node isn't blocked by them.
+    async fn get_metrics(&mut self, sender: oneshot::Sender<Result<NodeMetrics, DynError>>) {
+        let inventory = match self.inventory_repo.stats().await {
+            Ok(s) => InventoryStats {
+                max_bytes: self.max_inventory_size,
+                evicted: self.evicted_objects,
+                ..s
+            },
+            Err(e) => {
+                _ = sender.send(Err(Box::from(e.to_string())));
+                return;
+            }
+        };
+        let storage_bytes = match self.storage.size().await {
+            Ok(size) => size,
+            Err(e) => {
+                _ = sender.send(Err(Box::from(e.to_string())));
+                return;
+            }
+        };
+        let metrics = NodeMetrics {
+            connected_peers: self.swarm.connected_peers().count(),
+            inventory,
+            traffic: self.traffic_stats.clone(),
+            pow: PoWStats::default(),
+            storage_bytes,
+        };
+        let (pow_sender, pow_receiver) = oneshot::channel();
+        if let Some(sink) = self.pow_worker_command_sink.as_mut() {
+            _ = sink
+                .send(ProofOfWorkWorkerCommand::GetStats { sender: pow_sender })
+                .await;
+        }
+        task::spawn(async move {
+            // PoW worker is gone once the node is shutting down
+            let pow = pow_receiver.await.unwrap_or_default();
+            _ = sender.send(Ok(NodeMetrics { pow, ..metrics }));
+        });
+    }
+}
+
+/// Check that the rule does something and normalize it, so that empty conditions
+/// are unset and the folder it moves messages to is named the way it's stored
+fn validate_filter_rule(rule: &mut models::FilterRule) -> Result<(), Box<dyn Error>> {
+    for condition in [
+        &mut rule.sender,
+        &mut rule.subject_contains,
+        &mut rule.identity,
+        &mut rule.move_to,
+    ] {
+        if condition.as_ref().is_some_and(|c| c.trim().is_empty()) {
+            *condition = None;
+        }
+    }
+    if let Some(folder) = &rule.move_to {
+        match folder.parse::<Folder>() {
+            Ok(folder @ (Folder::Trash | Folder::Quarantine)) => {
+                rule.move_to = Some(folder.to_string())
+            }
+            _ => return Err(format!("messages can't be moved to {}", folder).into()),
+        }
+    }
+    if let (Some(min), Some(max)) = (rule.min_size, rule.max_size) {
+        if min > max {
+            return Err("min size of the messages is greater than the max one".into());
+        }
+    }
+    if !rule.mark_read && !rule.delete_message && !rule.notify && rule.move_to.is_none() {
+        return Err("filtering rule has no actions".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    path::Path,
+    process,
+};
+
+use fs2::FileExt;
+
+/// Name of the lock file in the data dir, it contains PID of the node owning the dir
+const LOCK_FILE_NAME: &str = "node.lock";
+
+#[derive(thiserror::Error, Debug)]
+pub enum DataDirLockError {
+    #[error("data dir is used by another running node{}", .0.map_or(String::new(), |pid| format!(" (pid {})", pid)))]
+    Locked(Option<u32>),
+    #[error("failed to lock data dir: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Exclusive ownership of the data dir, so that two nodes don't write the same database
+/// and announce the same peer id. The lock file is locked by the OS as long as this is
+/// alive, so it's released even if the node crashes.
+#[derive(Debug)]
+pub struct DataDirLock {
+    _file: File,
+}
+
+impl DataDirLock {
+    /// Lock the lock file in the data dir, creating it if needed. Lock files left by
+    /// nodes which are no longer running aren't locked, so they're taken over.
+    pub fn acquire(data_dir: &Path) -> Result<Self, DataDirLockError> {
+        fs::create_dir_all(data_dir)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(data_dir.join(LOCK_FILE_NAME))?;
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.kind() != fs2::lock_contended_error().kind() {
+                return Err(e.into());
+            }
+            // PID is only informational, the owner might not have written it yet
+            let mut pid = String::new();
+            _ = file.read_to_string(&mut pid);
+            return Err(DataDirLockError::Locked(pid.trim().parse().ok()));
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", process::id())?;
+        Ok(Self { _file: file })
+    }
+}
@@ -1,4 +1,7 @@
-use std::error::Error;
+use std::{
+    collections::{HashSet, VecDeque},
+    error::Error,
+};
 
 use async_std::task;
 use chrono::Utc;
@@ -6,32 +9,42 @@ use futures::{
     channel::{mpsc, oneshot},
     SinkExt,
 };
-use num_bigint::BigUint;
+use libp2p::PeerId;
 
 use crate::{
+    config::Config,
     network::{
+        address::Address,
         messages::{
-            MessageCommand, MessagePayload, NetworkMessage, Object, ObjectKind, UnencryptedPubkey,
+            KeyEndorsement, MessageCommand, MessagePayload, NetworkMessage, Object, ObjectKind,
+            UnencryptedMsg, ACK_DATA_LENGTH, MAX_INV_HASHES,
         },
-        node::worker::NodeWorker,
+        node::pubkeys::identity_pubkey_object,
     },
-    pow,
     repositories::{
-        address::AddressRepositorySync, inventory::InventoryRepositorySync,
-        message::MessageRepositorySync,
+        address::{AddressRepositorySync, PublicKeysUpdate},
+        inventory::InventoryRepositorySync,
+        message::{extract_text, MessageRepositorySync, QUARANTINE_FOLDER, TRASH_FOLDER},
+        sqlite::models,
     },
 };
 
-use super::{pow_worker::ProofOfWorkWorkerCommand, worker::WorkerCommand};
+use super::{
+    pow_worker::ProofOfWorkWorkerCommand,
+    protocol::{Action, Misbehavior, MsgContext, ProtocolEngine, PubkeyUpdate},
+    worker::{KeyMismatchEvent, MessageStatusEvent, WorkerCommand},
+};
 
+/// Carries out the protocol decided by [`ProtocolEngine`]: looks up the local state
+/// received messages concern and performs the resulting actions
 pub struct Handler {
     address_repo: Box<AddressRepositorySync>,
     inventory_repo: Box<InventoryRepositorySync>,
     message_repo: Box<MessageRepositorySync>,
-    requested_objects: Vec<String>, // TODO periodically request missing object from every connection we have
-    worker_event_sender: mpsc::Sender<WorkerCommand>,
+    worker_event_sender: mpsc::UnboundedSender<WorkerCommand>,
     pubkey_notifier_sink: mpsc::Sender<String>,
     pow_worker_sink: Option<mpsc::Sender<ProofOfWorkWorkerCommand>>,
+    engine: ProtocolEngine,
 }
 
 impl Handler {
@@ -39,17 +52,18 @@ impl Handler {
         address_repo: Box<AddressRepositorySync>,
         inventory_repo: Box<InventoryRepositorySync>,
         message_repo: Box<MessageRepositorySync>,
-        worker_event_sender: mpsc::Sender<WorkerCommand>,
+        worker_event_sender: mpsc::UnboundedSender<WorkerCommand>,
         pubkey_notifier_sink: mpsc::Sender<String>,
+        config: Config,
     ) -> Handler {
         Handler {
             address_repo,
             inventory_repo,
             message_repo,
-            requested_objects: Vec::new(),
             worker_event_sender,
             pubkey_notifier_sink,
             pow_worker_sink: None,
+            engine: ProtocolEngine::new(config),
         }
     }
 
@@ -57,54 +71,82 @@ impl Handler {
         self.pow_worker_sink = Some(sink);
     }
 
-    pub async fn handle_message(&mut self, msg: NetworkMessage) -> Option<NetworkMessage> {
+    /// Remember types of objects advertised by the peer, so that it's not offered others.
+    /// `None` means that the peer wants all objects (or disconnected).
+    pub fn set_peer_object_types(&mut self, peer: PeerId, types: Option<Vec<u8>>) {
+        self.engine.set_peer_object_types(peer, types);
+    }
+
+    /// Add penalty to the score of the peer, the peer is banned once the score is too high
+    pub async fn report_misbehavior(&mut self, peer: PeerId, misbehavior: Misbehavior) {
+        let actions = self.engine.report_misbehavior(peer, misbehavior);
+        self.perform(actions.into_iter().collect()).await;
+    }
+
+    /// Request of the whole inventory of a newly found peer, unless it was requested recently
+    pub fn inventory_request(&mut self, peer: PeerId) -> Option<NetworkMessage> {
+        self.engine.inventory_request(peer, Utc::now())
+    }
+
+    /// Halve misbehavior scores, so that occasional invalid objects relayed
+    /// by honest peers don't get them banned
+    pub fn decay_misbehavior_scores(&mut self) {
+        self.engine.decay_misbehavior_scores();
+    }
+
+    pub async fn handle_message(
+        &mut self,
+        peer: PeerId,
+        msg: NetworkMessage,
+    ) -> Option<NetworkMessage> {
         match msg.command {
             MessageCommand::GetData => Some(self.handle_get_data(msg.payload).await),
             MessageCommand::Inv => self.handle_inv(msg.payload).await,
-            MessageCommand::ReqInv => Some(self.handle_get_inv_message(msg.payload).await),
+            MessageCommand::ReqInv => Some(self.handle_get_inv_message(peer, msg.payload).await),
             MessageCommand::Objects => {
-                self.handle_objects(msg.payload).await;
+                self.handle_objects(peer, msg.payload).await;
                 None
             }
+            // peers are exchanged by the worker, which dials them
+            MessageCommand::Addr => None,
         }
     }
 
-    async fn handle_get_inv_message(&self, _: MessagePayload) -> NetworkMessage {
-        let inv = self
+    async fn handle_get_inv_message(
+        &self,
+        peer: PeerId,
+        payload: MessagePayload,
+    ) -> NetworkMessage {
+        let after = match payload {
+            MessagePayload::ReqInv { after } => Some(after),
+            _ => None,
+        };
+        let page = self
             .inventory_repo
-            .get()
+            .get_page(after, MAX_INV_HASHES)
             .await
-            .expect("Inventory repo not to fail");
-        NetworkMessage {
-            command: MessageCommand::Inv,
-            payload: MessagePayload::Inv { inventory: inv },
-        }
+            .unwrap_or_else(|e| {
+                log::error!("Failed to list inventory: {}", e);
+                Vec::new()
+            });
+        self.engine.inv_page(peer, page)
     }
 
     async fn handle_inv(&self, payload: MessagePayload) -> Option<NetworkMessage> {
-        let inv = if let MessagePayload::Inv { inventory } = payload {
-            inventory
-        } else {
-            Vec::new()
+        let wanted = self.engine.wanted_objects(payload);
+        // the objects are requested once they're announced again
+        let missing_objects = retry_db!(self.inventory_repo.get_missing_objects(wanted.clone()));
+        let missing_objects = match missing_objects {
+            Ok(m) => m,
+            Err(e) => {
+                log::error!("Failed to find missing objects: {}", e);
+                return None;
+            }
         };
-        let missing_objects = self
-            .inventory_repo
-            .get_missing_objects(inv)
-            .await
-            .expect("db won't fail");
-        if !missing_objects.is_empty() {
-            log::debug!("requesting {} missing objects...", missing_objects.len());
-            return Some(NetworkMessage {
-                command: MessageCommand::GetData,
-                payload: MessagePayload::GetData {
-                    inventory: missing_objects,
-                },
-            });
-        }
-        None
+        ProtocolEngine::request_objects(missing_objects)
     }
 
-    async fn handle_objects(&mut self, payload: MessagePayload) {
+    async fn handle_objects(&mut self, peer: PeerId, payload: MessagePayload) {
         let objects: Vec<Object> = if let MessagePayload::Objects { objects } = payload {
             objects
         } else {
@@ -112,247 +154,418 @@ impl Handler {
             return;
         };
 
-        for obj in objects {
-            let hash_str = bs58::encode(&obj.hash).into_string();
-            self.requested_objects.retain(|v| *v != hash_str);
-
-            if self
-                .inventory_repo
-                .get_object(hash_str.clone())
-                .await
-                .unwrap()
-                .is_some()
-            {
-                log::debug!(
-                    "object {} is already in the inventory, skipping it",
-                    hash_str
-                );
-                continue;
+        let mut known = HashSet::new();
+        for obj in &objects {
+            let hash = bs58::encode(&obj.hash).into_string();
+            match retry_db!(self.inventory_repo.get_object(hash.clone())) {
+                Ok(Some(_)) => _ = known.insert(hash),
+                Ok(None) => {}
+                Err(e) => {
+                    log::error!("Failed to look up received objects: {}", e);
+                    return;
+                }
             }
+        }
+        let actions = self.engine.on_objects(peer, objects, &known, Utc::now());
+        self.perform(actions).await;
+    }
 
-            let target = pow::get_pow_target(
-                &obj,
-                pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE,
-                pow::NETWORK_MIN_EXTRA_BYTES,
-            );
-            let pow_check_res =
-                pow::check_pow(target, BigUint::from_bytes_be(&obj.nonce), obj.hash.clone());
-            if pow_check_res.is_err() {
-                log::warn!("object {:?} has invalid nonce! skipping it", hash_str);
-                continue;
+    async fn handle_get_data(&self, payload: MessagePayload) -> NetworkMessage {
+        let mut objects: Vec<Object> = Vec::new();
+
+        for hash in ProtocolEngine::requested_objects(payload) {
+            match self.inventory_repo.get_object(hash).await {
+                Ok(Some(obj)) => objects.push(obj),
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to get requested object: {}", e),
             }
+        }
 
-            self.inventory_repo
-                .store_object(obj.clone())
-                .await
-                .expect("db won't fail");
+        ProtocolEngine::objects_reply(objects)
+    }
 
-            let handler_result = match &obj.kind {
-                ObjectKind::Msg { encrypted: _ } => self.handle_msg_object(obj.clone()).await,
-                ObjectKind::Broadcast {
-                    tag: _,
-                    encrypted: _,
-                } => Err("we don't support broadcast at the moment, skipping it...".into()),
-                ObjectKind::Getpubkey { tag: _ } => {
-                    self.handle_get_pubkey_object(obj.clone()).await
+    /// Carry out the actions in order. Actions resulting from processing of an object
+    /// are performed before the ones following it.
+    async fn perform(&mut self, actions: Vec<Action>) {
+        let mut queue = VecDeque::from(actions);
+        while let Some(action) = queue.pop_front() {
+            let res: Result<(), Box<dyn Error>> = match action {
+                Action::StoreObject(object) => {
+                    retry_db!(self.inventory_repo.store_object(object.clone())).map_err(Box::from)
+                }
+                Action::ProcessObject(object) => self.process_object(object).await.map(|actions| {
+                    for action in actions.into_iter().rev() {
+                        queue.push_front(action);
+                    }
+                }),
+                Action::Announce(objects) => {
+                    self.offer_inv(objects).await;
+                    Ok(())
+                }
+                Action::BanPeer { peer, reason } => {
+                    self.worker_event_sender
+                        .send(WorkerCommand::BanMisbehavingPeer { peer, reason })
+                        .await
+                        .expect("receiver not to be dropped");
+                    Ok(())
+                }
+                Action::EnqueuePoW(object) => {
+                    self.enqueue_pow(object).await;
+                    Ok(())
+                }
+                Action::PublishPubkey(identity) => self.publish_pubkey(identity).await,
+                Action::UpdatePubkey(update) => self.update_pubkey(update).await,
+                Action::RotateKeys { update, chain } => {
+                    self.rotate_contact_keys(update, chain).await
+                }
+                Action::SaveMessage {
+                    hash,
+                    msg,
+                    signature,
+                    verification_error,
+                    quarantined,
+                } => {
+                    self.save_received_msg(hash, msg, signature, verification_error, quarantined)
+                        .await
                 }
-                ObjectKind::Pubkey {
-                    tag: _,
-                    encrypted: _,
-                } => self.handle_pubkey_object(obj.clone()).await,
             };
-            if let Err(r) = handler_result {
-                log::error!("{:?}", r.to_string());
-                continue;
+            if let Err(e) = res {
+                log::error!("{:?}", e.to_string());
             }
         }
+    }
 
-        self.offer_inv().await;
+    /// Pass the object to the engine along with the local state it might concern
+    async fn process_object(&mut self, object: Object) -> Result<Vec<Action>, Box<dyn Error>> {
+        match &object.kind {
+            ObjectKind::Msg { encrypted, .. } => {
+                if encrypted.len() == ACK_DATA_LENGTH {
+                    let delivered = self
+                        .message_repo
+                        .mark_as_delivered(encrypted.clone())
+                        .await?;
+                    if let Some(msg) = delivered {
+                        log::debug!("received acknowledgement for one of our messages");
+                        self.worker_event_sender
+                            .send(WorkerCommand::MessageStatusChanged {
+                                event: MessageStatusEvent::with_message(msg),
+                            })
+                            .await
+                            .expect("receiver not to be dropped");
+                        return Ok(Vec::new());
+                    }
+                }
+                let identities = self.address_repo.get_identities().await?;
+                let device_keys: Vec<(String, ecies::SecretKey)> = self
+                    .address_repo
+                    .get_own_device_keys()
+                    .await?
+                    .into_iter()
+                    .filter_map(|key| {
+                        let secret_key = key
+                            .private_key
+                            .and_then(|k| ecies::SecretKey::parse_slice(&k).ok())?;
+                        Some((key.address, secret_key))
+                    })
+                    .collect();
+                let context = self.msg_context(&identities).await?;
+                self.engine
+                    .on_msg(&object, &identities, &device_keys, &context)
+            }
+            ObjectKind::Broadcast { .. } => {
+                let subscriptions: Vec<Address> = self
+                    .address_repo
+                    .get_subscriptions()
+                    .await?
+                    .into_iter()
+                    .filter_map(|s| Address::with_string_repr(&s.address).ok())
+                    .collect();
+                if subscriptions.is_empty() {
+                    return Ok(Vec::new());
+                }
+                let context = self.msg_context(&[]).await?;
+                self.engine
+                    .on_broadcast(&object, &subscriptions, &context.rotated)
+            }
+            ObjectKind::Getpubkey { tag } => {
+                let tag_str = bs58::encode(tag).into_string();
+                let responded_at = self
+                    .address_repo
+                    .get_getpubkey_responded_at(tag_str.clone())
+                    .await?;
+                let identities = self.address_repo.get_identities().await?;
+                let now = Utc::now();
+                let actions = self
+                    .engine
+                    .on_getpubkey(&object, identities, responded_at, now)?;
+                // repeated requests of the tag are ignored until the interval passes
+                if !actions.is_empty() {
+                    self.address_repo
+                        .update_getpubkey_responded_at(tag_str, now)
+                        .await?;
+                }
+                Ok(actions)
+            }
+            ObjectKind::Pubkey { tag, .. } => {
+                let tag_str = bs58::encode(tag).into_string();
+                let result = self
+                    .address_repo
+                    .get_by_ripe_or_tag(tag_str.clone())
+                    .await?;
+                match result {
+                    Some(address) => self.engine.on_pubkey(&object, &address),
+                    None => {
+                        log::debug!("no such address with tag {} in local db", tag_str);
+                        Ok(Vec::new()) // just ignore it
+                    }
+                }
+            }
+            ObjectKind::KeyUpdate { tag, .. } => {
+                let tag_str = bs58::encode(tag).into_string();
+                let result = self.address_repo.get_by_ripe_or_tag(tag_str).await?;
+                let Some(address) = result else {
+                    return Ok(Vec::new());
+                };
+                let known_rotations = self
+                    .address_repo
+                    .get_key_rotations(address.string_repr.clone())
+                    .await?
+                    .len();
+                self.engine
+                    .on_key_update(&object, &address, known_rotations)
+            }
+        }
     }
 
-    async fn handle_pubkey_object(&mut self, object: Object) -> Result<(), Box<dyn Error>> {
-        let (tag, encrypted) = if let ObjectKind::Pubkey { tag, encrypted } = object.kind {
-            (tag, encrypted)
-        } else {
-            return Err("incorrent object kind!".into());
-        };
+    /// Former keys of identities still in the grace period, addresses whose keys
+    /// were rotated and known senders, which received messages are checked against
+    async fn msg_context(&self, identities: &[Address]) -> Result<MsgContext, Box<dyn Error>> {
+        let since = Utc::now() - self.engine.config().key_rotation_grace_period;
+        let retired = self
+            .address_repo
+            .get_retired_keys(since)
+            .await?
+            .into_iter()
+            .filter_map(|r| {
+                let secret_key = r
+                    .private_encryption_key
+                    .and_then(|k| ecies::SecretKey::parse_slice(&k).ok())?;
+                Some((r.address, secret_key))
+            })
+            .collect();
+        let contacts = self.address_repo.get_contacts().await?;
+        let known_senders = identities
+            .iter()
+            .chain(&contacts)
+            .map(|a| a.string_repr.clone())
+            .collect();
+        let rotated = identities
+            .iter()
+            .cloned()
+            .chain(contacts)
+            .filter(|a| a.keys_rotated_at.is_some())
+            .collect();
+        Ok(MsgContext {
+            retired,
+            rotated,
+            known_senders,
+        })
+    }
 
-        let tag_str = bs58::encode(&tag).into_string();
+    async fn update_pubkey(&mut self, update: PubkeyUpdate) -> Result<(), Box<dyn Error>> {
         let result = self
             .address_repo
-            .get_by_ripe_or_tag(tag_str.clone())
-            .await
-            .expect("repo not to fail");
-        let decryption_result = match result {
-            Some(a) => {
-                let dec_result = ecies::decrypt(&a.public_decryption_key.serialize(), &encrypted);
-                dec_result
-            }
-            None => {
-                log::debug!("no such address with tag {} in local db", tag_str);
-                return Ok(());
-            } // just ignore it
-        };
-        let data: UnencryptedPubkey = match decryption_result {
-            Ok(d) => serde_cbor::from_slice(&d).expect("pubkey msg in correct format!"),
-            Err(_) => {
-                log::debug!("failed to decrypt pubkey object with tag {}", tag_str);
-                return Ok(());
-            } // just ignore it
-        };
-
-        self.address_repo
             .update_public_keys(
-                tag_str.clone(),
-                ecies::PublicKey::parse_slice(&data.public_signing_key, None)
-                    .expect("public signing key parses correctly"),
-                ecies::PublicKey::parse_slice(&data.public_encryption_key, None)
-                    .expect("public encryption key parses correctly"),
+                update.tag.clone(),
+                update.public_signing_key,
+                update.public_encryption_key,
+                update.pinned,
             )
-            .await
-            .expect("repo not to fail");
-
-        self.pubkey_notifier_sink.send(tag_str).await.unwrap();
-
-        Ok(())
+            .await?;
+        if result == PublicKeysUpdate::NotFound {
+            log::debug!("address {} was deleted meanwhile", update.address);
+            return Ok(());
+        }
+        if result == PublicKeysUpdate::Pinned {
+            log::warn!(
+                "received different pubkey for pinned contact {}, ignoring it. Re-pin the contact to accept new keys",
+                update.address
+            );
+            let event = KeyMismatchEvent {
+                address: update.address,
+                received_at: Utc::now(),
+            };
+            self.worker_event_sender
+                .send(WorkerCommand::KeyMismatch { event })
+                .await
+                .expect("receiver not to be dropped");
+            return Ok(());
+        }
+        self.save_pubkey_details(update).await
     }
 
-    async fn handle_get_pubkey_object(&mut self, object: Object) -> Result<(), Box<dyn Error>> {
-        let tag = if let ObjectKind::Getpubkey { tag } = object.kind {
-            tag
-        } else {
-            return Err("incorrect object kind!".into());
-        };
-        let identities = self
+    async fn rotate_contact_keys(
+        &mut self,
+        update: PubkeyUpdate,
+        chain: Vec<KeyEndorsement>,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(mut contact) = self
             .address_repo
-            .get_identities()
-            .await
-            .expect("repo not to fail");
-        for i in identities {
-            if i.tag == tag {
-                log::debug!("someone requested our pubkey! sending it out...");
-                // FIXME only send pubkey if it wasn't sent in the last 28 days
-                let ttl = chrono::Duration::days(28);
-                let expires = Utc::now() + ttl;
-                let serialized_psk = i.public_signing_key.unwrap().serialize();
-                let serialized_pek = i.public_encryption_key.unwrap().serialize();
-
-                let unencrypted_pubkey = UnencryptedPubkey {
-                    behaviour_bitfield: 0,
-                    public_signing_key: serialized_psk.to_vec(),
-                    public_encryption_key: serialized_pek.to_vec(),
-                };
+            .get_by_ripe_or_tag(update.address.clone())
+            .await?
+        else {
+            return Ok(());
+        };
+        contact.public_signing_key = Some(update.public_signing_key);
+        contact.public_encryption_key = Some(update.public_encryption_key);
+        contact.pubkey_received_at = Some(Utc::now());
+        contact.keys_rotated_at = Some(Utc::now());
+        let rotations = chain
+            .into_iter()
+            .enumerate()
+            .map(|(i, k)| models::KeyRotation {
+                address: update.address.clone(),
+                position: i as i64,
+                public_signing_key: k.public_signing_key,
+                public_encryption_key: k.public_encryption_key,
+                private_encryption_key: None,
+                signature: k.signature,
+                rotated_at: Utc::now(),
+            })
+            .collect();
+        self.address_repo.rotate_keys(contact, rotations).await?;
+        log::info!("keys of {} were rotated", update.address);
+        self.save_pubkey_details(update).await
+    }
 
-                let obj = Object::with_signing(
-                    &i,
-                    ObjectKind::Pubkey {
-                        tag: i.tag.clone(),
-                        encrypted: NodeWorker::serialize_and_encrypt_payload(
-                            unencrypted_pubkey,
-                            &i.public_decryption_key,
-                        ),
-                    },
-                    expires,
-                );
-                self.enqueue_pow(obj).await;
-            }
+    /// Save PoW difficulty and devices of the address along with its new keys
+    /// and notify messages waiting for them
+    async fn save_pubkey_details(&mut self, update: PubkeyUpdate) -> Result<(), Box<dyn Error>> {
+        self.address_repo
+            .update_pow_difficulty(
+                update.tag.clone(),
+                update.nonce_trials_per_byte,
+                update.extra_bytes,
+            )
+            .await?;
+        self.address_repo
+            .update_stranger_pow_difficulty(
+                update.tag.clone(),
+                update.stranger_nonce_trials_per_byte,
+                update.stranger_extra_bytes,
+            )
+            .await?;
+        if let Some(device_keys) = update.device_keys {
+            self.address_repo
+                .replace_device_keys(update.address, device_keys)
+                .await?;
         }
 
+        self.pubkey_notifier_sink.send(update.tag).await.unwrap();
         Ok(())
     }
 
-    async fn handle_msg_object(&mut self, object: Object) -> Result<(), Box<dyn Error>> {
-        let encrypted = if let ObjectKind::Msg { encrypted } = object.kind {
-            encrypted
-        } else {
-            return Err("incorrect object kind!".into());
-        };
-        let identities = self
-            .address_repo
-            .get_identities()
-            .await
-            .expect("Address repo not to fail");
-        for i in identities {
-            let decryption_result =
-                ecies::decrypt(&i.private_encryption_key.unwrap().serialize(), &encrypted);
-            if let Ok(msg) = decryption_result {
-                log::debug!("message object successfully decrypted! saving it...");
-                match serde_cbor::from_slice(msg.as_slice()) {
-                    Ok(msg) => {
-                        self.message_repo
-                            .save(
-                                bs58::encode(&object.hash).into_string(),
-                                msg,
-                                object.signature.clone(),
-                            )
-                            .await
-                            .expect("repo not to fail");
-                    }
-                    Err(e) => {
-                        log::error!("received malformed message! skipping it");
-                        return Err(Box::new(e));
-                    }
-                }
-            } else {
-                log::debug!(
-                    "message object with hash {} failed to decrypt, skipping...",
-                    bs58::encode(object.hash.clone()).into_string()
-                );
-                continue;
-            }
-        }
+    async fn publish_pubkey(&mut self, identity: Address) -> Result<(), Box<dyn Error>> {
+        let expires = Utc::now() + self.engine.config().pubkey_ttl;
+        let obj = identity_pubkey_object(self.address_repo.as_ref(), &identity, expires).await?;
+        self.enqueue_pow(obj).await;
+        self.address_repo
+            .update_pubkey_published_at(identity.string_repr.clone(), Utc::now())
+            .await?;
         Ok(())
     }
 
-    async fn offer_inv(&mut self) {
-        let inventory = self
-            .inventory_repo
-            .get()
-            .await
-            .expect("repo not to be failed");
-
-        let msg = NetworkMessage {
-            command: MessageCommand::Inv,
-            payload: MessagePayload::Inv { inventory },
+    async fn save_received_msg(
+        &mut self,
+        hash: String,
+        msg: UnencryptedMsg,
+        signature: Vec<u8>,
+        verification_error: Option<String>,
+        quarantined: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        // the object isn't processed again, so the message would be lost
+        let mut msg = retry_db!(self.message_repo.save(
+            hash.clone(),
+            msg.clone(),
+            signature.clone(),
+            verification_error.clone()
+        ))?;
+        if quarantined {
+            self.message_repo
+                .move_to_quarantine(msg.hash.clone())
+                .await?;
+            msg.folder = Some(QUARANTINE_FOLDER.to_string());
+        }
+        let Some(notify) = self.apply_filter_rules(&mut msg).await? else {
+            return Ok(());
         };
-        let (sender, receiver) = oneshot::channel();
+        let mut event = MessageStatusEvent::with_message(msg);
+        event.notify = notify;
         self.worker_event_sender
-            .send(WorkerCommand::BroadcastMsgByPubSub { sender, msg })
+            .send(WorkerCommand::MessageStatusChanged { event })
             .await
             .expect("receiver not to be dropped");
-        task::spawn(async move {
-            receiver
-                .await
-                .unwrap()
-                .expect("msg to be published in pubsub");
-        });
+        Ok(())
     }
 
-    async fn handle_get_data(&self, payload: MessagePayload) -> NetworkMessage {
-        let inv = if let MessagePayload::GetData { inventory } = payload {
-            inventory
-        } else {
-            Vec::new()
-        };
-
-        let mut objects: Vec<Object> = Vec::new();
-
-        for hash in inv {
-            if let Some(obj) = self
-                .inventory_repo
-                .get_object(hash)
-                .await
-                .expect("Repository not to fail")
-            {
-                objects.push(obj);
+    /// Perform actions of the filtering rules the received message matches. Returns
+    /// whether a rule asks to notify about it, or `None` if the message is deleted.
+    async fn apply_filter_rules(
+        &mut self,
+        msg: &mut models::Message,
+    ) -> Result<Option<bool>, Box<dyn Error>> {
+        let rules = self.message_repo.get_filter_rules().await?;
+        let (subject, _) = extract_text(&msg.data);
+        let matched: Vec<models::FilterRule> = rules
+            .into_iter()
+            .filter(|r| r.enabled && r.matches(msg, &subject))
+            .collect();
+        if matched.iter().any(|r| r.delete_message) {
+            log::debug!("message {} is deleted by a filtering rule", msg.hash);
+            self.message_repo.remove_message(msg.hash.clone()).await?;
+            return Ok(None);
+        }
+        if matched.iter().any(|r| r.mark_read) {
+            self.message_repo.mark_read(msg.hash.clone(), true).await?;
+            msg.read = true;
+        }
+        // the latest rule wins if several of them move the message
+        match matched.iter().rev().find_map(|r| r.move_to.as_deref()) {
+            Some(TRASH_FOLDER) => {
+                self.message_repo.move_to_trash(msg.hash.clone()).await?;
+                msg.folder = Some(TRASH_FOLDER.to_string());
+                msg.deleted_at = Some(Utc::now());
             }
+            Some(QUARANTINE_FOLDER) => {
+                self.message_repo
+                    .move_to_quarantine(msg.hash.clone())
+                    .await?;
+                msg.folder = Some(QUARANTINE_FOLDER.to_string());
+            }
+            _ => {}
         }
+        Ok(Some(matched.iter().any(|r| r.notify)))
+    }
 
-        log::debug!("requested {} objects from this node", objects.len());
-
-        NetworkMessage {
-            command: MessageCommand::Objects,
-            payload: MessagePayload::Objects { objects },
+    /// Announce newly received objects to other peers, the rest of the inventory
+    /// is known to them already or will be requested page by page. Types of objects
+    /// are announced too, so that peers filtering them don't request unwanted ones.
+    async fn offer_inv(&mut self, objects: Vec<(String, i64, u8)>) {
+        for chunk in objects.chunks(MAX_INV_HASHES) {
+            let msg = NetworkMessage {
+                command: MessageCommand::Inv,
+                payload: MessagePayload::inv(chunk.to_vec(), None),
+            };
+            let (sender, receiver) = oneshot::channel();
+            self.worker_event_sender
+                .send(WorkerCommand::BroadcastMsgByPubSub { sender, msg })
+                .await
+                .expect("receiver not to be dropped");
+            task::spawn(async move {
+                if let Err(e) = receiver.await.unwrap() {
+                    log::error!("Failed to offer inventory: {}", e);
+                }
+            });
         }
     }
 
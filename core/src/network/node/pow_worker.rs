@@ -1,30 +1,192 @@
-use futures::{channel::mpsc, select, SinkExt, StreamExt};
-use queues::{queue, IsQueue, Queue};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_std::task;
+
+use chrono::{DateTime, Utc};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::AbortHandle,
+    select, SinkExt, StreamExt,
+};
 
 use crate::{
-    network::{address::Address, messages::Object},
+    config::Config,
+    network::messages::{Object, ObjectKind},
+    pow::{self, PoWEngine, PoWError},
     repositories::{
-        address::AddressRepositorySync, inventory::InventoryRepositorySync,
-        message::MessageRepositorySync, sqlite::models::MessageStatus,
+        address::AddressRepositorySync,
+        inventory::InventoryRepositorySync,
+        message::MessageRepositorySync,
+        sqlite::models::{self, MessageStatus},
     },
 };
 
-use super::worker::{create_object_from_msg, WorkerCommand};
+use super::{
+    pubkeys::device_public_keys,
+    worker::{
+        create_broadcast_object, create_object_from_msg, message_ttl, MessageStatusEvent,
+        WorkerCommand, BROADCAST_RECIPIENT,
+    },
+};
+
+/// How long the engine is benchmarked for estimates
+const BENCHMARK_DURATION: Duration = Duration::from_millis(500);
+/// Approximate size of a msg object besides its body: keys and signature of the sender,
+/// ack object and encryption
+const MSG_OBJECT_OVERHEAD: usize = 600;
 
 pub enum ProofOfWorkWorkerCommand {
-    EnqueuePoW { object: Object },
-    NonceCalculated { object: Object },
+    EnqueuePoW {
+        object: Object,
+    },
+    NonceCalculated {
+        object: Object,
+    },
+    PoWFailed {
+        object: Object,
+        error: PoWError,
+    },
+    GetQueue {
+        sender: oneshot::Sender<Vec<PoWQueueItem>>,
+    },
+    GetStats {
+        sender: oneshot::Sender<PoWStats>,
+    },
+    /// Estimate PoW of a message with the body of given size
+    Estimate {
+        msg_size: usize,
+        ttl: Option<chrono::Duration>,
+        sender: oneshot::Sender<PoWEstimate>,
+    },
+    /// Stop PoW of the object and delete it, along with its message if `remove_message` is set
+    CancelObject {
+        hash: String,
+        remove_message: bool,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    },
+    /// Stop PoW of the running objects and don't start new ones until unpaused. The
+    /// objects are kept in the queue (and in the inventory without nonce), their PoW
+    /// starts over once resumed.
+    SetPaused {
+        paused: bool,
+    },
+    /// Stop PoW and leave the queue in the db, so that it's resumed on the next start
+    Shutdown {
+        sender: oneshot::Sender<()>,
+    },
+}
+
+/// Object in the PoW queue
+#[derive(Debug, Clone)]
+pub struct PoWQueueItem {
+    pub hash: String,
+    /// Kind of the object, e.g. `msg` or `getpubkey`
+    pub kind: String,
+    pub enqueued_at: DateTime<Utc>,
+    /// Rough estimate (from 0.0 to 1.0) of the PoW progress of the object being processed.
+    /// `None` for waiting objects, or if the hash rate isn't known yet.
+    pub progress: Option<f64>,
+}
+
+/// Counters of the PoW worker, e.g. for monitoring
+#[derive(Debug, Clone, Default)]
+pub struct PoWStats {
+    /// Number of objects waiting for PoW or being processed
+    pub queue_length: usize,
+    /// Hash rate measured on the last finished PoW, `None` until the first one is finished
+    pub trials_per_second: Option<f64>,
+    /// Objects whose PoW is finished since start
+    pub completed: u64,
+    /// PoW is paused, e.g. in low-power mode
+    pub paused: bool,
+}
+
+/// Expected PoW of an outgoing message
+#[derive(Debug, Clone)]
+pub struct PoWEstimate {
+    /// Expected number of nonces to try, i.e. difficulty of the object
+    pub expected_trials: f64,
+    /// Hash rate of the PoW engine measured by its benchmark
+    pub trials_per_second: f64,
+    pub expected_duration: Duration,
+}
+
+struct QueuedObject {
+    object: Object,
+    enqueued_at: DateTime<Utc>,
+}
+
+impl QueuedObject {
+    fn hash(&self) -> String {
+        bs58::encode(&self.object.hash).into_string()
+    }
+
+    /// Pubkey requests and replies are small and needed before messages can be sent,
+    /// so they go ahead of messages and broadcasts
+    fn is_interactive(&self) -> bool {
+        matches!(
+            self.object.kind,
+            ObjectKind::Getpubkey { .. } | ObjectKind::Pubkey { .. } | ObjectKind::KeyUpdate { .. }
+        )
+    }
+
+    fn to_queue_item(&self, progress: Option<f64>) -> PoWQueueItem {
+        let kind = match self.object.kind {
+            ObjectKind::Msg { .. } => "msg",
+            ObjectKind::Broadcast { .. } => "broadcast",
+            ObjectKind::Getpubkey { .. } => "getpubkey",
+            ObjectKind::Pubkey { .. } => "pubkey",
+            ObjectKind::KeyUpdate { .. } => "key update",
+        };
+        PoWQueueItem {
+            hash: self.hash(),
+            kind: kind.to_string(),
+            enqueued_at: self.enqueued_at,
+            progress,
+        }
+    }
+}
+
+struct RunningPoW {
+    queued: QueuedObject,
+    started_at: Instant,
+    /// Expected number of nonces to try before the target is reached
+    expected_trials: f64,
+    abort_handle: AbortHandle,
 }
 
 pub struct ProofOfWorkWorker {
     inventory: Box<InventoryRepositorySync>,
     message_repo: Box<MessageRepositorySync>,
     address_repo: Box<AddressRepositorySync>,
-    node_worker_sink: mpsc::Sender<WorkerCommand>,
+    node_worker_sink: mpsc::UnboundedSender<WorkerCommand>,
     command_sink: mpsc::Sender<ProofOfWorkWorkerCommand>,
     command_receiver: mpsc::Receiver<ProofOfWorkWorkerCommand>,
-    is_pow_running: bool,
-    waiting_objects: Queue<Object>,
+    running: Vec<RunningPoW>,
+    /// Interactive objects are kept before all the others
+    waiting_objects: VecDeque<QueuedObject>,
+    /// Max number of objects processed at the same time
+    concurrency: usize,
+    /// No PoW is started while it's set
+    paused: bool,
+    /// Hash rate measured on the last finished PoW
+    trials_per_second: Option<f64>,
+    /// Objects whose PoW is finished since start
+    completed: u64,
+    /// Hash rate measured by the benchmark of the engine, it's run once on the first estimate
+    benchmark_trials_per_second: Option<f64>,
+    /// `nonce_trials_per_byte` and `extra_bytes` of outgoing objects
+    pow_difficulty: (i32, i32),
+    /// See `Config::pow_trials_divisor`
+    pow_trials_divisor: u64,
+    engine: Arc<dyn PoWEngine>,
+    /// TTL of message objects, which are re-created after their pubkey is received
+    msg_ttl: chrono::Duration,
 }
 
 impl ProofOfWorkWorker {
@@ -32,7 +194,8 @@ impl ProofOfWorkWorker {
         inv: Box<InventoryRepositorySync>,
         msg_repo: Box<MessageRepositorySync>,
         addr_repo: Box<AddressRepositorySync>,
-        worker_sink: mpsc::Sender<WorkerCommand>,
+        worker_sink: mpsc::UnboundedSender<WorkerCommand>,
+        config: &Config,
     ) -> (ProofOfWorkWorker, mpsc::Sender<ProofOfWorkWorkerCommand>) {
         let (cmd_sink, cmd_receiver) = mpsc::channel(3);
 
@@ -44,74 +207,113 @@ impl ProofOfWorkWorker {
                 node_worker_sink: worker_sink,
                 command_sink: cmd_sink.clone(),
                 command_receiver: cmd_receiver,
-                waiting_objects: queue![],
-                is_pow_running: false,
+                waiting_objects: VecDeque::new(),
+                running: Vec::new(),
+                concurrency: config.pow_concurrency.max(1),
+                paused: false,
+                trials_per_second: None,
+                completed: 0,
+                benchmark_trials_per_second: None,
+                pow_difficulty: config.outgoing_pow_difficulty(),
+                pow_trials_divisor: config.pow_trials_divisor(),
+                engine: pow::engine(config.pow_engine),
+                msg_ttl: config.msg_ttl,
             },
             cmd_sink,
         );
     }
 
-    pub async fn run(mut self) {
-        let objects = self
-            .inventory
-            .get_missing_pow_objects()
-            .await
-            .expect("db won't fail");
-        let msgs = self
-            .message_repo
-            .get_messages_by_status(MessageStatus::WaitingForPOW)
-            .await
-            .expect("db won't fail");
-        for o in objects {
-            self.enqueue_pow(o);
-        }
-        for m in msgs {
-            let identity = self
-                .address_repo
-                .get_by_ripe_or_tag(m.sender.clone())
-                .await
-                .expect("db won't fail")
-                .expect("address exists in db");
-            let recipient: Address = self
-                .address_repo
-                .get_by_ripe_or_tag(m.recipient.clone())
-                .await
-                .expect("db won't fail")
-                .expect("address exists in db");
-
-            let obj = create_object_from_msg(&identity, &recipient, m.clone());
-            self.message_repo
-                .update_hash(m.hash, bs58::encode(obj.hash.clone()).into_string())
-                .await
-                .expect("db won't fail");
-            self.inventory
-                .store_object(obj.clone())
-                .await
-                .expect("db won't fail");
-            self.enqueue_pow(obj);
+    /// Resume PoW left by the previous run, must be done before the node handles
+    /// commands, so that objects of new messages aren't taken for pending ones
+    pub async fn resume(&mut self) {
+        if let Err(e) = self.resume_pending().await {
+            log::error!("Failed to resume PoW of pending objects: {}", e);
         }
+    }
 
+    pub async fn run(mut self) {
         loop {
             select! {
                 command = self.command_receiver.select_next_some() => {
                     match command {
                         ProofOfWorkWorkerCommand::EnqueuePoW { object } => {
-                            self.inventory.store_object(object.clone()).await.expect("db won't fail");
+                            let object = self.with_pow_difficulty(object);
+                            // the object isn't persisted then, it's dropped instead of being done without a trace
+                            if let Err(e) = retry_db!(self.inventory.store_object(object.clone())) {
+                                let hash = bs58::encode(&object.hash).into_string();
+                                log::error!("Failed to store object {}, dropping it: {}", hash, e);
+                                // message would wait for PoW forever otherwise
+                                self.fail_message(hash, format!("object can't be stored: {}", e)).await;
+                                continue;
+                            }
                             self.enqueue_pow(object);
                         },
                         ProofOfWorkWorkerCommand::NonceCalculated { object } => {
-                            self.inventory.update_nonce(bs58::encode(object.hash.clone()).into_string(), object.nonce.clone())
-                                .await
-                                .expect("db won't fail");
+                            // PoW might finish right before it's cancelled
+                            let running = match self.take_running(&object) {
+                                Some(r) => r,
+                                None => continue,
+                            };
+                            let elapsed = running.started_at.elapsed().as_secs_f64();
+                            if elapsed > 0.0 {
+                                self.trials_per_second = Some(running.expected_trials / elapsed);
+                            }
+                            self.completed += 1;
+                            // the object stays without nonce then, so its PoW is done again on the next start
+                            if let Err(e) = retry_db!(self.inventory.update_nonce(bs58::encode(&object.hash).into_string(), object.nonce.clone())) {
+                                log::error!("Failed to save nonce of object {}: {}", bs58::encode(&object.hash).into_string(), e);
+                                self.schedule();
+                                continue;
+                            }
                             self.node_worker_sink.send(WorkerCommand::NonceCalculated { obj: object }).await.expect("command successfully sent");
-                            match self.waiting_objects.remove() {
-                                Ok(o) => {
-                                    o.do_proof_of_work(self.command_sink.clone())
-                                },
-                                Err(_) => {
-                                    self.is_pow_running = false;
-                                }
+                            self.schedule();
+                        },
+                        ProofOfWorkWorkerCommand::PoWFailed { object, error } => {
+                            if self.take_running(&object).is_none() {
+                                continue;
+                            }
+                            let hash = bs58::encode(&object.hash).into_string();
+                            // don't retry it on the next start, user can retry a failed message manually
+                            if let Err(e) = retry_db!(self.inventory.remove_object(hash.clone())) {
+                                log::error!("Failed to remove object {}: {}", hash, e);
                             }
+                            // user's cancellation aborts the PoW, so it isn't reported here
+                            let reason = match error {
+                                PoWError::Cancelled => {
+                                    log::warn!("PoW for object {} was cancelled by the engine", hash);
+                                    "PoW was cancelled by the engine".to_string()
+                                }
+                                e => {
+                                    log::error!("PoW for object {} failed: {}", hash, e);
+                                    e.to_string()
+                                }
+                            };
+                            self.fail_message(hash, reason).await;
+                            self.schedule();
+                        }
+                        ProofOfWorkWorkerCommand::GetQueue { sender } => {
+                            _ = sender.send(self.get_queue());
+                        }
+                        ProofOfWorkWorkerCommand::GetStats { sender } => {
+                            _ = sender.send(PoWStats {
+                                queue_length: self.running.len() + self.waiting_objects.len(),
+                                trials_per_second: self.trials_per_second,
+                                completed: self.completed,
+                                paused: self.paused,
+                            });
+                        }
+                        ProofOfWorkWorkerCommand::Estimate { msg_size, ttl, sender } => {
+                            _ = sender.send(self.estimate(msg_size, ttl).await);
+                        }
+                        ProofOfWorkWorkerCommand::CancelObject { hash, remove_message, sender } => {
+                            let res = self.cancel_object(hash, remove_message).await;
+                            _ = sender.send(res);
+                        }
+                        ProofOfWorkWorkerCommand::SetPaused { paused } => self.set_paused(paused),
+                        ProofOfWorkWorkerCommand::Shutdown { sender } => {
+                            self.shutdown().await;
+                            _ = sender.send(());
+                            return;
                         }
                     }
                 }
@@ -119,12 +321,275 @@ impl ProofOfWorkWorker {
         }
     }
 
+    /// Remove the object from the running ones, if it's still running
+    fn take_running(&mut self, object: &Object) -> Option<RunningPoW> {
+        let i = self
+            .running
+            .iter()
+            .position(|r| r.queued.object.hash == object.hash)?;
+        Some(self.running.remove(i))
+    }
+
+    fn get_queue(&self) -> Vec<PoWQueueItem> {
+        let running = self.running.iter().map(|r| {
+            let progress = self.trials_per_second.map(|rate| {
+                (r.started_at.elapsed().as_secs_f64() * rate / r.expected_trials).min(0.99)
+            });
+            r.queued.to_queue_item(progress)
+        });
+        running
+            .chain(self.waiting_objects.iter().map(|q| q.to_queue_item(None)))
+            .collect()
+    }
+
+    async fn estimate(&mut self, msg_size: usize, ttl: Option<chrono::Duration>) -> PoWEstimate {
+        let trials_per_second = match self.benchmark_trials_per_second {
+            Some(rate) => rate,
+            None => {
+                let engine = self.engine.clone();
+                let rate = task::spawn_blocking(move || engine.benchmark(BENCHMARK_DURATION)).await;
+                log::debug!("PoW engine benchmark: {:.0} trials per second", rate);
+                *self.benchmark_trials_per_second.insert(rate)
+            }
+        };
+        let ttl = ttl.unwrap_or(self.msg_ttl).num_seconds().max(0) as u64;
+        let expected_trials = pow::expected_trials(
+            msg_size + MSG_OBJECT_OVERHEAD,
+            ttl,
+            self.pow_difficulty.0,
+            self.pow_difficulty.1,
+            self.pow_trials_divisor,
+        );
+        PoWEstimate {
+            expected_trials,
+            trials_per_second,
+            expected_duration: Duration::try_from_secs_f64(expected_trials / trials_per_second)
+                .unwrap_or(Duration::MAX),
+        }
+    }
+
+    async fn cancel_object(
+        &mut self,
+        hash: String,
+        remove_message: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(i) = self.running.iter().position(|r| r.queued.hash() == hash) {
+            let running = self.running.remove(i);
+            running.abort_handle.abort();
+            log::debug!("PoW for object {} was cancelled", hash);
+            self.schedule();
+        } else if let Some(i) = self.waiting_objects.iter().position(|q| q.hash() == hash) {
+            self.waiting_objects.remove(i);
+        } else {
+            return Err("no such object in PoW queue".into());
+        }
+
+        // object was never broadcasted, so it's safe to forget it
+        self.inventory
+            .remove_object(hash.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+        if remove_message {
+            self.message_repo
+                .remove_message(hash)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        if paused {
+            self.requeue_running();
+        } else {
+            self.schedule();
+        }
+    }
+
+    /// Abort running PoW and put the objects back in front of the queue, in the
+    /// order they were started
+    fn requeue_running(&mut self) {
+        for running in self.running.drain(..).rev() {
+            running.abort_handle.abort();
+            self.waiting_objects.push_front(running.queued);
+        }
+    }
+
+    /// Abort running PoW. Objects of messages are removed from the inventory, since
+    /// messages waiting for PoW get new objects (with fresh expiration time) on start.
+    /// Other objects stay in the inventory without nonce and are enqueued again on start.
+    async fn shutdown(&mut self) {
+        self.requeue_running();
+        for queued in self.waiting_objects.drain(..) {
+            if let ObjectKind::Msg { .. } = queued.object.kind {
+                if let Err(e) = retry_db!(self.inventory.remove_object(queued.hash())) {
+                    log::error!("Failed to remove object {}: {}", queued.hash(), e);
+                }
+            }
+        }
+    }
+
+    /// Enqueue objects left without nonce by the previous run. Objects of messages
+    /// waiting for PoW are re-created instead, so that they get fresh expiration time.
+    async fn resume_pending(&mut self) -> Result<(), String> {
+        let objects = retry_db!(self.inventory.get_missing_pow_objects())?;
+        let msgs = retry_db!(self
+            .message_repo
+            .get_messages_by_status(MessageStatus::WaitingForPOW))?;
+        for o in objects {
+            // objects of waiting messages are re-created below, so that PoW isn't done twice
+            let hash = bs58::encode(&o.hash).into_string();
+            if msgs.iter().any(|m| m.hash == hash) {
+                retry_db!(self.inventory.remove_object(hash.clone()))?;
+                continue;
+            }
+            self.enqueue_pow(o);
+        }
+        for m in msgs {
+            let obj = self
+                .create_message_object(&m)
+                .await
+                .map_err(|e| e.to_string());
+            let obj = match obj {
+                Ok(obj) => obj,
+                Err(e) => {
+                    log::error!("Failed to re-create object of message {}: {}", m.hash, e);
+                    retry_db!(self.message_repo.mark_as_failed(m.hash.clone(), e.clone()))?;
+                    continue;
+                }
+            };
+            let hash = bs58::encode(&obj.hash).into_string();
+            retry_db!(self.message_repo.update_hash(m.hash.clone(), hash.clone()))?;
+            retry_db!(self.inventory.store_object(obj.clone()))?;
+            self.enqueue_pow(obj);
+        }
+        Ok(())
+    }
+
+    /// Build a new object of the message waiting for PoW, with fresh expiration time
+    async fn create_message_object(
+        &mut self,
+        m: &models::Message,
+    ) -> Result<Object, Box<dyn Error>> {
+        let identity = self
+            .address_repo
+            .get_by_ripe_or_tag(m.sender.clone())
+            .await?
+            .ok_or("sender identity not found")?;
+        if m.recipient == BROADCAST_RECIPIENT {
+            return Ok(self.with_pow_difficulty(create_broadcast_object(
+                &identity,
+                m.clone(),
+                self.msg_ttl,
+            )));
+        }
+        let recipient = self
+            .address_repo
+            .get_by_ripe_or_tag(m.recipient.clone())
+            .await?
+            .ok_or("recipient address not found")?;
+        let device_keys =
+            device_public_keys(self.address_repo.as_ref(), recipient.string_repr.clone()).await?;
+        Ok(self.with_pow_difficulty(create_object_from_msg(
+            &identity,
+            &recipient,
+            &device_keys,
+            m.clone(),
+            message_ttl(self.msg_ttl, m.resend_count),
+        )))
+    }
+
+    /// Raise difficulty of the object to the configured one (but don't lower it,
+    /// since recipient might require more work)
+    fn with_pow_difficulty(&self, mut object: Object) -> Object {
+        object.nonce_trials_per_byte = object.nonce_trials_per_byte.max(self.pow_difficulty.0);
+        object.extra_bytes = object.extra_bytes.max(self.pow_difficulty.1);
+        object
+    }
+
+    /// Start PoW of waiting objects while there are free slots. Unless objects are
+    /// processed one by one, the last slot is reserved for interactive objects.
+    fn schedule(&mut self) {
+        while !self.paused && self.running.len() < self.concurrency {
+            let queued = match self.waiting_objects.front() {
+                Some(q) => q,
+                None => return,
+            };
+            let running_bulk = self
+                .running
+                .iter()
+                .filter(|r| !r.queued.is_interactive())
+                .count();
+            if !queued.is_interactive()
+                && self.concurrency > 1
+                && running_bulk >= self.concurrency - 1
+            {
+                return;
+            }
+            let queued = self
+                .waiting_objects
+                .pop_front()
+                .expect("queue is not empty");
+            self.start_pow(queued);
+        }
+    }
+
+    fn start_pow(&mut self, queued: QueuedObject) {
+        let object = &queued.object;
+        let target = pow::get_pow_target(
+            object,
+            object.nonce_trials_per_byte,
+            object.extra_bytes,
+            self.pow_trials_divisor,
+        );
+        let expected_trials =
+            2f64.powi(64) / u64::try_from(&target).unwrap_or(u64::MAX).max(1) as f64;
+        let abort_handle = object.clone().do_proof_of_work(
+            target,
+            self.engine.as_ref(),
+            self.command_sink.clone(),
+        );
+        self.running.push(RunningPoW {
+            queued,
+            started_at: Instant::now(),
+            expected_trials,
+            abort_handle,
+        });
+    }
+
+    /// Mark the message of the object failed, so that the user can retry it. Objects
+    /// which aren't messages (e.g. pubkeys) have no message to update.
+    async fn fail_message(&mut self, hash: String, reason: String) {
+        if let Err(e) = retry_db!(self
+            .message_repo
+            .mark_as_failed(hash.clone(), reason.clone()))
+        {
+            log::error!("Failed to update status of message {}: {}", hash, e);
+        }
+        let event = MessageStatusEvent::new(hash, MessageStatus::Failed);
+        self.node_worker_sink
+            .send(WorkerCommand::MessageStatusChanged { event })
+            .await
+            .expect("command successfully sent");
+    }
+
     fn enqueue_pow(&mut self, object: Object) {
-        if self.is_pow_running {
-            self.waiting_objects.add(object).unwrap();
+        let queued = QueuedObject {
+            object,
+            enqueued_at: Utc::now(),
+        };
+        if queued.is_interactive() {
+            // ahead of messages, but after interactive objects enqueued earlier
+            let i = self
+                .waiting_objects
+                .iter()
+                .position(|q| !q.is_interactive())
+                .unwrap_or(self.waiting_objects.len());
+            self.waiting_objects.insert(i, queued);
         } else {
-            object.do_proof_of_work(self.command_sink.clone());
-            self.is_pow_running = true;
+            self.waiting_objects.push_back(queued);
         }
+        self.schedule();
     }
 }
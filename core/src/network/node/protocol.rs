@@ -0,0 +1,752 @@
+//! Protocol logic of the node without any I/O. [`ProtocolEngine`] takes messages received
+//! from peers together with the local state they concern and returns [`Action`]s, which
+//! the handler of the node carries out with the repositories and channels.
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    iter,
+};
+
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use num_bigint::BigUint;
+
+use crate::{
+    config::{Config, NodeRole},
+    network::{
+        address::Address,
+        messages::{
+            InventoryCursor, KeyEndorsement, MessageCommand, MessagePayload, NetworkMessage,
+            Object, ObjectKind, UnencryptedKeyUpdate, UnencryptedMsg, UnencryptedPubkey,
+            MAX_INV_HASHES,
+        },
+        node::worker::{decrypt_and_deserialize_payload, PayloadError, MAX_DEVICE_KEYS},
+        validation,
+    },
+    pow,
+};
+
+/// Client nodes forget expired objects they've seen once there are more of them
+const MAX_SEEN_OBJECTS: usize = 100_000;
+/// Peer is banned once its misbehavior score reaches this value
+const MISBEHAVIOR_BAN_SCORE: u32 = 100;
+/// Minimal interval between full inventory requests to the same peer
+pub const INVENTORY_RESYNC_INTERVAL_SECONDS: i64 = 60;
+
+/// Violations of the protocol counted towards the misbehavior score of a peer
+#[derive(Debug, Clone, Copy)]
+pub enum Misbehavior {
+    InvalidPoW,
+    MalformedPayload,
+}
+
+impl Misbehavior {
+    fn penalty(&self) -> u32 {
+        match self {
+            Misbehavior::InvalidPoW => 25,
+            Misbehavior::MalformedPayload => 10,
+        }
+    }
+
+    fn ban_reason(&self) -> &'static str {
+        match self {
+            Misbehavior::InvalidPoW => "sent objects with invalid PoW",
+            Misbehavior::MalformedPayload => "sent malformed payloads",
+        }
+    }
+}
+
+/// Keys of a contact received in its pubkey
+#[derive(Debug, Clone)]
+pub struct PubkeyUpdate {
+    pub address: String,
+    pub tag: String,
+    pub public_signing_key: ecies::PublicKey,
+    pub public_encryption_key: ecies::PublicKey,
+    /// PoW difficulty required by the contact, at least the network minimum
+    pub nonce_trials_per_byte: i32,
+    pub extra_bytes: i32,
+    /// PoW difficulty the contact requires from strangers, at least the one above
+    pub stranger_nonce_trials_per_byte: i32,
+    pub stranger_extra_bytes: i32,
+    /// Keys of the other devices of the contact. `None` for own identities, whose
+    /// devices are managed locally.
+    pub device_keys: Option<Vec<Vec<u8>>>,
+    /// Keep previously received keys of the contact if they differ
+    pub pinned: bool,
+}
+
+/// Local state besides identities which received messages are checked against
+#[derive(Debug, Clone, Default)]
+pub struct MsgContext {
+    /// Former encryption keys of identities (by their address) still in the grace period
+    pub retired: Vec<(String, ecies::SecretKey)>,
+    /// Known addresses whose keys were rotated, so they don't hash to the sender
+    pub rotated: Vec<Address>,
+    /// Addresses of contacts and identities, which don't have to meet the PoW
+    /// difficulty required from strangers
+    pub known_senders: HashSet<String>,
+}
+
+/// Side effect of the protocol, carried out by the handler
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Store the object into the inventory
+    StoreObject(Object),
+    /// Look up the local state the object might be addressed to (identities, contacts,
+    /// sent messages) and pass the object to the engine along with it
+    ProcessObject(Object),
+    /// Announce new objects to other peers
+    Announce(Vec<(String, i64, u8)>),
+    BanPeer {
+        peer: PeerId,
+        reason: String,
+    },
+    /// Calculate PoW of the object and send it out
+    EnqueuePoW(Object),
+    /// Send out pubkey of the identity
+    PublishPubkey(Address),
+    /// Save keys of the contact and notify messages waiting for them
+    UpdatePubkey(PubkeyUpdate),
+    /// Save rotated keys of the contact along with its former keys endorsing them
+    RotateKeys {
+        update: PubkeyUpdate,
+        chain: Vec<KeyEndorsement>,
+    },
+    /// Save the received message, into Quarantine if `quarantined` is set
+    SaveMessage {
+        hash: String,
+        msg: UnencryptedMsg,
+        signature: Vec<u8>,
+        verification_error: Option<String>,
+        quarantined: bool,
+    },
+}
+
+pub struct ProtocolEngine {
+    config: Config,
+    /// Objects processed by the client node, which doesn't store them.
+    /// Hash and expiration time, so that they're not downloaded again.
+    seen_objects: HashMap<String, i64>,
+    /// Misbehavior scores of peers, decaying over time
+    misbehavior_scores: HashMap<PeerId, u32>,
+    /// Types of objects the peers want to sync, peers which aren't here want all of them
+    peer_object_types: HashMap<PeerId, Vec<u8>>,
+    /// Time of the last inventory request to each peer
+    inventory_requests: HashMap<PeerId, DateTime<Utc>>,
+}
+
+impl ProtocolEngine {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            seen_objects: HashMap::new(),
+            misbehavior_scores: HashMap::new(),
+            peer_object_types: HashMap::new(),
+            inventory_requests: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Remember types of objects advertised by the peer, so that it's not offered others.
+    /// `None` means that the peer wants all objects (or disconnected).
+    pub fn set_peer_object_types(&mut self, peer: PeerId, types: Option<Vec<u8>>) {
+        match types {
+            Some(types) => _ = self.peer_object_types.insert(peer, types),
+            None => _ = self.peer_object_types.remove(&peer),
+        }
+    }
+
+    /// Add penalty to the score of the peer, the peer is banned once the score is too high
+    pub fn report_misbehavior(&mut self, peer: PeerId, misbehavior: Misbehavior) -> Option<Action> {
+        let score = self.misbehavior_scores.entry(peer).or_default();
+        *score += misbehavior.penalty();
+        log::debug!(
+            "peer {} {}, score {}",
+            peer,
+            misbehavior.ban_reason(),
+            score
+        );
+        if *score < MISBEHAVIOR_BAN_SCORE {
+            return None;
+        }
+        self.misbehavior_scores.remove(&peer);
+        Some(Action::BanPeer {
+            peer,
+            reason: misbehavior.ban_reason().to_string(),
+        })
+    }
+
+    /// Halve misbehavior scores, so that occasional invalid objects relayed
+    /// by honest peers don't get them banned
+    pub fn decay_misbehavior_scores(&mut self) {
+        self.misbehavior_scores.retain(|_, score| {
+            *score /= 2;
+            *score > 0
+        });
+    }
+
+    /// Request of the whole inventory of a newly found peer. Peers found again soon after,
+    /// e.g. by mDNS on a busy network, aren't asked again for the resync interval.
+    pub fn inventory_request(
+        &mut self,
+        peer: PeerId,
+        now: DateTime<Utc>,
+    ) -> Option<NetworkMessage> {
+        let interval = chrono::Duration::seconds(INVENTORY_RESYNC_INTERVAL_SECONDS);
+        self.inventory_requests.retain(|_, t| *t + interval > now);
+        if self.inventory_requests.contains_key(&peer) {
+            log::debug!("Inventory was recently requested from {}, skipping", peer);
+            return None;
+        }
+        self.inventory_requests.insert(peer, now);
+        Some(NetworkMessage {
+            command: MessageCommand::ReqInv,
+            payload: MessagePayload::None,
+        })
+    }
+
+    /// Reply to the request of the inventory, `page` is the page of the local inventory
+    /// after the cursor of the request
+    pub fn inv_page(&self, peer: PeerId, page: Vec<(String, i64, u8)>) -> NetworkMessage {
+        // full page means there might be more objects
+        let next = if page.len() == MAX_INV_HASHES {
+            page.last().map(|(hash, expires, _)| InventoryCursor {
+                expires: *expires,
+                hash: hash.clone(),
+            })
+        } else {
+            None
+        };
+        let inv = match self.peer_object_types.get(&peer) {
+            Some(types) => page.into_iter().filter(|i| types.contains(&i.2)).collect(),
+            None => page,
+        };
+        NetworkMessage {
+            command: MessageCommand::Inv,
+            payload: MessagePayload::inv(inv, next),
+        }
+    }
+
+    /// Hashes from the announced inventory worth downloading, unless they're in the
+    /// inventory already
+    pub fn wanted_objects(&self, payload: MessagePayload) -> Vec<String> {
+        let MessagePayload::Inv {
+            inventory,
+            expires,
+            types,
+            ..
+        } = payload
+        else {
+            return Vec::new();
+        };
+        // expiration times and types might be missing if peer doesn't provide them
+        let expires_known = expires.len() == inventory.len();
+        let types_known = types.len() == inventory.len();
+        inventory
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !expires_known || self.config.is_within_sync_window(expires[*i]))
+            .filter(|(i, _)| !types_known || self.config.wants_object_type(types[*i]))
+            .map(|(_, hash)| hash)
+            .filter(|h| !self.seen_objects.contains_key(h))
+            .collect()
+    }
+
+    /// Request of the objects missing in the inventory, if there are any
+    pub fn request_objects(missing: Vec<String>) -> Option<NetworkMessage> {
+        if missing.is_empty() {
+            return None;
+        }
+        log::debug!("requesting {} missing objects...", missing.len());
+        Some(NetworkMessage {
+            command: MessageCommand::GetData,
+            payload: MessagePayload::GetData { inventory: missing },
+        })
+    }
+
+    /// Hashes of the objects requested by the peer
+    pub fn requested_objects(payload: MessagePayload) -> Vec<String> {
+        match payload {
+            MessagePayload::GetData { inventory } => inventory,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Reply to the request of objects with ones found in the inventory
+    pub fn objects_reply(objects: Vec<Object>) -> NetworkMessage {
+        log::debug!("requested {} objects from this node", objects.len());
+        NetworkMessage {
+            command: MessageCommand::Objects,
+            payload: MessagePayload::Objects { objects },
+        }
+    }
+
+    /// Check objects received from the peer, `known` are hashes of the ones which are
+    /// in the inventory already
+    pub fn on_objects(
+        &mut self,
+        peer: PeerId,
+        objects: Vec<Object>,
+        known: &HashSet<String>,
+        now: DateTime<Utc>,
+    ) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let mut new_objects = Vec::new();
+        let mut accepted = HashSet::new();
+        for obj in objects {
+            let hash_str = bs58::encode(&obj.hash).into_string();
+
+            if known.contains(&hash_str)
+                || accepted.contains(&hash_str)
+                || self.seen_objects.contains_key(&hash_str)
+            {
+                log::debug!(
+                    "object {} is already in the inventory, skipping it",
+                    hash_str
+                );
+                continue;
+            }
+
+            if let Err(e) = validation::validate_object(&obj) {
+                log::warn!("object {} is invalid: {}, skipping it", hash_str, e);
+                actions.extend(self.report_misbehavior(peer, Misbehavior::MalformedPayload));
+                continue;
+            }
+
+            // sender may voluntarily do more work, but not less than network minimum
+            let target = pow::get_pow_target(
+                &obj,
+                obj.nonce_trials_per_byte
+                    .max(pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE),
+                obj.extra_bytes.max(pow::NETWORK_MIN_EXTRA_BYTES),
+                self.config.pow_trials_divisor(),
+            );
+            let pow_check_res =
+                pow::check_pow(target, BigUint::from_bytes_be(&obj.nonce), obj.hash.clone());
+            if pow_check_res.is_err() {
+                log::warn!("object {:?} has invalid nonce! skipping it", hash_str);
+                actions.extend(self.report_misbehavior(peer, Misbehavior::InvalidPoW));
+                continue;
+            }
+
+            if !self.config.is_within_sync_window(obj.expires) {
+                log::debug!(
+                    "object {} is outside of the sync window, skipping it",
+                    hash_str
+                );
+                continue;
+            }
+
+            if !self.config.wants_object_type(obj.kind.object_type()) {
+                log::debug!("object {} is of unwanted type, skipping it", hash_str);
+                continue;
+            }
+
+            accepted.insert(hash_str.clone());
+            match self.config.role {
+                NodeRole::Full | NodeRole::Relay => {
+                    new_objects.push((hash_str, obj.expires, obj.kind.object_type()));
+                    actions.push(Action::StoreObject(obj.clone()));
+                }
+                NodeRole::Client => self.remember_seen_object(hash_str, obj.expires, now),
+            }
+            // relay has no identities, objects can't be addressed to it
+            if self.config.role != NodeRole::Relay {
+                actions.push(Action::ProcessObject(obj));
+            }
+        }
+        if !new_objects.is_empty() {
+            actions.push(Action::Announce(new_objects));
+        }
+        actions
+    }
+
+    fn remember_seen_object(&mut self, hash: String, expires: i64, now: DateTime<Utc>) {
+        if self.seen_objects.len() >= MAX_SEEN_OBJECTS {
+            let now = now.timestamp();
+            self.seen_objects.retain(|_, e| *e > now);
+        }
+        self.seen_objects.insert(hash, expires);
+    }
+
+    /// Handle pubkey object with the tag of the address
+    pub fn on_pubkey(
+        &self,
+        object: &Object,
+        address: &Address,
+    ) -> Result<Vec<Action>, Box<dyn Error>> {
+        let (tag, encrypted) = if let ObjectKind::Pubkey { tag, encrypted } = &object.kind {
+            (tag, encrypted)
+        } else {
+            return Err("incorrent object kind!".into());
+        };
+
+        let tag_str = bs58::encode(tag).into_string();
+        // keys of the address don't hash to it anymore, only key updates change them
+        if address.keys_rotated_at.is_some() {
+            log::debug!(
+                "ignoring pubkey with tag {}, its keys were rotated",
+                tag_str
+            );
+            return Ok(Vec::new());
+        }
+        let decryption_result =
+            decrypt_and_deserialize_payload(encrypted, &address.public_decryption_key);
+        let data: UnencryptedPubkey = match decryption_result {
+            Ok(d) => d,
+            Err(PayloadError::Decryption) => {
+                log::debug!("failed to decrypt pubkey object with tag {}", tag_str);
+                return Ok(Vec::new());
+            } // just ignore it
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        let (public_signing_key, public_encryption_key) = match (
+            ecies::PublicKey::parse_slice(&data.public_signing_key, None),
+            ecies::PublicKey::parse_slice(&data.public_encryption_key, None),
+        ) {
+            (Ok(psk), Ok(pek)) => (psk, pek),
+            _ => return Err("pubkey object contains malformed keys".into()),
+        };
+        if let Err(e) = validation::verify_signature(object, &data.public_signing_key) {
+            log::warn!("rejecting pubkey with tag {}: {}", tag_str, e);
+            return Ok(Vec::new());
+        }
+
+        // keys must hash to the ripe of the address, otherwise someone is trying
+        // to make us encrypt messages to a key that doesn't belong to the recipient
+        if Address::with_public_key(public_signing_key, public_encryption_key).ripe != address.ripe
+        {
+            log::warn!(
+                "rejecting pubkey with tag {}: keys don't match address {}",
+                tag_str,
+                address.string_repr
+            );
+            return Ok(Vec::new());
+        }
+
+        // devices of own identities are managed here, not learned from the network
+        let device_keys = address.private_signing_key.is_none().then(|| {
+            data.device_keys
+                .into_iter()
+                .filter(|k| ecies::PublicKey::parse_slice(k, None).is_ok())
+                .take(MAX_DEVICE_KEYS)
+                .collect()
+        });
+        let nonce_trials_per_byte = data
+            .nonce_trials_per_byte
+            .max(pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE);
+        let extra_bytes = data.extra_bytes.max(pow::NETWORK_MIN_EXTRA_BYTES);
+        Ok(vec![Action::UpdatePubkey(PubkeyUpdate {
+            address: address.string_repr.clone(),
+            tag: tag_str,
+            public_signing_key,
+            public_encryption_key,
+            nonce_trials_per_byte,
+            extra_bytes,
+            stranger_nonce_trials_per_byte: data
+                .stranger_nonce_trials_per_byte
+                .max(nonce_trials_per_byte),
+            stranger_extra_bytes: data.stranger_extra_bytes.max(extra_bytes),
+            device_keys,
+            pinned: self.config.pin_public_keys,
+        })])
+    }
+
+    /// Handle key update with the tag of the address. `known_rotations` is the number
+    /// of its former keys known already, updates with fewer of them are outdated.
+    pub fn on_key_update(
+        &self,
+        object: &Object,
+        address: &Address,
+        known_rotations: usize,
+    ) -> Result<Vec<Action>, Box<dyn Error>> {
+        let ObjectKind::KeyUpdate { tag, encrypted } = &object.kind else {
+            return Err("incorrect object kind!".into());
+        };
+        let tag_str = bs58::encode(tag).into_string();
+        // own identities rotate their keys themselves
+        if address.private_signing_key.is_some() {
+            return Ok(Vec::new());
+        }
+        let decryption_result =
+            decrypt_and_deserialize_payload(encrypted, &address.public_decryption_key);
+        let data: UnencryptedKeyUpdate = match decryption_result {
+            Ok(d) => d,
+            Err(PayloadError::Decryption) => {
+                log::debug!("failed to decrypt key update with tag {}", tag_str);
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        let (public_signing_key, public_encryption_key) = match (
+            ecies::PublicKey::parse_slice(&data.public_signing_key, None),
+            ecies::PublicKey::parse_slice(&data.public_encryption_key, None),
+        ) {
+            (Ok(psk), Ok(pek)) => (psk, pek),
+            _ => return Err("key update contains malformed keys".into()),
+        };
+        if let Err(e) = validation::verify_key_update(object, &data, address) {
+            log::warn!("rejecting key update with tag {}: {}", tag_str, e);
+            return Ok(Vec::new());
+        }
+        if data.chain.len() < known_rotations {
+            log::debug!("ignoring outdated key update with tag {}", tag_str);
+            return Ok(Vec::new());
+        }
+
+        let device_keys = data
+            .device_keys
+            .into_iter()
+            .filter(|k| ecies::PublicKey::parse_slice(k, None).is_ok())
+            .take(MAX_DEVICE_KEYS)
+            .collect();
+        let nonce_trials_per_byte = data
+            .nonce_trials_per_byte
+            .max(pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE);
+        let extra_bytes = data.extra_bytes.max(pow::NETWORK_MIN_EXTRA_BYTES);
+        Ok(vec![Action::RotateKeys {
+            update: PubkeyUpdate {
+                address: address.string_repr.clone(),
+                tag: tag_str,
+                public_signing_key,
+                public_encryption_key,
+                nonce_trials_per_byte,
+                extra_bytes,
+                stranger_nonce_trials_per_byte: data
+                    .stranger_nonce_trials_per_byte
+                    .max(nonce_trials_per_byte),
+                stranger_extra_bytes: data.stranger_extra_bytes.max(extra_bytes),
+                device_keys: Some(device_keys),
+                // former keys endorse the new ones, so they replace pinned keys
+                pinned: false,
+            },
+            chain: data.chain,
+        }])
+    }
+
+    /// Handle request of the pubkey, which might be of one of the identities. The
+    /// pubkey is sent out at most once per `getpubkey_response_interval`, `responded_at`
+    /// is when it was last sent in response to a request of the tag.
+    pub fn on_getpubkey(
+        &self,
+        object: &Object,
+        identities: Vec<Address>,
+        responded_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Action>, Box<dyn Error>> {
+        let ObjectKind::Getpubkey { tag } = &object.kind else {
+            return Err("incorrect object kind!".into());
+        };
+        // the pubkey is sent again once the previous one has expired anyway
+        let interval = self
+            .config
+            .getpubkey_response_interval
+            .min(self.config.pubkey_ttl);
+        Ok(identities
+            .into_iter()
+            .filter(|i| &i.tag == tag)
+            .filter(|i| {
+                let recent = i
+                    .pubkey_published_at
+                    .max(responded_at)
+                    .is_some_and(|t| t + interval > now);
+                if recent {
+                    log::debug!("someone requested our pubkey, but it was sent recently");
+                }
+                !recent
+            })
+            .map(|i| {
+                log::debug!("someone requested our pubkey! sending it out...");
+                Action::PublishPubkey(i)
+            })
+            .collect())
+    }
+
+    /// Handle msg object, which might be addressed to one of the identities or be
+    /// a copy for this device. `device_keys` are identities whose keys are kept on
+    /// another device along with the key of this device.
+    pub fn on_msg(
+        &self,
+        object: &Object,
+        identities: &[Address],
+        device_keys: &[(String, ecies::SecretKey)],
+        context: &MsgContext,
+    ) -> Result<Vec<Action>, Box<dyn Error>> {
+        let payloads: Vec<Vec<u8>> = if let ObjectKind::Msg { encrypted, copies } = &object.kind {
+            iter::once(encrypted).chain(copies).cloned().collect()
+        } else {
+            return Err("incorrect object kind!".into());
+        };
+        // messages might still be encrypted to former keys of identities
+        let secret_keys = identities
+            .iter()
+            .filter_map(|i| Some((i, i.private_encryption_key.as_ref()?)))
+            .chain(context.retired.iter().filter_map(|(address, key)| {
+                Some((identities.iter().find(|i| i.string_repr == *address)?, key))
+            }));
+        for (i, secret_key) in secret_keys {
+            match decrypt_any_payload(&payloads, secret_key) {
+                Ok(msg) => {
+                    if object.nonce_trials_per_byte < i.nonce_trials_per_byte
+                        || object.extra_bytes < i.extra_bytes
+                    {
+                        log::warn!(
+                            "message to {} doesn't meet required PoW difficulty, ignoring it",
+                            i.string_repr
+                        );
+                        continue;
+                    }
+                    let verification_error = verification_error(object, &msg, &context.rotated);
+                    // sender field is only trusted once the signature proves it
+                    let known_sender = verification_error.is_none()
+                        && context.known_senders.contains(&msg.sender_ripe);
+                    let quarantined = !known_sender
+                        && (object.nonce_trials_per_byte < i.stranger_nonce_trials_per_byte
+                            || object.extra_bytes < i.stranger_extra_bytes);
+                    if quarantined && !i.quarantine_strangers {
+                        log::warn!(
+                            "message to {} from stranger {} doesn't meet PoW difficulty required from strangers, ignoring it",
+                            i.string_repr,
+                            msg.sender_ripe
+                        );
+                        continue;
+                    }
+                    log::debug!("message object successfully decrypted! saving it...");
+                    // don't confirm delivery of messages to chans, like PyBitmessage does,
+                    // nor of quarantined ones until the user accepts them
+                    return Ok(received_msg(
+                        object,
+                        msg,
+                        !i.chan && !quarantined,
+                        quarantined,
+                        verification_error,
+                    ));
+                }
+                Err(PayloadError::Decryption) => continue,
+                Err(e) => {
+                    log::error!("received malformed message! skipping it");
+                    return Err(Box::new(e));
+                }
+            }
+        }
+
+        // copies for this device of identities whose keys are kept on another device,
+        // which acknowledges the messages
+        for (address, secret_key) in device_keys {
+            match decrypt_any_payload(&payloads, secret_key) {
+                Ok(msg) if msg.destination_ripe == *address => {
+                    log::debug!("copy of message to {} decrypted! saving it...", address);
+                    let verification_error = verification_error(object, &msg, &context.rotated);
+                    return Ok(received_msg(object, msg, false, false, verification_error));
+                }
+                Ok(_) | Err(PayloadError::Decryption) => continue,
+                Err(e) => {
+                    log::error!("received malformed message! skipping it");
+                    return Err(Box::new(e));
+                }
+            }
+        }
+        log::debug!(
+            "message object with hash {} failed to decrypt, skipping...",
+            bs58::encode(&object.hash).into_string()
+        );
+        Ok(Vec::new())
+    }
+
+    /// Handle broadcast object, which might come from one of the subscribed addresses.
+    /// It's encrypted with the key derived from the address, it's saved as a message
+    /// to the address, so that it shows up in the feed of the subscription.
+    pub fn on_broadcast(
+        &self,
+        object: &Object,
+        subscriptions: &[Address],
+        rotated: &[Address],
+    ) -> Result<Vec<Action>, Box<dyn Error>> {
+        let ObjectKind::Broadcast { tag, encrypted } = &object.kind else {
+            return Err("incorrect object kind!".into());
+        };
+        let Some(subscription) = subscriptions.iter().find(|s| &s.tag == tag) else {
+            return Ok(Vec::new());
+        };
+        let mut msg: UnencryptedMsg =
+            decrypt_and_deserialize_payload(encrypted, &subscription.public_decryption_key)?;
+        // anyone knowing the address can encrypt broadcasts with its tag
+        if msg.sender_ripe != subscription.string_repr {
+            log::warn!(
+                "broadcast with tag of {} comes from {}, ignoring it",
+                subscription.string_repr,
+                msg.sender_ripe
+            );
+            return Ok(Vec::new());
+        }
+        log::debug!("broadcast of {} decrypted! saving it...", msg.sender_ripe);
+        msg.destination_ripe = subscription.string_repr.clone();
+        let verification_error = verification_error(object, &msg, rotated);
+        Ok(received_msg(object, msg, false, false, verification_error))
+    }
+}
+
+/// Check the signature and the sender of the message, spoofed messages are kept so
+/// that the user sees them, but they're flagged with the returned error
+fn verification_error(
+    object: &Object,
+    msg: &UnencryptedMsg,
+    rotated: &[Address],
+) -> Option<String> {
+    match validation::verify_msg(object, msg, rotated) {
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!(
+                "message object with hash {} failed verification: {}",
+                bs58::encode(&object.hash).into_string(),
+                e
+            );
+            Some(e.to_string())
+        }
+    }
+}
+
+/// Save the verified message, acknowledging it if `acknowledge` is set and it isn't spoofed
+fn received_msg(
+    object: &Object,
+    msg: UnencryptedMsg,
+    acknowledge: bool,
+    quarantined: bool,
+    verification_error: Option<String>,
+) -> Vec<Action> {
+    let mut actions = Vec::new();
+    // don't confirm delivery of messages which might not come from the sender
+    if acknowledge && verification_error.is_none() {
+        actions.extend(msg.ack_object(object.expires).map(Action::EnqueuePoW));
+    }
+    actions.push(Action::SaveMessage {
+        hash: bs58::encode(&object.hash).into_string(),
+        msg,
+        signature: object.signature.clone(),
+        verification_error,
+        quarantined,
+    });
+    actions
+}
+
+/// Decrypt the first of the payloads (the message itself or its copies for other devices)
+/// encrypted to the key
+fn decrypt_any_payload(
+    payloads: &[Vec<u8>],
+    secret_key: &ecies::SecretKey,
+) -> Result<UnencryptedMsg, PayloadError> {
+    for payload in payloads {
+        match decrypt_and_deserialize_payload(payload, secret_key) {
+            Err(PayloadError::Decryption) => continue,
+            result => return result,
+        }
+    }
+    Err(PayloadError::Decryption)
+}
@@ -0,0 +1,409 @@
+//! Requesting pubkeys of recipients and publishing own ones. Objects carrying
+//! pubkeys and key updates of identities are built here.
+
+use chrono::{DateTime, Utc};
+use ecies::PublicKey;
+use std::error::Error;
+
+use log::debug;
+
+use crate::{
+    network::{
+        address::Address,
+        messages::{KeyEndorsement, Object, ObjectKind, UnencryptedKeyUpdate, UnencryptedPubkey},
+        validation,
+    },
+    repositories::{
+        address::AddressRepositorySync,
+        sqlite::models::{self, MessageStatus},
+    },
+};
+
+use super::worker::{
+    create_object_from_msg, message_ttl, MessageStatusEvent, NodeWorker, PubkeyRequest,
+    MAX_DEVICE_KEYS,
+};
+
+/// Cached public keys of contacts are forgotten after this time, so that they're
+/// requested again before sending the next message
+const PUBKEY_EXPIRY_DAYS: i64 = 28;
+
+impl NodeWorker {
+    /// Send getpubkey request for the recipient and start waiting for the pubkey.
+    /// Requests of pubkeys which are already awaited are counted as retries.
+    pub(super) async fn request_pubkey(
+        &mut self,
+        identity: &Address,
+        recipient: &Address,
+    ) -> PubkeyRequest {
+        let now = Utc::now();
+        let request = self
+            .tracked_pubkeys
+            .entry(bs58::encode(&recipient.tag).into_string())
+            .and_modify(|r| {
+                r.requested_at = now;
+                r.retries += 1;
+            })
+            .or_insert_with(|| PubkeyRequest::new(now))
+            .clone();
+        let obj = Object::with_signing(
+            identity,
+            ObjectKind::Getpubkey {
+                tag: recipient.tag.clone(),
+            },
+            now + self.msg_ttl,
+        );
+        self.enqueue_pow(obj).await;
+        request
+    }
+
+    /// Request pubkeys which still aren't received again once the objects of the
+    /// previous requests expire, so that recipients coming online later get them
+    pub(super) async fn retry_pubkey_requests(&mut self) -> Result<(), Box<dyn Error>> {
+        let deadline = Utc::now() - self.msg_ttl;
+        let tags: Vec<String> = self
+            .tracked_pubkeys
+            .iter()
+            .filter(|(_, request)| request.requested_at < deadline)
+            .map(|(tag, _)| tag.clone())
+            .collect();
+        for tag in tags {
+            let recipient = retry_db!(self.address_repo.get_by_ripe_or_tag(tag.clone()))?;
+            let Some(recipient) = recipient else {
+                self.tracked_pubkeys.remove(&tag);
+                continue;
+            };
+            debug!("requesting pubkey of {} again", recipient.string_repr);
+            if let Err(e) = self.rerequest_pubkey(&recipient).await {
+                // messages were cancelled, failed or their sender was deleted
+                debug!(
+                    "pubkey of {} isn't awaited anymore: {}",
+                    recipient.string_repr, e
+                );
+                self.tracked_pubkeys.remove(&tag);
+            }
+        }
+        Ok(())
+    }
+
+    /// Request pubkey of the recipient the message is waiting for right away
+    pub(super) async fn retry_pubkey_request(
+        &mut self,
+        hash: String,
+    ) -> Result<PubkeyRequest, Box<dyn Error>> {
+        let recipient = self.awaited_recipient(&hash).await?;
+        self.rerequest_pubkey(&recipient).await
+    }
+
+    /// Current request of the pubkey the message is waiting for
+    pub(super) async fn pubkey_request(
+        &mut self,
+        hash: String,
+    ) -> Result<Option<PubkeyRequest>, Box<dyn Error>> {
+        let recipient = self.awaited_recipient(&hash).await?;
+        Ok(self
+            .tracked_pubkeys
+            .get(&bs58::encode(&recipient.tag).into_string())
+            .cloned())
+    }
+
+    /// Recipient of the message waiting for its pubkey
+    async fn awaited_recipient(&mut self, hash: &str) -> Result<Address, Box<dyn Error>> {
+        let waiting = self
+            .messages_repo
+            .get_messages_by_status(MessageStatus::WaitingForPubkey)
+            .await?;
+        let msg = waiting
+            .into_iter()
+            .find(|m| m.hash == hash)
+            .ok_or("no such message waiting for pubkey")?;
+        let recipient = self.address_repo.get_by_ripe_or_tag(msg.recipient).await?;
+        Ok(recipient.ok_or("recipient of the message is unknown")?)
+    }
+
+    /// Request the pubkey again on behalf of the sender of a message waiting for it
+    /// and notify about the retry
+    async fn rerequest_pubkey(
+        &mut self,
+        recipient: &Address,
+    ) -> Result<PubkeyRequest, Box<dyn Error>> {
+        let waiting: Vec<models::Message> = self
+            .messages_repo
+            .get_messages_by_recipient(recipient.string_repr.clone(), None)
+            .await?
+            .into_iter()
+            .filter(|m| m.status == MessageStatus::WaitingForPubkey.to_string())
+            .collect();
+        let sender = waiting.first().ok_or("no messages wait for the pubkey")?;
+        let identity = self
+            .address_repo
+            .get_by_ripe_or_tag(sender.sender.clone())
+            .await?
+            .filter(|i| i.private_signing_key.is_some())
+            .ok_or("sender of the message is deleted")?;
+        let request = self.request_pubkey(&identity, recipient).await;
+        for m in waiting {
+            let mut event = MessageStatusEvent::new(m.hash, MessageStatus::WaitingForPubkey);
+            event.pubkey_request = Some(request.clone());
+            self.notify_message_status(event);
+        }
+        Ok(request)
+    }
+
+    /// Forget public keys of contacts which were received long ago
+    pub(super) async fn expire_public_keys(&mut self) -> Result<(), Box<dyn Error>> {
+        let deadline = Utc::now() - chrono::Duration::days(PUBKEY_EXPIRY_DAYS);
+        let contacts = retry_db!(self.address_repo.get_contacts())?;
+        for c in contacts
+            .into_iter()
+            .filter(|c| c.pubkey_received_at.is_some_and(|t| t < deadline))
+        {
+            debug!("public keys of {} have expired", c.string_repr);
+            // new keys are checked against the address anyway, so pinned keys
+            // can only be replaced with the same ones
+            retry_db!(self.address_repo.clear_public_keys(c.string_repr.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// Populate `tracked_pubkeys` with pubkeys awaited by messages since the previous run
+    pub(super) async fn track_waiting_messages(&mut self) -> Result<(), Box<dyn Error>> {
+        let msgs = retry_db!(self
+            .messages_repo
+            .get_messages_by_status(MessageStatus::WaitingForPubkey))?;
+        for m in msgs {
+            let recipient = retry_db!(self.address_repo.get_by_ripe_or_tag(m.recipient.clone()))?;
+            // the message fails once it's stale if the contact was deleted
+            let Some(recipient) = recipient else {
+                continue;
+            };
+            if recipient.public_encryption_key.is_some() {
+                retry_db!(self
+                    .messages_repo
+                    .update_message_status(m.hash.clone(), MessageStatus::WaitingForPOW))?;
+                continue;
+            }
+            // pubkey was requested when the message was created or retried later
+            let request = self
+                .tracked_pubkeys
+                .entry(bs58::encode(&recipient.tag).into_string())
+                .or_insert_with(|| PubkeyRequest::new(m.created_at));
+            request.since = request.since.min(m.created_at);
+            request.requested_at = request.requested_at.max(m.created_at);
+        }
+        Ok(())
+    }
+
+    /// Send out pubkey of the identity right away instead of waiting for a request
+    pub(super) async fn publish_pubkey(
+        &mut self,
+        identity: &Address,
+    ) -> Result<(), Box<dyn Error>> {
+        let object = identity_pubkey_object(
+            self.address_repo.as_ref(),
+            identity,
+            Utc::now() + self.pubkey_ttl,
+        )
+        .await?;
+        self.enqueue_pow(object).await;
+        self.address_repo
+            .update_pubkey_published_at(identity.string_repr.clone(), Utc::now())
+            .await
+    }
+
+    pub(super) async fn rotate_keys(&mut self, address: String) -> Result<(), Box<dyn Error>> {
+        let identity = self
+            .address_repo
+            .get_by_ripe_or_tag(address)
+            .await?
+            .filter(|i| i.private_signing_key.is_some())
+            .ok_or("no such identity")?;
+        if identity.chan {
+            return Err("keys of a chan are derived from its name".into());
+        }
+        let mut rotations = self
+            .address_repo
+            .get_key_rotations(identity.string_repr.clone())
+            .await?;
+        if rotations.len() >= validation::MAX_KEY_ROTATIONS {
+            return Err(format!(
+                "keys can't be rotated more than {} times",
+                validation::MAX_KEY_ROTATIONS
+            )
+            .into());
+        }
+
+        let keys = Address::generate();
+        let endorsement = KeyEndorsement::sign(
+            &identity,
+            &keys.public_signing_key.unwrap(),
+            &keys.public_encryption_key.unwrap(),
+        );
+        rotations.push(models::KeyRotation {
+            address: identity.string_repr.clone(),
+            position: rotations.len() as i64,
+            public_signing_key: endorsement.public_signing_key,
+            public_encryption_key: endorsement.public_encryption_key,
+            private_encryption_key: identity
+                .private_encryption_key
+                .map(|k| k.serialize().to_vec()),
+            signature: endorsement.signature,
+            rotated_at: Utc::now(),
+        });
+        let mut rotated = identity;
+        rotated.public_signing_key = keys.public_signing_key;
+        rotated.public_encryption_key = keys.public_encryption_key;
+        rotated.private_signing_key = keys.private_signing_key;
+        rotated.private_encryption_key = keys.private_encryption_key;
+        rotated.keys_rotated_at = Some(Utc::now());
+        self.address_repo
+            .rotate_keys(rotated.clone(), rotations)
+            .await?;
+        // contacts don't have to wait for the next request to learn the new keys
+        self.publish_pubkey(&rotated).await
+    }
+
+    pub(super) async fn handle_pubkey_notification(
+        &mut self,
+        tag: String,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.tracked_pubkeys.contains_key(&tag) {
+            return Ok(());
+        }
+        let addr = retry_db!(self.address_repo.get_by_ripe_or_tag(tag.clone()))?
+            .ok_or("address of the pubkey not found")?;
+        let msgs = retry_db!(self
+            .messages_repo
+            .get_messages_by_recipient(addr.string_repr.clone(), None))?;
+        for x in msgs
+            .into_iter()
+            .filter(|x| x.status == MessageStatus::WaitingForPubkey.to_string())
+        {
+            let identity = retry_db!(self.address_repo.get_by_ripe_or_tag(x.sender.clone()))?;
+            // the sender might be deleted while the message is waiting
+            let Some(identity) = identity else {
+                continue;
+            };
+            let ttl = message_ttl(self.msg_ttl, x.resend_count);
+            let device_keys =
+                device_public_keys(self.address_repo.as_ref(), addr.string_repr.clone()).await?;
+            let object = create_object_from_msg(&identity, &addr, &device_keys, x.clone(), ttl);
+            let old_hash = x.hash.clone();
+            let new_hash = bs58::encode(&object.hash).into_string();
+            retry_db!(self
+                .messages_repo
+                .update_hash(old_hash.clone(), new_hash.clone()))?;
+            retry_db!(self
+                .messages_repo
+                .update_message_status(new_hash.clone(), MessageStatus::WaitingForPOW))?;
+            let mut event = MessageStatusEvent::new(new_hash, MessageStatus::WaitingForPOW);
+            event.previous_hash = Some(old_hash);
+            self.notify_message_status(event);
+            self.enqueue_pow(object).await;
+        }
+        self.tracked_pubkeys.remove(&tag);
+        Ok(())
+    }
+}
+
+/// Public keys of the other devices of the address, which get their own copies of messages
+pub(crate) async fn device_public_keys(
+    address_repo: &AddressRepositorySync,
+    address: String,
+) -> Result<Vec<PublicKey>, Box<dyn Error>> {
+    Ok(address_repo
+        .get_device_keys(address)
+        .await?
+        .into_iter()
+        .filter(|k| k.private_key.is_none())
+        .filter_map(|k| PublicKey::parse_slice(&k.public_key, None).ok())
+        .take(MAX_DEVICE_KEYS)
+        .collect())
+}
+
+/// Build pubkey object of the identity, listing the keys of its other devices
+pub(crate) fn create_pubkey_object(
+    identity: &Address,
+    device_keys: &[PublicKey],
+    expires: DateTime<Utc>,
+) -> Object {
+    let unencrypted_pubkey = UnencryptedPubkey {
+        behaviour_bitfield: 0,
+        public_signing_key: identity.public_signing_key.unwrap().serialize().to_vec(),
+        public_encryption_key: identity.public_encryption_key.unwrap().serialize().to_vec(),
+        nonce_trials_per_byte: identity.nonce_trials_per_byte,
+        extra_bytes: identity.extra_bytes,
+        stranger_nonce_trials_per_byte: identity.stranger_nonce_trials_per_byte,
+        stranger_extra_bytes: identity.stranger_extra_bytes,
+        device_keys: device_keys.iter().map(|k| k.serialize().to_vec()).collect(),
+    };
+    Object::with_signing(
+        identity,
+        ObjectKind::Pubkey {
+            tag: identity.tag.clone(),
+            encrypted: NodeWorker::serialize_and_encrypt_payload(
+                unencrypted_pubkey,
+                &identity.public_decryption_key,
+            ),
+        },
+        expires,
+    )
+}
+
+/// Build pubkey object of the identity, which is a key update once its keys were rotated
+pub(crate) async fn identity_pubkey_object(
+    address_repo: &AddressRepositorySync,
+    identity: &Address,
+    expires: DateTime<Utc>,
+) -> Result<Object, Box<dyn Error>> {
+    let device_keys = device_public_keys(address_repo, identity.string_repr.clone()).await?;
+    if identity.keys_rotated_at.is_none() {
+        return Ok(create_pubkey_object(identity, &device_keys, expires));
+    }
+    let chain = address_repo
+        .get_key_rotations(identity.string_repr.clone())
+        .await?
+        .into_iter()
+        .map(|r| KeyEndorsement {
+            public_signing_key: r.public_signing_key,
+            public_encryption_key: r.public_encryption_key,
+            signature: r.signature,
+        })
+        .collect();
+    Ok(create_key_update_object(
+        identity,
+        chain,
+        &device_keys,
+        expires,
+    ))
+}
+
+/// Build key update of the identity, listing its former keys which endorse the current ones
+pub(crate) fn create_key_update_object(
+    identity: &Address,
+    chain: Vec<KeyEndorsement>,
+    device_keys: &[PublicKey],
+    expires: DateTime<Utc>,
+) -> Object {
+    let update = UnencryptedKeyUpdate {
+        public_signing_key: identity.public_signing_key.unwrap().serialize().to_vec(),
+        public_encryption_key: identity.public_encryption_key.unwrap().serialize().to_vec(),
+        chain,
+        nonce_trials_per_byte: identity.nonce_trials_per_byte,
+        extra_bytes: identity.extra_bytes,
+        stranger_nonce_trials_per_byte: identity.stranger_nonce_trials_per_byte,
+        stranger_extra_bytes: identity.stranger_extra_bytes,
+        device_keys: device_keys.iter().map(|k| k.serialize().to_vec()).collect(),
+    };
+    Object::with_signing(
+        identity,
+        ObjectKind::KeyUpdate {
+            tag: identity.tag.clone(),
+            encrypted: NodeWorker::serialize_and_encrypt_payload(
+                update,
+                &identity.public_decryption_key,
+            ),
+        },
+        expires,
+    )
+}
@@ -0,0 +1,55 @@
+use std::time::Instant;
+
+/// Token bucket refilled at a constant rate, used for rate and bandwidth limiting
+pub(crate) struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    /// Tokens added per second
+    rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create full bucket, so that bursts up to `capacity` are allowed right away
+    pub fn new(rate: u64, capacity: u64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            rate: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take `amount` tokens from the bucket, returns `false` if there are not enough of them.
+    /// Amounts bigger than the capacity are allowed when the bucket is full, leaving it
+    /// in debt, otherwise such amounts would never pass.
+    pub fn try_consume(&mut self, amount: u64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+
+        let amount = amount as f64;
+        if self.tokens >= amount || self.tokens >= self.capacity {
+            self.tokens -= amount;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Traffic counters of the node since start
+#[derive(Debug, Clone, Default)]
+pub struct TrafficStats {
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    /// Incoming messages dropped due to rate or bandwidth limits
+    pub dropped_messages: u64,
+    /// Gossipsub messages received from peers, including the dropped ones
+    pub gossip_messages_received: u64,
+    /// Gossipsub messages published by the node
+    pub gossip_messages_published: u64,
+    /// Objects received from peers and announced further
+    pub objects_relayed: u64,
+}
@@ -0,0 +1,494 @@
+//! Sending of messages and broadcasts of [`NodeWorker`], from composing them to
+//! marking them sent once their objects are announced.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use std::{collections::HashSet, error::Error};
+
+use futures::channel::oneshot;
+use log::debug;
+
+use crate::{
+    network::{address::Address, messages::UnencryptedMsg},
+    repositories::sqlite::models::{self, MessageStatus},
+};
+
+use super::{
+    client::compose_message,
+    pow_worker::ProofOfWorkWorkerCommand,
+    pubkeys::device_public_keys,
+    worker::{
+        create_broadcast_object, create_object_from_msg, message_ttl, MessageStatusEvent,
+        NodeWorker, BROADCAST_RECIPIENT,
+    },
+};
+
+/// Prepended to MIME data of auto-replies, see [`is_auto_submitted`]
+const AUTO_SUBMITTED_HEADER: &str = "Auto-Submitted: auto-replied\r\n";
+
+impl NodeWorker {
+    /// Send a copy of the message to each of the recipients, returns their hashes.
+    /// Copies which can't be sent are stored as failed, the draft is only removed
+    /// once every copy is on its way.
+    pub(super) async fn send_messages(
+        &mut self,
+        msg: models::Message,
+        from: String,
+        to: Vec<String>,
+        draft_hash: Option<String>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        self.own_identity(from.clone()).await?;
+        let mut hashes = Vec::new();
+        let mut all_sent = true;
+        for recipient in unique_recipients(to) {
+            let mut msg = msg.clone();
+            msg.recipient = recipient;
+            let res = self.send_message(msg.clone(), from.clone()).await;
+            match res.map_err(|e| e.to_string()) {
+                Ok(hash) => hashes.push(hash),
+                Err(reason) => {
+                    log::error!("Failed to send message to {}: {}", msg.recipient, reason);
+                    all_sent = false;
+                    hashes.push(self.save_failed_message(msg, reason).await?);
+                }
+            }
+        }
+        if let Some(hash) = draft_hash.filter(|_| all_sent) {
+            self.messages_repo.remove_message(hash).await?;
+        }
+        Ok(hashes)
+    }
+
+    pub(super) async fn schedule_message(
+        &mut self,
+        msg: models::Message,
+        from: String,
+        to: Vec<String>,
+        draft_hash: Option<String>,
+        send_at: DateTime<Utc>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut hashes = Vec::new();
+        for recipient in unique_recipients(to) {
+            let mut msg = msg.clone();
+            msg.recipient = recipient;
+            msg.sender = from.clone();
+            msg.hash = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+            msg.status = MessageStatus::Scheduled.to_string();
+            msg.send_at = Some(send_at);
+            self.messages_repo.save_model(msg.clone()).await?;
+            self.notify_message_status(MessageStatusEvent::new(
+                msg.hash.clone(),
+                MessageStatus::Scheduled,
+            ));
+            hashes.push(msg.hash);
+        }
+        if let Some(hash) = draft_hash {
+            self.messages_repo.remove_message(hash).await?;
+        }
+        Ok(hashes)
+    }
+
+    /// Pass scheduled messages which are due to the normal sending pipeline
+    pub(super) async fn send_scheduled_messages(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = Utc::now();
+        let msgs = retry_db!(self
+            .messages_repo
+            .get_messages_by_status(MessageStatus::Scheduled))?;
+        for mut msg in msgs
+            .into_iter()
+            .filter(|m| m.send_at.is_none_or(|t| t <= now))
+        {
+            debug!("sending scheduled message {}", msg.hash);
+            retry_db!(self.messages_repo.remove_message(msg.hash.clone()))?;
+            msg.created_at = now;
+            let from = msg.sender.clone();
+            if let Err(e) = self.send_message(msg, from).await {
+                log::error!("Failed to send scheduled message: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Hash of the message changes once it's sent, status events refer to the
+    /// previous one if the message was already stored (e.g. scheduled or retried)
+    pub(super) async fn send_message(
+        &mut self,
+        mut msg: models::Message,
+        from: String,
+    ) -> Result<String, Box<dyn Error>> {
+        let previous_hash = Some(msg.hash.clone()).filter(|h| !h.is_empty());
+        let event = |hash: String, status: MessageStatus| {
+            let mut event = MessageStatusEvent::new(hash, status);
+            event.previous_hash = previous_hash.clone();
+            event
+        };
+        let recipient_address = match Address::with_string_repr(&msg.recipient) {
+            Ok(a) => a,
+            Err(e) => {
+                let reason = format!("invalid recipient address: {}", e);
+                return self.save_failed_message(msg, reason).await;
+            }
+        };
+        // store the address as it's encoded canonically, e.g. with the prefix
+        msg.recipient = recipient_address.string_repr.clone();
+        // resent messages keep their ack data, so that any of the copies acknowledges them
+        if msg.ack_data.is_none() {
+            msg.ack_data = Some(UnencryptedMsg::generate_ack_data());
+        }
+
+        let identity = self.own_identity(from).await?;
+        let recipient: Option<Address> = self
+            .address_repo
+            .get_by_ripe_or_tag(msg.recipient.clone())
+            .await?;
+        match recipient {
+            Some(v) if v.public_encryption_key.is_some() => {
+                // every member of the chan would acknowledge the message
+                if v.chan {
+                    msg.ack_data = None;
+                }
+                msg.status = MessageStatus::WaitingForPOW.to_string();
+                let ttl = message_ttl(self.msg_ttl, msg.resend_count);
+                let device_keys =
+                    device_public_keys(self.address_repo.as_ref(), v.string_repr.clone()).await?;
+                let object = create_object_from_msg(&identity, &v, &device_keys, msg.clone(), ttl);
+                msg.hash = bs58::encode(&object.hash).into_string();
+                let hash = msg.hash.clone();
+                self.messages_repo.save_model(msg).await?;
+                self.notify_message_status(event(hash.clone(), MessageStatus::WaitingForPOW));
+                self.enqueue_pow(object).await;
+                Ok(hash)
+            }
+            recipient => {
+                // keys of a known contact might have expired
+                if recipient.is_none() {
+                    self.address_repo.store(recipient_address.clone()).await?;
+                }
+                msg.status = MessageStatus::WaitingForPubkey.to_string();
+                // we generate random hash value, cuz we don't really know real hash value of the message at the moment, and it's not that important
+                msg.hash = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+                self.messages_repo.save_model(msg.clone()).await?;
+                let request = self.request_pubkey(&identity, &recipient_address).await;
+                let mut event = event(msg.hash.clone(), MessageStatus::WaitingForPubkey);
+                event.pubkey_request = Some(request);
+                self.notify_message_status(event);
+                Ok(msg.hash)
+            }
+        }
+    }
+
+    /// Store the message as failed with given reason, so that it can be resent later
+    async fn save_failed_message(
+        &mut self,
+        mut msg: models::Message,
+        reason: String,
+    ) -> Result<String, Box<dyn Error>> {
+        let previous_hash = Some(msg.hash.clone()).filter(|h| !h.is_empty());
+        msg.status = MessageStatus::Failed.to_string();
+        msg.failure_reason = Some(reason);
+        msg.hash = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let hash = msg.hash.clone();
+        self.messages_repo.save_model(msg).await?;
+        let mut event = MessageStatusEvent::new(hash.clone(), MessageStatus::Failed);
+        event.previous_hash = previous_hash;
+        self.notify_message_status(event);
+        Ok(hash)
+    }
+
+    /// Own identity with given address, which messages can be sent from
+    async fn own_identity(&mut self, address: String) -> Result<Address, Box<dyn Error>> {
+        Ok(self
+            .address_repo
+            .get_by_ripe_or_tag(address)
+            .await?
+            .filter(|a| a.private_signing_key.is_some())
+            .ok_or("sender is not our own identity")?)
+    }
+
+    /// Broadcasts are encrypted with the key derived from the address of the identity,
+    /// so anyone subscribed to the address can read them
+    pub(super) async fn send_broadcast(
+        &mut self,
+        mut msg: models::Message,
+        from: String,
+    ) -> Result<String, Box<dyn Error>> {
+        let identity = self.own_identity(from).await?;
+        msg.sender = identity.string_repr.clone();
+        msg.recipient = BROADCAST_RECIPIENT.to_string();
+        msg.status = MessageStatus::WaitingForPOW.to_string();
+        let object = create_broadcast_object(&identity, msg.clone(), self.msg_ttl);
+        msg.hash = bs58::encode(&object.hash).into_string();
+        let hash = msg.hash.clone();
+        self.messages_repo.save_model(msg).await?;
+        self.notify_message_status(MessageStatusEvent::new(
+            hash.clone(),
+            MessageStatus::WaitingForPOW,
+        ));
+        self.enqueue_pow(object).await;
+        Ok(hash)
+    }
+
+    pub(super) fn notify_message_status(&mut self, event: MessageStatusEvent) {
+        self.message_status_subscribers
+            .retain(|s| s.unbounded_send(event.clone()).is_ok());
+    }
+
+    /// Mark the message as sent out, it's sent again if it's not acknowledged
+    /// before `resend_at` (expiration time of its object)
+    pub(super) async fn mark_message_sent(&mut self, hash: String, resend_at: Option<i64>) {
+        // acks are msg objects too, they have no message to update
+        match retry_db!(self
+            .messages_repo
+            .update_message_status(hash.clone(), MessageStatus::Sent))
+        {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                log::error!("Failed to mark message {} as sent: {}", hash, e);
+                return;
+            }
+        }
+        if let Some(expires) = resend_at {
+            let expires =
+                NaiveDateTime::from_timestamp_opt(expires, 0).expect("expiration time to be valid");
+            let resend_at = DateTime::from_utc(expires, Utc);
+            // the message isn't resent then, but it's still listed as sent
+            if let Err(e) = retry_db!(self.messages_repo.set_resend_at(hash.clone(), resend_at)) {
+                log::error!("Failed to set resend time of message {}: {}", hash, e);
+            }
+        }
+        self.notify_message_status(MessageStatusEvent::new(hash, MessageStatus::Sent));
+    }
+
+    /// Mark messages waiting for peers as sent once their announcements are published.
+    /// Objects which are no longer queued (e.g. after a restart) reach peers along with
+    /// the rest of the inventory.
+    pub(super) async fn send_waiting_messages(&mut self) -> Result<(), Box<dyn Error>> {
+        let waiting = retry_db!(self
+            .messages_repo
+            .get_messages_by_status(MessageStatus::WaitingForPeers))?;
+        for msg in waiting {
+            if self.is_pending_announcement(&msg.hash) {
+                continue;
+            }
+            if msg.recipient == BROADCAST_RECIPIENT {
+                self.mark_message_sent(msg.hash, None).await;
+                continue;
+            }
+            // the object might expire while the node is offline, then it's resent right away
+            let expires = retry_db!(self.inventory_repo.get_object(msg.hash.clone()))?
+                .map_or_else(|| Utc::now().timestamp(), |o| o.expires);
+            self.mark_message_sent(msg.hash, Some(expires)).await;
+        }
+        Ok(())
+    }
+
+    pub(super) async fn retry_message(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        let mut msg = self
+            .messages_repo
+            .get_messages_by_status(MessageStatus::Failed)
+            .await?
+            .into_iter()
+            .find(|m| m.hash == hash)
+            .ok_or("no such failed message")?;
+        self.messages_repo.remove_message(hash).await?;
+        msg.failure_reason = None;
+        msg.resend_count = 0;
+        msg.resend_at = None;
+        msg.created_at = Utc::now();
+        let from = msg.sender.clone();
+        self.send_message(msg, from).await?;
+        Ok(())
+    }
+
+    pub(super) async fn rebroadcast_object(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        let object = self
+            .inventory_repo
+            .get_object(hash.clone())
+            .await?
+            .ok_or("object is no longer in the inventory")?;
+        if object.nonce.is_empty() {
+            return Err("proof of work of the object isn't done yet".into());
+        }
+        if object.expires <= Utc::now().timestamp() {
+            return Err("object has expired".into());
+        }
+        // it's announced even if it was announced within the dedup window
+        self.recent_announcements.forget(&hash);
+        self.announce_objects(vec![(hash, object.expires, object.kind.object_type())]);
+        Ok(())
+    }
+
+    pub(super) async fn cancel_send(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        let mut status = None;
+        for s in [
+            MessageStatus::Scheduled,
+            MessageStatus::WaitingForPubkey,
+            MessageStatus::WaitingForPOW,
+            MessageStatus::WaitingForPeers,
+            MessageStatus::Sent,
+        ] {
+            let msgs = self.messages_repo.get_messages_by_status(s).await?;
+            if msgs.iter().any(|m| m.hash == hash) {
+                status = Some(s);
+                break;
+            }
+        }
+        match status.ok_or("no such outgoing message")? {
+            // object is created once the pubkey arrives or the message is due,
+            // but only for messages which are still waiting
+            MessageStatus::Scheduled | MessageStatus::WaitingForPubkey => {}
+            MessageStatus::WaitingForPOW => {
+                let (sender, receiver) = oneshot::channel();
+                self.send_pow_worker_command(ProofOfWorkWorkerCommand::CancelObject {
+                    hash: hash.clone(),
+                    remove_message: false,
+                    sender,
+                })
+                .await;
+                // PoW has just finished, the object is removed below so that it's not announced
+                if let Err(e) = receiver.await? {
+                    debug!("PoW of message {} wasn't cancelled: {}", hash, e);
+                }
+                self.announcement_batch.retain(|b| b.hash != hash);
+                self.inventory_repo.remove_object(hash.clone()).await?;
+            }
+            _ => {
+                if !self.remove_pending_announcement(&hash) {
+                    return Err("message has already been broadcast".into());
+                }
+                self.inventory_repo.remove_object(hash.clone()).await?;
+            }
+        }
+        self.messages_repo
+            .update_message_status(hash.clone(), MessageStatus::Cancelled)
+            .await?;
+        self.notify_message_status(MessageStatusEvent::new(hash, MessageStatus::Cancelled));
+        Ok(())
+    }
+
+    /// Mark messages which are waiting for recipient's pubkey for too long as failed
+    pub(super) async fn fail_stale_messages(&mut self) -> Result<(), Box<dyn Error>> {
+        let msgs = retry_db!(self
+            .messages_repo
+            .get_messages_by_status(MessageStatus::WaitingForPubkey))?;
+        let deadline = Utc::now() - self.pubkey_wait_timeout;
+        for m in msgs.into_iter().filter(|m| m.created_at < deadline) {
+            retry_db!(self.messages_repo.mark_as_failed(
+                m.hash.clone(),
+                "recipient's public key wasn't received in time".to_string(),
+            ))?;
+            self.notify_message_status(MessageStatusEvent::new(m.hash, MessageStatus::Failed));
+        }
+        Ok(())
+    }
+
+    /// Send messages which weren't acknowledged before their objects expired again,
+    /// with longer TTL. Messages are marked as failed after `max_resends` attempts.
+    pub(super) async fn resend_unacknowledged_messages(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.max_resends == 0 {
+            return Ok(());
+        }
+        let now = Utc::now();
+        let msgs = retry_db!(self
+            .messages_repo
+            .get_messages_by_status(MessageStatus::Sent))?;
+        for mut msg in msgs
+            .into_iter()
+            .filter(|m| m.resend_at.is_some_and(|t| t <= now))
+        {
+            if msg.resend_count >= self.max_resends as i32 {
+                let reason = format!(
+                    "recipient didn't acknowledge the message after {} attempts",
+                    msg.resend_count + 1
+                );
+                retry_db!(self
+                    .messages_repo
+                    .mark_as_failed(msg.hash.clone(), reason.clone()))?;
+                self.notify_message_status(MessageStatusEvent::new(
+                    msg.hash,
+                    MessageStatus::Failed,
+                ));
+                continue;
+            }
+            debug!("resending unacknowledged message {}", msg.hash);
+            retry_db!(self.messages_repo.remove_message(msg.hash.clone()))?;
+            msg.resend_count += 1;
+            msg.resend_at = None;
+            let from = msg.sender.clone();
+            if let Err(e) = self.send_message(msg, from).await {
+                log::error!("Failed to resend message: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reply to the message received by the identity if it has an auto-reply, unless
+    /// the sender got one within its interval. Automatic messages (e.g. other auto-replies)
+    /// are never replied to, so that two responders don't answer each other.
+    pub(super) async fn send_auto_reply(
+        &mut self,
+        msg: &models::Message,
+    ) -> Result<(), Box<dyn Error>> {
+        if msg.status != MessageStatus::Received.to_string()
+            || msg.folder.is_some()
+            || msg.sender == msg.recipient
+            || is_auto_submitted(&msg.data)
+        {
+            return Ok(());
+        }
+        let Some(auto_reply) = self
+            .address_repo
+            .get_auto_reply(msg.recipient.clone())
+            .await?
+        else {
+            return Ok(());
+        };
+        let replied_at = self
+            .address_repo
+            .get_auto_replied_at(auto_reply.address.clone(), msg.sender.clone())
+            .await?;
+        let interval = chrono::Duration::hours(auto_reply.interval_hours);
+        if replied_at.is_some_and(|t| Utc::now() - t < interval) {
+            return Ok(());
+        }
+        self.address_repo
+            .update_auto_replied_at(auto_reply.address.clone(), msg.sender.clone(), Utc::now())
+            .await?;
+
+        let subject = mail_parser::Message::parse(&msg.data)
+            .and_then(|m| m.subject().map(str::to_string))
+            .unwrap_or_default();
+        let mut reply = compose_message(
+            auto_reply.address.clone(),
+            msg.sender.clone(),
+            format!("{}{}", auto_reply.subject_prefix, subject),
+            auto_reply.body,
+        );
+        reply.data = [AUTO_SUBMITTED_HEADER.as_bytes(), &reply.data].concat();
+        debug!("Sending auto-reply to {}", msg.sender);
+        self.send_message(reply, auto_reply.address).await?;
+        Ok(())
+    }
+}
+
+/// Check if the MIME message has `Auto-Submitted` header (RFC 3834) marking it
+/// as sent automatically
+fn is_auto_submitted(data: &[u8]) -> bool {
+    let data = String::from_utf8_lossy(data);
+    data.lines()
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .any(|(name, value)| {
+            name.eq_ignore_ascii_case("Auto-Submitted") && !value.trim().eq_ignore_ascii_case("no")
+        })
+}
+
+/// Drop recipients which are repeated, the same address might be written with
+/// or without the prefix
+fn unique_recipients(to: Vec<String>) -> Vec<String> {
+    let mut canonical = HashSet::new();
+    to.into_iter()
+        .filter(|r| canonical.insert(Address::canonical_string_repr(r)))
+        .collect()
+}
@@ -0,0 +1,244 @@
+//! Setup of the on-disk storage of the node: the SQLite database with its migrations
+//! and the libp2p keypair.
+
+use async_std::task;
+use sqlx::{
+    migrate::{Migrate, MigrateError},
+    sqlite::SqlitePoolOptions,
+    SqliteConnection, SqlitePool,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use libp2p::identity;
+use log::{debug, info};
+
+use crate::{
+    config::Config,
+    network::address::Address,
+    repositories::sqlite::{
+        database::{self, DatabaseError, MIGRATIONS},
+        storage::SqliteStorage,
+    },
+};
+
+const POOL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Writes wait for the database writer connection for this long, they're queued
+/// during heavy object ingest
+const WRITER_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Open the database, encrypting and migrating it if needed
+pub(crate) fn open_sqlite_storage(
+    data_dir: &Path,
+    config: &Config,
+) -> Result<SqliteStorage, DatabaseError> {
+    let db_url = config.database_path(data_dir);
+    fs::create_dir_all(db_url.parent().unwrap()).map_err(DatabaseError::Open)?;
+
+    debug!("{:?}", db_url.to_str().unwrap());
+
+    if let Some(password) = &config.database_password {
+        if db_url.exists() && !database::is_encrypted(&db_url).map_err(DatabaseError::Open)? {
+            info!("Encrypting the database");
+            task::block_on(database::encrypt(&db_url, password))?;
+        }
+    }
+
+    let connect_options =
+        database::connect_options(&db_url, config.database_password.as_deref(), POOL_TIMEOUT);
+
+    let pool = task::block_on(
+        SqlitePoolOptions::new()
+            .max_connections(config.database_pool_size)
+            .connect_with(connect_options.clone()),
+    )?;
+
+    task::block_on(run_migrations(&pool, &db_url))?;
+    task::block_on(convert_legacy_addresses(&pool))?;
+
+    // SQLite allows a single writer at a time, so writes are queued for one connection
+    let writer = task::block_on(
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(WRITER_ACQUIRE_TIMEOUT)
+            .connect_with(connect_options),
+    )?;
+
+    Ok(SqliteStorage::new(pool, writer))
+}
+
+/// Check the database and apply pending migrations. Existing database is backed up
+/// before it's migrated.
+async fn run_migrations(pool: &SqlitePool, path: &Path) -> Result<(), DatabaseError> {
+    let mut conn = pool.acquire().await?;
+    database::check_connection(&mut conn).await?;
+    // same lock as `Migrator::run` takes, applied migrations are only listed once it's held
+    conn.lock().await?;
+    let res = apply_migrations(&mut conn, path).await;
+    conn.unlock().await?;
+    res
+}
+
+async fn apply_migrations(conn: &mut SqliteConnection, path: &Path) -> Result<(), DatabaseError> {
+    conn.ensure_migrations_table().await?;
+    if let Some(version) = conn.dirty_version().await? {
+        return Err(MigrateError::Dirty(version).into());
+    }
+
+    let applied: HashMap<_, _> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.checksum))
+        .collect();
+    let mut pending = Vec::new();
+    for m in MIGRATIONS
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+    {
+        match applied.get(&m.version) {
+            Some(checksum) if *checksum != m.checksum => {
+                return Err(MigrateError::VersionMismatch(m.version).into())
+            }
+            Some(_) => {}
+            None => pending.push(m),
+        }
+    }
+
+    if !applied.is_empty() && !pending.is_empty() {
+        let backup_path = database::backup(conn, path).await?;
+        info!(
+            "Database is backed up to {:?} before migration",
+            backup_path
+        );
+    }
+    for (i, m) in pending.iter().enumerate() {
+        let rows = match altered_rows(conn, &m.sql).await {
+            0 => String::new(),
+            n => format!(" ({} rows)", n),
+        };
+        info!(
+            "Applying database migration {}/{}: {}{}",
+            i + 1,
+            pending.len(),
+            m.description,
+            rows
+        );
+        // every migration runs in its own transaction, so an interrupted one is
+        // applied from scratch on the next start
+        let elapsed = conn.apply(m).await?;
+        debug!("Migration {} took {:?}", m.version, elapsed);
+    }
+    Ok(())
+}
+
+/// Number of rows in the tables altered by the migration, e.g. the inventory when
+/// columns are added to it, which tells why the migration takes long
+async fn altered_rows(conn: &mut SqliteConnection, sql: &str) -> i64 {
+    let statements: String = sql
+        .lines()
+        .filter(|l| !l.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let tables: HashSet<&str> = statements
+        .split(';')
+        .filter_map(|statement| {
+            let words: Vec<&str> = statement.split_whitespace().take(3).collect();
+            match words[..] {
+                [alter, table, name]
+                    if alter.eq_ignore_ascii_case("ALTER")
+                        && table.eq_ignore_ascii_case("TABLE") =>
+                {
+                    Some(name)
+                }
+                _ => None,
+            }
+        })
+        .collect();
+    let mut rows = 0;
+    for table in tables {
+        // the table may be created by the migration itself
+        let count: Result<(i64,), _> = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(&mut *conn)
+            .await;
+        rows += count.map(|(c,)| c).unwrap_or(0);
+    }
+    rows
+}
+
+/// Rewrite addresses stored before checksums were added (plain base58 of the ripe)
+/// into the current encoding, the ripe itself stays the same
+async fn convert_legacy_addresses(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let legacy: Vec<String> =
+        sqlx::query_scalar("SELECT address FROM addresses WHERE address NOT LIKE 'BM-%'")
+            .fetch_all(pool)
+            .await?;
+    if legacy.is_empty() {
+        return Ok(());
+    }
+
+    info!("Converting {} addresses to the new format", legacy.len());
+    let mut tx = pool.begin().await?;
+    for old in legacy {
+        let new = match Address::with_legacy_string_repr(&old) {
+            Some(a) => a.string_repr,
+            None => {
+                log::warn!("Stored address {} is malformed, skipping it", old);
+                continue;
+            }
+        };
+        sqlx::query("UPDATE addresses SET address = ? WHERE address = ?")
+            .bind(&new)
+            .bind(&old)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE messages SET sender = ? WHERE sender = ?")
+            .bind(&new)
+            .bind(&old)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE messages SET recipient = ? WHERE recipient = ?")
+            .bind(&new)
+            .bind(&old)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// Load libp2p identity keypair from the file, so that PeerId of the node stays the same
+/// across restarts. New keypair is generated (and saved) if there is no valid one yet or
+/// if `regenerate` is set.
+pub(super) fn load_or_generate_keypair(path: &Path, regenerate: bool) -> identity::Keypair {
+    if !regenerate {
+        match fs::read(path) {
+            Ok(bytes) => match identity::Keypair::from_protobuf_encoding(&bytes) {
+                Ok(k) => return k,
+                Err(e) => log::warn!("Stored peer key is malformed, generating new one: {}", e),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Failed to read stored peer key, generating new one: {}", e),
+        }
+    }
+
+    let key = identity::Keypair::generate_ed25519();
+    let bytes = key
+        .to_protobuf_encoding()
+        .expect("ed25519 keypair to be encodable");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    if let Err(e) = options.open(path).and_then(|mut f| f.write_all(&bytes)) {
+        log::warn!(
+            "Failed to save peer key, PeerId will change on restart: {}",
+            e
+        );
+    }
+    key
+}
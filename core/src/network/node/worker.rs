@@ -1,34 +1,47 @@
-use async_std::task;
-use chrono::Utc;
-use rand::distributions::{Alphanumeric, DistString};
-use sqlx::{
-    migrate::Migrator,
-    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
-    SqlitePool,
-};
+use async_std::{stream, task};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use ecies::PublicKey;
+use rand::seq::SliceRandom;
 use std::{
-    borrow::Cow, collections::HashMap, error::Error, fs, iter, path::PathBuf, str::FromStr,
-    time::Duration,
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    fs, iter,
+    path::PathBuf,
+    time::{Duration, Instant},
 };
 
 use futures::{
     channel::{mpsc, oneshot},
-    select, SinkExt, StreamExt,
+    future::Either,
+    select, AsyncRead, AsyncWrite, SinkExt, StreamExt,
 };
 use libp2p::{
-    core::upgrade::Version,
-    gossipsub::{self, MessageId, PublishError, Sha256Topic},
+    autonat,
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, ListenerId, MemoryTransport, OptionalTransport},
+        upgrade::Version,
+        ConnectedPoint,
+    },
+    dcutr,
+    gossipsub::{self, PublishError, Sha256Topic, TopicHash},
     identify, identity,
-    kad::{store::MemoryStore, Kademlia, KademliaConfig},
-    mdns, noise,
-    request_response::{self, ProtocolSupport},
-    swarm::{keep_alive, SwarmBuilder, SwarmEvent},
+    kad::{store::MemoryStore, BootstrapOk, Kademlia, KademliaConfig, KademliaEvent, QueryResult},
+    mdns,
+    multiaddr::Protocol,
+    noise, relay,
+    request_response::{self, ProtocolSupport, RequestId},
+    swarm::{dial_opts::DialOpts, SwarmBuilder, SwarmEvent},
     tcp, yamux, Multiaddr, PeerId, Swarm, Transport,
 };
+use libp2p_quic as quic;
 use log::{debug, info};
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use strum::{Display, EnumString};
 
 use crate::{
+    config::{Config, InventoryEviction, NodeRole, ObjectType, StorageKind, TransportKind},
     network::{
         address::Address,
         behaviour::{
@@ -36,42 +49,251 @@ use crate::{
             BitmessageProtocolCodec, BitmessageRequest, BitmessageResponse,
         },
         messages::{
-            MessageCommand, MessagePayload, MsgEncoding, NetworkMessage, Object, ObjectKind,
-            UnencryptedMsg,
+            DecodeError, InventoryCursor, InventoryVector, MessageCommand, MessagePayload,
+            MsgEncoding, NetworkMessage, Object, ObjectKind, PeerAddress, UnencryptedMsg,
+            LEGACY_PROTOCOL_VERSION, MAX_ADDR_PEERS, MAX_INV_HASHES, PROTOCOL_VERSION,
         },
+        validation,
     },
     repositories::{
         address::AddressRepositorySync,
-        inventory::InventoryRepositorySync,
-        message::MessageRepositorySync,
-        sqlite::{
-            address::SqliteAddressRepository,
-            inventory::SqliteInventoryRepository,
-            message::SqliteMessageRepository,
-            models::{self, MessageStatus},
-        },
+        inventory::{InventoryItem, InventoryRepositorySync, InventoryStats},
+        message::{FolderStats, MessageRepositorySync, Page},
+        peer::PeerRepositorySync,
+        sqlite::models::{self, MessageStatus},
+        storage::Storage,
     },
 };
 
+#[cfg(feature = "sqlite")]
+use crate::import::ImportProgress;
+#[cfg(feature = "legacy-bridge")]
+use crate::network::legacy::bridge::BridgeHandle;
+
+#[cfg(feature = "sqlite")]
+use super::storage::load_or_generate_keypair;
 use super::{
+    announcements::RecentAnnouncements,
+    command_queue::{self, CommandReceiver, CommandSender},
+    data_dir_lock::DataDirLock,
     handler::Handler,
-    pow_worker::{ProofOfWorkWorker, ProofOfWorkWorkerCommand},
+    pow_worker::{
+        PoWEstimate, PoWQueueItem, PoWStats, ProofOfWorkWorker, ProofOfWorkWorkerCommand,
+    },
+    protocol::Misbehavior,
+    rate_limit::{TokenBucket, TrafficStats},
 };
 
-const IDENTIFY_PROTO_NAME: &str = "/bitmessage/id/1.0.0";
+/// Identify protocol version is this prefix followed by [`PROTOCOL_VERSION`].
+/// Older nodes advertise `/bitmessage/id/1.0.0`, they're of [`LEGACY_PROTOCOL_VERSION`].
+const PROTOCOL_VERSION_PREFIX: &str = "/bitmessage/";
 const KADEMLIA_PROTO_NAME: &[u8] = b"/bitmessage/kad/1.0.0";
 
-const MIGRATIONS: Migrator = sqlx::migrate!("src/repositories/sqlite/migrations");
-const COMMON_PUBSUB_TOPIC: &'static str = "common";
-const POOL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Max amount of pubsub messages kept while there are no peers to publish them to
+const MAX_PENDING_BROADCASTS: usize = 16;
+/// Object isn't announced again for this time after it was announced
+const ANNOUNCEMENT_DEDUP_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Objects whose PoW is done are announced together once per this window,
+/// so that bulk sends don't publish an announcement per object
+const ANNOUNCEMENT_BATCH_WINDOW: Duration = Duration::from_secs(2);
+/// Whole inventory is announced this often, in case some announcements were lost
+const FULL_INVENTORY_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Max number of hashes of recently announced objects remembered
+const MAX_RECENT_ANNOUNCEMENTS: usize = 100_000;
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+/// Maintenance (e.g. dialing known peers) is done this often in low-power mode
+const LOW_POWER_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Max number of connections kept in low-power mode, so that the gossipsub mesh
+/// (whose heartbeat can't be changed at runtime) has fewer peers to maintain
+const LOW_POWER_PEERS: usize = 2;
+/// File in the data dir where libp2p identity keypair of the node is stored
+#[cfg(feature = "sqlite")]
+const PEER_KEY_FILE_NAME: &str = "peer_key";
+/// Marker file meaning that inventory wasn't announced before shutdown
+const PENDING_BROADCAST_FILE_NAME: &str = "pending_broadcast";
+/// Requested object which isn't received in this time is requested again from another peer
+const OBJECT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const OBJECT_REQUEST_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How often connected peers are checked for being idle
+const IDLE_PEERS_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How often scheduled messages are checked for being due
+const SCHEDULED_MESSAGES_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Object is not requested anymore after this number of retries
+const MAX_OBJECT_REQUEST_ATTEMPTS: u32 = 5;
+/// Remembered peer is redialed after this time once it fails, the delay doubles
+/// with each failure in a row
+const PEER_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_PEER_RECONNECT_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+/// Peers which weren't seen for this long are forgotten
+const PEER_RETENTION_DAYS: i64 = 30;
+/// Known peers are shared with connected ones this often
+const PEER_EXCHANGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Number of relays the node listens via while it's behind NAT
+const MAX_RELAYS: usize = 2;
+/// How often the database is compacted to give space of removed objects back
+const VACUUM_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Max number of other devices of an identity, which get copies of messages sent to it
+pub(crate) const MAX_DEVICE_KEYS: usize = 8;
+/// Prefix of the role of the node in the agent version advertised via identify
+const ROLE_PREFIX: &str = "role=";
+/// Prefix of the comma separated types of objects the node syncs in the agent version,
+/// missing if the node syncs all of them
+const OBJECT_TYPES_PREFIX: &str = "objects=";
+/// Recipient of sent broadcasts, the same as PyBitmessage shows
+pub const BROADCAST_RECIPIENT: &str = "[Broadcast subscribers]";
 
-#[derive(Debug)]
+/// Folder of the messages of an address. Parsed case-insensitively, displayed
+/// capitalized (e.g. `Inbox`) and serialized in lowercase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, EnumString, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(ascii_case_insensitive)]
 pub enum Folder {
     Inbox,
+    /// Outgoing messages which haven't been sent out yet
+    Outbox,
     Sent,
+    Drafts,
+    Trash,
+    /// Received messages from strangers who didn't meet the PoW difficulty required
+    /// from them, see [`Address::quarantine_strangers`]
+    Quarantine,
+    /// Broadcasts received from a subscription, listed by the address of the subscription
+    Broadcasts,
+}
+
+pub(super) type DynError = Box<dyn Error + Send + Sync>;
+
+/// Announced objects of one type: hash, expiration time and object type of each
+type TopicShard = (u8, Vec<(String, i64, u8)>);
+
+#[derive(thiserror::Error, Debug)]
+pub enum PayloadError {
+    #[error("payload failed to decrypt (wrong key or failed integrity check)")]
+    Decryption,
+    #[error("decrypted payload is malformed: {0}")]
+    Malformed(#[from] serde_cbor::Error),
+}
+
+/// How much work the node does, see [`WorkerCommand::SetPowerMode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerMode {
+    #[default]
+    Normal,
+    /// PoW is paused, maintenance is done less often and only a few peers are kept
+    /// connected, e.g. while running on battery. Objects still get announced and
+    /// received, the ones waiting for PoW are sent once the mode is switched back.
+    LowPower,
+}
+
+/// Status change of an outgoing message, or a newly received message
+#[derive(Debug, Clone)]
+pub struct MessageStatusEvent {
+    pub hash: String,
+    /// Set if the message got a new hash, since its object was re-created
+    /// (e.g. once the recipient's pubkey is received)
+    pub previous_hash: Option<String>,
+    pub status: String,
+    /// The message itself, set when it's received or its delivery is acknowledged
+    pub message: Option<models::Message>,
+    /// Request of the recipient's pubkey, set while the message is waiting for it
+    pub pubkey_request: Option<PubkeyRequest>,
+    /// Set if a filtering rule asks to notify about the received message,
+    /// see [`models::FilterRule::notify`]
+    pub notify: bool,
+}
+
+/// Avatar of an address, e.g. of an identity, a contact or a sender of a message
+#[derive(Debug, Clone, PartialEq)]
+pub enum Avatar {
+    /// Image set by the user
+    Image(Vec<u8>),
+    /// No image is set, an identicon generated from the seed (see
+    /// [`Address::avatar_seed`]) is shown instead
+    Identicon(String),
+}
+
+/// Request of the public key of a recipient whose messages are waiting for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubkeyRequest {
+    /// Time the key was requested for the first time
+    pub since: DateTime<Utc>,
+    /// Time of the latest request, it's repeated once its object expires
+    pub requested_at: DateTime<Utc>,
+    /// Number of times the request was repeated
+    pub retries: u32,
+}
+
+impl PubkeyRequest {
+    pub(super) fn new(since: DateTime<Utc>) -> Self {
+        Self {
+            since,
+            requested_at: since,
+            retries: 0,
+        }
+    }
+}
+
+/// Pubkey of a pinned contact was rejected, since its keys differ from the pinned ones.
+/// It's accepted once the contact is re-pinned, see [`WorkerCommand::RepinContact`].
+#[derive(Debug, Clone)]
+pub struct KeyMismatchEvent {
+    pub address: String,
+    pub received_at: DateTime<Utc>,
+}
+
+impl MessageStatusEvent {
+    pub fn new(hash: String, status: MessageStatus) -> Self {
+        Self {
+            hash,
+            previous_hash: None,
+            status: status.to_string(),
+            message: None,
+            pubkey_request: None,
+            notify: false,
+        }
+    }
+
+    pub fn with_message(message: models::Message) -> Self {
+        Self {
+            hash: message.hash.clone(),
+            previous_hash: None,
+            status: message.status.clone(),
+            message: Some(message),
+            pubkey_request: None,
+            notify: false,
+        }
+    }
+}
+
+/// Snapshot of the node counters and gauges, e.g. for monitoring
+#[derive(Debug, Clone, Default)]
+pub struct NodeMetrics {
+    pub connected_peers: usize,
+    pub inventory: InventoryStats,
+    pub traffic: TrafficStats,
+    pub pow: PoWStats,
+    /// Size of the database, 0 if everything is kept in memory
+    pub storage_bytes: u64,
+}
+
+/// State of the Kademlia DHT the node discovers peers with
+#[derive(Debug, Clone, Default)]
+pub struct DhtStatus {
+    /// Index (log2 of the distance) and number of peers of the non-empty buckets
+    /// of the routing table
+    pub buckets: Vec<(u32, usize)>,
+    /// Number of peers in the routing table
+    pub known_peers: usize,
+    /// `None` until the first bootstrap finishes
+    pub last_bootstrap: Option<BootstrapResult>,
 }
 
-type DynError = Box<dyn Error + Send + Sync>;
+#[derive(Debug, Clone)]
+pub struct BootstrapResult {
+    pub finished_at: DateTime<Utc>,
+    /// Set if the bootstrap failed, e.g. no peers are known or it timed out
+    pub error: Option<String>,
+}
 
 #[derive(Debug)]
 pub enum WorkerCommand {
@@ -83,12 +305,57 @@ pub enum WorkerCommand {
         peer: Multiaddr,
         sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
     },
+    /// Get all active listen addresses, waits until there is at least one
     GetListenerAddress {
-        sender: oneshot::Sender<Multiaddr>,
+        sender: oneshot::Sender<Vec<Multiaddr>>,
     },
     GetPeerID {
         sender: oneshot::Sender<PeerId>,
     },
+    GetConnectedPeers {
+        sender: oneshot::Sender<Vec<PeerId>>,
+    },
+    GetTrafficStats {
+        sender: oneshot::Sender<TrafficStats>,
+    },
+    GetDhtStatus {
+        sender: oneshot::Sender<DhtStatus>,
+    },
+    GetInventoryStats {
+        sender: oneshot::Sender<Result<InventoryStats, DynError>>,
+    },
+    /// Get a page of stored objects, ordered by expiration time and hash
+    GetInventory {
+        after: Option<InventoryCursor>,
+        limit: usize,
+        sender: oneshot::Sender<Result<Vec<InventoryItem>, DynError>>,
+    },
+    /// Get the config the node is running with
+    GetConfig {
+        sender: oneshot::Sender<Config>,
+    },
+    /// Apply bootstrap peers, bandwidth caps and inventory quota of the config right
+    /// away and save it to the data dir. Returns keys of the changed values which are
+    /// only applied after restart, see [`Config::restart_required`].
+    UpdateConfig {
+        config: Config,
+        sender: oneshot::Sender<Result<Vec<&'static str>, DynError>>,
+    },
+    GetMetrics {
+        sender: oneshot::Sender<Result<NodeMetrics, DynError>>,
+    },
+    /// Pause expensive work in low-power mode, or resume it
+    SetPowerMode {
+        mode: PowerMode,
+        sender: oneshot::Sender<()>,
+    },
+    GetPowerMode {
+        sender: oneshot::Sender<PowerMode>,
+    },
+    /// Stop the node, sender is notified once in-flight work is persisted
+    Shutdown {
+        sender: oneshot::Sender<()>,
+    },
     BroadcastMsgByPubSub {
         sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
         msg: NetworkMessage,
@@ -103,66 +370,538 @@ pub enum WorkerCommand {
         label: String,
         sender: oneshot::Sender<Result<String, DynError>>,
     },
+    /// Create identity with keys derived from the passphrase, returns its address
+    GenerateDeterministicIdentity {
+        passphrase: String,
+        label: String,
+        sender: oneshot::Sender<Result<String, DynError>>,
+    },
+    /// Join chan with the given name, returns its address. If the address is given,
+    /// it must match the one derived from the name.
+    JoinChan {
+        name: String,
+        address: Option<String>,
+        sender: oneshot::Sender<Result<String, DynError>>,
+    },
+    /// Export identities with private keys in PyBitmessage `keys.dat` format
+    ExportIdentities {
+        addresses: Vec<String>,
+        sender: oneshot::Sender<Result<String, DynError>>,
+    },
+    /// Import identities from `keys.dat` data, returns addresses of the new identities
+    ImportIdentities {
+        data: String,
+        sender: oneshot::Sender<Result<Vec<String>, DynError>>,
+    },
+    /// Generate key of this device for the identity kept on another device,
+    /// returns the code to pair it there
+    CreateDeviceKey {
+        address: String,
+        sender: oneshot::Sender<Result<String, DynError>>,
+    },
+    /// Add device with the pairing code to its identity
+    PairDevice {
+        code: String,
+        label: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    GetDeviceKeys {
+        address: String,
+        sender: oneshot::Sender<Result<Vec<models::DeviceKey>, DynError>>,
+    },
+    /// Stop sending copies of messages to the device (its key encoded as base58)
+    RemoveDeviceKey {
+        address: String,
+        public_key: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Set image shown as the avatar of the identity or the contact, `None` removes it
+    SetAvatar {
+        address: String,
+        avatar: Option<Vec<u8>>,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    GetAvatar {
+        address: String,
+        sender: oneshot::Sender<Result<Avatar, DynError>>,
+    },
     RenameIdentity {
         new_label: String,
         address: String,
         sender: oneshot::Sender<Result<(), DynError>>,
     },
+    /// Set PoW difficulty required for messages sent to the identity
+    SetIdentityPoWDifficulty {
+        address: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Set PoW difficulty the identity requires from senders which aren't its contacts,
+    /// and whether their messages which don't meet it are quarantined or dropped
+    SetStrangerPolicy {
+        address: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+        quarantine: bool,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
     DeleteIdentity {
         address: String,
         sender: oneshot::Sender<Result<(), DynError>>,
     },
+    /// Delete several identities at once, nothing is deleted if any of them fails
+    DeleteIdentities {
+        addresses: Vec<String>,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    GetContacts {
+        sender: oneshot::Sender<Result<Vec<Address>, DynError>>,
+    },
+    /// Find the contact with the given address, `None` if it's unknown or it's our own identity
+    ResolveContact {
+        address: String,
+        sender: oneshot::Sender<Result<Option<Address>, DynError>>,
+    },
+    /// Find the address typed as a recipient, which is either an address or a label
+    /// of a contact or an identity
+    ResolveRecipient {
+        input: String,
+        sender: oneshot::Sender<Result<Address, DynError>>,
+    },
+    AddContact {
+        address: String,
+        label: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    RenameContact {
+        address: String,
+        new_label: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    DeleteContact {
+        address: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Generate a fresh keypair (and thus a new address) for an existing identity.
+    /// Since the address changes, each contact is sent a message from the new address
+    /// mentioning the former one. If `archive_old` is set, private keys of the old
+    /// identity are removed, so it won't receive new messages anymore (already received
+    /// messages are kept).
+    RotateIdentity {
+        old_address: String,
+        new_label: Option<String>,
+        archive_old: bool,
+        sender: oneshot::Sender<Result<String, DynError>>,
+    },
+    /// Replace keys of the identity with new ones keeping its address. Contacts accept
+    /// them since they're endorsed by the former keys, which still decrypt messages
+    /// for the grace period.
+    RotateKeys {
+        address: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Forget pinned public keys of the contact, so that next received pubkey is accepted
+    RepinContact {
+        address: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Send failed message again
+    RetryMessage {
+        hash: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Announce the object again, e.g. a sent message for peers which joined after it
+    /// was sent. Fails if it has expired.
+    RebroadcastObject {
+        hash: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Request the pubkey the message is waiting for right away
+    RetryPubkeyRequest {
+        hash: String,
+        sender: oneshot::Sender<Result<PubkeyRequest, DynError>>,
+    },
+    GetPubkeyRequest {
+        hash: String,
+        sender: oneshot::Sender<Result<Option<PubkeyRequest>, DynError>>,
+    },
+    /// Move message to Trash
+    DeleteMessage {
+        hash: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Move message from Trash back to Inbox/Sent, or from Quarantine to Inbox
+    RestoreMessage {
+        hash: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Mark message as read or unread
+    MarkRead {
+        hash: String,
+        read: bool,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Count all and unread messages in the folders of the identity
+    GetFolderStats {
+        address: String,
+        sender: oneshot::Sender<Result<FolderStats, DynError>>,
+    },
+    GetPoWQueue {
+        sender: oneshot::Sender<Vec<PoWQueueItem>>,
+    },
+    /// Estimate PoW of a message with the body of given size, `ttl` defaults to
+    /// TTL of outgoing messages
+    EstimatePoW {
+        msg_size: usize,
+        ttl: Option<chrono::Duration>,
+        sender: oneshot::Sender<PoWEstimate>,
+    },
+    /// Cancel PoW of the object, deleting the message which wasn't sent yet
+    CancelPoW {
+        hash: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Stop sending the message which wasn't broadcast yet, the message is kept
+    /// with `Cancelled` status
+    CancelSend {
+        hash: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Get messages of the folder from the newest one, all of them if `page` is `None`
     GetMessages {
+        address: String,
+        folder: Folder,
+        page: Option<Page>,
+        sender: oneshot::Sender<Result<Vec<models::Message>, DynError>>,
+    },
+    /// Full-text search over subjects and bodies of the messages in the folder
+    SearchMessages {
+        query: String,
         address: String,
         folder: Folder,
         sender: oneshot::Sender<Result<Vec<models::Message>, DynError>>,
     },
+    /// Export messages as mbox file, or a single message as `.eml` file
+    ExportMessages {
+        hashes: Vec<String>,
+        mbox: bool,
+        sender: oneshot::Sender<Result<Vec<u8>, DynError>>,
+    },
+    /// Import messages from `.eml` or mbox file into Inbox or Sent of the identity,
+    /// returns the number of imported messages
+    ImportMessages {
+        address: String,
+        folder: Folder,
+        data: Vec<u8>,
+        sender: oneshot::Sender<Result<usize, DynError>>,
+    },
+    /// Import identities, contacts, subscriptions and messages from PyBitmessage data dir.
+    /// Other commands wait until the import is finished.
+    #[cfg(feature = "sqlite")]
+    ImportPyBitmessage {
+        dir: PathBuf,
+        progress: mpsc::UnboundedSender<ImportProgress>,
+    },
     SendMessage {
         msg: models::Message,
         from: String,
+        /// Recipients, each of them gets its own copy of the message
+        to: Vec<String>,
+        /// Draft the message was composed from, removed once every copy of the message is sent
+        draft_hash: Option<String>,
+        /// Returns hashes of the messages, one for each recipient
+        sender: oneshot::Sender<Result<Vec<String>, DynError>>,
+    },
+    /// Same as [`WorkerCommand::SendMessage`], but the messages are kept until
+    /// `send_at` comes
+    ScheduleMessage {
+        msg: models::Message,
+        from: String,
+        to: Vec<String>,
+        draft_hash: Option<String>,
+        send_at: DateTime<Utc>,
+        sender: oneshot::Sender<Result<Vec<String>, DynError>>,
+    },
+    /// Send the message to everyone subscribed to the identity, returns its hash
+    SendBroadcast {
+        msg: models::Message,
+        from: String,
+        sender: oneshot::Sender<Result<String, DynError>>,
+    },
+    /// Receive broadcasts of the address, they're listed in Inbox of the address
+    AddSubscription {
+        address: String,
+        label: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    RemoveSubscription {
+        address: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    GetSubscriptions {
+        sender: oneshot::Sender<Result<Vec<models::Subscription>, DynError>>,
+    },
+    /// Reply automatically to messages received by the identity, replacing its
+    /// existing auto-reply
+    SetAutoReply {
+        auto_reply: models::AutoReply,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    RemoveAutoReply {
+        address: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    GetAutoReply {
+        address: String,
+        sender: oneshot::Sender<Result<Option<models::AutoReply>, DynError>>,
+    },
+    GetFilterRules {
+        sender: oneshot::Sender<Result<Vec<models::FilterRule>, DynError>>,
+    },
+    /// Add filtering rule applied after the existing ones, returns its id
+    AddFilterRule {
+        rule: models::FilterRule,
+        sender: oneshot::Sender<Result<String, DynError>>,
+    },
+    UpdateFilterRule {
+        rule: models::FilterRule,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    RemoveFilterRule {
+        id: String,
         sender: oneshot::Sender<Result<(), DynError>>,
     },
+    /// Get status changes of outgoing messages until the receiver is dropped
+    SubscribeMessageStatus {
+        sender: mpsc::UnboundedSender<MessageStatusEvent>,
+    },
+    /// Get whether there are peers to broadcast objects to, the current state is sent
+    /// right away and then each change of it until the receiver is dropped
+    SubscribeConnectivity {
+        sender: mpsc::UnboundedSender<bool>,
+    },
+    /// Message status was changed by the handler or the PoW worker
+    MessageStatusChanged {
+        event: MessageStatusEvent,
+    },
+    /// Get pubkeys rejected for pinned contacts until the receiver is dropped
+    SubscribeKeyMismatch {
+        sender: mpsc::UnboundedSender<KeyMismatchEvent>,
+    },
+    /// Pubkey of a pinned contact was rejected by the handler
+    KeyMismatch {
+        event: KeyMismatchEvent,
+    },
+    /// Save unsent message as a draft, returns hash of the draft
+    SaveDraft {
+        msg: models::Message,
+        sender: oneshot::Sender<Result<String, DynError>>,
+    },
+    UpdateDraft {
+        hash: String,
+        msg: models::Message,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    /// Ban peer id or multiaddr, matching peers are disconnected and never connected again
+    BanPeer {
+        target: String,
+        reason: Option<String>,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    UnbanPeer {
+        target: String,
+        sender: oneshot::Sender<Result<(), DynError>>,
+    },
+    GetBannedPeers {
+        sender: oneshot::Sender<Result<Vec<models::BannedPeer>, DynError>>,
+    },
+    /// Misbehavior score of the peer reached the limit of the handler
+    BanMisbehavingPeer {
+        peer: PeerId,
+        reason: String,
+    },
+    /// Object received by the legacy bridge from a legacy node, relayed to other bridge nodes
+    #[cfg(feature = "legacy-bridge")]
+    PublishLegacyObject {
+        data: Vec<u8>,
+    },
+}
+
+/// Object whose PoW is done, waiting for the end of the batch window to be announced
+pub(super) struct BatchedAnnouncement {
+    pub(super) hash: String,
+    pub(super) expires: i64,
+    pub(super) object_type: u8,
+    /// Messages and broadcasts are marked sent once they're announced
+    pub(super) is_message: bool,
+    /// Time the message is resent unless it's acknowledged, broadcasts are never resent
+    pub(super) resend_at: Option<i64>,
+}
+
+/// Outstanding GetData request for a single object
+struct ObjectRequest {
+    peer: PeerId,
+    requested_at: Instant,
+    attempts: u32,
+    tried_peers: HashSet<PeerId>,
 }
 
 pub struct NodeWorker {
-    local_peer_id: PeerId,
-    swarm: Swarm<BitmessageNetBehaviour>,
+    pub(super) local_peer_id: PeerId,
+    /// `None` if the node doesn't store anything on disk
+    pub(super) data_dir: Option<PathBuf>,
+    /// Config the node has been started with, updated with `UpdateConfig`
+    pub(super) config: Config,
+    /// Released once the node is shut down, `None` with in-memory storage
+    data_dir_lock: Option<DataDirLock>,
+    pub(super) swarm: Swarm<BitmessageNetBehaviour>,
+    pub(super) listeners: Vec<ListenerId>,
     handler: Handler,
-    command_sender: mpsc::Sender<WorkerCommand>,
-    command_receiver: mpsc::Receiver<WorkerCommand>,
+    /// Commands of the clients
+    command_receiver: CommandReceiver,
+    /// Commands sent by the node to itself (e.g. results of PoW)
+    internal_command_receiver: mpsc::UnboundedReceiver<WorkerCommand>,
 
     pubkey_notifier: mpsc::Receiver<String>,
-    tracked_pubkeys: HashMap<String, bool>,
+    /// Tags of pubkeys we're waiting for and when they were last requested
+    pub(super) tracked_pubkeys: HashMap<String, PubkeyRequest>,
 
-    pending_commands: Vec<WorkerCommand>,
-    _sqlite_connection_pool: SqlitePool,
+    pub(super) pending_commands: Vec<WorkerCommand>,
+    pending_broadcasts: VecDeque<NetworkMessage>,
+    /// Own objects announced at the end of [`ANNOUNCEMENT_BATCH_WINDOW`]
+    pub(super) announcement_batch: Vec<BatchedAnnouncement>,
+    /// Objects announced within [`ANNOUNCEMENT_DEDUP_WINDOW`]
+    pub(super) recent_announcements: RecentAnnouncements,
+
+    peer_idle_timeout: Option<Duration>,
+    /// Messages waiting for recipient's pubkey longer than this are marked as failed
+    pub(super) pubkey_wait_timeout: chrono::Duration,
+    /// Spawned when the node is started
+    pow_worker: Option<ProofOfWorkWorker>,
+    trash_retention: Option<chrono::Duration>,
+    pub(super) max_inventory_size: Option<u64>,
+    pub(super) inventory_eviction: InventoryEviction,
+    /// Objects evicted since start due to `max_inventory_size`
+    pub(super) evicted_objects: u64,
+    last_vacuum: Instant,
+    pub(super) power_mode: PowerMode,
+    last_maintenance: Instant,
+    pub(super) role: NodeRole,
+    /// Roles advertised by connected peers
+    peer_roles: HashMap<PeerId, NodeRole>,
+    /// Protocol versions advertised by connected peers
+    peer_versions: HashMap<PeerId, u32>,
+    /// Addresses connected peers were dialed at
+    dialed_addresses: HashMap<PeerId, Multiaddr>,
+    /// Remote addresses of connected peers, checked against address bans
+    remote_addresses: HashMap<PeerId, Multiaddr>,
+    /// Banned addresses, banned peer ids are blocked by the behaviour
+    banned_addresses: Vec<Multiaddr>,
+    /// Set once AutoNAT finds out the node isn't reachable from outside
+    behind_nat: bool,
+    /// Connected relay servers and addresses to listen via them
+    relay_candidates: HashMap<PeerId, Multiaddr>,
+    /// Relays the node listens via
+    relay_listeners: HashMap<PeerId, ListenerId>,
+    /// Time of the last useful exchange (gossip or objects) with each connected peer
+    peer_activity: HashMap<PeerId, Instant>,
+    /// Requests sent to each peer which aren't answered yet
+    pending_requests: HashMap<PeerId, HashSet<RequestId>>,
+    /// Peers which are never disconnected due to inactivity (e.g. bootstrap nodes)
+    pub(super) protected_peers: HashSet<PeerId>,
+    /// Number of remembered peers the node keeps dialing while it has fewer connections
+    reconnect_peers: usize,
+    /// Objects requested from peers, but not received yet
+    requested_objects: HashMap<String, ObjectRequest>,
+    pub(super) download_limiter: Option<TokenBucket>,
+    pub(super) upload_limiter: Option<TokenBucket>,
+    peer_message_rate: Option<u32>,
+    /// TTL of outgoing messages and pubkey requests
+    pub(super) msg_ttl: chrono::Duration,
+    /// TTL of own pubkeys published when devices of identities change
+    pub(super) pubkey_ttl: chrono::Duration,
+    /// Max number of times unacknowledged messages are sent again
+    pub(super) max_resends: u32,
+    peer_limiters: HashMap<PeerId, TokenBucket>,
+    pub(super) traffic_stats: TrafficStats,
+    /// Result of the latest DHT bootstrap
+    last_bootstrap: Option<BootstrapResult>,
+    pub(super) message_status_subscribers: Vec<mpsc::UnboundedSender<MessageStatusEvent>>,
+    /// Whether any peer is subscribed to the topics objects are announced in
+    pub(super) online: bool,
+    pub(super) connectivity_subscribers: Vec<mpsc::UnboundedSender<bool>>,
+    pub(super) key_mismatch_subscribers: Vec<mpsc::UnboundedSender<KeyMismatchEvent>>,
+    pub(super) storage: Box<dyn Storage>,
     common_topic: Sha256Topic,
+    /// Topics of object types when announcements are sharded, see [`Config::topic_sharding`]
+    shard_topics: HashMap<u8, Sha256Topic>,
 
-    inventory_repo: Box<InventoryRepositorySync>,
-    address_repo: Box<AddressRepositorySync>,
-    messages_repo: Box<MessageRepositorySync>,
+    pub(super) inventory_repo: Box<InventoryRepositorySync>,
+    pub(super) address_repo: Box<AddressRepositorySync>,
+    pub(super) messages_repo: Box<MessageRepositorySync>,
+    pub(super) peer_repo: Box<PeerRepositorySync>,
 
-    pow_worker_command_sink: Option<mpsc::Sender<ProofOfWorkWorkerCommand>>,
+    pub(super) pow_worker_command_sink: Option<mpsc::Sender<ProofOfWorkWorkerCommand>>,
+    /// `None` if no legacy peers or listen address are configured
+    #[cfg(feature = "legacy-bridge")]
+    legacy_bridge: Option<BridgeHandle>,
 }
 
 impl NodeWorker {
-    pub fn new(
-        bootstrap_nodes: Option<Vec<Multiaddr>>,
+    pub(crate) fn new(
         data_dir: PathBuf,
-    ) -> (NodeWorker, mpsc::Sender<WorkerCommand>) {
-        let local_key = identity::Keypair::generate_ed25519();
+        config: Config,
+        storage: Box<dyn Storage>,
+        data_dir_lock: Option<DataDirLock>,
+    ) -> (NodeWorker, CommandSender) {
+        let local_key = match config.storage {
+            #[cfg(feature = "sqlite")]
+            StorageKind::Sqlite => {
+                fs::create_dir_all(&data_dir).expect("data folder is created");
+                load_or_generate_keypair(
+                    &data_dir.join(PEER_KEY_FILE_NAME),
+                    config.regenerate_peer_key,
+                )
+            }
+            StorageKind::Memory => identity::Keypair::generate_ed25519(),
+        };
         let local_peer_id = PeerId::from(local_key.public());
         info!("Local peer id: {:?}", local_peer_id);
 
-        let transport = tcp::async_io::Transport::default()
-            .upgrade(Version::V1Lazy)
-            .authenticate(noise::Config::new(&local_key).unwrap())
-            .multiplex(yamux::Config::default())
-            .boxed();
+        let (relay_transport, relay_client) = relay::client::new(local_peer_id);
+        let relay_transport = match config.nat_traversal {
+            true => OptionalTransport::some(relay_transport),
+            false => OptionalTransport::none(),
+        };
+        let transport = match config.transport {
+            TransportKind::Tcp if config.quic => with_quic(
+                upgrade_transport(
+                    relay_transport.or_transport(tcp::async_io::Transport::default()),
+                    &local_key,
+                ),
+                &local_key,
+            ),
+            TransportKind::Tcp => upgrade_transport(
+                relay_transport.or_transport(tcp::async_io::Transport::default()),
+                &local_key,
+            ),
+            TransportKind::Memory => upgrade_transport(
+                relay_transport.or_transport(MemoryTransport::default()),
+                &local_key,
+            ),
+        };
+        let mdns = match config.transport {
+            TransportKind::Tcp => Some(
+                mdns::async_io::Behaviour::new(mdns::Config::default(), local_peer_id).unwrap(),
+            ),
+            TransportKind::Memory => None,
+        };
 
         let mut swarm = SwarmBuilder::with_async_std_executor(
             transport,
             BitmessageNetBehaviour {
+                blocked_peers: Default::default(),
                 gossipsub: gossipsub::Behaviour::new(
                     gossipsub::MessageAuthenticity::Signed(local_key.clone()),
                     Default::default(),
@@ -182,22 +921,46 @@ impl NodeWorker {
                         )
                         .to_owned(),
                 ),
-                identify: identify::Behaviour::new(identify::Config::new(
-                    IDENTIFY_PROTO_NAME.to_string(),
-                    local_key.public(),
-                )),
-                mdns: mdns::async_io::Behaviour::new(mdns::Config::default(), local_peer_id)
-                    .unwrap(),
-                keep_alive: keep_alive::Behaviour::default(),
+                identify: identify::Behaviour::new(
+                    identify::Config::new(
+                        format!("{}{}", PROTOCOL_VERSION_PREFIX, PROTOCOL_VERSION),
+                        local_key.public(),
+                    )
+                    .with_agent_version(agent_version(&config)),
+                ),
+                mdns: mdns.into(),
+                autonat: config
+                    .nat_traversal
+                    .then(|| autonat::Behaviour::new(local_peer_id, Default::default()))
+                    .into(),
+                relay_client: config.nat_traversal.then_some(relay_client).into(),
+                dcutr: config
+                    .nat_traversal
+                    .then(|| dcutr::Behaviour::new(local_peer_id))
+                    .into(),
+                relay: config
+                    .relay_server
+                    .then(|| relay::Behaviour::new(local_peer_id, Default::default()))
+                    .into(),
+                keep_alive: Default::default(),
             },
             local_peer_id,
         )
         .build();
 
-        if let Some(bootstrap_peers) = bootstrap_nodes {
+        let mut protected_peers = HashSet::new();
+        if !config.bootstrap_peers.is_empty() {
+            let bootstrap_peers = &config.bootstrap_peers;
             // First, we add the addresses of the bootstrap nodes to our view of the DHT
-            for peer_address in &bootstrap_peers {
-                let peer_id = extract_peer_id_from_multiaddr(peer_address).unwrap(); // FIXME
+            for peer_address in bootstrap_peers {
+                let peer_id = match extract_peer_id_from_multiaddr(peer_address) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        log::warn!("Skipping bootstrap peer {}: {}", peer_address, e);
+                        continue;
+                    }
+                };
+                protected_peers.insert(peer_id);
                 swarm
                     .behaviour_mut()
                     .kademlia
@@ -206,100 +969,226 @@ impl NodeWorker {
 
             // Next, we add our own info to the DHT. This will then automatically be shared
             // with the other peers on the DHT. This operation will fail if we are a bootstrap peer.
-            swarm
-                .behaviour_mut()
-                .kademlia
-                .bootstrap()
-                .map_err(|err| err)
-                .unwrap();
+            if let Err(e) = swarm.behaviour_mut().kademlia.bootstrap() {
+                log::warn!("Failed to bootstrap the DHT: {:?}", e);
+            }
         }
 
-        let data_dir_buf = data_dir.join("db");
-        fs::create_dir_all(&data_dir_buf).expect("db folder is created");
-        let db_url = data_dir_buf.join("database.db");
-
-        debug!("{:?}", db_url.to_str().unwrap());
-
-        let topic = Sha256Topic::new(COMMON_PUBSUB_TOPIC);
+        let topic = Sha256Topic::new(&config.pubsub_topic);
         swarm
             .behaviour_mut()
             .gossipsub
             .subscribe(&topic)
             .expect("subscription not to fail");
+        let shard_topics: HashMap<u8, Sha256Topic> = if config.topic_sharding {
+            ObjectType::ALL
+                .iter()
+                .map(|t| (*t as u8, shard_topic(&config.pubsub_topic, *t)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        // objects of other types are announced by others, but not received
+        for (object_type, topic) in &shard_topics {
+            if config.wants_object_type(*object_type) {
+                swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .subscribe(topic)
+                    .expect("subscription not to fail");
+            }
+        }
 
-        let (sender, receiver) = mpsc::channel(3);
+        let (command_sender, command_receiver) = command_queue::channel(config.command_queue_size);
+        // commands of the handler and the PoW worker are never rejected, since the node
+        // can't wait for itself
+        let (sender, internal_command_receiver) = mpsc::unbounded();
         let (pubkey_notifier_sink, pubkey_notifier) = mpsc::channel(3);
 
-        let connect_options =
-            SqliteConnectOptions::from_str(&format!("sqlite://{}", db_url.to_string_lossy()))
-                .unwrap()
-                .create_if_missing(true)
-                .journal_mode(SqliteJournalMode::Wal)
-                .foreign_keys(true)
-                .synchronous(SqliteSynchronous::Normal)
-                .busy_timeout(POOL_TIMEOUT);
+        let inventory_repo = storage.inventory_repo();
+        let address_repo = storage.address_repo();
+        let message_repo = storage.message_repo();
+        let peer_repo = storage.peer_repo();
 
-        let pool = task::block_on(SqlitePoolOptions::new().connect_with(connect_options))
-            .expect("pool open");
+        let peer_idle_timeout = config.peer_idle_timeout.and_then(|t| t.to_std().ok());
+        let pubkey_wait_timeout = config.pubkey_wait_timeout;
+        let trash_retention = config.trash_retention;
+        let download_limiter = config.max_download_rate.map(|r| TokenBucket::new(r, r));
+        let upload_limiter = config.max_upload_rate.map(|r| TokenBucket::new(r, r));
+        let peer_message_rate = config.peer_message_rate;
+        let msg_ttl = config.msg_ttl;
+        let pubkey_ttl = config.pubkey_ttl;
+        let max_resends = config.max_resends;
+        let reconnect_peers = config.reconnect_peers;
+        let role = config.role;
+        let max_inventory_size = config.max_inventory_size;
+        let inventory_eviction = config.inventory_eviction;
 
-        task::block_on(MIGRATIONS.run(&pool)).expect("migrations not to fail");
-
-        let inventory_repo = Box::new(SqliteInventoryRepository::new(pool.clone()));
-        let address_repo = Box::new(SqliteAddressRepository::new(pool.clone()));
-        let message_repo = Box::new(SqliteMessageRepository::new(pool.clone()));
+        let (pow_worker, pow_worker_sink) = ProofOfWorkWorker::new(
+            inventory_repo.clone(),
+            message_repo.clone(),
+            address_repo.clone(),
+            sender.clone(),
+            &config,
+        );
+        // nothing is written to the data dir with in-memory storage
+        let data_dir = Some(data_dir).filter(|_| config.storage != StorageKind::Memory);
+        #[cfg(feature = "legacy-bridge")]
+        let legacy_bridge = BridgeHandle::new(&config, sender.clone());
+        #[cfg(feature = "legacy-bridge")]
+        if let Some(bridge) = &legacy_bridge {
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(&bridge.topic)
+                .expect("subscription not to fail");
+        }
+        let mut handler = Handler::new(
+            address_repo.clone(),
+            inventory_repo.clone(),
+            message_repo.clone(),
+            sender.clone(),
+            pubkey_notifier_sink,
+            config.clone(),
+        );
+        handler.set_pow_worker_sink(pow_worker_sink.clone());
 
         (
             Self {
                 local_peer_id,
+                data_dir,
+                config,
+                data_dir_lock,
                 swarm,
-                handler: Handler::new(
-                    address_repo.clone(),
-                    inventory_repo.clone(),
-                    message_repo.clone(),
-                    sender.clone(),
-                    pubkey_notifier_sink,
-                ),
-                command_sender: sender.clone(),
+                listeners: Vec::new(),
+                handler,
                 pubkey_notifier,
                 tracked_pubkeys: HashMap::new(),
-                command_receiver: receiver,
+                command_receiver,
+                internal_command_receiver,
                 pending_commands: Vec::new(),
-                _sqlite_connection_pool: pool,
+                pending_broadcasts: VecDeque::new(),
+                announcement_batch: Vec::new(),
+                recent_announcements: RecentAnnouncements::new(
+                    ANNOUNCEMENT_DEDUP_WINDOW,
+                    MAX_RECENT_ANNOUNCEMENTS,
+                ),
+                peer_idle_timeout,
+                pubkey_wait_timeout,
+                pow_worker: Some(pow_worker),
+                trash_retention,
+                max_inventory_size,
+                inventory_eviction,
+                evicted_objects: 0,
+                last_vacuum: Instant::now(),
+                power_mode: PowerMode::Normal,
+                last_maintenance: Instant::now(),
+                role,
+                peer_roles: HashMap::new(),
+                peer_versions: HashMap::new(),
+                dialed_addresses: HashMap::new(),
+                remote_addresses: HashMap::new(),
+                banned_addresses: Vec::new(),
+                behind_nat: false,
+                relay_candidates: HashMap::new(),
+                relay_listeners: HashMap::new(),
+                peer_activity: HashMap::new(),
+                pending_requests: HashMap::new(),
+                protected_peers,
+                reconnect_peers,
+                requested_objects: HashMap::new(),
+                download_limiter,
+                upload_limiter,
+                peer_message_rate,
+                msg_ttl,
+                pubkey_ttl,
+                max_resends,
+                peer_limiters: HashMap::new(),
+                message_status_subscribers: Vec::new(),
+                online: false,
+                connectivity_subscribers: Vec::new(),
+                key_mismatch_subscribers: Vec::new(),
+                traffic_stats: TrafficStats::default(),
+                last_bootstrap: None,
+                storage,
                 common_topic: topic,
+                shard_topics,
 
                 address_repo: address_repo.clone(),
                 inventory_repo: inventory_repo.clone(),
                 messages_repo: message_repo.clone(),
+                peer_repo,
 
-                pow_worker_command_sink: None,
+                pow_worker_command_sink: Some(pow_worker_sink),
+                #[cfg(feature = "legacy-bridge")]
+                legacy_bridge,
             },
-            sender,
+            command_sender,
         )
     }
 
+    /// Notify senders of `Dial` commands for the peer about the outcome of the dial
+    fn resolve_pending_dials(&mut self, peer_id: PeerId, error: Option<String>) {
+        let (dials, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_commands)
+            .into_iter()
+            .partition(|c| match c {
+                WorkerCommand::Dial { peer, .. } => {
+                    extract_peer_id_from_multiaddr(peer).ok() == Some(peer_id)
+                }
+                _ => false,
+            });
+        self.pending_commands = rest;
+        for d in dials {
+            if let WorkerCommand::Dial { sender, .. } = d {
+                let res = match &error {
+                    Some(e) => Err(Box::<dyn Error + Send + Sync>::from(e.clone()) as _),
+                    None => Ok(()),
+                };
+                // dialer might not wait for the result anymore
+                _ = sender.send(res);
+            }
+        }
+    }
+
     async fn handle_event<E>(&mut self, event: SwarmEvent<BitmessageBehaviourEvent, E>) {
         match event {
             SwarmEvent::NewListenAddr { address, .. } => {
                 info!("Listening on {:?}", address);
-                let indexes: Vec<usize> = self
-                    .pending_commands
-                    .iter()
-                    .enumerate()
-                    .filter_map(|(i, v)| match v {
-                        WorkerCommand::GetListenerAddress { sender: _ } => Some(i.clone()),
-                        _ => None,
-                    })
-                    .collect();
-                for i in indexes {
-                    if let WorkerCommand::GetListenerAddress { sender } =
-                        self.pending_commands.remove(i)
-                    {
-                        sender
-                            .send(address.clone())
-                            .expect("Receiver not to be dropped");
+                let (waiting, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_commands)
+                    .into_iter()
+                    .partition(|c| matches!(c, WorkerCommand::GetListenerAddress { .. }));
+                self.pending_commands = rest;
+                let listeners: Vec<Multiaddr> = self.swarm.listeners().cloned().collect();
+                for c in waiting {
+                    if let WorkerCommand::GetListenerAddress { sender } = c {
+                        _ = sender.send(listeners.clone());
                     }
                 }
             }
+            SwarmEvent::ConnectionEstablished {
+                peer_id, endpoint, ..
+            } => {
+                if self.is_address_banned(endpoint.get_remote_address()) {
+                    debug!("Disconnecting banned peer {}", peer_id);
+                    _ = self.swarm.disconnect_peer_id(peer_id);
+                    return;
+                }
+                self.remote_addresses
+                    .insert(peer_id, endpoint.get_remote_address().clone());
+                if let ConnectedPoint::Dialer { address, .. } = endpoint {
+                    self.dialed_addresses.insert(peer_id, address);
+                }
+                self.peer_activity.insert(peer_id, Instant::now());
+                self.resolve_pending_dials(peer_id, None);
+            }
+            SwarmEvent::OutgoingConnectionError {
+                peer_id: Some(peer_id),
+                error,
+            } => {
+                debug!("Failed to dial {}: {}", peer_id, error);
+                self.resolve_pending_dials(peer_id, Some(error.to_string()));
+                self.postpone_peer(peer_id).await;
+            }
             SwarmEvent::ConnectionClosed {
                 peer_id,
                 endpoint: _endpoint,
@@ -307,45 +1196,147 @@ impl NodeWorker {
                 cause: _cause,
             } => {
                 if num_established == 0 {
+                    self.peer_activity.remove(&peer_id);
+                    self.pending_requests.remove(&peer_id);
+                    self.peer_roles.remove(&peer_id);
+                    self.peer_versions.remove(&peer_id);
+                    self.handler.set_peer_object_types(peer_id, None);
+                    self.peer_limiters.remove(&peer_id);
+                    self.dialed_addresses.remove(&peer_id);
+                    self.remote_addresses.remove(&peer_id);
+                    self.relay_candidates.remove(&peer_id);
+                    if let Some(listener) = self.relay_listeners.remove(&peer_id) {
+                        self.swarm.remove_listener(listener);
+                        self.listen_via_relays();
+                    }
                     self.swarm
                         .behaviour_mut()
                         .gossipsub
                         .remove_explicit_peer(&peer_id);
                     self.swarm.behaviour_mut().kademlia.remove_peer(&peer_id);
                 }
+                self.update_connectivity();
             }
             SwarmEvent::Behaviour(BitmessageBehaviourEvent::RequestResponse(
                 request_response::Event::Message { message, peer, .. },
-            )) => match message {
-                request_response::Message::Request {
-                    request_id,
-                    request,
-                    channel,
-                } => {
-                    debug!("received request {}: {:?}", request_id, request);
-                    let msg = self.handler.handle_message(request.0).await.unwrap();
-                    self.swarm
-                        .behaviour_mut()
-                        .rpc
-                        .send_response(channel, BitmessageResponse(msg))
-                        .unwrap()
-                }
-                request_response::Message::Response {
-                    request_id,
-                    response,
-                } => {
-                    debug!("received response on {}: {:?}", request_id, response);
-                    let another_request = self.handler.handle_message(response.0).await;
-                    if let Some(m) = another_request {
-                        self.swarm
+            )) => {
+                self.peer_activity.insert(peer, Instant::now());
+                match message {
+                    request_response::Message::Request {
+                        request_id,
+                        request,
+                        channel,
+                    } => {
+                        if !self.accept_incoming(peer, request.0.len()) {
+                            return;
+                        }
+                        let (msg, version) = match NetworkMessage::decode(&request.0) {
+                            Ok(m) => m,
+                            Err(e) => {
+                                debug!("Ignoring request from {}: {}", peer, e);
+                                self.report_undecodable(peer, e).await;
+                                return;
+                            }
+                        };
+                        debug!("received request {}: {:?}", request_id, msg);
+                        self.forget_received_objects(&msg);
+                        let reply = match &msg.payload {
+                            MessagePayload::Addr { peers } => {
+                                self.store_exchanged_peers(peers).await;
+                                Some(self.peer_exchange_message().await)
+                            }
+                            _ => self.handler.handle_message(peer, msg).await,
+                        };
+                        let Some(reply) = reply else {
+                            return;
+                        };
+                        // the peer is able to decode messages of the version it sends
+                        let data = reply.encode(version.min(PROTOCOL_VERSION));
+                        if !self.accept_outgoing_response(data.len()) {
+                            debug!("Upload limit is exceeded, dropping response to {}", peer);
+                            return;
+                        }
+                        _ = self
+                            .swarm
                             .behaviour_mut()
                             .rpc
-                            .send_request(&peer, BitmessageRequest(m));
+                            .send_response(channel, BitmessageResponse(data));
+                    }
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    } => {
+                        self.finish_request(peer, request_id);
+                        if !self.accept_incoming(peer, response.0.len()) {
+                            return;
+                        }
+                        let msg = match NetworkMessage::decode(&response.0) {
+                            Ok((m, _)) => m,
+                            Err(e) => {
+                                debug!("Ignoring response from {}: {}", peer, e);
+                                self.report_undecodable(peer, e).await;
+                                return;
+                            }
+                        };
+                        debug!("received response on {}: {:?}", request_id, msg);
+                        if let MessagePayload::Addr { peers } = &msg.payload {
+                            self.store_exchanged_peers(peers).await;
+                            return;
+                        }
+                        self.forget_received_objects(&msg);
+                        self.request_next_inventory_page(peer, &msg);
+                        let another_request = self.handler.handle_message(peer, msg).await;
+                        if let Some(m) = another_request {
+                            self.send_request(peer, m);
+                        }
                     }
                 }
-            },
+            }
+            SwarmEvent::Behaviour(BitmessageBehaviourEvent::RequestResponse(
+                request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                },
+            )) => {
+                debug!("Request {} to {} failed: {}", request_id, peer, error);
+                self.finish_request(peer, request_id);
+            }
             SwarmEvent::Behaviour(BitmessageBehaviourEvent::Identify(e)) => {
-                self.handle_identify_event(e)
+                self.handle_identify_event(e).await
+            }
+            SwarmEvent::Behaviour(BitmessageBehaviourEvent::Autonat(
+                autonat::Event::StatusChanged { new, .. },
+            )) => self.handle_nat_status(new),
+            SwarmEvent::Behaviour(BitmessageBehaviourEvent::RelayClient(e)) => match e {
+                relay::client::Event::ReservationReqAccepted { relay_peer_id, .. } => {
+                    info!("Reachable via relay {}", relay_peer_id)
+                }
+                relay::client::Event::ReservationReqFailed {
+                    relay_peer_id,
+                    error,
+                    ..
+                } => debug!("Relay {} rejected reservation: {}", relay_peer_id, error),
+                e => debug!("Relay client event: {:?}", e),
+            },
+            SwarmEvent::Behaviour(BitmessageBehaviourEvent::Dcutr(e)) => match e {
+                dcutr::Event::DirectConnectionUpgradeSucceeded { remote_peer_id } => {
+                    info!(
+                        "Connected to {} directly with hole punching",
+                        remote_peer_id
+                    )
+                }
+                dcutr::Event::DirectConnectionUpgradeFailed {
+                    remote_peer_id,
+                    error,
+                } => debug!(
+                    "Hole punching to {} failed, staying relayed: {}",
+                    remote_peer_id, error
+                ),
+                e => debug!("DCUtR event: {:?}", e),
+            },
+            SwarmEvent::Behaviour(BitmessageBehaviourEvent::Relay(e)) => {
+                debug!("Relay server event: {:?}", e)
             }
             SwarmEvent::Behaviour(BitmessageBehaviourEvent::Mdns(mdns::Event::Discovered(
                 list,
@@ -362,325 +1353,1096 @@ impl NodeWorker {
                         .add_explicit_peer(&peer_id);
                     self.on_new_peer(peer_id.clone());
                 }
+                self.broadcast_pending().await;
             }
             SwarmEvent::Behaviour(BitmessageBehaviourEvent::Gossipsub(
-                gossipsub::Event::Message {
-                    propagation_source: _,
+                gossipsub::Event::Subscribed { peer_id, topic },
+            )) if self.is_object_topic(&topic) => {
+                debug!("Peer {} subscribed to the topic {}", peer_id, topic);
+                self.broadcast_pending().await;
+            }
+            SwarmEvent::Behaviour(BitmessageBehaviourEvent::Gossipsub(
+                gossipsub::Event::Unsubscribed { peer_id, topic },
+            )) if self.is_object_topic(&topic) => {
+                debug!("Peer {} unsubscribed from the topic {}", peer_id, topic);
+                self.update_connectivity();
+            }
+            SwarmEvent::Behaviour(BitmessageBehaviourEvent::Gossipsub(
+                gossipsub::Event::Message {
+                    propagation_source,
                     message_id: _,
                     message,
                 },
             )) => {
-                if message.topic != self.common_topic.hash() {
+                self.peer_activity
+                    .insert(propagation_source, Instant::now());
+                self.traffic_stats.gossip_messages_received += 1;
+                #[cfg(feature = "legacy-bridge")]
+                if let Some(sink) = self
+                    .legacy_bridge
+                    .as_ref()
+                    .filter(|b| message.topic == b.topic.hash())
+                    .map(|b| b.sink.clone())
+                {
+                    if self.accept_incoming(propagation_source, message.data.len()) {
+                        _ = sink.unbounded_send(message.data);
+                    }
                     return;
                 }
-                let msg: NetworkMessage = serde_cbor::from_slice(&message.data).unwrap();
-                let reply = self.handler.handle_message(msg).await;
-                if let Some(m) = reply {
-                    self.swarm
-                        .behaviour_mut()
-                        .rpc
-                        .send_request(&message.source.unwrap(), BitmessageRequest(m));
+                if !self.is_object_topic(&message.topic) {
+                    return;
+                }
+                if !self.accept_incoming(propagation_source, message.data.len()) {
+                    return;
+                }
+                let msg = match NetworkMessage::decode(&message.data) {
+                    Ok((m, _)) => m,
+                    Err(e) => {
+                        debug!("Ignoring gossip from {}: {}", propagation_source, e);
+                        self.report_undecodable(propagation_source, e).await;
+                        return;
+                    }
+                };
+                self.forget_received_objects(&msg);
+                let reply = self.handler.handle_message(propagation_source, msg).await;
+                if let (Some(m), Some(source)) = (reply, message.source) {
+                    self.send_request(source, m);
                 }
             }
+            SwarmEvent::Behaviour(BitmessageBehaviourEvent::Kademlia(
+                KademliaEvent::OutboundQueryProgressed {
+                    result: QueryResult::Bootstrap(result),
+                    ..
+                },
+            )) => match result {
+                // bootstrap queries the buckets one by one, it's done after the last one
+                Ok(BootstrapOk {
+                    num_remaining: 0, ..
+                }) => self.record_bootstrap(None),
+                Ok(_) => {}
+                Err(e) => self.record_bootstrap(Some(e.to_string())),
+            },
             _ => {}
         }
     }
 
-    async fn handle_command(&mut self, command: WorkerCommand) {
-        match command {
-            WorkerCommand::StartListening { multiaddr, sender } => {
-                debug!("Starting listening to the network...");
-                match self.swarm.listen_on(multiaddr.clone()) {
-                    Ok(_) => sender.send(Ok(())).expect("Receiver not to be dropped"),
-                    Err(e) => sender
-                        .send(Err(Box::new(e)))
-                        .expect("Receiver not to be dropped"),
-                };
-            }
-            WorkerCommand::Dial {
-                peer: _peer,
-                sender: _sender,
-            } => todo!(),
-            WorkerCommand::GetListenerAddress { sender } => match self.swarm.listeners().next() {
-                Some(v) => {
-                    sender.send(v.clone()).expect("Receiver not to be dropped");
-                }
-                None => {
-                    self.pending_commands
-                        .push(WorkerCommand::GetListenerAddress { sender });
-                }
-            },
-            WorkerCommand::GetPeerID { sender } => sender
-                .send(self.local_peer_id)
-                .expect("Receiver not to be dropped"),
-            WorkerCommand::BroadcastMsgByPubSub { sender, msg } => match self.publish_pubsub(msg) {
-                Ok(_) => sender.send(Ok(())).expect("receiver not to be dropped"),
-                Err(e) => sender
-                    .send(Err(Box::new(e)))
-                    .expect("receiver not to be dropped"),
-            },
-            WorkerCommand::NonceCalculated { obj } => {
-                match &obj.kind {
-                    ObjectKind::Msg { encrypted: _ } => self
-                        .messages_repo
-                        .update_message_status(
-                            bs58::encode(&obj.hash).into_string(),
-                            MessageStatus::Sent,
-                        )
-                        .await
-                        .unwrap(),
-                    _ => {}
-                }
+    /// Notify subscribers if peers to broadcast objects to have appeared or are gone
+    fn update_connectivity(&mut self) {
+        let online = self
+            .swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .any(|(_, topics)| topics.into_iter().any(|t| self.is_object_topic(t)));
+        if online == self.online {
+            return;
+        }
+        if online {
+            info!("Node is online");
+        } else {
+            info!("Node is offline, objects will be broadcast once peers appear");
+        }
+        self.online = online;
+        self.connectivity_subscribers
+            .retain(|s| s.unbounded_send(online).is_ok());
+    }
 
-                let inventory = self.inventory_repo.get().await.expect("repo not to fail");
-                let msg = NetworkMessage {
-                    command: MessageCommand::Inv,
-                    payload: MessagePayload::Inv { inventory },
-                };
-                let result = self.publish_pubsub(msg);
-                match result {
-                    Err(e) => {
-                        log::error!("Pubsub failed to publish the message: {}", e);
-                    }
-                    _ => {}
-                }
+    /// Announce objects whose PoW was done within the batch window, and mark their
+    /// messages sent
+    pub(super) async fn announce_batch(&mut self) {
+        let batch = std::mem::take(&mut self.announcement_batch);
+        if batch.is_empty() {
+            return;
+        }
+        self.announce_objects(
+            batch
+                .iter()
+                .map(|b| (b.hash.clone(), b.expires, b.object_type))
+                .collect(),
+        );
+        for b in batch.into_iter().filter(|b| b.is_message) {
+            // without peers the message waits for them along with its announcement
+            if !self.is_pending_announcement(&b.hash) {
+                self.mark_message_sent(b.hash, b.resend_at).await;
+                continue;
             }
-            WorkerCommand::GetOwnIdentities { sender } => {
-                let result = self.address_repo.get_identities().await;
-                match result {
-                    Ok(a) => {
-                        sender.send(Ok(a)).expect("receiver not to be dropped");
+            match retry_db!(self
+                .messages_repo
+                .update_message_status(b.hash.clone(), MessageStatus::WaitingForPeers))
+            {
+                Ok(true) => self.notify_message_status(MessageStatusEvent::new(
+                    b.hash,
+                    MessageStatus::WaitingForPeers,
+                )),
+                Ok(false) => {}
+                Err(e) => log::error!("Failed to update status of message {}: {}", b.hash, e),
+            }
+        }
+    }
+
+    /// Whether the object is in announcements waiting for peers
+    pub(super) fn is_pending_announcement(&self, hash: &str) -> bool {
+        self.pending_broadcasts
+            .iter()
+            .any(|msg| match &msg.payload {
+                MessagePayload::Inv { inventory, .. } => inventory.iter().any(|h| h == hash),
+                _ => false,
+            })
+    }
+
+    /// Remove the object from announcements waiting for peers. Returns `false` if it
+    /// isn't there, i.e. it was already announced.
+    pub(super) fn remove_pending_announcement(&mut self, hash: &str) -> bool {
+        let mut found = false;
+        for msg in self.pending_broadcasts.iter_mut() {
+            if let MessagePayload::Inv {
+                inventory,
+                expires,
+                types,
+                ..
+            } = &mut msg.payload
+            {
+                if let Some(i) = inventory.iter().position(|h| h == hash) {
+                    inventory.remove(i);
+                    if expires.len() > i {
+                        expires.remove(i);
                     }
-                    Err(e) => {
-                        sender
-                            .send(Err(Box::from(e.to_string())))
-                            .expect("receiver not to be dropped");
-                        return;
+                    if types.len() > i {
+                        types.remove(i);
                     }
+                    found = true;
                 }
             }
-            WorkerCommand::GenerateIdentity { label, sender } => {
-                let mut address = Address::generate();
-                address.label = label;
-                let res = self.address_repo.store(address.clone()).await;
-                match res {
-                    Ok(_) => {
-                        sender
-                            .send(Ok(address.string_repr))
-                            .expect("receiver not to be dropped");
+        }
+        found
+    }
+
+    /// Dial the most recently seen peers we aren't connected to, until the node
+    /// has `reconnect_peers` connections (or [`LOW_POWER_PEERS`] in low-power mode).
+    /// Peers which failed recently are skipped.
+    async fn dial_known_peers(&mut self) {
+        let wanted = match self.power_mode {
+            PowerMode::Normal => self.reconnect_peers,
+            PowerMode::LowPower => self.reconnect_peers.min(LOW_POWER_PEERS),
+        };
+        let missing = wanted.saturating_sub(self.swarm.connected_peers().count());
+        if missing == 0 {
+            return;
+        }
+        let known_peers = match retry_db!(self.peer_repo.get_dialable()) {
+            Ok(p) => p,
+            Err(e) => {
+                log::error!("Failed to list known peers: {}", e);
+                return;
+            }
+        };
+        let mut peers: Vec<(PeerId, Vec<Multiaddr>)> = Vec::new();
+        for p in known_peers {
+            let (peer_id, addr) = match (p.peer_id.parse::<PeerId>(), p.multiaddr.parse()) {
+                (Ok(peer_id), Ok(addr)) => (peer_id, addr),
+                _ => continue,
+            };
+            if peer_id == self.local_peer_id
+                || self.swarm.is_connected(&peer_id)
+                || self.is_address_banned(&addr)
+            {
+                continue;
+            }
+            match peers.iter().position(|(id, _)| *id == peer_id) {
+                Some(i) => peers[i].1.push(addr),
+                None if peers.len() < missing => peers.push((peer_id, vec![addr])),
+                None => {}
+            }
+        }
+        for (peer_id, addrs) in peers {
+            debug!("Reconnecting to known peer {}", peer_id);
+            if let Err(e) = self
+                .swarm
+                .dial(DialOpts::peer_id(peer_id).addresses(addrs).build())
+            {
+                debug!("Failed to dial {}: {}", peer_id, e);
+            }
+        }
+    }
+
+    /// Send a sample of known peers to the connected ones, they reply with theirs
+    async fn exchange_peers(&mut self) {
+        let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        if peers.is_empty() {
+            return;
+        }
+        let msg = self.peer_exchange_message().await;
+        for peer in peers {
+            self.send_request(peer, msg.clone());
+        }
+    }
+
+    /// Addr message with a random sample of the peers which didn't fail recently
+    async fn peer_exchange_message(&mut self) -> NetworkMessage {
+        let known_peers = retry_db!(self.peer_repo.get_dialable()).unwrap_or_else(|e| {
+            log::error!("Failed to list known peers: {}", e);
+            Vec::new()
+        });
+        let known_peers: Vec<_> = known_peers
+            .into_iter()
+            .filter(|p| p.failures == 0)
+            .collect();
+        let peers = known_peers
+            .choose_multiple(&mut rand::thread_rng(), MAX_ADDR_PEERS)
+            .map(|p| PeerAddress {
+                peer_id: p.peer_id.clone(),
+                multiaddr: p.multiaddr.clone(),
+                last_seen: p.last_seen.timestamp(),
+            })
+            .collect();
+        NetworkMessage {
+            command: MessageCommand::Addr,
+            payload: MessagePayload::Addr { peers },
+        }
+    }
+
+    /// Remember peers shared by another peer and dial them if the node lacks connections.
+    /// Peers the node knows already keep the time it saw them.
+    async fn store_exchanged_peers(&mut self, peers: &[PeerAddress]) {
+        let now = Utc::now();
+        for p in peers.iter().take(MAX_ADDR_PEERS) {
+            let (Ok(peer_id), Ok(addr)) = (p.peer_id.parse::<PeerId>(), p.multiaddr.parse()) else {
+                continue;
+            };
+            if peer_id == self.local_peer_id || self.is_address_banned(&addr) {
+                continue;
+            }
+            // the peer can't claim its peers were seen later than now
+            let last_seen = NaiveDateTime::from_timestamp_opt(p.last_seen, 0)
+                .map(|t| DateTime::<Utc>::from_utc(t, Utc))
+                .map_or(now, |t| t.min(now));
+            if let Err(e) = retry_db!(self.peer_repo.store_exchanged(
+                p.peer_id.clone(),
+                p.multiaddr.clone(),
+                last_seen
+            )) {
+                log::error!("Failed to store exchanged peer {}: {}", p.peer_id, e);
+            }
+        }
+        self.dial_known_peers().await;
+    }
+
+    pub(super) fn dht_status(&mut self) -> DhtStatus {
+        let buckets: Vec<(u32, usize)> = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .kbuckets()
+            .filter(|b| !b.is_empty())
+            .map(|b| (b.range().0.ilog2().unwrap_or_default(), b.num_entries()))
+            .collect();
+        DhtStatus {
+            known_peers: buckets.iter().map(|(_, n)| n).sum(),
+            buckets,
+            last_bootstrap: self.last_bootstrap.clone(),
+        }
+    }
+
+    fn record_bootstrap(&mut self, error: Option<String>) {
+        match &error {
+            Some(e) => debug!("DHT bootstrap has failed: {}", e),
+            None => debug!("DHT bootstrap has finished"),
+        }
+        self.last_bootstrap = Some(BootstrapResult {
+            finished_at: Utc::now(),
+            error,
+        });
+    }
+
+    /// Bootstrap again once the routing table is empty (e.g. after all peers have
+    /// disconnected), since no peers can be discovered without it
+    fn rebootstrap_if_empty(&mut self) {
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        if kademlia.kbuckets().any(|b| !b.is_empty()) {
+            return;
+        }
+        // bootstrap peers are removed from the routing table once they disconnect
+        for address in &self.config.bootstrap_peers {
+            if let Ok(peer_id) = extract_peer_id_from_multiaddr(address) {
+                kademlia.add_address(&peer_id, address.clone());
+            }
+        }
+        if let Err(e) = kademlia.bootstrap() {
+            self.record_bootstrap(Some(e.to_string()));
+        }
+    }
+
+    /// Penalize the peer for undecodable messages, unless they're just newer than ours
+    async fn report_undecodable(&mut self, peer: PeerId, error: DecodeError) {
+        if let DecodeError::Malformed(_) = error {
+            self.handler
+                .report_misbehavior(peer, Misbehavior::MalformedPayload)
+                .await;
+        }
+    }
+
+    pub(super) fn is_address_banned(&self, address: &Multiaddr) -> bool {
+        self.banned_addresses
+            .iter()
+            .any(|banned| is_address_prefix(banned, address))
+    }
+
+    /// Block the peer id or address, disconnecting matching peers.
+    /// Returns normalized target.
+    fn apply_ban(&mut self, target: &str) -> Result<String, Box<dyn Error>> {
+        if let Ok(peer_id) = target.parse::<PeerId>() {
+            self.swarm.behaviour_mut().blocked_peers.block_peer(peer_id);
+            return Ok(peer_id.to_string());
+        }
+        let address: Multiaddr = target
+            .parse()
+            .map_err(|_| format!("{} is neither a peer id nor a multiaddr", target))?;
+        let banned_peers: Vec<PeerId> = self
+            .remote_addresses
+            .iter()
+            .filter(|(_, a)| is_address_prefix(&address, a))
+            .map(|(p, _)| *p)
+            .collect();
+        self.banned_addresses.push(address.clone());
+        for peer in banned_peers {
+            debug!("Disconnecting banned peer {}", peer);
+            _ = self.swarm.disconnect_peer_id(peer);
+        }
+        Ok(address.to_string())
+    }
+
+    pub(super) async fn ban_peer(
+        &mut self,
+        target: String,
+        reason: Option<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let target = self.apply_ban(&target)?;
+        self.peer_repo.ban(target, reason).await
+    }
+
+    pub(super) async fn unban_peer(&mut self, target: String) -> Result<(), Box<dyn Error>> {
+        if !self.peer_repo.unban(target.clone()).await? {
+            return Err(format!("{} isn't banned", target).into());
+        }
+        match target.parse::<PeerId>() {
+            Ok(peer_id) => self
+                .swarm
+                .behaviour_mut()
+                .blocked_peers
+                .unblock_peer(peer_id),
+            Err(_) => self.banned_addresses.retain(|a| a.to_string() != target),
+        }
+        Ok(())
+    }
+
+    /// Back off from dialing remembered peer which can't be reached
+    async fn postpone_peer(&mut self, peer_id: PeerId) {
+        let failures = match retry_db!(self.peer_repo.record_failure(peer_id.to_string())) {
+            Ok(Some(f)) => f,
+            Ok(None) => return,
+            Err(e) => {
+                log::error!("Failed to record failure of peer {}: {}", peer_id, e);
+                return;
+            }
+        };
+        let backoff = PEER_RECONNECT_BACKOFF
+            .checked_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .unwrap_or(MAX_PEER_RECONNECT_BACKOFF)
+            .min(MAX_PEER_RECONNECT_BACKOFF);
+        let until = Utc::now() + chrono::Duration::from_std(backoff).expect("backoff to be small");
+        if let Err(e) = retry_db!(self.peer_repo.postpone(peer_id.to_string(), until)) {
+            log::error!("Failed to postpone peer {}: {}", peer_id, e);
+        }
+    }
+
+    /// Remove expired objects and evict objects beyond the size limit. The database
+    /// is compacted once in a while, so that the freed space is given back.
+    async fn maintain_inventory(&mut self) -> Result<(), Box<dyn Error>> {
+        retry_db!(self.inventory_repo.cleanup())?;
+        if let Some(max_size) = self.max_inventory_size {
+            let evicted = retry_db!(self.inventory_repo.evict(max_size, self.inventory_eviction))?;
+            if evicted > 0 {
+                info!(
+                    "Evicted {} objects to stay within inventory size limit",
+                    evicted
+                );
+                self.evicted_objects += evicted as u64;
+            }
+        }
+        if self.last_vacuum.elapsed() >= VACUUM_INTERVAL {
+            self.last_vacuum = Instant::now();
+            if let Err(e) = self.storage.vacuum().await {
+                log::warn!("Failed to compact the database: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Permanently remove messages which stay in Trash longer than retention period
+    async fn purge_trash(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(retention) = self.trash_retention {
+            retry_db!(self.messages_repo.purge_trash(Utc::now() - retention))?;
+        }
+        Ok(())
+    }
+
+    /// Publish message to the common topic, or to the topics of object types if announcements
+    /// are sharded. If there are no peers to publish it to, message is queued and will be
+    /// published when the first peer appears.
+    pub(super) fn publish_pubsub(&mut self, msg: NetworkMessage) -> Result<(), PublishError> {
+        let Some(msg) = self.skip_recently_announced(msg) else {
+            debug!("All objects of the announcement were announced recently, skipping it");
+            return Ok(());
+        };
+        let mut result = Ok(());
+        for msg in self.split_by_topic(msg) {
+            let serialized_msg = msg.encode(self.broadcast_version());
+            self.traffic_stats.bytes_sent += serialized_msg.len() as u64;
+            let topic = self.topic_of(&msg);
+            match self
+                .swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(topic, serialized_msg)
+            {
+                Ok(_) => self.traffic_stats.gossip_messages_published += 1,
+                Err(PublishError::InsufficientPeers) => {
+                    if self.pending_broadcasts.len() >= MAX_PENDING_BROADCASTS {
+                        self.pending_broadcasts.pop_front();
                     }
-                    Err(e) => sender
-                        .send(Err(Box::from(e.to_string())))
-                        .expect("receiver not to be dropped"),
+                    self.pending_broadcasts.push_back(msg);
+                    result = result.and(Err(PublishError::InsufficientPeers));
                 }
+                Err(e) => result = result.and(Err(e)),
             }
-            WorkerCommand::RenameIdentity {
-                new_label,
-                address,
-                sender,
-            } => match self.address_repo.update_label(address, new_label).await {
-                Ok(_) => {
-                    sender.send(Ok(())).expect("receiver not to be dropped");
-                }
-                Err(e) => sender
-                    .send(Err(Box::from(e.to_string())))
-                    .expect("receiver not to be dropped"),
+        }
+        result
+    }
+
+    /// Remove objects which were announced within the dedup window from the announcement,
+    /// `None` if nothing is left to announce
+    fn skip_recently_announced(&mut self, msg: NetworkMessage) -> Option<NetworkMessage> {
+        let MessagePayload::Inv {
+            inventory,
+            expires,
+            types,
+            next,
+        } = msg.payload
+        else {
+            return Some(msg);
+        };
+        let now = Instant::now();
+        let new: Vec<bool> = inventory
+            .iter()
+            .map(|hash| self.recent_announcements.insert(hash, now))
+            .collect();
+        let inventory = retain_flagged(inventory, &new);
+        if inventory.is_empty() && next.is_none() {
+            return None;
+        }
+        Some(NetworkMessage {
+            command: msg.command,
+            payload: MessagePayload::Inv {
+                inventory,
+                expires: retain_flagged(expires, &new),
+                types: retain_flagged(types, &new),
+                next,
             },
-            WorkerCommand::DeleteIdentity { address, sender } => {
-                match self.address_repo.delete_address(address).await {
-                    Ok(_) => {
-                        sender.send(Ok(())).expect("receiver not to be dropped");
-                    }
-                    Err(e) => sender
-                        .send(Err(Box::from(e.to_string())))
-                        .expect("receiver not to be dropped"),
+        })
+    }
+
+    /// Split announcement into one announcement per object type if they're sharded
+    fn split_by_topic(&self, msg: NetworkMessage) -> Vec<NetworkMessage> {
+        let types = match &msg.payload {
+            MessagePayload::Inv {
+                inventory, types, ..
+            } if !self.shard_topics.is_empty() && types.len() == inventory.len() => types.clone(),
+            _ => return vec![msg],
+        };
+        let (inventory, expires) = match msg.payload {
+            MessagePayload::Inv {
+                inventory, expires, ..
+            } => (inventory, expires),
+            _ => unreachable!("payload is checked above"),
+        };
+        let mut shards: Vec<TopicShard> = Vec::new();
+        for (i, (hash, object_type)) in inventory.into_iter().zip(types).enumerate() {
+            // expiration times might be missing, 0 is what peers assume then
+            let item = (
+                hash,
+                expires.get(i).copied().unwrap_or_default(),
+                object_type,
+            );
+            match shards.iter_mut().find(|(t, _)| *t == object_type) {
+                Some((_, items)) => items.push(item),
+                None => shards.push((object_type, vec![item])),
+            }
+        }
+        shards
+            .into_iter()
+            .map(|(_, items)| NetworkMessage {
+                command: msg.command.clone(),
+                payload: MessagePayload::inv(items, None),
+            })
+            .collect()
+    }
+
+    /// Topic the message is published to. With sharding, announcements of objects of one
+    /// type go to the topic of that type, everything else goes to the common topic.
+    fn topic_of(&self, msg: &NetworkMessage) -> Sha256Topic {
+        if let MessagePayload::Inv { types, .. } = &msg.payload {
+            if let Some(topic) = types
+                .first()
+                .filter(|t| types.iter().all(|other| other == *t))
+                .and_then(|t| self.shard_topics.get(t))
+            {
+                return topic.clone();
+            }
+        }
+        self.common_topic.clone()
+    }
+
+    /// Check if objects are announced in the topic
+    fn is_object_topic(&self, topic: &TopicHash) -> bool {
+        *topic == self.common_topic.hash() || self.shard_topics.values().any(|t| t.hash() == *topic)
+    }
+
+    /// Protocol version the peer can decode, peers which haven't advertised it
+    /// yet are treated as legacy ones
+    fn peer_version(&self, peer: &PeerId) -> u32 {
+        self.peer_versions
+            .get(peer)
+            .map_or(LEGACY_PROTOCOL_VERSION, |v| (*v).min(PROTOCOL_VERSION))
+    }
+
+    /// Gossip is encoded so that every connected peer can decode it
+    fn broadcast_version(&self) -> u32 {
+        self.swarm
+            .connected_peers()
+            .map(|p| self.peer_version(p))
+            .min()
+            .unwrap_or(PROTOCOL_VERSION)
+    }
+
+    /// Announce our whole inventory to the common topic, page by page
+    async fn broadcast_inventory(&mut self) {
+        let mut after = None;
+        loop {
+            let page = retry_db!(self.inventory_repo.get_page(after.clone(), MAX_INV_HASHES));
+            let page = match page {
+                Ok(page) => page,
+                Err(e) => {
+                    log::error!("Failed to list inventory: {}", e);
+                    return;
                 }
+            };
+            after = page.last().map(|(hash, expires, _)| InventoryCursor {
+                expires: *expires,
+                hash: hash.clone(),
+            });
+            let is_last = page.len() < MAX_INV_HASHES;
+            self.announce_objects(page);
+            if is_last {
+                break;
             }
-            WorkerCommand::GetMessages {
-                address,
-                folder,
-                sender,
-            } => match folder {
-                Folder::Inbox => {
-                    match self.messages_repo.get_messages_by_recipient(address).await {
-                        Ok(v) => sender.send(Ok(v)).expect("receiver not to be dropped"),
-                        Err(e) => sender
-                            .send(Err(Box::from(e.to_string())))
-                            .expect("receiver not to be dropped"),
-                    }
+        }
+    }
+
+    /// Announce objects to the common topic, split into messages of limited size
+    pub(super) fn announce_objects(&mut self, objects: Vec<(String, i64, u8)>) {
+        for chunk in objects.chunks(MAX_INV_HASHES) {
+            let msg = NetworkMessage {
+                command: MessageCommand::Inv,
+                payload: MessagePayload::inv(chunk.to_vec(), None),
+            };
+            match self.publish_pubsub(msg) {
+                Err(PublishError::InsufficientPeers) => {
+                    log::warn!("No peers to publish inventory to, it will be sent later")
+                }
+                Err(e) => {
+                    log::error!("Pubsub failed to publish the message: {}", e);
                 }
-                Folder::Sent => match self.messages_repo.get_messages_by_sender(address).await {
-                    Ok(v) => sender.send(Ok(v)).expect("receiver not to be dropped"),
-                    Err(e) => sender
-                        .send(Err(Box::from(e.to_string())))
-                        .expect("receiver not to be dropped"),
+                _ => {}
+            }
+        }
+    }
+
+    /// Request the rest of the peer's inventory if it didn't fit into the response
+    fn request_next_inventory_page(&mut self, peer: PeerId, msg: &NetworkMessage) {
+        if let MessagePayload::Inv {
+            next: Some(after), ..
+        } = &msg.payload
+        {
+            debug!("Requesting next page of inventory from {}", peer);
+            self.send_request(
+                peer,
+                NetworkMessage {
+                    command: MessageCommand::ReqInv,
+                    payload: MessagePayload::ReqInv {
+                        after: after.clone(),
+                    },
                 },
-            },
-            WorkerCommand::SendMessage {
-                mut msg,
-                from,
-                sender,
-            } => {
-                let identity = self
-                    .address_repo
-                    .get_by_ripe_or_tag(from)
-                    .await
-                    .unwrap()
-                    .unwrap();
-                let recipient: Option<Address> = self
-                    .address_repo
-                    .get_by_ripe_or_tag(msg.recipient.clone())
-                    .await
-                    .unwrap();
-                match recipient {
-                    Some(v) => {
-                        msg.status = MessageStatus::WaitingForPOW.to_string();
-                        let object = create_object_from_msg(&identity, &v, msg.clone());
-                        msg.hash = bs58::encode(&object.hash).into_string();
-                        self.messages_repo.save_model(msg).await.unwrap();
-                        self.enqueue_pow(object).await;
-                    }
-                    None => {
-                        let recipient_address = Address::with_string_repr(msg.recipient.clone());
-                        self.address_repo
-                            .store(recipient_address.clone())
-                            .await
-                            .unwrap();
-                        msg.status = MessageStatus::WaitingForPubkey.to_string();
-                        // we generate random hash value, cuz we don't really know real hash value of the message at the moment, and it's not that important
-                        msg.hash = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
-                        self.messages_repo.save_model(msg.clone()).await.unwrap();
-                        self.tracked_pubkeys
-                            .insert(bs58::encode(recipient_address.tag).into_string(), true);
-                        // send getpubkey request
-                        let obj = Object::with_signing(
-                            &identity,
-                            ObjectKind::Getpubkey {
-                                tag: Address::new(bs58::decode(msg.recipient).into_vec().unwrap())
-                                    .tag,
-                            },
-                            Utc::now() + chrono::Duration::days(7),
-                        );
-                        self.enqueue_pow(obj).await;
+            );
+        }
+    }
+
+    /// Stop the PoW worker, persist everything which is still in flight and close
+    /// listeners and the database.
+    async fn shutdown(&mut self) {
+        info!("Shutting down the node...");
+        let mut shutdown_waiters = Vec::new();
+
+        if let Some(mut pow_worker_sink) = self.pow_worker_command_sink.take() {
+            let (sender, mut receiver) = oneshot::channel();
+            if pow_worker_sink
+                .send(ProofOfWorkWorkerCommand::Shutdown { sender })
+                .await
+                .is_ok()
+            {
+                // keep handling commands, since PoW results might still arrive
+                loop {
+                    select! {
+                        _ = receiver => break,
+                        command = self.command_receiver.select_next_some() => match command {
+                            WorkerCommand::Shutdown { sender } => shutdown_waiters.push(sender),
+                            // the reply is dropped, so the client gets ClientError::Stopped
+                            c => debug!("Rejecting {:?}, the node is shutting down", c),
+                        },
+                        command = self.internal_command_receiver.select_next_some() => self.handle_command(command).await,
                     }
                 }
-                sender.send(Ok(())).unwrap();
             }
+        }
+
+        self.announce_batch().await;
+        self.flush_pending_broadcasts();
+        if !self.pending_broadcasts.is_empty() {
+            if let Some(data_dir) = &self.data_dir {
+                debug!("Inventory wasn't announced, it will be announced on the next start");
+                if let Err(e) = fs::write(data_dir.join(PENDING_BROADCAST_FILE_NAME), []) {
+                    log::error!("Failed to save pending broadcast marker: {}", e);
+                }
+            }
+        }
+
+        for id in self.listeners.drain(..) {
+            self.swarm.remove_listener(id);
+        }
+        self.storage.close().await;
+        // the process might exit right after the waiters are notified
+        self.data_dir_lock = None;
+
+        for sender in shutdown_waiters {
+            _ = sender.send(());
+        }
+        info!("Node has been shut down");
+    }
+
+    /// Publish announcements waiting for peers, along with messages waiting for them
+    async fn broadcast_pending(&mut self) {
+        self.flush_pending_broadcasts();
+        log_failure("send waiting messages", self.send_waiting_messages().await);
+        self.update_connectivity();
+    }
+
+    fn flush_pending_broadcasts(&mut self) {
+        let version = self.broadcast_version();
+        for msg in std::mem::take(&mut self.pending_broadcasts) {
+            let topic = self.topic_of(&msg);
+            match self
+                .swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(topic, msg.encode(version))
+            {
+                Ok(_) => {
+                    debug!("Published queued pubsub message");
+                    self.traffic_stats.gossip_messages_published += 1;
+                }
+                Err(PublishError::InsufficientPeers) => {
+                    // no peer is subscribed to the topic yet, try again later
+                    self.pending_broadcasts.push_back(msg);
+                }
+                Err(e) => log::error!("Pubsub failed to publish queued message: {}", e),
+            }
+        }
+    }
+
+    #[cfg(feature = "legacy-bridge")]
+    pub(super) fn publish_legacy_object(&mut self, data: Vec<u8>) {
+        let topic = match &self.legacy_bridge {
+            Some(bridge) => bridge.topic.clone(),
+            None => return,
         };
+        self.traffic_stats.bytes_sent += data.len() as u64;
+        match self.swarm.behaviour_mut().gossipsub.publish(topic, data) {
+            Ok(_) => self.traffic_stats.gossip_messages_published += 1,
+            Err(e) => debug!("Legacy object wasn't published to bridge nodes: {}", e),
+        }
     }
 
-    fn publish_pubsub(&mut self, msg: NetworkMessage) -> Result<MessageId, PublishError> {
-        let serialized_msg = serde_cbor::to_vec(&msg).unwrap();
-        self.swarm
+    /// Check incoming message against per-peer rate limit and global download cap.
+    /// Returns `false` if the message has to be dropped.
+    fn accept_incoming(&mut self, peer: PeerId, size: usize) -> bool {
+        self.traffic_stats.bytes_received += size as u64;
+        if let Some(rate) = self.peer_message_rate {
+            let limiter = self
+                .peer_limiters
+                .entry(peer)
+                .or_insert_with(|| TokenBucket::new(rate as u64, rate as u64));
+            if !limiter.try_consume(1) {
+                debug!("Peer {} exceeds message rate limit, dropping message", peer);
+                self.traffic_stats.dropped_messages += 1;
+                return false;
+            }
+        }
+        if let Some(limiter) = self.download_limiter.as_mut() {
+            if !limiter.try_consume(size as u64) {
+                debug!("Download limit is exceeded, dropping message from {}", peer);
+                self.traffic_stats.dropped_messages += 1;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Check response to other peer's request against global upload cap
+    fn accept_outgoing_response(&mut self, size: usize) -> bool {
+        if let Some(limiter) = self.upload_limiter.as_mut() {
+            if !limiter.try_consume(size as u64) {
+                return false;
+            }
+        }
+        self.traffic_stats.bytes_sent += size as u64;
+        true
+    }
+
+    /// Send request to the peer, keeping track of requested objects
+    fn send_request(&mut self, peer: PeerId, msg: NetworkMessage) {
+        let data = msg.encode(self.peer_version(&peer));
+        self.traffic_stats.bytes_sent += data.len() as u64;
+        if let MessagePayload::GetData { inventory } = &msg.payload {
+            for hash in inventory {
+                self.requested_objects
+                    .entry(hash.clone())
+                    .or_insert_with(|| ObjectRequest {
+                        peer,
+                        requested_at: Instant::now(),
+                        attempts: 0,
+                        tried_peers: HashSet::from([peer]),
+                    });
+            }
+        }
+        let request_id = self
+            .swarm
             .behaviour_mut()
-            .gossipsub
-            .publish(self.common_topic.clone(), serialized_msg)
+            .rpc
+            .send_request(&peer, BitmessageRequest(data));
+        self.pending_requests
+            .entry(peer)
+            .or_default()
+            .insert(request_id);
     }
 
-    pub async fn run(mut self) {
-        let (pow_worker, pow_worker_sink) = ProofOfWorkWorker::new(
-            self.inventory_repo.clone(),
-            self.messages_repo.clone(),
-            self.address_repo.clone(),
-            self.command_sender.clone(),
+    fn finish_request(&mut self, peer: PeerId, request_id: RequestId) {
+        if let Some(requests) = self.pending_requests.get_mut(&peer) {
+            requests.remove(&request_id);
+            if requests.is_empty() {
+                self.pending_requests.remove(&peer);
+            }
+        }
+    }
+
+    fn forget_received_objects(&mut self, msg: &NetworkMessage) {
+        if let MessagePayload::Objects { objects } = &msg.payload {
+            for obj in objects {
+                self.requested_objects
+                    .remove(&bs58::encode(&obj.hash).into_string());
+            }
+        }
+    }
+
+    /// Request objects which weren't received in time (e.g. the peer has disconnected
+    /// mid-transfer) again, preferring connected peers they weren't requested from yet
+    fn retry_object_requests(&mut self) {
+        let connected_peers: Vec<PeerId> = self.swarm.connected_peers().cloned().collect();
+        let mut retries: HashMap<PeerId, InventoryVector> = HashMap::new();
+        self.requested_objects.retain(|hash, request| {
+            if request.requested_at.elapsed() < OBJECT_REQUEST_TIMEOUT
+                && connected_peers.contains(&request.peer)
+            {
+                return true;
+            }
+            if request.attempts >= MAX_OBJECT_REQUEST_ATTEMPTS {
+                log::warn!(
+                    "Object {} wasn't received after {} attempts, giving up",
+                    hash,
+                    request.attempts
+                );
+                return false;
+            }
+            let peer = match connected_peers
+                .iter()
+                .find(|p| !request.tried_peers.contains(p))
+                .or_else(|| connected_peers.first())
+            {
+                Some(p) => *p,
+                // keep the request until some peer is connected
+                None => return true,
+            };
+            request.peer = peer;
+            request.requested_at = Instant::now();
+            request.attempts += 1;
+            request.tried_peers.insert(peer);
+            retries.entry(peer).or_default().push(hash.clone());
+            true
+        });
+        for (peer, inventory) in retries {
+            debug!("Re-requesting {} objects from {}", inventory.len(), peer);
+            self.send_request(
+                peer,
+                NetworkMessage {
+                    command: MessageCommand::GetData,
+                    payload: MessagePayload::GetData { inventory },
+                },
+            );
+        }
+    }
+
+    async fn maintain(&mut self) {
+        self.last_maintenance = Instant::now();
+        self.rebootstrap_if_empty();
+        self.handler.decay_misbehavior_scores();
+        self.dial_known_peers().await;
+        log_failure("fail stale messages", self.fail_stale_messages().await);
+        log_failure(
+            "resend messages",
+            self.resend_unacknowledged_messages().await,
         );
-        self.pow_worker_command_sink = Some(pow_worker_sink.clone());
-        self.handler.set_pow_worker_sink(pow_worker_sink);
+        log_failure("expire public keys", self.expire_public_keys().await);
+        log_failure("request pubkeys", self.retry_pubkey_requests().await);
+        log_failure("purge trash", self.purge_trash().await);
+        log_failure("maintain inventory", self.maintain_inventory().await);
+    }
+
+    /// Pause PoW and shed connections in low-power mode. Once it's switched back,
+    /// skipped maintenance and inventory announcement are done right away.
+    pub(super) async fn set_power_mode(&mut self, mode: PowerMode) {
+        if mode == self.power_mode {
+            return;
+        }
+        info!("Switching to {:?} power mode", mode);
+        self.power_mode = mode;
+        let paused = mode == PowerMode::LowPower;
+        self.send_pow_worker_command(ProofOfWorkWorkerCommand::SetPaused { paused })
+            .await;
+        match mode {
+            PowerMode::LowPower => self.disconnect_extra_peers(),
+            PowerMode::Normal => {
+                self.maintain().await;
+                self.broadcast_inventory().await;
+            }
+        }
+    }
+
+    /// Disconnect the least recently active peers beyond [`LOW_POWER_PEERS`],
+    /// protected peers and relays are kept
+    fn disconnect_extra_peers(&mut self) {
+        let mut peers: Vec<(PeerId, Instant)> = self
+            .peer_activity
+            .iter()
+            .filter(|(peer_id, _)| {
+                !self.protected_peers.contains(peer_id)
+                    && !self.relay_listeners.contains_key(peer_id)
+            })
+            .map(|(peer_id, last_activity)| (*peer_id, *last_activity))
+            .collect();
+        peers.sort_by_key(|(_, last_activity)| std::cmp::Reverse(*last_activity));
+        for (peer_id, _) in peers.into_iter().skip(LOW_POWER_PEERS) {
+            debug!("Disconnecting peer {} in low-power mode", peer_id);
+            _ = self.swarm.disconnect_peer_id(peer_id);
+        }
+    }
+
+    /// Idle peers are released first, so that their connections are closed once no
+    /// substream is open. Peers still connected on the next check (e.g. kept by the
+    /// gossipsub mesh) are disconnected, unless a request to them is in flight.
+    fn disconnect_idle_peers(&mut self) {
+        let timeout = match self.peer_idle_timeout {
+            Some(t) => t,
+            None => return,
+        };
+        let (idle_peers, active_peers): (Vec<_>, Vec<_>) = self
+            .peer_activity
+            .iter()
+            .filter(|(peer_id, _)| {
+                // relays keep the node reachable even if nothing is exchanged with them
+                !self.protected_peers.contains(peer_id)
+                    && !self.relay_listeners.contains_key(peer_id)
+            })
+            .map(|(peer_id, last_activity)| (*peer_id, last_activity.elapsed() > timeout))
+            .partition(|(_, idle)| *idle);
+        for (peer_id, _) in active_peers {
+            self.swarm.behaviour_mut().keep_alive.retain(peer_id);
+        }
+        for (peer_id, _) in idle_peers {
+            if self.swarm.behaviour_mut().keep_alive.release(peer_id) {
+                debug!("Releasing idle peer {}", peer_id);
+            } else if !self.pending_requests.contains_key(&peer_id) {
+                debug!("Disconnecting idle peer {}", peer_id);
+                _ = self.swarm.disconnect_peer_id(peer_id);
+            }
+        }
+    }
+
+    pub async fn run(mut self) {
+        let mut pow_worker = self.pow_worker.take().expect("node is started only once");
+        pow_worker.resume().await;
         task::spawn(pow_worker.run());
+        #[cfg(feature = "legacy-bridge")]
+        if let Some(bridge) = self.legacy_bridge.as_mut().and_then(|b| b.bridge.take()) {
+            task::spawn(bridge.run());
+        }
 
-        // populate tracked_pubkeys map
-        let msgs_waiting_for_pubkey = self
-            .messages_repo
-            .get_messages_by_status(MessageStatus::WaitingForPubkey)
-            .await
-            .unwrap();
-        for m in msgs_waiting_for_pubkey {
-            if self
-                .address_repo
-                .get_by_ripe_or_tag(m.recipient.clone())
-                .await
-                .unwrap()
-                .unwrap()
-                .public_encryption_key
-                .is_some()
-            {
-                self.messages_repo
-                    .update_message_status(m.hash, MessageStatus::WaitingForPOW)
-                    .await
-                    .expect("db won't to fail");
-            } else {
-                let tag = bs58::encode(
-                    self.address_repo
-                        .get_by_ripe_or_tag(m.recipient)
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .tag,
-                )
-                .into_string();
-                self.tracked_pubkeys.insert(tag, true);
+        log_failure("fail stale messages", self.fail_stale_messages().await);
+        log_failure("purge trash", self.purge_trash().await);
+        if let Err(e) = retry_db!(self.messages_repo.index_messages()) {
+            log::error!("Failed to index messages for search: {}", e);
+        }
+
+        match retry_db!(self.peer_repo.get_banned()) {
+            Ok(banned) => {
+                for banned in banned {
+                    if let Err(e) = self.apply_ban(&banned.target) {
+                        log::warn!("Ignoring ban: {}", e);
+                    }
+                }
             }
+            Err(e) => log::error!("Failed to load banned peers: {}", e),
         }
 
+        log_failure("expire public keys", self.expire_public_keys().await);
+        log_failure(
+            "resume messages waiting for pubkeys",
+            self.track_waiting_messages().await,
+        );
+
         // cleanup expired objects from the storage
-        self.inventory_repo.cleanup().await.unwrap();
+        log_failure("maintain inventory", self.maintain_inventory().await);
+        let since = Utc::now() - chrono::Duration::days(PEER_RETENTION_DAYS);
+        if let Err(e) = retry_db!(self.peer_repo.cleanup(since)) {
+            log::error!("Failed to remove old peers: {}", e);
+        }
+        self.dial_known_peers().await;
+
+        // inventory wasn't announced before the last shutdown, do it once peers appear
+        let pending_broadcast_path = self
+            .data_dir
+            .as_ref()
+            .map(|d| d.join(PENDING_BROADCAST_FILE_NAME))
+            .filter(|p| p.exists());
+        if let Some(pending_broadcast_path) = pending_broadcast_path {
+            self.broadcast_inventory().await;
+            if let Err(e) = fs::remove_file(&pending_broadcast_path) {
+                log::warn!("Failed to remove pending broadcast marker: {}", e);
+            }
+        }
+
+        let mut maintenance_timer = stream::interval(MAINTENANCE_INTERVAL).fuse();
+        let mut object_request_timer = stream::interval(OBJECT_REQUEST_CHECK_INTERVAL).fuse();
+        let mut idle_peers_timer = stream::interval(IDLE_PEERS_CHECK_INTERVAL).fuse();
+        let mut scheduled_messages_timer =
+            stream::interval(SCHEDULED_MESSAGES_CHECK_INTERVAL).fuse();
+        let mut peer_exchange_timer = stream::interval(PEER_EXCHANGE_INTERVAL).fuse();
+        let mut announcement_batch_timer = stream::interval(ANNOUNCEMENT_BATCH_WINDOW).fuse();
+        let mut full_inventory_timer = stream::interval(FULL_INVENTORY_ANNOUNCE_INTERVAL).fuse();
 
         debug!("node worker event loop started");
         loop {
             select! {
                 event = self.swarm.select_next_some() => self.handle_event(event).await,
                 command = self.command_receiver.next() => match command {
+                    Some(WorkerCommand::Shutdown { sender }) => {
+                        self.shutdown().await;
+                        _ = sender.send(());
+                        return;
+                    }
                     Some(c) => self.handle_command(c).await,
                     // Command channel closed, thus shutting down the network event loop.
                     None => {
-                        log::debug!("Shutting down network event loop...");
+                        self.shutdown().await;
                         return;
                     },
                 },
-                pubkey_notification = self.pubkey_notifier.next() => self.handle_pubkey_notification(pubkey_notification.unwrap()).await,
+                command = self.internal_command_receiver.select_next_some() => self.handle_command(command).await,
+                pubkey_notification = self.pubkey_notifier.next() => log_failure("send messages waiting for pubkey", self.handle_pubkey_notification(pubkey_notification.unwrap()).await),
+                _ = maintenance_timer.next() => {
+                    if self.power_mode == PowerMode::Normal
+                        || self.last_maintenance.elapsed() >= LOW_POWER_MAINTENANCE_INTERVAL
+                    {
+                        self.maintain().await;
+                    }
+                },
+                _ = object_request_timer.next() => self.retry_object_requests(),
+                _ = idle_peers_timer.next() => self.disconnect_idle_peers(),
+                _ = scheduled_messages_timer.next() => log_failure("send scheduled messages", self.send_scheduled_messages().await),
+                _ = peer_exchange_timer.next() => {
+                    if self.power_mode == PowerMode::Normal {
+                        self.exchange_peers().await;
+                    }
+                },
+                _ = announcement_batch_timer.next() => self.announce_batch().await,
+                _ = full_inventory_timer.next() => {
+                    if self.power_mode == PowerMode::Normal {
+                        self.broadcast_inventory().await;
+                    }
+                },
             }
         }
     }
 
-    async fn handle_pubkey_notification(&mut self, tag: String) {
-        if let Some(_) = self.tracked_pubkeys.get(&tag) {
-            let addr = self
-                .address_repo
-                .get_by_ripe_or_tag(tag.clone())
-                .await
-                .unwrap()
-                .expect("Address entity exists in db");
-            let msgs = self
-                .messages_repo
-                .get_messages_by_recipient(addr.string_repr.clone())
-                .await
-                .unwrap();
-            msgs.into_iter()
-                .filter(|x| x.status == MessageStatus::WaitingForPubkey.to_string())
-                .for_each(|x| {
-                    let identity =
-                        task::block_on(self.address_repo.get_by_ripe_or_tag(x.sender.clone()))
-                            .unwrap()
-                            .expect("identity exists in address repo");
-                    let object = create_object_from_msg(&identity, &addr, x.clone());
-                    let old_hash = x.hash.clone();
-                    let new_hash = bs58::encode(&object.hash).into_string();
-                    task::block_on(self.messages_repo.update_hash(old_hash, new_hash.clone()))
-                        .unwrap();
-                    task::block_on(
-                        self.messages_repo
-                            .update_message_status(new_hash, MessageStatus::WaitingForPOW),
-                    )
-                    .unwrap();
-                    task::block_on(self.enqueue_pow(object));
-                });
-            self.tracked_pubkeys.remove(&tag);
+    /// Listen via relays once the node turns out to be behind NAT, and stop once
+    /// it's reachable directly
+    fn handle_nat_status(&mut self, status: autonat::NatStatus) {
+        match status {
+            autonat::NatStatus::Private => {
+                info!("Node is behind NAT, listening via relays");
+                self.behind_nat = true;
+                self.listen_via_relays();
+            }
+            autonat::NatStatus::Public(address) => {
+                info!("Node is reachable at {}", address);
+                self.behind_nat = false;
+                for (_, listener) in self.relay_listeners.drain() {
+                    self.swarm.remove_listener(listener);
+                }
+            }
+            autonat::NatStatus::Unknown => {}
+        }
+    }
+
+    fn listen_via_relays(&mut self) {
+        if !self.behind_nat {
+            return;
+        }
+        let candidates: Vec<(PeerId, Multiaddr)> = self
+            .relay_candidates
+            .iter()
+            .filter(|(peer, _)| !self.relay_listeners.contains_key(peer))
+            .take(MAX_RELAYS.saturating_sub(self.relay_listeners.len()))
+            .map(|(peer, address)| (*peer, address.clone()))
+            .collect();
+        for (peer, address) in candidates {
+            match self.swarm.listen_on(address.with(Protocol::P2pCircuit)) {
+                Ok(listener) => _ = self.relay_listeners.insert(peer, listener),
+                Err(e) => debug!("Failed to listen via relay {}: {}", peer, e),
+            }
         }
     }
 
     /// When we receive IdentityInfo, if the peer supports our Kademlia protocol, we add
     /// their listen addresses to the DHT, so they will be propagated to other peers.
-    fn handle_identify_event(&mut self, identify_event: identify::Event) {
+    async fn handle_identify_event(&mut self, identify_event: identify::Event) {
         debug!("Received identify::Event: {:?}", identify_event);
 
         if let identify::Event::Received {
@@ -689,30 +2451,82 @@ impl NodeWorker {
                 identify::Info {
                     listen_addrs,
                     protocols,
+                    agent_version,
+                    protocol_version,
                     ..
                 },
         } = identify_event
         {
+            let version = protocol_version
+                .strip_prefix(PROTOCOL_VERSION_PREFIX)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(LEGACY_PROTOCOL_VERSION);
+            self.peer_versions.insert(peer_id, version);
+            // peers which don't advertise the role are full nodes
+            let role = agent_version
+                .split_whitespace()
+                .find_map(|s| s.strip_prefix(ROLE_PREFIX))
+                .and_then(|r| r.parse().ok())
+                .unwrap_or_default();
+            self.peer_roles.insert(peer_id, role);
+            let object_types = agent_version
+                .split_whitespace()
+                .find_map(|s| s.strip_prefix(OBJECT_TYPES_PREFIX))
+                .map(|types| {
+                    types
+                        .split(',')
+                        .filter_map(|t| t.parse::<ObjectType>().ok())
+                        .map(|t| t as u8)
+                        .collect()
+                });
+            self.handler.set_peer_object_types(peer_id, object_types);
+            if protocols
+                .iter()
+                .any(|p| p.as_bytes() == relay::HOP_PROTOCOL_NAME)
+            {
+                if let Some(address) = self.dialed_addresses.get(&peer_id) {
+                    let mut address = address.clone();
+                    if !matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+                        address.push(Protocol::P2p(peer_id.into()));
+                    }
+                    self.relay_candidates.insert(peer_id, address);
+                    self.listen_via_relays();
+                }
+            }
             if protocols
                 .iter()
                 .any(|p| p.as_bytes() == KADEMLIA_PROTO_NAME)
             {
-                for addr in listen_addrs {
-                    debug!("Adding received IdentifyInfo matching protocol '{}' to the DHT. Peer: {}, addr: {}", String::from_utf8_lossy(KADEMLIA_PROTO_NAME), peer_id, addr);
-                    self.swarm
-                        .behaviour_mut()
-                        .kademlia
-                        .add_address(&peer_id, addr);
+                // client nodes don't serve objects of others, so they're not worth
+                // reconnecting to or finding via the DHT
+                if role != NodeRole::Client {
+                    for addr in listen_addrs {
+                        debug!("Adding received IdentifyInfo matching protocol '{}' to the DHT. Peer: {}, addr: {}", String::from_utf8_lossy(KADEMLIA_PROTO_NAME), peer_id, addr);
+                        // remember the peer to reconnect to it after restart
+                        if let Err(e) = retry_db!(self
+                            .peer_repo
+                            .store_seen(peer_id.to_string(), addr.to_string()))
+                        {
+                            log::error!("Failed to store peer {}: {}", peer_id, e);
+                        }
+                        self.swarm
+                            .behaviour_mut()
+                            .kademlia
+                            .add_address(&peer_id, addr);
+                    }
                 }
 
                 self.swarm
                     .behaviour_mut()
                     .gossipsub
                     .add_explicit_peer(&peer_id);
+                self.broadcast_pending().await;
             }
         }
     }
 
+    /// Serialize and encrypt payload to the public key derived from `secret_key`.
+    /// See [`serialize_and_encrypt_payload_pub`] for integrity guarantees.
     pub fn serialize_and_encrypt_payload<T>(
         object: T,
         secret_key: &libsecp256k1::SecretKey,
@@ -729,26 +2543,112 @@ impl NodeWorker {
     }
 
     fn on_new_peer(&mut self, peer_id: PeerId) {
-        self.swarm.behaviour_mut().rpc.send_request(
-            &peer_id,
-            BitmessageRequest(NetworkMessage {
-                command: MessageCommand::ReqInv,
-                payload: MessagePayload::None,
-            }),
-        );
+        // client nodes only have their own objects, which they announce anyway
+        if self.peer_roles.get(&peer_id) == Some(&NodeRole::Client) {
+            return;
+        }
+        // mDNS may rediscover the same peers in bursts, don't pull full inventory every time
+        if let Some(msg) = self.handler.inventory_request(peer_id) {
+            self.send_request(peer_id, msg);
+        }
+    }
+
+    pub(super) async fn enqueue_pow(&mut self, object: Object) {
+        self.send_pow_worker_command(ProofOfWorkWorkerCommand::EnqueuePoW { object })
+            .await;
+    }
+
+    /// The command is dropped once the PoW worker is stopped on shutdown, objects of
+    /// messages waiting for PoW are re-created on the next start
+    pub(super) async fn send_pow_worker_command(&mut self, command: ProofOfWorkWorkerCommand) {
+        match self.pow_worker_command_sink.as_mut() {
+            Some(sink) => sink.send(command).await.expect("command successfully sent"),
+            None => debug!("PoW worker is stopped, dropping the command"),
+        }
     }
+}
 
-    async fn enqueue_pow(&mut self, object: Object) {
-        self.pow_worker_command_sink
-            .as_mut()
-            .unwrap()
-            .send(ProofOfWorkWorkerCommand::EnqueuePoW { object })
-            .await
-            .expect("command successfully sent");
+/// TTL of the message object, doubled each time the message is sent again,
+/// but not beyond the maximum TTL accepted by peers
+pub(crate) fn message_ttl(ttl: chrono::Duration, resend_count: i32) -> chrono::Duration {
+    let max_ttl = chrono::Duration::days(validation::MAX_OBJECT_TTL_DAYS);
+    let factor = 2i64.saturating_pow(resend_count.max(0) as u32);
+    let seconds = ttl.num_seconds().saturating_mul(factor);
+    chrono::Duration::seconds(seconds.min(max_ttl.num_seconds()))
+}
+
+/// Keep items whose flags are set. Items without a flag (e.g. when the peer didn't
+/// provide them) are dropped.
+fn retain_flagged<T>(items: Vec<T>, flags: &[bool]) -> Vec<T> {
+    items
+        .into_iter()
+        .zip(flags)
+        .filter(|(_, keep)| **keep)
+        .map(|(item, _)| item)
+        .collect()
+}
+
+/// Topic objects of given type are announced in when announcements are sharded
+fn shard_topic(pubsub_topic: &str, object_type: ObjectType) -> Sha256Topic {
+    Sha256Topic::new(format!("{}-{}", pubsub_topic, object_type))
+}
+
+/// Agent version advertised via identify, tells peers the role of the node and
+/// which objects it syncs
+fn agent_version(config: &Config) -> String {
+    let mut version = format!(
+        "nantoka/{} {}{}",
+        env!("CARGO_PKG_VERSION"),
+        ROLE_PREFIX,
+        config.role
+    );
+    if let Some(types) = &config.object_types {
+        let types: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+        version.push_str(&format!(" {}{}", OBJECT_TYPES_PREFIX, types.join(",")));
     }
+    version
+}
+
+/// Secure the raw transport with noise and multiplex connections with yamux
+fn upgrade_transport<T>(
+    transport: T,
+    local_key: &identity::Keypair,
+) -> Boxed<(PeerId, StreamMuxerBox)>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Dial: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+{
+    transport
+        .upgrade(Version::V1Lazy)
+        .authenticate(noise::Config::new(local_key).unwrap())
+        .multiplex(yamux::Config::default())
+        .boxed()
+}
+
+/// Combine the transport with QUIC, which has its own encryption and multiplexing
+fn with_quic(
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    local_key: &identity::Keypair,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    quic::async_std::Transport::new(quic::Config::new(local_key))
+        .or_transport(transport)
+        .map(|output, _| match output {
+            Either::Left((peer_id, connection)) => (peer_id, StreamMuxerBox::new(connection)),
+            Either::Right(output) => output,
+        })
+        .boxed()
+}
+
+/// Check whether the address starts with all protocols of the prefix
+fn is_address_prefix(prefix: &Multiaddr, address: &Multiaddr) -> bool {
+    prefix.iter().count() <= address.iter().count()
+        && prefix.iter().zip(address.iter()).all(|(p, a)| p == a)
 }
 
-fn extract_peer_id_from_multiaddr(
+pub(super) fn extract_peer_id_from_multiaddr(
     address_with_peer_id: &Multiaddr,
 ) -> Result<PeerId, Box<dyn Error>> {
     match address_with_peer_id.iter().last() {
@@ -763,10 +2663,74 @@ fn extract_peer_id_from_multiaddr(
     }
 }
 
+/// Store identity with known private keys, replacing the contact with the same address.
+/// Returns `false` if such identity already exists.
+pub(crate) async fn store_identity(
+    address_repo: &mut AddressRepositorySync,
+    address: Address,
+) -> Result<bool, Box<dyn Error>> {
+    let existing = address_repo
+        .get_by_ripe_or_tag(address.string_repr.clone())
+        .await?;
+    match existing {
+        Some(existing) if existing.private_signing_key.is_some() => return Ok(false),
+        // the address is known as a contact, so turn it into an identity
+        Some(_) => {
+            address_repo
+                .delete_address(address.string_repr.clone())
+                .await?
+        }
+        None => {}
+    }
+    address_repo.store(address).await?;
+    Ok(true)
+}
+
+/// Log failure of a background task, it's done again on its next run
+fn log_failure(task: &str, res: Result<(), Box<dyn Error>>) {
+    if let Err(e) = res {
+        log::error!("Failed to {}: {}", task, e);
+    }
+}
+
+/// Build broadcast object of the identity, encrypted with the key derived from its address
+pub fn create_broadcast_object(
+    identity: &Address,
+    msg: models::Message,
+    ttl: chrono::Duration,
+) -> Object {
+    let unenc_msg = UnencryptedMsg {
+        behavior_bitfield: 0,
+        sender_ripe: identity.string_repr.clone(),
+        // subscribers receive it under the address of the sender
+        destination_ripe: String::new(),
+        encoding: MsgEncoding::Simple,
+        message: msg.data,
+        public_encryption_key: identity.public_encryption_key.unwrap().serialize().to_vec(),
+        public_signing_key: identity.public_signing_key.unwrap().serialize().to_vec(),
+        ack_data: Vec::new(),
+    };
+    Object::with_signing(
+        identity,
+        ObjectKind::Broadcast {
+            tag: identity.tag.clone(),
+            encrypted: NodeWorker::serialize_and_encrypt_payload(
+                unenc_msg,
+                &identity.public_decryption_key,
+            ),
+        },
+        Utc::now() + ttl,
+    )
+}
+
+/// Build msg object encrypted to the recipient, with a copy of the payload
+/// for each of its other devices
 pub fn create_object_from_msg(
     identity: &Address,
     recipient: &Address,
+    device_keys: &[PublicKey],
     msg: models::Message,
+    ttl: chrono::Duration,
 ) -> Object {
     let unenc_msg = UnencryptedMsg {
         behavior_bitfield: 0,
@@ -774,22 +2738,41 @@ pub fn create_object_from_msg(
         destination_ripe: msg.recipient.clone(),
         encoding: MsgEncoding::Simple,
         message: msg.data.clone(),
-        public_encryption_key: recipient
-            .public_encryption_key
-            .unwrap()
-            .serialize()
-            .to_vec(),
+        // sender keys let the recipient check that the message comes from the sender address
+        public_encryption_key: identity.public_encryption_key.unwrap().serialize().to_vec(),
         public_signing_key: identity.public_signing_key.unwrap().serialize().to_vec(),
+        ack_data: msg.ack_data.unwrap_or_default(),
     };
+    let copies = device_keys
+        .iter()
+        .map(|k| serialize_and_encrypt_payload_pub(unenc_msg.clone(), k))
+        .collect();
     let encrypted =
         serialize_and_encrypt_payload_pub(unenc_msg, &recipient.public_encryption_key.unwrap());
-    Object::with_signing(
-        &identity,
-        ObjectKind::Msg { encrypted },
-        Utc::now() + chrono::Duration::days(7), // FIXME
-    )
+    let mut object = Object::with_signing(
+        identity,
+        ObjectKind::Msg { encrypted, copies },
+        Utc::now() + ttl,
+    );
+    // recipient might require more work than network minimum. The sender can't tell
+    // whether it's among contacts of the recipient, so it does the work required
+    // from strangers.
+    object.nonce_trials_per_byte = object
+        .nonce_trials_per_byte
+        .max(recipient.nonce_trials_per_byte)
+        .max(recipient.stranger_nonce_trials_per_byte);
+    object.extra_bytes = object
+        .extra_bytes
+        .max(recipient.extra_bytes)
+        .max(recipient.stranger_extra_bytes);
+    object
 }
 
+/// Serialize payload with CBOR and encrypt it with ECIES.
+///
+/// ECIES uses AES-256-GCM as its symmetric cipher, so the ciphertext is authenticated:
+/// any truncation or modification of it makes [`decrypt_and_deserialize_payload`] fail
+/// with [`PayloadError::Decryption`] instead of yielding a tampered payload.
 pub fn serialize_and_encrypt_payload_pub<T>(
     object: T,
     public_key: &libsecp256k1::PublicKey,
@@ -804,3 +2787,19 @@ where
     .unwrap();
     encrypted
 }
+
+/// Decrypt ECIES-encrypted payload and deserialize it from CBOR.
+///
+/// Decryption fails if the ciphertext has been tampered with, since the AEAD tag
+/// won't match. Successfully decrypted data is still checked to be a well-formed payload.
+pub fn decrypt_and_deserialize_payload<T>(
+    encrypted: &[u8],
+    secret_key: &libsecp256k1::SecretKey,
+) -> Result<T, PayloadError>
+where
+    T: DeserializeOwned,
+{
+    let decrypted =
+        ecies::decrypt(&secret_key.serialize(), encrypted).map_err(|_| PayloadError::Decryption)?;
+    Ok(serde_cbor::from_slice(&decrypted)?)
+}
@@ -0,0 +1,61 @@
+//! `bitmessage:` URIs for sharing addresses, e.g. `bitmessage:BM-...?label=Alice`.
+//! Other query params of the classic clients (e.g. `subject`) are ignored.
+
+use std::fmt;
+
+use super::address::{Address, AddressError};
+
+const SCHEME: &str = "bitmessage:";
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum UriError {
+    #[error("not a bitmessage URI")]
+    InvalidScheme,
+    #[error(transparent)]
+    InvalidAddress(#[from] AddressError),
+}
+
+/// Shared address along with a suggested label
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressUri {
+    pub address: String,
+    pub label: Option<String>,
+}
+
+impl AddressUri {
+    pub fn new(address: String, label: Option<String>) -> Self {
+        Self {
+            address,
+            label: label.filter(|l| !l.is_empty()),
+        }
+    }
+
+    /// Parse the URI, verifying the address checksum
+    pub fn parse(uri: &str) -> Result<Self, UriError> {
+        let uri = uri.trim();
+        let rest = match uri.get(..SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => &uri[SCHEME.len()..],
+            _ => return Err(UriError::InvalidScheme),
+        };
+        let rest = rest.strip_prefix("//").unwrap_or(rest);
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let address = Address::with_string_repr(address.trim_end_matches('/'))?;
+        let label = form_urlencoded::parse(query.as_bytes())
+            .find(|(k, _)| k == "label")
+            .map(|(_, v)| v.into_owned());
+        Ok(Self::new(address.string_repr, label))
+    }
+}
+
+impl fmt::Display for AddressUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SCHEME, self.address)?;
+        if let Some(label) = &self.label {
+            let query = form_urlencoded::Serializer::new(String::new())
+                .append_pair("label", label)
+                .finish();
+            write!(f, "?{}", query)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+//! Checks of objects received from peers, done before they're stored into the inventory
+//! and relayed further
+
+use chrono::Utc;
+use sha2::Digest;
+
+use super::{
+    address::Address,
+    messages::{Object, ObjectKind, UnencryptedKeyUpdate, UnencryptedMsg, ACK_DATA_LENGTH},
+};
+
+/// Maximum size of serialized object, the same as PyBitmessage accepts
+pub const MAX_OBJECT_SIZE: usize = 256 * 1024;
+/// Maximum time to live of objects
+pub const MAX_OBJECT_TTL_DAYS: i64 = 28;
+/// Objects may expire a bit later than the maximum TTL allows, since clocks of peers differ
+pub(crate) const EXPIRY_GRACE_PERIOD_SECONDS: i64 = 3 * 60 * 60;
+/// Objects which have just expired are still accepted from peers whose clocks are behind,
+/// the same as PyBitmessage does
+pub(crate) const EXPIRED_GRACE_PERIOD_SECONDS: i64 = 60 * 60;
+/// Length of tags of getpubkey, pubkey and broadcast objects
+const TAG_LENGTH: usize = 32;
+/// Length of serialized compact ECDSA signature
+const SIGNATURE_LENGTH: usize = 64;
+/// Number of bytes of the key hash shown as its fingerprint
+const KEY_FINGERPRINT_LENGTH: usize = 10;
+/// Max number of times keys of an identity can be rotated, so that key updates
+/// stay small
+pub const MAX_KEY_ROTATIONS: usize = 16;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ValidationError {
+    #[error("object is too large ({0} bytes)")]
+    TooLarge(usize),
+    #[error("object has already expired")]
+    Expired,
+    #[error("object expires too far in the future")]
+    ExpiresTooLate,
+    #[error("object hash doesn't match its contents")]
+    HashMismatch,
+    #[error("object signature is malformed")]
+    MalformedSignature,
+    #[error("object signature doesn't match its signing key")]
+    InvalidSignature,
+    #[error("object has invalid tag")]
+    InvalidTag,
+    #[error("object has no payload")]
+    EmptyPayload,
+    #[error("message sender doesn't match its keys")]
+    SenderMismatch,
+    #[error("new keys aren't endorsed by the former keys of the address")]
+    UnendorsedKeys,
+}
+
+/// Check everything that can be checked without decrypting the object
+pub fn validate_object(object: &Object) -> Result<(), ValidationError> {
+    let size = serde_cbor::to_vec(object)
+        .expect("object to be serializable")
+        .len();
+    if size > MAX_OBJECT_SIZE {
+        return Err(ValidationError::TooLarge(size));
+    }
+
+    validate_expiry(object.expires)?;
+
+    // signature isn't hashed, it's made over the hash itself
+    let expected = Object::new(object.expires, Vec::new(), object.kind.clone());
+    if expected.hash != object.hash {
+        return Err(ValidationError::HashMismatch);
+    }
+    if !object.signature.is_empty() && object.signature.len() != SIGNATURE_LENGTH {
+        return Err(ValidationError::MalformedSignature);
+    }
+
+    match &object.kind {
+        // acknowledgements are unsigned msg objects carrying just the ack data
+        ObjectKind::Msg { encrypted, .. } => {
+            if encrypted.len() < ACK_DATA_LENGTH {
+                return Err(ValidationError::EmptyPayload);
+            }
+        }
+        ObjectKind::Getpubkey { tag } => {
+            if tag.len() != TAG_LENGTH {
+                return Err(ValidationError::InvalidTag);
+            }
+        }
+        ObjectKind::Pubkey { tag, encrypted }
+        | ObjectKind::KeyUpdate { tag, encrypted }
+        | ObjectKind::Broadcast { tag, encrypted } => {
+            if tag.len() != TAG_LENGTH {
+                return Err(ValidationError::InvalidTag);
+            }
+            if encrypted.is_empty() {
+                return Err(ValidationError::EmptyPayload);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check expiration time of the object, tolerating clocks of peers which are a few
+/// hours off in either direction
+pub(crate) fn validate_expiry(expires: i64) -> Result<(), ValidationError> {
+    let now = Utc::now().timestamp();
+    if expires <= now - EXPIRED_GRACE_PERIOD_SECONDS {
+        return Err(ValidationError::Expired);
+    }
+    if expires > now + MAX_OBJECT_TTL_DAYS * 24 * 60 * 60 + EXPIRY_GRACE_PERIOD_SECONDS {
+        return Err(ValidationError::ExpiresTooLate);
+    }
+    Ok(())
+}
+
+/// Verify object signature against the public signing key embedded into its payload,
+/// which is known only once the object is decrypted
+pub fn verify_signature(object: &Object, public_signing_key: &[u8]) -> Result<(), ValidationError> {
+    let key = libsecp256k1::PublicKey::parse_slice(public_signing_key, None)
+        .map_err(|_| ValidationError::InvalidSignature)?;
+    let signature = libsecp256k1::Signature::parse_standard_slice(&object.signature)
+        .map_err(|_| ValidationError::MalformedSignature)?;
+    let message = libsecp256k1::Message::parse_slice(&object.hash)
+        .map_err(|_| ValidationError::HashMismatch)?;
+    if !libsecp256k1::verify(&message, &signature, &key) {
+        return Err(ValidationError::InvalidSignature);
+    }
+    Ok(())
+}
+
+/// Short fingerprint of the public key for showing to the user, first 10 bytes
+/// of its SHA-256 hash in groups of 2 bytes
+pub fn key_fingerprint(public_key: &[u8]) -> String {
+    sha2::Sha256::digest(public_key)[..KEY_FINGERPRINT_LENGTH]
+        .chunks(2)
+        .map(|c| c.iter().map(|b| format!("{:02X}", b)).collect())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Check that the message is signed by its sender, i.e. the embedded keys belong
+/// to the sender address. Keys of `rotated` addresses don't hash to them, so their
+/// known keys are compared instead.
+pub fn verify_msg(
+    object: &Object,
+    msg: &UnencryptedMsg,
+    rotated: &[Address],
+) -> Result<(), ValidationError> {
+    verify_signature(object, &msg.public_signing_key)?;
+    let sender =
+        Address::with_string_repr(&msg.sender_ripe).map_err(|_| ValidationError::SenderMismatch)?;
+    if rotated.iter().any(|a| {
+        a.ripe == sender.ripe
+            && a.public_signing_key
+                .is_some_and(|k| k.serialize().as_slice() == msg.public_signing_key.as_slice())
+    }) {
+        return Ok(());
+    }
+    let (psk, pek) = match (
+        ecies::PublicKey::parse_slice(&msg.public_signing_key, None),
+        ecies::PublicKey::parse_slice(&msg.public_encryption_key, None),
+    ) {
+        (Ok(psk), Ok(pek)) => (psk, pek),
+        _ => return Err(ValidationError::SenderMismatch),
+    };
+    if Address::with_public_key(psk, pek).ripe != sender.ripe {
+        return Err(ValidationError::SenderMismatch);
+    }
+    Ok(())
+}
+
+/// Check that the key update is signed by the new keys of the address and they're
+/// endorsed by the chain of its former keys, the first of which hash to the address
+pub fn verify_key_update(
+    object: &Object,
+    update: &UnencryptedKeyUpdate,
+    address: &Address,
+) -> Result<(), ValidationError> {
+    verify_signature(object, &update.public_signing_key)?;
+    if update.chain.is_empty() || update.chain.len() > MAX_KEY_ROTATIONS {
+        return Err(ValidationError::UnendorsedKeys);
+    }
+    let original = &update.chain[0];
+    let original_keys = (
+        ecies::PublicKey::parse_slice(&original.public_signing_key, None),
+        ecies::PublicKey::parse_slice(&original.public_encryption_key, None),
+    );
+    match original_keys {
+        (Ok(psk), Ok(pek)) if Address::with_public_key(psk, pek).ripe == address.ripe => {}
+        _ => return Err(ValidationError::UnendorsedKeys),
+    }
+    let next_keys = update
+        .chain
+        .iter()
+        .skip(1)
+        .map(|k| (&k.public_signing_key, &k.public_encryption_key))
+        .chain([(&update.public_signing_key, &update.public_encryption_key)]);
+    for (endorsement, (psk, pek)) in update.chain.iter().zip(next_keys) {
+        if !endorsement.endorses(psk, pek) {
+            return Err(ValidationError::UnendorsedKeys);
+        }
+    }
+    Ok(())
+}
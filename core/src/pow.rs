@@ -1,21 +1,68 @@
+use std::{sync::Arc, time::Duration};
+
 use chrono::Utc;
+use futures::channel::oneshot;
 use num_bigint::BigUint;
 use once_cell::sync::Lazy;
 use sha2::Digest;
 use sha2::Sha512;
 
-use crate::network::messages::Object;
+use crate::{
+    config::PoWEngineKind,
+    network::{
+        messages::Object,
+        validation::{EXPIRY_GRACE_PERIOD_SECONDS, MAX_OBJECT_TTL_DAYS},
+    },
+};
 
+#[cfg(feature = "pow-async")]
 pub mod async_pow;
+pub mod fast_pow;
+#[cfg(feature = "opencl")]
+pub mod opencl_pow;
 pub mod sync_pow;
 
 pub const NETWORK_MIN_NONCE_TRIALS_PER_BYTE: i32 = 1000;
 pub const NETWORK_MIN_EXTRA_BYTES: i32 = 1000;
+/// PyBitmessage assumes at least this TTL when checking PoW
+pub(crate) const MIN_POW_TTL_SECONDS: i64 = 300;
 
 #[derive(thiserror::Error, Debug)]
 pub enum PoWError {
     #[error("proof of work of object is insufficient (trivial_value > target)")]
     InsufficientProofOfWork,
+    #[error("proof of work was cancelled before the nonce was found")]
+    Cancelled,
+    #[error("proof of work target is unreachable (target is zero)")]
+    TargetUnreachable,
+}
+
+pub type PoWResult = Result<(BigUint, BigUint), PoWError>;
+
+/// Engine searching for the nonce which satisfies the PoW target
+pub trait PoWEngine: Send + Sync {
+    /// Start the search in the background. Dropping the returned receiver cancels it.
+    fn do_pow(&self, target: BigUint, initial_hash: Vec<u8>) -> oneshot::Receiver<PoWResult>;
+
+    /// Try nonces for the given time, returns number of nonces tried per second by all
+    /// workers. Blocks the calling thread.
+    fn benchmark(&self, duration: Duration) -> f64;
+}
+
+pub(crate) fn engine(kind: PoWEngineKind) -> Arc<dyn PoWEngine> {
+    match kind {
+        #[cfg(feature = "pow-async")]
+        PoWEngineKind::Async => Arc::new(async_pow::AsyncPoW {}),
+        PoWEngineKind::Fast => Arc::new(fast_pow::FastPoW {}),
+        #[cfg(feature = "opencl")]
+        PoWEngineKind::OpenCL => match opencl_pow::OpenClPoW::new() {
+            Some(engine) => Arc::new(engine),
+            None => {
+                log::warn!("There are no OpenCL devices, PoW is done on the CPU");
+                Arc::new(fast_pow::FastPoW {})
+            }
+        },
+    }
 }
 
 static TWO_POW_16: Lazy<BigUint> = Lazy::new(|| BigUint::from(2 as u32).pow(16));
@@ -40,10 +87,13 @@ pub(crate) fn check_pow(
     Ok(())
 }
 
+/// Target the trial value of the nonce must not exceed. Expected number of trials is
+/// divided by `trials_divisor`, which is the same for the whole network.
 pub(crate) fn get_pow_target(
     object: &Object,
     mut nonce_trials_per_byte: i32,
     mut extra_bytes: i32,
+    trials_divisor: u64,
 ) -> BigUint {
     if nonce_trials_per_byte == 0 {
         nonce_trials_per_byte = NETWORK_MIN_NONCE_TRIALS_PER_BYTE;
@@ -52,11 +102,55 @@ pub(crate) fn get_pow_target(
         extra_bytes = NETWORK_MIN_EXTRA_BYTES;
     }
 
-    let ttl = BigUint::from((object.expires - Utc::now().timestamp()) as u64);
-    let payload_bytes =
-        BigUint::from(serde_cbor::to_vec(&object.kind).unwrap().len() + (extra_bytes as usize) + 8);
-    let denominator: BigUint = BigUint::from(nonce_trials_per_byte as u32)
-        * (payload_bytes.clone() + ((ttl * payload_bytes) / TWO_POW_16.clone()));
+    let ttl = pow_ttl(object.expires);
+    let payload_size = serde_cbor::to_vec(&object.kind).unwrap().len();
+    TWO_POW_64.clone()
+        / pow_denominator(
+            payload_size,
+            ttl,
+            nonce_trials_per_byte,
+            extra_bytes,
+            trials_divisor,
+        )
+}
+
+/// Remaining TTL of the object as seen by PoW. It's clamped, so that objects which are
+/// about to expire (or already expired according to a skewed clock) still get a sane
+/// target, and objects expiring too late can't lower it.
+pub(crate) fn pow_ttl(expires: i64) -> u64 {
+    let max_ttl = MAX_OBJECT_TTL_DAYS * 24 * 60 * 60 + EXPIRY_GRACE_PERIOD_SECONDS;
+    (expires - Utc::now().timestamp()).clamp(MIN_POW_TTL_SECONDS, max_ttl) as u64
+}
 
-    TWO_POW_64.clone() / denominator
+/// Expected number of nonces to try until the PoW target of an object is reached,
+/// grows with both payload size and TTL
+pub(crate) fn expected_trials(
+    payload_size: usize,
+    ttl: u64,
+    nonce_trials_per_byte: i32,
+    extra_bytes: i32,
+    trials_divisor: u64,
+) -> f64 {
+    let denominator = pow_denominator(
+        payload_size,
+        ttl,
+        nonce_trials_per_byte,
+        extra_bytes,
+        trials_divisor,
+    );
+    u64::try_from(denominator).map_or(f64::INFINITY, |d| d as f64)
+}
+
+fn pow_denominator(
+    payload_size: usize,
+    ttl: u64,
+    nonce_trials_per_byte: i32,
+    extra_bytes: i32,
+    trials_divisor: u64,
+) -> BigUint {
+    let ttl = BigUint::from(ttl);
+    let payload_bytes = BigUint::from(payload_size + (extra_bytes as usize) + 8);
+    let denominator = BigUint::from(nonce_trials_per_byte as u32)
+        * (payload_bytes.clone() + ((ttl * payload_bytes) / TWO_POW_16.clone()));
+    (denominator / trials_divisor.max(1)).max(BigUint::from(1u32))
 }
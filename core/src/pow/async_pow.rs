@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant};
+
 use async_std::task;
 use futures::{
     channel::{mpsc, oneshot},
@@ -7,11 +9,22 @@ use log::info;
 use num_bigint::BigUint;
 use sha2::{Digest, Sha512};
 
+use super::{PoWEngine, PoWError, PoWResult};
+
+/// Reference PoW engine, which does all the math with `BigUint`
 pub struct AsyncPoW {}
 
-impl AsyncPoW {
-    pub fn do_pow(target: BigUint, initial_hash: Vec<u8>) -> oneshot::Receiver<(BigUint, BigUint)> {
+impl PoWEngine for AsyncPoW {
+    fn do_pow(&self, target: BigUint, initial_hash: Vec<u8>) -> oneshot::Receiver<PoWResult> {
         let (mut sender, receiver) = oneshot::channel();
+
+        if target == BigUint::from(0u32) {
+            sender
+                .send(Err(PoWError::TargetUnreachable))
+                .expect("receiver not to be dropped");
+            return receiver;
+        }
+
         let (internal_sender, mut internal_receiver) = mpsc::channel(1);
 
         let mut workers = Vec::new();
@@ -43,6 +56,9 @@ impl AsyncPoW {
             });
             workers.push(term_tx);
         }
+        // only workers should hold the senders, so that the channel is closed
+        // when all of them have terminated without a result
+        drop(internal_sender);
 
         task::spawn(async move {
             let mut cancellation_task = sender.cancellation().fuse();
@@ -56,17 +72,37 @@ impl AsyncPoW {
                     return;
                 },
                 result = internal_receiver.next() => {
-                    if let Some(res) = result {
-                        log::debug!("cancelling workers");
-                        for w in workers.into_iter() {
-                            _ = w.send(());
-                        }
-                        sender.send(res).expect("receiver not to be dropped");
-                        internal_receiver.close();
+                    log::debug!("cancelling workers");
+                    for w in workers.into_iter() {
+                        _ = w.send(());
                     }
+                    internal_receiver.close();
+                    let res = match result {
+                        Some(res) => Ok(res),
+                        None => Err(PoWError::Cancelled),
+                    };
+                    // receiver might be already dropped if PoW was cancelled in the meantime
+                    _ = sender.send(res);
                 }
             }
         });
         receiver
     }
+
+    fn benchmark(&self, duration: Duration) -> f64 {
+        let initial_hash = [0u8; 64];
+        let started_at = Instant::now();
+        let mut nonce = BigUint::from(0u32);
+        let mut tries: u64 = 0;
+        while started_at.elapsed() < duration {
+            nonce += 1u32;
+            let result_hash = Sha512::digest(Sha512::digest(
+                [nonce.to_bytes_be().as_slice(), initial_hash.as_slice()].concat(),
+            ));
+            _ = BigUint::from_bytes_be(&result_hash[0..8]);
+            tries += 1;
+        }
+        // workers run on all cores
+        tries as f64 / started_at.elapsed().as_secs_f64() * num_cpus::get() as f64
+    }
 }
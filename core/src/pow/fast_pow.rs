@@ -0,0 +1,125 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_std::task;
+use futures::{
+    channel::{mpsc, oneshot},
+    select, FutureExt, StreamExt,
+};
+use log::info;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha512};
+
+use super::{PoWEngine, PoWError, PoWResult};
+
+/// How many nonces are tried between checks of the stop flag
+const STOP_CHECK_INTERVAL: u64 = 1024;
+
+/// PoW engine doing all the math in `u64`, without allocations in the hot loop.
+/// Produces the same nonces as the reference `AsyncPoW` engine, but is a lot faster.
+pub struct FastPoW {}
+
+impl PoWEngine for FastPoW {
+    fn do_pow(&self, target: BigUint, initial_hash: Vec<u8>) -> oneshot::Receiver<PoWResult> {
+        let (mut sender, receiver) = oneshot::channel();
+
+        if target == BigUint::from(0u32) {
+            sender
+                .send(Err(PoWError::TargetUnreachable))
+                .expect("receiver not to be dropped");
+            return receiver;
+        }
+        // trial value is 64 bit, so bigger target is always reached
+        let target = u64::try_from(&target).unwrap_or(u64::MAX);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (internal_sender, mut internal_receiver) = mpsc::channel(1);
+        let num_of_cores = num_cpus::get() as u64;
+
+        for i in 0..num_of_cores {
+            let ih = initial_hash.clone();
+            let stop = stop.clone();
+            let mut s = internal_sender.clone();
+            task::spawn_blocking(move || {
+                info!("PoW has started");
+                let mut nonce = i;
+                let mut tries: u64 = 0;
+                loop {
+                    tries += 1;
+                    if tries % STOP_CHECK_INTERVAL == 0 && stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let trial_value = trial_value(nonce, &ih);
+                    if trial_value <= target {
+                        if !stop.swap(true, Ordering::Relaxed) {
+                            _ = s.try_send((trial_value, nonce));
+                        }
+                        break;
+                    }
+                    nonce = match nonce.checked_add(num_of_cores) {
+                        Some(n) => n,
+                        None => break,
+                    };
+                }
+                info!("PoW has ended");
+            });
+        }
+        // only workers should hold the senders, so that the channel is closed
+        // when all of them have terminated without a result
+        drop(internal_sender);
+
+        task::spawn(async move {
+            let mut cancellation_task = sender.cancellation().fuse();
+            select! {
+                () = cancellation_task => {
+                    log::debug!("cancelling workers");
+                    stop.store(true, Ordering::Relaxed);
+                },
+                result = internal_receiver.next() => {
+                    stop.store(true, Ordering::Relaxed);
+                    let res = match result {
+                        Some((trial_value, nonce)) => {
+                            Ok((BigUint::from(trial_value), BigUint::from(nonce)))
+                        }
+                        None => Err(PoWError::Cancelled),
+                    };
+                    // receiver might be already dropped if PoW was cancelled in the meantime
+                    _ = sender.send(res);
+                }
+            }
+        });
+        receiver
+    }
+
+    fn benchmark(&self, duration: Duration) -> f64 {
+        let initial_hash = [0; 64];
+        let started_at = Instant::now();
+        let mut nonce: u64 = 0;
+        while started_at.elapsed() < duration {
+            for _ in 0..STOP_CHECK_INTERVAL {
+                trial_value(nonce, &initial_hash);
+                nonce += 1;
+            }
+        }
+        // workers run on all cores
+        nonce as f64 / started_at.elapsed().as_secs_f64() * num_cpus::get() as f64
+    }
+}
+
+/// Hash the nonce the same way as [`super::check_pow`] does, i.e. as a big-endian
+/// number without leading zero bytes
+pub(super) fn trial_value(nonce: u64, initial_hash: &[u8]) -> u64 {
+    let bytes = nonce.to_be_bytes();
+    let start = ((nonce.leading_zeros() / 8) as usize).min(bytes.len() - 1);
+    let inner = Sha512::new()
+        .chain_update(&bytes[start..])
+        .chain_update(initial_hash)
+        .finalize();
+    let result_hash = Sha512::digest(inner);
+    u64::from_be_bytes(result_hash[0..8].try_into().unwrap())
+}
@@ -0,0 +1,239 @@
+use std::{
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_std::task;
+use futures::{channel::oneshot, select, FutureExt};
+use log::{info, warn};
+use num_bigint::BigUint;
+use opencl3::{
+    command_queue::CommandQueue,
+    context::Context,
+    device::{Device, CL_DEVICE_TYPE_ALL, CL_DEVICE_TYPE_GPU},
+    kernel::Kernel,
+    memory::{Buffer, ClMem, CL_MEM_READ_ONLY, CL_MEM_READ_WRITE},
+    platform::get_platforms,
+    program::Program,
+    types::{cl_uint, cl_ulong, CL_BLOCKING},
+};
+
+use super::{
+    fast_pow::{self, FastPoW},
+    PoWEngine, PoWError, PoWResult,
+};
+
+const KERNEL_SOURCE: &str = include_str!("opencl_pow.cl");
+/// Nonces are 8 bytes long from here on, so that the kernel hashes the same amount of
+/// data for each of them
+const FIRST_NONCE: u64 = 1 << 56;
+/// How many nonces a single run of the kernel tries, the stop flag is checked between
+/// the runs
+const BATCH_SIZE: usize = 1 << 20;
+/// The nonce, the initial hash and the padding have to fit into a single SHA-512 block
+const MAX_HASH_WORDS: usize = 12;
+
+/// PoW engine searching for the nonce on a GPU (or another device) with OpenCL. Nonces found by the device
+/// are checked on the CPU before they're returned. If the search can't be run on the
+/// device, the PoW is done by [`FastPoW`] instead.
+pub struct OpenClPoW {
+    device: Device,
+}
+
+impl OpenClPoW {
+    /// Engine running on the first OpenCL GPU or, if there's none, on any other OpenCL
+    /// device. `None` if there are no OpenCL devices.
+    pub fn new() -> Option<OpenClPoW> {
+        let platforms = get_platforms().ok()?;
+        let device = [CL_DEVICE_TYPE_GPU, CL_DEVICE_TYPE_ALL]
+            .into_iter()
+            .find_map(|kind| {
+                platforms
+                    .iter()
+                    .find_map(|p| p.get_devices(kind).ok()?.first().copied())
+            })?;
+        let device = Device::new(device);
+        info!(
+            "PoW is done by OpenCL device {}",
+            device.name().unwrap_or_default()
+        );
+        Some(OpenClPoW { device })
+    }
+}
+
+impl PoWEngine for OpenClPoW {
+    fn do_pow(&self, target: BigUint, initial_hash: Vec<u8>) -> oneshot::Receiver<PoWResult> {
+        let (mut sender, receiver) = oneshot::channel();
+
+        if target == BigUint::from(0u32) {
+            sender
+                .send(Err(PoWError::TargetUnreachable))
+                .expect("receiver not to be dropped");
+            return receiver;
+        }
+        // trial value is 64 bit, so bigger target is always reached
+        let trial_target = u64::try_from(&target).unwrap_or(u64::MAX);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let search = {
+            let (device, initial_hash, stop) = (self.device, initial_hash.clone(), stop.clone());
+            task::spawn_blocking(move || {
+                DeviceSearch::new(&device, &initial_hash, trial_target)?.run(&stop)
+            })
+        };
+
+        task::spawn(async move {
+            let mut cancellation_task = sender.cancellation().fuse();
+            let mut search = search.fuse();
+            let result = select! {
+                () = cancellation_task => {
+                    log::debug!("cancelling the search on the device");
+                    stop.store(true, Ordering::Relaxed);
+                    return;
+                },
+                result = search => result,
+            };
+            let res = match result {
+                Ok(Some(nonce)) => Ok((
+                    BigUint::from(fast_pow::trial_value(nonce, &initial_hash)),
+                    BigUint::from(nonce),
+                )),
+                Ok(None) => Err(PoWError::Cancelled),
+                Err(e) => {
+                    warn!("PoW can't be done with OpenCL, doing it on the CPU: {}", e);
+                    let mut fallback = FastPoW {}.do_pow(target, initial_hash);
+                    select! {
+                        () = cancellation_task => return,
+                        res = fallback => res.unwrap_or(Err(PoWError::Cancelled)),
+                    }
+                }
+            };
+            // receiver might be already dropped if PoW was cancelled in the meantime
+            _ = sender.send(res);
+        });
+        receiver
+    }
+
+    fn benchmark(&self, duration: Duration) -> f64 {
+        let benchmark = || -> Result<f64, String> {
+            // the target is never reached, so every run tries the whole batch
+            let search = DeviceSearch::new(&self.device, &[0; 32], 0)?;
+            let started_at = Instant::now();
+            let mut start = FIRST_NONCE;
+            while started_at.elapsed() < duration {
+                search.run_batch(start)?;
+                start += BATCH_SIZE as u64;
+            }
+            Ok((start - FIRST_NONCE) as f64 / started_at.elapsed().as_secs_f64())
+        };
+        benchmark().unwrap_or_else(|e| {
+            warn!(
+                "OpenCL PoW can't be benchmarked, the CPU is benchmarked: {}",
+                e
+            );
+            FastPoW {}.benchmark(duration)
+        })
+    }
+}
+
+/// Kernel built for the device, along with its buffers holding the initial hash and
+/// the nonce which is found
+struct DeviceSearch {
+    kernel: Kernel,
+    queue: CommandQueue,
+    found: Buffer<cl_ulong>,
+    _initial_hash: Buffer<cl_ulong>,
+    _program: Program,
+    _context: Context,
+    initial_hash: Vec<u8>,
+    target: u64,
+}
+
+impl DeviceSearch {
+    fn new(device: &Device, initial_hash: &[u8], target: u64) -> Result<DeviceSearch, String> {
+        if initial_hash.len() % 8 != 0 || initial_hash.len() > MAX_HASH_WORDS * 8 {
+            return Err(format!(
+                "initial hash of {} bytes can't be hashed by the kernel",
+                initial_hash.len()
+            ));
+        }
+        let words: Vec<cl_ulong> = initial_hash
+            .chunks_exact(8)
+            .map(|c| u64::from_be_bytes(c.try_into().unwrap()))
+            .collect();
+        let context = Context::from_device(device).map_err(|e| e.to_string())?;
+        let queue = CommandQueue::create(&context, device.id(), 0).map_err(|e| e.to_string())?;
+        let program = Program::create_and_build_from_source(&context, KERNEL_SOURCE, "")?;
+        let kernel = Kernel::create(&program, "search").map_err(|e| e.to_string())?;
+
+        let mut hash_buffer =
+            Buffer::<cl_ulong>::create(&context, CL_MEM_READ_ONLY, words.len(), ptr::null_mut())
+                .map_err(|e| e.to_string())?;
+        let mut found = Buffer::<cl_ulong>::create(&context, CL_MEM_READ_WRITE, 1, ptr::null_mut())
+            .map_err(|e| e.to_string())?;
+        queue
+            .enqueue_write_buffer(&mut hash_buffer, CL_BLOCKING, 0, &words, &[])
+            .and_then(|_| queue.enqueue_write_buffer(&mut found, CL_BLOCKING, 0, &[0], &[]))
+            .map_err(|e| e.to_string())?;
+        kernel
+            .set_arg(0, &hash_buffer.get())
+            .and_then(|_| kernel.set_arg(1, &(words.len() as cl_uint)))
+            .and_then(|_| kernel.set_arg(2, &target))
+            .and_then(|_| kernel.set_arg(4, &found.get()))
+            .map_err(|e| e.to_string())?;
+        Ok(DeviceSearch {
+            kernel,
+            queue,
+            found,
+            _initial_hash: hash_buffer,
+            _program: program,
+            _context: context,
+            initial_hash: initial_hash.to_vec(),
+            target,
+        })
+    }
+
+    /// Run the kernel batch by batch until the nonce is found, `None` if the search
+    /// was stopped or nonces ran out
+    fn run(&self, stop: &AtomicBool) -> Result<Option<u64>, String> {
+        let mut start = FIRST_NONCE;
+        while !stop.load(Ordering::Relaxed) {
+            if let Some(nonce) = self.run_batch(start)? {
+                // results of the device are only trusted once they're checked
+                if fast_pow::trial_value(nonce, &self.initial_hash) > self.target {
+                    return Err(format!("device found nonce {} which is wrong", nonce));
+                }
+                return Ok(Some(nonce));
+            }
+            start = match start.checked_add(BATCH_SIZE as u64) {
+                Some(s) => s,
+                None => break,
+            };
+        }
+        Ok(None)
+    }
+
+    /// Try nonces from `start` on, returns the one which reached the target if any
+    fn run_batch(&self, start: u64) -> Result<Option<u64>, String> {
+        self.kernel.set_arg(3, &start).map_err(|e| e.to_string())?;
+        self.queue
+            .enqueue_nd_range_kernel(
+                self.kernel.get(),
+                1,
+                ptr::null(),
+                [BATCH_SIZE].as_ptr(),
+                ptr::null(),
+                &[],
+            )
+            .map_err(|e| e.to_string())?;
+        let mut found = [0];
+        self.queue
+            .enqueue_read_buffer(&self.found, CL_BLOCKING, 0, &mut found, &[])
+            .map_err(|e| e.to_string())?;
+        Ok(Some(found[0]).filter(|n| *n != 0))
+    }
+}
@@ -1,4 +1,13 @@
-pub(crate) mod address;
-pub(crate) mod inventory;
-pub(crate) mod message;
+//! Traits of the repositories the node keeps its data in, so that the node can be
+//! embedded with a custom [`storage::Storage`], see
+//! [`NodeBuilder::storage`](crate::network::NodeBuilder::storage)
+
+pub mod address;
+pub mod inventory;
+pub(crate) mod memory;
+pub mod message;
+pub mod peer;
 pub(crate) mod sqlite;
+pub mod storage;
+
+pub use sqlite::models;
@@ -1,11 +1,24 @@
 use std::error::Error;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use dyn_clone::{clone_trait_object, DynClone};
 use ecies::PublicKey;
 
 use crate::network::address::Address;
 
+use super::sqlite::models;
+
+/// Outcome of [`AddressRepository::update_public_keys`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicKeysUpdate {
+    Updated,
+    /// Address already has different keys which are pinned, they are left untouched
+    Pinned,
+    /// There's no address with such ripe hash or tag
+    NotFound,
+}
+
 #[async_trait]
 pub trait AddressRepository: DynClone {
     /// Store known address
@@ -14,24 +27,178 @@ pub trait AddressRepository: DynClone {
     /// Delete address from repository
     async fn delete_address(&mut self, ripe: String) -> Result<(), Box<dyn Error>>;
 
+    /// Delete several addresses at once, either all of them are deleted or none
+    async fn delete_addresses(&mut self, addresses: Vec<String>) -> Result<(), Box<dyn Error>>;
+
     /// Get address by its ripe hash or tag
     async fn get_by_ripe_or_tag(&self, hash: String) -> Result<Option<Address>, Box<dyn Error>>;
 
-    /// Get contacts with known pubkeys
+    /// Get contacts, i.e. known addresses which aren't our own identities
     async fn get_contacts(&self) -> Result<Vec<Address>, Box<dyn Error>>;
 
     /// Get own identities, i.e. addresses which have private key
     async fn get_identities(&self) -> Result<Vec<Address>, Box<dyn Error>>;
 
+    /// Store public keys of the address along with the time they're received. If `pinned` is set and the address already has
+    /// different keys, they are left untouched.
     async fn update_public_keys(
         &mut self,
         hash: String,
         public_signing_key: PublicKey,
         public_encryption_key: PublicKey,
+        pinned: bool,
+    ) -> Result<PublicKeysUpdate, Box<dyn Error>>;
+
+    /// Forget public keys of the contact, so that new ones can be pinned. Keys of own
+    /// identities are never forgotten. Returns `false` if there's no such contact.
+    async fn clear_public_keys(&mut self, ripe: String) -> Result<bool, Box<dyn Error>>;
+
+    /// Set PoW difficulty required by the address (found by its ripe hash or tag)
+    async fn update_pow_difficulty(
+        &mut self,
+        hash: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Set PoW difficulty the address requires from strangers (found by its ripe hash or tag)
+    async fn update_stranger_pow_difficulty(
+        &mut self,
+        hash: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Set whether messages of strangers which don't meet the difficulty are quarantined
+    async fn set_quarantine_strangers(
+        &mut self,
+        address: String,
+        quarantine: bool,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Remember when own pubkey of the identity was sent out
+    async fn update_pubkey_published_at(
+        &mut self,
+        ripe: String,
+        time: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Get when own pubkey was last sent out in response to a request of the tag
+    async fn get_getpubkey_responded_at(
+        &self,
+        tag: String,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>>;
+
+    /// Remember when own pubkey was sent out in response to a request of the tag
+    async fn update_getpubkey_responded_at(
+        &mut self,
+        tag: String,
+        time: DateTime<Utc>,
     ) -> Result<(), Box<dyn Error>>;
 
     async fn update_label(&mut self, ripe: String, new_label: String)
         -> Result<(), Box<dyn Error>>;
+
+    /// Set image shown as the avatar of the address, `None` removes it. Returns
+    /// whether the address exists.
+    async fn set_avatar(
+        &mut self,
+        address: String,
+        avatar: Option<Vec<u8>>,
+    ) -> Result<bool, Box<dyn Error>>;
+
+    /// Get avatar image of the address, if it's set
+    async fn get_avatar(&self, address: String) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+
+    /// Remove private keys of the address, so it stops being our own identity
+    async fn remove_private_keys(&mut self, ripe: String) -> Result<(), Box<dyn Error>>;
+
+    /// Store encryption key of a device of the address, replacing the same existing key
+    async fn store_device_key(&mut self, key: models::DeviceKey) -> Result<(), Box<dyn Error>>;
+
+    /// Get keys of the devices receiving copies of messages sent to the address
+    async fn get_device_keys(
+        &self,
+        address: String,
+    ) -> Result<Vec<models::DeviceKey>, Box<dyn Error>>;
+
+    /// Get keys of this device (i.e. ones with private part) for all addresses
+    async fn get_own_device_keys(&self) -> Result<Vec<models::DeviceKey>, Box<dyn Error>>;
+
+    /// Replace public keys of the other devices of the address, e.g. with ones listed
+    /// in its pubkey. Keys of this device are kept.
+    async fn replace_device_keys(
+        &mut self,
+        address: String,
+        public_keys: Vec<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Remove key of a device of the address, returns whether it existed
+    async fn remove_device_key(
+        &mut self,
+        address: String,
+        public_key: Vec<u8>,
+    ) -> Result<bool, Box<dyn Error>>;
+
+    /// Replace keys of the address with the rotated ones (along with the time of rotation
+    /// and when they're received or published), and its former keys with `rotations`
+    async fn rotate_keys(
+        &mut self,
+        address: Address,
+        rotations: Vec<models::KeyRotation>,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Get former keys of the address, the original ones first
+    async fn get_key_rotations(
+        &self,
+        address: String,
+    ) -> Result<Vec<models::KeyRotation>, Box<dyn Error>>;
+
+    /// Get former keys of own identities (i.e. ones with private encryption key)
+    /// which were rotated since the time
+    async fn get_retired_keys(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<models::KeyRotation>, Box<dyn Error>>;
+
+    /// Store subscription to broadcasts of the address, replacing its label if it exists
+    async fn add_subscription(
+        &mut self,
+        subscription: models::Subscription,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Remove subscription to broadcasts of the address, returns whether it existed
+    async fn remove_subscription(&mut self, address: String) -> Result<bool, Box<dyn Error>>;
+
+    /// Get addresses whose broadcasts are received, the oldest subscriptions first
+    async fn get_subscriptions(&self) -> Result<Vec<models::Subscription>, Box<dyn Error>>;
+
+    /// Store auto-reply of the identity, replacing the existing one
+    async fn set_auto_reply(&mut self, auto_reply: models::AutoReply)
+        -> Result<(), Box<dyn Error>>;
+
+    /// Remove auto-reply of the identity, returns whether it existed
+    async fn remove_auto_reply(&mut self, address: String) -> Result<bool, Box<dyn Error>>;
+
+    async fn get_auto_reply(
+        &self,
+        address: String,
+    ) -> Result<Option<models::AutoReply>, Box<dyn Error>>;
+
+    /// Get when the sender was last auto-replied to by the identity
+    async fn get_auto_replied_at(
+        &self,
+        address: String,
+        sender: String,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>>;
+
+    /// Remember when the sender was auto-replied to by the identity
+    async fn update_auto_replied_at(
+        &mut self,
+        address: String,
+        sender: String,
+        time: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>>;
 }
 
 clone_trait_object!(AddressRepository);
@@ -1,15 +1,48 @@
 use std::error::Error;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use dyn_clone::{clone_trait_object, DynClone};
 
-use crate::network::messages::Object;
+use crate::{
+    config::{InventoryEviction, ObjectType},
+    network::messages::{InventoryCursor, Object},
+};
+
+/// Size of the inventory
+#[derive(Debug, Clone, Default)]
+pub struct InventoryStats {
+    pub objects: usize,
+    /// Total size of object data and signatures
+    pub bytes: u64,
+    /// Configured limit of `bytes`, `None` if the inventory isn't limited
+    pub max_bytes: Option<u64>,
+    /// Objects evicted since start to stay within the limit
+    pub evicted: u64,
+}
+
+/// Stored object, as listed for debugging
+#[derive(Debug, Clone)]
+pub struct InventoryItem {
+    pub hash: String,
+    /// `None` if the type is unknown to this node
+    pub object_type: Option<ObjectType>,
+    pub expires: DateTime<Utc>,
+}
 
 #[async_trait]
 pub trait InventoryRepository: DynClone {
     /// Get current inventory vector
     async fn get(&self) -> Result<Vec<String>, Box<dyn Error>>;
 
+    /// Get a page of current inventory vector along with expiration time and type of each
+    /// object. Objects are ordered by expiration time and hash, the page starts after `after`.
+    async fn get_page(
+        &self,
+        after: Option<InventoryCursor>,
+        limit: usize,
+    ) -> Result<Vec<(String, i64, u8)>, Box<dyn Error>>;
+
     /// Get object by its hash
     async fn get_object(&self, hash: String) -> Result<Option<Object>, Box<dyn Error>>;
 
@@ -26,8 +59,23 @@ pub trait InventoryRepository: DynClone {
     /// Update object nonce when PoW is done
     async fn update_nonce(&mut self, hash: String, nonce: Vec<u8>) -> Result<(), Box<dyn Error>>;
 
+    /// Remove object from the inventory
+    async fn remove_object(&mut self, hash: String) -> Result<(), Box<dyn Error>>;
+
     /// Cleanup the storage of expired items
     async fn cleanup(&mut self) -> Result<usize, Box<dyn Error>>;
+
+    /// Get number and total size of stored objects
+    async fn stats(&self) -> Result<InventoryStats, Box<dyn Error>>;
+
+    /// Remove objects in the order given by the policy, until the inventory takes at most
+    /// `max_bytes`. Own objects waiting for PoW are never removed, since they weren't sent yet.
+    /// Returns number of removed objects.
+    async fn evict(
+        &mut self,
+        max_bytes: u64,
+        policy: InventoryEviction,
+    ) -> Result<usize, Box<dyn Error>>;
 }
 
 clone_trait_object!(InventoryRepository);
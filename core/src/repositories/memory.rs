@@ -0,0 +1,5 @@
+pub mod address;
+pub mod inventory;
+pub mod message;
+pub mod peer;
+pub mod storage;
@@ -0,0 +1,531 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use ecies::PublicKey;
+
+use crate::{
+    network::address::Address,
+    repositories::{
+        address::{AddressRepository, PublicKeysUpdate},
+        sqlite::models,
+    },
+};
+
+use super::storage::SharedTables;
+
+#[derive(Clone)]
+pub struct MemoryAddressRepository {
+    tables: SharedTables,
+}
+
+impl MemoryAddressRepository {
+    pub fn new(tables: SharedTables) -> Self {
+        MemoryAddressRepository { tables }
+    }
+}
+
+fn is_ripe_or_tag(a: &Address, hash: &str) -> bool {
+    a.string_repr == hash || bs58::encode(&a.tag).into_string() == hash
+}
+
+fn is_identity(a: &Address) -> bool {
+    a.private_signing_key.is_some() && a.private_encryption_key.is_some()
+}
+
+#[async_trait]
+impl AddressRepository for MemoryAddressRepository {
+    async fn store(&mut self, a: Address) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        if tables
+            .addresses
+            .iter()
+            .any(|x| x.string_repr == a.string_repr)
+        {
+            return Err("address already exists".into());
+        }
+        tables.addresses.push(a);
+        Ok(())
+    }
+
+    async fn delete_address(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        self.delete_addresses(vec![hash]).await
+    }
+
+    async fn delete_addresses(&mut self, addresses: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        tables
+            .addresses
+            .retain(|a| !addresses.contains(&a.string_repr));
+        tables
+            .device_keys
+            .retain(|k| !addresses.contains(&k.address));
+        tables
+            .key_rotations
+            .retain(|r| !addresses.contains(&r.address));
+        for address in &addresses {
+            tables.avatars.remove(address);
+        }
+        tables
+            .auto_replies
+            .retain(|r| !addresses.contains(&r.address));
+        tables
+            .auto_replied_at
+            .retain(|(address, _), _| !addresses.contains(address));
+        Ok(())
+    }
+
+    async fn get_by_ripe_or_tag(&self, hash: String) -> Result<Option<Address>, Box<dyn Error>> {
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter()
+            .find(|a| is_ripe_or_tag(a, &hash))
+            .cloned())
+    }
+
+    async fn get_contacts(&self) -> Result<Vec<Address>, Box<dyn Error>> {
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter()
+            .filter(|a| a.private_signing_key.is_none() && a.private_encryption_key.is_none())
+            .cloned()
+            .collect())
+    }
+
+    async fn get_identities(&self) -> Result<Vec<Address>, Box<dyn Error>> {
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter()
+            .filter(|a| is_identity(a))
+            .cloned()
+            .collect())
+    }
+
+    async fn update_public_keys(
+        &mut self,
+        hash: String,
+        public_signing_key: PublicKey,
+        public_encryption_key: PublicKey,
+        pinned: bool,
+    ) -> Result<PublicKeysUpdate, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let mut result = PublicKeysUpdate::NotFound;
+        for a in tables
+            .addresses
+            .iter_mut()
+            .filter(|a| is_ripe_or_tag(a, &hash))
+        {
+            let same_keys = a.public_signing_key.map(|k| k.serialize())
+                == Some(public_signing_key.serialize())
+                && a.public_encryption_key.map(|k| k.serialize())
+                    == Some(public_encryption_key.serialize());
+            if pinned && a.public_signing_key.is_some() && !same_keys {
+                if result == PublicKeysUpdate::NotFound {
+                    result = PublicKeysUpdate::Pinned;
+                }
+                continue;
+            }
+            a.public_signing_key = Some(public_signing_key);
+            a.public_encryption_key = Some(public_encryption_key);
+            a.pubkey_received_at = Some(Utc::now());
+            result = PublicKeysUpdate::Updated;
+        }
+        Ok(result)
+    }
+
+    async fn clear_public_keys(&mut self, ripe: String) -> Result<bool, Box<dyn Error>> {
+        let mut cleared = false;
+        for a in self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter_mut()
+            .filter(|a| {
+                a.string_repr == ripe
+                    && a.private_signing_key.is_none()
+                    && a.private_encryption_key.is_none()
+            })
+        {
+            a.public_signing_key = None;
+            a.public_encryption_key = None;
+            a.pubkey_received_at = None;
+            cleared = true;
+        }
+        Ok(cleared)
+    }
+
+    async fn update_pow_difficulty(
+        &mut self,
+        hash: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        for a in self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter_mut()
+            .filter(|a| is_ripe_or_tag(a, &hash))
+        {
+            a.nonce_trials_per_byte = nonce_trials_per_byte;
+            a.extra_bytes = extra_bytes;
+        }
+        Ok(())
+    }
+
+    async fn update_stranger_pow_difficulty(
+        &mut self,
+        hash: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        for a in self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter_mut()
+            .filter(|a| is_ripe_or_tag(a, &hash))
+        {
+            a.stranger_nonce_trials_per_byte = nonce_trials_per_byte;
+            a.stranger_extra_bytes = extra_bytes;
+        }
+        Ok(())
+    }
+
+    async fn set_quarantine_strangers(
+        &mut self,
+        address: String,
+        quarantine: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        for a in self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter_mut()
+            .filter(|a| a.string_repr == address)
+        {
+            a.quarantine_strangers = quarantine;
+        }
+        Ok(())
+    }
+
+    async fn update_pubkey_published_at(
+        &mut self,
+        ripe: String,
+        time: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        for a in self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter_mut()
+            .filter(|a| a.string_repr == ripe)
+        {
+            a.pubkey_published_at = Some(time);
+        }
+        Ok(())
+    }
+
+    async fn get_getpubkey_responded_at(
+        &self,
+        tag: String,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.getpubkey_responses.get(&tag).copied())
+    }
+
+    async fn update_getpubkey_responded_at(
+        &mut self,
+        tag: String,
+        time: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        tables.getpubkey_responses.insert(tag, time);
+        Ok(())
+    }
+
+    async fn update_label(
+        &mut self,
+        ripe: String,
+        new_label: String,
+    ) -> Result<(), Box<dyn Error>> {
+        for a in self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter_mut()
+            .filter(|a| a.string_repr == ripe)
+        {
+            a.label = new_label.clone();
+        }
+        Ok(())
+    }
+
+    async fn set_avatar(
+        &mut self,
+        address: String,
+        avatar: Option<Vec<u8>>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        if !tables.addresses.iter().any(|a| a.string_repr == address) {
+            return Ok(false);
+        }
+        match avatar {
+            Some(avatar) => _ = tables.avatars.insert(address, avatar),
+            None => _ = tables.avatars.remove(&address),
+        }
+        Ok(true)
+    }
+
+    async fn get_avatar(&self, address: String) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        Ok(self.tables.lock().unwrap().avatars.get(&address).cloned())
+    }
+
+    async fn remove_private_keys(&mut self, ripe: String) -> Result<(), Box<dyn Error>> {
+        for a in self
+            .tables
+            .lock()
+            .unwrap()
+            .addresses
+            .iter_mut()
+            .filter(|a| a.string_repr == ripe)
+        {
+            a.private_signing_key = None;
+            a.private_encryption_key = None;
+        }
+        Ok(())
+    }
+
+    async fn store_device_key(&mut self, key: models::DeviceKey) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        tables
+            .device_keys
+            .retain(|k| k.address != key.address || k.public_key != key.public_key);
+        tables.device_keys.push(key);
+        Ok(())
+    }
+
+    async fn get_device_keys(
+        &self,
+        address: String,
+    ) -> Result<Vec<models::DeviceKey>, Box<dyn Error>> {
+        let tables = self.tables.lock().unwrap();
+        let mut keys: Vec<models::DeviceKey> = tables
+            .device_keys
+            .iter()
+            .filter(|k| k.address == address)
+            .cloned()
+            .collect();
+        keys.sort_by_key(|k| k.created_at);
+        Ok(keys)
+    }
+
+    async fn get_own_device_keys(&self) -> Result<Vec<models::DeviceKey>, Box<dyn Error>> {
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .device_keys
+            .iter()
+            .filter(|k| k.private_key.is_some())
+            .cloned()
+            .collect())
+    }
+
+    async fn replace_device_keys(
+        &mut self,
+        address: String,
+        public_keys: Vec<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        tables
+            .device_keys
+            .retain(|k| k.address != address || k.private_key.is_some());
+        for public_key in public_keys {
+            if tables
+                .device_keys
+                .iter()
+                .any(|k| k.address == address && k.public_key == public_key)
+            {
+                continue;
+            }
+            tables.device_keys.push(models::DeviceKey {
+                address: address.clone(),
+                public_key,
+                private_key: None,
+                label: String::new(),
+                created_at: Utc::now(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn remove_device_key(
+        &mut self,
+        address: String,
+        public_key: Vec<u8>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let len = tables.device_keys.len();
+        tables
+            .device_keys
+            .retain(|k| k.address != address || k.public_key != public_key);
+        Ok(tables.device_keys.len() < len)
+    }
+
+    async fn rotate_keys(
+        &mut self,
+        address: Address,
+        rotations: Vec<models::KeyRotation>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        for a in tables
+            .addresses
+            .iter_mut()
+            .filter(|a| a.string_repr == address.string_repr)
+        {
+            a.public_signing_key = address.public_signing_key;
+            a.public_encryption_key = address.public_encryption_key;
+            a.private_signing_key = address.private_signing_key;
+            a.private_encryption_key = address.private_encryption_key;
+            a.keys_rotated_at = address.keys_rotated_at;
+            a.pubkey_published_at = address.pubkey_published_at;
+            a.pubkey_received_at = address.pubkey_received_at;
+        }
+        tables
+            .key_rotations
+            .retain(|r| r.address != address.string_repr);
+        tables
+            .key_rotations
+            .extend(rotations.into_iter().map(|r| models::KeyRotation {
+                address: address.string_repr.clone(),
+                ..r
+            }));
+        Ok(())
+    }
+
+    async fn get_key_rotations(
+        &self,
+        address: String,
+    ) -> Result<Vec<models::KeyRotation>, Box<dyn Error>> {
+        let tables = self.tables.lock().unwrap();
+        let mut rotations: Vec<models::KeyRotation> = tables
+            .key_rotations
+            .iter()
+            .filter(|r| r.address == address)
+            .cloned()
+            .collect();
+        rotations.sort_by_key(|r| r.position);
+        Ok(rotations)
+    }
+
+    async fn get_retired_keys(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<models::KeyRotation>, Box<dyn Error>> {
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .key_rotations
+            .iter()
+            .filter(|r| r.private_encryption_key.is_some() && r.rotated_at >= since)
+            .cloned()
+            .collect())
+    }
+
+    async fn add_subscription(
+        &mut self,
+        subscription: models::Subscription,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables
+            .subscriptions
+            .iter_mut()
+            .find(|s| s.address == subscription.address)
+        {
+            Some(s) => s.label = subscription.label,
+            None => tables.subscriptions.push(subscription),
+        }
+        Ok(())
+    }
+
+    async fn remove_subscription(&mut self, address: String) -> Result<bool, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let len = tables.subscriptions.len();
+        tables.subscriptions.retain(|s| s.address != address);
+        Ok(tables.subscriptions.len() < len)
+    }
+
+    async fn get_subscriptions(&self) -> Result<Vec<models::Subscription>, Box<dyn Error>> {
+        let mut subscriptions = self.tables.lock().unwrap().subscriptions.clone();
+        subscriptions.sort_by_key(|s| s.created_at);
+        Ok(subscriptions)
+    }
+
+    async fn set_auto_reply(
+        &mut self,
+        auto_reply: models::AutoReply,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        tables
+            .auto_replies
+            .retain(|r| r.address != auto_reply.address);
+        tables.auto_replies.push(auto_reply);
+        Ok(())
+    }
+
+    async fn remove_auto_reply(&mut self, address: String) -> Result<bool, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let len = tables.auto_replies.len();
+        tables.auto_replies.retain(|r| r.address != address);
+        Ok(tables.auto_replies.len() < len)
+    }
+
+    async fn get_auto_reply(
+        &self,
+        address: String,
+    ) -> Result<Option<models::AutoReply>, Box<dyn Error>> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .auto_replies
+            .iter()
+            .find(|r| r.address == address)
+            .cloned())
+    }
+
+    async fn get_auto_replied_at(
+        &self,
+        address: String,
+        sender: String,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.auto_replied_at.get(&(address, sender)).copied())
+    }
+
+    async fn update_auto_replied_at(
+        &mut self,
+        address: String,
+        sender: String,
+        time: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        tables.auto_replied_at.insert((address, sender), time);
+        Ok(())
+    }
+}
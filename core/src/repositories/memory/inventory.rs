@@ -0,0 +1,177 @@
+use std::{collections::HashSet, error::Error};
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::{
+    config::InventoryEviction,
+    network::messages::{InventoryCursor, Object},
+    repositories::inventory::{InventoryRepository, InventoryStats},
+};
+
+use super::storage::SharedTables;
+
+#[derive(Clone)]
+pub struct MemoryInventoryRepository {
+    tables: SharedTables,
+}
+
+impl MemoryInventoryRepository {
+    pub fn new(tables: SharedTables) -> Self {
+        MemoryInventoryRepository { tables }
+    }
+}
+
+/// Size of the object as stored in the database
+fn object_size(o: &Object) -> u64 {
+    (serde_cbor::to_vec(&o.kind).unwrap().len() + o.signature.len()) as u64
+}
+
+/// Objects are announced only once their PoW is done and until they expire
+fn is_valid(o: &Object) -> bool {
+    !o.nonce.is_empty() && o.expires > Utc::now().timestamp()
+}
+
+#[async_trait]
+impl InventoryRepository for MemoryInventoryRepository {
+    async fn get(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .inventory
+            .iter()
+            .filter(|(_, o)| is_valid(o))
+            .map(|(hash, _)| hash.clone())
+            .collect())
+    }
+
+    async fn get_page(
+        &self,
+        after: Option<InventoryCursor>,
+        limit: usize,
+    ) -> Result<Vec<(String, i64, u8)>, Box<dyn Error>> {
+        let mut page: Vec<(String, i64, u8)> = self
+            .tables
+            .lock()
+            .unwrap()
+            .inventory
+            .iter()
+            .filter(|(_, o)| is_valid(o))
+            .map(|(hash, o)| (hash.clone(), o.expires, o.kind.object_type()))
+            .filter(|(hash, expires, _)| match &after {
+                Some(a) => (*expires, hash) > (a.expires, &a.hash),
+                None => true,
+            })
+            .collect();
+        page.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        page.truncate(limit);
+        Ok(page)
+    }
+
+    async fn get_object(&self, hash: String) -> Result<Option<Object>, Box<dyn Error>> {
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .inventory
+            .get(&hash)
+            .filter(|o| !o.nonce.is_empty())
+            .cloned())
+    }
+
+    async fn get_missing_objects(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let tables = self.tables.lock().unwrap();
+        let missing: HashSet<String> = hashes
+            .into_iter()
+            .filter(|h| !tables.inventory.get(h).is_some_and(is_valid))
+            .collect();
+        Ok(missing.into_iter().collect())
+    }
+
+    async fn store_object(&mut self, o: Object) -> Result<(), Box<dyn Error>> {
+        let hash = bs58::encode(&o.hash).into_string();
+        let mut tables = self.tables.lock().unwrap();
+        if tables.inventory.contains_key(&hash) {
+            return Err("object already exists".into());
+        }
+        tables.inventory.insert(hash, o);
+        Ok(())
+    }
+
+    async fn get_missing_pow_objects(&self) -> Result<Vec<Object>, Box<dyn Error>> {
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .inventory
+            .values()
+            .filter(|o| o.nonce.is_empty())
+            .cloned()
+            .collect())
+    }
+
+    async fn update_nonce(&mut self, hash: String, nonce: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        if let Some(o) = self.tables.lock().unwrap().inventory.get_mut(&hash) {
+            o.nonce = nonce;
+        }
+        Ok(())
+    }
+
+    async fn remove_object(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        self.tables.lock().unwrap().inventory.remove(&hash);
+        Ok(())
+    }
+
+    async fn cleanup(&mut self) -> Result<usize, Box<dyn Error>> {
+        let now = Utc::now().timestamp();
+        let mut tables = self.tables.lock().unwrap();
+        let count = tables.inventory.len();
+        tables.inventory.retain(|_, o| o.expires > now);
+        Ok(count - tables.inventory.len())
+    }
+
+    async fn stats(&self) -> Result<InventoryStats, Box<dyn Error>> {
+        let tables = self.tables.lock().unwrap();
+        Ok(InventoryStats {
+            objects: tables.inventory.len(),
+            bytes: tables.inventory.values().map(object_size).sum(),
+            ..Default::default()
+        })
+    }
+
+    async fn evict(
+        &mut self,
+        max_bytes: u64,
+        policy: InventoryEviction,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let total: u64 = tables.inventory.values().map(object_size).sum();
+        let mut excess = total.saturating_sub(max_bytes);
+        let mut candidates: Vec<(String, u64, i64)> = tables
+            .inventory
+            .iter()
+            .filter(|(_, o)| !o.nonce.is_empty())
+            .map(|(hash, o)| (hash.clone(), object_size(o), o.expires))
+            .collect();
+        match policy {
+            InventoryEviction::Expiring => candidates.sort_by(|a, b| (a.2, &a.0).cmp(&(b.2, &b.0))),
+            InventoryEviction::Largest => {
+                candidates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+            }
+        }
+        let mut removed = 0;
+        for (hash, size, _) in candidates {
+            if excess == 0 {
+                break;
+            }
+            tables.inventory.remove(&hash);
+            excess = excess.saturating_sub(size);
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
@@ -0,0 +1,505 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::{
+    network::{messages::UnencryptedMsg, node::worker::Folder, validation},
+    repositories::{
+        message::{
+            extract_text, FolderCounters, FolderStats, MessageRepository, Page, QUARANTINE_FOLDER,
+            TRASH_FOLDER,
+        },
+        sqlite::models::{self, MessageStatus},
+    },
+};
+
+use super::storage::{SharedTables, Tables};
+
+#[derive(Clone)]
+pub struct MemoryMessageRepository {
+    tables: SharedTables,
+}
+
+impl MemoryMessageRepository {
+    pub fn new(tables: SharedTables) -> Self {
+        MemoryMessageRepository { tables }
+    }
+
+    /// Get messages matching the filter along with expiration time of their objects
+    fn select<F: Fn(&models::Message) -> bool>(&self, filter: F) -> Vec<models::Message> {
+        let tables = self.tables.lock().unwrap();
+        tables
+            .messages
+            .iter()
+            .filter(|m| filter(m))
+            .map(|m| with_expiration(&tables, m))
+            .collect()
+    }
+}
+
+fn with_expiration(tables: &Tables, m: &models::Message) -> models::Message {
+    let mut m = m.clone();
+    m.expires = tables.inventory.get(&m.hash).and_then(|o| {
+        NaiveDateTime::from_timestamp_opt(o.expires, 0).map(|t| DateTime::<Utc>::from_utc(t, Utc))
+    });
+    m
+}
+
+fn is_draft(m: &models::Message) -> bool {
+    m.status == MessageStatus::Draft.to_string()
+}
+
+fn is_outbox(m: &models::Message) -> bool {
+    MessageStatus::OUTBOX
+        .iter()
+        .any(|s| m.status == s.to_string())
+}
+
+fn is_sent(m: &models::Message) -> bool {
+    !is_draft(m) && !is_outbox(m)
+}
+
+/// Order messages from the newest one and only keep the page of them, if it's given
+fn paginate(mut msgs: Vec<models::Message>, page: Option<Page>) -> Vec<models::Message> {
+    msgs.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.hash.cmp(&b.hash)));
+    match page {
+        Some(page) => msgs
+            .into_iter()
+            .skip(page.offset as usize)
+            .take(page.limit as usize)
+            .collect(),
+        None => msgs,
+    }
+}
+
+/// Every word of the query has to be found in the subject or the body
+fn matches_query(m: &models::Message, words: &[String]) -> bool {
+    let (subject, body) = extract_text(&m.data);
+    let text = format!("{} {}", subject, body).to_lowercase();
+    words.iter().all(|w| text.contains(w.as_str()))
+}
+
+#[async_trait]
+impl MessageRepository for MemoryMessageRepository {
+    async fn save(
+        &mut self,
+        hash: String,
+        msg: UnencryptedMsg,
+        signature: Vec<u8>,
+        verification_error: Option<String>,
+    ) -> Result<models::Message, Box<dyn Error>> {
+        let status = if verification_error.is_some() {
+            MessageStatus::Unverified
+        } else {
+            MessageStatus::Received
+        };
+        let model = models::Message {
+            hash,
+            sender: msg.sender_ripe,
+            recipient: msg.destination_ripe,
+            data: msg.message,
+            created_at: Utc::now(),
+            status: status.to_string(),
+            signature,
+            verified: verification_error.is_none(),
+            signer_fingerprint: Some(validation::key_fingerprint(&msg.public_signing_key)),
+            failure_reason: verification_error,
+            folder: None,
+            deleted_at: None,
+            ack_data: None,
+            read: false,
+            send_at: None,
+            resend_count: 0,
+            resend_at: None,
+            expires: None,
+            sender_label: None,
+            recipient_label: None,
+        };
+        self.save_model(model.clone()).await?;
+        Ok(model)
+    }
+
+    async fn save_model(&mut self, model: models::Message) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        if tables.messages.iter().any(|m| m.hash == model.hash) {
+            return Err("message already exists".into());
+        }
+        tables.messages.push(model);
+        Ok(())
+    }
+
+    async fn get_messages(&self) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        Ok(self.tables.lock().unwrap().messages.clone())
+    }
+
+    async fn get_messages_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        Ok(self.select(|m| hashes.contains(&m.hash)))
+    }
+
+    async fn get_messages_by_recipient(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        Ok(paginate(
+            self.select(|m| m.recipient == address && m.folder.is_none() && !is_draft(m)),
+            page,
+        ))
+    }
+
+    async fn get_messages_by_sender(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        Ok(paginate(
+            self.select(|m| m.sender == address && m.folder.is_none() && is_sent(m)),
+            page,
+        ))
+    }
+
+    async fn get_outbox(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        Ok(paginate(
+            self.select(|m| m.sender == address && m.folder.is_none() && is_outbox(m)),
+            page,
+        ))
+    }
+
+    async fn get_drafts(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let drafts = self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter()
+            .filter(|m| m.sender == address && m.folder.is_none() && is_draft(m))
+            .cloned()
+            .collect();
+        Ok(paginate(drafts, page))
+    }
+
+    async fn update_draft(
+        &mut self,
+        hash: String,
+        model: models::Message,
+    ) -> Result<(), Box<dyn Error>> {
+        for m in self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter_mut()
+            .filter(|m| m.hash == hash && is_draft(m))
+        {
+            m.sender = model.sender.clone();
+            m.recipient = model.recipient.clone();
+            m.data = model.data.clone();
+            m.created_at = model.created_at;
+        }
+        Ok(())
+    }
+
+    async fn search_messages(
+        &self,
+        query: String,
+        folder: Folder,
+        address: String,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        let in_folder = |m: &models::Message| match folder {
+            Folder::Inbox | Folder::Broadcasts => {
+                m.recipient == address && m.folder.is_none() && !is_draft(m)
+            }
+            Folder::Outbox => m.sender == address && m.folder.is_none() && is_outbox(m),
+            Folder::Sent => m.sender == address && m.folder.is_none() && is_sent(m),
+            Folder::Drafts => m.sender == address && m.folder.is_none() && is_draft(m),
+            Folder::Trash => {
+                (m.sender == address || m.recipient == address)
+                    && m.folder.as_deref() == Some(TRASH_FOLDER)
+            }
+            Folder::Quarantine => {
+                m.recipient == address && m.folder.as_deref() == Some(QUARANTINE_FOLDER)
+            }
+        };
+        Ok(self.select(|m| in_folder(m) && matches_query(m, &words)))
+    }
+
+    /// Messages are searched through directly, there is no index
+    async fn index_messages(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    async fn get_trashed_messages(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let trashed = self.select(|m| {
+            (m.sender == address || m.recipient == address)
+                && m.folder.as_deref() == Some(TRASH_FOLDER)
+        });
+        Ok(paginate(trashed, page))
+    }
+
+    async fn get_quarantined_messages(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let quarantined = self
+            .select(|m| m.recipient == address && m.folder.as_deref() == Some(QUARANTINE_FOLDER));
+        Ok(paginate(quarantined, page))
+    }
+
+    async fn move_to_quarantine(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        for m in self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter_mut()
+            .filter(|m| m.hash == hash)
+        {
+            m.folder = Some(QUARANTINE_FOLDER.to_string());
+        }
+        Ok(())
+    }
+
+    async fn move_to_trash(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        for m in self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter_mut()
+            .filter(|m| m.hash == hash)
+        {
+            m.folder = Some(TRASH_FOLDER.to_string());
+            m.deleted_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn restore_message(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        for m in self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter_mut()
+            .filter(|m| m.hash == hash)
+        {
+            m.folder = None;
+            m.deleted_at = None;
+        }
+        Ok(())
+    }
+
+    async fn purge_trash(&mut self, deleted_before: DateTime<Utc>) -> Result<(), Box<dyn Error>> {
+        self.tables.lock().unwrap().messages.retain(|m| {
+            m.folder.as_deref() != Some(TRASH_FOLDER)
+                || m.deleted_at.is_none_or(|d| d >= deleted_before)
+        });
+        Ok(())
+    }
+
+    async fn update_message_status(
+        &mut self,
+        hash: String,
+        status: MessageStatus,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut updated = false;
+        for m in self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter_mut()
+            .filter(|m| m.hash == hash)
+        {
+            m.status = status.to_string();
+            m.failure_reason = None;
+            updated = true;
+        }
+        Ok(updated)
+    }
+
+    async fn mark_read(&mut self, hash: String, read: bool) -> Result<bool, Box<dyn Error>> {
+        let mut updated = false;
+        for m in self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter_mut()
+            .filter(|m| m.hash == hash)
+        {
+            m.read = read;
+            updated = true;
+        }
+        Ok(updated)
+    }
+
+    async fn get_folder_stats(&self, address: String) -> Result<FolderStats, Box<dyn Error>> {
+        let tables = self.tables.lock().unwrap();
+        let count = |filter: &dyn Fn(&models::Message) -> bool| {
+            tables
+                .messages
+                .iter()
+                .filter(|m| filter(m))
+                .fold(FolderCounters::default(), |c, m| FolderCounters {
+                    total: c.total + 1,
+                    unread: c.unread + u64::from(!m.read),
+                })
+        };
+        Ok(FolderStats {
+            inbox: count(&|m| m.recipient == address && m.folder.is_none() && !is_draft(m)),
+            outbox: count(&|m| m.sender == address && m.folder.is_none() && is_outbox(m)),
+            sent: count(&|m| m.sender == address && m.folder.is_none() && is_sent(m)),
+            drafts: count(&|m| m.sender == address && m.folder.is_none() && is_draft(m)),
+            trash: count(&|m| {
+                (m.sender == address || m.recipient == address)
+                    && m.folder.as_deref() == Some(TRASH_FOLDER)
+            }),
+            quarantine: count(&|m| {
+                m.recipient == address && m.folder.as_deref() == Some(QUARANTINE_FOLDER)
+            }),
+        })
+    }
+
+    async fn mark_as_failed(&mut self, hash: String, reason: String) -> Result<(), Box<dyn Error>> {
+        for m in self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter_mut()
+            .filter(|m| m.hash == hash)
+        {
+            m.status = MessageStatus::Failed.to_string();
+            m.failure_reason = Some(reason.clone());
+        }
+        Ok(())
+    }
+
+    async fn set_resend_at(
+        &mut self,
+        hash: String,
+        resend_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        for m in self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter_mut()
+            .filter(|m| m.hash == hash && m.ack_data.is_some())
+        {
+            m.resend_at = Some(resend_at);
+        }
+        Ok(())
+    }
+
+    async fn mark_as_delivered(
+        &mut self,
+        ack_data: Vec<u8>,
+    ) -> Result<Option<models::Message>, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let msg = tables.messages.iter_mut().find(|m| {
+            m.ack_data.as_ref() == Some(&ack_data) && m.status == MessageStatus::Sent.to_string()
+        });
+        Ok(msg.map(|m| {
+            m.status = MessageStatus::Delivered.to_string();
+            m.clone()
+        }))
+    }
+
+    async fn update_hash(
+        &mut self,
+        old_hash: String,
+        new_hash: String,
+    ) -> Result<(), Box<dyn Error>> {
+        for m in self
+            .tables
+            .lock()
+            .unwrap()
+            .messages
+            .iter_mut()
+            .filter(|m| m.hash == old_hash)
+        {
+            m.hash = new_hash.clone();
+        }
+        Ok(())
+    }
+
+    async fn get_messages_by_status(
+        &self,
+        status: MessageStatus,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let status = status.to_string();
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .messages
+            .iter()
+            .filter(|m| m.status == status)
+            .cloned()
+            .collect())
+    }
+
+    async fn remove_message(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        self.tables
+            .lock()
+            .unwrap()
+            .messages
+            .retain(|m| m.hash != hash);
+        Ok(())
+    }
+
+    async fn get_filter_rules(&self) -> Result<Vec<models::FilterRule>, Box<dyn Error>> {
+        let mut rules = self.tables.lock().unwrap().filter_rules.clone();
+        rules.sort_by_key(|r| r.created_at);
+        Ok(rules)
+    }
+
+    async fn add_filter_rule(&mut self, rule: models::FilterRule) -> Result<(), Box<dyn Error>> {
+        self.tables.lock().unwrap().filter_rules.push(rule);
+        Ok(())
+    }
+
+    async fn update_filter_rule(
+        &mut self,
+        rule: models::FilterRule,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let Some(existing) = tables.filter_rules.iter_mut().find(|r| r.id == rule.id) else {
+            return Ok(false);
+        };
+        *existing = models::FilterRule {
+            created_at: existing.created_at,
+            ..rule
+        };
+        Ok(true)
+    }
+
+    async fn remove_filter_rule(&mut self, id: String) -> Result<bool, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let len = tables.filter_rules.len();
+        tables.filter_rules.retain(|r| r.id != id);
+        Ok(tables.filter_rules.len() < len)
+    }
+}
@@ -0,0 +1,147 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::repositories::{peer::PeerRepository, sqlite::models};
+
+use super::storage::SharedTables;
+
+#[derive(Clone)]
+pub struct MemoryPeerRepository {
+    tables: SharedTables,
+}
+
+impl MemoryPeerRepository {
+    pub fn new(tables: SharedTables) -> Self {
+        MemoryPeerRepository { tables }
+    }
+}
+
+#[async_trait]
+impl PeerRepository for MemoryPeerRepository {
+    async fn store_seen(
+        &mut self,
+        peer_id: String,
+        multiaddr: String,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = Utc::now();
+        let mut tables = self.tables.lock().unwrap();
+        tables.peers.retain(|p| p.multiaddr != multiaddr);
+        tables.peers.push(models::Peer {
+            multiaddr,
+            peer_id: peer_id.clone(),
+            last_seen: now,
+            failures: 0,
+            next_attempt: None,
+        });
+        for p in tables.peers.iter_mut().filter(|p| p.peer_id == peer_id) {
+            p.failures = 0;
+            p.next_attempt = None;
+            p.last_seen = now;
+        }
+        Ok(())
+    }
+
+    async fn store_exchanged(
+        &mut self,
+        peer_id: String,
+        multiaddr: String,
+        last_seen: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        if !tables.peers.iter().any(|p| p.multiaddr == multiaddr) {
+            tables.peers.push(models::Peer {
+                multiaddr,
+                peer_id,
+                last_seen,
+                failures: 0,
+                next_attempt: None,
+            });
+        }
+        Ok(())
+    }
+
+    async fn get_dialable(&self) -> Result<Vec<models::Peer>, Box<dyn Error>> {
+        let now = Utc::now();
+        let mut peers: Vec<models::Peer> = self
+            .tables
+            .lock()
+            .unwrap()
+            .peers
+            .iter()
+            .filter(|p| p.next_attempt.is_none_or(|t| t <= now))
+            .cloned()
+            .collect();
+        peers.sort_by_key(|p| std::cmp::Reverse(p.last_seen));
+        Ok(peers)
+    }
+
+    async fn record_failure(&mut self, peer_id: String) -> Result<Option<u32>, Box<dyn Error>> {
+        let mut failures = None;
+        for p in self
+            .tables
+            .lock()
+            .unwrap()
+            .peers
+            .iter_mut()
+            .filter(|p| p.peer_id == peer_id)
+        {
+            p.failures += 1;
+            failures = Some(p.failures);
+        }
+        Ok(failures)
+    }
+
+    async fn postpone(
+        &mut self,
+        peer_id: String,
+        until: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        for p in self
+            .tables
+            .lock()
+            .unwrap()
+            .peers
+            .iter_mut()
+            .filter(|p| p.peer_id == peer_id)
+        {
+            p.next_attempt = Some(until);
+        }
+        Ok(())
+    }
+
+    async fn cleanup(&mut self, seen_before: DateTime<Utc>) -> Result<usize, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let count = tables.peers.len();
+        tables.peers.retain(|p| p.last_seen >= seen_before);
+        Ok(count - tables.peers.len())
+    }
+
+    async fn ban(&mut self, target: String, reason: Option<String>) -> Result<(), Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        tables.banned_peers.retain(|b| b.target != target);
+        tables.banned_peers.push(models::BannedPeer {
+            target: target.clone(),
+            reason,
+            banned_at: Utc::now(),
+        });
+        tables
+            .peers
+            .retain(|p| p.peer_id != target && p.multiaddr != target);
+        Ok(())
+    }
+
+    async fn unban(&mut self, target: String) -> Result<bool, Box<dyn Error>> {
+        let mut tables = self.tables.lock().unwrap();
+        let count = tables.banned_peers.len();
+        tables.banned_peers.retain(|b| b.target != target);
+        Ok(tables.banned_peers.len() < count)
+    }
+
+    async fn get_banned(&self) -> Result<Vec<models::BannedPeer>, Box<dyn Error>> {
+        let mut banned = self.tables.lock().unwrap().banned_peers.clone();
+        banned.sort_by_key(|b| std::cmp::Reverse(b.banned_at));
+        Ok(banned)
+    }
+}
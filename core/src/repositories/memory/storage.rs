@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    network::{address::Address, messages::Object},
+    repositories::{
+        address::AddressRepositorySync, inventory::InventoryRepositorySync,
+        message::MessageRepositorySync, peer::PeerRepositorySync, sqlite::models, storage::Storage,
+    },
+};
+
+use super::{
+    address::MemoryAddressRepository, inventory::MemoryInventoryRepository,
+    message::MemoryMessageRepository, peer::MemoryPeerRepository,
+};
+
+/// Data of all repositories, so that they can look into each other
+/// like joined tables do
+#[derive(Default)]
+pub struct Tables {
+    pub addresses: Vec<Address>,
+    /// Objects by their hash
+    pub inventory: HashMap<String, Object>,
+    pub messages: Vec<models::Message>,
+    pub peers: Vec<models::Peer>,
+    pub banned_peers: Vec<models::BannedPeer>,
+    pub device_keys: Vec<models::DeviceKey>,
+    pub key_rotations: Vec<models::KeyRotation>,
+    /// Avatar images by address
+    pub avatars: HashMap<String, Vec<u8>>,
+    /// When own pubkey was last sent out on request, by the requested tag
+    pub getpubkey_responses: HashMap<String, DateTime<Utc>>,
+    pub subscriptions: Vec<models::Subscription>,
+    pub auto_replies: Vec<models::AutoReply>,
+    /// When senders were last auto-replied to, by the identity and the sender
+    pub auto_replied_at: HashMap<(String, String), DateTime<Utc>>,
+    pub filter_rules: Vec<models::FilterRule>,
+}
+
+pub type SharedTables = Arc<Mutex<Tables>>;
+
+/// Storage keeping everything in memory, which is lost once the node is stopped
+#[derive(Default)]
+pub struct MemoryStorage {
+    tables: SharedTables,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    fn address_repo(&self) -> Box<AddressRepositorySync> {
+        Box::new(MemoryAddressRepository::new(self.tables.clone()))
+    }
+
+    fn inventory_repo(&self) -> Box<InventoryRepositorySync> {
+        Box::new(MemoryInventoryRepository::new(self.tables.clone()))
+    }
+
+    fn message_repo(&self) -> Box<MessageRepositorySync> {
+        Box::new(MemoryMessageRepository::new(self.tables.clone()))
+    }
+
+    fn peer_repo(&self) -> Box<PeerRepositorySync> {
+        Box::new(MemoryPeerRepository::new(self.tables.clone()))
+    }
+
+    async fn close(&self) {}
+
+    async fn vacuum(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    async fn size(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(0)
+    }
+}
@@ -1,43 +1,183 @@
 use std::error::Error;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use dyn_clone::{clone_trait_object, DynClone};
 
-use crate::network::messages::UnencryptedMsg;
+use crate::network::{messages::UnencryptedMsg, node::worker::Folder};
 
 use super::sqlite::models::{self, MessageStatus};
 
+/// Folders of messages besides the default ones, shared by all the storages
+pub(crate) const TRASH_FOLDER: &str = "Trash";
+pub(crate) const QUARANTINE_FOLDER: &str = "Quarantine";
+
+/// Extract subject and plain text body from MIME message
+pub(crate) fn extract_text(data: &[u8]) -> (String, String) {
+    match mail_parser::Message::parse(data) {
+        Some(m) => (
+            m.subject().unwrap_or_default().to_string(),
+            m.body_text(0).unwrap_or_default().to_string(),
+        ),
+        None => (String::new(), String::from_utf8_lossy(data).to_string()),
+    }
+}
+
+/// Number of messages in a folder
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FolderCounters {
+    pub total: u64,
+    pub unread: u64,
+}
+
+/// Message counters of the folders of an identity
+#[derive(Debug, Clone, Default)]
+pub struct FolderStats {
+    pub inbox: FolderCounters,
+    pub outbox: FolderCounters,
+    pub sent: FolderCounters,
+    pub drafts: FolderCounters,
+    pub trash: FolderCounters,
+    pub quarantine: FolderCounters,
+}
+
+/// Part of a folder, messages of folders are ordered from the newest one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    /// Number of skipped messages
+    pub offset: u32,
+    /// Max number of returned messages
+    pub limit: u32,
+}
+
 #[async_trait]
 pub trait MessageRepository: DynClone {
-    /// Save received message in repository
+    /// Save received message in repository, returns the stored message. Messages
+    /// which failed signature verification are marked as unverified with the given reason.
     async fn save(
         &mut self,
         hash: String,
         msg: UnencryptedMsg,
         signature: Vec<u8>,
-    ) -> Result<(), Box<dyn Error>>;
+        verification_error: Option<String>,
+    ) -> Result<models::Message, Box<dyn Error>>;
 
     async fn save_model(&mut self, model: models::Message) -> Result<(), Box<dyn Error>>;
 
     /// Get all messages in repository
     async fn get_messages(&self) -> Result<Vec<models::Message>, Box<dyn Error>>;
 
+    /// Get messages with the given hashes, unknown hashes are skipped
+    async fn get_messages_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>>;
+
+    /// Get received messages of the address, all of them if `page` is `None`
     async fn get_messages_by_recipient(
         &self,
         address: String,
+        page: Option<Page>,
     ) -> Result<Vec<models::Message>, Box<dyn Error>>;
 
+    /// Get outgoing messages of the address which have been sent out
     async fn get_messages_by_sender(
         &self,
         address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>>;
+
+    /// Get outgoing messages of the address which haven't been sent out yet,
+    /// see [`MessageStatus::OUTBOX`]
+    async fn get_outbox(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>>;
+
+    /// Get unsent drafts of the address
+    async fn get_drafts(
+        &self,
+        address: String,
+        page: Option<Page>,
     ) -> Result<Vec<models::Message>, Box<dyn Error>>;
 
+    /// Replace sender, recipient and contents of the draft
+    async fn update_draft(
+        &mut self,
+        hash: String,
+        model: models::Message,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Full-text search over subjects and bodies of the messages in the folder of the address
+    async fn search_messages(
+        &self,
+        query: String,
+        folder: Folder,
+        address: String,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>>;
+
+    /// Add messages which are missing in the search index to it
+    async fn index_messages(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// Get messages of the address (sent or received) which were moved to Trash
+    async fn get_trashed_messages(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>>;
+
+    /// Get received messages of the address which were put into Quarantine
+    async fn get_quarantined_messages(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>>;
+
+    /// Move received message to Quarantine, e.g. when the sender is a stranger
+    /// who didn't do enough PoW
+    async fn move_to_quarantine(&mut self, hash: String) -> Result<(), Box<dyn Error>>;
+
+    /// Move message to Trash
+    async fn move_to_trash(&mut self, hash: String) -> Result<(), Box<dyn Error>>;
+
+    /// Move message from Trash back to its original folder, or from Quarantine to Inbox
+    async fn restore_message(&mut self, hash: String) -> Result<(), Box<dyn Error>>;
+
+    /// Permanently remove messages which were moved to Trash before `deleted_before`
+    async fn purge_trash(&mut self, deleted_before: DateTime<Utc>) -> Result<(), Box<dyn Error>>;
+
+    /// Returns `false` if there is no such message
     async fn update_message_status(
         &mut self,
         hash: String,
         status: MessageStatus,
+    ) -> Result<bool, Box<dyn Error>>;
+
+    /// Mark message as read or unread. Returns `false` if there is no such message.
+    async fn mark_read(&mut self, hash: String, read: bool) -> Result<bool, Box<dyn Error>>;
+
+    /// Count all and unread messages in each folder of the address
+    async fn get_folder_stats(&self, address: String) -> Result<FolderStats, Box<dyn Error>>;
+
+    /// Mark message as failed, storing the reason of the failure
+    async fn mark_as_failed(&mut self, hash: String, reason: String) -> Result<(), Box<dyn Error>>;
+
+    /// Set the time the message is sent again unless it's acknowledged before.
+    /// Messages without ack data (e.g. sent to chans) are never sent again.
+    async fn set_resend_at(
+        &mut self,
+        hash: String,
+        resend_at: DateTime<Utc>,
     ) -> Result<(), Box<dyn Error>>;
 
+    /// Mark sent message with given ack data as delivered.
+    /// Returns the message, or `None` if there is no such sent message.
+    async fn mark_as_delivered(
+        &mut self,
+        ack_data: Vec<u8>,
+    ) -> Result<Option<models::Message>, Box<dyn Error>>;
+
     /// Update hash of message when inventory object is created
     async fn update_hash(
         &mut self,
@@ -51,6 +191,20 @@ pub trait MessageRepository: DynClone {
     ) -> Result<Vec<models::Message>, Box<dyn Error>>;
 
     async fn remove_message(&mut self, hash: String) -> Result<(), Box<dyn Error>>;
+
+    /// Get filtering rules in the order they're applied
+    async fn get_filter_rules(&self) -> Result<Vec<models::FilterRule>, Box<dyn Error>>;
+
+    async fn add_filter_rule(&mut self, rule: models::FilterRule) -> Result<(), Box<dyn Error>>;
+
+    /// Update the rule with the same id. Returns `false` if there is no such rule.
+    async fn update_filter_rule(
+        &mut self,
+        rule: models::FilterRule,
+    ) -> Result<bool, Box<dyn Error>>;
+
+    /// Remove the rule, returns whether it existed
+    async fn remove_filter_rule(&mut self, id: String) -> Result<bool, Box<dyn Error>>;
 }
 
 clone_trait_object!(MessageRepository);
@@ -0,0 +1,56 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dyn_clone::{clone_trait_object, DynClone};
+
+use super::sqlite::models;
+
+#[async_trait]
+pub trait PeerRepository: DynClone {
+    /// Remember the address the peer listens on, resetting failures of the peer
+    async fn store_seen(
+        &mut self,
+        peer_id: String,
+        multiaddr: String,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Remember the address of the peer learned from another peer, unless it's known
+    /// already. It's stored with the time the other peer saw it.
+    async fn store_exchanged(
+        &mut self,
+        peer_id: String,
+        multiaddr: String,
+        last_seen: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Get addresses of peers which can be dialed now, most recently seen first
+    async fn get_dialable(&self) -> Result<Vec<models::Peer>, Box<dyn Error>>;
+
+    /// Count failed dial of the peer, returns number of failures in a row
+    /// or `None` if the peer isn't stored
+    async fn record_failure(&mut self, peer_id: String) -> Result<Option<u32>, Box<dyn Error>>;
+
+    /// Don't dial the peer until given time
+    async fn postpone(
+        &mut self,
+        peer_id: String,
+        until: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Forget peers which weren't seen since given time
+    async fn cleanup(&mut self, seen_before: DateTime<Utc>) -> Result<usize, Box<dyn Error>>;
+
+    /// Ban peer id or multiaddr, stored addresses of the banned peer are forgotten
+    async fn ban(&mut self, target: String, reason: Option<String>) -> Result<(), Box<dyn Error>>;
+
+    /// Lift the ban, returns `false` if the target wasn't banned
+    async fn unban(&mut self, target: String) -> Result<bool, Box<dyn Error>>;
+
+    /// Get all bans, most recent first
+    async fn get_banned(&self) -> Result<Vec<models::BannedPeer>, Box<dyn Error>>;
+}
+
+clone_trait_object!(PeerRepository);
+
+pub type PeerRepositorySync = dyn PeerRepository + Send + Sync;
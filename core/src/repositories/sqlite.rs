@@ -1,4 +1,16 @@
+//! SQLite storage. Models are used by all the storages, so they're available
+//! even without the `sqlite` feature.
+
+#[cfg(feature = "sqlite")]
 pub mod address;
+#[cfg(feature = "sqlite")]
+pub mod database;
+#[cfg(feature = "sqlite")]
 pub mod inventory;
+#[cfg(feature = "sqlite")]
 pub mod message;
 pub mod models;
+#[cfg(feature = "sqlite")]
+pub mod peer;
+#[cfg(feature = "sqlite")]
+pub mod storage;
@@ -1,21 +1,27 @@
 use std::error::Error;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use ecies::{PublicKey, SecretKey};
 use sqlx::{QueryBuilder, SqlitePool};
 
-use crate::{network::address::Address, repositories::address::AddressRepository};
+use crate::{
+    network::address::Address,
+    repositories::address::{AddressRepository, PublicKeysUpdate},
+};
 
 use super::models;
 
 #[derive(Clone)]
 pub struct SqliteAddressRepository {
     pool: SqlitePool,
+    /// Single connection writing to the database, see [`super::storage::SqliteStorage`]
+    writer: SqlitePool,
 }
 
 impl SqliteAddressRepository {
-    pub fn new(pool: SqlitePool) -> SqliteAddressRepository {
-        SqliteAddressRepository { pool }
+    pub fn new(pool: SqlitePool, writer: SqlitePool) -> SqliteAddressRepository {
+        SqliteAddressRepository { pool, writer }
     }
 
     fn serialize(a: Address) -> models::Address {
@@ -47,11 +53,20 @@ impl SqliteAddressRepository {
             } else {
                 Some(a.label)
             },
+            nonce_trials_per_byte: a.nonce_trials_per_byte,
+            extra_bytes: a.extra_bytes,
+            pubkey_published_at: a.pubkey_published_at,
+            pubkey_received_at: a.pubkey_received_at,
+            chan: a.chan,
+            keys_rotated_at: a.keys_rotated_at,
+            stranger_nonce_trials_per_byte: a.stranger_nonce_trials_per_byte,
+            stranger_extra_bytes: a.stranger_extra_bytes,
+            quarantine_strangers: a.quarantine_strangers,
         }
     }
 
     fn deserialize(m: &models::Address) -> Result<Address, Box<dyn Error>> {
-        let mut address = Address::with_string_repr(m.address.clone());
+        let mut address = Address::with_string_repr(&m.address)?;
         let mut psk = None;
         let mut ppsk = None;
         let mut pek = None;
@@ -74,6 +89,15 @@ impl SqliteAddressRepository {
         address.public_encryption_key = pek;
         address.private_encryption_key = ppek;
         address.label = m.label.clone().unwrap_or("".to_string());
+        address.nonce_trials_per_byte = m.nonce_trials_per_byte;
+        address.extra_bytes = m.extra_bytes;
+        address.pubkey_published_at = m.pubkey_published_at;
+        address.pubkey_received_at = m.pubkey_received_at;
+        address.chan = m.chan;
+        address.keys_rotated_at = m.keys_rotated_at;
+        address.stranger_nonce_trials_per_byte = m.stranger_nonce_trials_per_byte;
+        address.stranger_extra_bytes = m.stranger_extra_bytes;
+        address.quarantine_strangers = m.quarantine_strangers;
         Ok(address)
     }
 }
@@ -83,7 +107,7 @@ impl AddressRepository for SqliteAddressRepository {
     async fn store(&mut self, a: Address) -> Result<(), Box<dyn Error>> {
         let model = Self::serialize(a);
         QueryBuilder::new(
-            "INSERT INTO addresses (address, tag, public_encryption_key, public_signing_key, private_signing_key, private_encryption_key, label) "
+            "INSERT INTO addresses (address, tag, public_encryption_key, public_signing_key, private_signing_key, private_encryption_key, label, nonce_trials_per_byte, extra_bytes, pubkey_published_at, pubkey_received_at, chan, keys_rotated_at, stranger_nonce_trials_per_byte, stranger_extra_bytes, quarantine_strangers) "
         )
         .push_values([model], |mut b, model| {
             b.push_bind(model.address)
@@ -92,18 +116,51 @@ impl AddressRepository for SqliteAddressRepository {
              .push_bind(model.public_signing_key)
              .push_bind(model.private_signing_key)
              .push_bind(model.private_encryption_key)
-             .push_bind(model.label);
+             .push_bind(model.label)
+             .push_bind(model.nonce_trials_per_byte)
+             .push_bind(model.extra_bytes)
+             .push_bind(model.pubkey_published_at)
+             .push_bind(model.pubkey_received_at)
+             .push_bind(model.chan)
+             .push_bind(model.keys_rotated_at)
+             .push_bind(model.stranger_nonce_trials_per_byte)
+             .push_bind(model.stranger_extra_bytes)
+             .push_bind(model.quarantine_strangers);
         }).build()
-          .execute(&self.pool)
+          .execute(&self.writer)
           .await?;
         Ok(())
     }
 
     async fn delete_address(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
-        sqlx::query("DELETE FROM addresses WHERE address = ?")
-            .bind(hash)
-            .execute(&self.pool)
-            .await?;
+        self.delete_addresses(vec![hash]).await
+    }
+
+    async fn delete_addresses(&mut self, addresses: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let mut tx = self.writer.begin().await?;
+        for address in addresses {
+            sqlx::query("DELETE FROM addresses WHERE address = ?")
+                .bind(&address)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM device_keys WHERE address = ?")
+                .bind(&address)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM key_rotations WHERE address = ?")
+                .bind(&address)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM auto_replies WHERE address = ?")
+                .bind(&address)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM auto_reply_senders WHERE address = ?")
+                .bind(address)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
         Ok(())
     }
 
@@ -124,7 +181,7 @@ impl AddressRepository for SqliteAddressRepository {
     }
 
     async fn get_contacts(&self) -> Result<Vec<Address>, Box<dyn Error>> {
-        let results: Vec<models::Address> = sqlx::query_as("SELECT * FROM addresses WHERE public_signing_key IS NOT NULL AND public_encryption_key IS NOT NULL")
+        let results: Vec<models::Address> = sqlx::query_as("SELECT * FROM addresses WHERE private_signing_key IS NULL AND private_encryption_key IS NULL")
             .fetch_all(&self.pool)
             .await?;
         let mut contacts = vec![];
@@ -152,17 +209,140 @@ impl AddressRepository for SqliteAddressRepository {
         hash: String,
         public_signing_key: PublicKey,
         public_encryption_key: PublicKey,
+        pinned: bool,
+    ) -> Result<PublicKeysUpdate, Box<dyn Error>> {
+        let psk = public_signing_key.serialize().to_vec();
+        let pek = public_encryption_key.serialize().to_vec();
+        let result = sqlx::query(
+            "UPDATE addresses SET public_signing_key = ?, public_encryption_key = ?, pubkey_received_at = ? \
+            WHERE (address = ? OR tag = ?) \
+            AND (? = 0 OR public_signing_key IS NULL OR (public_signing_key = ? AND public_encryption_key = ?))",
+        )
+        .bind(Some(&psk))
+        .bind(Some(&pek))
+        .bind(Utc::now())
+        .bind(&hash)
+        .bind(&hash)
+        .bind(pinned)
+        .bind(&psk)
+        .bind(&pek)
+        .execute(&self.writer)
+        .await?;
+        if result.rows_affected() > 0 {
+            return Ok(PublicKeysUpdate::Updated);
+        }
+        let exists: Option<(i64,)> =
+            sqlx::query_as("SELECT 1 FROM addresses WHERE address = ? OR tag = ?")
+                .bind(&hash)
+                .bind(&hash)
+                .fetch_optional(&self.writer)
+                .await?;
+        Ok(match exists {
+            Some(_) => PublicKeysUpdate::Pinned,
+            None => PublicKeysUpdate::NotFound,
+        })
+    }
+
+    async fn clear_public_keys(&mut self, ripe: String) -> Result<bool, Box<dyn Error>> {
+        let result = sqlx::query("UPDATE addresses SET public_signing_key = NULL, public_encryption_key = NULL, pubkey_received_at = NULL \
+            WHERE address = ? AND private_signing_key IS NULL AND private_encryption_key IS NULL")
+            .bind(ripe)
+            .execute(&self.writer)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn update_pow_difficulty(
+        &mut self,
+        hash: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "UPDATE addresses SET nonce_trials_per_byte = ?, extra_bytes = ? WHERE address = ? OR tag = ?",
+        )
+        .bind(nonce_trials_per_byte)
+        .bind(extra_bytes)
+        .bind(&hash)
+        .bind(&hash)
+        .execute(&self.writer)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_stranger_pow_difficulty(
+        &mut self,
+        hash: String,
+        nonce_trials_per_byte: i32,
+        extra_bytes: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "UPDATE addresses SET stranger_nonce_trials_per_byte = ?, stranger_extra_bytes = ? \
+            WHERE address = ? OR tag = ?",
+        )
+        .bind(nonce_trials_per_byte)
+        .bind(extra_bytes)
+        .bind(&hash)
+        .bind(&hash)
+        .execute(&self.writer)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_quarantine_strangers(
+        &mut self,
+        address: String,
+        quarantine: bool,
     ) -> Result<(), Box<dyn Error>> {
-        sqlx::query("UPDATE addresses SET public_signing_key = ?, public_encryption_key = ? WHERE address = ? OR tag = ?")
-            .bind(Some(public_signing_key.serialize().to_vec()))
-            .bind(Some(public_encryption_key.serialize().to_vec()))
-            .bind(&hash)
-            .bind(&hash)
-            .execute(&self.pool)
+        sqlx::query("UPDATE addresses SET quarantine_strangers = ? WHERE address = ?")
+            .bind(quarantine)
+            .bind(address)
+            .execute(&self.writer)
             .await?;
         Ok(())
     }
 
+    async fn update_pubkey_published_at(
+        &mut self,
+        ripe: String,
+        time: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        sqlx::query("UPDATE addresses SET pubkey_published_at = ? WHERE address = ?")
+            .bind(time)
+            .bind(ripe)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_getpubkey_responded_at(
+        &self,
+        tag: String,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let responded_at =
+            sqlx::query_scalar("SELECT responded_at FROM getpubkey_responses WHERE tag = ?")
+                .bind(tag)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(responded_at)
+    }
+
+    async fn update_getpubkey_responded_at(
+        &mut self,
+        tag: String,
+        time: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "INSERT INTO getpubkey_responses (tag, responded_at) VALUES (?, ?)
+            ON CONFLICT (tag) DO UPDATE SET responded_at = excluded.responded_at",
+        )
+        .bind(tag)
+        .bind(time)
+        .execute(&self.writer)
+        .await?;
+        Ok(())
+    }
+
     async fn update_label(
         &mut self,
         ripe: String,
@@ -171,8 +351,283 @@ impl AddressRepository for SqliteAddressRepository {
         sqlx::query("UPDATE addresses SET label = ? WHERE address = ?")
             .bind(Some(new_label))
             .bind(Some(ripe))
-            .execute(&self.pool)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_avatar(
+        &mut self,
+        address: String,
+        avatar: Option<Vec<u8>>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let result = sqlx::query("UPDATE addresses SET avatar = ? WHERE address = ?")
+            .bind(avatar)
+            .bind(address)
+            .execute(&self.writer)
             .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_avatar(&self, address: String) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let avatar: Option<Option<Vec<u8>>> =
+            sqlx::query_scalar("SELECT avatar FROM addresses WHERE address = ?")
+                .bind(address)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(avatar.flatten())
+    }
+
+    async fn remove_private_keys(&mut self, ripe: String) -> Result<(), Box<dyn Error>> {
+        sqlx::query("UPDATE addresses SET private_signing_key = NULL, private_encryption_key = NULL WHERE address = ?")
+            .bind(ripe)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn store_device_key(&mut self, key: models::DeviceKey) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO device_keys (address, public_key, private_key, label, created_at) \
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(key.address)
+        .bind(key.public_key)
+        .bind(key.private_key)
+        .bind(key.label)
+        .bind(key.created_at)
+        .execute(&self.writer)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_device_keys(
+        &self,
+        address: String,
+    ) -> Result<Vec<models::DeviceKey>, Box<dyn Error>> {
+        let keys =
+            sqlx::query_as("SELECT * FROM device_keys WHERE address = ? ORDER BY created_at")
+                .bind(address)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(keys)
+    }
+
+    async fn get_own_device_keys(&self) -> Result<Vec<models::DeviceKey>, Box<dyn Error>> {
+        let keys = sqlx::query_as("SELECT * FROM device_keys WHERE private_key IS NOT NULL")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(keys)
+    }
+
+    async fn replace_device_keys(
+        &mut self,
+        address: String,
+        public_keys: Vec<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut tx = self.writer.begin().await?;
+        sqlx::query("DELETE FROM device_keys WHERE address = ? AND private_key IS NULL")
+            .bind(&address)
+            .execute(&mut *tx)
+            .await?;
+        for key in public_keys {
+            sqlx::query(
+                "INSERT OR IGNORE INTO device_keys (address, public_key, label, created_at) \
+                VALUES (?, ?, '', ?)",
+            )
+            .bind(&address)
+            .bind(key)
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_device_key(
+        &mut self,
+        address: String,
+        public_key: Vec<u8>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let result = sqlx::query("DELETE FROM device_keys WHERE address = ? AND public_key = ?")
+            .bind(address)
+            .bind(public_key)
+            .execute(&self.writer)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn rotate_keys(
+        &mut self,
+        address: Address,
+        rotations: Vec<models::KeyRotation>,
+    ) -> Result<(), Box<dyn Error>> {
+        let model = Self::serialize(address);
+        let mut tx = self.writer.begin().await?;
+        sqlx::query(
+            "UPDATE addresses SET public_signing_key = ?, public_encryption_key = ?, \
+            private_signing_key = ?, private_encryption_key = ?, keys_rotated_at = ?, \
+            pubkey_published_at = ?, pubkey_received_at = ? WHERE address = ?",
+        )
+        .bind(model.public_signing_key)
+        .bind(model.public_encryption_key)
+        .bind(model.private_signing_key)
+        .bind(model.private_encryption_key)
+        .bind(model.keys_rotated_at)
+        .bind(model.pubkey_published_at)
+        .bind(model.pubkey_received_at)
+        .bind(&model.address)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM key_rotations WHERE address = ?")
+            .bind(&model.address)
+            .execute(&mut *tx)
+            .await?;
+        for rotation in rotations {
+            sqlx::query(
+                "INSERT INTO key_rotations (address, position, public_signing_key, \
+                public_encryption_key, private_encryption_key, signature, rotated_at) \
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&model.address)
+            .bind(rotation.position)
+            .bind(rotation.public_signing_key)
+            .bind(rotation.public_encryption_key)
+            .bind(rotation.private_encryption_key)
+            .bind(rotation.signature)
+            .bind(rotation.rotated_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_key_rotations(
+        &self,
+        address: String,
+    ) -> Result<Vec<models::KeyRotation>, Box<dyn Error>> {
+        let rotations =
+            sqlx::query_as("SELECT * FROM key_rotations WHERE address = ? ORDER BY position")
+                .bind(address)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rotations)
+    }
+
+    async fn get_retired_keys(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<models::KeyRotation>, Box<dyn Error>> {
+        let rotations = sqlx::query_as(
+            "SELECT * FROM key_rotations WHERE private_encryption_key IS NOT NULL AND rotated_at >= ?",
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rotations)
+    }
+
+    async fn add_subscription(
+        &mut self,
+        subscription: models::Subscription,
+    ) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "INSERT INTO subscriptions (address, label, created_at) VALUES (?, ?, ?)
+            ON CONFLICT (address) DO UPDATE SET label = excluded.label",
+        )
+        .bind(subscription.address)
+        .bind(subscription.label)
+        .bind(subscription.created_at)
+        .execute(&self.writer)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_subscription(&mut self, address: String) -> Result<bool, Box<dyn Error>> {
+        let result = sqlx::query("DELETE FROM subscriptions WHERE address = ?")
+            .bind(address)
+            .execute(&self.writer)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_subscriptions(&self) -> Result<Vec<models::Subscription>, Box<dyn Error>> {
+        let subscriptions = sqlx::query_as("SELECT * FROM subscriptions ORDER BY created_at")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(subscriptions)
+    }
+
+    async fn set_auto_reply(
+        &mut self,
+        auto_reply: models::AutoReply,
+    ) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "INSERT INTO auto_replies (address, subject_prefix, body, interval_hours)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (address) DO UPDATE SET subject_prefix = excluded.subject_prefix,
+            body = excluded.body, interval_hours = excluded.interval_hours",
+        )
+        .bind(auto_reply.address)
+        .bind(auto_reply.subject_prefix)
+        .bind(auto_reply.body)
+        .bind(auto_reply.interval_hours)
+        .execute(&self.writer)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_auto_reply(&mut self, address: String) -> Result<bool, Box<dyn Error>> {
+        let result = sqlx::query("DELETE FROM auto_replies WHERE address = ?")
+            .bind(address)
+            .execute(&self.writer)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_auto_reply(
+        &self,
+        address: String,
+    ) -> Result<Option<models::AutoReply>, Box<dyn Error>> {
+        let auto_reply = sqlx::query_as("SELECT * FROM auto_replies WHERE address = ?")
+            .bind(address)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(auto_reply)
+    }
+
+    async fn get_auto_replied_at(
+        &self,
+        address: String,
+        sender: String,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let replied_at = sqlx::query_scalar(
+            "SELECT replied_at FROM auto_reply_senders WHERE address = ? AND sender = ?",
+        )
+        .bind(address)
+        .bind(sender)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(replied_at)
+    }
+
+    async fn update_auto_replied_at(
+        &mut self,
+        address: String,
+        sender: String,
+        time: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "INSERT INTO auto_reply_senders (address, sender, replied_at) VALUES (?, ?, ?)
+            ON CONFLICT (address, sender) DO UPDATE SET replied_at = excluded.replied_at",
+        )
+        .bind(address)
+        .bind(sender)
+        .bind(time)
+        .execute(&self.writer)
+        .await?;
         Ok(())
     }
 }
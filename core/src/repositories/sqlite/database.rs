@@ -0,0 +1,192 @@
+//! Opening of the node database, which is optionally encrypted with SQLCipher
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use sqlx::{
+    migrate::{MigrateError, Migrator},
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
+    ConnectOptions, Connection, SqliteConnection,
+};
+
+pub(crate) const MIGRATIONS: Migrator = sqlx::migrate!("src/repositories/sqlite/migrations");
+/// Header of unencrypted SQLite database files
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+/// How long the check waits for the database locked by another process
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(thiserror::Error, Debug)]
+pub enum DatabaseError {
+    #[error("database was created by a newer version (schema {0}), update the app to open it")]
+    NewerSchema(i64),
+    #[error("database is corrupted: {0}")]
+    Corrupted(String),
+    #[error("failed to back up the database: {0}")]
+    Backup(#[from] io::Error),
+    #[error("failed to open the database: {0}")]
+    Open(io::Error),
+    #[error("failed to migrate the database: {0}")]
+    Migrate(#[from] MigrateError),
+    #[error("failed to read the database: {0}")]
+    Sqlx(#[from] sqlx::Error),
+}
+
+/// Connection options of the database, keyed with `password` if it's set
+pub fn connect_options(
+    path: &Path,
+    password: Option<&str>,
+    busy_timeout: Duration,
+) -> SqliteConnectOptions {
+    let mut options =
+        SqliteConnectOptions::from_str(&format!("sqlite://{}", path.to_string_lossy()))
+            .unwrap()
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .foreign_keys(true)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(busy_timeout);
+    if let Some(password) = password {
+        options = options.pragma("key", quote(password));
+    }
+    options
+}
+
+/// Check if the database exists and is encrypted. Encrypted files have no
+/// plaintext SQLite header.
+pub fn is_encrypted(path: &Path) -> io::Result<bool> {
+    let mut header = [0; SQLITE_HEADER.len()];
+    match fs::File::open(path) {
+        Ok(mut file) => match file.read_exact(&mut header) {
+            Ok(_) => Ok(header != SQLITE_HEADER),
+            // empty database files are created by SQLite before the first write
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Try to read the encrypted database with given password
+pub async fn check_password(path: &Path, password: &str) -> bool {
+    let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.to_string_lossy()))
+        .unwrap()
+        .pragma("key", quote(password));
+    let mut conn = match options.connect().await {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+    let result = sqlx::query("SELECT count(*) FROM sqlite_master")
+        .fetch_one(&mut conn)
+        .await
+        .is_ok();
+    let _ = conn.close().await;
+    result
+}
+
+/// Encrypt existing plaintext database with given password. The data is exported
+/// into a new encrypted file, which then replaces the original one.
+pub async fn encrypt(path: &Path, password: &str) -> Result<(), sqlx::Error> {
+    let encrypted_path = path.with_extension("db.encrypted");
+    let _ = fs::remove_file(&encrypted_path);
+
+    // attached database inherits the open flags, so the new file must be allowed to be created
+    let mut conn = SqliteConnection::connect_with(
+        &SqliteConnectOptions::from_str(&format!("sqlite://{}", path.to_string_lossy()))?
+            .create_if_missing(true),
+    )
+    .await?;
+    // move everything from the WAL into the main file, so that it's not lost
+    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+        .execute(&mut conn)
+        .await?;
+    sqlx::query("ATTACH DATABASE ? AS encrypted KEY ?")
+        .bind(encrypted_path.to_string_lossy())
+        .bind(password)
+        .execute(&mut conn)
+        .await?;
+    sqlx::query("SELECT sqlcipher_export('encrypted')")
+        .execute(&mut conn)
+        .await?;
+    sqlx::query("DETACH DATABASE encrypted")
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+
+    fs::rename(&encrypted_path, path)?;
+    for suffix in ["-wal", "-shm"] {
+        let mut aux_path = path.as_os_str().to_owned();
+        aux_path.push(suffix);
+        let _ = fs::remove_file(aux_path);
+    }
+    Ok(())
+}
+
+/// Check that the database can be opened by this version of the node, see
+/// [`check_connection`]. Missing database is fine, it's created on start.
+pub async fn check(path: &Path, password: Option<&str>) -> Result<(), DatabaseError> {
+    if !path.exists() {
+        return Ok(());
+    }
+    // plaintext database is encrypted only once the node is started
+    let password = password.filter(|_| is_encrypted(path).unwrap_or(false));
+    let mut conn = connect_options(path, password, CHECK_TIMEOUT)
+        .create_if_missing(false)
+        .connect()
+        .await?;
+    let result = check_connection(&mut conn).await;
+    let _ = conn.close().await;
+    result
+}
+
+/// Refuse the database migrated by a newer version of the node, since the schema is
+/// unknown to this one, and run the quick integrity check
+pub(crate) async fn check_connection(conn: &mut SqliteConnection) -> Result<(), DatabaseError> {
+    let has_migrations: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if has_migrations {
+        let applied: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations")
+            .fetch_one(&mut *conn)
+            .await?;
+        let latest = MIGRATIONS.iter().map(|m| m.version).max().unwrap_or_default();
+        if let Some(version) = applied.filter(|v| *v > latest) {
+            return Err(DatabaseError::NewerSchema(version));
+        }
+    }
+
+    let problems: Vec<String> = sqlx::query_scalar("PRAGMA quick_check")
+        .fetch_all(&mut *conn)
+        .await?;
+    if problems != ["ok"] {
+        return Err(DatabaseError::Corrupted(problems.join("; ")));
+    }
+    Ok(())
+}
+
+/// Copy the database file next to it (as `<name>.db.backup`), e.g. before it's migrated.
+/// Returns path of the copy.
+pub(crate) async fn backup(
+    conn: &mut SqliteConnection,
+    path: &Path,
+) -> Result<PathBuf, DatabaseError> {
+    // move everything from the WAL into the main file, so that the copy is complete
+    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+        .execute(&mut *conn)
+        .await?;
+    let backup_path = path.with_extension("db.backup");
+    fs::copy(path, &backup_path)?;
+    Ok(backup_path)
+}
+
+/// Quote password as SQL string literal for the `key` pragma
+fn quote(password: &str) -> String {
+    format!("'{}'", password.replace('\'', "''"))
+}
@@ -1,5 +1,4 @@
-use crate::network::messages::Object;
-use crate::pow;
+use crate::network::messages::{InventoryCursor, Object, MAX_INV_HASHES};
 use std::{
     collections::{hash_map::RandomState, HashSet},
     error::Error,
@@ -9,18 +8,26 @@ use async_trait::async_trait;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use sqlx::{QueryBuilder, SqlitePool};
 
-use crate::repositories::inventory::InventoryRepository;
+use crate::{
+    config::InventoryEviction,
+    repositories::inventory::{InventoryRepository, InventoryStats},
+};
 
 use super::models::{self};
 
 #[derive(Clone)]
 pub struct SqliteInventoryRepository {
     pool: SqlitePool,
+    /// Single connection writing to the database, see [`super::storage::SqliteStorage`]
+    writer: SqlitePool,
 }
 
 impl SqliteInventoryRepository {
-    pub fn new(conn_pool: SqlitePool) -> SqliteInventoryRepository {
-        SqliteInventoryRepository { pool: conn_pool }
+    pub fn new(conn_pool: SqlitePool, writer: SqlitePool) -> SqliteInventoryRepository {
+        SqliteInventoryRepository {
+            pool: conn_pool,
+            writer,
+        }
     }
 
     fn deserialize_model(m: models::Object) -> Object {
@@ -30,8 +37,8 @@ impl SqliteInventoryRepository {
             expires: m.expires.timestamp(),
             kind: serde_cbor::from_slice(&m.data).expect("data not to be malformed"),
             signature: m.signature.clone(),
-            nonce_trials_per_byte: pow::NETWORK_MIN_NONCE_TRIALS_PER_BYTE, // FIXME save this in db
-            extra_bytes: pow::NETWORK_MIN_EXTRA_BYTES,                     // FIXME save this in db
+            nonce_trials_per_byte: m.nonce_trials_per_byte,
+            extra_bytes: m.extra_bytes,
         }
     }
 }
@@ -48,6 +55,40 @@ impl InventoryRepository for SqliteInventoryRepository {
         Ok(rows)
     }
 
+    async fn get_page(
+        &self,
+        after: Option<InventoryCursor>,
+        limit: usize,
+    ) -> Result<Vec<(String, i64, u8)>, Box<dyn Error>> {
+        let mut query = QueryBuilder::new(
+            "SELECT hash, expires, object_type FROM inventory WHERE nonce IS NOT NULL AND expires > ",
+        );
+        query.push_bind(Utc::now());
+        if let Some(after) = after {
+            let expires = DateTime::<Utc>::from_utc(
+                NaiveDateTime::from_timestamp_opt(after.expires, 0).ok_or("invalid cursor")?,
+                Utc,
+            );
+            query
+                .push(" AND (expires > ")
+                .push_bind(expires)
+                .push(" OR (expires = ")
+                .push_bind(expires)
+                .push(" AND hash > ")
+                .push_bind(after.hash)
+                .push("))");
+        }
+        query
+            .push(" ORDER BY expires, hash LIMIT ")
+            .push_bind(limit as i64);
+        let rows: Vec<(String, DateTime<Utc>, i32)> =
+            query.build_query_as().fetch_all(&self.pool).await?;
+        Ok(rows
+            .into_iter()
+            .map(|(hash, expires, object_type)| (hash, expires.timestamp(), object_type as u8))
+            .collect())
+    }
+
     async fn get_object(&self, hash: String) -> Result<Option<Object>, Box<dyn Error>> {
         let obj: Result<models::Object, sqlx::Error> =
             sqlx::query_as("SELECT * FROM inventory WHERE hash = ? AND nonce IS NOT NULL")
@@ -71,12 +112,28 @@ impl InventoryRepository for SqliteInventoryRepository {
         &self,
         hashes: Vec<String>,
     ) -> Result<Vec<String>, Box<dyn Error>> {
+        if hashes.is_empty() {
+            return Ok(Vec::new());
+        }
         let incoming_objects: HashSet<String, RandomState> =
             HashSet::from_iter(hashes.clone().into_iter());
 
-        let existing_objects = self.get().await?;
-
-        let existing_objects = HashSet::from_iter(existing_objects.into_iter());
+        // only look up the offered hashes, so that the whole inventory isn't loaded,
+        // in chunks to stay below the limit of query parameters
+        let mut existing_objects = HashSet::new();
+        for chunk in hashes.chunks(MAX_INV_HASHES) {
+            let mut query = QueryBuilder::new(
+                "SELECT hash FROM inventory WHERE nonce IS NOT NULL AND expires > ",
+            );
+            query.push_bind(Utc::now()).push(" AND hash IN (");
+            let mut separated = query.separated(", ");
+            for hash in chunk {
+                separated.push_bind(hash);
+            }
+            separated.push_unseparated(")");
+            let existing: Vec<String> = query.build_query_scalar().fetch_all(&self.pool).await?;
+            existing_objects.extend(existing);
+        }
 
         let missing_objects: Vec<String> = incoming_objects
             .difference(&existing_objects)
@@ -105,10 +162,12 @@ impl InventoryRepository for SqliteInventoryRepository {
                 Utc,
             ),
             signature: o.signature,
+            nonce_trials_per_byte: o.nonce_trials_per_byte,
+            extra_bytes: o.extra_bytes,
         };
 
         QueryBuilder::new(
-            "INSERT INTO inventory (hash, nonce, object_type, data, expires, signature) ",
+            "INSERT INTO inventory (hash, nonce, object_type, data, expires, signature, nonce_trials_per_byte, extra_bytes) ",
         )
         .push_values([model], |mut b, model| {
             b.push_bind(model.hash)
@@ -116,10 +175,12 @@ impl InventoryRepository for SqliteInventoryRepository {
                 .push_bind(model.object_type)
                 .push_bind(model.data)
                 .push_bind(model.expires)
-                .push_bind(model.signature);
+                .push_bind(model.signature)
+                .push_bind(model.nonce_trials_per_byte)
+                .push_bind(model.extra_bytes);
         })
         .build()
-        .execute(&self.pool)
+        .execute(&self.writer)
         .await?;
 
         Ok(())
@@ -137,11 +198,19 @@ impl InventoryRepository for SqliteInventoryRepository {
         Ok(objects)
     }
 
+    async fn remove_object(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        sqlx::query("DELETE FROM inventory WHERE hash = ?")
+            .bind(hash)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
     async fn update_nonce(&mut self, hash: String, nonce: Vec<u8>) -> Result<(), Box<dyn Error>> {
         sqlx::query("UPDATE inventory SET nonce = ? WHERE hash = ?")
             .bind(nonce)
             .bind(hash)
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         Ok(())
     }
@@ -150,8 +219,53 @@ impl InventoryRepository for SqliteInventoryRepository {
     async fn cleanup(&mut self) -> Result<usize, Box<dyn Error>> {
         let result = sqlx::query("DELETE FROM inventory WHERE expires <= ?")
             .bind(Utc::now())
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         Ok(result.rows_affected() as usize)
     }
+
+    async fn stats(&self) -> Result<InventoryStats, Box<dyn Error>> {
+        let (objects, bytes): (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*), COALESCE(SUM(LENGTH(data) + LENGTH(signature)), 0) FROM inventory",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(InventoryStats {
+            objects: objects as usize,
+            bytes: bytes as u64,
+            ..Default::default()
+        })
+    }
+
+    async fn evict(
+        &mut self,
+        max_bytes: u64,
+        policy: InventoryEviction,
+    ) -> Result<usize, Box<dyn Error>> {
+        let excess = self.stats().await?.bytes.saturating_sub(max_bytes);
+        if excess == 0 {
+            return Ok(0);
+        }
+        let order = match policy {
+            InventoryEviction::Expiring => "expires, hash",
+            InventoryEviction::Largest => "size DESC, hash",
+        };
+        // remove objects until their total size covers the excess
+        let result = sqlx::query(&format!(
+            "DELETE FROM inventory WHERE hash IN (
+                SELECT hash FROM (
+                    SELECT hash, size, SUM(size) OVER (ORDER BY {} ROWS UNBOUNDED PRECEDING) AS total
+                    FROM (
+                        SELECT hash, expires, LENGTH(data) + LENGTH(signature) AS size
+                        FROM inventory WHERE nonce IS NOT NULL
+                    )
+                ) WHERE total - size < ?
+            )",
+            order
+        ))
+        .bind(excess as i64)
+        .execute(&self.writer)
+        .await?;
+        Ok(result.rows_affected() as usize)
+    }
 }
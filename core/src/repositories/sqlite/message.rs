@@ -1,24 +1,56 @@
 use std::error::Error;
 
 use async_trait::async_trait;
-use chrono::Utc;
-use sqlx::{QueryBuilder, SqlitePool};
+use chrono::{DateTime, Utc};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
 
-use crate::{network::messages::UnencryptedMsg, repositories::message::MessageRepository};
+use crate::{
+    network::{messages::UnencryptedMsg, node::worker::Folder, validation},
+    repositories::message::{
+        extract_text, FolderCounters, FolderStats, MessageRepository, Page, QUARANTINE_FOLDER,
+        TRASH_FOLDER,
+    },
+};
 
 use super::models::{self, MessageStatus};
 
 #[derive(Clone)]
 pub struct SqliteMessageRepository {
     pool: SqlitePool,
+    /// Single connection writing to the database, see [`super::storage::SqliteStorage`]
+    writer: SqlitePool,
 }
 
 impl SqliteMessageRepository {
-    pub fn new(conn_pool: SqlitePool) -> Self {
-        SqliteMessageRepository { pool: conn_pool }
+    pub fn new(conn_pool: SqlitePool, writer: SqlitePool) -> Self {
+        SqliteMessageRepository {
+            pool: conn_pool,
+            writer,
+        }
+    }
+
+    async fn index_message(&self, hash: String, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        let (subject, body) = extract_text(data);
+        sqlx::query("INSERT INTO messages_fts (hash, subject, body) VALUES (?, ?, ?)")
+            .bind(hash)
+            .bind(subject)
+            .bind(body)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
     }
 }
 
+/// Convert user input to FTS5 query, matching every word as a prefix,
+/// so that special characters in the input can't break the query syntax
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|w| format!("\"{}\"*", w.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 #[async_trait]
 impl MessageRepository for SqliteMessageRepository {
     /// Save received message in repository
@@ -27,20 +59,39 @@ impl MessageRepository for SqliteMessageRepository {
         hash: String,
         msg: UnencryptedMsg,
         signature: Vec<u8>,
-    ) -> Result<(), Box<dyn Error>> {
+        verification_error: Option<String>,
+    ) -> Result<models::Message, Box<dyn Error>> {
+        let status = if verification_error.is_some() {
+            MessageStatus::Unverified
+        } else {
+            MessageStatus::Received
+        };
         let model = models::Message {
             hash,
             sender: msg.sender_ripe,
             recipient: msg.destination_ripe,
             data: msg.message,
             created_at: Utc::now(),
-            status: MessageStatus::Received.to_string(),
+            status: status.to_string(),
             signature,
+            verified: verification_error.is_none(),
+            signer_fingerprint: Some(validation::key_fingerprint(&msg.public_signing_key)),
+            failure_reason: verification_error,
+            folder: None,
+            deleted_at: None,
+            ack_data: None,
+            read: false,
+            send_at: None,
+            resend_count: 0,
+            resend_at: None,
+            expires: None,
+            sender_label: None,
+            recipient_label: None,
         };
 
-        self.save_model(model).await?;
+        self.save_model(model.clone()).await?;
 
-        Ok(())
+        Ok(model)
     }
 
     /// Get all messages in repository
@@ -51,31 +102,286 @@ impl MessageRepository for SqliteMessageRepository {
         Ok(results)
     }
 
+    async fn get_messages_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let mut results = Vec::new();
+        // in chunks to stay below the limit of query parameters
+        for chunk in hashes.chunks(500) {
+            let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(
+                "SELECT messages.*, inventory.expires FROM messages \
+                LEFT JOIN inventory ON inventory.hash = messages.hash \
+                WHERE messages.hash IN (",
+            );
+            let mut separated = query.separated(", ");
+            for hash in chunk {
+                separated.push_bind(hash);
+            }
+            separated.push_unseparated(")");
+            let messages: Vec<models::Message> =
+                query.build_query_as().fetch_all(&self.pool).await?;
+            results.extend(messages);
+        }
+        Ok(results)
+    }
+
     async fn get_messages_by_recipient(
         &self,
         address: String,
+        page: Option<Page>,
     ) -> Result<Vec<models::Message>, Box<dyn Error>> {
-        let results = sqlx::query_as("SELECT * FROM messages WHERE recipient = ?")
-            .bind(address)
-            .fetch_all(&self.pool)
-            .await?;
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT messages.*, inventory.expires FROM messages \
+            LEFT JOIN inventory ON inventory.hash = messages.hash \
+            WHERE recipient = ",
+        );
+        builder
+            .push_bind(address)
+            .push(" AND messages.folder IS NULL AND status != ")
+            .push_bind(MessageStatus::Draft.to_string());
+        push_page(&mut builder, page);
+        let results = builder.build_query_as().fetch_all(&self.pool).await?;
         Ok(results)
     }
 
     async fn get_messages_by_sender(
         &self,
         address: String,
+        page: Option<Page>,
     ) -> Result<Vec<models::Message>, Box<dyn Error>> {
-        let results = sqlx::query_as("SELECT * FROM messages WHERE sender = ?")
-            .bind(address)
-            .fetch_all(&self.pool)
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT messages.*, inventory.expires FROM messages \
+            LEFT JOIN inventory ON inventory.hash = messages.hash \
+            WHERE sender = ",
+        );
+        builder
+            .push_bind(address)
+            .push(" AND messages.folder IS NULL AND status != ")
+            .push_bind(MessageStatus::Draft.to_string());
+        push_outbox_statuses(&mut builder, false);
+        push_page(&mut builder, page);
+        let results = builder.build_query_as().fetch_all(&self.pool).await?;
+        Ok(results)
+    }
+
+    async fn get_outbox(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT messages.*, inventory.expires FROM messages \
+            LEFT JOIN inventory ON inventory.hash = messages.hash \
+            WHERE sender = ",
+        );
+        builder
+            .push_bind(address)
+            .push(" AND messages.folder IS NULL");
+        push_outbox_statuses(&mut builder, true);
+        push_page(&mut builder, page);
+        let results = builder.build_query_as().fetch_all(&self.pool).await?;
+        Ok(results)
+    }
+
+    async fn get_drafts(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let mut builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("SELECT * FROM messages WHERE sender = ");
+        builder
+            .push_bind(address)
+            .push(" AND folder IS NULL AND status = ")
+            .push_bind(MessageStatus::Draft.to_string());
+        push_page(&mut builder, page);
+        let results = builder.build_query_as().fetch_all(&self.pool).await?;
+        Ok(results)
+    }
+
+    async fn update_draft(
+        &mut self,
+        hash: String,
+        model: models::Message,
+    ) -> Result<(), Box<dyn Error>> {
+        let (subject, body) = extract_text(&model.data);
+        sqlx::query(
+            "UPDATE messages SET sender = ?, recipient = ?, data = ?, created_at = ? \
+            WHERE hash = ? AND status = ?",
+        )
+        .bind(model.sender)
+        .bind(model.recipient)
+        .bind(model.data)
+        .bind(model.created_at)
+        .bind(hash.clone())
+        .bind(MessageStatus::Draft.to_string())
+        .execute(&self.writer)
+        .await?;
+        sqlx::query("UPDATE messages_fts SET subject = ?, body = ? WHERE hash = ?")
+            .bind(subject)
+            .bind(body)
+            .bind(hash)
+            .execute(&self.writer)
             .await?;
+        Ok(())
+    }
+
+    async fn search_messages(
+        &self,
+        query: String,
+        folder: Folder,
+        address: String,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let query = fts_query(&query);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT messages.*, inventory.expires FROM messages_fts \
+            JOIN messages ON messages.hash = messages_fts.hash \
+            LEFT JOIN inventory ON inventory.hash = messages.hash \
+            WHERE messages_fts MATCH ",
+        );
+        builder.push_bind(query);
+        match folder {
+            Folder::Inbox | Folder::Broadcasts => builder
+                .push(" AND recipient = ")
+                .push_bind(address)
+                .push(" AND messages.folder IS NULL AND status != ")
+                .push_bind(MessageStatus::Draft.to_string()),
+            Folder::Outbox => push_outbox_statuses(
+                builder
+                    .push(" AND sender = ")
+                    .push_bind(address)
+                    .push(" AND messages.folder IS NULL"),
+                true,
+            ),
+            Folder::Sent => push_outbox_statuses(
+                builder
+                    .push(" AND sender = ")
+                    .push_bind(address)
+                    .push(" AND messages.folder IS NULL AND status != ")
+                    .push_bind(MessageStatus::Draft.to_string()),
+                false,
+            ),
+            Folder::Drafts => builder
+                .push(" AND sender = ")
+                .push_bind(address)
+                .push(" AND messages.folder IS NULL AND status = ")
+                .push_bind(MessageStatus::Draft.to_string()),
+            Folder::Trash => builder
+                .push(" AND (sender = ")
+                .push_bind(address.clone())
+                .push(" OR recipient = ")
+                .push_bind(address)
+                .push(") AND messages.folder = ")
+                .push_bind(TRASH_FOLDER),
+            Folder::Quarantine => builder
+                .push(" AND recipient = ")
+                .push_bind(address)
+                .push(" AND messages.folder = ")
+                .push_bind(QUARANTINE_FOLDER),
+        };
+        builder.push(" ORDER BY messages_fts.rank");
+
+        let results = builder.build_query_as().fetch_all(&self.pool).await?;
         Ok(results)
     }
 
+    async fn index_messages(&mut self) -> Result<(), Box<dyn Error>> {
+        let missing: Vec<(String, Vec<u8>)> = sqlx::query_as(
+            "SELECT hash, data FROM messages WHERE hash NOT IN (SELECT hash FROM messages_fts)",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        for (hash, data) in missing {
+            self.index_message(hash, &data).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_trashed_messages(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT messages.*, inventory.expires FROM messages \
+            LEFT JOIN inventory ON inventory.hash = messages.hash \
+            WHERE (sender = ",
+        );
+        builder
+            .push_bind(address.clone())
+            .push(" OR recipient = ")
+            .push_bind(address)
+            .push(") AND messages.folder = ")
+            .push_bind(TRASH_FOLDER);
+        push_page(&mut builder, page);
+        let results = builder.build_query_as().fetch_all(&self.pool).await?;
+        Ok(results)
+    }
+
+    async fn get_quarantined_messages(
+        &self,
+        address: String,
+        page: Option<Page>,
+    ) -> Result<Vec<models::Message>, Box<dyn Error>> {
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT messages.*, inventory.expires FROM messages \
+            LEFT JOIN inventory ON inventory.hash = messages.hash \
+            WHERE recipient = ",
+        );
+        builder
+            .push_bind(address)
+            .push(" AND messages.folder = ")
+            .push_bind(QUARANTINE_FOLDER);
+        push_page(&mut builder, page);
+        let results = builder.build_query_as().fetch_all(&self.pool).await?;
+        Ok(results)
+    }
+
+    async fn move_to_quarantine(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        sqlx::query("UPDATE messages SET folder = ? WHERE hash = ?")
+            .bind(QUARANTINE_FOLDER)
+            .bind(hash)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn move_to_trash(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        sqlx::query("UPDATE messages SET folder = ?, deleted_at = ? WHERE hash = ?")
+            .bind(TRASH_FOLDER)
+            .bind(Utc::now())
+            .bind(hash)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn restore_message(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
+        sqlx::query("UPDATE messages SET folder = NULL, deleted_at = NULL WHERE hash = ?")
+            .bind(hash)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn purge_trash(&mut self, deleted_before: DateTime<Utc>) -> Result<(), Box<dyn Error>> {
+        sqlx::query("DELETE FROM messages WHERE folder = ? AND deleted_at < ?")
+            .bind(TRASH_FOLDER)
+            .bind(deleted_before)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
     async fn save_model(&mut self, model: models::Message) -> Result<(), Box<dyn Error>> {
+        let hash = model.hash.clone();
+        let data = model.data.clone();
         QueryBuilder::new(
-            "INSERT INTO messages (hash, sender, recipient, data, created_at, status, signature) ",
+            "INSERT INTO messages (hash, sender, recipient, data, created_at, status, signature, failure_reason, ack_data, read, send_at, verified, signer_fingerprint, resend_count, resend_at) ",
         )
         .push_values([model], |mut b, model| {
             b.push_bind(model.hash)
@@ -84,11 +390,20 @@ impl MessageRepository for SqliteMessageRepository {
                 .push_bind(model.data)
                 .push_bind(model.created_at)
                 .push_bind(model.status)
-                .push_bind(model.signature);
+                .push_bind(model.signature)
+                .push_bind(model.failure_reason)
+                .push_bind(model.ack_data)
+                .push_bind(model.read)
+                .push_bind(model.send_at)
+                .push_bind(model.verified)
+                .push_bind(model.signer_fingerprint)
+                .push_bind(model.resend_count)
+                .push_bind(model.resend_at);
         })
         .build()
-        .execute(&self.pool)
+        .execute(&self.writer)
         .await?;
+        self.index_message(hash, &data).await?;
         Ok(())
     }
 
@@ -96,15 +411,105 @@ impl MessageRepository for SqliteMessageRepository {
         &mut self,
         hash: String,
         status: MessageStatus,
+    ) -> Result<bool, Box<dyn Error>> {
+        let result =
+            sqlx::query("UPDATE messages SET status = ?, failure_reason = NULL WHERE hash = ?")
+                .bind(status.to_string())
+                .bind(hash)
+                .execute(&self.writer)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn mark_read(&mut self, hash: String, read: bool) -> Result<bool, Box<dyn Error>> {
+        let result = sqlx::query("UPDATE messages SET read = ? WHERE hash = ?")
+            .bind(read)
+            .bind(hash)
+            .execute(&self.writer)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_folder_stats(&self, address: String) -> Result<FolderStats, Box<dyn Error>> {
+        // conditions match the ones of the folder queries above
+        let counts: (i64, i64, i64, i64, i64, i64, i64, i64, i64, i64, i64, i64) = sqlx::query_as(
+            "SELECT \
+            COALESCE(SUM(recipient = ?1 AND folder IS NULL AND status != ?2), 0), \
+            COALESCE(SUM(recipient = ?1 AND folder IS NULL AND status != ?2 AND NOT read), 0), \
+            COALESCE(SUM(sender = ?1 AND folder IS NULL AND status IN (?4, ?5, ?6, ?8)), 0), \
+            COALESCE(SUM(sender = ?1 AND folder IS NULL AND status IN (?4, ?5, ?6, ?8) AND NOT read), 0), \
+            COALESCE(SUM(sender = ?1 AND folder IS NULL AND status NOT IN (?2, ?4, ?5, ?6, ?8)), 0), \
+            COALESCE(SUM(sender = ?1 AND folder IS NULL AND status NOT IN (?2, ?4, ?5, ?6, ?8) AND NOT read), 0), \
+            COALESCE(SUM(sender = ?1 AND folder IS NULL AND status = ?2), 0), \
+            COALESCE(SUM(sender = ?1 AND folder IS NULL AND status = ?2 AND NOT read), 0), \
+            COALESCE(SUM(folder = ?3), 0), \
+            COALESCE(SUM(folder = ?3 AND NOT read), 0), \
+            COALESCE(SUM(recipient = ?1 AND folder = ?7), 0), \
+            COALESCE(SUM(recipient = ?1 AND folder = ?7 AND NOT read), 0) \
+            FROM messages WHERE sender = ?1 OR recipient = ?1",
+        )
+        .bind(address)
+        .bind(MessageStatus::Draft.to_string())
+        .bind(TRASH_FOLDER)
+        .bind(MessageStatus::OUTBOX[0].to_string())
+        .bind(MessageStatus::OUTBOX[1].to_string())
+        .bind(MessageStatus::OUTBOX[2].to_string())
+        .bind(QUARANTINE_FOLDER)
+        .bind(MessageStatus::OUTBOX[3].to_string())
+        .fetch_one(&self.pool)
+        .await?;
+        let counters = |total: i64, unread: i64| FolderCounters {
+            total: total as u64,
+            unread: unread as u64,
+        };
+        Ok(FolderStats {
+            inbox: counters(counts.0, counts.1),
+            outbox: counters(counts.2, counts.3),
+            sent: counters(counts.4, counts.5),
+            drafts: counters(counts.6, counts.7),
+            trash: counters(counts.8, counts.9),
+            quarantine: counters(counts.10, counts.11),
+        })
+    }
+
+    async fn mark_as_failed(&mut self, hash: String, reason: String) -> Result<(), Box<dyn Error>> {
+        sqlx::query("UPDATE messages SET status = ?, failure_reason = ? WHERE hash = ?")
+            .bind(MessageStatus::Failed.to_string())
+            .bind(reason)
+            .bind(hash)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_resend_at(
+        &mut self,
+        hash: String,
+        resend_at: DateTime<Utc>,
     ) -> Result<(), Box<dyn Error>> {
-        sqlx::query("UPDATE messages SET status = ? WHERE hash = ?")
-            .bind(status.to_string())
+        sqlx::query("UPDATE messages SET resend_at = ? WHERE hash = ? AND ack_data IS NOT NULL")
+            .bind(resend_at)
             .bind(hash)
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         Ok(())
     }
 
+    async fn mark_as_delivered(
+        &mut self,
+        ack_data: Vec<u8>,
+    ) -> Result<Option<models::Message>, Box<dyn Error>> {
+        let msg = sqlx::query_as(
+            "UPDATE messages SET status = ? WHERE ack_data = ? AND status = ? RETURNING *",
+        )
+        .bind(MessageStatus::Delivered.to_string())
+        .bind(ack_data)
+        .bind(MessageStatus::Sent.to_string())
+        .fetch_optional(&self.writer)
+        .await?;
+        Ok(msg)
+    }
+
     async fn get_messages_by_status(
         &self,
         status: MessageStatus,
@@ -119,11 +524,77 @@ impl MessageRepository for SqliteMessageRepository {
     async fn remove_message(&mut self, hash: String) -> Result<(), Box<dyn Error>> {
         sqlx::query("DELETE FROM messages WHERE hash = ?")
             .bind(hash)
-            .execute(&self.pool)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_filter_rules(&self) -> Result<Vec<models::FilterRule>, Box<dyn Error>> {
+        let rules = sqlx::query_as("SELECT * FROM filter_rules ORDER BY created_at")
+            .fetch_all(&self.pool)
             .await?;
+        Ok(rules)
+    }
+
+    async fn add_filter_rule(&mut self, rule: models::FilterRule) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "INSERT INTO filter_rules (id, label, enabled, sender, subject_contains, identity,
+            min_size, max_size, move_to, mark_read, delete_message, notify, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(rule.id)
+        .bind(rule.label)
+        .bind(rule.enabled)
+        .bind(rule.sender)
+        .bind(rule.subject_contains)
+        .bind(rule.identity)
+        .bind(rule.min_size)
+        .bind(rule.max_size)
+        .bind(rule.move_to)
+        .bind(rule.mark_read)
+        .bind(rule.delete_message)
+        .bind(rule.notify)
+        .bind(rule.created_at)
+        .execute(&self.writer)
+        .await?;
         Ok(())
     }
 
+    async fn update_filter_rule(
+        &mut self,
+        rule: models::FilterRule,
+    ) -> Result<bool, Box<dyn Error>> {
+        // the rule keeps its place in the order
+        let result = sqlx::query(
+            "UPDATE filter_rules SET label = ?, enabled = ?, sender = ?, subject_contains = ?,
+            identity = ?, min_size = ?, max_size = ?, move_to = ?, mark_read = ?,
+            delete_message = ?, notify = ? WHERE id = ?",
+        )
+        .bind(rule.label)
+        .bind(rule.enabled)
+        .bind(rule.sender)
+        .bind(rule.subject_contains)
+        .bind(rule.identity)
+        .bind(rule.min_size)
+        .bind(rule.max_size)
+        .bind(rule.move_to)
+        .bind(rule.mark_read)
+        .bind(rule.delete_message)
+        .bind(rule.notify)
+        .bind(rule.id)
+        .execute(&self.writer)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_filter_rule(&mut self, id: String) -> Result<bool, Box<dyn Error>> {
+        let result = sqlx::query("DELETE FROM filter_rules WHERE id = ?")
+            .bind(id)
+            .execute(&self.writer)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     async fn update_hash(
         &mut self,
         old_hash: String,
@@ -132,8 +603,42 @@ impl MessageRepository for SqliteMessageRepository {
         sqlx::query("UPDATE messages SET hash = ? WHERE hash = ?")
             .bind(new_hash)
             .bind(old_hash)
-            .execute(&self.pool)
+            .execute(&self.writer)
             .await?;
         Ok(())
     }
 }
+
+/// Only keep messages with (or without if `outbox` is false) [`MessageStatus::OUTBOX`] statuses
+fn push_outbox_statuses<'a, 'args>(
+    builder: &'a mut QueryBuilder<'args, Sqlite>,
+    outbox: bool,
+) -> &'a mut QueryBuilder<'args, Sqlite> {
+    builder.push(if outbox {
+        " AND status IN ("
+    } else {
+        " AND status NOT IN ("
+    });
+    let mut separated = builder.separated(", ");
+    for status in MessageStatus::OUTBOX {
+        separated.push_bind(status.to_string());
+    }
+    separated.push_unseparated(")");
+    builder
+}
+
+/// Order messages from the newest one and only keep the page of them, if it's given
+fn push_page<'a, 'args>(
+    builder: &'a mut QueryBuilder<'args, Sqlite>,
+    page: Option<Page>,
+) -> &'a mut QueryBuilder<'args, Sqlite> {
+    builder.push(" ORDER BY messages.created_at DESC, messages.hash");
+    if let Some(page) = page {
+        builder
+            .push(" LIMIT ")
+            .push_bind(page.limit as i64)
+            .push(" OFFSET ")
+            .push_bind(page.offset as i64);
+    }
+    builder
+}
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE inventory DROP COLUMN nonce_trials_per_byte;
+ALTER TABLE inventory DROP COLUMN extra_bytes;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE inventory ADD nonce_trials_per_byte INTEGER NOT NULL DEFAULT 1000;
+ALTER TABLE inventory ADD extra_bytes INTEGER NOT NULL DEFAULT 1000;
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE messages DROP COLUMN failure_reason;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE messages ADD folder TEXT;
+ALTER TABLE messages ADD deleted_at TIMESTAMP;
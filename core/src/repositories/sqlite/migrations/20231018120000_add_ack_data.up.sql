@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE messages ADD ack_data BLOB;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE addresses DROP COLUMN extra_bytes;
+ALTER TABLE addresses DROP COLUMN nonce_trials_per_byte;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE addresses ADD nonce_trials_per_byte INTEGER NOT NULL DEFAULT 1000;
+ALTER TABLE addresses ADD extra_bytes INTEGER NOT NULL DEFAULT 1000;
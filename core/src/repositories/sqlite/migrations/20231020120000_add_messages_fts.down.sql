@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TRIGGER messages_fts_update_hash;
+DROP TRIGGER messages_fts_delete;
+DROP TABLE messages_fts;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- Subject and body are extracted from MIME data by the repository, so rows are inserted by it,
+-- while deletions and hash updates are kept in sync by triggers
+CREATE VIRTUAL TABLE messages_fts USING fts5(hash UNINDEXED, subject, body);
+
+CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+    DELETE FROM messages_fts WHERE hash = old.hash;
+END;
+
+CREATE TRIGGER messages_fts_update_hash AFTER UPDATE OF hash ON messages BEGIN
+    UPDATE messages_fts SET hash = new.hash WHERE hash = old.hash;
+END;
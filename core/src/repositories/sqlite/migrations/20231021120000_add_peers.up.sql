@@ -0,0 +1,10 @@
+-- Add up migration script here
+CREATE TABLE peers (
+    multiaddr TEXT PRIMARY KEY NOT NULL,
+    peer_id TEXT NOT NULL,
+    last_seen TIMESTAMP NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 0,
+    next_attempt TIMESTAMP
+);
+
+CREATE INDEX peers_peer_id ON peers (peer_id);
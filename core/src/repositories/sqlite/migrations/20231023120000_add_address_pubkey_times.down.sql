@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE addresses DROP COLUMN pubkey_received_at;
+ALTER TABLE addresses DROP COLUMN pubkey_published_at;
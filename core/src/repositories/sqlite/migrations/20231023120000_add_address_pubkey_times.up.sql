@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE addresses ADD pubkey_published_at TIMESTAMP;
+ALTER TABLE addresses ADD pubkey_received_at TIMESTAMP;
+-- keys cached before the timestamps were tracked expire as if they were received now
+UPDATE addresses SET pubkey_received_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now') WHERE public_signing_key IS NOT NULL AND private_signing_key IS NULL;
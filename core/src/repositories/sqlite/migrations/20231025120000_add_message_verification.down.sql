@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE messages DROP COLUMN signer_fingerprint;
+ALTER TABLE messages DROP COLUMN verified;
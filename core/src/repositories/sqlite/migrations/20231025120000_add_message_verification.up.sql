@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE messages ADD verified BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE messages ADD signer_fingerprint TEXT;
+-- messages with signatures not matching the sender were already saved as Unverified
+UPDATE messages SET verified = 1 WHERE status = 'Received';
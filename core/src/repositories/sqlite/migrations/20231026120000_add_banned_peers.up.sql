@@ -0,0 +1,6 @@
+-- Add up migration script here
+CREATE TABLE banned_peers (
+    target TEXT PRIMARY KEY NOT NULL,
+    reason TEXT,
+    banned_at TIMESTAMP NOT NULL
+);
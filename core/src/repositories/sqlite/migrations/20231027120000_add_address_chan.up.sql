@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE addresses ADD chan BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE messages DROP COLUMN resend_at;
+ALTER TABLE messages DROP COLUMN resend_count;
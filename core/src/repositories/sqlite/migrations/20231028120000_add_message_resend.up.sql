@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE messages ADD resend_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE messages ADD resend_at TIMESTAMP;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+CREATE TABLE device_keys (
+    address TEXT NOT NULL,
+    public_key BLOB NOT NULL,
+    private_key BLOB,
+    label TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (address, public_key)
+);
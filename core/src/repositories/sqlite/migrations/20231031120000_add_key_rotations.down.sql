@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE key_rotations;
+ALTER TABLE addresses DROP COLUMN keys_rotated_at;
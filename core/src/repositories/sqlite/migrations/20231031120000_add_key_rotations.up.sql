@@ -0,0 +1,12 @@
+-- Add up migration script here
+ALTER TABLE addresses ADD keys_rotated_at TIMESTAMP;
+CREATE TABLE key_rotations (
+    address TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    public_signing_key BLOB NOT NULL,
+    public_encryption_key BLOB NOT NULL,
+    private_encryption_key BLOB,
+    signature BLOB NOT NULL,
+    rotated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (address, position)
+);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE addresses DROP COLUMN quarantine_strangers;
+ALTER TABLE addresses DROP COLUMN stranger_extra_bytes;
+ALTER TABLE addresses DROP COLUMN stranger_nonce_trials_per_byte;
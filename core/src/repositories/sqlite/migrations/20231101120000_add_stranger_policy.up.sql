@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE addresses ADD stranger_nonce_trials_per_byte INTEGER NOT NULL DEFAULT 1000;
+ALTER TABLE addresses ADD stranger_extra_bytes INTEGER NOT NULL DEFAULT 1000;
+ALTER TABLE addresses ADD quarantine_strangers BOOLEAN NOT NULL DEFAULT 0;
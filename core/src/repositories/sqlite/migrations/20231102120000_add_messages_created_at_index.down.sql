@@ -0,0 +1,2 @@
+-- Add down migration script here
+DROP INDEX messages_created_at;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+CREATE INDEX messages_created_at ON messages (created_at);
@@ -0,0 +1,5 @@
+-- Add up migration script here
+CREATE TABLE getpubkey_responses (
+    tag TEXT PRIMARY KEY NOT NULL,
+    responded_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Add up migration script here
+CREATE TABLE subscriptions (
+    address TEXT PRIMARY KEY NOT NULL,
+    label TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE auto_reply_senders;
+DROP TABLE auto_replies;
@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE auto_replies (
+    address TEXT PRIMARY KEY NOT NULL,
+    subject_prefix TEXT NOT NULL DEFAULT '',
+    body TEXT NOT NULL,
+    interval_hours INTEGER NOT NULL
+);
+
+CREATE TABLE auto_reply_senders (
+    address TEXT NOT NULL,
+    sender TEXT NOT NULL,
+    replied_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (address, sender)
+);
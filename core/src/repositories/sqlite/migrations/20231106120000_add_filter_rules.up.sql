@@ -0,0 +1,16 @@
+-- Add up migration script here
+CREATE TABLE filter_rules (
+    id TEXT PRIMARY KEY NOT NULL,
+    label TEXT NOT NULL DEFAULT '',
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    sender TEXT,
+    subject_contains TEXT,
+    identity TEXT,
+    min_size INTEGER,
+    max_size INTEGER,
+    move_to TEXT,
+    mark_read BOOLEAN NOT NULL DEFAULT FALSE,
+    delete_message BOOLEAN NOT NULL DEFAULT FALSE,
+    notify BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL
+);
@@ -1,6 +1,10 @@
 use chrono::{DateTime, Utc};
 use strum::{Display, EnumString};
 
+use crate::network;
+
+/// Row of the SQLite storage, other storages keep the network types
+#[cfg(feature = "sqlite")]
 #[derive(sqlx::FromRow, Debug, PartialEq)]
 pub(crate) struct Address {
     pub address: String,
@@ -10,8 +14,19 @@ pub(crate) struct Address {
     pub private_signing_key: Option<Vec<u8>>,
     pub private_encryption_key: Option<Vec<u8>>,
     pub label: Option<String>,
+    pub nonce_trials_per_byte: i32,
+    pub extra_bytes: i32,
+    pub pubkey_published_at: Option<DateTime<Utc>>,
+    pub pubkey_received_at: Option<DateTime<Utc>>,
+    pub chan: bool,
+    pub keys_rotated_at: Option<DateTime<Utc>>,
+    pub stranger_nonce_trials_per_byte: i32,
+    pub stranger_extra_bytes: i32,
+    pub quarantine_strangers: bool,
 }
 
+/// Row of the SQLite storage, other storages keep the network types
+#[cfg(feature = "sqlite")]
 #[derive(sqlx::FromRow, Debug, PartialEq, Clone)]
 pub(crate) struct Object {
     pub hash: String,
@@ -20,18 +35,48 @@ pub(crate) struct Object {
     pub data: Vec<u8>,
     pub expires: DateTime<Utc>,
     pub signature: Vec<u8>,
+    pub nonce_trials_per_byte: i32,
+    pub extra_bytes: i32,
 }
 
-#[derive(EnumString, Display)]
+#[derive(EnumString, Display, Clone, Copy)]
 pub enum MessageStatus {
     WaitingForPubkey,
     WaitingForPOW,
+    /// PoW of the message is done, but there are no peers to broadcast it to yet
+    WaitingForPeers,
     Sent,
     Received,
+    /// Received message whose signature doesn't match its sender, so it might be
+    /// spoofed, see `failure_reason` of the message
+    Unverified,
+    /// Recipient has acknowledged the message
+    Delivered,
+    /// Message can't be delivered, see `failure_reason` of the message
+    Failed,
+    /// Unsent message saved by the user for later editing
+    Draft,
+    /// Sending was cancelled by the user before the message was broadcast, it isn't
+    /// retried
+    Cancelled,
+    /// Message waiting to be sent at `send_at`
+    Scheduled,
     Unknown,
 }
 
-#[derive(sqlx::FromRow, Debug, PartialEq, Clone)]
+impl MessageStatus {
+    /// Outgoing messages which haven't been sent out yet, they're kept in Outbox
+    /// instead of Sent
+    pub const OUTBOX: [MessageStatus; 4] = [
+        MessageStatus::WaitingForPubkey,
+        MessageStatus::WaitingForPOW,
+        MessageStatus::WaitingForPeers,
+        MessageStatus::Scheduled,
+    ];
+}
+
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Message {
     pub hash: String,
     pub sender: String,
@@ -40,4 +85,174 @@ pub struct Message {
     pub created_at: DateTime<Utc>,
     pub status: String,
     pub signature: Vec<u8>,
+    pub failure_reason: Option<String>,
+    /// Folder the message was moved to by the user (e.g. Trash), `None` for Inbox/Sent
+    pub folder: Option<String>,
+    /// Time when the message was moved to Trash
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Random data the recipient sends back to acknowledge the delivery (for outgoing messages)
+    pub ack_data: Option<Vec<u8>>,
+    /// Whether the user has seen the message, outgoing messages are always read
+    pub read: bool,
+    /// Time the message is scheduled to be sent at
+    pub send_at: Option<DateTime<Utc>>,
+    /// Whether the signature of the received message was made by its sender
+    pub verified: bool,
+    /// Fingerprint of the public key the received message was signed with
+    pub signer_fingerprint: Option<String>,
+    /// Number of times the unacknowledged message was sent again
+    pub resend_count: i32,
+    /// Time the sent message is sent again unless it's acknowledged before,
+    /// i.e. expiration time of its object
+    pub resend_at: Option<DateTime<Utc>>,
+    /// Expiration time of the message object (if it's still in the inventory)
+    #[cfg_attr(feature = "sqlite", sqlx(default))]
+    pub expires: Option<DateTime<Utc>>,
+    /// Label of the sender if it's one of our identities or a contact, resolved
+    /// when messages are listed
+    #[cfg_attr(feature = "sqlite", sqlx(default))]
+    pub sender_label: Option<String>,
+    /// Label of the recipient, the same as [`Message::sender_label`]
+    #[cfg_attr(feature = "sqlite", sqlx(default))]
+    pub recipient_label: Option<String>,
+}
+
+impl Message {
+    /// Sender as it's shown to the user, e.g. `Alice (BM-...)`
+    pub fn sender_display_name(&self) -> String {
+        display_name(&self.sender, self.sender_label.as_deref())
+    }
+
+    /// Recipient as it's shown to the user, e.g. `Alice (BM-...)`
+    pub fn recipient_display_name(&self) -> String {
+        display_name(&self.recipient, self.recipient_label.as_deref())
+    }
+}
+
+fn display_name(address: &str, label: Option<&str>) -> String {
+    match label {
+        Some(label) if !label.is_empty() => format!("{} ({})", label, address),
+        _ => address.to_string(),
+    }
+}
+
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct Peer {
+    /// Address the peer listens on, including its peer id
+    pub multiaddr: String,
+    pub peer_id: String,
+    pub last_seen: DateTime<Utc>,
+    /// Number of failed dials since the peer was seen last time
+    pub failures: u32,
+    /// Peer isn't dialed before this time after failures
+    pub next_attempt: Option<DateTime<Utc>>,
+}
+
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct BannedPeer {
+    /// Peer id or multiaddr, address bans also apply to addresses it's a prefix of
+    pub target: String,
+    pub reason: Option<String>,
+    pub banned_at: DateTime<Utc>,
+}
+
+/// Encryption key of a device receiving copies of messages sent to the identity
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeviceKey {
+    /// Identity the device receives messages for
+    pub address: String,
+    /// Serialized public encryption key of the device
+    pub public_key: Vec<u8>,
+    /// Private part of the key, only kept on the device itself
+    pub private_key: Option<Vec<u8>>,
+    pub label: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Keys the address had before they were rotated, along with the signature of the keys
+/// which replaced them
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyRotation {
+    pub address: String,
+    /// Number of the rotation, the original keys of the address are the first
+    pub position: i64,
+    pub public_signing_key: Vec<u8>,
+    pub public_encryption_key: Vec<u8>,
+    /// Kept by own identities to decrypt messages sent to the former keys for a while
+    pub private_encryption_key: Option<Vec<u8>>,
+    /// Signature of the next keys made with this signing key
+    pub signature: Vec<u8>,
+    pub rotated_at: DateTime<Utc>,
+}
+
+/// Address whose broadcasts are received, they're listed in its own feed
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct Subscription {
+    pub address: String,
+    pub label: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Reply sent automatically to messages received by the identity, e.g. while
+/// its owner is away
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct AutoReply {
+    pub address: String,
+    /// Prepended to the subject of the received message, e.g. `Re: `
+    pub subject_prefix: String,
+    pub body: String,
+    /// Each sender gets at most one reply within this many hours
+    pub interval_hours: i64,
+}
+
+/// Rule applied to messages once they're received. All of the conditions which are
+/// set have to match, then all of the actions are performed.
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct FilterRule {
+    pub id: String,
+    pub label: String,
+    /// Disabled rules are kept, but not applied
+    pub enabled: bool,
+    pub sender: Option<String>,
+    /// Matched case-insensitively
+    pub subject_contains: Option<String>,
+    /// Identity or subscription the message is received by
+    pub identity: Option<String>,
+    /// Min size of the message data in bytes
+    pub min_size: Option<i64>,
+    /// Max size of the message data in bytes
+    pub max_size: Option<i64>,
+    /// Folder the message is moved to, `Trash` or `Quarantine`
+    pub move_to: Option<String>,
+    pub mark_read: bool,
+    /// Remove the message for good, the other actions are skipped then
+    pub delete_message: bool,
+    /// Notify about the message even if notifications of its identity are muted
+    pub notify: bool,
+    /// Rules are applied from the oldest one
+    pub created_at: DateTime<Utc>,
+}
+
+impl FilterRule {
+    /// Whether the received message matches the conditions of the rule
+    pub fn matches(&self, msg: &Message, subject: &str) -> bool {
+        let size = msg.data.len() as i64;
+        self.sender.as_ref().is_none_or(|s| {
+            network::address::Address::canonical_string_repr(s)
+                == network::address::Address::canonical_string_repr(&msg.sender)
+        }) && self.identity.as_ref().is_none_or(|i| *i == msg.recipient)
+            && self
+                .subject_contains
+                .as_ref()
+                .is_none_or(|s| subject.to_lowercase().contains(&s.to_lowercase()))
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+    }
 }
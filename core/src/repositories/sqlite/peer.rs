@@ -0,0 +1,146 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+
+use crate::repositories::peer::PeerRepository;
+
+use super::models;
+
+#[derive(Clone)]
+pub struct SqlitePeerRepository {
+    pool: SqlitePool,
+    /// Single connection writing to the database, see [`super::storage::SqliteStorage`]
+    writer: SqlitePool,
+}
+
+impl SqlitePeerRepository {
+    pub fn new(conn_pool: SqlitePool, writer: SqlitePool) -> Self {
+        SqlitePeerRepository {
+            pool: conn_pool,
+            writer,
+        }
+    }
+}
+
+#[async_trait]
+impl PeerRepository for SqlitePeerRepository {
+    async fn store_seen(
+        &mut self,
+        peer_id: String,
+        multiaddr: String,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = Utc::now();
+        sqlx::query(
+            "INSERT INTO peers (multiaddr, peer_id, last_seen) VALUES (?, ?, ?)
+            ON CONFLICT (multiaddr) DO UPDATE SET peer_id = excluded.peer_id, last_seen = excluded.last_seen",
+        )
+        .bind(multiaddr)
+        .bind(&peer_id)
+        .bind(now)
+        .execute(&self.writer)
+        .await?;
+        sqlx::query(
+            "UPDATE peers SET failures = 0, next_attempt = NULL, last_seen = ? WHERE peer_id = ?",
+        )
+        .bind(now)
+        .bind(peer_id)
+        .execute(&self.writer)
+        .await?;
+        Ok(())
+    }
+
+    async fn store_exchanged(
+        &mut self,
+        peer_id: String,
+        multiaddr: String,
+        last_seen: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "INSERT INTO peers (multiaddr, peer_id, last_seen) VALUES (?, ?, ?)
+            ON CONFLICT (multiaddr) DO NOTHING",
+        )
+        .bind(multiaddr)
+        .bind(peer_id)
+        .bind(last_seen)
+        .execute(&self.writer)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_dialable(&self) -> Result<Vec<models::Peer>, Box<dyn Error>> {
+        let peers = sqlx::query_as(
+            "SELECT * FROM peers WHERE next_attempt IS NULL OR next_attempt <= ?
+            ORDER BY last_seen DESC",
+        )
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(peers)
+    }
+
+    async fn record_failure(&mut self, peer_id: String) -> Result<Option<u32>, Box<dyn Error>> {
+        let failures: Option<u32> = sqlx::query_scalar(
+            "UPDATE peers SET failures = failures + 1 WHERE peer_id = ? RETURNING failures",
+        )
+        .bind(peer_id)
+        .fetch_optional(&self.writer)
+        .await?;
+        Ok(failures)
+    }
+
+    async fn postpone(
+        &mut self,
+        peer_id: String,
+        until: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        sqlx::query("UPDATE peers SET next_attempt = ? WHERE peer_id = ?")
+            .bind(until)
+            .bind(peer_id)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn cleanup(&mut self, seen_before: DateTime<Utc>) -> Result<usize, Box<dyn Error>> {
+        let result = sqlx::query("DELETE FROM peers WHERE last_seen < ?")
+            .bind(seen_before)
+            .execute(&self.writer)
+            .await?;
+        Ok(result.rows_affected() as usize)
+    }
+
+    async fn ban(&mut self, target: String, reason: Option<String>) -> Result<(), Box<dyn Error>> {
+        sqlx::query(
+            "INSERT INTO banned_peers (target, reason, banned_at) VALUES (?, ?, ?)
+            ON CONFLICT (target) DO UPDATE SET reason = excluded.reason, banned_at = excluded.banned_at",
+        )
+        .bind(&target)
+        .bind(reason)
+        .bind(Utc::now())
+        .execute(&self.writer)
+        .await?;
+        sqlx::query("DELETE FROM peers WHERE peer_id = ? OR multiaddr = ?")
+            .bind(&target)
+            .bind(&target)
+            .execute(&self.writer)
+            .await?;
+        Ok(())
+    }
+
+    async fn unban(&mut self, target: String) -> Result<bool, Box<dyn Error>> {
+        let result = sqlx::query("DELETE FROM banned_peers WHERE target = ?")
+            .bind(target)
+            .execute(&self.writer)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_banned(&self) -> Result<Vec<models::BannedPeer>, Box<dyn Error>> {
+        let banned = sqlx::query_as("SELECT * FROM banned_peers ORDER BY banned_at DESC")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(banned)
+    }
+}
@@ -0,0 +1,80 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use sqlx::SqlitePool;
+
+use crate::repositories::{
+    address::AddressRepositorySync, inventory::InventoryRepositorySync,
+    message::MessageRepositorySync, peer::PeerRepositorySync, storage::Storage,
+};
+
+use super::{
+    address::SqliteAddressRepository, inventory::SqliteInventoryRepository,
+    message::SqliteMessageRepository, peer::SqlitePeerRepository,
+};
+
+/// Storage in SQLite database, the pool is expected to be already migrated.
+///
+/// Reads go through the pool, while all writes are queued for the single `writer`
+/// connection. Concurrent writers would fail with "database is locked" once SQLite
+/// gives up waiting for each other, e.g. during heavy object ingest.
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    writer: SqlitePool,
+}
+
+impl SqliteStorage {
+    pub fn new(pool: SqlitePool, writer: SqlitePool) -> Self {
+        SqliteStorage { pool, writer }
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    fn address_repo(&self) -> Box<AddressRepositorySync> {
+        Box::new(SqliteAddressRepository::new(
+            self.pool.clone(),
+            self.writer.clone(),
+        ))
+    }
+
+    fn inventory_repo(&self) -> Box<InventoryRepositorySync> {
+        Box::new(SqliteInventoryRepository::new(
+            self.pool.clone(),
+            self.writer.clone(),
+        ))
+    }
+
+    fn message_repo(&self) -> Box<MessageRepositorySync> {
+        Box::new(SqliteMessageRepository::new(
+            self.pool.clone(),
+            self.writer.clone(),
+        ))
+    }
+
+    fn peer_repo(&self) -> Box<PeerRepositorySync> {
+        Box::new(SqlitePeerRepository::new(
+            self.pool.clone(),
+            self.writer.clone(),
+        ))
+    }
+
+    async fn close(&self) {
+        self.writer.close().await;
+        self.pool.close().await;
+    }
+
+    async fn vacuum(&self) -> Result<(), Box<dyn Error>> {
+        sqlx::query("VACUUM").execute(&self.writer).await?;
+        Ok(())
+    }
+
+    async fn size(&self) -> Result<u64, Box<dyn Error>> {
+        let (size,): (i64,) = sqlx::query_as(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(size as u64)
+    }
+}
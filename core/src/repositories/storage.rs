@@ -0,0 +1,29 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+
+use super::{
+    address::AddressRepositorySync, inventory::InventoryRepositorySync,
+    message::MessageRepositorySync, peer::PeerRepositorySync,
+};
+
+/// Backend the node keeps its data in, providing repositories which share it
+#[async_trait]
+pub trait Storage: Send + Sync {
+    fn address_repo(&self) -> Box<AddressRepositorySync>;
+
+    fn inventory_repo(&self) -> Box<InventoryRepositorySync>;
+
+    fn message_repo(&self) -> Box<MessageRepositorySync>;
+
+    fn peer_repo(&self) -> Box<PeerRepositorySync>;
+
+    /// Release the storage when the node is shut down
+    async fn close(&self);
+
+    /// Give space freed by removed data back to the system
+    async fn vacuum(&self) -> Result<(), Box<dyn Error>>;
+
+    /// Size of the stored data in bytes, 0 if it isn't persisted
+    async fn size(&self) -> Result<u64, Box<dyn Error>>;
+}
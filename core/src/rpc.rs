@@ -0,0 +1,695 @@
+//! Minimal JSON-RPC 2.0 over HTTP server, so that headless nodes can be scripted
+//! and used as a daemon by thin clients.
+//!
+//! Every request is a `POST` with a JSON-RPC object in the body, parameters are
+//! passed by name, e.g.
+//! `{"jsonrpc": "2.0", "id": 1, "method": "get_messages", "params": {"address": "...", "folder": "inbox"}}`.
+//!
+//! Requests have to carry the API token (`Authorization: Bearer <token>`) and
+//! `Content-Type: application/json`. Requests with `Origin` header are rejected,
+//! so that web pages opened in a browser on the same machine can't use the API.
+
+use std::{io, time::Duration};
+
+use async_std::{
+    future,
+    io::{prelude::BufReadExt, BufReader, ReadExt, WriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    task,
+};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use rand::distributions::{Alphanumeric, DistString};
+use serde_json::{json, Value};
+
+use crate::{
+    network::{
+        address::Address,
+        node::{
+            client::{parse_recipients, ClientError, NodeClient},
+            worker::Folder,
+        },
+    },
+    repositories::{message::FolderCounters, sqlite::models},
+};
+
+/// Maximum size of the request body
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+/// Maximum length of the request line and of each header line
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+/// Maximum number of headers of the request
+const MAX_HEADERS: usize = 64;
+/// How long the client may take to send the whole request, so that slow clients
+/// can't hold connections open
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Length of generated API tokens
+const TOKEN_LENGTH: usize = 32;
+/// File in the data dir the generated API token is written to, so that local
+/// clients can read it
+pub const TOKEN_FILE: &str = "rpc_token";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// Node is busy or stuck, the request might succeed later
+const NODE_UNAVAILABLE: i64 = -32000;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl From<ClientError> for RpcError {
+    fn from(e: ClientError) -> Self {
+        match e {
+            ClientError::Node(message) => Self::new(INTERNAL_ERROR, message),
+            e => Self::new(NODE_UNAVAILABLE, e.to_string()),
+        }
+    }
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Random token for the API, e.g. for a single run of the server
+pub fn generate_token() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), TOKEN_LENGTH)
+}
+
+/// Accept API connections on the given address until the listener fails. Only
+/// requests with the `token` are handled.
+pub async fn serve(
+    addr: impl ToSocketAddrs,
+    client: NodeClient,
+    token: String,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("RPC server is listening on {}", listener.local_addr()?);
+
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = stream?;
+        let client = client.clone();
+        let token = token.clone();
+        task::spawn(async move {
+            if let Err(e) = handle_connection(stream, client, &token).await {
+                log::debug!("RPC connection failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Headers of the request which matter for the API
+#[derive(Default)]
+struct Headers {
+    content_length: usize,
+    content_type: Option<String>,
+    authorization: Option<String>,
+    has_origin: bool,
+}
+
+impl Headers {
+    fn is_json(&self) -> bool {
+        self.content_type.as_deref().is_some_and(|t| {
+            // parameters like charset are allowed
+            let media_type = t.split(';').next().unwrap_or_default().trim();
+            media_type.eq_ignore_ascii_case("application/json")
+        })
+    }
+
+    fn is_authorized(&self, token: &str) -> bool {
+        self.authorization
+            .as_deref()
+            .and_then(|a| a.strip_prefix("Bearer "))
+            .is_some_and(|t| tokens_match(t.trim().as_bytes(), token.as_bytes()))
+    }
+}
+
+/// Compare tokens in constant time, so that they can't be guessed byte by byte
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    client: NodeClient,
+    token: &str,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.clone());
+    let request = future::timeout(REQUEST_TIMEOUT, read_request(&mut reader, token)).await;
+    let (status, body) = match request {
+        Ok(Ok(Ok(body))) => ("200 OK", handle_request(&body, client).await.to_string()),
+        Ok(Ok(Err(status))) => (status, String::new()),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => ("408 Request Timeout", String::new()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+/// Read the request, returns its body or the status it's rejected with. Limits are
+/// checked before the token, so that clients without it can't exhaust memory.
+async fn read_request(
+    reader: &mut BufReader<TcpStream>,
+    token: &str,
+) -> io::Result<Result<Vec<u8>, &'static str>> {
+    let request_line = match read_line(reader).await? {
+        Some(line) => line,
+        None => return Ok(Err("400 Bad Request")),
+    };
+
+    let mut headers = Headers::default();
+    let mut count = 0;
+    loop {
+        let line = match read_line(reader).await? {
+            Some(line) => line,
+            None => return Ok(Err("400 Bad Request")),
+        };
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        count += 1;
+        if count > MAX_HEADERS {
+            return Ok(Err("400 Bad Request"));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                match value.parse() {
+                    Ok(length) => headers.content_length = length,
+                    Err(_) => return Ok(Err("400 Bad Request")),
+                }
+            } else if name.eq_ignore_ascii_case("content-type") {
+                headers.content_type = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("authorization") {
+                headers.authorization = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("origin") {
+                headers.has_origin = true;
+            }
+        }
+    }
+
+    let content_length = headers.content_length;
+    if !request_line.starts_with("POST ") {
+        Ok(Err("405 Method Not Allowed"))
+    } else if content_length > MAX_REQUEST_SIZE {
+        Ok(Err("413 Payload Too Large"))
+    } else if headers.has_origin {
+        // browsers add it to cross-origin requests, API clients don't need it
+        Ok(Err("403 Forbidden"))
+    } else if !headers.is_authorized(token) {
+        Ok(Err("401 Unauthorized"))
+    } else if !headers.is_json() {
+        Ok(Err("415 Unsupported Media Type"))
+    } else {
+        let mut body = Vec::with_capacity(content_length);
+        reader
+            .take(content_length as u64)
+            .read_to_end(&mut body)
+            .await?;
+        if body.len() < content_length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Ok(body))
+    }
+}
+
+/// Read a line of the request head, `None` if it's longer than [`MAX_LINE_LENGTH`].
+/// The line is empty once the client closed the connection.
+async fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    reader
+        .take(MAX_LINE_LENGTH as u64)
+        .read_line(&mut line)
+        .await?;
+    if line.len() == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+async fn handle_request(body: &[u8], mut client: NodeClient) -> Value {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(e) => return error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string())),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(m) => m,
+        None => return error_response(id, RpcError::new(INVALID_REQUEST, "method is missing")),
+    };
+    let params = request.get("params").cloned().unwrap_or(json!({}));
+
+    match call(&mut client, method, &params).await {
+        Ok(result) => json!({"jsonrpc": "2.0", "result": result, "id": id}),
+        Err(e) => error_response(id, e),
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": {"code": error.code, "message": error.message},
+        "id": id,
+    })
+}
+
+async fn call(client: &mut NodeClient, method: &str, params: &Value) -> Result<Value, RpcError> {
+    let result = match method {
+        "get_network_status" => {
+            let peer_id = client.get_peer_id().await?;
+            let listen_addresses = client.get_listeners().await?;
+            let connected_peers = client.get_connected_peers().await?;
+            let traffic = client.get_traffic_stats().await?;
+            let queue = client.command_queue_stats();
+            let inventory = client
+                .get_inventory_stats()
+                .await
+                .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?;
+            json!({
+                "peer_id": peer_id.to_string(),
+                "listen_addresses": listen_addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+                "connected_peers": connected_peers.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+                "bytes_received": traffic.bytes_received,
+                "bytes_sent": traffic.bytes_sent,
+                "dropped_messages": traffic.dropped_messages,
+                "queued_commands": queue.queued,
+                "max_queued_commands": queue.max_queued,
+                "rejected_commands": queue.rejected,
+                "inventory_objects": inventory.objects,
+                "inventory_bytes": inventory.bytes,
+                "max_inventory_bytes": inventory.max_bytes,
+                "evicted_objects": inventory.evicted,
+            })
+        }
+        "get_identities" => {
+            let identities = client.get_own_identities().await?;
+            Value::Array(identities.iter().map(address_to_json).collect())
+        }
+        "generate_identity" => {
+            let address = client
+                .generate_new_identity(str_param(params, "label")?)
+                .await
+                .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?;
+            json!(address)
+        }
+        "generate_deterministic_identity" => {
+            let address = client
+                .generate_deterministic_identity(
+                    str_param(params, "passphrase")?,
+                    str_param(params, "label")?,
+                )
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            json!(address)
+        }
+        "join_chan" => {
+            let address = params
+                .get("address")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            let address = client
+                .join_chan(str_param(params, "name")?, address)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            json!(address)
+        }
+        "export_identities" => {
+            let data = client
+                .export_identities(addresses_param(params)?)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            json!(data)
+        }
+        "import_identities" => {
+            let addresses = client
+                .import_identities(str_param(params, "data")?)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            json!(addresses)
+        }
+        "create_device_key" => {
+            let code = client
+                .create_device_key(str_param(params, "address")?)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            json!(code)
+        }
+        "pair_device" => {
+            let label = params
+                .get("label")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            client
+                .pair_device(str_param(params, "code")?, label)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            Value::Null
+        }
+        "get_device_keys" => {
+            let keys = client
+                .get_device_keys(str_param(params, "address")?)
+                .await
+                .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?;
+            Value::Array(
+                keys.iter()
+                    .map(|k| {
+                        json!({
+                            "public_key": bs58::encode(&k.public_key).into_string(),
+                            "label": k.label,
+                            "this_device": k.private_key.is_some(),
+                            "created_at": k.created_at.to_rfc3339(),
+                        })
+                    })
+                    .collect(),
+            )
+        }
+        "remove_device_key" => {
+            client
+                .remove_device_key(
+                    str_param(params, "address")?,
+                    str_param(params, "public_key")?,
+                )
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            Value::Null
+        }
+        "rename_identity" => {
+            client
+                .rename_identity(str_param(params, "address")?, str_param(params, "label")?)
+                .await?;
+            Value::Null
+        }
+        "set_identity_pow_difficulty" => {
+            client
+                .set_identity_pow_difficulty(
+                    str_param(params, "address")?,
+                    int_param(params, "nonce_trials_per_byte")?,
+                    int_param(params, "extra_bytes")?,
+                )
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            Value::Null
+        }
+        "set_stranger_policy" => {
+            let quarantine = params
+                .get("quarantine")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            client
+                .set_stranger_policy(
+                    str_param(params, "address")?,
+                    int_param(params, "nonce_trials_per_byte")?,
+                    int_param(params, "extra_bytes")?,
+                    quarantine,
+                )
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            Value::Null
+        }
+        "delete_identity" => {
+            client
+                .delete_identity(str_param(params, "address")?)
+                .await?;
+            Value::Null
+        }
+        "delete_identities" => {
+            client
+                .delete_identities(addresses_param(params)?)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            Value::Null
+        }
+        "rotate_keys" => {
+            client
+                .rotate_keys(str_param(params, "address")?)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            Value::Null
+        }
+        "get_contacts" => {
+            let contacts = client.get_contacts().await?;
+            Value::Array(contacts.iter().map(address_to_json).collect())
+        }
+        "resolve_contact" => {
+            let contact = client
+                .resolve_contact(str_param(params, "address")?)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            contact.as_ref().map_or(Value::Null, address_to_json)
+        }
+        "add_contact" => {
+            client
+                .add_contact(str_param(params, "address")?, str_param(params, "label")?)
+                .await
+                .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?;
+            Value::Null
+        }
+        "send_message" => {
+            let from = identity_param(client, params, "from").await?;
+            let to = recipients_param(params)?;
+            let (title, body) = (str_param(params, "title")?, str_param(params, "body")?);
+            // messages are sent right away unless `send_at` is given
+            let hashes = match params.get("send_at") {
+                Some(_) => client
+                    .schedule_message(None, from, to, title, body, time_param(params, "send_at")?)
+                    .await
+                    .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?,
+                None => client
+                    .send_message(from, to, title, body)
+                    .await
+                    .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?,
+            };
+            Value::Array(hashes.into_iter().map(Value::String).collect())
+        }
+        "get_messages" => {
+            let (address, folder) = (str_param(params, "address")?, folder_param(params)?);
+            // the whole folder is returned unless `limit` is given
+            let messages = match params.get("limit") {
+                Some(_) => {
+                    let offset = match params.get("offset") {
+                        Some(_) => count_param(params, "offset")?,
+                        None => 0,
+                    };
+                    client
+                        .get_messages_page(address, folder, offset, count_param(params, "limit")?)
+                        .await?
+                }
+                None => client.get_messages(address, folder).await?,
+            };
+            Value::Array(messages.iter().map(message_to_json).collect())
+        }
+        "search_messages" => {
+            let messages = client
+                .search_messages(
+                    str_param(params, "query")?,
+                    str_param(params, "address")?,
+                    folder_param(params)?,
+                )
+                .await?;
+            Value::Array(messages.iter().map(message_to_json).collect())
+        }
+        "get_pow_queue" => {
+            let queue = client.get_pow_queue().await?;
+            Value::Array(
+                queue
+                    .iter()
+                    .map(|i| {
+                        json!({
+                            "hash": i.hash,
+                            "kind": i.kind,
+                            "enqueued_at": i.enqueued_at.to_rfc3339(),
+                            "progress": i.progress,
+                        })
+                    })
+                    .collect(),
+            )
+        }
+        "cancel_pow" => {
+            client
+                .cancel_pow(str_param(params, "hash")?)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            Value::Null
+        }
+        "cancel_send" => {
+            client
+                .cancel_send(str_param(params, "hash")?)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            Value::Null
+        }
+        "delete_message" => {
+            client.delete_message(str_param(params, "hash")?).await?;
+            Value::Null
+        }
+        "mark_read" => {
+            // marks as read unless `read` is false
+            let read = params.get("read").and_then(Value::as_bool).unwrap_or(true);
+            client
+                .mark_read(str_param(params, "hash")?, read)
+                .await
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            Value::Null
+        }
+        "get_folder_stats" => {
+            let stats = client
+                .get_folder_stats(str_param(params, "address")?)
+                .await
+                .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?;
+            let counters = |c: FolderCounters| json!({"total": c.total, "unread": c.unread});
+            json!({
+                "inbox": counters(stats.inbox),
+                "outbox": counters(stats.outbox),
+                "sent": counters(stats.sent),
+                "drafts": counters(stats.drafts),
+                "trash": counters(stats.trash),
+                "quarantine": counters(stats.quarantine),
+            })
+        }
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", method),
+            ))
+        }
+    };
+    Ok(result)
+}
+
+fn str_param(params: &Value, name: &str) -> Result<String, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing string param {}", name)))
+}
+
+fn int_param(params: &Value, name: &str) -> Result<i32, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_i64)
+        .and_then(|v| i32::try_from(v).ok())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing integer param {}", name)))
+}
+
+fn count_param(params: &Value, name: &str) -> Result<u32, RpcError> {
+    u32::try_from(int_param(params, name)?)
+        .map_err(|_| RpcError::new(INVALID_PARAMS, format!("negative param {}", name)))
+}
+
+/// Time in RFC 3339 format, e.g. `2023-10-24T12:00:00Z`
+fn time_param(params: &Value, name: &str) -> Result<DateTime<Utc>, RpcError> {
+    DateTime::parse_from_rfc3339(&str_param(params, name)?)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid param {}: {}", name, e)))
+}
+
+fn addresses_param(params: &Value) -> Result<Vec<String>, RpcError> {
+    params
+        .get("addresses")
+        .and_then(Value::as_array)
+        .map(|a| {
+            a.iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing array param addresses"))
+}
+
+/// Address of an own identity, e.g. the sender of a message
+async fn identity_param(
+    client: &mut NodeClient,
+    params: &Value,
+    name: &str,
+) -> Result<String, RpcError> {
+    let address = str_param(params, name)?;
+    let identities = client.get_own_identities().await?;
+    if !identities.iter().any(|i| i.string_repr == address) {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("{} is not an own identity", address),
+        ));
+    }
+    Ok(address)
+}
+
+/// Recipients are passed either as an array or as a comma separated string
+fn recipients_param(params: &Value) -> Result<Vec<String>, RpcError> {
+    let recipients = match params.get("to") {
+        Some(Value::String(s)) => parse_recipients(s),
+        Some(Value::Array(a)) => a
+            .iter()
+            .map(|v| v.as_str().map(str::to_string))
+            .collect::<Option<_>>()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "recipients must be strings"))?,
+        _ => return Err(RpcError::new(INVALID_PARAMS, "missing param to")),
+    };
+    if recipients.is_empty() {
+        return Err(RpcError::new(INVALID_PARAMS, "no recipients"));
+    }
+    for recipient in &recipients {
+        Address::with_string_repr(recipient)
+            .map_err(|e| RpcError::new(INVALID_PARAMS, format!("{}: {}", recipient, e)))?;
+    }
+    Ok(recipients)
+}
+
+fn folder_param(params: &Value) -> Result<Folder, RpcError> {
+    let folder = str_param(params, "folder")?;
+    folder
+        .parse()
+        .map_err(|_| RpcError::new(INVALID_PARAMS, format!("unknown folder {}", folder)))
+}
+
+fn address_to_json(address: &Address) -> Value {
+    json!({
+        "address": address.string_repr,
+        "label": address.label,
+        "nonce_trials_per_byte": address.nonce_trials_per_byte,
+        "extra_bytes": address.extra_bytes,
+        "stranger_nonce_trials_per_byte": address.stranger_nonce_trials_per_byte,
+        "stranger_extra_bytes": address.stranger_extra_bytes,
+        "quarantine_strangers": address.quarantine_strangers,
+        "chan": address.chan,
+    })
+}
+
+fn message_to_json(msg: &models::Message) -> Value {
+    json!({
+        "hash": msg.hash,
+        "sender": msg.sender,
+        "sender_label": msg.sender_label,
+        "recipient": msg.recipient,
+        "recipient_label": msg.recipient_label,
+        "created_at": msg.created_at.to_rfc3339(),
+        "status": msg.status,
+        "failure_reason": msg.failure_reason,
+        "read": msg.read,
+        "send_at": msg.send_at.map(|t| t.to_rfc3339()),
+        "verified": msg.verified,
+        "signer_fingerprint": msg.signer_fingerprint,
+        "expires": msg.expires.map(|e| e.to_rfc3339()),
+        // raw MIME message
+        "data": String::from_utf8_lossy(&msg.data),
+    })
+}
@@ -0,0 +1,371 @@
+//! Helpers for tests running several nodes in one process. Nodes use in-memory
+//! storage and transport, so they don't touch the disk or the network.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_std::{future, task};
+use futures::{channel::mpsc, io::Cursor, StreamExt};
+use libp2p::{multiaddr::Protocol, request_response::Codec, Multiaddr, PeerId};
+
+use crate::{
+    config::{Config, PoWEngineKind, StorageKind, TransportKind},
+    network::{
+        self,
+        address::Address,
+        behaviour::{BitmessageProtocol, BitmessageProtocolCodec, BitmessageRequest},
+        messages::{KeyEndorsement, MsgEncoding, Object, ObjectKind, UnencryptedMsg},
+        node::{
+            client::NodeClient,
+            pow_worker::ProofOfWorkWorkerCommand,
+            pubkeys::{create_key_update_object, create_pubkey_object},
+            worker::{serialize_and_encrypt_payload_pub, MessageStatusEvent},
+        },
+    },
+    pow,
+    repositories::{memory::storage::MemoryStorage, storage::Storage},
+};
+
+#[cfg(feature = "sqlite")]
+use crate::{
+    network::node::storage::open_sqlite_storage,
+    repositories::sqlite::database::{self, MIGRATIONS},
+};
+#[cfg(feature = "sqlite")]
+use sqlx::{migrate::Migrate, Connection, Executor, SqliteConnection};
+
+pub use crate::pow::{PoWError, NETWORK_MIN_EXTRA_BYTES, NETWORK_MIN_NONCE_TRIALS_PER_BYTE};
+
+/// Short TTL of test objects, so that their PoW is quick
+const TEST_OBJECT_TTL_MINUTES: i64 = 10;
+/// Nodes of test networks do far less PoW than the network minimum, otherwise tests
+/// would spend most of their time on it
+const TEST_POW_TRIALS_DIVISOR: u64 = 100;
+
+/// Node running in the background
+pub struct TestNode {
+    pub client: NodeClient,
+    pub peer_id: PeerId,
+    /// Listen address of the node, including its peer id
+    pub address: Multiaddr,
+}
+
+/// Config of a node which keeps everything in memory and can only reach nodes
+/// in the same process
+pub fn test_config() -> Config {
+    Config {
+        listen_addresses: vec!["/memory/0".parse().unwrap()],
+        transport: TransportKind::Memory,
+        storage: StorageKind::Memory,
+        reconnect_peers: 0,
+        msg_ttl: chrono::Duration::minutes(TEST_OBJECT_TTL_MINUTES),
+        pubkey_ttl: chrono::Duration::minutes(TEST_OBJECT_TTL_MINUTES),
+        peer_idle_timeout: None,
+        pow_trials_divisor: TEST_POW_TRIALS_DIVISOR,
+        ..Default::default()
+    }
+}
+
+/// Start a node with given config and wait until it listens
+pub async fn spawn_node(config: Config) -> TestNode {
+    start_node(PathBuf::new(), config).await
+}
+
+/// Start a node keeping its database in `data_dir`, so that it can be restarted
+#[cfg(feature = "sqlite")]
+pub async fn spawn_sqlite_node(data_dir: &Path) -> TestNode {
+    start_node(data_dir.to_path_buf(), sqlite_config()).await
+}
+
+/// Same as [`spawn_sqlite_node`], storage of the config is replaced with SQLite
+#[cfg(feature = "sqlite")]
+pub async fn spawn_sqlite_node_with_config(data_dir: &Path, config: Config) -> TestNode {
+    let config = Config {
+        storage: StorageKind::Sqlite,
+        ..config
+    };
+    start_node(data_dir.to_path_buf(), config).await
+}
+
+async fn start_node(data_dir: PathBuf, config: Config) -> TestNode {
+    let listen_addresses = config.listen_addresses.clone();
+    let (mut client, worker) =
+        network::new(data_dir, config).expect("data dir not to be locked by another node");
+    task::spawn(worker.run());
+    for address in listen_addresses {
+        client
+            .start_listening(address)
+            .await
+            .expect("listening not to fail");
+    }
+    let peer_id = client.get_peer_id().await.expect("node to respond");
+    let address = client
+        .get_listeners()
+        .await
+        .expect("node to respond")
+        .remove(0)
+        .with(Protocol::P2p(peer_id.into()));
+    TestNode {
+        client,
+        peer_id,
+        address,
+    }
+}
+
+/// Start `count` nodes with [`test_config`], each one connected to all previous ones
+pub async fn spawn_network(count: usize) -> Vec<TestNode> {
+    let mut nodes: Vec<TestNode> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut node = spawn_node(test_config()).await;
+        for peer in &nodes {
+            node.client
+                .dial(peer.address.clone())
+                .await
+                .expect("dial not to fail");
+        }
+        nodes.push(node);
+    }
+    nodes
+}
+
+/// Directory in the system temp dir for a test's data, unique to the test process
+pub fn data_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("nantoka-{}-{}", name, std::process::id()))
+}
+
+/// Storages to run repository tests against: the in-memory one and, with the `sqlite`
+/// feature, a SQLite one in `data_dir`
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+pub fn storages(data_dir: &Path) -> Vec<Box<dyn Storage>> {
+    let mut storages: Vec<Box<dyn Storage>> = vec![Box::new(MemoryStorage::new())];
+    #[cfg(feature = "sqlite")]
+    storages.push(open_database(data_dir));
+    storages
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_config() -> Config {
+    Config {
+        storage: StorageKind::Sqlite,
+        ..test_config()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+async fn connect_database(data_dir: &Path) -> SqliteConnection {
+    let options = database::connect_options(
+        &sqlite_config().database_path(data_dir),
+        None,
+        Duration::from_secs(5),
+    );
+    SqliteConnection::connect_with(&options)
+        .await
+        .expect("database to be opened")
+}
+
+/// Open the database in `data_dir` as the node does, applying pending migrations
+#[cfg(feature = "sqlite")]
+pub fn open_database(data_dir: &Path) -> Box<dyn Storage> {
+    Box::new(open_sqlite_storage(data_dir, &sqlite_config()).expect("database to be opened"))
+}
+
+/// Create the database in `data_dir` with only migrations older than `version`
+/// applied, as an older version of the node leaves it
+#[cfg(feature = "sqlite")]
+pub async fn create_outdated_database(data_dir: &Path, version: i64) {
+    let path = sqlite_config().database_path(data_dir);
+    std::fs::create_dir_all(path.parent().expect("database to be in a dir"))
+        .expect("database dir to be created");
+    let mut conn = connect_database(data_dir).await;
+    conn.ensure_migrations_table()
+        .await
+        .expect("migrations table to be created");
+    for m in MIGRATIONS
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration() && m.version < version)
+    {
+        conn.apply(m).await.expect("migration to be applied");
+    }
+}
+
+/// Run the SQL on the database in `data_dir`, e.g. to fill an outdated one with data
+#[cfg(feature = "sqlite")]
+pub async fn execute_sql(data_dir: &Path, sql: &str) {
+    let mut conn = connect_database(data_dir).await;
+    conn.execute(sql).await.expect("SQL to be executed");
+}
+
+/// Versions of the migrations applied to the database in `data_dir`, along with SQL of
+/// its tables, indices and triggers
+#[cfg(feature = "sqlite")]
+pub async fn database_schema(data_dir: &Path) -> (Vec<i64>, Vec<String>) {
+    let mut conn = connect_database(data_dir).await;
+    let versions = conn
+        .list_applied_migrations()
+        .await
+        .expect("migrations to be listed")
+        .into_iter()
+        .map(|m| m.version)
+        .collect();
+    let schema = sqlx::query_scalar(
+        "SELECT sql FROM sqlite_master WHERE sql IS NOT NULL AND name != '_sqlx_migrations' \
+        ORDER BY type, name",
+    )
+    .fetch_all(&mut conn)
+    .await
+    .expect("schema to be read");
+    (versions, schema)
+}
+
+/// Wait until the message gets given status, following changes of its hash.
+/// Panics if it doesn't happen within the timeout.
+pub async fn wait_for_status(
+    events: &mut mpsc::UnboundedReceiver<MessageStatusEvent>,
+    hash: &str,
+    status: &str,
+    timeout: Duration,
+) -> MessageStatusEvent {
+    let mut hash = hash.to_string();
+    let wait = async {
+        while let Some(event) = events.next().await {
+            if event.previous_hash.as_ref() == Some(&hash) {
+                hash = event.hash.clone();
+            }
+            if event.hash != hash {
+                continue;
+            }
+            if event.status == status {
+                return event;
+            }
+            assert_ne!(event.status, "Failed", "message {} has failed", hash);
+        }
+        panic!("node has stopped");
+    };
+    future::timeout(timeout, wait)
+        .await
+        .unwrap_or_else(|_| panic!("message didn't become {} in time", status))
+}
+
+/// Getpubkey object requesting the pubkey of the address, without PoW
+pub fn getpubkey_object(address: &Address) -> Object {
+    let expires = chrono::Utc::now() + chrono::Duration::minutes(TEST_OBJECT_TTL_MINUTES);
+    Object::new(
+        expires.timestamp(),
+        Vec::new(),
+        ObjectKind::Getpubkey {
+            tag: address.tag.clone(),
+        },
+    )
+}
+
+/// Pubkey object of the identity as it's sent out on request, without PoW
+pub fn pubkey_object(identity: &Address) -> Object {
+    let expires = chrono::Utc::now() + chrono::Duration::minutes(TEST_OBJECT_TTL_MINUTES);
+    create_pubkey_object(identity, &[], expires)
+}
+
+/// Msg object from the identity to the recipient with the network minimum
+/// PoW difficulty, without PoW
+pub fn msg_object(identity: &Address, recipient: &Address) -> Object {
+    spoofed_msg_object(identity, &identity.string_repr, recipient)
+}
+
+/// Same as [`msg_object`], but the message claims to come from `sender`
+pub fn spoofed_msg_object(identity: &Address, sender: &str, recipient: &Address) -> Object {
+    let msg = UnencryptedMsg {
+        behavior_bitfield: 0,
+        sender_ripe: sender.to_string(),
+        destination_ripe: recipient.string_repr.clone(),
+        encoding: MsgEncoding::Simple,
+        message: b"Subject: Hello\n\nHello there".to_vec(),
+        public_signing_key: identity.public_signing_key.unwrap().serialize().to_vec(),
+        public_encryption_key: identity.public_encryption_key.unwrap().serialize().to_vec(),
+        ack_data: UnencryptedMsg::generate_ack_data(),
+    };
+    let encrypted =
+        serialize_and_encrypt_payload_pub(msg, &recipient.public_encryption_key.unwrap());
+    let expires = chrono::Utc::now() + chrono::Duration::minutes(TEST_OBJECT_TTL_MINUTES);
+    Object::with_signing(
+        identity,
+        ObjectKind::Msg {
+            encrypted,
+            copies: Vec::new(),
+        },
+        expires,
+    )
+}
+
+/// Rotate keys of the identity to ones derived from the seed, returns it with the new keys
+/// along with its key update, without PoW
+pub fn rotate_keys(identity: &Address, seed: u64) -> (Address, Object) {
+    let keys = Address::generate_seeded(seed);
+    let endorsement = KeyEndorsement::sign(
+        identity,
+        &keys.public_signing_key.unwrap(),
+        &keys.public_encryption_key.unwrap(),
+    );
+    let mut rotated = identity.clone();
+    rotated.public_signing_key = keys.public_signing_key;
+    rotated.public_encryption_key = keys.public_encryption_key;
+    rotated.private_signing_key = keys.private_signing_key;
+    rotated.private_encryption_key = keys.private_encryption_key;
+    rotated.keys_rotated_at = Some(chrono::Utc::now());
+    let expires = chrono::Utc::now() + chrono::Duration::minutes(TEST_OBJECT_TTL_MINUTES);
+    let object = create_key_update_object(&rotated, vec![endorsement], &[], expires);
+    (rotated, object)
+}
+
+/// Do PoW of the object, so that peers accept it
+pub async fn with_pow(mut object: Object) -> Object {
+    let target = pow::get_pow_target(&object, object.nonce_trials_per_byte, object.extra_bytes, 1);
+    let (_, nonce) = pow::engine(PoWEngineKind::Fast)
+        .do_pow(target, object.hash.clone())
+        .await
+        .expect("engine not to stop")
+        .expect("PoW not to fail");
+    object.nonce = nonce.to_bytes_be();
+    object
+}
+
+/// Run PoW of the object the same way the PoW worker does. If `abort_after` is set,
+/// the PoW is aborted once it passes, as the worker does on cancellation.
+pub async fn run_pow(
+    object: Object,
+    engine: PoWEngineKind,
+    abort_after: Option<Duration>,
+) -> Result<Object, PoWError> {
+    let (sender, mut results) = mpsc::channel(1);
+    let target = pow::get_pow_target(&object, object.nonce_trials_per_byte, object.extra_bytes, 1);
+    let abort_handle = object.do_proof_of_work(target, pow::engine(engine).as_ref(), sender);
+    if let Some(delay) = abort_after {
+        task::sleep(delay).await;
+        abort_handle.abort();
+    }
+    match results.next().await.expect("PoW to report its result") {
+        ProofOfWorkWorkerCommand::NonceCalculated { object } => Ok(object),
+        ProofOfWorkWorkerCommand::PoWFailed { error, .. } => Err(error),
+        _ => unreachable!("PoW only reports its result"),
+    }
+}
+
+/// Encode the request as it's written to a peer
+pub async fn write_request(data: Vec<u8>) -> Vec<u8> {
+    let mut io = Cursor::new(Vec::new());
+    BitmessageProtocolCodec()
+        .write_request(&BitmessageProtocol(), &mut io, BitmessageRequest(data))
+        .await
+        .expect("writing into memory not to fail");
+    io.into_inner()
+}
+
+/// Read the request from data received from a peer
+pub async fn read_request(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut io = Cursor::new(data);
+    BitmessageProtocolCodec()
+        .read_request(&BitmessageProtocol(), &mut io)
+        .await
+        .map(|r| r.0)
+}
@@ -0,0 +1,78 @@
+use std::{path::PathBuf, time::Duration};
+
+use async_std::task;
+use nantoka_core::{
+    config::Config,
+    network::{
+        self,
+        node::{client::ClientError, worker::PowerMode},
+    },
+    testing,
+};
+
+#[async_std::test]
+async fn requests_to_unresponsive_node_fail() {
+    let config = Config {
+        command_queue_size: 1,
+        command_timeout: chrono::Duration::milliseconds(100),
+        ..testing::test_config()
+    };
+    // the worker isn't run, so commands stay in the queue
+    let (mut client, worker) = network::new(PathBuf::new(), config).unwrap();
+
+    let err = client.get_contacts().await.unwrap_err();
+    assert!(matches!(err, ClientError::Timeout), "{:?}", err);
+    let err = client.get_contacts().await.unwrap_err();
+    assert!(matches!(err, ClientError::Busy), "{:?}", err);
+    let stats = client.command_queue_stats();
+    assert_eq!((stats.queued, stats.capacity, stats.rejected), (1, 1, 1));
+
+    drop(worker);
+    let err = client.get_contacts().await.unwrap_err();
+    assert!(matches!(err, ClientError::Stopped), "{:?}", err);
+}
+
+#[async_std::test]
+async fn worker_errors_are_returned_to_client() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let err = node
+        .client
+        .retry_message("unknown".to_string())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Node(_)));
+    assert!(node.client.get_contacts().await.unwrap().is_empty());
+}
+
+#[async_std::test]
+async fn commands_sent_during_shutdown_are_rejected() {
+    let (mut client, worker) = network::new(PathBuf::new(), testing::test_config()).unwrap();
+    let worker = task::spawn(worker.run());
+    let mut other = client.clone();
+    // PoW worker is busy benchmarking the engine for the first estimate, so it
+    // stops a while after the node started shutting down
+    let mut estimating = client.clone();
+    let estimate = task::spawn(async move { estimating.estimate_pow(100, None).await });
+    task::sleep(Duration::from_millis(50)).await;
+    let shutdown = task::spawn(async move { client.shutdown().await });
+    task::sleep(Duration::from_millis(50)).await;
+    // commands queued behind the shutdown reach the node while PoW is drained,
+    // those which need the PoW worker must not bring the node down
+    for _ in 0..10 {
+        let results = [
+            other.get_pow_queue().await.map(|_| ()),
+            other.estimate_pow(100, None).await.map(|_| ()),
+            other.set_power_mode(PowerMode::LowPower).await,
+        ];
+        for result in results {
+            assert!(
+                matches!(result, Ok(_) | Err(ClientError::Stopped)),
+                "{:?}",
+                result
+            );
+        }
+    }
+    shutdown.await.unwrap();
+    worker.await;
+    estimate.await.unwrap();
+}
@@ -0,0 +1,107 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    io,
+};
+
+use async_std::task;
+use nantoka_core::testing;
+
+/// Limit of a single chunk of the codec
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Limit of the whole request
+const MAX_REQUEST_SIZE: usize = 10_000_000;
+
+/// Tracks memory allocated by each thread, so that tests running in parallel
+/// don't affect each other
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+    static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+fn track(delta: isize) {
+    _ = ALLOCATED.try_with(|allocated| {
+        allocated.set(allocated.get() + delta);
+        _ = PEAK.try_with(|peak| peak.set(peak.get().max(allocated.get())));
+    });
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            track(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        track(-(layout.size() as isize));
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Read the request on the current thread, returns it along with the peak amount
+/// of memory allocated meanwhile. Only the reading is measured, not the setup of
+/// the executor.
+fn read_request(data: &[u8]) -> (io::Result<Vec<u8>>, usize) {
+    task::block_on(async {
+        let baseline = ALLOCATED.with(Cell::get);
+        PEAK.with(|peak| peak.set(baseline));
+        let result = testing::read_request(data).await;
+        let peak = PEAK.with(Cell::get) - baseline;
+        (result, peak.max(0) as usize)
+    })
+}
+
+fn varint(mut n: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[async_std::test]
+async fn requests_are_transferred_in_chunks() {
+    let data: Vec<u8> = (0..3 * CHUNK_SIZE + 1).map(|i| i as u8).collect();
+    let written = testing::write_request(data.clone()).await;
+    assert_eq!(testing::read_request(&written).await.unwrap(), data);
+    assert!(testing::read_request(&written[..CHUNK_SIZE]).await.is_err());
+}
+
+#[test]
+fn chunked_request_is_allocated_as_it_arrives() {
+    // peer announces a full chunk, but only a few bytes of it arrive
+    let mut data = varint(CHUNK_SIZE);
+    data.extend_from_slice(&[1; 16]);
+    let (result, peak) = read_request(&data);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert!(peak < 2 * CHUNK_SIZE, "{} bytes allocated", peak);
+
+    let mut data = varint(CHUNK_SIZE + 1);
+    data.extend_from_slice(&[1; 16]);
+    let (result, _) = read_request(&data);
+    assert!(result.is_err(), "chunk is larger than the limit");
+}
+
+#[test]
+fn oversized_request_is_rejected() {
+    let mut data = Vec::new();
+    for _ in 0..MAX_REQUEST_SIZE / CHUNK_SIZE + 1 {
+        data.extend(varint(CHUNK_SIZE));
+        data.extend(vec![1; CHUNK_SIZE]);
+    }
+    let (result, _) = read_request(&data);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
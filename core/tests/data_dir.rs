@@ -0,0 +1,47 @@
+use std::{fs, process};
+
+use nantoka_core::network::node::data_dir_lock::{DataDirLock, DataDirLockError};
+#[cfg(feature = "sqlite")]
+use nantoka_core::{
+    config::{Config, StorageKind},
+    network::{NodeBuildError, NodeBuilder},
+    testing,
+};
+
+#[test]
+fn data_dir_is_used_by_one_node() {
+    let data_dir = testing::data_dir("lock");
+    let lock = DataDirLock::acquire(&data_dir).unwrap();
+    assert!(matches!(
+        DataDirLock::acquire(&data_dir),
+        Err(DataDirLockError::Locked(Some(pid))) if pid == process::id()
+    ));
+    drop(lock);
+    let lock = DataDirLock::acquire(&data_dir).unwrap();
+    drop(lock);
+
+    // lock files of nodes which aren't running anymore are taken over, even if the PID
+    // is reused (e.g. by the node restarted in a container)
+    for pid in [u32::MAX, process::id()] {
+        fs::write(data_dir.join("node.lock"), pid.to_string()).unwrap();
+        drop(DataDirLock::acquire(&data_dir).unwrap());
+    }
+    fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn unreadable_database_fails_the_build() {
+    let data_dir = testing::data_dir("broken-db");
+    let config = Config {
+        storage: StorageKind::Sqlite,
+        ..testing::test_config()
+    };
+    // the file isn't a database, so it can't be migrated
+    let database_path = config.database_path(&data_dir);
+    fs::create_dir_all(database_path.parent().unwrap()).unwrap();
+    fs::write(&database_path, [7; 4096]).unwrap();
+    let result = NodeBuilder::new(config).data_dir(data_dir.clone()).build();
+    assert!(matches!(result, Err(NodeBuildError::Database(_))));
+    fs::remove_dir_all(data_dir).unwrap();
+}
@@ -0,0 +1,696 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use libp2p::PeerId;
+use nantoka_core::{
+    config::{Config, NodeRole, ObjectType},
+    network::{
+        address::Address,
+        messages::{MessageCommand, MessagePayload, Object, ObjectKind},
+        node::protocol::{Action, MsgContext, ProtocolEngine, INVENTORY_RESYNC_INTERVAL_SECONDS},
+    },
+    testing,
+};
+
+/// Names of the actions, so that tables can list expected ones
+fn kinds(actions: &[Action]) -> Vec<&'static str> {
+    actions
+        .iter()
+        .map(|a| match a {
+            Action::StoreObject(_) => "store",
+            Action::ProcessObject(_) => "process",
+            Action::Announce(_) => "announce",
+            Action::BanPeer { .. } => "ban",
+            Action::EnqueuePoW(_) => "pow",
+            Action::PublishPubkey(_) => "publish-pubkey",
+            Action::UpdatePubkey(_) => "update-pubkey",
+            Action::RotateKeys { .. } => "rotate-keys",
+            Action::SaveMessage { .. } => "save-message",
+        })
+        .collect()
+}
+
+fn hash(object: &Object) -> String {
+    bs58::encode(&object.hash).into_string()
+}
+
+fn inv(items: &[(&str, i64, u8)]) -> MessagePayload {
+    MessagePayload::inv(
+        items
+            .iter()
+            .map(|(h, e, t)| (h.to_string(), *e, *t))
+            .collect(),
+        None,
+    )
+}
+
+#[test]
+fn announced_objects_are_filtered() {
+    let soon = (Utc::now() + chrono::Duration::hours(1)).timestamp();
+    let later = (Utc::now() + chrono::Duration::days(2)).timestamp();
+    let light = Config {
+        sync_window: Some(chrono::Duration::days(1)),
+        ..Default::default()
+    };
+    let no_broadcasts = Config {
+        object_types: Some(vec![ObjectType::Msg, ObjectType::Pubkey]),
+        ..Default::default()
+    };
+    let table = [
+        (
+            "everything is wanted by default",
+            Config::default(),
+            inv(&[("a", soon, 0), ("b", later, 1)]),
+            vec!["a", "b"],
+        ),
+        (
+            "objects expiring within the sync window are skipped",
+            light.clone(),
+            inv(&[("a", soon, 0), ("b", later, 0)]),
+            vec!["b"],
+        ),
+        (
+            "objects of unwanted types are skipped",
+            no_broadcasts,
+            inv(&[("a", soon, 0), ("b", soon, 1), ("c", soon, 3)]),
+            vec!["a", "c"],
+        ),
+        (
+            "objects without expiration times are wanted",
+            light,
+            MessagePayload::Inv {
+                inventory: vec!["a".to_string()],
+                expires: Vec::new(),
+                types: Vec::new(),
+                next: None,
+            },
+            vec!["a"],
+        ),
+        (
+            "other payloads are ignored",
+            Config::default(),
+            MessagePayload::None,
+            vec![],
+        ),
+    ];
+    for (name, config, payload, expected) in table {
+        let engine = ProtocolEngine::new(config);
+        assert_eq!(engine.wanted_objects(payload), expected, "{}", name);
+    }
+}
+
+#[test]
+fn missing_objects_are_requested() {
+    assert!(ProtocolEngine::request_objects(Vec::new()).is_none());
+    let request = ProtocolEngine::request_objects(vec!["a".to_string()]).unwrap();
+    assert!(matches!(request.command, MessageCommand::GetData));
+    let requested = ProtocolEngine::requested_objects(request.payload);
+    assert_eq!(requested, vec!["a".to_string()]);
+
+    let reply = ProtocolEngine::objects_reply(Vec::new());
+    assert!(matches!(reply.command, MessageCommand::Objects));
+}
+
+#[async_std::test]
+async fn objects_outside_sync_window_are_skipped() {
+    let identity = Address::generate_seeded(1);
+    let soon = testing::with_pow(testing::getpubkey_object(&identity)).await;
+    let later = testing::with_pow(Object::new(
+        (Utc::now() + chrono::Duration::days(2)).timestamp(),
+        Vec::new(),
+        ObjectKind::Getpubkey {
+            tag: identity.tag.clone(),
+        },
+    ))
+    .await;
+    let mut engine = ProtocolEngine::new(Config {
+        sync_window: Some(chrono::Duration::days(1)),
+        ..Default::default()
+    });
+
+    let (soon_hash, later_hash) = (hash(&soon), hash(&later));
+    let announced = inv(&[
+        (soon_hash.as_str(), soon.expires, 0),
+        (later_hash.as_str(), later.expires, 0),
+    ]);
+    // the window is off by default, so everything is synced
+    let full_node = ProtocolEngine::new(Config::default());
+    assert_eq!(
+        full_node.wanted_objects(announced.clone()),
+        vec![soon_hash, later_hash.clone()]
+    );
+    assert_eq!(engine.wanted_objects(announced), vec![later_hash]);
+
+    // peer might send objects which weren't requested
+    let actions = engine.on_objects(PeerId::random(), vec![soon], &HashSet::new(), Utc::now());
+    assert!(actions.is_empty());
+    let actions = engine.on_objects(
+        PeerId::random(),
+        vec![later.clone()],
+        &HashSet::new(),
+        Utc::now(),
+    );
+    assert_eq!(kinds(&actions), vec!["store", "process", "announce"]);
+    assert!(matches!(&actions[0], Action::StoreObject(o) if o.hash == later.hash));
+}
+
+#[test]
+fn rediscovered_peers_are_synced_once_per_interval() {
+    let mut engine = ProtocolEngine::new(Config::default());
+    let peers: Vec<_> = (0..3).map(|_| PeerId::random()).collect();
+    let start = Utc::now();
+    let interval = chrono::Duration::seconds(INVENTORY_RESYNC_INTERVAL_SECONDS);
+    // burst of discoveries, e.g. by mDNS on a busy network
+    let mut requests = 0;
+    for i in 0..100 {
+        let now = start + interval * i / 100;
+        for peer in &peers {
+            if let Some(msg) = engine.inventory_request(*peer, now) {
+                assert!(matches!(msg.command, MessageCommand::ReqInv));
+                requests += 1;
+            }
+        }
+    }
+    assert_eq!(requests, peers.len());
+    // peers are limited on their own, a new one is synced right away
+    assert!(engine
+        .inventory_request(PeerId::random(), start + interval / 2)
+        .is_some());
+
+    let requests = peers
+        .iter()
+        .filter(|peer| engine.inventory_request(**peer, start + interval).is_some())
+        .count();
+    assert_eq!(requests, peers.len(), "interval has passed");
+}
+
+#[test]
+fn inventory_page_respects_peer_object_types() {
+    let peer = PeerId::random();
+    let page = vec![
+        ("a".to_string(), 1, 0),
+        ("b".to_string(), 2, 1),
+        ("c".to_string(), 3, 2),
+    ];
+    let table = [
+        ("peer wants everything", None, vec!["a", "b", "c"]),
+        ("peer skips broadcasts", Some(vec![0, 2, 3]), vec!["a", "c"]),
+        ("peer wants only pubkeys", Some(vec![3]), vec![]),
+    ];
+    for (name, types, expected) in table {
+        let mut engine = ProtocolEngine::new(Config::default());
+        engine.set_peer_object_types(peer, types);
+        let reply = engine.inv_page(peer, page.clone());
+        let MessagePayload::Inv {
+            inventory, next, ..
+        } = reply.payload
+        else {
+            panic!("{}: reply is not an inventory", name);
+        };
+        assert_eq!(inventory, expected, "{}", name);
+        assert!(next.is_none(), "{}", name);
+    }
+}
+
+#[async_std::test]
+async fn received_objects_are_checked() {
+    let identity = Address::generate_seeded(1);
+    let valid = testing::with_pow(testing::getpubkey_object(&identity)).await;
+    let mut invalid_pow = testing::getpubkey_object(&identity);
+    invalid_pow.nonce = vec![1];
+    let mut tampered = valid.clone();
+    tampered.expires += 1;
+
+    let relay = Config {
+        role: NodeRole::Relay,
+        ..Default::default()
+    };
+    let client = Config {
+        role: NodeRole::Client,
+        ..Default::default()
+    };
+    let no_getpubkeys = Config {
+        object_types: Some(vec![ObjectType::Msg]),
+        ..Default::default()
+    };
+    let table = [
+        (
+            "valid object is stored, processed and announced",
+            Config::default(),
+            vec![valid.clone()],
+            vec![],
+            vec!["store", "process", "announce"],
+        ),
+        (
+            "known object is skipped",
+            Config::default(),
+            vec![valid.clone()],
+            vec![hash(&valid)],
+            vec![],
+        ),
+        (
+            "duplicates are handled once",
+            Config::default(),
+            vec![valid.clone(), valid.clone()],
+            vec![],
+            vec!["store", "process", "announce"],
+        ),
+        (
+            "relay doesn't process objects",
+            relay,
+            vec![valid.clone()],
+            vec![],
+            vec!["store", "announce"],
+        ),
+        (
+            "client doesn't store objects",
+            client,
+            vec![valid.clone()],
+            vec![],
+            vec!["process"],
+        ),
+        (
+            "object of unwanted type is skipped",
+            no_getpubkeys,
+            vec![valid.clone()],
+            vec![],
+            vec![],
+        ),
+        (
+            "object with invalid PoW is skipped",
+            Config::default(),
+            vec![invalid_pow],
+            vec![],
+            vec![],
+        ),
+        (
+            "tampered object is skipped",
+            Config::default(),
+            vec![tampered],
+            vec![],
+            vec![],
+        ),
+    ];
+    for (name, config, objects, known, expected) in table {
+        let mut engine = ProtocolEngine::new(config);
+        let known: HashSet<String> = known.into_iter().collect();
+        let actions = engine.on_objects(PeerId::random(), objects, &known, Utc::now());
+        assert_eq!(kinds(&actions), expected, "{}", name);
+    }
+}
+
+#[async_std::test]
+async fn client_remembers_seen_objects() {
+    let object = testing::with_pow(testing::getpubkey_object(&Address::generate_seeded(1))).await;
+    let mut engine = ProtocolEngine::new(Config {
+        role: NodeRole::Client,
+        ..Default::default()
+    });
+    let peer = PeerId::random();
+    let known = HashSet::new();
+    let actions = engine.on_objects(peer, vec![object.clone()], &known, Utc::now());
+    assert_eq!(kinds(&actions), vec!["process"]);
+
+    let actions = engine.on_objects(peer, vec![object.clone()], &known, Utc::now());
+    assert!(actions.is_empty());
+    assert!(engine
+        .wanted_objects(inv(&[(hash(&object).as_str(), object.expires, 2)]))
+        .is_empty());
+}
+
+#[test]
+fn misbehaving_peer_is_banned() {
+    let mut object = testing::getpubkey_object(&Address::generate_seeded(1));
+    object.nonce = vec![1];
+    let mut engine = ProtocolEngine::new(Config::default());
+    let peer = PeerId::random();
+    let known = HashSet::new();
+    for _ in 0..3 {
+        let actions = engine.on_objects(peer, vec![object.clone()], &known, Utc::now());
+        assert!(actions.is_empty());
+    }
+    let actions = engine.on_objects(peer, vec![object.clone()], &known, Utc::now());
+    assert!(
+        matches!(&actions[..], [Action::BanPeer { peer: p, .. }] if *p == peer),
+        "{:?}",
+        kinds(&actions)
+    );
+
+    // scores decay, so that occasional invalid objects don't get peers banned
+    let other = PeerId::random();
+    for _ in 0..10 {
+        let actions = engine.on_objects(other, vec![object.clone()], &known, Utc::now());
+        assert!(actions.is_empty());
+        engine.decay_misbehavior_scores();
+    }
+}
+
+#[test]
+fn pubkeys_update_keys_of_their_addresses() {
+    let identity = Address::generate_seeded(1);
+    let contact = Address::with_string_repr(&identity.string_repr).unwrap();
+    let stranger = Address::generate_seeded(2);
+    let pubkey = testing::pubkey_object(&identity);
+    let mut forged = testing::pubkey_object(&stranger);
+    forged.kind = pubkey.kind.clone();
+
+    let table = [
+        (
+            "contact gets its keys",
+            &contact,
+            &pubkey,
+            vec!["update-pubkey"],
+        ),
+        (
+            "own identity gets its keys",
+            &identity,
+            &pubkey,
+            vec!["update-pubkey"],
+        ),
+        (
+            "pubkey of another address is ignored",
+            &stranger,
+            &pubkey,
+            vec![],
+        ),
+        (
+            "pubkey with foreign signature is ignored",
+            &contact,
+            &forged,
+            vec![],
+        ),
+    ];
+    let engine = ProtocolEngine::new(Config::default());
+    for (name, address, object, expected) in table {
+        let actions = engine.on_pubkey(object, address).unwrap();
+        assert_eq!(kinds(&actions), expected, "{}", name);
+    }
+
+    let actions = engine.on_pubkey(&pubkey, &contact).unwrap();
+    let [Action::UpdatePubkey(update)] = &actions[..] else {
+        panic!("keys are not updated");
+    };
+    assert_eq!(update.address, identity.string_repr);
+    assert_eq!(Some(update.public_signing_key), identity.public_signing_key);
+    assert_eq!(update.device_keys, Some(Vec::new()));
+    let actions = engine.on_pubkey(&pubkey, &identity).unwrap();
+    let [Action::UpdatePubkey(update)] = &actions[..] else {
+        panic!("keys are not updated");
+    };
+    assert!(update.device_keys.is_none());
+
+    assert!(engine
+        .on_pubkey(&testing::getpubkey_object(&identity), &contact)
+        .is_err());
+}
+
+#[test]
+fn pubkey_with_keys_of_another_address_is_rejected() {
+    let identity = Address::generate_seeded(1);
+    let contact = Address::with_string_repr(&identity.string_repr).unwrap();
+    // signed by the impostor and readable with the tag of the address, but its keys
+    // hash to another ripe
+    let mut impostor = Address::generate_seeded(2);
+    impostor.tag = contact.tag.clone();
+    impostor.public_decryption_key = contact.public_decryption_key;
+    let impostor_pubkey = testing::pubkey_object(&impostor);
+    let ObjectKind::Pubkey { tag, .. } = &impostor_pubkey.kind else {
+        panic!("not a pubkey");
+    };
+    assert_eq!(tag, &contact.tag);
+
+    let engine = ProtocolEngine::new(Config::default());
+    for (name, address) in [("contact", &contact), ("own identity", &identity)] {
+        let actions = engine.on_pubkey(&impostor_pubkey, address).unwrap();
+        assert!(actions.is_empty(), "keys of {} are updated", name);
+    }
+    // rejected pubkey doesn't keep the real one from being accepted
+    let actions = engine
+        .on_pubkey(&testing::pubkey_object(&identity), &contact)
+        .unwrap();
+    let [Action::UpdatePubkey(update)] = &actions[..] else {
+        panic!("keys are not updated");
+    };
+    assert_eq!(
+        Some(update.public_encryption_key),
+        identity.public_encryption_key
+    );
+    assert_ne!(
+        Some(update.public_encryption_key),
+        impostor.public_encryption_key
+    );
+}
+
+#[test]
+fn pubkey_is_published_on_request() {
+    let identity = Address::generate_seeded(1);
+    let mut published = identity.clone();
+    published.pubkey_published_at = Some(Utc::now());
+    let mut published_long_ago = identity.clone();
+    published_long_ago.pubkey_published_at = Some(Utc::now() - chrono::Duration::days(30));
+    let request = testing::getpubkey_object(&identity);
+    let served_recently = Some(Utc::now() - chrono::Duration::hours(1));
+    let served_long_ago = Some(Utc::now() - chrono::Duration::days(2));
+
+    let table = [
+        (
+            "identity publishes its pubkey",
+            vec![identity.clone()],
+            None,
+            vec!["publish-pubkey"],
+        ),
+        (
+            "recently published pubkey isn't sent again",
+            vec![published],
+            None,
+            vec![],
+        ),
+        (
+            "expired pubkey is sent again",
+            vec![published_long_ago.clone()],
+            None,
+            vec!["publish-pubkey"],
+        ),
+        (
+            "repeated request of recently served tag is ignored",
+            vec![published_long_ago.clone()],
+            served_recently,
+            vec![],
+        ),
+        (
+            "tag is served again once the interval passes",
+            vec![published_long_ago],
+            served_long_ago,
+            vec!["publish-pubkey"],
+        ),
+        (
+            "pubkey of other identities isn't sent",
+            vec![Address::generate_seeded(2)],
+            None,
+            vec![],
+        ),
+    ];
+    let engine = ProtocolEngine::new(Config::default());
+    for (name, identities, responded_at, expected) in table {
+        let actions = engine
+            .on_getpubkey(&request, identities, responded_at, Utc::now())
+            .unwrap();
+        assert_eq!(kinds(&actions), expected, "{}", name);
+    }
+}
+
+#[test]
+fn endorsed_keys_replace_former_ones() {
+    let identity = Address::generate_seeded(1);
+    let contact = Address::with_string_repr(&identity.string_repr).unwrap();
+    let (rotated, update) = testing::rotate_keys(&identity, 3);
+    // keys of a stranger can't endorse keys of the address, even if the update
+    // is encrypted to it
+    let mut impostor = Address::generate_seeded(2);
+    impostor.tag = identity.tag.clone();
+    impostor.public_decryption_key = identity.public_decryption_key;
+    let (_, forged) = testing::rotate_keys(&impostor, 4);
+
+    let table = [
+        (
+            "contact gets the endorsed keys",
+            &contact,
+            &update,
+            0,
+            vec!["rotate-keys"],
+        ),
+        (
+            "update of the same keys is accepted again",
+            &contact,
+            &update,
+            1,
+            vec!["rotate-keys"],
+        ),
+        ("outdated update is ignored", &contact, &update, 2, vec![]),
+        (
+            "own identity ignores its updates",
+            &identity,
+            &update,
+            0,
+            vec![],
+        ),
+        (
+            "keys endorsed by a stranger are ignored",
+            &contact,
+            &forged,
+            0,
+            vec![],
+        ),
+    ];
+    let engine = ProtocolEngine::new(Config::default());
+    for (name, address, object, known, expected) in table {
+        let actions = engine.on_key_update(object, address, known).unwrap();
+        assert_eq!(kinds(&actions), expected, "{}", name);
+    }
+
+    let actions = engine.on_key_update(&update, &contact, 0).unwrap();
+    let [Action::RotateKeys { update, chain }] = &actions[..] else {
+        panic!("keys are not rotated");
+    };
+    assert_eq!(Some(update.public_signing_key), rotated.public_signing_key);
+    assert_eq!(
+        Some(update.public_encryption_key),
+        rotated.public_encryption_key
+    );
+    assert_eq!(chain.len(), 1);
+
+    // original keys of the address don't replace the rotated ones anymore
+    let mut rotated_contact = contact.clone();
+    rotated_contact.keys_rotated_at = rotated.keys_rotated_at;
+    let actions = engine
+        .on_pubkey(&testing::pubkey_object(&identity), &rotated_contact)
+        .unwrap();
+    assert!(actions.is_empty());
+}
+
+#[test]
+fn strangers_have_to_do_more_work() {
+    let mut identity = Address::generate_seeded(1);
+    identity.stranger_nonce_trials_per_byte = identity.nonce_trials_per_byte * 4;
+    let mut quarantining = identity.clone();
+    quarantining.quarantine_strangers = true;
+    let sender = Address::generate_seeded(2);
+    let contacts = MsgContext {
+        known_senders: HashSet::from([sender.string_repr.clone()]),
+        ..Default::default()
+    };
+    let strangers = MsgContext::default();
+    let cheap = testing::msg_object(&sender, &identity);
+    let mut costly = testing::msg_object(&sender, &identity);
+    costly.nonce_trials_per_byte = identity.stranger_nonce_trials_per_byte;
+    // signed by a stranger, claiming to come from the contact
+    let impostor = Address::generate_seeded(3);
+    let spoofed = testing::spoofed_msg_object(&impostor, &sender.string_repr, &identity);
+
+    let table = [
+        (
+            "contact does the usual work",
+            &identity,
+            &contacts,
+            &cheap,
+            vec!["pow", "save-message"],
+        ),
+        (
+            "stranger doing the usual work is dropped",
+            &identity,
+            &strangers,
+            &cheap,
+            vec![],
+        ),
+        (
+            "stranger doing more work gets through",
+            &identity,
+            &strangers,
+            &costly,
+            vec!["pow", "save-message"],
+        ),
+        (
+            "stranger doing the usual work is quarantined without acknowledgement",
+            &quarantining,
+            &strangers,
+            &cheap,
+            vec!["save-message"],
+        ),
+        (
+            "stranger claiming to be a contact is dropped",
+            &identity,
+            &contacts,
+            &spoofed,
+            vec![],
+        ),
+    ];
+    let engine = ProtocolEngine::new(Config::default());
+    for (name, identity, context, object, expected) in table {
+        let actions = engine
+            .on_msg(object, std::slice::from_ref(identity), &[], context)
+            .unwrap();
+        assert_eq!(kinds(&actions), expected, "{}", name);
+    }
+
+    for (object, context) in [(&cheap, &strangers), (&spoofed, &contacts)] {
+        let actions = engine
+            .on_msg(object, std::slice::from_ref(&quarantining), &[], context)
+            .unwrap();
+        let [Action::SaveMessage { quarantined, .. }] = &actions[..] else {
+            panic!("message is not saved");
+        };
+        assert!(quarantined);
+    }
+}
+
+#[test]
+fn messages_below_required_difficulty_are_dropped() {
+    let mut identity = Address::generate_seeded(1);
+    identity.nonce_trials_per_byte *= 2;
+    identity.stranger_nonce_trials_per_byte = identity.nonce_trials_per_byte;
+    let sender = Address::generate_seeded(2);
+    let context = MsgContext {
+        known_senders: HashSet::from([sender.string_repr.clone()]),
+        ..Default::default()
+    };
+    let below = testing::msg_object(&sender, &identity);
+    let mut at = testing::msg_object(&sender, &identity);
+    at.nonce_trials_per_byte = identity.nonce_trials_per_byte;
+    let mut too_few_bytes = at.clone();
+    too_few_bytes.extra_bytes -= 1;
+
+    let engine = ProtocolEngine::new(Config::default());
+    let table = [
+        ("below the required difficulty", &below, vec![]),
+        (
+            "at the required difficulty",
+            &at,
+            vec!["pow", "save-message"],
+        ),
+        ("below the required extra bytes", &too_few_bytes, vec![]),
+    ];
+    for (name, object, expected) in table {
+        let actions = engine
+            .on_msg(object, &[identity.clone()], &[], &context)
+            .unwrap();
+        assert_eq!(kinds(&actions), expected, "{}", name);
+    }
+}
+
+#[async_std::test]
+async fn objects_are_checked_against_their_difficulty() {
+    let identity = Address::generate_seeded(1);
+    let mut harder = testing::getpubkey_object(&Address::generate_seeded(2));
+    harder.nonce_trials_per_byte *= 2;
+    let harder = testing::with_pow(harder).await;
+    // nonce only meets the network minimum, while the object claims way more work
+    let mut overstated = testing::with_pow(testing::getpubkey_object(&identity)).await;
+    overstated.nonce_trials_per_byte *= 1_000_000;
+
+    let mut engine = ProtocolEngine::new(Config::default());
+    let known = HashSet::new();
+    let actions = engine.on_objects(PeerId::random(), vec![overstated], &known, Utc::now());
+    assert!(actions.is_empty(), "{:?}", kinds(&actions));
+    let actions = engine.on_objects(PeerId::random(), vec![harder], &known, Utc::now());
+    assert_eq!(kinds(&actions), vec!["store", "process", "announce"]);
+}
@@ -0,0 +1,252 @@
+#![cfg(feature = "legacy-bridge")]
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use async_std::{
+    future,
+    io::{ReadExt, WriteExt},
+    net::TcpStream,
+    task,
+};
+use nantoka_core::{
+    config::Config,
+    network::legacy::wire::{
+        Header, InventoryHash, LegacyObject, Message, NetAddr, Version, WireError, HEADER_LENGTH,
+        MAGIC, MAX_INVENTORY_VECTORS, MAX_PAYLOAD_SIZE, PROTOCOL_VERSION, STREAM,
+    },
+    testing,
+};
+
+fn version() -> Message {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 8444));
+    Message::Version(Version {
+        version: PROTOCOL_VERSION,
+        services: 1,
+        timestamp: 1_700_000_000,
+        addr_recv: NetAddr::new(addr),
+        addr_from: NetAddr::new("[::1]:8444".parse().unwrap()),
+        nonce: 42,
+        user_agent: "/test:1.0/".to_string(),
+        streams: vec![STREAM],
+    })
+}
+
+fn hashes(range: std::ops::Range<u64>) -> Vec<InventoryHash> {
+    range
+        .map(|i| {
+            let mut hash = [0; 32];
+            hash[..8].copy_from_slice(&i.to_be_bytes());
+            hash
+        })
+        .collect()
+}
+
+fn object() -> LegacyObject {
+    LegacyObject {
+        nonce: 1,
+        expires: 1_700_000_000,
+        object_type: 2,
+        version: 4,
+        stream: STREAM,
+        payload: vec![7; 100],
+    }
+}
+
+/// One message of every type
+fn messages() -> Vec<Message> {
+    vec![
+        version(),
+        Message::Verack,
+        Message::Inv(hashes(0..3)),
+        Message::GetData(hashes(3..5)),
+        Message::Object(object()),
+        Message::Ping,
+        Message::Pong,
+        Message::Other("addr".to_string()),
+    ]
+}
+
+fn header(command: &str, length: u32) -> [u8; HEADER_LENGTH] {
+    let mut data = [0; HEADER_LENGTH];
+    data[..4].copy_from_slice(&MAGIC.to_be_bytes());
+    data[4..4 + command.len()].copy_from_slice(command.as_bytes());
+    data[16..20].copy_from_slice(&length.to_be_bytes());
+    data
+}
+
+fn decode(data: &[u8]) -> Result<Message, WireError> {
+    let header = Header::decode(data[..HEADER_LENGTH].try_into().unwrap())?;
+    let payload = &data[HEADER_LENGTH..];
+    assert_eq!(header.length, payload.len());
+    header.verify(payload)?;
+    Message::decode(&header.command, payload)
+}
+
+#[test]
+fn messages_roundtrip() {
+    for message in messages() {
+        assert_eq!(decode(&message.encode()), Ok(message.clone()));
+    }
+    let object = object();
+    assert_eq!(LegacyObject::decode(&object.encode()), Ok(object));
+}
+
+#[test]
+fn truncated_messages_are_rejected() {
+    for message in messages() {
+        let data = message.encode();
+        let payload = &data[HEADER_LENGTH..];
+        // object payload is opaque, only its fields are checked
+        let checked = match &message {
+            Message::Object(o) => payload.len() - o.payload.len(),
+            _ => payload.len(),
+        };
+        for length in 0..checked {
+            assert!(
+                Message::decode(message.command(), &payload[..length]).is_err(),
+                "{} truncated to {} bytes",
+                message.command(),
+                length
+            );
+        }
+        if !payload.is_empty() {
+            let header = Header::decode(data[..HEADER_LENGTH].try_into().unwrap()).unwrap();
+            assert_eq!(
+                header.verify(&payload[..payload.len() - 1]),
+                Err(WireError::BadChecksum)
+            );
+        }
+    }
+}
+
+#[test]
+fn oversized_var_ints_are_rejected() {
+    let huge = [0xff; 9];
+    let mut inv = huge.to_vec();
+    inv.extend_from_slice(&[0; 64]);
+    assert_eq!(
+        Message::decode("inv", &inv),
+        Err(WireError::Malformed("inventory"))
+    );
+    let mut too_many = vec![0xfe];
+    too_many.extend_from_slice(&(MAX_INVENTORY_VECTORS as u32 + 1).to_be_bytes());
+    assert_eq!(
+        Message::decode("getdata", &too_many),
+        Err(WireError::Malformed("inventory"))
+    );
+
+    // user agent length and number of streams
+    let Message::Version(v) = version() else {
+        unreachable!()
+    };
+    let fixed = {
+        let data = version().encode();
+        data[HEADER_LENGTH..data.len() - v.user_agent.len() - 3].to_vec()
+    };
+    let mut long_user_agent = fixed.clone();
+    long_user_agent.extend_from_slice(&huge);
+    long_user_agent.extend_from_slice(v.user_agent.as_bytes());
+    assert_eq!(
+        Message::decode("version", &long_user_agent),
+        Err(WireError::Malformed("version"))
+    );
+    let mut many_streams = fixed;
+    many_streams.push(0);
+    many_streams.extend_from_slice(&huge);
+    many_streams.push(1);
+    assert_eq!(
+        Message::decode("version", &many_streams),
+        Err(WireError::Malformed("version"))
+    );
+
+    let mut object = object().encode()[..20].to_vec();
+    object.extend_from_slice(&huge[..3]);
+    assert_eq!(
+        Message::decode("object", &object),
+        Err(WireError::Malformed("object"))
+    );
+}
+
+#[test]
+fn oversized_payloads_are_rejected() {
+    for command in ["version", "inv", "getdata", "object", "ping", "addr"] {
+        let length = MAX_PAYLOAD_SIZE as u32 + 1;
+        assert_eq!(
+            Header::decode(&header(command, length)),
+            Err(WireError::TooLarge(length as usize))
+        );
+        assert!(Header::decode(&header(command, MAX_PAYLOAD_SIZE as u32)).is_ok());
+    }
+    let mut bad_magic = header("inv", 0);
+    bad_magic[0] = 0;
+    assert_eq!(Header::decode(&bad_magic), Err(WireError::BadMagic));
+}
+
+async fn read_message(stream: &mut TcpStream) -> Option<Message> {
+    let mut header = [0; HEADER_LENGTH];
+    stream.read_exact(&mut header).await.ok()?;
+    let header = Header::decode(&header).unwrap();
+    let mut payload = vec![0; header.length];
+    stream.read_exact(&mut payload).await.ok()?;
+    Some(Message::decode(&header.command, &payload).unwrap())
+}
+
+#[async_std::test]
+async fn legacy_node_announcing_too_many_objects_is_dropped() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let _node = testing::spawn_node(Config {
+        legacy_listen_address: Some(SocketAddr::from((Ipv4Addr::LOCALHOST, port))),
+        ..testing::test_config()
+    })
+    .await;
+    let mut stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(s) => break s,
+            // bridge might not listen yet
+            Err(_) => task::sleep(Duration::from_millis(50)).await,
+        }
+    };
+
+    stream.write_all(&version().encode()).await.unwrap();
+    let (mut got_version, mut got_verack) = (false, false);
+    while !(got_version && got_verack) {
+        match read_message(&mut stream)
+            .await
+            .expect("bridge to shake hands")
+        {
+            Message::Version(_) => {
+                stream.write_all(&Message::Verack.encode()).await.unwrap();
+                got_version = true;
+            }
+            Message::Verack => got_verack = true,
+            _ => {}
+        }
+    }
+
+    // all announced objects are requested, up to the limit
+    let announced = hashes(0..MAX_INVENTORY_VECTORS as u64);
+    stream
+        .write_all(&Message::Inv(announced.clone()).encode())
+        .await
+        .unwrap();
+    assert_eq!(
+        read_message(&mut stream).await,
+        Some(Message::GetData(announced))
+    );
+    let more = hashes(MAX_INVENTORY_VECTORS as u64..MAX_INVENTORY_VECTORS as u64 + 1);
+    stream
+        .write_all(&Message::Inv(more).encode())
+        .await
+        .unwrap();
+    let closed = future::timeout(Duration::from_secs(10), read_message(&mut stream))
+        .await
+        .expect("bridge to close the connection");
+    assert_eq!(closed, None);
+}
@@ -0,0 +1,1985 @@
+use std::time::Duration;
+#[cfg(feature = "sqlite")]
+use std::{fs, path::PathBuf};
+
+use futures::StreamExt;
+use nantoka_core::{
+    config::{Config, NodeRole, ObjectType},
+    network::{
+        address::Address,
+        messages::InventoryCursor,
+        node::{
+            client,
+            worker::{self, Avatar, Folder, PowerMode},
+        },
+    },
+    repositories::models::{AutoReply, FilterRule},
+    testing,
+};
+#[cfg(feature = "sqlite")]
+use nantoka_core::{ImportProgress, ImportStage, NodeClient};
+#[cfg(feature = "sqlite")]
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, Connection};
+
+/// Includes PoW of the getpubkey, pubkey, message and ack objects
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[async_std::test]
+async fn message_is_delivered() {
+    let mut nodes = testing::spawn_network(2).await;
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = nodes[1]
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+
+    let mut events = nodes[0].client.subscribe_message_status().await.unwrap();
+    let hashes = nodes[0]
+        .client
+        .send_message(
+            alice.clone(),
+            vec![bob.clone()],
+            "Hello".to_string(),
+            "Hello from Alice".to_string(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(hashes.len(), 1);
+    testing::wait_for_status(&mut events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+
+    let inbox = nodes[1]
+        .client
+        .get_messages(bob.clone(), Folder::Inbox)
+        .await
+        .unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].sender, alice);
+    assert_eq!(inbox[0].recipient, bob);
+    assert!(!inbox[0].read);
+    assert!(inbox[0].verified);
+    assert!(inbox[0].signer_fingerprint.is_some());
+    assert_eq!(inbox[0].recipient_label.as_deref(), Some("bob"));
+    assert_eq!(inbox[0].sender_display_name(), alice);
+
+    nodes[1]
+        .client
+        .add_contact(alice.clone(), "Alice".to_string())
+        .await
+        .unwrap();
+    let inbox = nodes[1]
+        .client
+        .get_messages(bob.clone(), Folder::Inbox)
+        .await
+        .unwrap();
+    assert_eq!(inbox[0].sender_display_name(), format!("Alice ({})", alice));
+
+    let stats = nodes[1].client.get_folder_stats(bob.clone()).await.unwrap();
+    assert_eq!((stats.inbox.total, stats.inbox.unread), (1, 1));
+    nodes[1]
+        .client
+        .mark_read(inbox[0].hash.clone(), true)
+        .await
+        .unwrap();
+    let stats = nodes[1].client.get_folder_stats(bob).await.unwrap();
+    assert_eq!(stats.inbox.unread, 0);
+}
+
+#[async_std::test]
+async fn relay_has_no_identities() {
+    let mut relay = testing::spawn_node(Config {
+        role: NodeRole::Relay,
+        ..testing::test_config()
+    })
+    .await;
+    assert!(relay
+        .client
+        .generate_new_identity("relay".to_string())
+        .await
+        .is_err());
+}
+
+#[async_std::test]
+async fn message_waiting_for_pubkey_is_cancelled() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    // nobody can answer the pubkey request, so the message keeps waiting
+    let mut other = testing::spawn_node(testing::test_config()).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = other
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+
+    let mut events = node.client.subscribe_message_status().await.unwrap();
+    let hashes = node
+        .client
+        .send_message(
+            alice.clone(),
+            vec![bob],
+            "Hello".to_string(),
+            "Never mind".to_string(),
+        )
+        .await
+        .unwrap();
+    node.client.cancel_send(hashes[0].clone()).await.unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Cancelled", DELIVERY_TIMEOUT).await;
+
+    let sent = node.client.get_messages(alice, Folder::Sent).await.unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].status, "Cancelled");
+}
+
+#[async_std::test]
+async fn cancelled_message_is_not_retried() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    // keys of own identities are known, so the message goes straight to PoW
+    let bob = node
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    // PoW waits in the queue until it's cancelled
+    node.client
+        .set_power_mode(PowerMode::LowPower)
+        .await
+        .unwrap();
+
+    let mut events = node.client.subscribe_message_status().await.unwrap();
+    let hashes = node
+        .client
+        .send_message(
+            alice.clone(),
+            vec![bob],
+            "Hello".to_string(),
+            "Never mind".to_string(),
+        )
+        .await
+        .unwrap();
+    node.client.cancel_send(hashes[0].clone()).await.unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Cancelled", DELIVERY_TIMEOUT).await;
+
+    node.client.set_power_mode(PowerMode::Normal).await.unwrap();
+    async_std::task::sleep(Duration::from_secs(2)).await;
+    assert!(node.client.get_pow_queue().await.unwrap().is_empty());
+    let sent = node.client.get_messages(alice, Folder::Sent).await.unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].status, "Cancelled");
+    assert!(
+        events.try_next().is_err(),
+        "no status changes after cancelling"
+    );
+}
+
+#[async_std::test]
+async fn message_waiting_for_pubkey_too_long_fails() {
+    let mut node = testing::spawn_node(Config {
+        pubkey_wait_timeout: chrono::Duration::seconds(1),
+        ..testing::test_config()
+    })
+    .await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    // nobody can answer the pubkey request
+    let bob = Address::generate_seeded(1).string_repr;
+
+    let mut events = node.client.subscribe_message_status().await.unwrap();
+    let hashes = node
+        .client
+        .send_message(
+            alice.clone(),
+            vec![bob],
+            "Hello".to_string(),
+            "Are you there?".to_string(),
+        )
+        .await
+        .unwrap();
+    // stale messages are checked on maintenance
+    testing::wait_for_status(&mut events, &hashes[0], "Failed", Duration::from_secs(120)).await;
+
+    let sent = node
+        .client
+        .get_messages(alice.clone(), Folder::Sent)
+        .await
+        .unwrap();
+    assert_eq!(sent[0].status, "Failed");
+    assert!(sent[0].failure_reason.is_some());
+
+    // retried message waits for the pubkey again
+    node.client.retry_message(hashes[0].clone()).await.unwrap();
+    let outbox = node
+        .client
+        .get_messages(alice, Folder::Outbox)
+        .await
+        .unwrap();
+    assert_eq!(outbox.len(), 1);
+    assert_eq!(outbox[0].status, "WaitingForPubkey");
+    assert!(outbox[0].failure_reason.is_none());
+}
+
+#[async_std::test]
+async fn message_with_unreachable_pow_target_fails() {
+    // difficulty is so high that the target is zero, the test divisor would
+    // bring it back into reach
+    let mut node = testing::spawn_node(Config {
+        msg_ttl: chrono::Duration::days(28),
+        pow_difficulty_multiplier: 2_000_000.0,
+        pow_trials_divisor: 1,
+        ..testing::test_config()
+    })
+    .await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    // keys of own identities are known, so the message goes straight to PoW
+    let bob = node
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+
+    let mut events = node.client.subscribe_message_status().await.unwrap();
+    let hashes = node
+        .client
+        .send_message(
+            alice.clone(),
+            vec![bob],
+            "Hello".to_string(),
+            "Hello from Alice".to_string(),
+        )
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Failed", DELIVERY_TIMEOUT).await;
+
+    let sent = node.client.get_messages(alice, Folder::Sent).await.unwrap();
+    assert_eq!(sent[0].status, "Failed");
+    assert!(sent[0]
+        .failure_reason
+        .as_deref()
+        .is_some_and(|r| r.contains("unreachable")));
+    assert!(node.client.get_pow_queue().await.unwrap().is_empty());
+}
+
+#[cfg(feature = "sqlite")]
+#[async_std::test]
+async fn message_whose_object_cant_be_stored_fails() {
+    let data_dir = testing::data_dir("store-fails");
+    let mut node = testing::spawn_sqlite_node(&data_dir).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = node
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    // constraint failures aren't transient, so the object isn't stored again
+    testing::execute_sql(
+        &data_dir,
+        "CREATE TRIGGER fail_inventory BEFORE INSERT ON inventory \
+        BEGIN SELECT RAISE(ABORT, 'no space left'); END",
+    )
+    .await;
+
+    let mut events = node.client.subscribe_message_status().await.unwrap();
+    let hashes = node
+        .client
+        .send_message(alice.clone(), vec![bob], "Hi".to_string(), "Hi".to_string())
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Failed", DELIVERY_TIMEOUT).await;
+
+    let sent = node.client.get_messages(alice, Folder::Sent).await.unwrap();
+    assert_eq!(sent[0].status, "Failed");
+    assert!(sent[0]
+        .failure_reason
+        .as_deref()
+        .is_some_and(|r| r.contains("no space left")));
+    node.client.shutdown().await.unwrap();
+    fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[async_std::test]
+async fn draft_is_kept_if_a_recipient_fails() {
+    let data_dir = testing::data_dir("draft-kept");
+    let mut node = testing::spawn_sqlite_node(&data_dir).await;
+    let mut identities = Vec::new();
+    for label in ["alice", "bob", "carol"] {
+        identities.push(
+            node.client
+                .generate_new_identity(label.to_string())
+                .await
+                .unwrap(),
+        );
+    }
+    let [alice, bob, carol] = identities.try_into().unwrap();
+    testing::execute_sql(
+        &data_dir,
+        &format!(
+            "CREATE TRIGGER fail_carol BEFORE INSERT ON messages \
+            WHEN NEW.recipient = '{}' AND NEW.status != 'Failed' \
+            BEGIN SELECT RAISE(ABORT, 'no space left'); END",
+            carol
+        ),
+    )
+    .await;
+    let draft = node
+        .client
+        .save_draft(
+            alice.clone(),
+            bob.clone(),
+            "Hi".to_string(),
+            "Hi".to_string(),
+        )
+        .await
+        .unwrap();
+
+    let hashes = node
+        .client
+        .send_draft(
+            draft.clone(),
+            alice.clone(),
+            vec![bob, carol.clone()],
+            "Hi".to_string(),
+            "Hi".to_string(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(hashes.len(), 2);
+    let sent = node
+        .client
+        .get_messages(alice.clone(), Folder::Sent)
+        .await
+        .unwrap();
+    let failed = sent.into_iter().find(|m| m.hash == hashes[1]).unwrap();
+    assert_eq!(failed.recipient, carol);
+    assert_eq!(failed.status, "Failed");
+    assert!(failed
+        .failure_reason
+        .is_some_and(|r| r.contains("no space left")));
+    let drafts = node
+        .client
+        .get_messages(alice, Folder::Drafts)
+        .await
+        .unwrap();
+    assert_eq!(drafts.len(), 1);
+    assert_eq!(drafts[0].hash, draft);
+    node.client.shutdown().await.unwrap();
+    fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[async_std::test]
+async fn sending_from_unknown_identity_fails() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let bob = node
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    let stranger = Address::generate().string_repr;
+    assert!(node
+        .client
+        .send_message(stranger, vec![bob], "Hi".to_string(), "Hi".to_string())
+        .await
+        .is_err());
+    // the worker keeps running
+    assert_eq!(node.client.get_own_identities().await.unwrap().len(), 1);
+}
+
+#[async_std::test]
+async fn pubkey_request_is_retried_on_demand() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let mut other = testing::spawn_node(testing::test_config()).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = other
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+
+    let mut events = node.client.subscribe_message_status().await.unwrap();
+    let hashes = node
+        .client
+        .send_message(
+            alice,
+            vec![bob],
+            "Hello".to_string(),
+            "Are you there?".to_string(),
+        )
+        .await
+        .unwrap();
+    let event = testing::wait_for_status(
+        &mut events,
+        &hashes[0],
+        "WaitingForPubkey",
+        DELIVERY_TIMEOUT,
+    )
+    .await;
+    let request = event.pubkey_request.expect("request to be attached");
+    assert_eq!(request.retries, 0);
+    assert_eq!(
+        node.client
+            .get_pubkey_request(hashes[0].clone())
+            .await
+            .unwrap(),
+        Some(request.clone())
+    );
+
+    let retried = node
+        .client
+        .retry_pubkey_request(hashes[0].clone())
+        .await
+        .unwrap();
+    assert_eq!(retried.since, request.since);
+    assert_eq!(retried.retries, 1);
+    assert!(node
+        .client
+        .retry_pubkey_request("unknown".to_string())
+        .await
+        .is_err());
+}
+
+#[cfg(feature = "sqlite")]
+#[async_std::test]
+async fn resent_message_keeps_its_ttl_after_restart() {
+    let data_dir = testing::data_dir("resend");
+    let mut node = testing::spawn_sqlite_node(&data_dir).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    // keys of own identities are known, so the message goes straight to PoW
+    let bob = node
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    // PoW isn't done before the node is stopped
+    node.client
+        .set_power_mode(PowerMode::LowPower)
+        .await
+        .unwrap();
+    node.client
+        .send_message(alice, vec![bob], "Hi".to_string(), "Hi".to_string())
+        .await
+        .unwrap();
+    node.client.shutdown().await.unwrap();
+
+    // the message was sent twice before, so its TTL is four times longer
+    testing::execute_sql(&data_dir, "UPDATE messages SET resend_count = 2").await;
+    let mut node = testing::spawn_sqlite_node(&data_dir).await;
+    // object is re-created once PoW of waiting messages is resumed
+    let recreated = async {
+        loop {
+            let inventory = node.client.get_inventory(None, 100).await.unwrap();
+            match inventory
+                .into_iter()
+                .find(|item| item.object_type == Some(ObjectType::Msg))
+            {
+                Some(msg) => return msg,
+                None => async_std::task::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    };
+    let msg = async_std::future::timeout(DELIVERY_TIMEOUT, recreated)
+        .await
+        .expect("message object to be re-created");
+    assert!(msg.expires > chrono::Utc::now() + testing::test_config().msg_ttl * 3);
+    node.client.shutdown().await.unwrap();
+    fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[async_std::test]
+async fn scheduled_message_is_sent_when_due() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let mut other = testing::spawn_node(testing::test_config()).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = other
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+
+    let mut events = node.client.subscribe_message_status().await.unwrap();
+    let hashes = node
+        .client
+        .schedule_message(
+            None,
+            alice.clone(),
+            vec![bob],
+            "Hello".to_string(),
+            "Later".to_string(),
+            chrono::Utc::now() + chrono::Duration::seconds(1),
+        )
+        .await
+        .unwrap();
+    let outbox = node
+        .client
+        .get_messages(alice.clone(), Folder::Outbox)
+        .await
+        .unwrap();
+    assert_eq!(outbox[0].status, "Scheduled");
+    let sent = node
+        .client
+        .get_messages(alice.clone(), Folder::Sent)
+        .await
+        .unwrap();
+    assert!(sent.is_empty());
+    // nodes aren't connected, so the message waits for the pubkey once it's due
+    testing::wait_for_status(
+        &mut events,
+        &hashes[0],
+        "WaitingForPubkey",
+        DELIVERY_TIMEOUT,
+    )
+    .await;
+    let stats = node.client.get_folder_stats(alice).await.unwrap();
+    assert_eq!(stats.outbox.total, 1);
+    assert_eq!(stats.sent.total, 0);
+}
+
+#[async_std::test]
+async fn idle_peer_is_disconnected() {
+    // only the active peer shares the topic with the node, so that the others don't
+    // relay its objects and look active themselves
+    let quiet_config = || Config {
+        pubsub_topic: "quiet".to_string(),
+        ..testing::test_config()
+    };
+    let mut protected = testing::spawn_node(quiet_config()).await;
+    let idle = testing::spawn_node(quiet_config()).await;
+    let mut active = testing::spawn_node(testing::test_config()).await;
+    let mut node = testing::spawn_node(Config {
+        bootstrap_peers: vec![protected.address.clone()],
+        peer_idle_timeout: Some(chrono::Duration::seconds(3)),
+        ..testing::test_config()
+    })
+    .await;
+    node.client.dial(protected.address.clone()).await.unwrap();
+    node.client.dial(idle.address.clone()).await.unwrap();
+    node.client.dial(active.address.clone()).await.unwrap();
+    let alice = active
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+
+    // idle peers are released on one check and disconnected on the next one, since
+    // the gossipsub mesh keeps their connections open
+    let disconnect = async {
+        while node
+            .client
+            .get_connected_peers()
+            .await
+            .unwrap()
+            .contains(&idle.peer_id)
+        {
+            active
+                .client
+                .send_broadcast(alice.clone(), "Hi".to_string(), "Hi".to_string())
+                .await
+                .unwrap();
+            async_std::task::sleep(Duration::from_millis(500)).await;
+        }
+    };
+    async_std::future::timeout(Duration::from_secs(60), disconnect)
+        .await
+        .expect("idle peer to be disconnected");
+    let peers = node.client.get_connected_peers().await.unwrap();
+    assert!(peers.contains(&protected.peer_id));
+    assert!(peers.contains(&active.peer_id));
+    let peers = protected.client.get_connected_peers().await.unwrap();
+    assert!(peers.contains(&node.peer_id));
+}
+
+#[async_std::test]
+async fn banned_peer_is_disconnected() {
+    let mut nodes = testing::spawn_network(2).await;
+    let banned = nodes[1].peer_id;
+    nodes[0]
+        .client
+        .ban_peer(banned.to_string(), Some("testing".to_string()))
+        .await
+        .unwrap();
+    let bans = nodes[0].client.list_banned().await.unwrap();
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0].target, banned.to_string());
+
+    let disconnect = async {
+        while nodes[0]
+            .client
+            .get_connected_peers()
+            .await
+            .unwrap()
+            .contains(&banned)
+        {
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    async_std::future::timeout(Duration::from_secs(10), disconnect)
+        .await
+        .expect("banned peer to be disconnected");
+    let address = nodes[1].address.clone();
+    assert!(nodes[0].client.dial(address).await.is_err());
+
+    nodes[0]
+        .client
+        .unban_peer(banned.to_string())
+        .await
+        .unwrap();
+    assert!(nodes[0].client.list_banned().await.unwrap().is_empty());
+    assert!(nodes[0]
+        .client
+        .unban_peer(banned.to_string())
+        .await
+        .is_err());
+}
+
+#[async_std::test]
+async fn pow_estimate_grows_with_message_size() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let small = node.client.estimate_pow(100, None).await.unwrap();
+    let large = node.client.estimate_pow(100_000, None).await.unwrap();
+    assert!(small.trials_per_second > 0.0);
+    assert!(large.expected_trials > small.expected_trials * 10.0);
+    assert!(large.expected_duration > small.expected_duration);
+}
+
+#[async_std::test]
+async fn chan_message_reaches_members() {
+    let mut nodes = testing::spawn_network(2).await;
+    let chan = nodes[0]
+        .client
+        .join_chan("testing chan".to_string(), None)
+        .await
+        .unwrap();
+    assert!(nodes[0]
+        .client
+        .join_chan("testing chan".to_string(), None)
+        .await
+        .is_err());
+    assert!(nodes[1]
+        .client
+        .join_chan("another chan".to_string(), Some(chan.clone()))
+        .await
+        .is_err());
+    assert_eq!(
+        nodes[1]
+            .client
+            .join_chan("testing chan".to_string(), Some(chan.clone()))
+            .await
+            .unwrap(),
+        chan
+    );
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+
+    let mut events = nodes[1].client.subscribe_message_status().await.unwrap();
+    let hashes = nodes[0]
+        .client
+        .send_message(
+            alice.clone(),
+            vec![chan.clone()],
+            "Hello".to_string(),
+            "Hello, chan".to_string(),
+        )
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Received", DELIVERY_TIMEOUT).await;
+
+    let inbox = nodes[1]
+        .client
+        .get_messages(chan.clone(), Folder::Inbox)
+        .await
+        .unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].sender, alice);
+    assert_eq!(inbox[0].recipient, chan);
+    // the sent message shows up in the chan inbox of the sender as well
+    let inbox = nodes[0]
+        .client
+        .get_messages(chan, Folder::Inbox)
+        .await
+        .unwrap();
+    assert_eq!(inbox.len(), 1);
+}
+
+#[async_std::test]
+async fn sender_is_resolved_into_contact() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let identity = node
+        .client
+        .generate_new_identity("me".to_string())
+        .await
+        .unwrap();
+    let contact = testing::spawn_node(testing::test_config())
+        .await
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    node.client
+        .add_contact(contact.clone(), "bob".to_string())
+        .await
+        .unwrap();
+
+    let resolved = node.client.resolve_contact(contact).await.unwrap().unwrap();
+    assert_eq!(resolved.label, "bob");
+    assert!(node
+        .client
+        .resolve_contact(identity)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(node
+        .client
+        .resolve_contact("BM-invalid".to_string())
+        .await
+        .is_err());
+}
+
+#[async_std::test]
+async fn identities_are_deleted_at_once() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let mut identities = Vec::new();
+    for label in ["first", "second"] {
+        identities.push(
+            node.client
+                .generate_new_identity(label.to_string())
+                .await
+                .unwrap(),
+        );
+    }
+    let contact = testing::spawn_node(testing::test_config())
+        .await
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    node.client
+        .add_contact(contact.clone(), "bob".to_string())
+        .await
+        .unwrap();
+
+    // contacts aren't identities, so nothing is deleted
+    let mut addresses = identities.clone();
+    addresses.push(contact);
+    assert!(node.client.delete_identities(addresses).await.is_err());
+    assert_eq!(node.client.get_own_identities().await.unwrap().len(), 2);
+
+    node.client.delete_identities(identities).await.unwrap();
+    assert!(node.client.get_own_identities().await.unwrap().is_empty());
+    assert_eq!(node.client.get_contacts().await.unwrap().len(), 1);
+}
+
+#[async_std::test]
+async fn avatar_falls_back_to_identicon() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let identity = node
+        .client
+        .generate_new_identity("me".to_string())
+        .await
+        .unwrap();
+    let stranger = testing::spawn_node(testing::test_config())
+        .await
+        .client
+        .generate_new_identity("stranger".to_string())
+        .await
+        .unwrap();
+
+    let Avatar::Identicon(seed) = node.client.get_avatar(identity.clone()).await.unwrap() else {
+        panic!("identity has no avatar set");
+    };
+    assert_eq!(
+        node.client.get_avatar(identity.clone()).await.unwrap(),
+        Avatar::Identicon(seed)
+    );
+    assert!(matches!(
+        node.client.get_avatar(stranger.clone()).await.unwrap(),
+        Avatar::Identicon(_)
+    ));
+
+    node.client
+        .set_avatar(identity.clone(), Some(vec![1, 2, 3]))
+        .await
+        .unwrap();
+    assert_eq!(
+        node.client.get_avatar(identity.clone()).await.unwrap(),
+        Avatar::Image(vec![1, 2, 3])
+    );
+    assert!(node
+        .client
+        .set_avatar(identity.clone(), Some(vec![0; 1024 * 1024]))
+        .await
+        .is_err());
+    assert!(node
+        .client
+        .set_avatar(stranger, Some(vec![1, 2, 3]))
+        .await
+        .is_err());
+
+    node.client
+        .set_avatar(identity.clone(), None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        node.client.get_avatar(identity).await.unwrap(),
+        Avatar::Identicon(_)
+    ));
+}
+
+#[async_std::test]
+async fn message_is_received_with_sharded_topics() {
+    let config = Config {
+        topic_sharding: true,
+        ..testing::test_config()
+    };
+    let mut sender = testing::spawn_node(config.clone()).await;
+    let mut receiver = testing::spawn_node(config).await;
+    receiver.client.dial(sender.address.clone()).await.unwrap();
+    let chan = sender
+        .client
+        .join_chan("sharded chan".to_string(), None)
+        .await
+        .unwrap();
+    receiver
+        .client
+        .join_chan("sharded chan".to_string(), Some(chan.clone()))
+        .await
+        .unwrap();
+    let alice = sender
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+
+    let mut events = receiver.client.subscribe_message_status().await.unwrap();
+    let hashes = sender
+        .client
+        .send_message(
+            alice,
+            vec![chan],
+            "Hello".to_string(),
+            "Hello over the msg topic".to_string(),
+        )
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Received", DELIVERY_TIMEOUT).await;
+}
+
+#[async_std::test]
+async fn exported_messages_are_imported_once() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let eml = format!(
+        "From: Bob <BM-2cTestSender@bitmessage>\r\nTo: {}@bitmessage\r\n\
+        Date: Tue, 1 Jul 2003 10:52:37 +0200\r\nSubject: Hello\r\n\r\nFrom the archive\r\n",
+        alice
+    );
+    let imported = node
+        .client
+        .import_messages(alice.clone(), Folder::Inbox, eml.into_bytes())
+        .await
+        .unwrap();
+    assert_eq!(imported, 1);
+    let inbox = node
+        .client
+        .get_messages(alice.clone(), Folder::Inbox)
+        .await
+        .unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].sender, "BM-2cTestSender");
+
+    let mbox = node
+        .client
+        .export_messages(vec![inbox[0].hash.clone()], true)
+        .await
+        .unwrap();
+    // body line looking like a separator is escaped
+    assert!(String::from_utf8_lossy(&mbox).contains("\n>From the archive"));
+    let imported = node
+        .client
+        .import_messages(alice, Folder::Inbox, mbox)
+        .await
+        .unwrap();
+    assert_eq!(imported, 0);
+}
+
+#[cfg(feature = "sqlite")]
+async fn import_pybitmessage(client: &mut NodeClient, dir: PathBuf) -> Vec<(ImportStage, usize)> {
+    let mut progress = client.import_pybitmessage(dir).await.unwrap();
+    while let Some(p) = progress.next().await {
+        if let ImportProgress::Finished(res) = p {
+            return res.unwrap();
+        }
+    }
+    panic!("import didn't finish");
+}
+
+#[cfg(feature = "sqlite")]
+#[async_std::test]
+async fn pybitmessage_data_dir_is_imported_once() {
+    let mut source = testing::spawn_node(testing::test_config()).await;
+    let alice = source
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let keys_dat = source
+        .client
+        .export_identities(vec![alice.clone()])
+        .await
+        .unwrap();
+
+    let dir = testing::data_dir("pybitmessage");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("keys.dat"), keys_dat).unwrap();
+    // v3 address, it's converted into v4 one with the same ripe
+    let bob_v3 = "BM-2D7T5LAQNjJucgWrQqh9tdM7pZK4v5PHeP";
+    let bob = Address::new([vec![0], vec![0x11; 19]].concat()).string_repr;
+    let mut conn = SqliteConnectOptions::new()
+        .filename(dir.join("messages.dat"))
+        .create_if_missing(true)
+        .connect()
+        .await
+        .unwrap();
+    for query in [
+        "CREATE TABLE addressbook (label text, address text)",
+        "CREATE TABLE subscriptions (label text, address text, enabled bool)",
+        "CREATE TABLE inbox (msgid blob, toaddress text, fromaddress text, subject text,
+            received text, message text, folder text, read bool)",
+        "CREATE TABLE sent (msgid blob, toaddress text, fromaddress text, subject text,
+            message text, senttime integer, status text, folder text)",
+    ] {
+        sqlx::query(query).execute(&mut conn).await.unwrap();
+    }
+    sqlx::query("INSERT INTO addressbook VALUES ('Bob', ?)")
+        .bind(bob_v3)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO inbox VALUES (x'01', ?, ?, 'Hello', '1600000000', 'Hi', 'inbox', 0)")
+        .bind(&alice)
+        .bind(bob_v3)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    // unsent message is skipped
+    sqlx::query(
+        "INSERT INTO sent VALUES (x'02', ?1, ?2, 'Re: Hello', 'Hi', 1600000100, 'ackreceived', 'trash'),
+            (x'03', ?1, ?2, 'Queued', '', 0, 'msgqueued', 'sent')",
+    )
+    .bind(bob_v3)
+    .bind(&alice)
+    .execute(&mut conn)
+    .await
+    .unwrap();
+    conn.close().await.unwrap();
+
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let imported = import_pybitmessage(&mut node.client, dir.clone()).await;
+    assert_eq!(
+        imported,
+        vec![
+            (ImportStage::Identities, 1),
+            (ImportStage::Contacts, 1),
+            (ImportStage::Subscriptions, 0),
+            (ImportStage::Messages, 2),
+        ]
+    );
+    let contacts = node.client.get_contacts().await.unwrap();
+    assert!(contacts
+        .iter()
+        .any(|c| c.string_repr == bob && c.label == "Bob"));
+    let inbox = node
+        .client
+        .get_messages(alice.clone(), Folder::Inbox)
+        .await
+        .unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].sender, bob);
+    assert!(!inbox[0].read);
+    let trash = node
+        .client
+        .get_messages(alice.clone(), Folder::Trash)
+        .await
+        .unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].recipient, bob);
+
+    let imported = import_pybitmessage(&mut node.client, dir.clone()).await;
+    assert!(imported.iter().all(|(_, count)| *count == 0));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[async_std::test]
+async fn message_copy_reaches_paired_device() {
+    let mut nodes = testing::spawn_network(3).await;
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = nodes[1]
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    nodes[0]
+        .client
+        .add_contact(bob.clone(), "Bob".to_string())
+        .await
+        .unwrap();
+
+    // keys of the identity are on node 1, node 2 only gets copies of messages
+    assert!(nodes[1]
+        .client
+        .create_device_key(bob.clone())
+        .await
+        .is_err());
+    let code = nodes[2]
+        .client
+        .create_device_key(bob.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        nodes[2]
+            .client
+            .create_device_key(bob.clone())
+            .await
+            .unwrap(),
+        code
+    );
+    nodes[1]
+        .client
+        .pair_device(code, "laptop".to_string())
+        .await
+        .unwrap();
+    let devices = nodes[1].client.get_device_keys(bob.clone()).await.unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].label, "laptop");
+
+    // pubkey listing the device is published on pairing
+    let pubkey = async {
+        while nodes[0]
+            .client
+            .resolve_contact(bob.clone())
+            .await
+            .unwrap()
+            .and_then(|c| c.public_encryption_key)
+            .is_none()
+        {
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    async_std::future::timeout(DELIVERY_TIMEOUT, pubkey)
+        .await
+        .expect("pubkey to be received");
+
+    let mut events = nodes[0].client.subscribe_message_status().await.unwrap();
+    let hashes = nodes[0]
+        .client
+        .send_message(
+            alice.clone(),
+            vec![bob.clone()],
+            "Hello".to_string(),
+            "Hello to all your devices".to_string(),
+        )
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+
+    let copy = async {
+        loop {
+            let inbox = nodes[2]
+                .client
+                .get_messages(bob.clone(), Folder::Inbox)
+                .await
+                .unwrap();
+            if !inbox.is_empty() {
+                return inbox;
+            }
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    let inbox = async_std::future::timeout(Duration::from_secs(30), copy)
+        .await
+        .expect("copy to reach the paired device");
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].sender, alice);
+}
+
+#[async_std::test]
+async fn messages_are_delivered_after_keys_are_rotated() {
+    let mut nodes = testing::spawn_network(2).await;
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = nodes[1]
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+
+    // bob learns the original keys of alice
+    let mut events = nodes[1].client.subscribe_message_status().await.unwrap();
+    let hashes = nodes[1]
+        .client
+        .send_message(
+            bob.clone(),
+            vec![alice.clone()],
+            "Hello".to_string(),
+            "Hello from Bob".to_string(),
+        )
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+
+    nodes[0].client.rotate_keys(alice.clone()).await.unwrap();
+    assert!(nodes[0]
+        .client
+        .export_identities(vec![alice.clone()])
+        .await
+        .is_err());
+    let rotated = async {
+        loop {
+            let contacts = nodes[1].client.get_contacts().await.unwrap();
+            if contacts
+                .iter()
+                .any(|c| c.string_repr == alice && c.keys_rotated_at.is_some())
+            {
+                break;
+            }
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    async_std::future::timeout(DELIVERY_TIMEOUT, rotated)
+        .await
+        .expect("bob to get the new keys of alice");
+
+    let hashes = nodes[1]
+        .client
+        .send_message(
+            bob.clone(),
+            vec![alice.clone()],
+            "Hello again".to_string(),
+            "To the new keys".to_string(),
+        )
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+
+    // messages signed with the new keys are verified by bob
+    let mut events = nodes[0].client.subscribe_message_status().await.unwrap();
+    let hashes = nodes[0]
+        .client
+        .send_message(
+            alice.clone(),
+            vec![bob.clone()],
+            "Hi".to_string(),
+            "From the new keys".to_string(),
+        )
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+    let inbox = nodes[1]
+        .client
+        .get_messages(bob, Folder::Inbox)
+        .await
+        .unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert!(inbox[0].verified);
+}
+
+#[async_std::test]
+async fn contacts_are_notified_of_rotated_identity() {
+    let mut nodes = testing::spawn_network(2).await;
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let carol = nodes[0]
+        .client
+        .generate_new_identity("carol".to_string())
+        .await
+        .unwrap();
+    let bob = nodes[1]
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    nodes[0]
+        .client
+        .add_contact(bob.clone(), "Bob".to_string())
+        .await
+        .unwrap();
+
+    let mut events = nodes[0].client.subscribe_message_status().await.unwrap();
+    let mut bob_events = nodes[1].client.subscribe_message_status().await.unwrap();
+    let hashes = nodes[1]
+        .client
+        .send_message(
+            bob.clone(),
+            vec![alice.clone()],
+            "Before rotation".to_string(),
+            "Hello Alice".to_string(),
+        )
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut bob_events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+
+    let new_alice = nodes[0]
+        .client
+        .rotate_identity(alice.clone(), None, true)
+        .await
+        .unwrap();
+    let new_carol = nodes[0]
+        .client
+        .rotate_identity(carol.clone(), Some("new carol".to_string()), false)
+        .await
+        .unwrap();
+    assert_ne!(new_alice, alice);
+    assert_ne!(new_carol, carol);
+
+    let identities = nodes[0].client.get_own_identities().await.unwrap();
+    let label = |address: &str| {
+        identities
+            .iter()
+            .find(|i| i.string_repr == address)
+            .map(|i| i.label.clone())
+    };
+    assert_eq!(label(&alice), None, "archived identity has no private keys");
+    assert_eq!(label(&new_alice).as_deref(), Some("alice"));
+    assert_eq!(label(&carol).as_deref(), Some("carol"));
+    assert_eq!(label(&new_carol).as_deref(), Some("new carol"));
+
+    // past messages of the old identity are still readable once it's archived
+    let inbox = nodes[0]
+        .client
+        .get_messages(alice.clone(), Folder::Inbox)
+        .await
+        .unwrap();
+    let past = inbox.iter().find(|m| m.sender == bob).unwrap();
+    let mime = mail_parser::Message::parse(&past.data).unwrap();
+    assert_eq!(mime.subject(), Some("Before rotation"));
+    assert!(mime.body_text(0).unwrap().contains("Hello Alice"));
+
+    // each contact is sent a notice from the new address
+    let mut notices = Vec::new();
+    for folder in [Folder::Outbox, Folder::Sent] {
+        notices.extend(
+            nodes[0]
+                .client
+                .get_messages(new_alice.clone(), folder)
+                .await
+                .unwrap(),
+        );
+    }
+    assert_eq!(notices.len(), 1);
+    assert_eq!(notices[0].recipient, bob);
+    testing::wait_for_status(&mut events, &notices[0].hash, "Delivered", DELIVERY_TIMEOUT).await;
+
+    let inbox = nodes[1]
+        .client
+        .get_messages(bob, Folder::Inbox)
+        .await
+        .unwrap();
+    let notice = inbox.iter().find(|m| m.sender == new_alice).unwrap();
+    let body = mail_parser::Message::parse(&notice.data)
+        .and_then(|m| m.body_text(0).map(|b| b.to_string()))
+        .unwrap();
+    assert!(body.contains(&alice));
+}
+
+#[async_std::test]
+async fn config_is_updated_at_runtime() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let peer = testing::spawn_node(testing::test_config()).await;
+
+    let mut config = node.client.get_config().await.unwrap();
+    config.bootstrap_peers = vec![peer.address.clone()];
+    config.max_download_rate = Some(512 * 1024);
+    config.max_inventory_size = Some(16 * 1024 * 1024);
+    assert!(node
+        .client
+        .update_config(config.clone())
+        .await
+        .unwrap()
+        .is_empty());
+    let stats = node.client.get_inventory_stats().await.unwrap();
+    assert_eq!(stats.max_bytes, Some(16 * 1024 * 1024));
+    let updated = node.client.get_config().await.unwrap();
+    assert_eq!(updated.bootstrap_peers, config.bootstrap_peers);
+    assert_eq!(updated.max_download_rate, Some(512 * 1024));
+
+    config.listen_addresses = vec!["/memory/34064".parse().unwrap()];
+    assert_eq!(
+        node.client.update_config(config.clone()).await.unwrap(),
+        ["listen_addresses"]
+    );
+
+    // bootstrap peers have to contain peer ids
+    config.bootstrap_peers = vec!["/memory/1".parse().unwrap()];
+    assert!(node.client.update_config(config).await.is_err());
+    let updated = node.client.get_config().await.unwrap();
+    assert_eq!(updated.bootstrap_peers, [peer.address]);
+}
+
+#[async_std::test]
+async fn messages_are_loaded_page_by_page() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = node
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    for i in 0..5 {
+        node.client
+            .save_draft(alice.clone(), bob.clone(), i.to_string(), String::new())
+            .await
+            .unwrap();
+    }
+
+    let mut loaded = Vec::new();
+    for (offset, expected) in [(0, 2), (2, 2), (4, 1), (5, 0)] {
+        let page = node
+            .client
+            .get_messages_page(alice.clone(), Folder::Drafts, offset, 2)
+            .await
+            .unwrap();
+        assert_eq!(page.len(), expected);
+        loaded.extend(page);
+    }
+    assert!(loaded
+        .windows(2)
+        .all(|w| w[0].created_at >= w[1].created_at));
+    let mut hashes: Vec<String> = loaded.into_iter().map(|m| m.hash).collect();
+    hashes.sort();
+    hashes.dedup();
+    assert_eq!(hashes.len(), 5);
+}
+
+#[async_std::test]
+async fn message_is_sent_once_peers_appear() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let peer = testing::spawn_node(testing::test_config()).await;
+    let mut connectivity = node.client.subscribe_connectivity().await.unwrap();
+    assert_eq!(connectivity.next().await, Some(false));
+    let chan = node
+        .client
+        .join_chan("offline chan".to_string(), None)
+        .await
+        .unwrap();
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+
+    let mut events = node.client.subscribe_message_status().await.unwrap();
+    let hashes = node
+        .client
+        .send_message(
+            alice.clone(),
+            vec![chan],
+            "Hello".to_string(),
+            "Sent while offline".to_string(),
+        )
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "WaitingForPeers", DELIVERY_TIMEOUT).await;
+    let stats = node.client.get_folder_stats(alice).await.unwrap();
+    assert_eq!(stats.outbox.total, 1);
+
+    node.client.dial(peer.address.clone()).await.unwrap();
+    let online = async_std::future::timeout(Duration::from_secs(10), connectivity.next())
+        .await
+        .expect("node to get online");
+    assert_eq!(online, Some(true));
+    testing::wait_for_status(&mut events, &hashes[0], "Sent", DELIVERY_TIMEOUT).await;
+}
+
+#[async_std::test]
+async fn broadcast_is_published_once_first_peer_appears() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let mut peer = testing::spawn_node(testing::test_config()).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    peer.client
+        .add_subscription(alice.clone(), "Alice's news".to_string())
+        .await
+        .unwrap();
+
+    let mut events = node.client.subscribe_message_status().await.unwrap();
+    let mut received = peer.client.subscribe_message_status().await.unwrap();
+    let hash = node
+        .client
+        .send_broadcast(
+            alice.clone(),
+            "News".to_string(),
+            "Published without peers".to_string(),
+        )
+        .await
+        .unwrap();
+    // the announcement is queued, as there's no peer to publish it to
+    testing::wait_for_status(&mut events, &hash, "WaitingForPeers", DELIVERY_TIMEOUT).await;
+
+    node.client.dial(peer.address.clone()).await.unwrap();
+    testing::wait_for_status(&mut events, &hash, "Sent", DELIVERY_TIMEOUT).await;
+    testing::wait_for_status(&mut received, &hash, "Received", DELIVERY_TIMEOUT).await;
+    let feed = peer
+        .client
+        .get_messages(alice.clone(), Folder::Broadcasts)
+        .await
+        .unwrap();
+    assert_eq!(feed.len(), 1);
+    assert_eq!(feed[0].sender, alice);
+}
+
+#[cfg(feature = "sqlite")]
+#[async_std::test]
+async fn broadcast_waiting_for_pow_survives_restart() {
+    let data_dir = testing::data_dir("broadcast");
+    let mut node = testing::spawn_sqlite_node(&data_dir).await;
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    node.client
+        .set_power_mode(PowerMode::LowPower)
+        .await
+        .unwrap();
+    node.client
+        .send_broadcast(alice.clone(), "News".to_string(), "Hi".to_string())
+        .await
+        .unwrap();
+    node.client.shutdown().await.unwrap();
+
+    let mut node = testing::spawn_sqlite_node(&data_dir).await;
+    let recreated = async {
+        loop {
+            let inventory = node.client.get_inventory(None, 100).await.unwrap();
+            if inventory
+                .iter()
+                .any(|item| item.object_type == Some(ObjectType::Broadcast))
+            {
+                return;
+            }
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    // PoW of the re-created object is resumed
+    async_std::future::timeout(DELIVERY_TIMEOUT, recreated)
+        .await
+        .expect("broadcast object to be re-created");
+    let outbox = node
+        .client
+        .get_messages(alice, Folder::Outbox)
+        .await
+        .unwrap();
+    assert_eq!(outbox.len(), 1);
+    assert_eq!(outbox[0].recipient, worker::BROADCAST_RECIPIENT);
+    node.client.shutdown().await.unwrap();
+    fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[async_std::test]
+async fn recipient_is_resolved_by_label() {
+    let mut node = testing::spawn_node(testing::test_config()).await;
+    let identity = node
+        .client
+        .generate_new_identity("Me".to_string())
+        .await
+        .unwrap();
+    let mut contacts = Vec::new();
+    for (seed, label) in (1..).zip(["Bob Smith", "twin", "twin"]) {
+        let contact = Address::generate_seeded(seed).string_repr;
+        node.client
+            .add_contact(contact.clone(), label.to_string())
+            .await
+            .unwrap();
+        contacts.push(contact);
+    }
+
+    let resolved = node
+        .client
+        .resolve_recipient(" bob smith ".to_string())
+        .await
+        .unwrap();
+    assert_eq!(resolved.string_repr, contacts[0]);
+    let resolved = node
+        .client
+        .resolve_recipient("me".to_string())
+        .await
+        .unwrap();
+    assert_eq!(resolved.string_repr, identity);
+    // unknown addresses are resolved as well
+    let stranger = Address::generate_seeded(4).string_repr;
+    let resolved = node
+        .client
+        .resolve_recipient(stranger.clone())
+        .await
+        .unwrap();
+    assert_eq!(resolved.string_repr, stranger);
+    for input in ["twin", "alice", "BM-invalid"] {
+        assert!(
+            node.client
+                .resolve_recipient(input.to_string())
+                .await
+                .is_err(),
+            "{}",
+            input
+        );
+    }
+
+    assert_eq!(
+        client::parse_recipients(&format!("Bob Smith, {} {},, twin", stranger, contacts[1])),
+        vec!["Bob Smith", stranger.as_str(), contacts[1].as_str(), "twin"]
+    );
+}
+
+#[async_std::test]
+async fn broadcast_reaches_subscribers() {
+    let mut nodes = testing::spawn_network(2).await;
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    nodes[1]
+        .client
+        .add_subscription(alice.clone(), "Alice's news".to_string())
+        .await
+        .unwrap();
+    assert!(nodes[1]
+        .client
+        .add_subscription("BM-invalid".to_string(), String::new())
+        .await
+        .is_err());
+    let subscriptions = nodes[1].client.get_subscriptions().await.unwrap();
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].address, alice);
+
+    let mut sent_events = nodes[0].client.subscribe_message_status().await.unwrap();
+    let mut events = nodes[1].client.subscribe_message_status().await.unwrap();
+    let hash = nodes[0]
+        .client
+        .send_broadcast(
+            alice.clone(),
+            "News".to_string(),
+            "Hello, subscribers".to_string(),
+        )
+        .await
+        .unwrap();
+    let sent_event =
+        testing::wait_for_status(&mut sent_events, &hash, "Sent", DELIVERY_TIMEOUT).await;
+    testing::wait_for_status(&mut events, &hash, "Received", DELIVERY_TIMEOUT).await;
+    // sent objects can be offered again until they expire
+    nodes[0]
+        .client
+        .rebroadcast_object(sent_event.hash)
+        .await
+        .unwrap();
+    assert!(nodes[0]
+        .client
+        .rebroadcast_object("unknown".to_string())
+        .await
+        .is_err());
+
+    let sent = nodes[0]
+        .client
+        .get_messages(alice.clone(), Folder::Sent)
+        .await
+        .unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].recipient, worker::BROADCAST_RECIPIENT);
+    assert!(sent[0].resend_at.is_none());
+    let feed = nodes[1]
+        .client
+        .get_messages(alice.clone(), Folder::Broadcasts)
+        .await
+        .unwrap();
+    assert_eq!(feed.len(), 1);
+    assert_eq!(feed[0].sender, alice);
+    assert_eq!(feed[0].recipient, alice);
+    assert!(feed[0].verified);
+    // broadcasts are listed by the address of the subscription, like its Inbox
+    let inbox = nodes[1]
+        .client
+        .get_messages(alice.clone(), Folder::Inbox)
+        .await
+        .unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!("broadcasts".parse::<Folder>().unwrap(), Folder::Broadcasts);
+
+    nodes[1].client.remove_subscription(alice).await.unwrap();
+    assert!(nodes[1]
+        .client
+        .get_subscriptions()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[async_std::test]
+async fn auto_reply_is_sent_once_per_sender() {
+    let mut nodes = testing::spawn_network(2).await;
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = nodes[1]
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    nodes[1]
+        .client
+        .set_auto_reply(AutoReply {
+            address: bob.clone(),
+            subject_prefix: "Away: ".to_string(),
+            body: "Back on Monday".to_string(),
+            interval_hours: 24,
+        })
+        .await
+        .unwrap();
+    assert!(nodes[1]
+        .client
+        .set_auto_reply(AutoReply {
+            address: alice.clone(),
+            subject_prefix: String::new(),
+            body: String::new(),
+            interval_hours: 24,
+        })
+        .await
+        .is_err());
+
+    let mut events = nodes[0].client.subscribe_message_status().await.unwrap();
+    for (i, title) in ["Hello", "Are you there?"].into_iter().enumerate() {
+        let hashes = nodes[0]
+            .client
+            .send_message(
+                alice.clone(),
+                vec![bob.clone()],
+                title.to_string(),
+                "Hello from Alice".to_string(),
+            )
+            .await
+            .unwrap();
+        testing::wait_for_status(&mut events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+        let received = async {
+            while nodes[1]
+                .client
+                .get_messages(bob.clone(), Folder::Inbox)
+                .await
+                .unwrap()
+                .len()
+                <= i
+            {
+                async_std::task::sleep(Duration::from_millis(100)).await;
+            }
+        };
+        async_std::future::timeout(Duration::from_secs(30), received)
+            .await
+            .expect("message to be received");
+    }
+
+    // the second message is within the interval, so it isn't replied to
+    let mut replies = nodes[1]
+        .client
+        .get_messages(bob.clone(), Folder::Outbox)
+        .await
+        .unwrap();
+    replies.extend(
+        nodes[1]
+            .client
+            .get_messages(bob.clone(), Folder::Sent)
+            .await
+            .unwrap(),
+    );
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].recipient, alice);
+    let mime = mail_parser::Message::parse(&replies[0].data).unwrap();
+    assert_eq!(mime.subject(), Some("Away: Hello"));
+    assert!(String::from_utf8_lossy(&replies[0].data).starts_with("Auto-Submitted: auto-replied"));
+
+    nodes[1]
+        .client
+        .remove_auto_reply(bob.clone())
+        .await
+        .unwrap();
+    assert!(nodes[1].client.get_auto_reply(bob).await.unwrap().is_none());
+}
+
+#[async_std::test]
+async fn filter_rules_are_applied_to_received_messages() {
+    let mut nodes = testing::spawn_network(2).await;
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = nodes[1]
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    let rule = FilterRule {
+        id: String::new(),
+        label: "Sales".to_string(),
+        enabled: true,
+        sender: None,
+        subject_contains: Some("sale".to_string()),
+        identity: Some(bob.clone()),
+        min_size: None,
+        max_size: None,
+        move_to: Some("trash".to_string()),
+        mark_read: true,
+        delete_message: false,
+        notify: true,
+        created_at: chrono::Utc::now(),
+    };
+    nodes[1].client.add_filter_rule(rule.clone()).await.unwrap();
+    let spam_rule = nodes[1]
+        .client
+        .add_filter_rule(FilterRule {
+            subject_contains: Some("spam".to_string()),
+            // matched regardless of the `BM-` prefix
+            sender: Some(alice.trim_start_matches("BM-").to_string()),
+            move_to: None,
+            delete_message: true,
+            ..rule.clone()
+        })
+        .await
+        .unwrap();
+    // disabled rules are skipped
+    nodes[1]
+        .client
+        .add_filter_rule(FilterRule {
+            enabled: false,
+            subject_contains: Some("hello".to_string()),
+            delete_message: true,
+            ..rule.clone()
+        })
+        .await
+        .unwrap();
+    assert!(nodes[1]
+        .client
+        .add_filter_rule(FilterRule {
+            move_to: None,
+            mark_read: false,
+            notify: false,
+            ..rule.clone()
+        })
+        .await
+        .is_err());
+    assert!(nodes[1]
+        .client
+        .add_filter_rule(FilterRule {
+            move_to: Some("sent".to_string()),
+            ..rule.clone()
+        })
+        .await
+        .is_err());
+    let rules = nodes[1].client.get_filter_rules().await.unwrap();
+    assert_eq!(rules.len(), 3);
+    assert_eq!(rules[0].move_to.as_deref(), Some("Trash"));
+    assert_eq!(rules[1].id, spam_rule);
+
+    let mut events = nodes[0].client.subscribe_message_status().await.unwrap();
+    let mut received = nodes[1].client.subscribe_message_status().await.unwrap();
+    for title in ["Big SALE", "spam spam", "Hello"] {
+        let hashes = nodes[0]
+            .client
+            .send_message(
+                alice.clone(),
+                vec![bob.clone()],
+                title.to_string(),
+                "Hello from Alice".to_string(),
+            )
+            .await
+            .unwrap();
+        testing::wait_for_status(&mut events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+    }
+
+    let inbox = nodes[1]
+        .client
+        .get_messages(bob.clone(), Folder::Inbox)
+        .await
+        .unwrap();
+    assert_eq!(inbox.len(), 1);
+    let mime = mail_parser::Message::parse(&inbox[0].data).unwrap();
+    assert_eq!(mime.subject(), Some("Hello"));
+    assert!(!inbox[0].read);
+    let trash = nodes[1]
+        .client
+        .get_messages(bob.clone(), Folder::Trash)
+        .await
+        .unwrap();
+    assert_eq!(trash.len(), 1);
+    assert!(trash[0].read);
+
+    // the deleted message isn't reported, the moved one is reported to be notified about
+    let mut notified = Vec::new();
+    while let Ok(Some(event)) = received.try_next() {
+        if let Some(msg) = event.message {
+            notified.push((msg.hash, event.notify));
+        }
+    }
+    assert_eq!(
+        notified,
+        vec![
+            (trash[0].hash.clone(), true),
+            (inbox[0].hash.clone(), false)
+        ]
+    );
+
+    nodes[1].client.remove_filter_rule(spam_rule).await.unwrap();
+    assert_eq!(nodes[1].client.get_filter_rules().await.unwrap().len(), 2);
+}
+
+#[async_std::test]
+async fn pow_is_paused_in_low_power_mode() {
+    let mut nodes = testing::spawn_network(2).await;
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = nodes[1]
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    nodes[0]
+        .client
+        .set_power_mode(PowerMode::LowPower)
+        .await
+        .unwrap();
+    assert_eq!(
+        nodes[0].client.get_power_mode().await.unwrap(),
+        PowerMode::LowPower
+    );
+
+    let mut events = nodes[0].client.subscribe_message_status().await.unwrap();
+    let hashes = nodes[0]
+        .client
+        .send_message(
+            alice,
+            vec![bob],
+            "Hello".to_string(),
+            "Hello from Alice".to_string(),
+        )
+        .await
+        .unwrap();
+    async_std::task::sleep(Duration::from_secs(2)).await;
+    // objects wait in the queue instead of being processed
+    assert!(!nodes[0].client.get_pow_queue().await.unwrap().is_empty());
+    assert!(nodes[0].client.get_metrics().await.unwrap().pow.paused);
+
+    nodes[0]
+        .client
+        .set_power_mode(PowerMode::Normal)
+        .await
+        .unwrap();
+    testing::wait_for_status(&mut events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+    assert!(!nodes[0].client.get_metrics().await.unwrap().pow.paused);
+}
+
+#[async_std::test]
+async fn inventory_is_listed_by_expiration() {
+    let mut nodes = testing::spawn_network(2).await;
+    let alice = nodes[0]
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    let bob = nodes[1]
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+
+    let mut events = nodes[0].client.subscribe_message_status().await.unwrap();
+    let hashes = nodes[0]
+        .client
+        .send_message(alice, vec![bob], "Hi".to_string(), "Hi".to_string())
+        .await
+        .unwrap();
+    // the message gets the hash of its object once the pubkey of bob arrives
+    let delivered =
+        testing::wait_for_status(&mut events, &hashes[0], "Delivered", DELIVERY_TIMEOUT).await;
+
+    let inventory = nodes[0].client.get_inventory(None, 100).await.unwrap();
+    let msg = inventory
+        .iter()
+        .find(|item| item.hash == delivered.hash)
+        .expect("sent message to be in the inventory");
+    assert_eq!(msg.object_type, Some(ObjectType::Msg));
+    assert!(msg.expires > chrono::Utc::now());
+    assert!(inventory.windows(2).all(|w| w[0].expires <= w[1].expires));
+
+    // the next page starts right after the cursor
+    let cursor = InventoryCursor {
+        expires: inventory[0].expires.timestamp(),
+        hash: inventory[0].hash.clone(),
+    };
+    let next = nodes[0]
+        .client
+        .get_inventory(Some(cursor), 100)
+        .await
+        .unwrap();
+    let hashes: Vec<_> = next.iter().map(|item| &item.hash).collect();
+    let expected: Vec<_> = inventory[1..].iter().map(|item| &item.hash).collect();
+    assert_eq!(hashes, expected);
+}
@@ -0,0 +1,107 @@
+#![cfg(feature = "sqlite")]
+
+use std::fs;
+
+use async_std::task;
+use nantoka_core::{
+    network::address::Address,
+    testing::{self, NETWORK_MIN_EXTRA_BYTES, NETWORK_MIN_NONCE_TRIALS_PER_BYTE},
+};
+
+#[test]
+fn migrations_are_applied_once() {
+    let data_dir = testing::data_dir("migrate-twice");
+    task::block_on(testing::open_database(&data_dir).close());
+    let (versions, schema) = task::block_on(testing::database_schema(&data_dir));
+    assert!(!versions.is_empty());
+
+    // nothing is pending, so the second run leaves the database as it is
+    task::block_on(testing::open_database(&data_dir).close());
+    assert_eq!(
+        task::block_on(testing::database_schema(&data_dir)),
+        (versions, schema)
+    );
+    fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[test]
+fn upgraded_database_matches_fresh_one() {
+    let fresh = testing::data_dir("migrate-fresh");
+    task::block_on(testing::open_database(&fresh).close());
+
+    // database of a version before PoW difficulties were stored, with some data
+    let upgraded = testing::data_dir("migrate-upgraded");
+    task::block_on(async {
+        testing::create_outdated_database(&upgraded, 20231015120000).await;
+        testing::execute_sql(
+            &upgraded,
+            "INSERT INTO addresses (address, tag) VALUES ('BM-contact', 'tag')",
+        )
+        .await;
+    });
+    task::block_on(testing::open_database(&upgraded).close());
+
+    assert_eq!(
+        task::block_on(testing::database_schema(&upgraded)),
+        task::block_on(testing::database_schema(&fresh))
+    );
+    fs::remove_dir_all(fresh).unwrap();
+    fs::remove_dir_all(upgraded).unwrap();
+}
+
+#[test]
+fn difficulty_of_existing_rows_is_the_network_minimum() {
+    let data_dir = testing::data_dir("migrate-defaults");
+    let contact = Address::generate_seeded(1);
+    let object = testing::getpubkey_object(&contact);
+    let hash = bs58::encode(&object.hash).into_string();
+    let data: String = serde_cbor::to_vec(&object.kind)
+        .unwrap()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    // rows stored before PoW difficulties were
+    task::block_on(async {
+        testing::create_outdated_database(&data_dir, 20231015120000).await;
+        testing::execute_sql(
+            &data_dir,
+            &format!(
+                "INSERT INTO addresses (address, tag) VALUES ('{}', '{}'); \
+                INSERT INTO inventory (hash, object_type, nonce, data, expires, signature) \
+                VALUES ('{}', 2, X'01', X'{}', datetime('now', '+1 day'), X'')",
+                contact.string_repr,
+                bs58::encode(&contact.tag).into_string(),
+                hash,
+                data
+            ),
+        )
+        .await;
+    });
+
+    let storage = testing::open_database(&data_dir);
+    task::block_on(async {
+        let object = storage
+            .inventory_repo()
+            .get_object(hash)
+            .await
+            .unwrap()
+            .expect("object to be kept");
+        assert_eq!(
+            (object.nonce_trials_per_byte, object.extra_bytes),
+            (NETWORK_MIN_NONCE_TRIALS_PER_BYTE, NETWORK_MIN_EXTRA_BYTES)
+        );
+        let address = storage
+            .address_repo()
+            .get_by_ripe_or_tag(contact.string_repr.clone())
+            .await
+            .unwrap()
+            .expect("address to be kept");
+        assert_eq!(
+            (address.nonce_trials_per_byte, address.extra_bytes),
+            (NETWORK_MIN_NONCE_TRIALS_PER_BYTE, NETWORK_MIN_EXTRA_BYTES)
+        );
+        storage.close().await;
+    });
+    fs::remove_dir_all(data_dir).unwrap();
+}
@@ -0,0 +1,41 @@
+use nantoka_core::network::{
+    address::Address,
+    node::worker::{
+        decrypt_and_deserialize_payload, serialize_and_encrypt_payload_pub, PayloadError,
+    },
+};
+
+/// ECIES ciphertext starts with the ephemeral public key and the AES-GCM nonce,
+/// followed by the tag and the encrypted data
+const ECIES_TAG_OFFSET: usize = 65 + 16;
+
+#[test]
+fn tampered_payloads_are_rejected() {
+    let recipient = Address::generate_seeded(1);
+    let public_key = recipient.public_encryption_key.unwrap();
+    let secret_key = recipient.private_encryption_key.unwrap();
+    let encrypted = serialize_and_encrypt_payload_pub("Hello".to_string(), &public_key);
+    let decrypted: String = decrypt_and_deserialize_payload(&encrypted, &secret_key).unwrap();
+    assert_eq!(decrypted, "Hello");
+
+    for (name, i) in [
+        ("tag", ECIES_TAG_OFFSET),
+        ("ciphertext", encrypted.len() - 1),
+    ] {
+        let mut tampered = encrypted.clone();
+        tampered[i] ^= 1;
+        // integrity is guaranteed by the tag of AES-GCM used by ECIES
+        assert!(ecies::decrypt(&secret_key.serialize(), &tampered).is_err());
+        let result = decrypt_and_deserialize_payload::<String>(&tampered, &secret_key);
+        assert!(matches!(result, Err(PayloadError::Decryption)), "{}", name);
+    }
+
+    let truncated = &encrypted[..encrypted.len() - 1];
+    let result = decrypt_and_deserialize_payload::<String>(truncated, &secret_key);
+    assert!(matches!(result, Err(PayloadError::Decryption)));
+
+    // authentic ciphertext of something which isn't CBOR
+    let encrypted = ecies::encrypt(&public_key.serialize(), &[0xff, 0x00]).unwrap();
+    let result = decrypt_and_deserialize_payload::<String>(&encrypted, &secret_key);
+    assert!(matches!(result, Err(PayloadError::Malformed(_))));
+}
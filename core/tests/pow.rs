@@ -0,0 +1,139 @@
+use std::{collections::HashSet, fs, time::Duration};
+
+use chrono::Utc;
+use libp2p::PeerId;
+use nantoka_core::{
+    config::{Config, ObjectType, PoWEngineKind},
+    network::{
+        address::Address,
+        node::protocol::{Action, ProtocolEngine},
+    },
+    testing::{self, PoWError, NETWORK_MIN_EXTRA_BYTES, NETWORK_MIN_NONCE_TRIALS_PER_BYTE},
+};
+
+#[async_std::test]
+async fn pow_is_completed() {
+    for &engine in PoWEngineKind::ALL {
+        let mut object = testing::getpubkey_object(&Address::generate_seeded(1));
+        // the reference engine is slow, it's the outcome which is tested here
+        object.nonce_trials_per_byte = 10;
+        object.extra_bytes = 10;
+        let object = testing::run_pow(object, engine, None)
+            .await
+            .unwrap_or_else(|e| panic!("PoW of {:?} engine to be completed: {}", engine, e));
+        assert!(!object.nonce.is_empty());
+    }
+}
+
+#[async_std::test]
+async fn aborted_pow_is_cancelled() {
+    for &engine in PoWEngineKind::ALL {
+        let mut object = testing::getpubkey_object(&Address::generate_seeded(1));
+        // way too hard to be finished before it's aborted
+        object.nonce_trials_per_byte = 1_000_000_000;
+        let result = testing::run_pow(object, engine, Some(Duration::from_millis(100))).await;
+        assert!(
+            matches!(result, Err(PoWError::Cancelled)),
+            "{:?}: {:?}",
+            engine,
+            result
+        );
+    }
+}
+
+#[async_std::test]
+async fn overflowing_difficulty_is_unreachable() {
+    let mut object = testing::getpubkey_object(&Address::generate_seeded(1));
+    // expected number of trials doesn't fit into 64 bits, so the target is zero
+    object.nonce_trials_per_byte = i32::MAX;
+    object.extra_bytes = i32::MAX;
+    object.expires = (chrono::Utc::now() + chrono::Duration::days(28)).timestamp();
+    let result = testing::run_pow(object, PoWEngineKind::Fast, None).await;
+    assert!(
+        matches!(result, Err(PoWError::TargetUnreachable)),
+        "{:?}",
+        result
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[async_std::test]
+async fn difficulty_multiplier_raises_pow_of_outgoing_objects() {
+    let data_dir = testing::data_dir("multiplier");
+    let config = Config {
+        pow_difficulty_multiplier: 3.0,
+        ..testing::test_config()
+    };
+    assert_eq!(
+        config.outgoing_pow_difficulty(),
+        (
+            3 * NETWORK_MIN_NONCE_TRIALS_PER_BYTE,
+            3 * NETWORK_MIN_EXTRA_BYTES
+        )
+    );
+    let mut node = testing::spawn_sqlite_node_with_config(&data_dir, config.clone()).await;
+    let mut default_node = testing::spawn_node(testing::test_config()).await;
+
+    // target is lower, so more nonces are expected to be tried
+    let harder = node.client.estimate_pow(1000, None).await.unwrap();
+    let easier = default_node.client.estimate_pow(1000, None).await.unwrap();
+    assert!(
+        harder.expected_trials > 2.0 * easier.expected_trials,
+        "{} trials, {} by default",
+        harder.expected_trials,
+        easier.expected_trials
+    );
+
+    let alice = node
+        .client
+        .generate_new_identity("alice".to_string())
+        .await
+        .unwrap();
+    // keys of own identities are known, so the message goes straight to PoW
+    let bob = node
+        .client
+        .generate_new_identity("bob".to_string())
+        .await
+        .unwrap();
+    node.client
+        .send_message(alice, vec![bob], "Hi".to_string(), "Hi".to_string())
+        .await
+        .unwrap();
+    let sent = async {
+        loop {
+            let inventory = node.client.get_inventory(None, 100).await.unwrap();
+            match inventory
+                .into_iter()
+                .find(|item| item.object_type == Some(ObjectType::Msg))
+            {
+                Some(msg) => return msg,
+                None => async_std::task::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    };
+    let msg = async_std::future::timeout(Duration::from_secs(120), sent)
+        .await
+        .expect("message to be sent");
+    node.client.shutdown().await.unwrap();
+
+    let storage = testing::open_database(&data_dir);
+    let object = storage
+        .inventory_repo()
+        .get_object(msg.hash)
+        .await
+        .unwrap()
+        .expect("object to be stored");
+    storage.close().await;
+    assert_eq!(
+        (object.nonce_trials_per_byte, object.extra_bytes),
+        config.outgoing_pow_difficulty()
+    );
+    // peers check the nonce against the difficulty carried by the object
+    let mut engine = ProtocolEngine::new(testing::test_config());
+    let actions = engine.on_objects(PeerId::random(), vec![object], &HashSet::new(), Utc::now());
+    assert!(
+        actions.iter().any(|a| matches!(a, Action::StoreObject(_))),
+        "object is rejected"
+    );
+    fs::remove_dir_all(data_dir).unwrap();
+}
@@ -0,0 +1,142 @@
+use nantoka_core::network::messages::{
+    DecodeError, MessageCommand, MessagePayload, NetworkMessage, PeerAddress,
+    LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use serde_cbor::Value;
+
+fn inv_message() -> NetworkMessage {
+    NetworkMessage {
+        command: MessageCommand::Inv,
+        payload: MessagePayload::inv(vec![("hash".to_string(), 1, 0)], None),
+    }
+}
+
+fn text(s: &str) -> Value {
+    Value::Text(s.to_string())
+}
+
+fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::Map(entries.into_iter().map(|(k, v)| (text(k), v)).collect())
+}
+
+fn envelope(message: Value) -> Vec<u8> {
+    serde_cbor::to_vec(&map(vec![
+        ("version", Value::Integer(PROTOCOL_VERSION as i128)),
+        ("message", message),
+    ]))
+    .unwrap()
+}
+
+#[test]
+fn messages_of_both_versions_are_decoded() {
+    for version in [LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION] {
+        let (msg, decoded_version) =
+            NetworkMessage::decode(&inv_message().encode(version)).unwrap();
+        assert_eq!(decoded_version, version);
+        assert!(
+            matches!(msg.payload, MessagePayload::Inv { inventory, .. } if inventory == ["hash"])
+        );
+    }
+}
+
+#[test]
+fn corrupted_messages_are_rejected() {
+    let data = inv_message().encode(PROTOCOL_VERSION);
+    for corrupted in [
+        Vec::new(),
+        vec![0xff; 16],
+        data[..data.len() / 2].to_vec(),
+        serde_cbor::to_vec(&text("inv")).unwrap(),
+    ] {
+        assert!(matches!(
+            NetworkMessage::decode(&corrupted),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+}
+
+#[test]
+fn unknown_commands_are_rejected() {
+    let data = envelope(map(vec![
+        ("command", text("Teleport")),
+        ("payload", map(vec![("kind", text("None"))])),
+    ]));
+    assert!(matches!(
+        NetworkMessage::decode(&data),
+        Err(DecodeError::UnknownCommand(c)) if c == "Teleport"
+    ));
+}
+
+#[test]
+fn known_peers_are_exchanged() {
+    let peer = PeerAddress {
+        peer_id: "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".to_string(),
+        multiaddr: "/ip4/203.0.113.7/tcp/34064".to_string(),
+        last_seen: 1_700_000_000,
+    };
+    let msg = NetworkMessage {
+        command: MessageCommand::Addr,
+        payload: MessagePayload::Addr {
+            peers: vec![peer.clone()],
+        },
+    };
+    let (decoded, _) = NetworkMessage::decode(&msg.encode(PROTOCOL_VERSION)).unwrap();
+    assert!(matches!(decoded.command, MessageCommand::Addr));
+    assert!(matches!(decoded.payload, MessagePayload::Addr { peers } if peers == [peer]));
+}
+
+#[test]
+fn unknown_object_kinds_are_skipped() {
+    let object = |kind: &str| {
+        map(vec![
+            ("hash", Value::Array(vec![Value::Integer(1)])),
+            ("nonce", Value::Array(Vec::new())),
+            ("expires", Value::Integer(0)),
+            ("signature", Value::Array(Vec::new())),
+            (
+                "kind",
+                map(vec![
+                    ("kind", text(kind)),
+                    ("tag", Value::Array(Vec::new())),
+                ]),
+            ),
+            ("nonce_trials_per_byte", Value::Integer(1000)),
+            ("extra_bytes", Value::Integer(1000)),
+        ])
+    };
+    let data = envelope(map(vec![
+        ("command", text("Objects")),
+        (
+            "payload",
+            map(vec![
+                ("kind", text("Objects")),
+                (
+                    "objects",
+                    Value::Array(vec![object("Hologram"), object("Getpubkey")]),
+                ),
+            ]),
+        ),
+    ]));
+    let (msg, _) = NetworkMessage::decode(&data).unwrap();
+    assert!(matches!(msg.payload, MessagePayload::Objects { objects } if objects.len() == 1));
+}
+
+#[test]
+fn inventory_without_object_types_is_decoded() {
+    let data = envelope(map(vec![
+        ("command", text("Inv")),
+        (
+            "payload",
+            map(vec![
+                ("kind", text("Inv")),
+                ("inventory", Value::Array(vec![text("hash")])),
+                ("expires", Value::Array(vec![Value::Integer(1)])),
+            ]),
+        ),
+    ]));
+    let (msg, _) = NetworkMessage::decode(&data).unwrap();
+    assert!(matches!(
+        msg.payload,
+        MessagePayload::Inv { inventory, types, .. } if inventory == ["hash"] && types.is_empty()
+    ));
+}
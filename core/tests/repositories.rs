@@ -0,0 +1,95 @@
+use std::fs;
+
+use nantoka_core::{
+    network::address::Address,
+    repositories::address::{AddressRepositorySync, PublicKeysUpdate},
+    testing,
+};
+
+/// Store public keys of `keys` for the contact
+async fn update_keys(
+    repo: &mut AddressRepositorySync,
+    contact: &Address,
+    keys: &Address,
+) -> PublicKeysUpdate {
+    repo.update_public_keys(
+        bs58::encode(&contact.tag).into_string(),
+        keys.public_signing_key.unwrap(),
+        keys.public_encryption_key.unwrap(),
+        true,
+    )
+    .await
+    .unwrap()
+}
+
+async fn signing_key(repo: &AddressRepositorySync, contact: &Address) -> Option<[u8; 65]> {
+    repo.get_by_ripe_or_tag(contact.string_repr.clone())
+        .await
+        .unwrap()
+        .and_then(|a| a.public_signing_key)
+        .map(|k| k.serialize())
+}
+
+#[async_std::test]
+async fn pinned_keys_are_replaced_only_after_repin() {
+    let data_dir = testing::data_dir("repin");
+    for storage in testing::storages(&data_dir) {
+        let mut repo = storage.address_repo();
+        let contact = Address::generate_seeded(1);
+        let other = Address::generate_seeded(2);
+        let identity = Address::generate_seeded(3);
+        let mut stored = contact.clone();
+        stored.private_signing_key = None;
+        stored.private_encryption_key = None;
+        repo.store(stored).await.unwrap();
+        repo.store(identity.clone()).await.unwrap();
+
+        assert_eq!(
+            update_keys(repo.as_mut(), &other, &other).await,
+            PublicKeysUpdate::NotFound
+        );
+        assert_eq!(
+            update_keys(repo.as_mut(), &contact, &contact).await,
+            PublicKeysUpdate::Updated
+        );
+        assert_eq!(
+            update_keys(repo.as_mut(), &contact, &other).await,
+            PublicKeysUpdate::Pinned,
+            "different keys are pinned"
+        );
+        assert_eq!(
+            signing_key(repo.as_ref(), &contact).await,
+            contact.public_signing_key.map(|k| k.serialize())
+        );
+
+        // re-pinning forgets the keys, so new ones are accepted
+        assert!(repo
+            .clear_public_keys(contact.string_repr.clone())
+            .await
+            .unwrap());
+        assert_eq!(
+            update_keys(repo.as_mut(), &contact, &other).await,
+            PublicKeysUpdate::Updated
+        );
+        assert_eq!(
+            signing_key(repo.as_ref(), &contact).await,
+            other.public_signing_key.map(|k| k.serialize())
+        );
+
+        // only contacts are re-pinned
+        assert!(!repo
+            .clear_public_keys(identity.string_repr.clone())
+            .await
+            .unwrap());
+        assert!(!repo
+            .clear_public_keys(other.string_repr.clone())
+            .await
+            .unwrap());
+        assert_eq!(
+            signing_key(repo.as_ref(), &identity).await,
+            identity.public_signing_key.map(|k| k.serialize())
+        );
+        storage.close().await;
+    }
+    _ = fs::remove_dir_all(data_dir);
+}
@@ -0,0 +1,156 @@
+#![cfg(feature = "rpc")]
+
+use std::time::{Duration, Instant};
+
+use async_std::{
+    io::{ReadExt, WriteExt},
+    net::TcpStream,
+    task,
+};
+use nantoka_core::{network::address::Address, rpc, testing};
+use serde_json::{json, Value};
+
+const TOKEN: &str = "secret";
+const BODY: &str = r#"{"jsonrpc": "2.0", "id": 1, "method": "get_identities"}"#;
+
+/// Start the API server of a new node on a free port
+async fn spawn_server() -> u16 {
+    let node = testing::spawn_node(testing::test_config()).await;
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    task::spawn(rpc::serve(
+        ("127.0.0.1", port),
+        node.client,
+        TOKEN.to_string(),
+    ));
+    port
+}
+
+/// Send the request with given extra headers, returns status code and body
+async fn post(port: u16, headers: &[&str]) -> (u16, String) {
+    post_body(port, headers, BODY).await
+}
+
+async fn post_body(port: u16, headers: &[&str], body: &str) -> (u16, String) {
+    let mut request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n", body.len());
+    for header in headers {
+        request.push_str(&format!("{}\r\n", header));
+    }
+    request.push_str(&format!("\r\n{}", body));
+    send(port, &request).await
+}
+
+/// Send raw request, returns status code and body of the response
+async fn send(port: u16, request: &str) -> (u16, String) {
+    let mut stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(s) => break s,
+            // server might not listen yet
+            Err(_) => task::sleep(Duration::from_millis(50)).await,
+        }
+    };
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, b)| b.to_string())
+        .unwrap_or_default();
+    (status, body)
+}
+
+#[async_std::test]
+async fn authorized_json_requests_are_handled() {
+    let port = spawn_server().await;
+    let (status, body) = post(
+        port,
+        &[
+            "Authorization: Bearer secret",
+            "Content-Type: application/json; charset=utf-8",
+        ],
+    )
+    .await;
+    assert_eq!(status, 200);
+    let response: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(response["result"], Value::Array(Vec::new()));
+}
+
+#[async_std::test]
+async fn requests_without_token_are_rejected() {
+    let port = spawn_server().await;
+    let json = "Content-Type: application/json";
+    assert_eq!(post(port, &[json]).await.0, 401);
+    assert_eq!(
+        post(port, &["Authorization: Bearer secreT", json]).await.0,
+        401
+    );
+    assert_eq!(post(port, &["Authorization: secret", json]).await.0, 401);
+}
+
+#[async_std::test]
+async fn browser_requests_are_rejected() {
+    let port = spawn_server().await;
+    let (auth, json) = (
+        "Authorization: Bearer secret",
+        "Content-Type: application/json",
+    );
+    let origin = "Origin: http://evil.example";
+    assert_eq!(post(port, &[auth, json, origin]).await.0, 403);
+    // simple requests of web pages can't set the content type
+    assert_eq!(post(port, &[auth, "Content-Type: text/plain"]).await.0, 415);
+    assert_eq!(post(port, &[auth]).await.0, 415);
+}
+
+#[async_std::test]
+async fn oversized_requests_are_rejected_before_authentication() {
+    let port = spawn_server().await;
+    // nothing is sent beyond the point the request is rejected at, so that the
+    // connection isn't reset with unread data
+    let long_line = format!("POST /{}", "a".repeat(8 * 1024 - 6));
+    assert_eq!(send(port, &long_line).await.0, 400);
+    let many_headers = format!("POST / HTTP/1.1\r\n{}", "X-Header: 1\r\n".repeat(65));
+    assert_eq!(send(port, &many_headers).await.0, 400);
+    let bad_length = "POST / HTTP/1.1\r\nContent-Length: many\r\n";
+    assert_eq!(send(port, bad_length).await.0, 400);
+    // the body isn't read, nor is the token needed to reject it
+    let large_body = "POST / HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n";
+    assert_eq!(send(port, large_body).await.0, 413);
+}
+
+#[async_std::test]
+async fn slow_requests_time_out() {
+    let port = spawn_server().await;
+    let started = Instant::now();
+    // the head is never finished
+    assert_eq!(send(port, "POST / HTTP/1.1\r\n").await.0, 408);
+    assert!(started.elapsed() >= Duration::from_secs(10));
+}
+
+#[async_std::test]
+async fn messages_are_sent_from_own_identities_only() {
+    let port = spawn_server().await;
+    let address = Address::generate().string_repr;
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "send_message",
+        "params": {"from": address, "to": address, "title": "Hi", "body": "Hi"},
+    });
+    let headers = [
+        "Authorization: Bearer secret",
+        "Content-Type: application/json",
+    ];
+    let (status, body) = post_body(port, &headers, &request.to_string()).await;
+    assert_eq!(status, 200);
+    let response: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(response["error"]["code"], -32602);
+    // node keeps serving requests
+    let (_, body) = post(port, &headers).await;
+    let response: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(response["result"], Value::Array(Vec::new()));
+}